@@ -0,0 +1,196 @@
+//! Measure the cost of drawing, without a show.
+//!
+//! Synthetic frames of tunnels are generated in-process and drawn by the same
+//! renderer as the show, including the config's calibration and projection,
+//! into an offscreen framebuffer the size of the config's resolution.  The GL
+//! pipeline is drained with glFinish before and after each timed frame, so the
+//! timings cover packing arcs, submitting the draw calls, and the GPU drawing
+//! them, and can be compared between machines and drivers before a show.
+
+use gl::types::GLuint;
+use opengl_graphics::GlGraphics;
+use piston_window::*;
+use sdl2_window::Sdl2Window;
+use std::{error::Error, sync::Arc, time::Duration, time::Instant};
+use tunnels_lib::{ArcSegment, Snapshot, Timestamp};
+
+use crate::config::ClientConfig;
+use crate::draw::ArcRenderer;
+use crate::show::OPENGL;
+
+/// Segments in each ring of a synthetic tunnel.
+const SEGMENTS: usize = 16;
+
+/// Most arcs in each layer of a synthetic frame.
+const LAYER_SIZE: usize = 256;
+
+/// Draw frames of synthetic tunnels with the provided number of arcs, and
+/// print percentiles of the time taken to draw a frame.
+pub fn run(cfg: &ClientConfig, frames: usize, arcs: usize) -> Result<(), Box<dyn Error>> {
+    // A window is needed for a GL context, but nothing is drawn to it.
+    let mut window: PistonWindow<Sdl2Window> = WindowSettings::new("tunnelclient bench", [64, 64])
+        .graphics_api(OPENGL)
+        .vsync(false)
+        .build()?;
+    gl::load_with(|name| window.window.get_proc_address(name) as *const _);
+    let target = Framebuffer::new(cfg.x_resolution, cfg.y_resolution)?;
+    let mut gl_graphics = GlGraphics::new(OPENGL);
+    let mut renderer = ArcRenderer::new()?;
+    let viewport = Viewport {
+        rect: [0, 0, cfg.x_resolution as i32, cfg.y_resolution as i32],
+        draw_size: [cfg.x_resolution, cfg.y_resolution],
+        window_size: [cfg.x_extent, cfg.y_extent],
+    };
+
+    let mut times = Vec::with_capacity(frames);
+    for n in 0..frames {
+        let frame = synthetic_frame(n, arcs);
+        unsafe { gl::Finish() };
+        let start = Instant::now();
+        target.bind();
+        gl_graphics.draw(viewport, |c, gl| {
+            clear([0.0, 0.0, 0.0, 1.0], gl);
+            renderer.draw(&frame, c.transform, gl, cfg);
+        });
+        unsafe { gl::Finish() };
+        times.push(start.elapsed());
+    }
+    if times.is_empty() {
+        println!("No frames drawn.");
+        return Ok(());
+    }
+    times.sort();
+    let ms = |p| percentile(&times, p).as_secs_f64() * 1000.0;
+    println!(
+        "Drew {} frames of {} arcs at {}x{}.",
+        frames, arcs, cfg.x_resolution, cfg.y_resolution
+    );
+    println!(
+        "Frame draw time: p50 {:.3} ms, p90 {:.3} ms, p99 {:.3} ms, max {:.3} ms.",
+        ms(0.5),
+        ms(0.9),
+        ms(0.99),
+        ms(1.0)
+    );
+    Ok(())
+}
+
+/// An offscreen color buffer to draw into.
+struct Framebuffer {
+    framebuffer: GLuint,
+    color: GLuint,
+}
+
+impl Framebuffer {
+    /// Create the framebuffer in the current GL context.
+    fn new(width: u32, height: u32) -> Result<Self, Box<dyn Error>> {
+        let mut target = Self {
+            framebuffer: 0,
+            color: 0,
+        };
+        unsafe {
+            gl::GenFramebuffers(1, &mut target.framebuffer);
+            gl::GenRenderbuffers(1, &mut target.color);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, target.color);
+            // Match the window, which GlGraphics draws into in sRGB.
+            gl::RenderbufferStorage(
+                gl::RENDERBUFFER,
+                gl::SRGB8_ALPHA8,
+                width as i32,
+                height as i32,
+            );
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            target.bind();
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::RENDERBUFFER,
+                target.color,
+            );
+            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                return Err("Unable to create an offscreen framebuffer.".into());
+            }
+        }
+        Ok(target)
+    }
+
+    fn bind(&self) {
+        unsafe { gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer) };
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteRenderbuffers(1, &self.color);
+        }
+    }
+}
+
+/// Return the value below which the fraction p of sorted values fall.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[i]
+}
+
+/// Return a frame of concentric tunnels with the provided number of arcs,
+/// rotated according to the frame number so that frames differ.
+fn synthetic_frame(n: usize, arcs: usize) -> Snapshot {
+    let rings = arcs.div_ceil(SEGMENTS);
+    let arcs: Vec<ArcSegment> = (0..arcs)
+        .map(|i| {
+            let ring = (i / SEGMENTS) as f64 / rings.max(1) as f64;
+            let segment = (i % SEGMENTS) as f64 / SEGMENTS as f64;
+            ArcSegment {
+                level: 1.0,
+                thickness: 0.1,
+                hue: ring,
+                sat: 1.0,
+                val: 1.0,
+                x: 0.0,
+                y: 0.0,
+                rad_x: 0.1 + 0.8 * ring,
+                rad_y: 0.1 + 0.8 * ring,
+                start: segment,
+                stop: segment + 0.8 / SEGMENTS as f64,
+                rot_angle: (n as f64 * 0.01 * (1.0 + ring)).fract(),
+                palette: false,
+            }
+        })
+        .collect();
+    Snapshot {
+        frame_number: n as u64,
+        time: Timestamp::ZERO,
+        layers: arcs
+            .chunks(LAYER_SIZE)
+            .map(|layer| Arc::new(layer.to_vec()))
+            .collect(),
+        palette: None,
+        blend: Vec::new(),
+        color: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_synthetic_frame() {
+        let frame = synthetic_frame(3, 1000);
+        assert_eq!(4, frame.layers.len());
+        assert_eq!(1000, frame.layers.iter().map(|l| l.len()).sum::<usize>());
+        assert_ne!(frame, synthetic_frame(4, 1000));
+        assert!(synthetic_frame(0, 0).layers.is_empty());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let times: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(Duration::from_millis(1), percentile(&times, 0.0));
+        assert_eq!(Duration::from_millis(51), percentile(&times, 0.5));
+        assert_eq!(Duration::from_millis(100), percentile(&times, 1.0));
+    }
+}
@@ -0,0 +1,107 @@
+//! Loading and saving calibrations in the client config file.
+//!
+//! See tunnels_lib::calibration for what a calibration consists of.
+use std::error::Error;
+use yaml_rust::{yaml::Hash, Yaml};
+
+pub use tunnels_lib::calibration::{Calibration, EdgeBlend};
+
+/// Load a calibration from a client config document.
+/// Every part of the calibration is optional.
+pub fn from_yaml(cfg: &Yaml) -> Result<Calibration, Box<dyn Error>> {
+    let mut cal = Calibration::default();
+    if !cfg["corners"].is_badvalue() {
+        let corners = points_from_yaml(&cfg["corners"]).ok_or("Bad calibration corners.")?;
+        if corners.len() != 4 {
+            return Err("Calibration must have exactly four corners.".into());
+        }
+        cal.corners.copy_from_slice(&corners);
+    }
+    let blend = &cfg["edge_blend"];
+    if !blend.is_badvalue() {
+        let width = |name: &str, current: f64| -> Result<f64, Box<dyn Error>> {
+            match &blend[name] {
+                Yaml::BadValue => Ok(current),
+                v => Ok(as_f64(v).ok_or(format!("Bad edge blend {}.", name))?),
+            }
+        };
+        cal.blend = EdgeBlend {
+            left: width("left", cal.blend.left)?,
+            right: width("right", cal.blend.right)?,
+            top: width("top", cal.blend.top)?,
+            bottom: width("bottom", cal.blend.bottom)?,
+            gamma: width("gamma", cal.blend.gamma)?,
+        };
+    }
+    if !cfg["mask"].is_badvalue() {
+        cal.mask = points_from_yaml(&cfg["mask"]).ok_or("Bad calibration mask.")?;
+    }
+    Ok(cal)
+}
+
+/// Write a calibration into a client config document, replacing any
+/// calibration already present.
+pub fn write_yaml(cal: &Calibration, doc: &mut Hash) {
+    let key = |k: &str| Yaml::String(k.to_string());
+    doc.insert(key("corners"), points_to_yaml(&cal.corners));
+    let mut blend = Hash::new();
+    blend.insert(key("left"), Yaml::Real(cal.blend.left.to_string()));
+    blend.insert(key("right"), Yaml::Real(cal.blend.right.to_string()));
+    blend.insert(key("top"), Yaml::Real(cal.blend.top.to_string()));
+    blend.insert(key("bottom"), Yaml::Real(cal.blend.bottom.to_string()));
+    blend.insert(key("gamma"), Yaml::Real(cal.blend.gamma.to_string()));
+    doc.insert(key("edge_blend"), Yaml::Hash(blend));
+    doc.insert(key("mask"), points_to_yaml(&cal.mask));
+}
+
+/// Accept both integers and floats.
+pub fn as_f64(v: &Yaml) -> Option<f64> {
+    v.as_f64().or_else(|| v.as_i64().map(|i| i as f64))
+}
+
+fn points_from_yaml(v: &Yaml) -> Option<Vec<[f64; 2]>> {
+    v.as_vec()?
+        .iter()
+        .map(|p| {
+            let p = p.as_vec()?;
+            if p.len() != 2 {
+                return None;
+            }
+            Some([as_f64(&p[0])?, as_f64(&p[1])?])
+        })
+        .collect()
+}
+
+fn points_to_yaml(points: &[[f64; 2]]) -> Yaml {
+    Yaml::Array(
+        points
+            .iter()
+            .map(|p| {
+                Yaml::Array(vec![
+                    Yaml::Real(p[0].to_string()),
+                    Yaml::Real(p[1].to_string()),
+                ])
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let cal = Calibration {
+            corners: [[0.1, 0.05], [0.95, 0.0], [0.9, 1.0], [0.0, 0.85]],
+            blend: EdgeBlend {
+                right: 0.2,
+                ..Default::default()
+            },
+            mask: vec![[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]],
+        };
+        let mut doc = Hash::new();
+        write_yaml(&cal, &mut doc);
+        assert_eq!(cal, from_yaml(&Yaml::Hash(doc)).unwrap());
+    }
+}
@@ -0,0 +1,247 @@
+//! Interactive calibration of the projected output using the mouse.
+//!
+//! Press C to enter or leave calibration mode.  While calibrating:
+//! - drag the corner handles to warp the image
+//! - drag the edge handles to set the width of each edge blend
+//! - right-click to add a mask vertex; drag mask vertices to move them
+//! - press Backspace to remove the mask vertex under the cursor, or the most
+//!   recently added one
+//! - press R to reset the calibration
+//! - press S to save the calibration to the config file
+use crate::calibration::{Calibration, EdgeBlend};
+use crate::config::ClientConfig;
+use graphics::types::Color;
+use graphics::{DrawState, Ellipse, Graphics, Line, Polygon, Rectangle};
+use log::{error, info};
+use piston_window::{
+    Button, Context, GenericEvent, Key, MouseButton, MouseCursorEvent, PressEvent, ReleaseEvent,
+};
+
+/// How close the cursor must be to a handle to grab it, as a fraction of the
+/// window size.
+const GRAB_RADIUS: f64 = 0.03;
+/// Drawn size of a handle, in pixels.
+const HANDLE_SIZE: f64 = 12.0;
+/// Number of steps used to draw each edge blend ramp.
+const BLEND_STEPS: usize = 32;
+
+const BLACK: Color = [0.0, 0.0, 0.0, 1.0];
+const OUTLINE_COLOR: Color = [0.0, 1.0, 0.0, 1.0];
+const CORNER_COLOR: Color = [1.0, 1.0, 0.0, 1.0];
+const BLEND_COLOR: Color = [0.0, 0.8, 1.0, 1.0];
+const MASK_COLOR: Color = [1.0, 0.0, 1.0, 1.0];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Handle {
+    Corner(usize),
+    BlendLeft,
+    BlendRight,
+    BlendTop,
+    BlendBottom,
+    Mask(usize),
+}
+
+#[derive(Default)]
+pub struct Calibrator {
+    active: bool,
+    /// Last known cursor position, as a fraction of the window size.
+    cursor: [f64; 2],
+    dragging: Option<Handle>,
+}
+
+impl Calibrator {
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Handle a window event.
+    /// Return true if calibration mode was entered or left.
+    pub fn handle_event<E: GenericEvent>(&mut self, e: &E, cfg: &mut ClientConfig) -> bool {
+        if let Some(Button::Keyboard(Key::C)) = e.press_args() {
+            self.active = !self.active;
+            self.dragging = None;
+            info!(
+                "{} calibration mode.",
+                if self.active { "Entering" } else { "Leaving" }
+            );
+            return true;
+        }
+        if !self.active {
+            return false;
+        }
+        let cal = &mut cfg.calibration;
+        if let Some([x, y]) = e.mouse_cursor_args() {
+            self.cursor = [x / cfg.x_extent, y / cfg.y_extent];
+            if let Some(handle) = self.dragging {
+                move_handle(cal, handle, self.cursor);
+            }
+        }
+        match e.press_args() {
+            Some(Button::Mouse(MouseButton::Left)) => {
+                self.dragging = nearest_handle(cal, self.cursor);
+            }
+            Some(Button::Mouse(MouseButton::Right)) => {
+                cal.mask.push(self.cursor);
+            }
+            Some(Button::Keyboard(Key::Backspace)) => {
+                match nearest_handle(cal, self.cursor) {
+                    Some(Handle::Mask(i)) => {
+                        cal.mask.remove(i);
+                    }
+                    _ => {
+                        cal.mask.pop();
+                    }
+                }
+                self.dragging = None;
+            }
+            Some(Button::Keyboard(Key::R)) => {
+                *cal = Calibration::default();
+                self.dragging = None;
+            }
+            Some(Button::Keyboard(Key::S)) => match cfg.save_calibration() {
+                Ok(()) => info!("Saved calibration."),
+                Err(e) => error!("Failed to save calibration: {}", e),
+            },
+            _ => (),
+        }
+        if let Some(Button::Mouse(MouseButton::Left)) = e.release_args() {
+            self.dragging = None;
+        }
+        false
+    }
+
+    /// Draw the calibration handles, if calibrating.
+    pub fn draw<G: Graphics>(&self, cal: &Calibration, size: [f64; 2], c: &Context, gl: &mut G) {
+        if !self.active {
+            return;
+        }
+        let draw_state = DrawState::new_alpha();
+        let scale = |[x, y]: [f64; 2]| [x * size[0], y * size[1]];
+
+        // Outline the warped image.
+        let outline = Line::new(OUTLINE_COLOR, 1.0);
+        for i in 0..4 {
+            let [x0, y0] = scale(cal.corners[i]);
+            let [x1, y1] = scale(cal.corners[(i + 1) % 4]);
+            outline.draw([x0, y0, x1, y1], &draw_state, c.transform, gl);
+        }
+
+        // Mark the inside edge of each blend.
+        let blend_line = Line::new(BLEND_COLOR, 1.0);
+        let b = &cal.blend;
+        for &[x0, y0, x1, y1] in &[
+            [b.left, 0.0, b.left, 1.0],
+            [1.0 - b.right, 0.0, 1.0 - b.right, 1.0],
+            [0.0, b.top, 1.0, b.top],
+            [0.0, 1.0 - b.bottom, 1.0, 1.0 - b.bottom],
+        ] {
+            let [x0, y0] = scale([x0, y0]);
+            let [x1, y1] = scale([x1, y1]);
+            blend_line.draw([x0, y0, x1, y1], &draw_state, c.transform, gl);
+        }
+
+        for (handle, pos) in handles(cal) {
+            let color = match handle {
+                Handle::Corner(_) => CORNER_COLOR,
+                Handle::Mask(_) => MASK_COLOR,
+                _ => BLEND_COLOR,
+            };
+            let [x, y] = scale(pos);
+            let rect = [
+                x - HANDLE_SIZE / 2.0,
+                y - HANDLE_SIZE / 2.0,
+                HANDLE_SIZE,
+                HANDLE_SIZE,
+            ];
+            if Some(handle) == self.dragging {
+                Rectangle::new(color).draw(rect, &draw_state, c.transform, gl);
+            } else {
+                Ellipse::new(color).draw(rect, &draw_state, c.transform, gl);
+            }
+        }
+    }
+}
+
+/// Draw the edge blends and mask over the rendered image.
+pub fn draw_blend_and_mask<G: Graphics>(
+    cal: &Calibration,
+    size: [f64; 2],
+    c: &Context,
+    gl: &mut G,
+) {
+    let draw_state = DrawState::new_alpha();
+    let [w, h] = size;
+    let EdgeBlend {
+        left,
+        right,
+        top,
+        bottom,
+        ..
+    } = cal.blend;
+
+    // Darken each ramp in steps, from the outside edge inwards.
+    for step in 0..BLEND_STEPS {
+        let t0 = step as f64 / BLEND_STEPS as f64;
+        let t1 = (step + 1) as f64 / BLEND_STEPS as f64;
+        let alpha = 1.0 - cal.blend.ramp((t0 + t1) / 2.0);
+        let shade = Rectangle::new([0.0, 0.0, 0.0, alpha as f32]);
+        let mut draw = |rect: [f64; 4]| {
+            if rect[2] > 0.0 && rect[3] > 0.0 {
+                shade.draw(rect, &draw_state, c.transform, gl);
+            }
+        };
+        draw([left * t0 * w, 0.0, left * (t1 - t0) * w, h]);
+        draw([w - right * t1 * w, 0.0, right * (t1 - t0) * w, h]);
+        draw([0.0, top * t0 * h, w, top * (t1 - t0) * h]);
+        draw([0.0, h - bottom * t1 * h, w, bottom * (t1 - t0) * h]);
+    }
+
+    if cal.mask.len() >= 3 {
+        let mask: Vec<[f64; 2]> = cal.mask.iter().map(|[x, y]| [x * w, y * h]).collect();
+        Polygon::new(BLACK).draw(&mask, &draw_state, c.transform, gl);
+    }
+}
+
+/// Every draggable handle and its position.
+fn handles(cal: &Calibration) -> Vec<(Handle, [f64; 2])> {
+    let b = &cal.blend;
+    let mut handles: Vec<(Handle, [f64; 2])> = cal
+        .corners
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (Handle::Corner(i), *p))
+        .collect();
+    handles.push((Handle::BlendLeft, [b.left, 0.5]));
+    handles.push((Handle::BlendRight, [1.0 - b.right, 0.5]));
+    handles.push((Handle::BlendTop, [0.5, b.top]));
+    handles.push((Handle::BlendBottom, [0.5, 1.0 - b.bottom]));
+    handles.extend(
+        cal.mask
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (Handle::Mask(i), *p)),
+    );
+    handles
+}
+
+/// Find the handle closest to a position, if any is close enough to grab.
+fn nearest_handle(cal: &Calibration, [x, y]: [f64; 2]) -> Option<Handle> {
+    handles(cal)
+        .into_iter()
+        .map(|(handle, [hx, hy])| (handle, (hx - x).hypot(hy - y)))
+        .filter(|(_, dist)| *dist <= GRAB_RADIUS)
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(handle, _)| handle)
+}
+
+fn move_handle(cal: &mut Calibration, handle: Handle, [x, y]: [f64; 2]) {
+    let (x, y) = (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0));
+    match handle {
+        Handle::Corner(i) => cal.corners[i] = [x, y],
+        Handle::BlendLeft => cal.blend.left = x,
+        Handle::BlendRight => cal.blend.right = 1.0 - x,
+        Handle::BlendTop => cal.blend.top = y,
+        Handle::BlendBottom => cal.blend.bottom = 1.0 - y,
+        Handle::Mask(i) => cal.mask[i] = [x, y],
+    }
+}
@@ -1,33 +1,54 @@
 //! Loading and parsing client configurations.
+use crate::calibration::{self, Calibration};
 use crate::draw::{Transform, TransformDirection};
 use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::PathBuf;
 use std::time::Duration;
-use yaml_rust::YamlLoader;
+use tunnels_lib::client_profile::ClientProfile;
+use tunnels_lib::projection::{Dome, Panorama, Projection};
+use tunnels_lib::show_id;
+use tunnels_lib::thread_config::ThreadConfig;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClientConfig {
     /// Hostname of the machine running the controller.
     pub server_hostname: String,
+    /// ID of the show to join, if the server has one.
+    pub show_id: Option<String>,
     /// Virtual video channel to listen to.
     pub video_channel: u64,
     /// Delay between current time and time to render.
     pub render_delay: Duration,
     /// Delay between host/client time synchronization updates.
     pub timesync_interval: Duration,
+    /// Requested window size, in logical pixels.
     pub x_resolution: u32,
     pub y_resolution: u32,
     /// If true, perform anti-aliasing.  Adds a small additional GPU load.
     pub anti_alias: bool,
     /// If true, use alpha-blending rather than stomping underlying beams.
     pub alpha_blend: bool,
+    /// If true, add a small amount of noise to each beam's level every frame.
+    /// This hides banding in dim scenes at the cost of slight shimmer.
+    pub dither: bool,
+    /// If true, interpolate between snapshots to draw smoothly at a higher
+    /// frame rate than the server renders at.
+    pub interpolate: bool,
     /// If true, set the window to fullscreen on creation.
     pub fullscreen: bool,
     /// If true, capture and hide the cursor.
     pub capture_mouse: bool,
+    /// Width of the drawing area in logical pixels.
+    /// This may differ from the requested resolution, such as when running
+    /// fullscreen on a display with a different size.
+    pub x_extent: f64,
+    /// Height of the drawing area in logical pixels.
+    pub y_extent: f64,
     /// Used to rescale unit-scale sizes to the current resolution.
     pub critical_size: f64,
     /// Used to rescale unit-scale lineweights to the current resolution.
@@ -40,6 +61,22 @@ pub struct ClientConfig {
     pub transformation: Option<Transform>,
     /// Log at debug level?  This option is ignored when running in remote mode.
     pub log_level_debug: bool,
+    /// Fit of the image to the projection surface.
+    pub calibration: Calibration,
+    /// If set, re-project the image for a dome or a panorama.
+    pub projection: Option<Projection>,
+    /// Scheduling of the thread that draws frames.
+    pub render_thread: ThreadConfig,
+    /// The file this config was loaded from, if any.
+    #[serde(skip)]
+    pub path: Option<String>,
+    /// If set, record every received snapshot to an archive at this path.
+    #[serde(skip)]
+    pub record: Option<PathBuf>,
+    /// If set, play back snapshots from the archive at this path instead of
+    /// connecting to a server.
+    #[serde(skip)]
+    pub playback: Option<PathBuf>,
 }
 
 impl ClientConfig {
@@ -53,6 +90,7 @@ impl ClientConfig {
         anti_alias: bool,
         fullscreen: bool,
         alpha_blend: bool,
+        dither: bool,
         capture_mouse: bool,
         transformation: Option<Transform>,
         log_level_debug: bool,
@@ -61,6 +99,7 @@ impl ClientConfig {
 
         ClientConfig {
             server_hostname: host,
+            show_id: None,
             video_channel,
             render_delay,
             timesync_interval,
@@ -69,16 +108,76 @@ impl ClientConfig {
             anti_alias,
             fullscreen,
             capture_mouse,
+            x_extent: f64::from(x_resolution),
+            y_extent: f64::from(y_resolution),
             critical_size: f64::from(cmp::min(x_resolution, y_resolution)),
             thickness_scale: 0.5,
             x_center: f64::from(x_resolution / 2),
             y_center: f64::from(y_resolution / 2),
             alpha_blend,
+            dither,
+            interpolate: false,
             transformation,
             log_level_debug,
+            calibration: Calibration::default(),
+            projection: None,
+            render_thread: ThreadConfig::default(),
+            path: None,
+            record: None,
+            playback: None,
         }
     }
 
+    /// Create a configuration from a profile managed by the server.
+    pub fn from_profile(
+        profile: ClientProfile,
+        host: String,
+        show_id: Option<String>,
+    ) -> ClientConfig {
+        let transformation = if profile.flip_horizontal {
+            Some(Transform::Flip(TransformDirection::Horizontal))
+        } else {
+            None
+        };
+        let mut config = ClientConfig::new(
+            profile.video_channel,
+            host,
+            profile.resolution,
+            Duration::from_secs(profile.timesync_interval),
+            Duration::from_secs_f64(profile.render_delay),
+            profile.anti_alias,
+            profile.fullscreen,
+            profile.alpha_blend,
+            profile.dither,
+            profile.capture_mouse,
+            transformation,
+            false,
+        );
+        config.show_id = show_id;
+        config.calibration = profile.calibration;
+        config.projection = profile.projection;
+        config
+    }
+
+    /// Recompute drawing geometry to fill a window of the provided logical size.
+    /// Return true if the geometry changed.
+    ///
+    /// All drawing is done in logical pixels; the graphics backend scales
+    /// these onto physical pixels, so high-DPI and fractionally scaled
+    /// displays render with the same geometry as any other.
+    pub fn fit_to_window(&mut self, window_size: [f64; 2]) -> bool {
+        let [x_extent, y_extent] = window_size;
+        if x_extent == self.x_extent && y_extent == self.y_extent {
+            return false;
+        }
+        self.x_extent = x_extent;
+        self.y_extent = y_extent;
+        self.critical_size = x_extent.min(y_extent);
+        self.x_center = (x_extent / 2.0).floor();
+        self.y_center = (y_extent / 2.0).floor();
+        true
+    }
+
     /// Loads, parses, and returns a config from path.
     /// This method panics if anything is wrong and is only appropriate for use during one-time
     /// initialization.
@@ -111,7 +210,7 @@ impl ClientConfig {
             None
         };
 
-        Ok(ClientConfig::new(
+        let mut config = ClientConfig::new(
             video_channel,
             host,
             (x_resolution, y_resolution),
@@ -120,11 +219,85 @@ impl ClientConfig {
             flag("anti_alias", "Bad anti-alias flag.")?,
             flag("fullscreen", "Bad fullscreen flag.")?,
             flag("alpha_blend", "Bad alpha blend flag.")?,
+            // Optional for compatibility with existing configurations.
+            cfg["dither"].as_bool().unwrap_or(false),
             flag("capture_mouse", "Bad mouse capture flag.")?,
             transformation,
             flag("log_level_debug", "Bad log level flag.")?,
-        ))
+        );
+        config.show_id = match &cfg["show_id"] {
+            Yaml::BadValue => None,
+            v => {
+                let id = v.as_str().ok_or("Bad show ID.")?;
+                show_id::validate(id)?;
+                Some(id.to_string())
+            }
+        };
+        // Optional for compatibility with existing configurations.
+        config.interpolate = cfg["interpolate"].as_bool().unwrap_or(false);
+        config.calibration = calibration::from_yaml(cfg)?;
+        config.projection = projection_from_yaml(cfg)?;
+        config.render_thread = ThreadConfig {
+            core: match &cfg["render_core"] {
+                Yaml::BadValue => None,
+                v => Some(v.as_i64().filter(|c| *c >= 0).ok_or("Bad render core.")? as usize),
+            },
+            high_priority: cfg["render_high_priority"].as_bool().unwrap_or(false),
+        };
+        config.path = Some(config_path.to_string());
+        Ok(config)
+    }
+
+    /// Write the current calibration back into the config file it was loaded
+    /// from, leaving the rest of the file's settings as they were.
+    pub fn save_calibration(&self) -> Result<(), Box<dyn Error>> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or("This config was not loaded from a file.")?;
+        let mut docs = YamlLoader::load_from_str(&fs::read_to_string(path)?)?;
+        match docs.get_mut(0) {
+            Some(Yaml::Hash(doc)) => calibration::write_yaml(&self.calibration, doc),
+            _ => return Err(format!("Config file {} is not a YAML mapping.", path).into()),
+        }
+        let mut out = String::new();
+        YamlEmitter::new(&mut out).dump(&docs[0])?;
+        out.push('\n');
+        fs::write(path, out)?;
+        Ok(())
     }
 }
 
+/// Load an optional dome or panorama projection.  Every setting of each has
+/// a default.
+fn projection_from_yaml(cfg: &Yaml) -> Result<Option<Projection>, Box<dyn Error>> {
+    let (dome, pano) = (&cfg["dome"], &cfg["equirectangular"]);
+    let angle = |section: &Yaml, name: &str, current: f64| -> Result<f64, Box<dyn Error>> {
+        match &section[name] {
+            Yaml::BadValue => Ok(current),
+            v => Ok(calibration::as_f64(v).ok_or(format!("Bad projection {}.", name))?),
+        }
+    };
+    let projection = match (dome.is_badvalue(), pano.is_badvalue()) {
+        (true, true) => return Ok(None),
+        (false, false) => return Err("Only one of dome and equirectangular may be set.".into()),
+        (false, true) => {
+            let d = Dome::default();
+            Projection::Dome(Dome {
+                tilt: angle(dome, "tilt", d.tilt)?,
+                edge_angle: angle(dome, "edge_angle", d.edge_angle)?,
+            })
+        }
+        (true, false) => {
+            let p = Panorama::default();
+            Projection::Equirectangular(Panorama {
+                elevation: angle(pano, "elevation", p.elevation)?,
+                edge_angle: angle(pano, "edge_angle", p.edge_angle)?,
+            })
+        }
+    };
+    projection.validate()?;
+    Ok(Some(projection))
+}
+
 pub type Resolution = (u32, u32);
@@ -1,16 +1,26 @@
-use std::sync::Arc;
+//! Drawing snapshots with an instanced shader.
+//!
+//! Each layer of arcs is drawn with a single instanced draw call.  Every arc
+//! is packed into a record of the instance buffer holding its placement,
+//! radii, angles, and color, and the vertex shader builds the arc's triangles
+//! from that record.  Projection mapping and the calibration warp are applied
+//! in the shader as well, so nothing is tessellated on the CPU.
+use std::cell::Cell;
+use std::error::Error;
+use std::ffi::CString;
+use std::mem::size_of;
+use std::ptr;
 
 use crate::config::ClientConfig;
 use crate::constants::TWOPI;
-use graphics::radians::Radians;
-use graphics::triangulation::stream_quad_tri_list;
-use graphics::types::Color;
-use graphics::types::{Matrix2d, Radius, Rectangle, Resolution, Scalar};
-use graphics::{rectangle, CircleArc, DrawState, Graphics, Transformed};
-use piston_window::Context;
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLsizeiptr, GLuint};
+use graphics::color::gamma_srgb_to_linear;
+use graphics::types::{Color, Matrix2d};
+use graphics::Transformed;
+use opengl_graphics::GlGraphics;
 use serde::{Deserialize, Serialize};
-use tunnels_lib::ArcSegment;
-use tunnels_lib::Snapshot;
+use tunnels_lib::color;
+use tunnels_lib::{ArcSegment, BlendMode, Snapshot};
 
 /// The axis along which to perform a transformation.
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
@@ -28,164 +38,121 @@ pub enum Transform {
     //Mirror(TransformDirection),
 }
 
-pub trait Draw<G: Graphics> {
-    /// Given a context and gl instance, draw this entity to the screen.
-    fn draw(&self, c: &Context, gl: &mut G, cfg: &ClientConfig);
+#[inline]
+fn color_from_rgb(r: f64, g: f64, b: f64, a: f64) -> Color {
+    [r as f32, g as f32, b as f32, a as f32]
 }
 
-impl<T, G> Draw<G> for Vec<T>
-where
-    G: Graphics,
-    T: Draw<G>,
-{
-    fn draw(&self, c: &Context, gl: &mut G, cfg: &ClientConfig) {
-        for e in self {
-            e.draw(c, gl, cfg);
-        }
-    }
+thread_local! {
+    /// State of the dither noise generator.
+    static DITHER_STATE: Cell<u32> = Cell::new(0x9E37_79B9);
 }
 
-impl<T, G> Draw<G> for Arc<T>
-where
-    G: Graphics,
-    T: Draw<G>,
-{
-    fn draw(&self, c: &Context, gl: &mut G, cfg: &ClientConfig) {
-        (**self).draw(c, gl, cfg);
-    }
+/// Return uniform noise spanning one step of an 8-bit color channel.
+/// A fast xorshift generator is plenty; this only needs to look random.
+fn dither_noise() -> f64 {
+    let x = DITHER_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        state.set(x);
+        x
+    });
+    (f64::from(x) / f64::from(u32::MAX) - 0.5) / 255.0
 }
 
-#[inline]
-fn color_from_rgb(r: f64, g: f64, b: f64, a: f64) -> Color {
-    [r as f32, g as f32, b as f32, a as f32]
+/// Offset a level by dither noise.  Fully dark and fully bright levels are
+/// left alone, so black stays black.
+fn dither(level: f64) -> f64 {
+    if level <= 0.0 || level >= 1.0 {
+        level
+    } else {
+        (level + dither_noise()).clamp(0.0, 1.0)
+    }
 }
 
 /// Convert HSV to a Piston RGB color.
+/// The conversion is shared with the server, so that anything it computes
+/// from the color of an arc matches what is drawn.
 #[inline]
 fn hsv_to_rgb(hue: f64, sat: f64, val: f64, alpha: f64) -> Color {
-    if sat == 0.0 {
-        color_from_rgb(val, val, val, alpha)
-    } else {
-        let var_h = if hue == 1.0 { 0.0 } else { hue * 6.0 };
-
-        let var_i = var_h.floor();
-        let var_1 = val * (1.0 - sat);
-        let var_2 = val * (1.0 - sat * (var_h - var_i));
-        let var_3 = val * (1.0 - sat * (1.0 - (var_h - var_i)));
-
-        match var_i as i64 {
-            0 => color_from_rgb(val, var_3, var_1, alpha),
-            1 => color_from_rgb(var_2, val, var_1, alpha),
-            2 => color_from_rgb(var_1, val, var_3, alpha),
-            3 => color_from_rgb(var_1, var_2, val, alpha),
-            4 => color_from_rgb(var_3, var_1, val, alpha),
-            _ => color_from_rgb(val, var_1, var_2, alpha),
-        }
-    }
+    let [r, g, b] = color::hsv_to_rgb(hue, sat, val);
+    color_from_rgb(r, g, b, alpha)
 }
 
-/// Draws circle arc using triangulation.
-pub fn draw_circle_arc_improved<R: Into<Rectangle>, G>(
-    ca: &CircleArc,
-    rectangle: R,
-    draw_state: &DrawState,
-    transform: Matrix2d,
-    g: &mut G,
-) where
-    G: Graphics,
-{
-    let rectangle = rectangle.into();
-    g.tri_list(&draw_state, &ca.color, |f| {
-        improved_with_arc_tri_list(
-            ca.start,
-            ca.end,
-            ca.resolution,
-            transform,
-            rectangle,
-            ca.radius,
-            |vertices| f(vertices),
-        )
-    });
-}
+/// Most quads drawn for a single arc.  A full circle is drawn with this many,
+/// and shorter arcs with proportionally fewer.
+const MAX_QUADS: usize = 128;
 
-/// Streams an arc between the two radian boundaries.
-#[inline(always)]
-fn improved_with_arc_tri_list<F>(
-    start_radians: Scalar,
-    end_radians: Scalar,
-    resolution: Resolution,
-    m: Matrix2d,
-    rect: Rectangle,
-    border_radius: Radius,
-    f: F,
-) where
-    F: FnMut(&[[f32; 2]]),
-{
-    let (x, y, w, h) = (rect[0], rect[1], rect[2], rect[3]);
-    let (cw, ch) = (0.5 * w, 0.5 * h);
-    let (cw1, ch1) = (cw + border_radius, ch + border_radius);
-    let (cw2, ch2) = (cw - border_radius, ch - border_radius);
-    let (cx, cy) = (x + cw, y + ch);
-    let mut i = 0;
-
-    let twopi = <Scalar as Radians>::_360();
-    let max_seg_size = twopi / resolution as Scalar;
-
-    // Take true modulus by 2pi.
-    let delta = (((end_radians - start_radians) % twopi) + twopi) % twopi;
-
-    // Taking ceiling here implies that the resolution parameter provides a
-    // lower bound on the drawn resolution.
-    let n_quads = (delta / max_seg_size).ceil() as u64;
-
-    // n_quads * seg_size exactly spans the included angle.
-    let seg_size = delta / n_quads as Scalar;
-    stream_quad_tri_list(
-        m,
-        || {
-            if i > n_quads {
-                return None;
-            }
+/// Vertices drawn for each arc: two triangles for each of its quads.
+/// Vertices of quads an arc doesn't use are collapsed to a point.
+const VERTICES_PER_ARC: usize = 6 * MAX_QUADS;
 
-            let angle = start_radians + (i as Scalar * seg_size);
-
-            let cos = angle.cos();
-            let sin = angle.sin();
-            i += 1;
-            Some((
-                [cx + cos * cw1, cy + sin * ch1],
-                [cx + cos * cw2, cy + sin * ch2],
-            ))
-        },
-        f,
-    );
+/// An arc colored and positioned for drawing, as laid out in the instance
+/// buffer.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ArcInstance {
+    /// Rows of the affine transformation from the arc's frame, centered on
+    /// the arc, to normalized device coordinates.
+    transform: [[f32; 3]; 2],
+    /// Horizontal and vertical radius of the middle of the arc.
+    radius: [f32; 2],
+    /// Half the thickness of the arc.
+    border: f32,
+    /// Angle at which the arc starts, in radians.
+    start: f32,
+    /// Angle spanned by each quad, in radians.
+    step: f32,
+    /// Number of quads drawn; a float, since instance attributes are.
+    quads: f32,
+    color: [f32; 4],
 }
 
-impl<G: Graphics> Draw<G> for ArcSegment {
-    fn draw(&self, c: &Context, gl: &mut G, cfg: &ClientConfig) {
-        let thickness = self.thickness * cfg.critical_size * cfg.thickness_scale / 2.0;
+impl ArcInstance {
+    /// Place an arc of a layer blended in the provided mode.  The base
+    /// transformation maps window coordinates to normalized device
+    /// coordinates.
+    pub fn new(arc: &ArcSegment, blend: BlendMode, base: Matrix2d, cfg: &ClientConfig) -> Self {
+        let thickness = arc.thickness * cfg.critical_size * cfg.thickness_scale / 2.0;
 
-        let (val, alpha) = if cfg.alpha_blend {
-            (self.val, self.level)
+        // Additive blending ignores alpha, so it always carries the beam level
+        // in the color.
+        let carry_in_alpha = cfg.alpha_blend && blend != BlendMode::Additive;
+        let (mut val, mut alpha) = if carry_in_alpha {
+            (arc.val, arc.level)
         } else {
-            (self.val * self.level, 1.0)
+            (arc.val * arc.level, 1.0)
         };
-
-        let color = hsv_to_rgb(self.hue, self.sat, val, alpha);
+        // Dither whichever component carries the beam level.
+        if cfg.dither {
+            if carry_in_alpha {
+                alpha = dither(alpha);
+            } else {
+                val = dither(val);
+            }
+        }
+        // Masks darken by as much as a beam would light.
+        if blend == BlendMode::Mask {
+            val = 0.0;
+        }
+        // Colors are blended in linear light, as the rest of the window is.
+        let color = gamma_srgb_to_linear(hsv_to_rgb(arc.hue, arc.sat, val, alpha));
 
         let (x, y) = {
             let (x0, y0) = match cfg.transformation {
-                None => (self.x, self.y),
-                Some(Transform::Flip(TransformDirection::Horizontal)) => (-1.0 * self.x, self.y),
-                Some(Transform::Flip(TransformDirection::Vertical)) => (self.x, -1.0 * self.y),
+                None => (arc.x, arc.y),
+                Some(Transform::Flip(TransformDirection::Horizontal)) => (-1.0 * arc.x, arc.y),
+                Some(Transform::Flip(TransformDirection::Vertical)) => (arc.x, -1.0 * arc.y),
             };
-            let x = x0 * f64::from(cfg.x_resolution) + cfg.x_center;
-            let y = y0 * f64::from(cfg.y_resolution) + cfg.y_center;
+            let x = x0 * cfg.x_extent + cfg.x_center;
+            let y = y0 * cfg.y_extent + cfg.y_center;
             (x, y)
         };
 
         let transform = {
-            let t = c.transform.trans(x, y).rot_rad(self.rot_angle * TWOPI);
+            let t = base.trans(x, y).rot_rad(arc.rot_angle * TWOPI);
             match cfg.transformation {
                 None => t,
                 Some(Transform::Flip(TransformDirection::Horizontal)) => t.flip_h(),
@@ -193,22 +160,385 @@ impl<G: Graphics> Draw<G> for ArcSegment {
             }
         };
 
-        let x_size = self.rad_x * cfg.critical_size;
-        let y_size = self.rad_y * cfg.critical_size;
+        let start = arc.start * TWOPI;
+        // Take true modulus by 2pi.
+        let delta = (((arc.stop * TWOPI - start) % TWOPI) + TWOPI) % TWOPI;
+        // Taking the ceiling makes MAX_QUADS a lower bound on the drawn
+        // resolution, and the quads exactly span the included angle.
+        let quads = (delta / (TWOPI / MAX_QUADS as f64)).ceil();
+        let step = if quads > 0.0 { delta / quads } else { 0.0 };
+
+        let row = |r: [f64; 3]| [r[0] as f32, r[1] as f32, r[2] as f32];
+        Self {
+            transform: [row(transform[0]), row(transform[1])],
+            radius: [
+                (arc.rad_x * cfg.critical_size) as f32,
+                (arc.rad_y * cfg.critical_size) as f32,
+            ],
+            border: thickness as f32,
+            start: start as f32,
+            step: step as f32,
+            quads: quads.min(MAX_QUADS as f64) as f32,
+            color,
+        }
+    }
+}
+
+/// Pack every arc of a layer into instances, replacing the contents of out.
+pub fn pack_layer(
+    layer: &[ArcSegment],
+    blend: BlendMode,
+    base: Matrix2d,
+    cfg: &ClientConfig,
+    out: &mut Vec<ArcInstance>,
+) {
+    out.clear();
+    out.extend(
+        layer
+            .iter()
+            .map(|arc| ArcInstance::new(arc, blend, base, cfg)),
+    );
+}
+
+/// Builds the triangles of each arc from its instance record.  The quad and
+/// corner come from the vertex index; each quad is two triangles.
+/// A panorama wraps around at its left and right edges, so it is drawn with
+/// two copies of every arc, and a quad that crosses the seam is unwrapped
+/// around its first corner and drawn once on each side of it.
+/// project mirrors tunnels_lib::projection::ProjectionMapping::apply.
+const VERTEX_SHADER: &str = r#"
+#version 330 core
+
+const float PI = 3.14159265358979;
+const int NO_PROJECTION = 0;
+const int DOME = 1;
+const int PANORAMA = 2;
+
+const ivec2 CORNERS[6] = ivec2[6](
+    ivec2(0, 1), ivec2(0, 0), ivec2(1, 1),
+    ivec2(1, 1), ivec2(0, 0), ivec2(1, 0)
+);
+
+layout(location = 0) in vec3 row0;
+layout(location = 1) in vec3 row1;
+// Radii, border, and start angle.
+layout(location = 2) in vec4 shape;
+// Step angle and quad count.
+layout(location = 3) in vec2 steps;
+layout(location = 4) in vec4 color;
+
+uniform int projection;
+// Focal length, and the sine and cosine of the rotation.
+uniform vec3 projection_params;
+uniform vec2 projection_scale;
+uniform mat3 warp;
+
+flat out vec4 v_color;
+
+vec2 place(int i, bool outer) {
+    float angle = shape.w + float(i) * steps.x;
+    float border = outer ? shape.z : -shape.z;
+    vec3 local = vec3(cos(angle) * (shape.x + border), sin(angle) * (shape.y + border), 1.0);
+    return vec2(dot(row0, local), dot(row1, local));
+}
+
+vec2 project(vec2 ndc) {
+    vec2 uv = ndc / projection_scale;
+    float f = projection_params.x;
+    float s = projection_params.y;
+    float c = projection_params.z;
+    float x = uv.x;
+    float y = uv.y * c + f * s;
+    float z = f * c - uv.y * s;
+    if (projection == PANORAMA) {
+        return vec2(atan(x, z) / PI, atan(y, length(vec2(x, z))) / (PI / 2.0));
+    }
+    float horizontal = length(vec2(x, y));
+    if (horizontal == 0.0) {
+        return vec2(0.0);
+    }
+    float r = atan(horizontal, z) / (PI / 2.0);
+    return vec2(x, y) * r / horizontal * projection_scale;
+}
+
+void main() {
+    v_color = color;
+    int quad = gl_VertexID / 6;
+    if (float(quad) >= steps.y) {
+        gl_Position = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+    ivec2 corner = CORNERS[gl_VertexID % 6];
+    vec2 p = place(quad + corner.x, corner.y == 1);
+    if (projection != NO_PROJECTION) {
+        p = project(p);
+    }
+    if (projection == PANORAMA) {
+        float reference = project(place(quad, true)).x;
+        if (p.x - reference > 1.0) {
+            p.x -= 2.0;
+        } else if (reference - p.x > 1.0) {
+            p.x += 2.0;
+        }
+        if (gl_InstanceID % 2 == 1) {
+            p.x -= 2.0 * sign(reference);
+        }
+    }
+    vec3 warped = warp * vec3(p, 1.0);
+    gl_Position = vec4(warped.xy / warped.z, 0.0, 1.0);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"
+#version 330 core
+
+flat in vec4 v_color;
+out vec4 frag_color;
+
+void main() {
+    frag_color = v_color;
+}
+"#;
+
+/// Projection uniform values, matching the constants in the vertex shader.
+const NO_PROJECTION: GLint = 0;
+const DOME: GLint = 1;
+const PANORAMA: GLint = 2;
+
+const IDENTITY: [f32; 9] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+
+/// Draws snapshots with the instanced arc shader.
+/// The shader and buffers live in the GL context that was current when the
+/// renderer was created, and must be dropped before that context is.
+pub struct ArcRenderer {
+    program: GLuint,
+    vao: GLuint,
+    instance_buffer: GLuint,
+    projection: GLint,
+    projection_params: GLint,
+    projection_scale: GLint,
+    warp: GLint,
+    /// Reused between layers to avoid allocating every frame.
+    instances: Vec<ArcInstance>,
+}
+
+impl ArcRenderer {
+    /// Compile the shaders and create the buffers in the current GL context.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        unsafe {
+            let vertex = compile(gl::VERTEX_SHADER, VERTEX_SHADER)?;
+            let fragment = match compile(gl::FRAGMENT_SHADER, FRAGMENT_SHADER) {
+                Ok(fragment) => fragment,
+                Err(e) => {
+                    gl::DeleteShader(vertex);
+                    return Err(e);
+                }
+            };
+            let program = link(vertex, fragment)?;
+
+            let (mut vao, mut instance_buffer) = (0, 0);
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut instance_buffer);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, instance_buffer);
+            // Location, size, and offset in floats of each instance attribute.
+            for &(location, size, offset) in
+                &[(0, 3, 0), (1, 3, 3), (2, 4, 6), (3, 2, 10), (4, 4, 12)]
+            {
+                gl::EnableVertexAttribArray(location);
+                gl::VertexAttribPointer(
+                    location,
+                    size,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    size_of::<ArcInstance>() as GLsizei,
+                    (offset * size_of::<f32>()) as *const _,
+                );
+            }
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            Ok(Self {
+                program,
+                vao,
+                instance_buffer,
+                projection: uniform(program, "projection"),
+                projection_params: uniform(program, "projection_params"),
+                projection_scale: uniform(program, "projection_scale"),
+                warp: uniform(program, "warp"),
+                instances: Vec::new(),
+            })
+        }
+    }
+
+    /// Draw every layer of a snapshot, blending each with what has already
+    /// been drawn.  Call from inside GlGraphics::draw, before drawing
+    /// anything other than clearing, since the renderer draws immediately
+    /// while GlGraphics holds on to triangles until the end of the frame.
+    pub fn draw(
+        &mut self,
+        snapshot: &Snapshot,
+        base: Matrix2d,
+        gl: &mut GlGraphics,
+        cfg: &ClientConfig,
+    ) {
+        let (projection, params, scale) = match &cfg.projection {
+            None => (NO_PROJECTION, [0.0; 3], [1.0; 2]),
+            Some(projection) => {
+                let mapping = projection.mapping([
+                    cfg.critical_size / cfg.x_extent,
+                    cfg.critical_size / cfg.y_extent,
+                ]);
+                (
+                    if mapping.panorama { PANORAMA } else { DOME },
+                    [
+                        mapping.focal_length as f32,
+                        mapping.rotation_sin as f32,
+                        mapping.rotation_cos as f32,
+                    ],
+                    [mapping.scale[0] as f32, mapping.scale[1] as f32],
+                )
+            }
+        };
+        let warp = cfg
+            .calibration
+            .warp()
+            .map_or(IDENTITY, |warp| warp.ndc_matrix());
+        let copies = if projection == PANORAMA { 2 } else { 1 };
+
+        unsafe {
+            gl::UseProgram(self.program);
+            gl::Uniform1i(self.projection, projection);
+            gl::Uniform3f(self.projection_params, params[0], params[1], params[2]);
+            gl::Uniform2f(self.projection_scale, scale[0], scale[1]);
+            gl::UniformMatrix3fv(self.warp, 1, gl::FALSE, warp.as_ptr());
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_buffer);
+            // Each copy of a panorama's arcs reads the same record.
+            for location in 0..5 {
+                gl::VertexAttribDivisor(location, copies);
+            }
+            gl::Enable(gl::BLEND);
+            gl::BlendEquation(gl::FUNC_ADD);
+        }
 
-        let bound = rectangle::centered([0.0, 0.0, x_size, y_size]);
+        for (i, layer) in snapshot.layers.iter().enumerate() {
+            let blend = snapshot.blend_mode(i);
+            pack_layer(layer, blend, base, cfg, &mut self.instances);
+            if self.instances.is_empty() {
+                continue;
+            }
+            unsafe {
+                match blend {
+                    BlendMode::Additive => {
+                        gl::BlendFuncSeparate(gl::ONE, gl::ONE, gl::ONE, gl::ONE)
+                    }
+                    BlendMode::Alpha | BlendMode::Mask => gl::BlendFuncSeparate(
+                        gl::SRC_ALPHA,
+                        gl::ONE_MINUS_SRC_ALPHA,
+                        gl::ONE,
+                        gl::ONE,
+                    ),
+                }
+                // Orphan the previous contents rather than waiting for draws
+                // still reading them.
+                gl::BufferData(
+                    gl::ARRAY_BUFFER,
+                    (self.instances.len() * size_of::<ArcInstance>()) as GLsizeiptr,
+                    self.instances.as_ptr() as *const _,
+                    gl::STREAM_DRAW,
+                );
+                gl::DrawArraysInstanced(
+                    gl::TRIANGLES,
+                    0,
+                    VERTICES_PER_ARC as GLsizei,
+                    (self.instances.len() as GLuint * copies) as GLsizei,
+                );
+            }
+        }
 
-        let start = self.start * TWOPI;
-        let stop = self.stop * TWOPI;
+        unsafe {
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::UseProgram(0);
+        }
+        // GlGraphics tracks the program and blending it last set, which are
+        // no longer current.
+        gl.clear_program();
+        gl.clear_draw_state();
+    }
+}
+
+impl Drop for ArcRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.instance_buffer);
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
 
-        let ca = CircleArc::new(color, thickness, start, stop);
-        //ca.draw(bound, &Default::default(), transform, gl);
-        draw_circle_arc_improved(&ca, bound, &Default::default(), transform, gl);
+unsafe fn compile(kind: GLenum, source: &str) -> Result<GLuint, Box<dyn Error>> {
+    let shader = gl::CreateShader(kind);
+    let source = CString::new(source)?;
+    gl::ShaderSource(shader, 1, &source.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+    let mut status = GLint::from(gl::FALSE);
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+    if status == GLint::from(gl::TRUE) {
+        return Ok(shader);
     }
+    let mut len = 0;
+    gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
+    let mut log = vec![0u8; len.max(1) as usize];
+    gl::GetShaderInfoLog(
+        shader,
+        len,
+        ptr::null_mut(),
+        log.as_mut_ptr() as *mut GLchar,
+    );
+    gl::DeleteShader(shader);
+    Err(format!(
+        "Arc shader failed to compile: {}",
+        String::from_utf8_lossy(&log).trim_end_matches('\0')
+    )
+    .into())
 }
 
-impl<G: Graphics> Draw<G> for Snapshot {
-    fn draw(&self, c: &Context, gl: &mut G, cfg: &ClientConfig) {
-        self.layers.draw(c, gl, cfg);
+/// Link a program from its shaders, which are then no longer needed.
+unsafe fn link(vertex: GLuint, fragment: GLuint) -> Result<GLuint, Box<dyn Error>> {
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vertex);
+    gl::AttachShader(program, fragment);
+    gl::LinkProgram(program);
+    gl::DetachShader(program, vertex);
+    gl::DetachShader(program, fragment);
+    gl::DeleteShader(vertex);
+    gl::DeleteShader(fragment);
+    let mut status = GLint::from(gl::FALSE);
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+    if status == GLint::from(gl::TRUE) {
+        return Ok(program);
     }
+    let mut len = 0;
+    gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
+    let mut log = vec![0u8; len.max(1) as usize];
+    gl::GetProgramInfoLog(
+        program,
+        len,
+        ptr::null_mut(),
+        log.as_mut_ptr() as *mut GLchar,
+    );
+    gl::DeleteProgram(program);
+    Err(format!(
+        "Arc shader failed to link: {}",
+        String::from_utf8_lossy(&log).trim_end_matches('\0')
+    )
+    .into())
+}
+
+unsafe fn uniform(program: GLuint, name: &str) -> GLint {
+    let name = CString::new(name).expect("Uniform names have no nul bytes.");
+    gl::GetUniformLocation(program, name.as_ptr())
 }
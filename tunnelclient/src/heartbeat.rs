@@ -0,0 +1,48 @@
+//! Let the server know which video channel this client is showing.
+
+use log::{info, warn};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use std::{error::Error, thread};
+use tunnels_lib::{
+    heartbeat::{Heartbeat, INTERVAL, PORT},
+    RunFlag,
+};
+use zmq::{Context, DONTWAIT};
+
+/// Send heartbeats to the server until the run flag is tripped.
+pub fn start_heartbeat(
+    host: &str,
+    video_channel: u64,
+    show_id: Option<String>,
+    ctx: &mut Context,
+    run_flag: RunFlag,
+) -> Result<(), Box<dyn Error>> {
+    let socket = ctx.socket(zmq::PUSH)?;
+    // Don't hold up shutdown trying to deliver stale heartbeats.
+    socket.set_linger(0)?;
+    socket.connect(&format!("tcp://{}:{}", host, PORT))?;
+
+    let mut msg = Vec::new();
+    Heartbeat {
+        video_channel,
+        show_id,
+    }
+    .serialize(&mut Serializer::new(&mut msg))?;
+
+    thread::Builder::new()
+        .name("heartbeat".to_string())
+        .spawn(move || {
+            while run_flag.should_run() {
+                match socket.send(&msg, DONTWAIT) {
+                    // The server isn't listening; try again next time.
+                    Ok(()) | Err(zmq::Error::EAGAIN) => (),
+                    Err(e) => warn!("Heartbeat send error: {}.", e),
+                }
+                thread::sleep(INTERVAL);
+            }
+            info!("Heartbeat service shutting down.");
+        })
+        .map_err(|e| format!("Heartbeat service thread failed to spawn: {}", e))?;
+    Ok(())
+}
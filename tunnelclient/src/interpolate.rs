@@ -3,8 +3,8 @@
 use std::sync::Arc;
 
 use interpolation::lerp;
+use tunnels_lib::angle::interpolate as interpolate_angle;
 use tunnels_lib::ArcSegment;
-use tunnels_lib::{min_included_angle, modulo};
 
 /// Allow an entity to be interpolated with another instance of Self.
 pub trait Interpolate {
@@ -34,17 +34,17 @@ impl<T: Interpolate> Interpolate for Arc<T> {
     }
 }
 
-/// Interpolate a pytunnel-style unit angle.
-/// Ensure that we always interpolate along the shortest path between the two angular coordinates
-/// that we are easing between.
-#[inline(always)]
-fn interpolate_angle(a: f64, b: f64, alpha: f64) -> f64 {
-    let shortest_angle = min_included_angle(a, b);
-    modulo(a + shortest_angle * alpha, 1.0)
-}
-
 impl Interpolate for ArcSegment {
+    /// Angles take the short way around.  The start of the arc is treated as
+    /// an angle and its extent as a length, so that a full circle stays full
+    /// rather than collapsing when its stop wraps onto its start.
     fn interpolate_with(&self, other: &Self, alpha: f64) -> Self {
+        let start = interpolate_angle(self.start, other.start, alpha);
+        let extent = lerp(
+            &(self.stop - self.start),
+            &(other.stop - other.start),
+            &alpha,
+        );
         ArcSegment {
             level: lerp(&self.level, &other.level, &alpha),
             thickness: lerp(&self.thickness, &other.thickness, &alpha),
@@ -55,9 +55,10 @@ impl Interpolate for ArcSegment {
             y: lerp(&self.y, &other.y, &alpha),
             rad_x: lerp(&self.rad_x, &other.rad_x, &alpha),
             rad_y: lerp(&self.rad_y, &other.rad_y, &alpha),
-            start: interpolate_angle(self.start, other.start, alpha),
-            stop: interpolate_angle(self.stop, other.stop, alpha),
+            start,
+            stop: start + extent,
             rot_angle: interpolate_angle(self.rot_angle, other.rot_angle, alpha),
+            palette: self.palette,
         }
     }
 }
@@ -67,16 +68,6 @@ mod tests {
     use super::*;
     use crate::receive::test::arc_segment_for_test;
     use interpolation::lerp;
-    use tunnels_lib::assert_almost_eq;
-
-    #[test]
-    fn test_interp_angle() {
-        assert_almost_eq(0.0, interpolate_angle(0.0, 0.0, 0.0));
-        assert_almost_eq(0.0, interpolate_angle(0.0, 1.0, 0.5));
-        assert_almost_eq(0.95, interpolate_angle(0.0, 0.9, 0.5));
-        assert_almost_eq(0.0, interpolate_angle(0.2, 0.8, 0.5));
-        assert_almost_eq(0.0, interpolate_angle(0.2, 0.8, 0.5));
-    }
 
     #[test]
     fn test_interp_arcs() {
@@ -88,6 +79,19 @@ mod tests {
         assert_eq!(halfway, a.interpolate_with(&b, 0.5));
     }
 
+    #[test]
+    fn test_interp_full_circle() {
+        let mut a = arc_segment_for_test(0.0, 0.0);
+        a.start = 0.0;
+        a.stop = 1.0;
+        let mut b = a.clone();
+        b.start = 0.9;
+        b.stop = 1.9;
+        let halfway = a.interpolate_with(&b, 0.5);
+        assert!((halfway.start - 0.95).abs() < 1e-9);
+        assert!((halfway.stop - halfway.start - 1.0).abs() < 1e-9);
+    }
+
     impl Interpolate for f64 {
         fn interpolate_with(&self, other: &Self, alpha: f64) -> Self {
             lerp(self, other, &alpha)
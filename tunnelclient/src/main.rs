@@ -3,11 +3,18 @@ mod constants {
     pub const TWOPI: f64 = 2.0 * PI;
 }
 
+mod bench;
+mod calibration;
+mod calibrator;
 mod config;
 mod draw;
+mod heartbeat;
 mod interpolate;
+mod playback;
 mod receive;
+mod recovery;
 mod remote;
+mod sanitize;
 mod show;
 mod snapshot_manager;
 mod timesync;
@@ -17,14 +24,16 @@ use crate::remote::{administrate, run_remote};
 use crate::show::Show;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use std::env;
-use tunnels_lib::RunFlag;
+use std::path::PathBuf;
+use tunnels_lib::{show_id, RunFlag};
 use zmq::Context;
 
 fn main() {
     // Check if running in remote mode.
     let first_arg = env::args().nth(1).expect(
         "First argument must be 'remote' to run in remote mode, \
-        'admin' to run the client administrator,
+        'admin' to run the client administrator, either optionally followed by a show ID,
+         '--bench' to time drawing synthetic frames,
          or the integer virtual video channel to listen to.",
     );
 
@@ -32,10 +41,21 @@ fn main() {
 
     if first_arg == "remote" {
         init_logger(LevelFilter::Info);
-        run_remote(&mut ctx);
+        run_remote(&mut ctx, show_id_arg());
     } else if first_arg == "admin" {
         init_logger(LevelFilter::Info);
-        administrate();
+        administrate(show_id_arg());
+    } else if first_arg == "--bench" {
+        let config_path = env::args().nth(2).expect("No config path arg provided.");
+        let cfg = ClientConfig::load(0, &config_path).expect("Failed to load config");
+        let count = |n: usize, default: usize| -> usize {
+            env::args().nth(n).map_or(default, |arg| {
+                arg.parse()
+                    .expect("Frame and arc counts must be positive integers.")
+            })
+        };
+        init_logger(LevelFilter::Info);
+        bench::run(&cfg, count(3, 1000), count(4, 2000)).expect("Bench failed");
     } else {
         let video_channel: u64 = first_arg
             .parse()
@@ -43,7 +63,21 @@ fn main() {
 
         let config_path = env::args().nth(2).expect("No config path arg provided.");
 
-        let cfg = ClientConfig::load(video_channel, &config_path).expect("Failed to load config");
+        let mut cfg =
+            ClientConfig::load(video_channel, &config_path).expect("Failed to load config");
+
+        // Optionally record what we receive, or play back a recording.
+        if let Some(mode) = env::args().nth(3) {
+            let path = env::args()
+                .nth(4)
+                .map(PathBuf::from)
+                .expect("No recording path arg provided.");
+            match mode.as_str() {
+                "record" => cfg.record = Some(path),
+                "play" => cfg.playback = Some(path),
+                _ => panic!("Unknown mode {}; expected 'record' or 'play'.", mode),
+            }
+        }
         init_logger(if cfg.log_level_debug {
             LevelFilter::Debug
         } else {
@@ -56,6 +90,15 @@ fn main() {
     }
 }
 
+/// In remote and admin modes, the optional second argument is a show ID.
+fn show_id_arg() -> Option<String> {
+    let id = env::args().nth(2)?;
+    if let Err(e) = show_id::validate(&id) {
+        panic!("{}", e);
+    }
+    Some(id)
+}
+
 fn init_logger(level: LevelFilter) {
     SimpleLogger::init(level, LogConfig::default()).expect("Could not configure logger.");
 }
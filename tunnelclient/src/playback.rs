@@ -0,0 +1,222 @@
+//! Record the snapshots a client receives, and render recordings offline.
+//!
+//! Recordings are snapshot archives, the same format the server records, so
+//! a client can also play back one channel of a server recording.  Playback
+//! stands in for the network entirely: snapshots are fed to the client on
+//! their recorded timeline, measured by this host's clock, so a render
+//! regression can be reproduced or the draw path benchmarked without a
+//! running server.
+
+use crate::timesync::Timesync;
+use log::{error, info};
+use simple_error::bail;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tunnels_lib::archive::{self, ArchiveFrame, ArchiveWriter};
+use tunnels_lib::queue::bounded;
+use tunnels_lib::{RunFlag, Snapshot};
+
+/// Most snapshots to hold for the client at once.
+const QUEUE_CAPACITY: usize = 64;
+
+/// Write buffered snapshots out to the recording at least this often, so a
+/// client that dies loses at most this much of the recording.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Write every snapshot taken from queue to a new archive at path, and pass
+/// it on through the returned queue.
+/// Recording stops when the returned queue is dropped or the run flag is
+/// stopped, and the recording is then flushed.  Join the returned handle to
+/// wait for it.
+pub fn record(
+    queue: Receiver<Snapshot>,
+    path: &Path,
+    video_channel: u8,
+    run_flag: RunFlag,
+) -> Result<(Receiver<Snapshot>, JoinHandle<()>), Box<dyn Error>> {
+    let mut writer = Some(ArchiveWriter::create(path)?);
+    let (send, recv) = bounded(QUEUE_CAPACITY);
+    info!("Recording snapshots to {}.", path.display());
+    let recorder = thread::Builder::new()
+        .name("recorder".to_string())
+        .spawn(move || {
+            let mut last_flush = Instant::now();
+            while run_flag.should_run() {
+                match queue.recv_timeout(FLUSH_INTERVAL) {
+                    Ok(snapshot) => {
+                        if let Some(w) = writer.as_mut() {
+                            let frame = ArchiveFrame {
+                                video_channel,
+                                snapshot: snapshot.clone(),
+                            };
+                            if let Err(e) = w.write(&frame) {
+                                error!("Recording error: {}.  Recording stopped.", e);
+                                writer = None;
+                            }
+                        }
+                        if send.send(snapshot).is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if last_flush.elapsed() >= FLUSH_INTERVAL {
+                    flush(&mut writer);
+                    last_flush = Instant::now();
+                }
+            }
+            flush(&mut writer);
+            info!("Recording stopped.");
+        })?;
+    Ok((recv, recorder))
+}
+
+/// Flush a recording.  If that fails, stop recording.
+fn flush<W: Write>(writer: &mut Option<ArchiveWriter<W>>) {
+    if let Some(w) = writer.as_mut() {
+        if let Err(e) = w.flush() {
+            error!("Recording flush error: {}.  Recording stopped.", e);
+            *writer = None;
+        }
+    }
+}
+
+/// Play back the snapshots for one video channel from the archive or replay
+/// buffer at path.
+/// Return a queue of snapshots, and the clock to render them by.  Once the
+/// last snapshot has been drawn, the run flag is stopped.
+pub fn play(
+    path: &Path,
+    video_channel: u8,
+    render_delay: Duration,
+    mut run_flag: RunFlag,
+) -> Result<(Receiver<Snapshot>, Timesync), Box<dyn Error>> {
+    // Playback starts from the time of the first snapshot.
+    let first = match channel_snapshots(path, video_channel)?.next() {
+        Some(first) => first?,
+        None => bail!(
+            "{} contains no snapshots for video channel {}.",
+            path.display(),
+            video_channel
+        ),
+    };
+    let clock = Timesync::local(first.time);
+    let player_clock = clock.clone();
+    let path = path.to_path_buf();
+    let (send, recv) = bounded(QUEUE_CAPACITY);
+    info!("Playing back {}.", path.display());
+    thread::Builder::new()
+        .name("player".to_string())
+        .spawn(move || {
+            let snapshots = match channel_snapshots(&path, video_channel) {
+                Ok(snapshots) => snapshots,
+                Err(e) => {
+                    error!("Playback error: {}.", e);
+                    return;
+                }
+            };
+            for snapshot in snapshots {
+                let snapshot = match snapshot {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => {
+                        error!("Playback error: {}.", e);
+                        break;
+                    }
+                };
+                thread::sleep(snapshot.time.duration_since(player_clock.now()));
+                if !run_flag.should_run() || send.send(snapshot).is_err() {
+                    return;
+                }
+            }
+            // Give the client time to draw the last snapshot.
+            thread::sleep(render_delay);
+            info!("Playback complete.");
+            run_flag.stop();
+        })?;
+    Ok((recv, clock))
+}
+
+/// Return every snapshot for one video channel in an archive, in order.
+fn channel_snapshots(
+    path: &Path,
+    video_channel: u8,
+) -> Result<impl Iterator<Item = Result<Snapshot, Box<dyn Error>>>, Box<dyn Error>> {
+    Ok(archive::open(path)?.filter_map(move |frame| match frame {
+        Ok(frame) if frame.video_channel == video_channel => Some(Ok(frame.snapshot)),
+        Ok(_) => None,
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{fs, sync::Arc};
+    use tunnels_lib::Timestamp;
+
+    fn snapshot(frame_number: u64) -> Snapshot {
+        Snapshot {
+            frame_number,
+            time: Timestamp(frame_number as i64 * 1000),
+            layers: vec![Arc::new(Vec::new())],
+            palette: None,
+            blend: Vec::new(),
+            color: None,
+        }
+    }
+
+    #[test]
+    fn test_record_and_play() -> Result<(), Box<dyn Error>> {
+        let name = format!("tunnelclient-{}.tunarc", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let (send, queue) = bounded(QUEUE_CAPACITY);
+        let (recorded, recorder) = record(queue, &path, 1, RunFlag::new())?;
+        for n in 0..5 {
+            send.send(snapshot(n))?;
+        }
+        drop(send);
+        assert_eq!(5, recorded.iter().count());
+        recorder.join().unwrap();
+
+        let run_flag = RunFlag::new();
+        let (played, clock) = play(&path, 1, Duration::ZERO, run_flag.clone())?;
+        let played = played.iter().collect::<Vec<_>>();
+        assert_eq!(
+            (0..5).collect::<Vec<_>>(),
+            played.iter().map(|s| s.frame_number).collect::<Vec<_>>()
+        );
+        assert!(clock.now() >= Timestamp(4000));
+        assert!(!run_flag.should_run());
+
+        // No snapshots for another channel.
+        assert!(play(&path, 2, Duration::ZERO, RunFlag::new()).is_err());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_until_stopped() -> Result<(), Box<dyn Error>> {
+        let name = format!("tunnelclient-stopped-{}.tunarc", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let (send, queue) = bounded(QUEUE_CAPACITY);
+        let mut run_flag = RunFlag::new();
+        let (recorded, recorder) = record(queue, &path, 1, run_flag.clone())?;
+        for n in 0..3 {
+            send.send(snapshot(n))?;
+        }
+        for _ in 0..3 {
+            recorded.recv()?;
+        }
+        // The receiver is still connected, as it is in a live show.
+        run_flag.stop();
+        recorder.join().unwrap();
+        assert_eq!(3, channel_snapshots(&path, 1)?.count());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
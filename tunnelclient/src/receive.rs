@@ -1,21 +1,91 @@
 //! 0mq communication and deserialization.
 
-use log::error;
+use crate::recovery::Retry;
+use log::{error, info, warn};
 use rmp_serde::decode::Error as DecodeError;
 use rmp_serde::Deserializer;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use std::error::Error;
+use std::fmt;
 use std::io::Cursor;
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+use tunnels_lib::chunk::Reassembler;
+use tunnels_lib::queue::bounded;
 use zmq;
 use zmq::{Context, Socket, DONTWAIT};
 
 // --- receive and handle messages ---
 
+/// Most messages to hold for the consumer at once.  A client stuck drawing a
+/// frame loses whatever arrives beyond this, rather than queueing it forever.
+const QUEUE_CAPACITY: usize = 64;
+
+/// Report dropped messages to the consumer at most this often.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Consider the server gone if we hear nothing from it for this long.  The
+/// server sends a keepalive every second even when it has no frames for us.
+const STALE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Longest to wait for a message before checking whether the server is gone.
+const RECEIVE_TIMEOUT_MS: i32 = 500;
+
 pub type ReceiveResult<T> = Result<T, DecodeError>;
 
+/// Something that went wrong in a receiver running in a thread.
+#[derive(Debug)]
+pub enum ReceiveError {
+    /// A message arrived but couldn't be decoded.
+    Decode(DecodeError),
+    /// The chunks of a message couldn't be put back together.
+    Reassemble(String),
+    /// This many messages were dropped because the consumer fell behind.
+    Dropped(usize),
+}
+
+impl fmt::Display for ReceiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "Unable to decode a message: {}", e),
+            Self::Reassemble(e) => write!(f, "Unable to reassemble a message: {}", e),
+            Self::Dropped(n) => write!(f, "Receive queue is full; dropped {} messages", n),
+        }
+    }
+}
+
+/// Tally of the errors reported by a receiver.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiveStats {
+    pub undecodable: u64,
+    pub damaged: u64,
+    pub dropped: u64,
+}
+
+impl ReceiveStats {
+    pub fn record(&mut self, e: &ReceiveError) {
+        match e {
+            ReceiveError::Decode(_) => self.undecodable += 1,
+            ReceiveError::Reassemble(_) => self.damaged += 1,
+            ReceiveError::Dropped(n) => self.dropped += *n as u64,
+        }
+    }
+}
+
+impl fmt::Display for ReceiveStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} undecodable, {} damaged, and {} dropped messages",
+            self.undecodable, self.damaged, self.dropped
+        )
+    }
+}
+
 pub trait Receive {
     /// Return the raw message buffer if one was available.
     fn receive_buffer(&mut self, block: bool) -> Option<Vec<u8>>;
@@ -37,74 +107,191 @@ pub trait Receive {
     }
 }
 
+/// Whether a receiver has heard from the server recently.
+#[derive(Clone, Default)]
+pub struct Signal(Arc<AtomicBool>);
+
+impl Signal {
+    pub fn present(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, present: bool) {
+        self.0.store(present, Ordering::Relaxed);
+    }
+}
+
 /// Receive messages via a zmq SUB socket, draining a PUB/SUB network.
+/// If the server goes quiet, the socket is recreated, backing off between
+/// attempts until the server is heard from again.
 pub struct SubReceiver {
     socket: Socket,
+    reassembler: Reassembler,
+    ctx: Context,
+    addr: String,
+    topic: Vec<u8>,
+    keepalive_topic: Vec<u8>,
+    last_heard: Instant,
+    signal: Signal,
+    retry: Retry,
 }
 
 impl SubReceiver {
-    /// Create a new 0mq SUB connected to the provided socket addr.
+    /// Create a new 0mq SUB connected to the provided socket addr, subscribed
+    /// to the provided topic and to the server's keepalives.
     pub fn new(
         host: &str,
         port: u64,
         topic: &[u8],
+        keepalive_topic: &[u8],
         ctx: &mut Context,
     ) -> Result<Self, Box<dyn Error>> {
-        let socket = ctx.socket(zmq::SUB)?;
         let addr = format!("tcp://{}:{}", host, port);
-        socket.connect(&addr)?;
-        socket.set_subscribe(topic)?;
+        let socket = subscribe(ctx, &addr, &[topic, keepalive_topic])?;
+
+        Ok(SubReceiver {
+            socket,
+            reassembler: Reassembler::new(),
+            ctx: ctx.clone(),
+            addr,
+            topic: topic.to_vec(),
+            keepalive_topic: keepalive_topic.to_vec(),
+            last_heard: Instant::now(),
+            signal: Signal::default(),
+            retry: Retry::new(),
+        })
+    }
+
+    /// Return a handle on whether this receiver is hearing from the server.
+    pub fn signal(&self) -> Signal {
+        self.signal.clone()
+    }
 
-        Ok(SubReceiver { socket })
+    /// Check whether the server has gone quiet, and if so, try reconnecting
+    /// when an attempt is due.
+    fn check_signal(&mut self) {
+        let now = Instant::now();
+        if now - self.last_heard < STALE_TIMEOUT {
+            if !self.signal.present() {
+                info!("Receiving from {}.", self.addr);
+                self.signal.set(true);
+                self.retry.succeeded();
+            }
+            return;
+        }
+        if self.signal.present() {
+            warn!("Lost signal from {}.", self.addr);
+            self.signal.set(false);
+        }
+        if !self.retry.due(now) {
+            return;
+        }
+        // Until we hear from the server, every attempt counts as a failure.
+        let delay = self.retry.failed(now);
+        match subscribe(&self.ctx, &self.addr, &[&self.topic, &self.keepalive_topic]) {
+            Ok(socket) => {
+                info!(
+                    "Reconnecting to {}; next attempt in {} seconds.",
+                    self.addr,
+                    delay.as_secs_f64()
+                );
+                self.socket = socket;
+                self.reassembler = Reassembler::new();
+            }
+            Err(e) => error!("Unable to reconnect to {}: {}.", self.addr, e),
+        }
     }
 
     /// Run this receiver in a thread, posting deserialized messages to a channel.
     /// Takes ownership of the receiver and moves to the worker thread.
+    /// The queue is bounded; messages that arrive while it is full are dropped
+    /// and periodically counted on the error channel, along with any messages
+    /// that couldn't be decoded.  The error channel never blocks the worker;
+    /// errors are dropped if the consumer doesn't keep up with them either.
     /// Quits when the output queue is dropped.
-    pub fn run_async<T>(mut self) -> Result<Receiver<T>, Box<dyn Error>>
+    pub fn run_async<T>(mut self) -> Result<(Receiver<T>, Receiver<ReceiveError>), Box<dyn Error>>
     where
         T: DeserializeOwned + Send + 'static,
     {
-        let (tx, rx) = channel::<T>();
+        let (tx, rx) = bounded::<T>(QUEUE_CAPACITY);
+        let (err_tx, err_rx) = bounded::<ReceiveError>(QUEUE_CAPACITY);
         thread::Builder::new()
             .name("subscribe_receiver".to_string())
             .spawn(move || {
+                let mut last_report: Option<Instant> = None;
                 loop {
-                    // blocking receive
-                    match self.receive(true) {
-                        Some(Ok(msg)) => {
+                    // blocking receive, timing out to check on the server
+                    match self.receive_parts(0) {
+                        Ok(Some(buf)) => match self.deserialize_msg(buf) {
                             // post message to queue
                             // if a send fails, the other side has hung up and we should quit
-                            match tx.send(msg) {
-                                Ok(_) => continue,
-                                Err(_) => break,
+                            Ok(msg) => {
+                                if tx.send(msg).is_err() {
+                                    break;
+                                }
                             }
+                            Err(e) => {
+                                let _ = err_tx.send(ReceiveError::Decode(e));
+                            }
+                        },
+                        Ok(None) => (),
+                        Err(e) => {
+                            let _ = err_tx.send(e);
                         }
-                        _ => continue,
+                    }
+                    self.check_signal();
+                    let now = Instant::now();
+                    if matches!(last_report, Some(t) if now - t < REPORT_INTERVAL) {
+                        continue;
+                    }
+                    let dropped = tx.take_dropped();
+                    if dropped > 0 {
+                        let _ = err_tx.send(ReceiveError::Dropped(dropped));
+                        last_report = Some(now);
                     }
                 }
             })?;
-        Ok(rx)
+        Ok((rx, err_rx))
+    }
+
+    /// Receive the next whole message, if one is available.
+    fn receive_parts(&mut self, flag: i32) -> Result<Option<Vec<u8>>, ReceiveError> {
+        // The frame messages start with the video channel, used as a 0mq topic filter.  Discard
+        // the topic filter, leaving just the msgpacked frame data, which may arrive in chunks.
+        let parts = match self.socket.recv_multipart(flag) {
+            Ok(parts) => parts,
+            Err(_) => return Ok(None),
+        };
+        self.last_heard = Instant::now();
+        if parts.first() == Some(&self.keepalive_topic) {
+            return Ok(None);
+        }
+        self.reassembler
+            .receive(parts)
+            .map_err(|e| ReceiveError::Reassemble(e.to_string()))
+    }
+}
+
+/// Create a SUB socket connected to addr and subscribed to topics.
+fn subscribe(ctx: &Context, addr: &str, topics: &[&[u8]]) -> Result<Socket, Box<dyn Error>> {
+    let socket = ctx.socket(zmq::SUB)?;
+    socket.set_rcvtimeo(RECEIVE_TIMEOUT_MS)?;
+    socket.connect(addr)?;
+    for topic in topics {
+        socket.set_subscribe(topic)?;
     }
+    Ok(socket)
 }
 
 impl Receive for SubReceiver {
     fn receive_buffer(&mut self, block: bool) -> Option<Vec<u8>> {
         let flag = if block { 0 } else { DONTWAIT };
-
-        // The frame messages are two parts; the first part is the video channel, used as a 0mq
-        // topic filter.  Discard the topic filter, leaving just the msgpacked frame data as the
-        // second part of the message.
-        if let Ok(mut parts) = self.socket.recv_multipart(flag) {
-            let n_parts = parts.len();
-            if n_parts != 2 {
-                error!("Buffer receive error, got {} parts: {:?}", n_parts, parts);
+        match self.receive_parts(flag) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                error!("{}", e);
                 None
-            } else {
-                parts.pop()
             }
-        } else {
-            None
         }
     }
 }
@@ -112,7 +299,12 @@ impl Receive for SubReceiver {
 #[cfg(test)]
 pub mod test {
     use super::*;
-    use tunnels_lib::{ArcSegment, Snapshot};
+    use tunnels_lib::{
+        color::ColorCorrection,
+        palette::{Hsv, Palette},
+        ArcSegment, BlendMode, Snapshot, Timestamp,
+    };
+
     pub fn arc_segment_for_test(linear: f64, radial: f64) -> ArcSegment {
         ArcSegment {
             level: linear,
@@ -128,6 +320,7 @@ pub mod test {
             start: radial,
             stop: radial,
             rot_angle: radial,
+            palette: false,
         }
     }
 
@@ -476,6 +669,136 @@ pub mod test {
         println!("{:?}", result);
     }
 
+    /// The snapshot every golden file holds, as sent by a server of each
+    /// protocol version in turn.
+    fn golden_snapshots() -> Vec<(&'static str, &'static [u8], Snapshot)> {
+        let mut arc = arc_segment_for_test(0.5, 0.25);
+        arc.rad_y = 0.75;
+        let mut raw = Snapshot {
+            frame_number: 1234,
+            time: Timestamp::from_micros(16_667),
+            layers: vec![
+                Arc::new(vec![arc.clone()]),
+                Arc::new(vec![arc_segment_for_test(1.0, 0.5), arc.clone()]),
+            ],
+            palette: None,
+            blend: Vec::new(),
+            color: None,
+        };
+        let v1 = raw.clone();
+
+        let mut v2 = raw.clone();
+        Arc::make_mut(&mut v2.layers[1])[0].palette = true;
+        v2.palette = Some(Palette(vec![
+            Hsv {
+                hue: 0.0,
+                sat: 1.0,
+                val: 1.0,
+            },
+            Hsv {
+                hue: 0.5,
+                sat: 0.5,
+                val: 0.25,
+            },
+        ]));
+
+        raw.blend = vec![BlendMode::Alpha, BlendMode::Additive];
+        let v3 = raw.clone();
+
+        raw.blend = Vec::new();
+        raw.color = Some(ColorCorrection {
+            hue_shift: 0.25,
+            saturation: 0.5,
+            brightness: 0.75,
+        });
+        let v4 = raw;
+
+        vec![
+            (
+                "v1_raw_color",
+                &include_bytes!("../fixtures/snapshot_v1_raw_color.msgpack")[..],
+                v1,
+            ),
+            (
+                "v2_palette",
+                &include_bytes!("../fixtures/snapshot_v2_palette.msgpack")[..],
+                v2,
+            ),
+            (
+                "v3_blend",
+                &include_bytes!("../fixtures/snapshot_v3_blend.msgpack")[..],
+                v3,
+            ),
+            (
+                "v4_color",
+                &include_bytes!("../fixtures/snapshot_v4_color.msgpack")[..],
+                v4,
+            ),
+        ]
+    }
+
+    /// Snapshots sent by servers of every past protocol version still parse.
+    #[test]
+    fn test_decode_golden() {
+        for (version, buf, expected) in golden_snapshots() {
+            let cur = Cursor::new(buf);
+            let mut de = Deserializer::new(cur);
+            let decoded: Snapshot = Deserialize::deserialize(&mut de)
+                .unwrap_or_else(|e| panic!("Golden snapshot {} failed to parse: {}", version, e));
+            assert_eq!(expected, decoded, "golden snapshot {}", version);
+        }
+    }
+
+    /// The server still sends each kind of snapshot exactly as it used to,
+    /// so clients of every past version can read whatever they understand.
+    /// If this fails because the protocol changed on purpose, add a golden
+    /// file for the new version rather than changing an existing one.
+    #[test]
+    fn test_encode_golden() {
+        for (version, buf, snapshot) in golden_snapshots() {
+            let encoded = rmp_serde::to_vec(&snapshot).unwrap();
+            assert!(
+                encoded == buf,
+                "Snapshot protocol changed: golden snapshot {} no longer matches what the \
+                 server sends.",
+                version
+            );
+        }
+    }
+
+    /// Frames that can't be decoded are reported on the error channel, and
+    /// don't stop the frames that can from arriving.
+    #[test]
+    fn test_receive_errors() -> Result<(), Box<dyn Error>> {
+        let mut ctx = Context::new();
+        let publisher = ctx.socket(zmq::PUB)?;
+        publisher.bind("tcp://127.0.0.1:*")?;
+        let endpoint = publisher.get_last_endpoint()?.unwrap();
+        let port = endpoint.rsplit(':').next().unwrap().parse()?;
+        let receiver = SubReceiver::new("127.0.0.1", port, &[0], b"keepalive", &mut ctx)?;
+        let (frames, errors) = receiver.run_async::<Snapshot>()?;
+
+        let (_, good, expected) = golden_snapshots().pop().unwrap();
+        // Keep publishing until the subscription has been set up.
+        let received = loop {
+            // 0xc1 is never used in msgpack.
+            publisher.send_multipart([&[0][..], &[0xc1]], 0)?;
+            publisher.send_multipart([&[0][..], good], 0)?;
+            if let Ok(snapshot) = frames.recv_timeout(Duration::from_millis(50)) {
+                break snapshot;
+            }
+        };
+        assert_eq!(expected, received);
+
+        let mut stats = ReceiveStats::default();
+        for e in errors.try_iter() {
+            stats.record(&e);
+        }
+        assert!(stats.undecodable > 0);
+        assert_eq!(0, stats.damaged);
+        Ok(())
+    }
+
     #[test]
     fn test_unpack_multiple() {
         let buf = [146, 1, 2];
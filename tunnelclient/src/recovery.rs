@@ -0,0 +1,162 @@
+//! Recover from losing the window or its GL context.
+//!
+//! A display being unplugged or a GPU driver reset can take the GL context,
+//! and the shaders and buffers stored in it, along with it.  Rather than
+//! leaving a render node dark until someone restarts it, the client drops its
+//! window and renderer and opens fresh ones, then carries on drawing from the
+//! latest snapshots.  The display may take a while to come back, so failed
+//! attempts are retried with a growing delay.
+
+use std::time::{Duration, Instant};
+
+/// How long to wait after the first failed attempt.
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest to wait between attempts.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Decide when to next try again, backing off after each failure.
+pub struct Retry {
+    delay: Duration,
+    next: Option<Instant>,
+}
+
+impl Retry {
+    pub fn new() -> Self {
+        Self {
+            delay: FIRST_DELAY,
+            next: None,
+        }
+    }
+
+    /// Return true if it is time to try again.
+    pub fn due(&self, now: Instant) -> bool {
+        match self.next {
+            Some(next) => now >= next,
+            None => true,
+        }
+    }
+
+    /// Record a failed attempt, and return how long until the next one.
+    pub fn failed(&mut self, now: Instant) -> Duration {
+        let delay = self.delay;
+        self.next = Some(now + delay);
+        self.delay = (delay * 2).min(MAX_DELAY);
+        delay
+    }
+
+    /// Record a successful attempt, so the next loss starts over.
+    pub fn succeeded(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Something that can be lost and rebuilt, such as the window.
+pub struct Recoverable<S> {
+    /// None while waiting to be rebuilt.
+    current: Option<S>,
+    retry: Retry,
+}
+
+/// The result of trying to rebuild.
+pub enum Attempt<E> {
+    /// Nothing was lost, or the next attempt isn't due yet.
+    NotDue,
+    Rebuilt,
+    /// The attempt failed, and the next is due after the delay.
+    Failed(E, Duration),
+}
+
+impl<S> Recoverable<S> {
+    pub fn new(current: S) -> Self {
+        Self {
+            current: Some(current),
+            retry: Retry::new(),
+        }
+    }
+
+    /// Return the current value, unless it was lost and not yet rebuilt.
+    pub fn get(&mut self) -> Option<&mut S> {
+        self.current.as_mut()
+    }
+
+    /// Drop the current value, so it is rebuilt by the next due attempt.
+    pub fn lose(&mut self) {
+        self.current = None;
+    }
+
+    /// If the value was lost and an attempt is due, try to build a fresh one.
+    pub fn rebuild<E>(&mut self, now: Instant, build: impl FnOnce() -> Result<S, E>) -> Attempt<E> {
+        if self.current.is_some() || !self.retry.due(now) {
+            return Attempt::NotDue;
+        }
+        match build() {
+            Ok(current) => {
+                self.current = Some(current);
+                self.retry.succeeded();
+                Attempt::Rebuilt
+            }
+            Err(e) => Attempt::Failed(e, self.retry.failed(now)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry() {
+        let t0 = Instant::now();
+        let mut retry = Retry::new();
+        assert!(retry.due(t0));
+        assert_eq!(FIRST_DELAY, retry.failed(t0));
+        assert!(!retry.due(t0));
+        assert!(retry.due(t0 + FIRST_DELAY));
+
+        let delays = (0..10).map(|_| retry.failed(t0)).collect::<Vec<_>>();
+        assert_eq!(FIRST_DELAY * 2, delays[0]);
+        assert_eq!(MAX_DELAY, delays[9]);
+
+        retry.succeeded();
+        assert!(retry.due(t0));
+        assert_eq!(FIRST_DELAY, retry.failed(t0));
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let t0 = Instant::now();
+        let mut window = Recoverable::new(0);
+        assert!(matches!(
+            window.rebuild(t0, || Ok::<_, ()>(1)),
+            Attempt::NotDue
+        ));
+        assert_eq!(Some(&mut 0), window.get());
+
+        window.lose();
+        assert!(window.get().is_none());
+        // The display isn't back yet.
+        assert!(
+            matches!(window.rebuild(t0, || Err(())), Attempt::Failed((), d) if d == FIRST_DELAY)
+        );
+        assert!(matches!(
+            window.rebuild(t0, || Ok::<_, ()>(1)),
+            Attempt::NotDue
+        ));
+        assert!(window.get().is_none());
+
+        assert!(matches!(
+            window.rebuild(t0 + FIRST_DELAY, || Ok::<_, ()>(1)),
+            Attempt::Rebuilt
+        ));
+        assert_eq!(Some(&mut 1), window.get());
+
+        // A later loss is rebuilt straight away.
+        window.lose();
+        assert!(matches!(
+            window.rebuild(t0 + FIRST_DELAY, || Ok::<_, ()>(2)),
+            Attempt::Rebuilt
+        ));
+        assert_eq!(Some(&mut 2), window.get());
+    }
+}
@@ -3,6 +3,7 @@
 //! Very basic control; every message received is a full configuration struct, and the receipt of
 //! a message completely tears down an existing show and brings up a new one using the new
 //! parameters.
+//! The server may also push a client profile it manages, which is handled the same way.
 //! Also provide the tools needed for simple remote administration.
 
 use crate::config::{ClientConfig, Resolution};
@@ -16,15 +17,17 @@ use rmp_serde::decode::from_read;
 use rmp_serde::encode::write;
 use std::error::Error;
 use std::io::{stdin, stdout, Write};
-use std::sync::mpsc::{channel, Sender};
+use std::sync::mpsc::{sync_channel, SyncSender};
 use std::thread;
 use std::time::Duration;
+use tunnels_lib::client_profile::AssignProfile;
+use tunnels_lib::ports;
+use tunnels_lib::show_id::service_name;
 use tunnels_lib::RunFlag;
 use zero_configure::{run_service, Controller};
 use zmq::Context;
 
-const SERVICE_NAME: &str = "tunnelclient";
-const PORT: u16 = 15000;
+const PORT: u16 = ports::CLIENT_REMOTE;
 
 // --- client remote control ---
 
@@ -33,17 +36,21 @@ const PORT: u16 = 15000;
 /// OpenGL resources between threads.
 /// Spawn a second thread to run the remote service, passing configurations to run back across a
 /// channel.
+/// The client is advertised to servers and administrators of the show with
+/// the provided ID.
 /// Panics if the remote service thread fails to spawn.
-pub fn run_remote(ctx: &mut Context) {
+pub fn run_remote(ctx: &mut Context, show_id: Option<String>) {
     // Create a channel to wait on config requests.
-    let (send, recv) = channel();
+    // The remote service waits for the show to pick up each config before
+    // accepting another.
+    let (send, recv) = sync_channel(1);
 
     // Spawn a thread to receive config requests.
     thread::Builder::new()
         .name("remote_service".to_string())
-        .spawn(|| {
+        .spawn(move || {
             let mut ctx = Context::new();
-            run_remote_service(&mut ctx, send);
+            run_remote_service(&mut ctx, show_id.as_deref(), send);
         })
         .expect("Failed to spawn remote service thread");
 
@@ -72,11 +79,15 @@ pub fn run_remote(ctx: &mut Context) {
 /// Run the remote discovery and configuration service, passing config states and cancellation
 /// flags back to the main thread.
 /// Panics if the service completes with an error.
-pub fn run_remote_service(_ctx: &mut Context, sender: Sender<(ClientConfig, RunFlag)>) {
+pub fn run_remote_service(
+    _ctx: &mut Context,
+    show_id: Option<&str>,
+    sender: SyncSender<(ClientConfig, RunFlag)>,
+) {
     // Run flag for currently-executing show, if there is one.
     let mut running_flag: Option<RunFlag> = None;
 
-    run_service(SERVICE_NAME, PORT, |request_buffer| {
+    run_service(&service_name(show_id), PORT, |request_buffer| {
         // Attempt to deserialize this request buffer as a client configuration.
         match deserialize_config(request_buffer) {
             Ok(config) => {
@@ -110,8 +121,16 @@ pub fn run_remote_service(_ctx: &mut Context, sender: Sender<(ClientConfig, RunF
     .expect("Remote configuration service crashed")
 }
 
+/// Accept either a full configuration from the administrator or a profile
+/// assigned by the server.
 fn deserialize_config(buffer: &[u8]) -> Result<ClientConfig, String> {
-    from_read(buffer).map_err(|e| e.to_string())
+    from_read(buffer).or_else(|config_err| {
+        from_read(buffer)
+            .map(|assign: AssignProfile| {
+                ClientConfig::from_profile(assign.profile, assign.server_hostname, assign.show_id)
+            })
+            .map_err(|_| config_err.to_string())
+    })
 }
 
 // --- remote administration ---
@@ -123,9 +142,10 @@ pub struct Administrator {
 }
 
 impl Administrator {
-    pub fn new() -> Self {
+    /// Administer the clients of the show with the provided ID.
+    pub fn new(show_id: Option<&str>) -> Self {
         Administrator {
-            controller: Controller::new(SERVICE_NAME),
+            controller: Controller::new(&service_name(show_id)),
         }
     }
 
@@ -279,12 +299,14 @@ where
     let mut timesync_interval = Duration::from_secs(60);
     let mut render_delay = 0.040;
     let mut alpha_blend = true;
+    let mut dither = false;
     let mut capture_mouse = true;
 
     if prompt_y_n("Configure advanced settings") {
         capture_mouse = prompt_y_n("Capture mouse");
         anti_alias = prompt_y_n("Use anti-aliasing");
         alpha_blend = prompt_y_n("Use alpha channel blending");
+        dither = prompt_y_n("Dither levels to reduce banding");
         let timesync_interval_secs = prompt(
             "Host/client time resynchronization interval in seconds (default 60)",
             parse_uint,
@@ -302,6 +324,7 @@ where
         anti_alias,
         fullscreen,
         alpha_blend,
+        dither,
         capture_mouse,
         transformation,
         false,
@@ -309,13 +332,14 @@ where
 }
 
 /// Slightly janky interactive command line utility for administering a fleet of tunnel clients.
-pub fn administrate() {
+/// Only the clients of the show with the provided ID are administered.
+pub fn administrate(show_id: Option<String>) {
     let host = hostname::get()
         .expect("Couldn't get hostname for this machine")
         .into_string()
         .unwrap();
     println!("Starting administrator...");
-    let admin = Administrator::new();
+    let admin = Administrator::new(show_id.as_deref());
 
     // Wait a couple seconds for dns-sd to do its business.
     thread::sleep(Duration::from_secs(2));
@@ -346,7 +370,8 @@ quit    Quit.";
             }
             "conf" | "c" => {
                 let client_name = prompt("Enter client name", &parse_client_name);
-                let config = configure_one(host.clone());
+                let mut config = configure_one(host.clone());
+                config.show_id = show_id.clone();
                 match admin.run_with_config(&client_name, config) {
                     Ok(msg) => {
                         println!("{}", msg);
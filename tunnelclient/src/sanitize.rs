@@ -0,0 +1,168 @@
+//! Defensive checks on snapshots received from the network.
+//!
+//! The server checks every frame before sending it, but a mismatched server
+//! version or a corrupted message could still deliver geometry that the GL
+//! driver handles badly; NaN or enormous radii can hang it outright.  Every
+//! received arc is checked before it reaches the renderer.  Parameters with a
+//! fixed range are clamped into it, and arcs with non-finite values, negative
+//! radii, or absurd coordinates are dropped.
+
+use log::error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tunnels_lib::{ArcSegment, Snapshot};
+
+/// Largest radius, offset, or thickness we will draw, in units of the
+/// half-screen.
+const MAX_EXTENT: f64 = 10.0;
+
+/// Report protocol errors at most this often.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Clean up received snapshots, and periodically log what had to be fixed.
+pub struct SnapshotFilter {
+    rejected: usize,
+    clamped: usize,
+    last_report: Option<Instant>,
+}
+
+impl SnapshotFilter {
+    pub fn new() -> Self {
+        Self {
+            rejected: 0,
+            clamped: 0,
+            last_report: None,
+        }
+    }
+
+    /// Drop or clamp any invalid arcs in the snapshot.
+    pub fn filter(&mut self, snapshot: &mut Snapshot) {
+        let mut invalid = false;
+        for layer in snapshot.layers.iter_mut() {
+            if layer.iter().all(|arc| !rejected(arc) && !needs_clamp(arc)) {
+                continue;
+            }
+            invalid = true;
+            let arcs = Arc::make_mut(layer);
+            let count = arcs.len();
+            arcs.retain(|arc| !rejected(arc));
+            self.rejected += count - arcs.len();
+            for arc in arcs.iter_mut().filter(|arc| needs_clamp(arc)) {
+                clamp(arc);
+                self.clamped += 1;
+            }
+        }
+        if invalid {
+            self.report(snapshot.frame_number);
+        }
+    }
+
+    fn report(&mut self, frame_number: u64) {
+        let now = Instant::now();
+        if let Some(last_report) = self.last_report {
+            if now - last_report < REPORT_INTERVAL {
+                return;
+            }
+        }
+        error!(
+            "Protocol error: dropped {} and clamped {} invalid arcs, most recently in frame {}.",
+            self.rejected, self.clamped, frame_number
+        );
+        self.rejected = 0;
+        self.clamped = 0;
+        self.last_report = Some(now);
+    }
+}
+
+/// Return true if an arc can't be drawn at all.
+fn rejected(arc: &ArcSegment) -> bool {
+    let values = [
+        arc.level,
+        arc.thickness,
+        arc.hue,
+        arc.sat,
+        arc.val,
+        arc.x,
+        arc.y,
+        arc.rad_x,
+        arc.rad_y,
+        arc.start,
+        arc.stop,
+        arc.rot_angle,
+    ];
+    values.iter().any(|v| !v.is_finite())
+        || arc.rad_x < 0.0
+        || arc.rad_y < 0.0
+        || [arc.x, arc.y, arc.rad_x, arc.rad_y]
+            .iter()
+            .any(|v| v.abs() > MAX_EXTENT)
+}
+
+fn unit(v: f64) -> bool {
+    (0.0..=1.0).contains(&v)
+}
+
+/// Return true if an arc has parameters outside their range.
+fn needs_clamp(arc: &ArcSegment) -> bool {
+    !unit(arc.level)
+        || !unit(arc.sat)
+        || !unit(arc.val)
+        || !(0.0..=MAX_EXTENT).contains(&arc.thickness)
+}
+
+fn clamp(arc: &mut ArcSegment) {
+    arc.level = arc.level.clamp(0.0, 1.0);
+    arc.sat = arc.sat.clamp(0.0, 1.0);
+    arc.val = arc.val.clamp(0.0, 1.0);
+    arc.thickness = arc.thickness.clamp(0.0, MAX_EXTENT);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::receive::test::arc_segment_for_test;
+    use tunnels_lib::Timestamp;
+
+    #[test]
+    fn test_filter() {
+        let good = arc_segment_for_test(0.5, 0.5);
+        let nan = ArcSegment {
+            hue: f64::NAN,
+            ..good.clone()
+        };
+        let negative = ArcSegment {
+            rad_y: -0.1,
+            ..good.clone()
+        };
+        let absurd = ArcSegment {
+            x: 1e12,
+            ..good.clone()
+        };
+        let bright = ArcSegment {
+            level: 1.5,
+            thickness: -0.5,
+            ..good.clone()
+        };
+        let mut snapshot = Snapshot {
+            frame_number: 0,
+            time: Timestamp::ZERO,
+            layers: vec![
+                Arc::new(vec![good.clone(), nan, negative]),
+                Arc::new(vec![absurd, bright, good.clone()]),
+            ],
+            palette: None,
+            blend: Vec::new(),
+            color: None,
+        };
+        let mut filter = SnapshotFilter::new();
+        filter.filter(&mut snapshot);
+
+        assert_eq!(vec![good.clone()], *snapshot.layers[0]);
+        let clamped = ArcSegment {
+            level: 1.0,
+            thickness: 0.0,
+            ..good.clone()
+        };
+        assert_eq!(vec![clamped, good], *snapshot.layers[1]);
+    }
+}
@@ -1,10 +1,15 @@
+use crate::calibrator::{draw_blend_and_mask, Calibrator};
 use crate::config::ClientConfig;
-use crate::draw::Draw;
-use crate::receive::SubReceiver;
+use crate::constants::TWOPI;
+use crate::draw::ArcRenderer;
+use crate::heartbeat::start_heartbeat;
+use crate::playback::{play, record};
+use crate::receive::{ReceiveError, ReceiveStats, Signal, SubReceiver};
+use crate::recovery::{Attempt, Recoverable};
 use crate::snapshot_manager::InterpResult::*;
 use crate::snapshot_manager::{SnapshotManager, SnapshotUpdateError};
 use crate::timesync::{Client as TimesyncClient, Synchronizer};
-use graphics::clear;
+use graphics::{clear, CircleArc, Context as DrawContext, Graphics};
 use log::{debug, error, info, max_level, warn, Level};
 use opengl_graphics::{GlGraphics, OpenGL};
 use piston_window::*;
@@ -12,21 +17,47 @@ use sdl2_window::Sdl2Window;
 use std::error::Error;
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tunnels_lib::show_id::{frame_topic, keepalive_topic};
 use tunnels_lib::RunFlag;
 use tunnels_lib::{Snapshot, Timestamp};
 use zmq::Context;
 
+/// Instanced arrays, which draw arcs, are core from 3.3.
+pub const OPENGL: OpenGL = OpenGL::V3_3;
+
+/// How long to sleep between checks while waiting to rebuild the window.
+const REBUILD_POLL: Duration = Duration::from_millis(100);
+
+/// Log receive errors at most this often.
+const RECEIVE_ERROR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where snapshots come from, and the clock to draw them by, along with
+/// whether the server is being heard and what went wrong receiving from it.
+type Connection = (
+    Receiver<Snapshot>,
+    Arc<Mutex<Synchronizer>>,
+    Option<(Signal, Receiver<ReceiveError>)>,
+);
+
 /// Top-level structure that owns all of the show data.
 pub struct Show {
-    gl: GlGraphics, // OpenGL drawing backend.
+    surface: Recoverable<Surface>,
     snapshot_manager: SnapshotManager,
     timesync: Arc<Mutex<Synchronizer>>,
+    /// Whether the server is being heard; None when playing back.
+    signal: Option<Signal>,
+    /// Errors from the receiver; None when playing back.
+    receive_errors: Option<Receiver<ReceiveError>>,
+    receive_stats: ReceiveStats,
+    last_receive_error_logged: Option<Instant>,
     cfg: ClientConfig,
     run_flag: RunFlag,
-    window: PistonWindow<Sdl2Window>,
+    /// The recorder thread, if recording.
+    recorder: Option<JoinHandle<()>>,
     render_logger: RenderIssueLogger,
+    calibrator: Calibrator,
 }
 
 impl Show {
@@ -37,117 +68,161 @@ impl Show {
     ) -> Result<Self, Box<dyn Error>> {
         info!("Running on video channel {}.", cfg.video_channel);
 
-        // Start up the timesync service.
-        let mut timesync_client = TimesyncClient::new(&cfg.server_hostname, ctx)?;
-
-        // Synchronize timing with master host.
-        info!(
-            "Synchronizing timing.  This will take about {} seconds.",
-            timesync_client.synchronization_duration().as_secs()
-        );
-
-        let synchronizer = Synchronizer::new(timesync_client.synchronize()?);
-
-        info!("Synchronized.");
-
-        // Spin off another thread to periodically update our host time synchronization.
-        let timesync_period = cfg.timesync_interval;
-        let timesync = Arc::new(Mutex::new(synchronizer));
-        let timesync_remote = timesync.clone();
-        let timesync_run_flag = run_flag.clone();
-
-        thread::Builder::new()
-            .name("timesync".to_string())
-            .spawn(move || {
-                // FIXME: rather than sleep/flag polling we should use a select
-                // mechanism to ensure prompt quit.
-                while timesync_run_flag.should_run() {
-                    thread::sleep(timesync_period);
-                    match timesync_client.synchronize() {
-                        Ok(sync) => {
-                            let new_estimate = sync.now();
-                            let mut synchronizer =
-                                timesync_remote.lock().expect("Timesync mutex poisoned.");
-                            let old_estimate = synchronizer.now();
-                            info!(
-                                "Updating time sync.  Change from previous estimate: {}",
-                                new_estimate - old_estimate
-                            );
-                            synchronizer.update_current(sync);
-                        }
-                        Err(e) => {
-                            warn!("{}", e);
-                        }
-                    }
-                }
-                info!("Timesync service shutting down.");
-            })
-            .map_err(|e| format!("Timesync service thread failed to spawn: {}", e))?;
-
-        // Set up snapshot reception and management.
-        let snapshot_queue: Receiver<Snapshot> =
-            SubReceiver::new(&cfg.server_hostname, 6000, &[cfg.video_channel as u8], ctx)?
-                .run_async()?;
-
-        let snapshot_manager = SnapshotManager::new(snapshot_queue);
+        let (snapshot_queue, timesync, link) = match &cfg.playback {
+            Some(path) => {
+                let (queue, clock) = play(
+                    path,
+                    cfg.video_channel as u8,
+                    cfg.render_delay,
+                    run_flag.clone(),
+                )?;
+                (queue, Arc::new(Mutex::new(Synchronizer::new(clock))), None)
+            }
+            None => connect(&cfg, ctx, &run_flag)?,
+        };
+        let (snapshot_queue, recorder) = match &cfg.record {
+            Some(path) => {
+                let (queue, recorder) = record(
+                    snapshot_queue,
+                    path,
+                    cfg.video_channel as u8,
+                    run_flag.clone(),
+                )?;
+                (queue, Some(recorder))
+            }
+            None => (snapshot_queue, None),
+        };
 
-        let opengl = OpenGL::V3_2;
+        let snapshot_manager = SnapshotManager::new(snapshot_queue, cfg.interpolate);
 
         // Sleep for a render delay to make sure we have snapshots before we start rendering.
         thread::sleep(cfg.render_delay);
 
-        // Create the window.
-        let mut window: PistonWindow<Sdl2Window> = WindowSettings::new(
-            format!("tunnelclient: channel {}", cfg.video_channel),
-            [cfg.x_resolution, cfg.y_resolution],
-        )
-        .graphics_api(opengl)
-        .exit_on_esc(true)
-        .vsync(true)
-        .samples(if cfg.anti_alias { 4 } else { 0 })
-        .fullscreen(cfg.fullscreen)
-        .build()?;
-
-        window.set_capture_cursor(cfg.capture_mouse);
-        window.set_max_fps(120);
-
         Ok(Show {
-            gl: GlGraphics::new(opengl),
+            surface: Recoverable::new(Surface::open(&cfg)?),
             snapshot_manager,
             timesync,
+            signal: link.as_ref().map(|(signal, _)| signal.clone()),
+            receive_errors: link.map(|(_, errors)| errors),
+            receive_stats: ReceiveStats::default(),
+            last_receive_error_logged: None,
             cfg,
             run_flag,
-            window,
+            recorder,
             render_logger: RenderIssueLogger::new(Duration::from_secs(1)),
+            calibrator: Calibrator::default(),
         })
     }
 
     /// Run the show's event loop.
     pub fn run(&mut self) {
+        if let Err(e) = self.cfg.render_thread.apply_to_current() {
+            error!("Unable to configure render thread: {}", e);
+        }
+
         // Run the event loop.
-        while let Some(e) = self.window.next() {
+        loop {
             if !self.run_flag.should_run() {
                 info!("Quit flag tripped, ending show.");
                 break;
             }
 
+            let next = match self.surface.get() {
+                Some(surface) => surface.window.next(),
+                None => {
+                    self.rebuild();
+                    continue;
+                }
+            };
+            let e = match next {
+                Some(e) => e,
+                None => {
+                    // Quitting is done with escape, so the window was closed
+                    // from under us, such as by its display going away.
+                    error!("The window closed; rebuilding it.");
+                    self.surface.lose();
+                    continue;
+                }
+            };
+
+            if let Some(Button::Keyboard(Key::Escape)) = e.press_args() {
+                info!("Escape pressed, ending show.");
+                break;
+            }
+
+            if self.calibrator.handle_event(&e, &mut self.cfg) {
+                // Show the cursor while calibrating.
+                if let Some(surface) = self.surface.get() {
+                    surface
+                        .window
+                        .set_capture_cursor(self.cfg.capture_mouse && !self.calibrator.active());
+                }
+            }
+
             if let Some(update_args) = e.update_args() {
                 self.update(update_args.dt);
             }
 
             if let Some(r) = e.render_args() {
+                if self.surface.get().map_or(false, |s| s.context_lost()) {
+                    error!("Lost the GL context; rebuilding the window.");
+                    self.surface.lose();
+                    continue;
+                }
                 self.render(&r);
             }
         }
 
-        // If the window is closed, the event loop will exit normally.  Flip the run flag to stop
+        // When escape is pressed, the event loop will exit normally.  Flip the run flag to stop
         // to ensure all of the services close down and we don't leak a timesync thread.
         // TODO: hold onto the join handle for the timesync service?
         self.run_flag.stop();
+        // Wait for the recording to be flushed.
+        if let Some(recorder) = self.recorder.take() {
+            if recorder.join().is_err() {
+                error!("Recorder thread panicked.");
+            }
+        }
+    }
+
+    /// Try to open a fresh window and renderer, if an attempt is due.
+    /// Snapshots keep arriving in the meantime, so the show picks up from the
+    /// latest one.
+    fn rebuild(&mut self) {
+        let cfg = &self.cfg;
+        match self.surface.rebuild(Instant::now(), || Surface::open(cfg)) {
+            Attempt::NotDue => thread::sleep(REBUILD_POLL),
+            Attempt::Rebuilt => {
+                info!("Rebuilt the window; resuming the show.");
+                if let Some(surface) = self.surface.get() {
+                    surface
+                        .window
+                        .set_capture_cursor(self.cfg.capture_mouse && !self.calibrator.active());
+                }
+            }
+            Attempt::Failed(e, delay) => {
+                error!(
+                    "Unable to rebuild the window: {}.  Trying again in {} seconds.",
+                    e,
+                    delay.as_secs_f64()
+                );
+            }
+        }
     }
 
     /// Render a frame to the window.
     fn render(&mut self, args: &RenderArgs) {
+        // Track the actual size of the window, which may not be the size we
+        // asked for.
+        if self.cfg.fit_to_window(args.window_size) {
+            info!(
+                "Drawing to {}x{} logical pixels at {}x scale.",
+                args.window_size[0],
+                args.window_size[1],
+                f64::from(args.draw_size[0]) / args.window_size[0]
+            );
+        }
+
         // Get frame interpolation from the snapshot service.
 
         let delayed_time = match self.timesync.lock() {
@@ -160,7 +235,11 @@ impl Show {
             Ok(ref mut ts) => ts.now() - Timestamp::from_duration(self.cfg.render_delay),
         };
 
+        // Don't leave the last frame frozen on screen if the server is gone.
+        let no_signal = self.signal.as_ref().map_or(false, |s| !s.present());
+
         let maybe_frame = match self.snapshot_manager.get_interpolated(delayed_time) {
+            _ if no_signal => None,
             NoData => {
                 self.render_logger
                     .log(delayed_time, "No data available from snapshot service.");
@@ -174,28 +253,44 @@ impl Show {
                 );
                 None
             }
-            Good(layers) => Some(layers),
-            MissingNewer(layers) => {
+            Good(snapshot) => Some(snapshot),
+            MissingNewer(snapshot) => {
                 self.render_logger
                     .log(delayed_time, "Interpolation had no newer layer.");
-                Some(layers)
+                Some(snapshot)
             }
-            MissingOlder(layers) => {
+            MissingOlder(snapshot) => {
                 self.render_logger
                     .log(delayed_time, "Interpolation had no older layer");
-                Some(layers)
+                Some(snapshot)
             }
         };
 
-        if let Some(frame) = maybe_frame {
+        // Keep drawing while calibrating even if there is no data, so the
+        // operator can see what they are adjusting.
+        if maybe_frame.is_some() || no_signal || self.calibrator.active() {
+            let surface = match self.surface.get() {
+                Some(surface) => surface,
+                None => return,
+            };
             let cfg = &self.cfg;
+            let calibrator = &self.calibrator;
+            let size = [cfg.x_extent, cfg.y_extent];
+            let arcs = &mut surface.arcs;
 
-            self.gl.draw(args.viewport(), |c, gl| {
+            surface.gl.draw(args.viewport(), |c, gl| {
                 // Clear the screen.
                 clear([0.0, 0.0, 0.0, 1.0], gl);
 
                 // Draw everything.
-                frame.draw(&c, gl, cfg);
+                if let Some(frame) = maybe_frame {
+                    arcs.draw(&frame, c.transform, gl, cfg);
+                }
+                if no_signal {
+                    draw_no_signal(size, &c, gl);
+                }
+                draw_blend_and_mask(&cfg.calibration, size, &c, gl);
+                calibrator.draw(&cfg.calibration, size, &c, gl);
             });
         }
     }
@@ -210,12 +305,233 @@ impl Show {
             };
             println!("An error occurred during snapshot update: {:?}", msg);
         }
+        self.report_receive_errors();
         // Update the interpolation parameter on our time synchronization.
         self.timesync
             .lock()
             .expect("Timesync mutex poisoned")
             .update(dt);
     }
+
+    /// Tally any errors from the receiver, logging them occasionally.
+    fn report_receive_errors(&mut self) {
+        let errors = match &self.receive_errors {
+            Some(errors) => errors,
+            None => return,
+        };
+        for e in errors.try_iter() {
+            self.receive_stats.record(&e);
+            let now = Instant::now();
+            if matches!(self.last_receive_error_logged, Some(t) if now - t < RECEIVE_ERROR_INTERVAL)
+            {
+                continue;
+            }
+            warn!("{}; {} so far.", e, self.receive_stats);
+            self.last_receive_error_logged = Some(now);
+        }
+    }
+}
+
+/// Number of dashes in the no signal marker.
+const NO_SIGNAL_DASHES: usize = 8;
+
+/// Show that the server can't be heard, with a dim dashed ring in the middle
+/// of the screen.
+fn draw_no_signal<G: Graphics>(size: [f64; 2], c: &DrawContext, gl: &mut G) {
+    let [w, h] = size;
+    let radius = w.min(h) / 20.0;
+    let rect = [
+        w / 2.0 - radius,
+        h / 2.0 - radius,
+        2.0 * radius,
+        2.0 * radius,
+    ];
+    let dash = TWOPI / NO_SIGNAL_DASHES as f64;
+    for i in 0..NO_SIGNAL_DASHES {
+        let start = dash * i as f64;
+        CircleArc::new(
+            [0.3, 0.3, 0.3, 1.0],
+            radius / 10.0,
+            start,
+            start + dash / 2.0,
+        )
+        .draw(rect, &c.draw_state, c.transform, gl);
+    }
+}
+
+/// Synchronize with the server, and subscribe to snapshots for our channel.
+/// Return the snapshot queue, the synchronized clock, which is kept up to
+/// date until the run flag is stopped, and whether the server is being heard.
+fn connect(
+    cfg: &ClientConfig,
+    ctx: &mut Context,
+    run_flag: &RunFlag,
+) -> Result<Connection, Box<dyn Error>> {
+    // Start up the timesync service.
+    let mut timesync_client = TimesyncClient::new(&cfg.server_hostname, ctx)?;
+
+    // Synchronize timing with master host.
+    info!(
+        "Synchronizing timing.  This will take about {} seconds.",
+        timesync_client.synchronization_duration().as_secs()
+    );
+
+    let synchronizer = Synchronizer::new(timesync_client.synchronize()?);
+
+    info!("Synchronized.");
+
+    // Spin off another thread to periodically update our host time synchronization.
+    let timesync_period = cfg.timesync_interval;
+    let timesync = Arc::new(Mutex::new(synchronizer));
+    let timesync_remote = timesync.clone();
+    let timesync_run_flag = run_flag.clone();
+
+    thread::Builder::new()
+        .name("timesync".to_string())
+        .spawn(move || {
+            // FIXME: rather than sleep/flag polling we should use a select
+            // mechanism to ensure prompt quit.
+            while timesync_run_flag.should_run() {
+                thread::sleep(timesync_period);
+                match timesync_client.synchronize() {
+                    Ok(sync) => {
+                        let new_estimate = sync.now();
+                        let mut synchronizer =
+                            timesync_remote.lock().expect("Timesync mutex poisoned.");
+                        let old_estimate = synchronizer.now();
+                        info!(
+                            "Updating time sync.  Change from previous estimate: {}",
+                            new_estimate - old_estimate
+                        );
+                        synchronizer.update_current(sync);
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                    }
+                }
+            }
+            info!("Timesync service shutting down.");
+        })
+        .map_err(|e| format!("Timesync service thread failed to spawn: {}", e))?;
+
+    start_heartbeat(
+        &cfg.server_hostname,
+        cfg.video_channel,
+        cfg.show_id.clone(),
+        ctx,
+        run_flag.clone(),
+    )?;
+
+    // Set up snapshot reception and management.
+    let topic = frame_topic(cfg.show_id.as_deref(), cfg.video_channel as u8);
+    let keepalive = keepalive_topic(cfg.show_id.as_deref());
+    let receiver = SubReceiver::new(&cfg.server_hostname, 6000, &topic, &keepalive, ctx)?;
+    let signal = receiver.signal();
+    let (snapshot_queue, errors) = receiver.run_async()?;
+
+    Ok((snapshot_queue, timesync, Some((signal, errors))))
+}
+
+/// The window and the renderers drawing into its GL context.
+struct Surface {
+    /// The renderers are declared before the window, so that their shaders
+    /// and buffers are released while the context that holds them still
+    /// exists.
+    arcs: ArcRenderer,
+    gl: GlGraphics,
+    window: PistonWindow<Sdl2Window>,
+}
+
+impl Surface {
+    fn open(cfg: &ClientConfig) -> Result<Self, Box<dyn Error>> {
+        let build = || -> Result<PistonWindow<Sdl2Window>, Box<dyn Error>> {
+            WindowSettings::new(
+                format!("tunnelclient: channel {}", cfg.video_channel),
+                [cfg.x_resolution, cfg.y_resolution],
+            )
+            .graphics_api(OPENGL)
+            .exit_on_esc(false)
+            .vsync(true)
+            .samples(if cfg.anti_alias { 4 } else { 0 })
+            .fullscreen(cfg.fullscreen)
+            .build()
+        };
+        request_robust_context(true)?;
+        let mut window = match build() {
+            Ok(window) => window,
+            Err(e) => {
+                warn!(
+                    "Unable to open a window with a robust GL context ({}); GPU resets won't be detected.",
+                    e
+                );
+                request_robust_context(false)?;
+                build()?
+            }
+        };
+
+        window.set_capture_cursor(cfg.capture_mouse);
+        window.set_max_fps(120);
+        // Load GL functions from the new context for the arc renderer; GlGraphics
+        // loads its own.
+        gl::load_with(|name| window.window.get_proc_address(name) as *const _);
+
+        Ok(Self {
+            arcs: ArcRenderer::new()?,
+            gl: GlGraphics::new(OPENGL),
+            window,
+        })
+    }
+
+    /// Return true if the driver reports that the GL context was reset, taking
+    /// everything stored in it.  Only robust contexts report resets.
+    fn context_lost(&self) -> bool {
+        gl::GetGraphicsResetStatus::is_loaded()
+            && unsafe { gl::GetGraphicsResetStatus() } != gl::NO_ERROR
+    }
+}
+
+/// The few SDL calls needed to ask for a robust context, which the window
+/// backend doesn't expose.
+mod sdl {
+    use std::os::raw::c_int;
+
+    pub const INIT_VIDEO: u32 = 0x20;
+    pub const GL_CONTEXT_FLAGS: c_int = 20;
+    pub const GL_CONTEXT_RESET_NOTIFICATION: c_int = 25;
+    pub const GL_CONTEXT_ROBUST_ACCESS_FLAG: c_int = 0x4;
+    pub const GL_CONTEXT_RESET_LOSE_CONTEXT: c_int = 0x1;
+
+    extern "C" {
+        pub fn SDL_InitSubSystem(flags: u32) -> c_int;
+        pub fn SDL_GL_SetAttribute(attr: c_int, value: c_int) -> c_int;
+    }
+}
+
+/// Ask for the next window's GL context to be robust, with the lose context
+/// reset strategy, so that the driver reports GPU resets through
+/// glGetGraphicsResetStatus, or ask for an ordinary context.
+/// SDL resets GL attributes when its video subsystem starts, so it is started
+/// here first, and the window backend then shares it.  It is shut down along
+/// with the window.
+fn request_robust_context(robust: bool) -> Result<(), Box<dyn Error>> {
+    let (flags, reset) = if robust {
+        (
+            sdl::GL_CONTEXT_ROBUST_ACCESS_FLAG,
+            sdl::GL_CONTEXT_RESET_LOSE_CONTEXT,
+        )
+    } else {
+        (0, 0)
+    };
+    let ok = unsafe {
+        sdl::SDL_InitSubSystem(sdl::INIT_VIDEO) == 0
+            && sdl::SDL_GL_SetAttribute(sdl::GL_CONTEXT_FLAGS, flags) == 0
+            && sdl::SDL_GL_SetAttribute(sdl::GL_CONTEXT_RESET_NOTIFICATION, reset) == 0
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err("Unable to initialize SDL video.".into())
+    }
 }
 
 /// Logging helper that either logs everything at debug level or occasionally logs at warn level.
@@ -230,7 +546,7 @@ impl RenderIssueLogger {
     fn new(interval: Duration) -> Self {
         Self {
             interval,
-            last_logged: Timestamp(0),
+            last_logged: Timestamp::ZERO,
             missed: 0,
             log_all: max_level() >= Level::Debug,
         }
@@ -249,7 +565,7 @@ impl RenderIssueLogger {
             warn!(
                 "Missed {} snapshots in the last {} seconds.",
                 self.missed,
-                dt.0 as f64 / 1_000_000.
+                dt.as_secs_f64()
             );
             self.missed = 0;
         }
@@ -1,10 +1,12 @@
 //! Handle emptying a queue of snapshots, maintaining a time-ordered collection,
 //! and interpolating between them on demand.
 
+use crate::interpolate::Interpolate;
+use crate::sanitize::SnapshotFilter;
 use std::collections::VecDeque;
 use std::sync::mpsc::{Receiver, TryRecvError};
+use tunnels_lib::Snapshot;
 use tunnels_lib::Timestamp;
-use tunnels_lib::{LayerCollection, Snapshot};
 
 /// Handle receiving and maintaining a collection of snapshots.
 /// Provide interpolated snapshots on request.
@@ -12,6 +14,10 @@ pub struct SnapshotManager {
     snapshot_queue: Receiver<Snapshot>,
     snapshots: VecDeque<Snapshot>, // Ordered queue of snapshots; latest is snapshots.front()
     oldest_relevant_snapshot_time: Timestamp,
+    filter: SnapshotFilter,
+    /// If true, interpolate between the snapshots either side of the render
+    /// time rather than drawing the newer one.
+    interpolate: bool,
 }
 
 pub enum SnapshotUpdateError {
@@ -19,11 +25,11 @@ pub enum SnapshotUpdateError {
 }
 
 pub enum InterpResult {
-    NoData,                        // no data is available at all
-    Good(LayerCollection),         // Both snapshots were available.
-    MissingNewer(LayerCollection), // Data is out-of-date for current timestamp.
-    MissingOlder(LayerCollection), // We only have snapshot data newer than requested.
-    Error(Vec<Snapshot>),          // Something went wrong and we couldn't perform interpolation.
+    NoData,                 // no data is available at all
+    Good(Snapshot),         // Both snapshots were available.
+    MissingNewer(Snapshot), // Data is out-of-date for current timestamp.
+    MissingOlder(Snapshot), // We only have snapshot data newer than requested.
+    Error(Vec<Snapshot>),   // Something went wrong and we couldn't perform interpolation.
 }
 
 enum InsertStrategy {
@@ -32,11 +38,13 @@ enum InsertStrategy {
 }
 
 impl SnapshotManager {
-    pub fn new(queue: Receiver<Snapshot>) -> Self {
+    pub fn new(queue: Receiver<Snapshot>, interpolate: bool) -> Self {
         SnapshotManager {
             snapshot_queue: queue,
             snapshots: VecDeque::new(),
-            oldest_relevant_snapshot_time: Timestamp(0),
+            oldest_relevant_snapshot_time: Timestamp::ZERO,
+            filter: SnapshotFilter::new(),
+            interpolate,
         }
     }
 
@@ -79,11 +87,16 @@ impl SnapshotManager {
         }
     }
 
-    /// Drain the snapshot queue and store all the results.
+    /// Drain the snapshot queue and store all the results, after cleaning up
+    /// any invalid arcs.  Arcs drawn from a palette are resolved into raw
+    /// color, and any master color correction is applied, so that
+    /// interpolation and drawing never need either.
     fn drain_queue(&mut self) -> Result<(), SnapshotUpdateError> {
         loop {
             match self.get_from_queue() {
-                Ok(Some(snapshot)) => {
+                Ok(Some(mut snapshot)) => {
+                    self.filter.filter(&mut snapshot);
+                    snapshot.resolve_color();
                     self.insert_snapshot(snapshot);
                 }
                 Ok(None) => return Ok(()),
@@ -126,10 +139,10 @@ impl SnapshotManager {
                 let s = &snaps[0];
                 if s.time < time {
                     self.oldest_relevant_snapshot_time = s.time;
-                    InterpResult::MissingNewer(s.layers.clone())
+                    InterpResult::MissingNewer(s.clone())
                 } else {
                     // don't update oldest relevant time as we're missing it!
-                    InterpResult::MissingOlder(s.layers.clone())
+                    InterpResult::MissingOlder(s.clone())
                 }
             }
             _ => {
@@ -137,21 +150,21 @@ impl SnapshotManager {
                 if let Some(s) = snaps.front() {
                     if s.time < time {
                         self.oldest_relevant_snapshot_time = s.time;
-                        return InterpResult::MissingNewer(s.layers.clone());
+                        return InterpResult::MissingNewer(s.clone());
                     }
                 }
                 // Find the two snapshots that bracket the requested timestamp.
                 for (newer, older) in snaps.iter().zip(snaps.iter().skip(1)) {
                     if time <= newer.time && time >= older.time {
-                        // #11 interpolation is not necessary with 60 fps render server and microsecond timing.
-                        // Also it causes annoying artifacts where chicklets sometimes appear where they shouldn't.
-                        // let older_time = older.time.0 as f64;
-                        // let newer_time = newer.time.0 as f64;
-                        //let alpha = (time.0 as f64 - older_time) / (newer_time - older_time);
-                        //let interpolation_result = older.layers.interpolate_with(&newer.layers, alpha);
-
                         self.oldest_relevant_snapshot_time = older.time;
-                        return InterpResult::Good(newer.layers.clone());
+                        // #11 interpolation is not necessary when the
+                        // server renders as fast as the client draws, and
+                        // can cause artifacts where chicklets briefly appear
+                        // where they shouldn't, so it is optional.
+                        if !self.interpolate {
+                            return InterpResult::Good(newer.clone());
+                        }
+                        return InterpResult::Good(interpolate(older, newer, time));
                     }
                 }
                 InterpResult::Error(Vec::from(snaps.clone()))
@@ -160,12 +173,26 @@ impl SnapshotManager {
     }
 }
 
+/// Return a snapshot interpolated between two others at the provided time.
+/// Everything but the arcs themselves is taken from the newer snapshot.
+fn interpolate(older: &Snapshot, newer: &Snapshot, time: Timestamp) -> Snapshot {
+    let span = (newer.time.0 - older.time.0) as f64;
+    let alpha = if span > 0.0 {
+        (time.0 - older.time.0) as f64 / span
+    } else {
+        1.0
+    };
+    Snapshot {
+        layers: older.layers.interpolate_with(&newer.layers, alpha),
+        ..newer.clone()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use tunnels_lib::{ArcSegment, Snapshot};
 
     use super::*;
-    use crate::interpolate::Interpolate;
     use crate::receive::test::arc_segment_for_test;
     use std::iter::Iterator;
     use std::sync::mpsc::{channel, Sender};
@@ -176,6 +203,9 @@ mod tests {
             frame_number: n,
             time,
             layers: Vec::new(),
+            palette: None,
+            blend: Vec::new(),
+            color: None,
         }
     }
 
@@ -197,7 +227,7 @@ mod tests {
 
     fn setup_sm() -> (Sender<Snapshot>, SnapshotManager) {
         let (tx, rx) = channel();
-        let sm = SnapshotManager::new(rx);
+        let sm = SnapshotManager::new(rx, false);
         (tx, sm)
     }
 
@@ -256,7 +286,7 @@ mod tests {
         let snap = mksnapshot_with_arc(0, Timestamp(0), arc_segment_for_test(0.2, 0.3));
         sm.insert_snapshot(snap.clone());
         if let InterpResult::MissingNewer(f) = sm.get_interpolated(Timestamp(1000)) {
-            assert_eq!(snap.layers, f);
+            assert_eq!(snap.layers, f.layers);
         } else {
             panic!();
         }
@@ -268,7 +298,7 @@ mod tests {
         let snap = mksnapshot_with_arc(0, Timestamp(10000), arc_segment_for_test(0.2, 0.3));
         sm.insert_snapshot(snap.clone());
         if let InterpResult::MissingOlder(f) = sm.get_interpolated(Timestamp(1000)) {
-            assert_eq!(snap.layers, f);
+            assert_eq!(snap.layers, f.layers);
         } else {
             panic!();
         }
@@ -287,7 +317,7 @@ mod tests {
     fn test_interp_two_frames_exact_newer() {
         let (mut sm, _snap0, snap1) = setup_two_frame_test();
         if let InterpResult::Good(f) = sm.get_interpolated(Timestamp(1000)) {
-            assert_eq!(snap1.layers, f);
+            assert_eq!(snap1.layers, f.layers);
         } else {
             panic!();
         }
@@ -297,7 +327,7 @@ mod tests {
     fn test_interp_two_frames_exact_older() {
         let (mut sm, snap0, _snap1) = setup_two_frame_test();
         if let InterpResult::Good(f) = sm.get_interpolated(Timestamp(0)) {
-            assert_eq!(snap0.layers, f);
+            assert_eq!(snap0.layers, f.layers);
         } else {
             panic!();
         }
@@ -307,9 +337,30 @@ mod tests {
     fn test_interp_two_frames_middle() {
         let (mut sm, snap0, snap1) = setup_two_frame_test();
         if let InterpResult::Good(f) = sm.get_interpolated(Timestamp(5000)) {
-            assert_eq!(snap0.layers.interpolate_with(&snap1.layers, 0.0), f);
+            assert_eq!(snap0.layers.interpolate_with(&snap1.layers, 0.0), f.layers);
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_interp_enabled() {
+        let (_, mut sm) = setup_sm();
+        sm.interpolate = true;
+        let mut older = arc_segment_for_test(0.2, 0.3);
+        older.hue = 0.9;
+        let mut newer = arc_segment_for_test(0.6, 0.7);
+        newer.hue = 0.1;
+        sm.insert_snapshot(mksnapshot_with_arc(0, Timestamp(0), older.clone()));
+        sm.insert_snapshot(mksnapshot_with_arc(1, Timestamp(10000), newer.clone()));
+        if let InterpResult::Good(f) = sm.get_interpolated(Timestamp(2500)) {
+            assert_eq!(1, f.frame_number);
+            assert_eq!(older.interpolate_with(&newer, 0.25), f.layers[0][0]);
+            // Hue takes the short way around the wheel.
+            assert!((f.layers[0][0].hue - 0.95).abs() < 1e-9);
         } else {
             panic!();
         }
+        assert_eq!(Timestamp(0), sm.oldest_relevant_snapshot_time);
     }
 }
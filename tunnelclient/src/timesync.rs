@@ -3,18 +3,17 @@
 //! http://www.mine-control.com/zack/timesync/timesync.html
 
 use crate::receive::Receive;
-use interpolation::lerp;
 use simple_error::bail;
-use stats::{mean, stddev};
+use stats::stddev;
 use std::error::Error;
 use std::mem;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
-use tunnels_lib::{number::UnipolarFloat, Timestamp};
+use tunnels_lib::{number::UnipolarFloat, ports, Timestamp};
 use zmq;
 use zmq::{Context, Socket, DONTWAIT};
 
-const PORT: u64 = 8989;
+const PORT: u16 = ports::TIMESYNC;
 
 /// Provide estimates of the offset between this host's monotonic clock and the server's.
 pub struct Client {
@@ -94,7 +93,10 @@ impl Client {
             m.timestamp - Timestamp::from_duration(delta)
         });
         // Take the average of these estimates, and we're done
-        let best_remote_time_estimate = Timestamp(mean(remote_time_estimates) as i64);
+        let best_remote_time_estimate = match Timestamp::mean(remote_time_estimates) {
+            Some(t) => t,
+            None => bail!("No usable synchronization samples."),
+        };
         Ok(Timesync {
             ref_time: reference_time,
             host_ref_time: best_remote_time_estimate,
@@ -127,6 +129,14 @@ pub struct Timesync {
 }
 
 impl Timesync {
+    /// Use this host's clock as the host clock, reading start as of now.
+    pub fn local(start: Timestamp) -> Self {
+        Self {
+            ref_time: Instant::now(),
+            host_ref_time: start,
+        }
+    }
+
     /// Return an estimate of what time it is now on the host.
     pub fn now(&self) -> Timestamp {
         self.host_ref_time + Timestamp::from_duration(self.ref_time.elapsed())
@@ -176,7 +186,7 @@ impl Synchronizer {
             current
         } else {
             let old = self.last.now();
-            Timestamp(lerp(&old.0, &current.0, &self.alpha.val()))
+            old.lerp(current, self.alpha.val())
         }
     }
 }
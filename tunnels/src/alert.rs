@@ -0,0 +1,182 @@
+//! Problems that need the operator's attention during a show.
+//!
+//! Raising an alert logs it, flashes the alert LED on the main controller
+//! until it is acknowledged, and queues it for display by front ends.  Front
+//! ends poll for alerts over a zmq REP socket: each request is the msgpack id
+//! of the last alert they have seen (0 for none), and each reply is every
+//! queued alert newer than that, as msgpack with structs encoded as maps.
+//! Only the most recent alerts are kept.
+
+use chrono::Local;
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tracing::{error, info, warn};
+use tunnels_lib::{ports, RunFlag};
+use zmq::Context;
+
+const PORT: u16 = ports::ALERTS;
+
+/// How many alerts to keep for front ends.
+const MAX_QUEUED: usize = 100;
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub enum AlertKind {
+    /// A MIDI control surface stopped accepting messages.
+    MidiDeviceLost,
+    /// A render client stopped sending heartbeats.
+    ClientLost,
+    /// The show fell behind its frame schedule.
+    FrameOverrun,
+    /// An edit to the show config couldn't be applied.
+    ConfigRejected,
+    /// The flash limit was overridden.
+    FlashLimitOverride,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// Increases by one with each alert raised.
+    pub id: u64,
+    /// Local time the alert was raised, as HH:MM:SS.
+    pub time: String,
+    pub kind: AlertKind,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct Queue {
+    next_id: u64,
+    alerts: VecDeque<Alert>,
+}
+
+/// The most recent alerts, shared with the alert server.
+#[derive(Clone, Default)]
+pub struct AlertQueue(Arc<Mutex<Queue>>);
+
+impl AlertQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log an alert and queue it for front ends.
+    pub fn push(&self, kind: AlertKind, message: String) {
+        warn!("{}", message);
+        let mut queue = match self.0.lock() {
+            Ok(queue) => queue,
+            Err(_) => return,
+        };
+        queue.next_id += 1;
+        let alert = Alert {
+            id: queue.next_id,
+            time: Local::now().format("%H:%M:%S").to_string(),
+            kind,
+            message,
+        };
+        if queue.alerts.len() == MAX_QUEUED {
+            queue.alerts.pop_front();
+        }
+        queue.alerts.push_back(alert);
+    }
+
+    /// Return every queued alert with an id greater than the provided id.
+    pub fn since(&self, id: u64) -> Vec<Alert> {
+        match self.0.lock() {
+            Ok(queue) => queue
+                .alerts
+                .iter()
+                .filter(|alert| alert.id > id)
+                .cloned()
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+pub struct AlertServer {
+    join_handle: Option<thread::JoinHandle<()>>,
+    run: RunFlag,
+}
+
+impl AlertServer {
+    /// Start serving alerts from the provided queue.
+    /// The server will run until it is dropped.
+    pub fn start(ctx: &mut Context, alerts: AlertQueue) -> Result<Self, Box<dyn Error>> {
+        let socket = ctx.socket(zmq::REP)?;
+        socket.bind(&format!("tcp://*:{}", PORT))?;
+        // time out once per second
+        socket.set_rcvtimeo(1000)?;
+        let run = RunFlag::new();
+        let run_local = run.clone();
+
+        let jh = thread::Builder::new()
+            .name("alert".to_string())
+            .spawn(move || {
+                let mut resp = Vec::new();
+                loop {
+                    if !run.should_run() {
+                        return;
+                    }
+                    let msg = match socket.recv_bytes(0) {
+                        Err(zmq::Error::EAGAIN) => continue,
+                        Err(e) => {
+                            error!("Alert server receive error: {}.", e);
+                            continue;
+                        }
+                        Ok(msg) => msg,
+                    };
+                    // A malformed request gets everything.
+                    let last_seen =
+                        u64::deserialize(&mut Deserializer::new(&msg[..])).unwrap_or_default();
+                    resp.clear();
+                    if let Err(e) = alerts
+                        .since(last_seen)
+                        .serialize(&mut Serializer::new(&mut resp).with_struct_map())
+                    {
+                        error!("Alert serialization error: {}.", e);
+                    }
+                    if let Err(e) = socket.send(&resp, 0) {
+                        error!("Alert server send error: {}.", e);
+                    }
+                }
+            })?;
+        info!("Alert server started.");
+        Ok(Self {
+            join_handle: Some(jh),
+            run: run_local,
+        })
+    }
+}
+
+impl Drop for AlertServer {
+    fn drop(&mut self) {
+        self.run.stop();
+        self.join_handle.take().unwrap().join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let alerts = AlertQueue::new();
+        for i in 0..MAX_QUEUED + 2 {
+            alerts.push(AlertKind::FrameOverrun, format!("alert {}", i));
+        }
+        let all = alerts.since(0);
+        assert_eq!(MAX_QUEUED, all.len());
+        // The oldest were dropped.
+        assert_eq!(3, all[0].id);
+        assert_eq!("alert 2", all[0].message);
+        let newer = alerts.since(all[MAX_QUEUED - 2].id);
+        assert_eq!(1, newer.len());
+        assert_eq!(MAX_QUEUED as u64 + 2, newer[0].id);
+    }
+}
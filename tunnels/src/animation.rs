@@ -1,34 +1,55 @@
+use crate::audio::AudioSource;
 use crate::clock::ControllableClock;
 use crate::master_ui::EmitStateChange as EmitShowStateChange;
 use crate::{clock::Clock, clock_bank::ClockBank};
-use crate::{clock_bank::ClockIdx, waveforms};
+use crate::{clock_bank::ClockIdx, params, random::ShowRng, validation, waveforms};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tunnels_lib::number::{BipolarFloat, Phase, UnipolarFloat};
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
-pub enum Waveform {
-    Sine,
-    Triangle,
-    Square,
-    Sawtooth,
+/// Declare a fieldless enum along with NAMES, the name of each variant in
+/// declaration order, for listing the options on controls.
+macro_rules! named_variants {
+    ($(#[$meta:meta])* pub enum $name:ident { $($variant:ident,)* }) => {
+        $(#[$meta])*
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($variant)),*];
+        }
+    };
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug)]
-pub enum Target {
-    Rotation,
-    Thickness,
-    Size,
-    AspectRatio,
-    Color,
-    ColorSpread,
-    ColorPeriodicity,
-    ColorSaturation,
-    MarqueeRotation,
-    Segments,
-    Blacking,
-    PositionX,
-    PositionY,
+named_variants! {
+    #[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+    pub enum Waveform {
+        Sine,
+        Triangle,
+        Square,
+        Sawtooth,
+    }
+}
+
+named_variants! {
+    #[derive(Copy, Clone, Serialize, Deserialize, Debug)]
+    pub enum Target {
+        Rotation,
+        Thickness,
+        Size,
+        AspectRatio,
+        Color,
+        ColorSpread,
+        ColorPeriodicity,
+        ColorSaturation,
+        MarqueeRotation,
+        Segments,
+        Blacking,
+        PositionX,
+        PositionY,
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -43,6 +64,27 @@ pub struct Animation {
     smoothing: UnipolarFloat,
     internal_clock: Clock,
     clock_source: Option<ClockIdx>,
+    /// Chance that each cycle of the waveform is played rather than skipped.
+    #[serde(default = "default_probability")]
+    probability: UnipolarFloat,
+    /// Is the current cycle being skipped?
+    #[serde(default)]
+    skipping: bool,
+    /// Phase the waveform was at when the current cycle started being
+    /// skipped, held until the next cycle plays.
+    #[serde(skip, default = "zero_phase")]
+    held_phase: Phase,
+    /// If set, follow this level of the audio input instead of the waveform.
+    #[serde(default)]
+    audio_source: Option<AudioSource>,
+}
+
+fn default_probability() -> UnipolarFloat {
+    UnipolarFloat::new(params::ANIMATION_PROBABILITY.default)
+}
+
+fn zero_phase() -> Phase {
+    Phase::ZERO
 }
 
 impl Default for Animation {
@@ -64,6 +106,10 @@ impl Animation {
             smoothing: UnipolarFloat::new(0.25),
             internal_clock: Clock::new(),
             clock_source: None,
+            probability: default_probability(),
+            skipping: false,
+            held_phase: Phase::ZERO,
+            audio_source: None,
         }
     }
 
@@ -73,6 +119,10 @@ impl Animation {
     }
 
     fn phase(&self, external_clocks: &ClockBank) -> Phase {
+        // Hold the waveform where the last played cycle left it.
+        if self.skipping {
+            return self.held_phase;
+        }
         match self.clock_source {
             None => self.internal_clock.phase(),
             Some(id) => external_clocks.phase(id),
@@ -89,38 +139,72 @@ impl Animation {
         self.internal_clock.rate = speed.val() * ControllableClock::RATE_SCALE;
     }
 
-    pub fn update_state(&mut self, delta_t: Duration) {
+    pub fn update_state(
+        &mut self,
+        delta_t: Duration,
+        external_clocks: &ClockBank,
+        rng: &mut ShowRng,
+    ) {
         if self.active() {
             self.internal_clock.update_state(delta_t);
         }
+        let ticked = match self.clock_source {
+            None => self.internal_clock.ticked(),
+            Some(id) => external_clocks.ticked(id),
+        };
+        if ticked {
+            self.skipping = rng.gen::<f64>() >= self.probability.val();
+        }
+        // Once skipping, this keeps the phase from the frame before the tick.
+        if !self.skipping {
+            self.held_phase = self.phase(external_clocks);
+        }
     }
 
     pub fn get_value(&self, phase_offset: Phase, external_clocks: &ClockBank) -> f64 {
         if !self.active() {
             return 0.;
         }
-
-        let angle = self.phase(external_clocks) + phase_offset * (self.n_periods as f64);
-        let waveform_func = match self.waveform {
-            Waveform::Sine => waveforms::sine,
-            Waveform::Square => waveforms::square,
-            Waveform::Sawtooth => waveforms::sawtooth,
-            Waveform::Triangle => waveforms::triangle,
-        };
-        let mut result =
-            self.weight.val() * waveform_func(angle, self.smoothing, self.duty_cycle, self.pulse);
+        let mut result = self.weight.val() * self.raw_value(phase_offset, external_clocks);
 
         // scale this animation by submaster level if using external clock
-        if let Some(id) = self.clock_source {
+        if let (None, Some(id)) = (self.audio_source, self.clock_source) {
             result *= external_clocks.submaster_level(id).val();
         }
+        result
+    }
+
+    /// Return the output of this animation as if at full weight.
+    fn raw_value(&self, phase_offset: Phase, external_clocks: &ClockBank) -> f64 {
+        let result = if let Some(source) = self.audio_source {
+            external_clocks.audio_level(source).val()
+        } else {
+            let angle = self.phase(external_clocks) + phase_offset * (self.n_periods as f64);
+            let waveform_func = match self.waveform {
+                Waveform::Sine => waveforms::sine,
+                Waveform::Square => waveforms::square,
+                Waveform::Sawtooth => waveforms::sawtooth,
+                Waveform::Triangle => waveforms::triangle,
+            };
+            waveform_func(angle, self.smoothing, self.duty_cycle, self.pulse)
+        };
         if self.invert {
-            -1.0 * result
+            -result
         } else {
             result
         }
     }
 
+    /// Return the raw output of this animation, for display while it is
+    /// being listened to.  An animation with no weight isn't run by its
+    /// tunnel, so run its internal clock here instead.
+    pub fn listen(&mut self, delta_t: Duration, external_clocks: &ClockBank) -> BipolarFloat {
+        if !self.active() {
+            self.internal_clock.update_state(delta_t);
+        }
+        BipolarFloat::new(self.raw_value(Phase::ZERO, external_clocks))
+    }
+
     /// Emit the current value of all controllable animator state.
     pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
         use StateChange::*;
@@ -134,6 +218,8 @@ impl Animation {
         emitter.emit_animation_state_change(DutyCycle(self.duty_cycle));
         emitter.emit_animation_state_change(Smoothing(self.smoothing));
         emitter.emit_animation_state_change(ClockSource(self.clock_source));
+        emitter.emit_animation_state_change(Probability(self.probability));
+        emitter.emit_animation_state_change(AudioSource(self.audio_source));
     }
 
     /// Handle a control event.
@@ -155,6 +241,10 @@ impl Animation {
 
     fn handle_state_change<E: EmitStateChange>(&mut self, sc: StateChange, emitter: &mut E) {
         use StateChange::*;
+        let sc = match validation::animation(sc) {
+            Some(sc) => sc,
+            None => return,
+        };
         match sc {
             Waveform(v) => self.waveform = v,
             Pulse(v) => self.pulse = v,
@@ -166,6 +256,13 @@ impl Animation {
             DutyCycle(v) => self.duty_cycle = v,
             Smoothing(v) => self.smoothing = v,
             ClockSource(v) => self.clock_source = v,
+            Probability(v) => {
+                self.probability = v;
+                if v.val() >= 1.0 {
+                    self.skipping = false;
+                }
+            }
+            AudioSource(v) => self.audio_source = v,
         };
         emitter.emit_animation_state_change(sc);
     }
@@ -183,6 +280,10 @@ pub enum StateChange {
     DutyCycle(UnipolarFloat),
     Smoothing(UnipolarFloat),
     ClockSource(Option<ClockIdx>),
+    /// Chance that each cycle is played rather than skipped.
+    Probability(UnipolarFloat),
+    /// Follow a level of the audio input, or go back to the waveform.
+    AudioSource(Option<AudioSource>),
 }
 
 pub enum ControlMessage {
@@ -201,3 +302,76 @@ impl<T: EmitShowStateChange> EmitStateChange for T {
         self.emit(ShowStateChange::Animation(sc))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::master_ui::DummyEmitter;
+    use tunnels_lib::assert_almost_eq;
+
+    #[test]
+    fn test_listen() {
+        let clocks = ClockBank::new();
+        let mut a = Animation::new();
+        a.control(
+            ControlMessage::Set(StateChange::Speed(BipolarFloat::new(0.5))),
+            &mut DummyEmitter,
+        );
+        assert_eq!(0.0, a.listen(Duration::ZERO, &clocks).val());
+
+        // An animation with no weight still runs while listened to.
+        let raw = a.listen(Duration::from_millis(100), &clocks).val();
+        assert!(raw.abs() > 0.1);
+        assert_eq!(0.0, a.get_value(Phase::ZERO, &clocks));
+
+        a.control(
+            ControlMessage::Set(StateChange::Weight(UnipolarFloat::new(0.5))),
+            &mut DummyEmitter,
+        );
+        assert_almost_eq(0.5 * raw, a.get_value(Phase::ZERO, &clocks));
+        a.control(ControlMessage::ToggleInvert, &mut DummyEmitter);
+        assert_almost_eq(-raw, a.listen(Duration::ZERO, &clocks).val());
+    }
+
+    /// Run an animation at full weight through a few cycles, returning its
+    /// value and whether it was skipping after each frame.
+    fn run(probability: f64) -> Vec<(f64, bool)> {
+        let clocks = ClockBank::new();
+        let mut rng = crate::random::stream(0, 0);
+        let mut a = Animation::new();
+        for sc in [
+            StateChange::Weight(UnipolarFloat::ONE),
+            StateChange::Speed(BipolarFloat::new(-1.0)),
+            StateChange::Probability(UnipolarFloat::new(probability)),
+        ] {
+            a.control(ControlMessage::Set(sc), &mut DummyEmitter);
+        }
+        (0..300)
+            .map(|_| {
+                a.update_state(Duration::from_millis(10), &clocks, &mut rng);
+                (a.get_value(Phase::ZERO, &clocks), a.skipping)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_skip_holds_phase() {
+        let frames = run(0.0);
+        let start = frames.iter().position(|(_, skipping)| *skipping).unwrap();
+        assert!(start > 0);
+        // The value the last played frame left off at is held while skipping.
+        let held = frames[start - 1].0;
+        assert!(held.abs() > 0.01);
+        for (value, skipping) in &frames[start..] {
+            assert!(skipping);
+            assert_almost_eq(held, *value);
+        }
+    }
+
+    #[test]
+    fn test_never_skips_at_full_probability() {
+        let frames = run(1.0);
+        assert!(frames.iter().all(|(_, skipping)| !skipping));
+        assert!(frames.windows(2).any(|w| w[0].0 != w[1].0));
+    }
+}
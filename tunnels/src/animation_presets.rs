@@ -0,0 +1,75 @@
+use crate::animation::Animation;
+use serde::{Deserialize, Serialize};
+
+/// A library of animations that can be saved from any animator and applied
+/// to any other, independent of the beam store.
+#[derive(Serialize, Deserialize)]
+pub struct AnimationPresets {
+    presets: Vec<Option<AnimationPreset>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AnimationPreset {
+    /// Describes the animation at the time it was saved.
+    pub name: String,
+    pub animation: Animation,
+}
+
+impl Default for AnimationPresets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnimationPresets {
+    pub const N_PRESETS: usize = 16;
+
+    pub fn new() -> Self {
+        Self {
+            presets: vec![None; Self::N_PRESETS],
+        }
+    }
+
+    /// Save a copy of the provided animation into a preset slot, returning
+    /// the name it was saved under.
+    pub fn save(&mut self, index: usize, animation: &Animation) -> Option<&str> {
+        let slot = self.presets.get_mut(index)?;
+        let preset = slot.insert(AnimationPreset {
+            name: format!("{:?} {:?} {}", animation.waveform, animation.target, index),
+            animation: animation.clone(),
+        });
+        Some(&preset.name)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&AnimationPreset> {
+        self.presets.get(index)?.as_ref()
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = (usize, &Option<AnimationPreset>)> {
+        self.presets.iter().enumerate()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::animation::{Target, Waveform};
+
+    #[test]
+    fn test_save() {
+        let mut presets = AnimationPresets::new();
+        assert!(presets.get(0).is_none());
+
+        let mut animation = Animation::new();
+        animation.waveform = Waveform::Triangle;
+        animation.target = Target::Rotation;
+        assert_eq!(Some("Triangle Rotation 3"), presets.save(3, &animation));
+        assert!(presets
+            .save(AnimationPresets::N_PRESETS, &animation)
+            .is_none());
+
+        let preset = presets.get(3).unwrap();
+        assert!(matches!(preset.animation.waveform, Waveform::Triangle));
+        assert_eq!(1, presets.items().filter(|(_, p)| p.is_some()).count());
+    }
+}
@@ -0,0 +1,536 @@
+//! Capture audio from a system input device for analysis.
+//!
+//! Samples are processed on the audio thread, which publishes results through
+//! atomics that the show reads once per frame.  Along with the overall peak,
+//! the input is split into low, mid, and high frequency bands, each with its
+//! own peak.
+//!
+//! The peaks are also analyzed into smoothed levels and a beat pulse, which
+//! animations can follow in place of their waveform.
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Host, Sample, SampleFormat, Stream, StreamConfig,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_error::{bail, SimpleError};
+use std::{
+    error::Error,
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{error, info};
+use tunnels_lib::number::UnipolarFloat;
+
+use crate::master_ui::EmitStateChange as EmitShowStateChange;
+
+/// Select the audio input to analyze.
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Name of the audio host API, such as "ALSA" or "JACK".
+    /// Uses the system default if not provided.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Name of the input device, such as an ALSA loopback or a mixer feed.
+    /// Uses the host's default input if not provided.
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+/// Return the names of every available audio host.
+pub fn list_hosts() -> Vec<&'static str> {
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| id.name())
+        .collect()
+}
+
+/// Return the names of every input device on the selected host.
+pub fn list_input_devices(host: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(get_host(host)?
+        .input_devices()?
+        .filter_map(|d| d.name().ok())
+        .collect())
+}
+
+fn get_host(name: Option<&str>) -> Result<Host, Box<dyn Error>> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(cpal::default_host()),
+    };
+    for id in cpal::available_hosts() {
+        if id.name().eq_ignore_ascii_case(name) {
+            return Ok(cpal::host_from_id(id)?);
+        }
+    }
+    bail!(
+        "Audio host {} is not available. Available hosts: {}.",
+        name,
+        list_hosts().join(", ")
+    );
+}
+
+fn get_input_device(host: &Host, name: Option<&str>) -> Result<cpal::Device, Box<dyn Error>> {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return host
+                .default_input_device()
+                .ok_or_else(|| SimpleError::new("No audio input device is available.").into())
+        }
+    };
+    let mut available = Vec::new();
+    for device in host.input_devices()? {
+        if let Ok(device_name) = device.name() {
+            if device_name == name {
+                return Ok(device);
+            }
+            available.push(device_name);
+        }
+    }
+    bail!(
+        "No audio input device named {}. Available devices: {}.",
+        name,
+        available.join(", ")
+    );
+}
+
+/// A frequency band of the audio input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Band {
+    Low,
+    Mid,
+    High,
+}
+
+impl Band {
+    pub const ALL: [Band; N_BANDS] = [Band::Low, Band::Mid, Band::High];
+}
+
+pub const N_BANDS: usize = 3;
+
+/// Largest absolute sample values since the last read, as f32 bits.
+#[derive(Default)]
+struct Peaks {
+    overall: AtomicU32,
+    bands: [AtomicU32; N_BANDS],
+}
+
+/// Record a peak value if it is larger than the one already recorded.
+fn record_peak(peak: &AtomicU32, value: f32) {
+    // The bit patterns of non-negative floats sort in the same order as their
+    // values.
+    peak.fetch_max(value.to_bits(), Ordering::Relaxed);
+}
+
+fn take_peak(peak: &AtomicU32) -> f64 {
+    f32::from_bits(peak.swap(0, Ordering::Relaxed)) as f64
+}
+
+/// Split a signal into frequency bands with a pair of one-pole crossovers.
+/// The bands always sum to the input.
+struct BandSplitter {
+    low_coeff: f32,
+    high_coeff: f32,
+    /// Input lowpassed at the low crossover.
+    low: f32,
+    /// Input lowpassed at the high crossover.
+    not_high: f32,
+}
+
+impl BandSplitter {
+    /// Crossover between the low and mid bands, in Hz.
+    const LOW_CROSSOVER: f32 = 250.;
+    /// Crossover between the mid and high bands, in Hz.
+    const HIGH_CROSSOVER: f32 = 4000.;
+
+    fn new(sample_rate: u32) -> Self {
+        let coeff = |freq: f32| 1. - (-2. * PI * freq / sample_rate as f32).exp();
+        Self {
+            low_coeff: coeff(Self::LOW_CROSSOVER),
+            high_coeff: coeff(Self::HIGH_CROSSOVER),
+            low: 0.,
+            not_high: 0.,
+        }
+    }
+
+    /// Filter one sample, returning its component in each band.
+    fn split(&mut self, sample: f32) -> [f32; N_BANDS] {
+        self.low += self.low_coeff * (sample - self.low);
+        self.not_high += self.high_coeff * (sample - self.not_high);
+        [self.low, self.not_high - self.low, sample - self.not_high]
+    }
+}
+
+/// A running audio input stream.
+/// Capture stops when this is dropped.
+pub struct AudioInput {
+    peaks: Arc<Peaks>,
+    _stream: Stream,
+}
+
+impl AudioInput {
+    /// Start capturing from the configured input device.
+    pub fn start(cfg: &AudioConfig) -> Result<Self, Box<dyn Error>> {
+        let host = get_host(cfg.host.as_deref())?;
+        let device = get_input_device(&host, cfg.device.as_deref())?;
+        let supported = device.default_input_config()?;
+        let config: StreamConfig = supported.config();
+        let peaks = Arc::new(Peaks::default());
+
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, peaks.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, peaks.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, peaks.clone())?,
+        };
+        stream.play()?;
+        info!(
+            "Capturing audio from {} at {} Hz.",
+            device
+                .name()
+                .unwrap_or_else(|_| "unknown device".to_string()),
+            config.sample_rate.0
+        );
+        Ok(Self {
+            peaks,
+            _stream: stream,
+        })
+    }
+
+    /// Return the peak amplitude on [0, 1] received since the last call.
+    pub fn take_peak(&self) -> f64 {
+        take_peak(&self.peaks.overall)
+    }
+
+    /// Return the peak amplitude in each band received since the last call.
+    pub fn take_band_peaks(&self) -> [f64; N_BANDS] {
+        let mut peaks = [0.; N_BANDS];
+        for (peak, band) in peaks.iter_mut().zip(self.peaks.bands.iter()) {
+            *peak = take_peak(band);
+        }
+        peaks
+    }
+}
+
+fn build_stream<T: Sample>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    peaks: Arc<Peaks>,
+) -> Result<Stream, Box<dyn Error>> {
+    let n_channels = config.channels.max(1) as usize;
+    let mut splitter = BandSplitter::new(config.sample_rate.0);
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let buffer_peak = data.iter().map(|s| s.to_f32().abs()).fold(0.0f32, f32::max);
+            record_peak(&peaks.overall, buffer_peak);
+
+            // Split the mono mix of each frame into bands.
+            let mut band_peaks = [0.0f32; N_BANDS];
+            for frame in data.chunks(n_channels) {
+                let mono = frame.iter().map(|s| s.to_f32()).sum::<f32>() / n_channels as f32;
+                for (peak, v) in band_peaks.iter_mut().zip(splitter.split(mono).iter()) {
+                    *peak = peak.max(v.abs());
+                }
+            }
+            for (peak, v) in peaks.bands.iter().zip(band_peaks.iter()) {
+                record_peak(peak, *v);
+            }
+        },
+        |e| error!("Audio input error: {}.", e),
+    )?;
+    Ok(stream)
+}
+
+/// A level derived from the audio input that an animation can follow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioSource {
+    /// Overall level of the input.
+    Envelope,
+    /// A pulse on every beat, fading out before the next.
+    Beat,
+    /// Level of one frequency band.
+    Band(Band),
+}
+
+impl AudioSource {
+    pub const ALL: [AudioSource; 2 + N_BANDS] = [
+        AudioSource::Envelope,
+        AudioSource::Beat,
+        AudioSource::Band(Band::Low),
+        AudioSource::Band(Band::Mid),
+        AudioSource::Band(Band::High),
+    ];
+}
+
+/// The current level of every audio source.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AudioLevels {
+    envelope: f64,
+    beat: f64,
+    bands: [f64; N_BANDS],
+}
+
+impl AudioLevels {
+    pub fn get(&self, source: AudioSource) -> UnipolarFloat {
+        UnipolarFloat::new(match source {
+            AudioSource::Envelope => self.envelope,
+            AudioSource::Beat => self.beat,
+            AudioSource::Band(band) => self.bands[band as usize],
+        })
+    }
+}
+
+/// Analyze the peaks from the audio input into levels for animations.
+///
+/// Each peak is scaled against the loudest the input has recently been, so
+/// animations respond the same way whatever the input gain, then smoothed
+/// into an envelope that rises quickly and falls back more slowly.  A beat is
+/// a jump in the low band envelope well above its recent average.
+pub struct AudioAnalyzer {
+    /// Recent loudest peak of the overall input and of each band.
+    loudest: [f64; N_BANDS + 1],
+    /// Envelope of the overall input and of each band.
+    envelopes: [f64; N_BANDS + 1],
+    /// Slow average of the low band envelope.
+    low_average: f64,
+    /// Seconds since the last beat.
+    since_beat: f64,
+}
+
+impl Default for AudioAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioAnalyzer {
+    /// Input quieter than this, in dBFS, is treated as silence.
+    const FLOOR_DB: f64 = -50.;
+    /// Time constant for forgetting how loud the input has been, in seconds.
+    const LOUDEST_RELEASE: f64 = 10.;
+    /// Envelope time constants, in seconds.
+    const ATTACK: f64 = 0.01;
+    const RELEASE: f64 = 0.15;
+    /// Time constant of the low band average that beats stand out from.
+    const BEAT_AVERAGE: f64 = 1.;
+    /// How far above its average the low band must jump to be a beat.
+    const BEAT_THRESHOLD: f64 = 1.5;
+    /// Shortest time between beats, in seconds; 240 BPM.
+    const BEAT_HOLDOFF: f64 = 0.25;
+    /// Time constant of the beat pulse fading out, in seconds.
+    const BEAT_DECAY: f64 = 0.1;
+
+    pub fn new() -> Self {
+        Self {
+            loudest: [0.; N_BANDS + 1],
+            envelopes: [0.; N_BANDS + 1],
+            low_average: 0.,
+            since_beat: f64::INFINITY,
+        }
+    }
+
+    /// Update the analysis with the peak level overall and of each band since
+    /// the last update, and return the current levels.
+    pub fn update(
+        &mut self,
+        delta_t: Duration,
+        peak: f64,
+        band_peaks: [f64; N_BANDS],
+    ) -> AudioLevels {
+        let floor = db_to_amplitude(Self::FLOOR_DB);
+        let peaks = std::iter::once(peak).chain(band_peaks.iter().copied());
+        for ((envelope, loudest), peak) in self
+            .envelopes
+            .iter_mut()
+            .zip(self.loudest.iter_mut())
+            .zip(peaks)
+        {
+            let release = if peak > *loudest {
+                0.
+            } else {
+                Self::LOUDEST_RELEASE
+            };
+            *loudest = follow(*loudest, peak, release, delta_t).max(floor);
+            let target = (peak / *loudest).min(1.);
+            let time_constant = if target > *envelope {
+                Self::ATTACK
+            } else {
+                Self::RELEASE
+            };
+            *envelope = follow(*envelope, target, time_constant, delta_t);
+        }
+
+        let low = self.envelopes[1 + Band::Low as usize];
+        self.since_beat += delta_t.as_secs_f64();
+        if self.since_beat >= Self::BEAT_HOLDOFF && low > Self::BEAT_THRESHOLD * self.low_average {
+            self.since_beat = 0.;
+        }
+        self.low_average = follow(self.low_average, low, Self::BEAT_AVERAGE, delta_t);
+
+        let mut bands = [0.; N_BANDS];
+        bands.copy_from_slice(&self.envelopes[1..]);
+        AudioLevels {
+            envelope: self.envelopes[0],
+            beat: (-self.since_beat / Self::BEAT_DECAY).exp(),
+            bands,
+        }
+    }
+}
+
+/// Move an envelope toward a target level, closing about two thirds of the
+/// gap every time constant, in seconds.
+pub fn follow(envelope: f64, target: f64, time_constant: f64, delta_t: Duration) -> f64 {
+    if time_constant <= 0. {
+        return target;
+    }
+    let alpha = 1. - (-delta_t.as_secs_f64() / time_constant).exp();
+    envelope + alpha * (target - envelope)
+}
+
+/// Convert a level in decibels relative to full scale into an amplitude.
+pub fn db_to_amplitude(db: f64) -> f64 {
+    10f64.powf(db / 20.)
+}
+
+/// Convert an amplitude into a level in decibels relative to full scale.
+pub fn amplitude_to_db(amplitude: f64) -> f64 {
+    20. * amplitude.log10()
+}
+
+/// Peak level meter with a falling ballistic, for display.
+pub struct LevelMeter {
+    /// Current meter reading in dBFS.
+    db: f64,
+    /// Last level emitted, quantized to avoid flooding UIs with updates.
+    emitted: Option<u8>,
+}
+
+impl Default for LevelMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LevelMeter {
+    /// The bottom of the meter scale, in dBFS.
+    const FLOOR_DB: f64 = -60.;
+    /// How quickly the meter falls after a peak, in dB per second.
+    const FALL_RATE: f64 = 20.;
+    /// Resolution of emitted meter updates.
+    const STEPS: f64 = 127.;
+
+    pub fn new() -> Self {
+        Self {
+            db: Self::FLOOR_DB,
+            emitted: None,
+        }
+    }
+
+    /// Update the meter with the peak amplitude since the last update.
+    /// Emit the meter level if it has visibly changed.
+    pub fn update<E: EmitStateChange>(&mut self, delta_t: Duration, peak: f64, emitter: &mut E) {
+        let fallen = self.db - Self::FALL_RATE * delta_t.as_secs_f64();
+        self.db = amplitude_to_db(peak).max(fallen).max(Self::FLOOR_DB);
+        let level = self.level();
+        let quantized = (level.val() * Self::STEPS).round() as u8;
+        if self.emitted != Some(quantized) {
+            self.emitted = Some(quantized);
+            emitter.emit_audio_state_change(StateChange::Level(level));
+        }
+    }
+
+    /// The meter reading, scaled onto the meter's range.
+    pub fn level(&self) -> UnipolarFloat {
+        UnipolarFloat::new(1.0 - self.db / Self::FLOOR_DB)
+    }
+}
+
+pub enum StateChange {
+    /// Input level meter reading.
+    Level(UnipolarFloat),
+}
+
+pub trait EmitStateChange {
+    fn emit_audio_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_audio_state_change(&mut self, sc: StateChange) {
+        use crate::show::StateChange as ShowStateChange;
+        self.emit(ShowStateChange::Audio(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Return the peak amplitude in each band of a sine wave, once the
+    /// filters have settled.
+    fn band_peaks(freq: f32) -> [f32; N_BANDS] {
+        const SAMPLE_RATE: u32 = 48000;
+        let mut splitter = BandSplitter::new(SAMPLE_RATE);
+        let mut peaks = [0.0f32; N_BANDS];
+        for i in 0..SAMPLE_RATE {
+            let sample = (2. * PI * freq * i as f32 / SAMPLE_RATE as f32).sin();
+            let bands = splitter.split(sample);
+            assert!((bands.iter().sum::<f32>() - sample).abs() < 1e-4);
+            if i > SAMPLE_RATE / 2 {
+                for (peak, v) in peaks.iter_mut().zip(bands.iter()) {
+                    *peak = peak.max(v.abs());
+                }
+            }
+        }
+        peaks
+    }
+
+    #[test]
+    fn test_analyzer() {
+        let mut analyzer = AudioAnalyzer::new();
+        let frame = Duration::from_millis(20);
+        let quiet = db_to_amplitude(-30.);
+        let mut update = |peak: f64| analyzer.update(frame, peak, [peak, 0., 0.]);
+
+        // A steady input settles at full scale, however quiet.
+        let mut levels = update(quiet);
+        for _ in 0..100 {
+            levels = update(quiet);
+        }
+        assert!(levels.get(AudioSource::Envelope).val() > 0.99);
+        assert!(levels.get(AudioSource::Band(Band::Low)).val() > 0.99);
+        assert_eq!(0., levels.get(AudioSource::Band(Band::High)).val());
+        // The beat at its onset has faded.
+        assert!(levels.get(AudioSource::Beat).val() < 0.01);
+
+        // A kick well above the recent level is a beat.
+        for _ in 0..50 {
+            update(quiet * 0.1);
+        }
+        assert_eq!(1., update(quiet).get(AudioSource::Beat).val());
+        assert!(update(quiet).get(AudioSource::Beat).val() < 1.);
+
+        // Silence is silent.
+        for _ in 0..100 {
+            levels = update(0.);
+        }
+        assert!(levels.get(AudioSource::Envelope).val() < 0.01);
+    }
+
+    #[test]
+    fn test_band_splitter() {
+        for (freq, band) in [(40., Band::Low), (1000., Band::Mid), (15000., Band::High)].iter() {
+            let peaks = band_peaks(*freq);
+            let loudest = (0..N_BANDS)
+                .max_by(|a, b| peaks[*a].partial_cmp(&peaks[*b]).unwrap())
+                .unwrap();
+            assert_eq!(*band, Band::ALL[loudest], "{} Hz: {:?}", freq, peaks);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Unattended operation: slowly evolve the show without an operator.
+//!
+//! When enabled, the autopilot counts beats of the master clock and, on a
+//! fixed schedule of beats, nudges a tunnel parameter, recalls a stored beam
+//! into a channel, or rotates the colors of every tunnel.  All choices are
+//! drawn from the show's random seed, varied by the autopilot's own seed, so
+//! the same pair of seeds always produces the same sequence of decisions, and
+//! rerolling the show's seed gives the autopilot fresh choices as well.
+
+use crate::{
+    beam::Beam,
+    clock_bank::{ClockBank, ClockIdx},
+    master_ui::{DummyEmitter, EmitStateChange as EmitShowStateChange},
+    mixer::{ChannelIdx, Mixer},
+    random::{self, ShowRng},
+    show::StateChange as ShowStateChange,
+    tunnel::{ControlMessage as TunnelControlMessage, StateChange as TunnelStateChange, Tunnel},
+};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::debug;
+use tunnels_lib::number::{BipolarFloat, Phase, UnipolarFloat};
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Autopilot {
+    enabled: bool,
+    /// Varies the autopilot's choices for the same show seed.
+    seed: u64,
+    /// Number of beats that have elapsed since the autopilot was last enabled.
+    beats: u64,
+    /// Time elapsed since the last beat.
+    since_beat: Duration,
+    /// The generator for the autopilot's choices, and the show seed it was
+    /// derived from.
+    #[serde(skip)]
+    rng: Option<(u64, ShowRng)>,
+}
+
+impl Default for Autopilot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Autopilot {
+    /// The autopilot's stream of the show seed, next to the mixer's stream
+    /// for choices made across channels.
+    const RANDOM_STREAM: u64 = u64::MAX - 1;
+    /// The autopilot follows this clock.
+    const MASTER_CLOCK: ClockIdx = ClockIdx(0);
+    /// If the master clock hasn't ticked in this long, count a beat anyway.
+    /// This keeps the autopilot moving if nobody has set a tempo.
+    const MAX_BEAT_INTERVAL: Duration = Duration::from_secs(2);
+    /// Mutate a tunnel parameter this often, in beats.
+    const MUTATE_INTERVAL: u64 = 4;
+    /// Rotate the colors of all tunnels this often, in beats.
+    const COLOR_ROTATE_INTERVAL: u64 = 16;
+    /// Recall a stored beam this often, in beats.
+    const RECALL_INTERVAL: u64 = 64;
+    /// Maximum change applied to a parameter in a single mutation.
+    const MUTATION_SIZE: f64 = 0.08;
+    /// Maximum hue shift applied in a single color rotation.
+    const COLOR_ROTATION_SIZE: f64 = 0.15;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            seed: 0,
+            beats: 0,
+            since_beat: Duration::from_secs(0),
+            rng: None,
+        }
+    }
+
+    /// Advance the autopilot, possibly making changes to the mixer.
+    /// Beams are recalled from the provided stored beams.
+    /// Return the channels whose beams were modified.
+    pub fn update_state(
+        &mut self,
+        delta_t: Duration,
+        stored_beams: &[&Beam],
+        mixer: &mut Mixer,
+        clocks: &ClockBank,
+    ) -> Vec<ChannelIdx> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        self.since_beat += delta_t;
+        if !clocks.ticked(Self::MASTER_CLOCK) && self.since_beat < Self::MAX_BEAT_INTERVAL {
+            return Vec::new();
+        }
+        self.since_beat = Duration::from_secs(0);
+        self.beats += 1;
+
+        // Start a fresh stream if the show seed has been rerolled.
+        let show_seed = mixer.seed();
+        if self.rng.as_ref().map(|(seed, _)| *seed) != Some(show_seed) {
+            let rng = random::variant(show_seed, Self::RANDOM_STREAM, self.seed);
+            self.rng = Some((show_seed, rng));
+        }
+        let rng = match &mut self.rng {
+            Some((_, rng)) => rng,
+            None => unreachable!(),
+        };
+
+        let mut modified = Vec::new();
+        if self.beats % Self::RECALL_INTERVAL == 0 {
+            modified.extend(recall_beam(stored_beams, mixer, rng));
+        }
+        if self.beats % Self::COLOR_ROTATE_INTERVAL == 0 {
+            modified.extend(rotate_colors(mixer, rng));
+        }
+        if self.beats % Self::MUTATE_INTERVAL == 0 {
+            modified.extend(mutate_tunnel(mixer, rng));
+        }
+        modified
+    }
+
+    /// Emit the current value of all controllable autopilot state.
+    pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
+        emitter.emit_autopilot_state_change(StateChange::Enabled(self.enabled));
+        emitter.emit_autopilot_state_change(StateChange::Seed(self.seed));
+    }
+
+    /// Handle a control event.
+    /// Emit any state changes that have happened as a result of handling.
+    pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) {
+        use ControlMessage::*;
+        match msg {
+            Set(sc) => self.handle_state_change(sc, emitter),
+            ToggleEnabled => self.handle_state_change(StateChange::Enabled(!self.enabled), emitter),
+        }
+    }
+
+    fn handle_state_change<E: EmitStateChange>(&mut self, sc: StateChange, emitter: &mut E) {
+        use StateChange::*;
+        match sc {
+            Enabled(v) => {
+                if v && !self.enabled {
+                    // Start the schedule over so each session is reproducible.
+                    self.beats = 0;
+                    self.since_beat = Duration::from_secs(0);
+                    self.rng = None;
+                }
+                self.enabled = v;
+            }
+            Seed(v) => {
+                self.seed = v;
+                self.rng = None;
+            }
+        };
+        emitter.emit_autopilot_state_change(sc);
+    }
+}
+
+/// Pick a channel to act on, preferring channels that are currently visible.
+fn pick_channel<R: Rng>(mixer: &mut Mixer, tunnels_only: bool, rng: &mut R) -> Option<ChannelIdx> {
+    let mut live = Vec::new();
+    let mut all = Vec::new();
+    for (i, channel) in mixer.channels().enumerate() {
+        if tunnels_only && !matches!(channel.beam, Beam::Tunnel(_)) {
+            continue;
+        }
+        all.push(ChannelIdx(i));
+        if channel.level > 0.0 || channel.bump {
+            live.push(ChannelIdx(i));
+        }
+    }
+    if live.is_empty() {
+        all.choose(rng).copied()
+    } else {
+        live.choose(rng).copied()
+    }
+}
+
+/// Replace the beam in a channel with a randomly-chosen stored beam.
+fn recall_beam<R: Rng>(
+    stored_beams: &[&Beam],
+    mixer: &mut Mixer,
+    rng: &mut R,
+) -> Option<ChannelIdx> {
+    let beam = (*stored_beams.choose(rng)?).clone();
+    let channel = pick_channel(mixer, false, rng)?;
+    debug!("Autopilot recalling a beam into channel {}.", channel.0);
+    *mixer.beam(channel) = beam;
+    Some(channel)
+}
+
+/// Shift the color center of every tunnel by the same random amount.
+fn rotate_colors<R: Rng>(mixer: &mut Mixer, rng: &mut R) -> Vec<ChannelIdx> {
+    let shift = rng.gen_range(-Autopilot::COLOR_ROTATION_SIZE..Autopilot::COLOR_ROTATION_SIZE);
+    let mut modified = Vec::new();
+    for (i, channel) in mixer.channels().enumerate() {
+        if let Beam::Tunnel(tunnel) = &mut channel.beam {
+            for sc in capture_tunnel_state(tunnel) {
+                if let TunnelStateChange::ColorCenter(v) = sc {
+                    let hue = Phase::new(v.val() + shift);
+                    tunnel.control(
+                        TunnelControlMessage::Set(TunnelStateChange::ColorCenter(
+                            UnipolarFloat::new(hue.val()),
+                        )),
+                        &mut DummyEmitter,
+                    );
+                }
+            }
+            modified.push(ChannelIdx(i));
+        }
+    }
+    modified
+}
+
+/// Nudge a single randomly-chosen parameter of a randomly-chosen tunnel.
+fn mutate_tunnel<R: Rng>(mixer: &mut Mixer, rng: &mut R) -> Option<ChannelIdx> {
+    let channel = pick_channel(mixer, true, rng)?;
+    let tunnel = match mixer.beam(channel) {
+        Beam::Tunnel(t) => t,
+        Beam::Look(_) | Beam::Generator(_) => return None,
+    };
+    let candidates = capture_tunnel_state(tunnel)
+        .into_iter()
+        .filter(|sc| {
+            use TunnelStateChange::*;
+            // Leave geometry that could push the beam offscreen or make it
+            // disappear entirely alone.
+            !matches!(
+                sc,
+                ColorCenter(_) | Segments(_) | Blacking(_) | PositionX(_) | PositionY(_)
+            )
+        })
+        .collect::<Vec<_>>();
+    let sc = candidates.choose(rng)?;
+    let delta = rng.gen_range(-Autopilot::MUTATION_SIZE..Autopilot::MUTATION_SIZE);
+    let unipolar = |v: UnipolarFloat| UnipolarFloat::new(v.val() + delta);
+    let bipolar = |v: BipolarFloat| BipolarFloat::new(v.val() + delta);
+    use TunnelStateChange::*;
+    let mutated = match *sc {
+        Thickness(v) => Thickness(unipolar(v)),
+        Size(v) => Size(unipolar(v)),
+        AspectRatio(v) => AspectRatio(unipolar(v)),
+        ColorWidth(v) => ColorWidth(unipolar(v)),
+        ColorSpread(v) => ColorSpread(unipolar(v)),
+        ColorSaturation(v) => ColorSaturation(unipolar(v)),
+        RotationSpeed(v) => RotationSpeed(bipolar(v)),
+        MarqueeSpeed(v) => MarqueeSpeed(bipolar(v)),
+        _ => return None,
+    };
+    tunnel.control(TunnelControlMessage::Set(mutated), &mut DummyEmitter);
+    Some(channel)
+}
+
+/// Capture the complete controllable state of a tunnel.
+fn capture_tunnel_state(tunnel: &Tunnel) -> Vec<TunnelStateChange> {
+    let mut capture = TunnelStateCapture(Vec::new());
+    tunnel.emit_state(&mut capture);
+    capture.0
+}
+
+struct TunnelStateCapture(Vec<TunnelStateChange>);
+
+impl EmitShowStateChange for TunnelStateCapture {
+    fn emit(&mut self, sc: ShowStateChange) {
+        if let ShowStateChange::Tunnel(sc) = sc {
+            self.0.push(sc);
+        }
+    }
+}
+
+pub enum ControlMessage {
+    Set(StateChange),
+    ToggleEnabled,
+}
+
+pub enum StateChange {
+    Enabled(bool),
+    Seed(u64),
+}
+
+pub trait EmitStateChange {
+    fn emit_autopilot_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_autopilot_state_change(&mut self, sc: StateChange) {
+        self.emit(ShowStateChange::Autopilot(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Run an autopilot with the provided seed on a copy of the provided
+    /// mixer for a few hundred beats, and return the state of every beam in
+    /// the mixer afterwards.
+    fn run(mixer: &Mixer, seed: u64) -> Vec<String> {
+        let mut autopilot = Autopilot::new();
+        autopilot.control(
+            ControlMessage::Set(StateChange::Seed(seed)),
+            &mut DummyEmitter,
+        );
+        autopilot.control(ControlMessage::ToggleEnabled, &mut DummyEmitter);
+        let mut mixer = mixer.clone();
+        let stored = Beam::Tunnel(Tunnel::new());
+        let clocks = ClockBank::new();
+        for _ in 0..200 {
+            autopilot.update_state(
+                Autopilot::MAX_BEAT_INTERVAL,
+                &[&stored],
+                &mut mixer,
+                &clocks,
+            );
+        }
+        mixer
+            .channels()
+            .map(|channel| format!("{:?}", channel.beam))
+            .collect()
+    }
+
+    #[test]
+    fn test_seed_reproducible() {
+        let mixer = Mixer::new(1);
+        assert_eq!(run(&mixer, 7), run(&mixer, 7));
+        assert_ne!(run(&mixer, 7), run(&mixer, 8));
+
+        // The show seed drives the autopilot too.
+        let mut rerolled = mixer.clone();
+        rerolled.reroll_seed();
+        assert_ne!(run(&mixer, 7), run(&rerolled, 7));
+    }
+}
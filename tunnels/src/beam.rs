@@ -1,4 +1,10 @@
-use crate::{clock_bank::ClockBank, look::Look, tunnel::Tunnel};
+use crate::{
+    clock_bank::ClockBank,
+    generator::{generator_names, GeneratorBeam},
+    look::Look,
+    random::ShowRng,
+    tunnel::Tunnel,
+};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tunnels_lib::number::UnipolarFloat;
@@ -12,13 +18,51 @@ use tunnels_lib::ArcSegment;
 pub enum Beam {
     Tunnel(Tunnel),
     Look(Look),
+    /// A custom beam type provided by a generator plugin.
+    Generator(GeneratorBeam),
 }
 
 impl Beam {
-    pub fn update_state(&mut self, delta_t: Duration) {
+    /// Return the names of every beam type that can be selected from the
+    /// beam type controls: the tunnel, then every registered generator.
+    pub fn type_names() -> Vec<&'static str> {
+        let mut names = vec!["Tunnel"];
+        names.extend(generator_names());
+        names
+    }
+
+    /// Create a fresh beam of the type at the provided index into
+    /// type_names, if it exists.
+    pub fn new_of_type(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(Self::Tunnel(Tunnel::new())),
+            _ => GeneratorBeam::new(generator_names().get(index - 1)?).map(Self::Generator),
+        }
+    }
+
+    /// Return the index into type_names of this beam's type.
+    /// Looks are not a selectable beam type.
+    pub fn type_index(&self) -> Option<usize> {
+        match self {
+            Self::Tunnel(_) => Some(0),
+            Self::Look(_) => None,
+            Self::Generator(g) => generator_names()
+                .iter()
+                .position(|name| *name == g.kind())
+                .map(|i| i + 1),
+        }
+    }
+
+    pub fn update_state(
+        &mut self,
+        delta_t: Duration,
+        external_clocks: &ClockBank,
+        rng: &mut ShowRng,
+    ) {
         match self {
-            Self::Tunnel(t) => t.update_state(delta_t),
-            Self::Look(l) => l.update_state(delta_t),
+            Self::Tunnel(t) => t.update_state(delta_t, external_clocks, rng),
+            Self::Look(l) => l.update_state(delta_t, external_clocks, rng),
+            Self::Generator(g) => g.update_state(delta_t, external_clocks, rng),
         }
     }
 
@@ -26,11 +70,22 @@ impl Beam {
         &self,
         level: UnipolarFloat,
         mask: bool,
+        resolution: f64,
         external_clocks: &ClockBank,
     ) -> Vec<ArcSegment> {
         match self {
-            Self::Tunnel(t) => t.render(level, mask, external_clocks),
-            Self::Look(l) => l.render(level, mask, external_clocks),
+            Self::Tunnel(t) => t.render(level, mask, resolution, external_clocks),
+            Self::Look(l) => l.render(level, mask, resolution, external_clocks),
+            Self::Generator(g) => g.render(level, mask, external_clocks),
+        }
+    }
+
+    /// Return the number of arcs this beam renders at full resolution.
+    pub fn arc_count(&self) -> usize {
+        match self {
+            Self::Tunnel(t) => t.arc_count(),
+            Self::Look(l) => l.arc_count(),
+            Self::Generator(g) => g.arc_count(),
         }
     }
 }
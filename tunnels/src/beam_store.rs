@@ -1,16 +1,39 @@
 use crate::{beam::Beam, tunnel::Tunnel};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::{collections::BTreeMap, error::Error, fmt};
+use tunnels_lib::number::UnipolarFloat;
 
 /// Save beams in a grid store intended for simple access via APC button grid.
 #[derive(Serialize, Deserialize)]
 pub struct BeamStore {
     beams: Vec<Vec<Option<Beam>>>,
     n_pages: usize,
+    /// Metadata for every slot that has any.
+    #[serde(default)]
+    meta: BTreeMap<BeamStoreAddr, SlotMeta>,
+}
+
+/// User-assigned metadata for a beam store slot.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SlotMeta {
+    /// Name for front ends to display, such as "hero look".
+    #[serde(default)]
+    pub name: String,
+    /// Hue for front ends to display the slot in.
+    #[serde(default)]
+    pub color: Option<UnipolarFloat>,
+    /// A locked slot can't be saved over or deleted.
+    #[serde(default)]
+    pub locked: bool,
 }
 
 impl BeamStore {
     pub const N_ROWS: usize = 5;
     pub const COLS_PER_PAGE: usize = 8;
+    /// Longest allowed slot name, in characters.
+    pub const MAX_NAME_LEN: usize = 32;
 
     pub fn new(n_pages: usize) -> Self {
         let mut rows = Vec::with_capacity(Self::N_ROWS);
@@ -24,6 +47,7 @@ impl BeamStore {
         Self {
             beams: rows,
             n_pages,
+            meta: BTreeMap::new(),
         }
     }
 
@@ -31,6 +55,11 @@ impl BeamStore {
         self.beams[addr.row][addr.col] = beam;
     }
 
+    /// Return the contents of a slot without copying them.
+    pub fn slot(&self, addr: BeamStoreAddr) -> &Option<Beam> {
+        &self.beams[addr.row][addr.col]
+    }
+
     pub fn get(&mut self, addr: BeamStoreAddr) -> Option<Beam> {
         return self.beams[addr.row][addr.col].clone();
     }
@@ -43,13 +72,101 @@ impl BeamStore {
         })
     }
 
+    /// Return every stored beam in the columns of the provided page.
+    pub fn occupied_on_page(&self, page: usize) -> Vec<&Beam> {
+        let cols = page * Self::COLS_PER_PAGE..(page + 1) * Self::COLS_PER_PAGE;
+        self.items()
+            .filter(|(addr, _)| cols.contains(&addr.col))
+            .filter_map(|(_, beam)| beam.as_ref())
+            .collect()
+    }
+
     pub fn n_pages(&self) -> usize {
         self.n_pages
     }
+
+    /// Return true if the slot is locked against being overwritten.
+    pub fn locked(&self, addr: BeamStoreAddr) -> bool {
+        matches!(self.meta.get(&addr), Some(meta) if meta.locked)
+    }
+
+    /// Replace the metadata of a slot.
+    pub fn set_meta(&mut self, addr: BeamStoreAddr, meta: SlotMeta) -> Result<(), Box<dyn Error>> {
+        if addr.row >= Self::N_ROWS || addr.col >= Self::COLS_PER_PAGE * self.n_pages {
+            bail!("Beam store has no slot at {}.", addr);
+        }
+        if meta.name.chars().count() > Self::MAX_NAME_LEN {
+            bail!(
+                "Beam store slot name \"{}\" is longer than {} characters.",
+                meta.name,
+                Self::MAX_NAME_LEN
+            );
+        }
+        if meta == SlotMeta::default() {
+            self.meta.remove(&addr);
+        } else {
+            self.meta.insert(addr, meta);
+        }
+        Ok(())
+    }
+
+    /// Return the metadata of every slot that has any, in row order.
+    pub fn meta(&self) -> impl Iterator<Item = (BeamStoreAddr, &SlotMeta)> {
+        self.meta.iter().map(|(addr, meta)| (*addr, meta))
+    }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, JsonSchema,
+)]
 pub struct BeamStoreAddr {
     pub row: usize,
     pub col: usize,
 }
+
+impl fmt::Display for BeamStoreAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "row {}, column {}", self.row, self.col)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rmp_serde::{Deserializer, Serializer};
+
+    #[test]
+    fn test_set_meta() {
+        let mut store = BeamStore::new(1);
+        let addr = BeamStoreAddr { row: 1, col: 2 };
+        let meta = SlotMeta {
+            name: "hero".to_string(),
+            color: Some(UnipolarFloat::new(0.5)),
+            locked: true,
+        };
+        store.set_meta(addr, meta.clone()).unwrap();
+        assert!(store.locked(addr));
+        assert!(!store.locked(BeamStoreAddr { row: 2, col: 1 }));
+        assert_eq!(vec![(addr, &meta)], store.meta().collect::<Vec<_>>());
+
+        // Metadata is saved with the show.
+        let mut saved = Vec::new();
+        store.serialize(&mut Serializer::new(&mut saved)).unwrap();
+        let loaded = BeamStore::deserialize(&mut Deserializer::new(&saved[..])).unwrap();
+        assert_eq!(vec![(addr, &meta)], loaded.meta().collect::<Vec<_>>());
+
+        // Clearing the metadata forgets the slot.
+        store.set_meta(addr, SlotMeta::default()).unwrap();
+        assert!(!store.locked(addr));
+        assert_eq!(0, store.meta().count());
+
+        assert!(store
+            .set_meta(BeamStoreAddr { row: 0, col: 8 }, meta.clone())
+            .is_err());
+        let long = SlotMeta {
+            name: "x".repeat(BeamStore::MAX_NAME_LEN + 1),
+            ..meta
+        };
+        assert!(store.set_meta(addr, long).is_err());
+    }
+}
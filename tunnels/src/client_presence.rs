@@ -0,0 +1,137 @@
+//! Track which video channels have a client connected.
+//!
+//! Clients send a heartbeat naming their video channel every second.  A
+//! channel with no recent heartbeat is considered idle, and each video output
+//! can be configured to stop doing work while it is idle.
+
+use rmp_serde::Deserializer;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
+};
+use tracing::{error, info};
+use tunnels_lib::{
+    heartbeat::{Heartbeat, PORT, TIMEOUT},
+    RunFlag,
+};
+use zmq::Context;
+
+use crate::{diagnostic::DIAGNOSTIC_CHANNEL, mixer::Mixer};
+
+type LastSeen = [Option<Instant>; Mixer::N_VIDEO_CHANNELS];
+
+pub struct ClientPresence {
+    last_seen: Arc<Mutex<LastSeen>>,
+    /// Liveness of each video channel as of the last poll.
+    live: [bool; Mixer::N_VIDEO_CHANNELS],
+    /// Channels whose client has disconnected since last checked.
+    lost: Vec<usize>,
+    join_handle: Option<thread::JoinHandle<()>>,
+    run: RunFlag,
+}
+
+impl ClientPresence {
+    /// Start listening for heartbeats from clients of the show with the
+    /// provided ID.
+    /// The service will run until it is dropped.
+    pub fn start(ctx: &mut Context, show_id: Option<String>) -> Result<Self, Box<dyn Error>> {
+        let socket = ctx.socket(zmq::PULL)?;
+        socket.bind(&format!("tcp://*:{}", PORT))?;
+        // time out once per second
+        socket.set_rcvtimeo(1000)?;
+        let run = RunFlag::new();
+        let run_local = run.clone();
+        let last_seen = Arc::new(Mutex::new([None; Mixer::N_VIDEO_CHANNELS]));
+        let last_seen_local = last_seen.clone();
+
+        let jh = thread::Builder::new()
+            .name("client_presence".to_string())
+            .spawn(move || loop {
+                if !run.should_run() {
+                    return;
+                }
+                let msg = match socket.recv_bytes(0) {
+                    Err(zmq::Error::EAGAIN) => continue,
+                    Err(e) => {
+                        error!("Heartbeat receive error: {}.", e);
+                        continue;
+                    }
+                    Ok(msg) => msg,
+                };
+                match Heartbeat::deserialize(&mut Deserializer::new(&msg[..])) {
+                    // Clients of another show don't count.
+                    Ok(Heartbeat {
+                        show_id: client_show_id,
+                        ..
+                    }) if client_show_id != show_id => {}
+                    Ok(Heartbeat { video_channel, .. })
+                        if (video_channel as usize) < Mixer::N_VIDEO_CHANNELS =>
+                    {
+                        if let Ok(mut last_seen) = last_seen.lock() {
+                            last_seen[video_channel as usize] = Some(Instant::now());
+                        }
+                    }
+                    // Clients showing the diagnostic layer don't count.
+                    Ok(Heartbeat { video_channel, .. })
+                        if video_channel as usize == DIAGNOSTIC_CHANNEL => {}
+                    Ok(Heartbeat { video_channel, .. }) => {
+                        error!("Heartbeat from unknown video channel {}.", video_channel);
+                    }
+                    Err(e) => {
+                        error!("Heartbeat deserialization error: {}.", e);
+                    }
+                }
+            })?;
+        info!("Client presence service started.");
+        Ok(Self {
+            last_seen: last_seen_local,
+            live: [false; Mixer::N_VIDEO_CHANNELS],
+            lost: Vec::new(),
+            join_handle: Some(jh),
+            run: run_local,
+        })
+    }
+
+    /// Return whether each video channel currently has a client connected.
+    /// Log any change since the last poll.
+    pub fn poll(&mut self) -> [bool; Mixer::N_VIDEO_CHANNELS] {
+        let now = Instant::now();
+        let last_seen = match self.last_seen.lock() {
+            Ok(last_seen) => *last_seen,
+            Err(_) => return self.live,
+        };
+        for (chan, seen) in last_seen.iter().enumerate() {
+            let live = matches!(seen, Some(t) if now - *t < TIMEOUT);
+            if live != self.live[chan] {
+                info!(
+                    "Video channel {} client {}.",
+                    chan,
+                    if live { "connected" } else { "disconnected" }
+                );
+                if !live {
+                    self.lost.push(chan);
+                }
+                self.live[chan] = live;
+            }
+        }
+        self.live
+    }
+
+    /// Return the video channels whose client has disconnected since the
+    /// last call.
+    pub fn take_lost(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.lost)
+    }
+}
+
+impl Drop for ClientPresence {
+    fn drop(&mut self) {
+        info!("Client presence service shutting down...");
+        self.run.stop();
+        self.join_handle.take().unwrap().join().unwrap();
+        info!("Client presence service shut down.");
+    }
+}
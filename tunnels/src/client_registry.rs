@@ -0,0 +1,105 @@
+//! Centrally managed configuration of render clients.
+//!
+//! Each known client has a profile in the show config, keyed by the name its
+//! remote control service is advertised under (usually its hostname).  When a
+//! known client appears on the network, push its profile to it so it starts
+//! showing the right video channel with the right calibration.  If it drops
+//! off and comes back, push it again.
+
+use rmp_serde::encode::to_vec;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    thread,
+    time::Duration,
+};
+use tracing::{error, info};
+use tunnels_lib::{
+    client_profile::{AssignProfile, ClientProfile},
+    show_id::service_name,
+    RunFlag,
+};
+use zero_configure::Controller;
+
+/// How often to check for clients appearing or disappearing.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ClientRegistry {
+    run: RunFlag,
+}
+
+impl ClientRegistry {
+    /// Start configuring the clients of the show with the provided ID that
+    /// have profiles.
+    /// The service will run until it is dropped.
+    pub fn start(
+        profiles: BTreeMap<String, ClientProfile>,
+        show_id: Option<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let server_hostname = hostname::get()?
+            .into_string()
+            .map_err(|_| "This machine's hostname is not valid unicode.")?;
+        let run = RunFlag::new();
+        let run_local = run.clone();
+
+        thread::Builder::new()
+            .name("client_registry".to_string())
+            .spawn(move || {
+                let controller = Controller::new(&service_name(show_id.as_deref()));
+                // Clients that are present and have been dealt with.
+                let mut seen = HashSet::new();
+                while run.should_run() {
+                    let present = controller.list();
+                    seen.retain(|name| present.contains(name));
+                    for name in present {
+                        if seen.contains(&name) {
+                            continue;
+                        }
+                        let profile = match profiles.get(&name) {
+                            Some(profile) => profile.clone(),
+                            None => {
+                                info!("Found unregistered client {}.", name);
+                                seen.insert(name);
+                                continue;
+                            }
+                        };
+                        let assign = AssignProfile {
+                            server_hostname: server_hostname.clone(),
+                            profile,
+                            show_id: show_id.clone(),
+                        };
+                        match configure(&controller, &name, &assign) {
+                            Ok(response) => {
+                                info!("Configured client {}:\n{}", name, response);
+                                seen.insert(name);
+                            }
+                            // Try again next time around.
+                            Err(e) => error!("Failed to configure client {}: {}.", name, e),
+                        }
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })?;
+        info!("Client registry started.");
+        Ok(Self { run: run_local })
+    }
+}
+
+/// Send a profile to a client, returning its response.
+fn configure(
+    controller: &Controller,
+    name: &str,
+    assign: &AssignProfile,
+) -> Result<String, Box<dyn Error>> {
+    let response = controller.send(name, &to_vec(assign)?)?;
+    Ok(String::from_utf8(response)?)
+}
+
+impl Drop for ClientRegistry {
+    fn drop(&mut self) {
+        // The service thread may be blocked waiting on an unresponsive
+        // client, so don't wait for it to finish.
+        self.run.stop();
+        info!("Client registry shut down.");
+    }
+}
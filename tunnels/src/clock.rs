@@ -2,8 +2,12 @@ use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 use tunnels_lib::number::{BipolarFloat, Phase, UnipolarFloat};
 
+use crate::midi_clock::MidiSync;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Clock {
+    /// in unit angle; saved with the show so that motion resumes where it
+    /// left off
     phase: Phase,
     /// in unit angle per second
     pub rate: f64,
@@ -76,6 +80,27 @@ impl Clock {
         }
     }
 
+    /// Set this clock's phase and rate from midi clock instead of advancing
+    /// it, keeping its direction.
+    fn follow(&mut self, sync: MidiSync) {
+        let reverse = self.rate < 0.0;
+        if let Some(rate) = sync.rate {
+            self.rate = if reverse { -rate } else { rate };
+        }
+        let phase = if reverse {
+            Phase::new(1.0 - sync.phase.val())
+        } else {
+            sync.phase
+        };
+        // We ticked if we passed the start of a cycle.
+        self.ticked = if reverse {
+            phase.val() > self.phase.val()
+        } else {
+            phase.val() < self.phase.val()
+        };
+        self.phase = phase;
+    }
+
     fn set_one_shot(&mut self, one_shot: bool) {
         self.one_shot = one_shot;
         if !one_shot {
@@ -86,6 +111,11 @@ impl Clock {
     pub fn phase(&self) -> Phase {
         self.phase
     }
+
+    /// Return true if the clock ticked on its most recent update.
+    pub fn ticked(&self) -> bool {
+        self.ticked
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,6 +126,9 @@ pub struct ControllableClock {
     tick_age: Option<Duration>,
     /// If true, reset the clock's phase to zero on every tap.
     retrigger: bool,
+    /// If true, follow incoming midi clock when there is any.
+    #[serde(default)]
+    midi_sync: bool,
 }
 
 impl Default for ControllableClock {
@@ -117,6 +150,7 @@ impl ControllableClock {
             sync: TapSync::new(),
             tick_age: None,
             retrigger: false,
+            midi_sync: false,
         }
     }
 
@@ -128,12 +162,26 @@ impl ControllableClock {
         self.clock.submaster_level
     }
 
+    /// Return true if the clock ticked on its most recent update.
+    pub fn ticked(&self) -> bool {
+        self.clock.ticked
+    }
+
     const TICK_DISPLAY_DURATION: Duration = Duration::from_millis(250);
 
-    /// Update the state of this clock.
+    /// Update the state of this clock, following midi clock if provided and
+    /// we're synced to it.
     /// The clock may need to emit state update messages.
-    pub fn update_state<E: EmitStateChange>(&mut self, delta_t: Duration, emitter: &mut E) {
-        self.clock.update_state(delta_t);
+    pub fn update_state<E: EmitStateChange>(
+        &mut self,
+        delta_t: Duration,
+        midi_sync: Option<MidiSync>,
+        emitter: &mut E,
+    ) {
+        match midi_sync.filter(|_| self.midi_sync) {
+            Some(sync) => self.clock.follow(sync),
+            None => self.clock.update_state(delta_t),
+        }
         if self.clock.ticked {
             emitter.emit_clock_state_change(StateChange::Ticked(true));
             self.tick_age = Some(Duration::new(0, 0));
@@ -162,6 +210,7 @@ impl ControllableClock {
         emitter.emit_clock_state_change(Retrigger(self.retrigger));
         emitter.emit_clock_state_change(OneShot(self.clock.one_shot));
         emitter.emit_clock_state_change(SubmasterLevel(self.clock.submaster_level));
+        emitter.emit_clock_state_change(MidiSync(self.midi_sync));
         emitter.emit_clock_state_change(Ticked(self.tick_indicator_state()));
     }
 
@@ -189,6 +238,9 @@ impl ControllableClock {
             ToggleRetrigger => {
                 self.handle_state_change(StateChange::Retrigger(!self.retrigger), emitter);
             }
+            ToggleMidiSync => {
+                self.handle_state_change(StateChange::MidiSync(!self.midi_sync), emitter);
+            }
         }
     }
 
@@ -199,6 +251,7 @@ impl ControllableClock {
             Retrigger(v) => self.retrigger = v,
             OneShot(v) => self.clock.set_one_shot(v),
             SubmasterLevel(v) => self.clock.submaster_level = v,
+            MidiSync(v) => self.midi_sync = v,
             Ticked(_) => (),
         };
         emitter.emit_clock_state_change(sc);
@@ -210,6 +263,7 @@ pub enum StateChange {
     Retrigger(bool),
     OneShot(bool),
     SubmasterLevel(UnipolarFloat),
+    MidiSync(bool),
     /// Outgoing only, no effect as control.
     Ticked(bool),
 }
@@ -219,6 +273,7 @@ pub enum ControlMessage {
     Tap,
     ToggleOneShot,
     ToggleRetrigger,
+    ToggleMidiSync,
 }
 
 pub trait EmitStateChange {
@@ -1,11 +1,14 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::{
+    audio::{AudioLevels, AudioSource},
     clock::{
         ControlMessage as ClockControlMessage, ControllableClock,
         EmitStateChange as EmitClockStateChange, StateChange as ClockStateChange,
     },
     master_ui::EmitStateChange as EmitShowStateChange,
+    midi::Realtime,
+    midi_clock::MidiClock,
 };
 use serde::{Deserialize, Serialize};
 use tunnels_lib::number::{Phase, UnipolarFloat};
@@ -21,26 +24,60 @@ pub const N_CLOCKS: usize = 4;
 pub struct ClockIdx(pub usize);
 
 /// Maintain a indexable collection of clocks.
+/// The latest audio levels ride along with the clocks, so that anything
+/// that can follow a clock can also follow the music.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ClockBank([ControllableClock; N_CLOCKS]);
+#[serde(transparent)]
+pub struct ClockBank {
+    clocks: [ControllableClock; N_CLOCKS],
+    #[serde(skip)]
+    audio: AudioLevels,
+    /// Incoming midi clock, which clocks can be synced to.
+    #[serde(skip)]
+    midi_clock: MidiClock,
+}
 
 impl ClockBank {
     pub fn new() -> Self {
-        Self(Default::default())
+        Self {
+            clocks: Default::default(),
+            audio: AudioLevels::default(),
+            midi_clock: MidiClock::new(),
+        }
     }
 
     pub fn phase(&self, index: ClockIdx) -> Phase {
-        self.0[index].phase()
+        self.clocks[index].phase()
     }
 
     pub fn submaster_level(&self, index: ClockIdx) -> UnipolarFloat {
-        self.0[index].submaster_level()
+        self.clocks[index].submaster_level()
+    }
+
+    pub fn ticked(&self, index: ClockIdx) -> bool {
+        self.clocks[index].ticked()
+    }
+
+    pub fn audio_level(&self, source: AudioSource) -> UnipolarFloat {
+        self.audio.get(source)
+    }
+
+    /// Update the audio levels, which are zero until set.
+    pub fn set_audio_levels(&mut self, levels: AudioLevels) {
+        self.audio = levels;
+    }
+
+    /// Handle a midi realtime message received at the given time.
+    pub fn receive_midi_clock(&mut self, msg: Realtime, at: Instant) {
+        self.midi_clock.receive(msg, at);
     }
 
     pub fn update_state<E: EmitStateChange>(&mut self, delta_t: Duration, emitter: &mut E) {
-        for (i, clock) in self.0.iter_mut().enumerate() {
+        let midi_sync = self.midi_clock.sync(Instant::now());
+        for (i, clock) in self.clocks.iter_mut().enumerate() {
             clock.update_state(
                 delta_t,
+                midi_sync,
                 &mut ChannelEmitter {
                     channel: ClockIdx(i),
                     emitter,
@@ -50,7 +87,7 @@ impl ClockBank {
     }
 
     pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
-        for (i, clock) in self.0.iter().enumerate() {
+        for (i, clock) in self.clocks.iter().enumerate() {
             clock.emit_state(&mut ChannelEmitter {
                 channel: ClockIdx(i),
                 emitter,
@@ -59,7 +96,7 @@ impl ClockBank {
     }
 
     pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) {
-        self.0[msg.channel].control(
+        self.clocks[msg.channel].control(
             msg.msg,
             &mut ChannelEmitter {
                 channel: msg.channel,
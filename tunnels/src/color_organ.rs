@@ -0,0 +1,186 @@
+//! Drive mixer channel levels from the audio input, like a classic color organ.
+//!
+//! Each channel may follow one frequency band of the audio input.  While the
+//! organ is enabled, a following channel is drawn at its fader level scaled
+//! by an envelope of its band's peak level, so the faders still set the
+//! brightest each channel can get.  Channels that don't follow a band are
+//! unaffected.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tunnels_lib::number::UnipolarFloat;
+
+use crate::{
+    audio::{db_to_amplitude, follow, Band, N_BANDS},
+    master_ui::EmitStateChange as EmitShowStateChange,
+    mixer::ChannelIdx,
+    params,
+};
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ColorOrgan {
+    enabled: bool,
+    /// The band followed by each mixer channel, if any.
+    bands: Vec<Option<Band>>,
+    /// How quickly the envelopes rise to meet louder audio.
+    attack: UnipolarFloat,
+    /// How quickly the envelopes fall back when the audio gets quieter.
+    release: UnipolarFloat,
+    /// Amplification of the band levels before they drive the envelopes.
+    gain: UnipolarFloat,
+    /// Current envelope level of each band.
+    #[serde(skip)]
+    envelopes: [f64; N_BANDS],
+}
+
+impl Default for ColorOrgan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ColorOrgan {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            bands: Vec::new(),
+            attack: UnipolarFloat::new(params::COLOR_ORGAN_ATTACK.default),
+            release: UnipolarFloat::new(params::COLOR_ORGAN_RELEASE.default),
+            gain: UnipolarFloat::new(params::COLOR_ORGAN_GAIN.default),
+            envelopes: [0.; N_BANDS],
+        }
+    }
+
+    /// Update the envelopes with the peak level of each band since the last
+    /// update.
+    pub fn update_state(&mut self, delta_t: Duration, band_peaks: [f64; N_BANDS]) {
+        let gain = db_to_amplitude(self.gain.val() * max_value(&params::COLOR_ORGAN_GAIN));
+        let attack = self.attack.val() * max_value(&params::COLOR_ORGAN_ATTACK);
+        let release = self.release.val() * max_value(&params::COLOR_ORGAN_RELEASE);
+        for (envelope, peak) in self.envelopes.iter_mut().zip(band_peaks.iter()) {
+            let target = (peak * gain).min(1.);
+            let time_constant = if target > *envelope { attack } else { release };
+            *envelope = follow(*envelope, target, time_constant, delta_t);
+        }
+    }
+
+    /// Return the factor to scale the level of a channel by.
+    pub fn level(&self, channel: ChannelIdx) -> UnipolarFloat {
+        if !self.enabled {
+            return UnipolarFloat::ONE;
+        }
+        match self.band(channel) {
+            Some(band) => UnipolarFloat::new(self.envelopes[band as usize]),
+            None => UnipolarFloat::ONE,
+        }
+    }
+
+    fn band(&self, channel: ChannelIdx) -> Option<Band> {
+        self.bands.get(channel.0).copied().flatten()
+    }
+
+    /// Emit the current value of all controllable color organ state.
+    pub fn emit_state<E: EmitStateChange>(&self, n_channels: usize, emitter: &mut E) {
+        use StateChange::*;
+        emitter.emit_color_organ_state_change(Enabled(self.enabled));
+        emitter.emit_color_organ_state_change(Attack(self.attack));
+        emitter.emit_color_organ_state_change(Release(self.release));
+        emitter.emit_color_organ_state_change(Gain(self.gain));
+        for channel in (0..n_channels).map(ChannelIdx) {
+            emitter.emit_color_organ_state_change(ChannelBand(channel, self.band(channel)));
+        }
+    }
+
+    /// Handle a control event.
+    /// Emit any state changes that have happened as a result of handling.
+    pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) {
+        use ControlMessage::*;
+        match msg {
+            Set(sc) => self.handle_state_change(sc, emitter),
+            ToggleEnabled => self.handle_state_change(StateChange::Enabled(!self.enabled), emitter),
+        }
+    }
+
+    fn handle_state_change<E: EmitStateChange>(&mut self, sc: StateChange, emitter: &mut E) {
+        use StateChange::*;
+        match sc {
+            Enabled(v) => self.enabled = v,
+            Attack(v) => self.attack = v,
+            Release(v) => self.release = v,
+            Gain(v) => self.gain = v,
+            ChannelBand(channel, band) => {
+                if self.bands.len() <= channel.0 {
+                    self.bands.resize(channel.0 + 1, None);
+                }
+                self.bands[channel.0] = band;
+            }
+        };
+        emitter.emit_color_organ_state_change(sc);
+    }
+}
+
+/// The value of a parameter in its physical unit at full scale.
+fn max_value(spec: &params::ParamSpec) -> f64 {
+    spec.unit.map_or(1., |(_, max)| max)
+}
+
+pub enum ControlMessage {
+    Set(StateChange),
+    ToggleEnabled,
+}
+
+pub enum StateChange {
+    Enabled(bool),
+    Attack(UnipolarFloat),
+    Release(UnipolarFloat),
+    Gain(UnipolarFloat),
+    /// Follow this band with a mixer channel, or stop following.
+    ChannelBand(ChannelIdx, Option<Band>),
+}
+
+pub trait EmitStateChange {
+    fn emit_color_organ_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_color_organ_state_change(&mut self, sc: StateChange) {
+        use crate::show::StateChange as ShowStateChange;
+        self.emit(ShowStateChange::ColorOrgan(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::master_ui::DummyEmitter;
+
+    fn set(organ: &mut ColorOrgan, sc: StateChange) {
+        organ.control(ControlMessage::Set(sc), &mut DummyEmitter);
+    }
+
+    #[test]
+    fn test_color_organ() {
+        let mut organ = ColorOrgan::new();
+        let frame = Duration::from_millis(20);
+        set(
+            &mut organ,
+            StateChange::ChannelBand(ChannelIdx(2), Some(Band::Low)),
+        );
+        set(&mut organ, StateChange::Gain(UnipolarFloat::ZERO));
+        set(&mut organ, StateChange::Attack(UnipolarFloat::ZERO));
+
+        organ.update_state(frame, [0.5, 0., 0.]);
+        // Disabled, every channel is drawn at its fader level.
+        assert_eq!(UnipolarFloat::ONE, organ.level(ChannelIdx(2)));
+
+        organ.control(ControlMessage::ToggleEnabled, &mut DummyEmitter);
+        assert_eq!(UnipolarFloat::new(0.5), organ.level(ChannelIdx(2)));
+        assert_eq!(UnipolarFloat::ONE, organ.level(ChannelIdx(0)));
+        assert_eq!(UnipolarFloat::ONE, organ.level(ChannelIdx(20)));
+
+        // Release is gradual.
+        organ.update_state(frame, [0., 0., 0.]);
+        let level = organ.level(ChannelIdx(2)).val();
+        assert!(level > 0. && level < 0.5, "{}", level);
+    }
+}
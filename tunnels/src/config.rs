@@ -0,0 +1,280 @@
+//! Loading and parsing show configurations.
+//!
+//! Everything in the config file is optional; an empty file (or no file at
+//! all) produces a show with the default behavior.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{collections::BTreeMap, error::Error, fs::File, path::Path};
+use tunnels_lib::{client_profile::ClientProfile, show_id, thread_config::ThreadConfig};
+
+use crate::{
+    audio::AudioConfig,
+    device::Device,
+    dmx::DmxConfig,
+    external::ExternalSourceConfig,
+    flash_limit::FlashLimitConfig,
+    frame_check::FrameCheckConfig,
+    grid_confirm::GridConfirm,
+    logging::LoggingConfig,
+    midi_controls::EncoderConfig,
+    midi_file::MidiFileConfig,
+    mixer::Mixer,
+    output_profile::OutputProfileConfig,
+    palette::PaletteConfig,
+    pixel_map::PixelMapConfig,
+    scheduler::ScheduleRule,
+    screensaver::ScreensaverConfig,
+    send::ReplayBufferConfig,
+    silence_gate::SilenceGateConfig,
+    stereo::StereoPair,
+    trigger::TriggerConfig,
+    video_out::{GeometryPreset, VideoOutputConfig},
+    white_point::WhitePoint,
+};
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ShowConfig {
+    /// Tag published frames and client traffic with this ID, so that several
+    /// shows can share a network.  Clients must be configured with the same ID.
+    #[serde(default)]
+    pub show_id: Option<String>,
+    /// Automation rules that fire at configured local times.
+    #[serde(default)]
+    pub schedule: Vec<ScheduleRule>,
+    /// Contact-closure trigger inputs from venue show control.
+    #[serde(default)]
+    pub triggers: Option<TriggerConfig>,
+    /// DMX input from a house lighting console.
+    #[serde(default)]
+    pub dmx: Option<DmxConfig>,
+    /// Mixer channels fed by other programs instead of their beams.
+    #[serde(default)]
+    pub external_sources: Vec<ExternalSourceConfig>,
+    /// LED fixtures driven from the image of a video channel.
+    #[serde(default)]
+    pub pixel_map: Option<PixelMapConfig>,
+    /// Publish the clocks and audio level as an extra video channel, after
+    /// the mixer's channels, for display in the booth.
+    #[serde(default)]
+    pub diagnostic_layer: bool,
+    /// Pre-programmed segments that can be played by triggers.
+    #[serde(default)]
+    pub midi_files: Vec<MidiFileConfig>,
+    /// Name of a midi input port sending midi clock, such as from a DJ
+    /// mixer, for clocks to sync to.
+    #[serde(default)]
+    pub midi_clock: Option<String>,
+    /// Controls that send relative values from endless encoders.
+    #[serde(default)]
+    pub encoders: Vec<EncoderConfig>,
+    /// Devices whose absolute controls should use soft takeover.
+    #[serde(default)]
+    pub soft_takeover: Vec<Device>,
+    /// How a beam store grid press that saves over or deletes a slot must be
+    /// confirmed.
+    #[serde(default)]
+    pub grid_confirm: GridConfirm,
+    /// Seed for the autopilot's choices, in place of the one saved with the
+    /// show.  Along with the show's random seed, the same seed always makes
+    /// the same sequence of choices.
+    #[serde(default)]
+    pub autopilot_seed: Option<u64>,
+    /// Physical properties of the display attached to each video channel.
+    #[serde(default)]
+    pub video_outputs: Vec<VideoOutputConfig>,
+    /// Named sets of output routing, video outputs, and pixel map, one of
+    /// which is active at a time.  If any are defined, video_outputs and
+    /// pixel_map must be left out here and given in each profile instead.
+    #[serde(default)]
+    pub output_profiles: Vec<OutputProfileConfig>,
+    /// Named venue geometry adjustments that can be selected for any video
+    /// channel.
+    #[serde(default)]
+    pub geometry_presets: Vec<GeometryPreset>,
+    /// Named palettes of colors that tunnels can draw from.
+    #[serde(default)]
+    pub palettes: Vec<PaletteConfig>,
+    /// Pairs of video channels to render as left and right eye views.
+    #[serde(default)]
+    pub stereo: Vec<StereoPair>,
+    /// Audio input selection.  Uses the system default input if audio
+    /// analysis is needed but no input is configured.
+    #[serde(default)]
+    pub audio: Option<AudioConfig>,
+    /// Fade the show out when the audio input is silent.
+    #[serde(default)]
+    pub silence_gate: Option<SilenceGateConfig>,
+    /// Show a dim look from the beam store after a while without control
+    /// input.
+    #[serde(default)]
+    pub screensaver: Option<ScreensaverConfig>,
+    /// Continuously record the last few minutes of output.
+    #[serde(default)]
+    pub replay_buffer: Option<ReplayBufferConfig>,
+    /// Sanity checks applied to every published frame.
+    #[serde(default)]
+    pub frame_check: FrameCheckConfig,
+    /// Limit how often each output can flash, for the safety of
+    /// photosensitive viewers.  On by default.
+    #[serde(default)]
+    pub flash_limit: FlashLimitConfig,
+    /// Scheduling of the thread that updates the show state each frame.
+    #[serde(default)]
+    pub show_thread: ThreadConfig,
+    /// Scheduling of the thread that renders and sends each frame.
+    #[serde(default)]
+    pub render_thread: ThreadConfig,
+    /// Spread the checking and sending of each video channel's frame across
+    /// the frame period, rather than sending them all at once.
+    #[serde(default)]
+    pub interleave: bool,
+    /// Most arcs to send each frame, summed over every video channel.  When
+    /// exceeded, the lowest mixer channels are rendered with fewer segments.
+    #[serde(default)]
+    pub arc_budget: Option<usize>,
+    /// Profiles of known render clients, keyed by the name each client's
+    /// remote control service is advertised under.  Each client is sent its
+    /// profile whenever it appears on the network.
+    #[serde(default)]
+    pub clients: BTreeMap<String, ClientProfile>,
+    /// Log levels and log file output.
+    #[serde(default)]
+    pub logging: LoggingConfig,
+}
+
+impl ShowConfig {
+    /// Load a show configuration from a YAML file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let cfg: Self = serde_yaml::from_reader(file)?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Check the internal consistency of the configuration.
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(id) = &self.show_id {
+            show_id::validate(id)?;
+        }
+        for rule in &self.schedule {
+            rule.validate(self.palettes.len())?;
+        }
+        for encoder in &self.encoders {
+            encoder.validate()?;
+        }
+        self.grid_confirm.validate()?;
+        for output in &self.video_outputs {
+            output.validate()?;
+        }
+        if !self.output_profiles.is_empty()
+            && (!self.video_outputs.is_empty() || self.pixel_map.is_some())
+        {
+            bail!("With output profiles, video outputs and the pixel map must be given in each profile.");
+        }
+        OutputProfileConfig::validate_all(&self.output_profiles)?;
+        GeometryPreset::validate_all(&self.geometry_presets)?;
+        PaletteConfig::validate_all(&self.palettes)?;
+        StereoPair::validate_all(&self.stereo)?;
+        if let Some(pixel_map) = &self.pixel_map {
+            pixel_map.validate()?;
+        }
+        if let Some(gate) = &self.silence_gate {
+            gate.validate()?;
+        }
+        if let Some(replay_buffer) = &self.replay_buffer {
+            replay_buffer.validate()?;
+        }
+        self.frame_check.validate()?;
+        self.flash_limit.validate()?;
+        self.logging.validate()?;
+        if self.arc_budget == Some(0) {
+            bail!("Arc budget must be positive.");
+        }
+        self.validate_clients()?;
+        Ok(())
+    }
+
+    /// The output profiles of the show.  A show config without profiles has
+    /// a single profile made of its video outputs and pixel map.
+    pub fn output_profiles(&self) -> Vec<OutputProfileConfig> {
+        if !self.output_profiles.is_empty() {
+            return self.output_profiles.clone();
+        }
+        vec![OutputProfileConfig {
+            name: OutputProfileConfig::DEFAULT_NAME.to_string(),
+            routing: BTreeMap::new(),
+            video_outputs: self.video_outputs.clone(),
+            pixel_map: self.pixel_map.clone(),
+        }]
+    }
+
+    fn validate_clients(&self) -> Result<(), Box<dyn Error>> {
+        for (name, profile) in &self.clients {
+            if let Err(e) = profile.validate() {
+                bail!("Client {}: {}", name, e);
+            }
+            let channel = profile.video_channel as usize;
+            if channel >= Mixer::N_VIDEO_CHANNELS {
+                bail!(
+                    "Client {} video channel {} is out of range; there are {} video channels.",
+                    name,
+                    channel,
+                    Mixer::N_VIDEO_CHANNELS
+                );
+            }
+            if let Some(kelvin) = profile.color_temperature {
+                WhitePoint::validate_temperature(kelvin)?;
+            }
+        }
+        for profile in self.output_profiles() {
+            if let Err(e) = self.validate_client_temperatures(&profile.video_outputs) {
+                if self.output_profiles.is_empty() {
+                    return Err(e);
+                }
+                bail!("Output profile {}: {}", profile.name, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that clients agree with each other and with the video outputs
+    /// on the color temperature of each video channel.
+    fn validate_client_temperatures(
+        &self,
+        video_outputs: &[VideoOutputConfig],
+    ) -> Result<(), Box<dyn Error>> {
+        // Color temperature set for each video channel, and where it was set.
+        let mut temperatures: BTreeMap<usize, (f64, String)> = video_outputs
+            .iter()
+            .filter_map(|output| {
+                let kelvin = output.color_temperature?;
+                Some((output.channel, (kelvin, "video output config".to_string())))
+            })
+            .collect();
+        for (name, profile) in &self.clients {
+            let channel = profile.video_channel as usize;
+            let kelvin = match profile.color_temperature {
+                Some(kelvin) => kelvin,
+                None => continue,
+            };
+            let source = format!("client {}", name);
+            if let Some((other, other_source)) = temperatures.get(&channel) {
+                if *other != kelvin {
+                    bail!(
+                        "Video channel {} color temperature is {} K in {} but {} K in {}.",
+                        channel,
+                        other,
+                        other_source,
+                        kelvin,
+                        source
+                    );
+                }
+            }
+            temperatures.insert(channel, (kelvin, source));
+        }
+        Ok(())
+    }
+}
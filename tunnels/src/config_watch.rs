@@ -0,0 +1,91 @@
+//! Apply edits to the show config while the show is running.
+//!
+//! The config file is checked for changes once a second.  An edited config is
+//! loaded and validated in full before any of it is applied, so a typo leaves
+//! the show running on the config it already had.  Only the parts of the
+//! config that can change under a running show are applied: the controller
+//! mappings (encoders, soft takeover, and trigger inputs), the schedule, grid
+//! confirmation, the arc budget, stereo pairs, and palettes.  Everything else,
+//! such as the hardware the show talks to, takes effect when the show is
+//! restarted.
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::config::ShowConfig;
+
+/// How often to check the config file for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ConfigWatcher {
+    path: PathBuf,
+    /// When the file was last modified, as of the last check.
+    modified: Option<SystemTime>,
+    last_poll: Option<Instant>,
+}
+
+impl ConfigWatcher {
+    /// Watch a config file that has just been loaded.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            modified: modified(&path),
+            path,
+            last_poll: None,
+        }
+    }
+
+    /// If the config file has changed since it was last checked, load it.
+    /// Return None if it hasn't changed, or isn't due to be checked.
+    pub fn poll(&mut self, now: Instant) -> Option<Result<ShowConfig, Box<dyn Error>>> {
+        if matches!(self.last_poll, Some(t) if now - t < POLL_INTERVAL) {
+            return None;
+        }
+        self.last_poll = Some(now);
+        if !self.changed(modified(&self.path)) {
+            return None;
+        }
+        Some(ShowConfig::load(&self.path))
+    }
+
+    /// Record the modification time of the file.
+    /// Return true if there is a new version of the file to load.
+    fn changed(&mut self, modified: Option<SystemTime>) -> bool {
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        // A missing file is most likely part way through being saved; load it
+        // once it reappears.
+        modified.is_some()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_changed() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::from_secs(1);
+        let mut watcher = ConfigWatcher {
+            path: PathBuf::new(),
+            modified: Some(t0),
+            last_poll: None,
+        };
+        assert!(!watcher.changed(Some(t0)));
+        assert!(watcher.changed(Some(t1)));
+        assert!(!watcher.changed(Some(t1)));
+        // Replaced by an editor saving through a temporary file.
+        assert!(!watcher.changed(None));
+        assert!(watcher.changed(Some(t0)));
+    }
+}
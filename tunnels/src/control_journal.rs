@@ -0,0 +1,229 @@
+//! Recording the control events of a performance.
+//!
+//! Every control event that the active midi mapping acts on is written to a
+//! journal, along with the position of the master clock when it arrived.
+//! Positions are measured in beats from the start of the journal, so that a
+//! performance can be exported to a MIDI file and played back in time with
+//! whatever tempo is tapped in later.
+//!
+//! The clock only moves once a frame, while events arrive in between, so
+//! each event is placed by the time it arrived, from the position of the
+//! clock at the last frame and how fast it has been moving.
+
+use rmp_serde::{decode::Error as DecodeError, Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::{
+    clock_bank::{ClockBank, ClockIdx},
+    device::Device,
+    midi::Event,
+};
+
+/// Positions are measured on this clock, like the autopilot.
+const MASTER_CLOCK: ClockIdx = ClockIdx(0);
+
+/// Written at the start of every journal to identify the format.
+const MAGIC: &[u8] = b"TUNJRNL1";
+
+/// A journal is written alongside a snapshot recording, with this extension.
+const EXTENSION: &str = "controls";
+
+/// Return the path of the journal that accompanies the recording at path.
+pub fn journal_path(recording: &Path) -> PathBuf {
+    let mut path = recording.as_os_str().to_owned();
+    path.push(".");
+    path.push(EXTENSION);
+    path.into()
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Position of the master clock, in beats from the start of the journal.
+    pub beats: f64,
+    pub device: Device,
+    pub event: Event,
+}
+
+pub struct JournalWriter<W: Write> {
+    writer: W,
+    /// Whole beats of the master clock elapsed since the journal started.
+    beats: u64,
+    phase: f64,
+    /// When the clock was last followed, if ever.
+    updated: Option<Instant>,
+    /// How fast the clock was last moving, in beats per second.
+    rate: f64,
+    /// Position of the most recently written event.
+    last_written: f64,
+}
+
+impl JournalWriter<BufWriter<File>> {
+    /// Create a new journal at the provided path, clobbering any existing file.
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> JournalWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, Box<dyn Error>> {
+        writer.write_all(MAGIC)?;
+        Ok(Self {
+            writer,
+            beats: 0,
+            phase: 0.0,
+            updated: None,
+            rate: 0.0,
+            last_written: 0.0,
+        })
+    }
+
+    /// Follow the master clock, as of the provided time.
+    pub fn update_state(&mut self, clocks: &ClockBank, now: Instant) {
+        self.advance(
+            clocks.ticked(MASTER_CLOCK),
+            clocks.phase(MASTER_CLOCK).val(),
+            now,
+        );
+    }
+
+    fn advance(&mut self, ticked: bool, phase: f64, now: Instant) {
+        let before = self.position();
+        if ticked {
+            self.beats += 1;
+        }
+        self.phase = phase;
+        if let Some(updated) = self.updated {
+            let elapsed = (now - updated).as_secs_f64();
+            if elapsed > 0.0 {
+                self.rate = (self.position() - before).max(0.0) / elapsed;
+            }
+        }
+        self.updated = Some(now);
+    }
+
+    /// Position of the clock when it was last followed.
+    fn position(&self) -> f64 {
+        self.beats as f64 + self.phase
+    }
+
+    /// Return the position of the clock at the provided time.  Positions
+    /// never run backwards, so events stay in the order they were written.
+    fn position_at(&self, at: Instant) -> f64 {
+        let offset = match self.updated {
+            Some(updated) if at >= updated => (at - updated).as_secs_f64(),
+            Some(updated) => -(updated - at).as_secs_f64(),
+            None => 0.0,
+        };
+        (self.position() + self.rate * offset).max(self.last_written)
+    }
+
+    /// Write a control event that arrived at the provided time to the
+    /// journal.  The journal is flushed after every event, so that a
+    /// performance survives the show crashing.
+    pub fn write(
+        &mut self,
+        at: Instant,
+        device: Device,
+        event: Event,
+    ) -> Result<(), Box<dyn Error>> {
+        let entry = JournalEntry {
+            beats: self.position_at(at),
+            device,
+            event,
+        };
+        self.last_written = entry.beats;
+        entry.serialize(&mut Serializer::new(&mut self.writer))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read every entry from the journal at the provided path.
+pub fn read_journal(path: &Path) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+    read_entries(BufReader::new(File::open(path)?))
+}
+
+fn read_entries<R: Read>(mut reader: R) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        bail!("Not a tunnels control journal.");
+    }
+    let mut entries = Vec::new();
+    loop {
+        match JournalEntry::deserialize(&mut Deserializer::new(&mut reader)) {
+            Ok(entry) => entries.push(entry),
+            Err(DecodeError::InvalidMarkerRead(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Ok(entries)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi::{cc, event};
+    use std::time::Duration;
+
+    #[test]
+    fn test_round_trip() {
+        let mut journal = JournalWriter::new(Vec::new()).unwrap();
+        let now = Instant::now();
+        journal.advance(false, 0.25, now);
+        journal
+            .write(now, Device::AkaiApc40, event(cc(0, 7), 10))
+            .unwrap();
+        journal.advance(true, 0.5, now + Duration::from_secs(1));
+        journal
+            .write(
+                now + Duration::from_secs(1),
+                Device::Gamepad,
+                event(cc(1, 2), 20),
+            )
+            .unwrap();
+
+        let entries = read_entries(&journal.writer[..]).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| (e.beats, e.device, e.event.mapping, e.event.value))
+            .collect();
+        assert_eq!(
+            vec![
+                (0.25, Device::AkaiApc40, cc(0, 7), 10),
+                (1.5, Device::Gamepad, cc(1, 2), 20)
+            ],
+            summary
+        );
+        assert!(read_entries(&b"not a journal"[..]).is_err());
+    }
+
+    #[test]
+    fn test_placed_by_arrival() {
+        let mut journal = JournalWriter::new(Vec::new()).unwrap();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        // Two beats a second, followed every 100 ms.
+        journal.advance(false, 0.0, at(0));
+        journal.advance(false, 0.2, at(100));
+        assert_eq!(0.2, journal.position_at(at(100)));
+        // Events between frames land between frames.
+        assert!((journal.position_at(at(150)) - 0.3).abs() < 1e-9);
+        // An event that arrived before the last frame lands before it.
+        assert!((journal.position_at(at(75)) - 0.15).abs() < 1e-9);
+        journal
+            .write(at(150), Device::Trigger, event(cc(0, 0), 1))
+            .unwrap();
+        // But never before one already written.
+        assert!((journal.position_at(at(120)) - 0.3).abs() < 1e-9);
+    }
+}
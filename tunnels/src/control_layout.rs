@@ -0,0 +1,417 @@
+//! A description of every control the show exposes, for control surfaces
+//! that build their layout at runtime instead of hardcoding it.
+//!
+//! The layout is organized into pages of named groups of parameters, whose
+//! metadata comes from the parameter registry.  It is generated from the
+//! actual structure of the running show, so it always matches the number of
+//! mixer channels, clocks, and beam store slots.  Front ends request it over
+//! a zmq REP socket and receive it as msgpack, with structs encoded as maps.
+//!
+//! The layout carries no current values.  Each parameter names the control
+//! surface control that drives it, so a front end can set the parameter by
+//! sending events for that control to the control server, and follow it by
+//! listening as that surface would.
+
+use rmp_serde::Serializer;
+use serde::Serialize;
+use std::{error::Error, thread};
+use tracing::{error, info};
+use tunnels_lib::{ports, RunFlag};
+use zmq::Context;
+
+use crate::{
+    animation_presets::AnimationPresets,
+    beam::Beam,
+    beam_store::{BeamStore, BeamStoreAddr},
+    clock_bank::{ClockIdx, N_CLOCKS},
+    midi_controls::{
+        animation_param_control, animation_preset_control, autopilot_param_control,
+        beam_store_slot_control, beam_type_control, channel_param_control, clock_param_control,
+        color_organ_band_control, color_organ_param_control, geometry_preset_control,
+        master_ui_param_control, mixer_param_control, output_profile_control,
+        palette_param_control, palette_select_control, tunnel_param_control, video_channel_control,
+        ParamControl, MIXER_CHANNELS_PER_PAGE,
+    },
+    mixer::{ChannelIdx, Mixer, VideoChannel},
+    palette::Deck,
+    params::{self, ParamSpec},
+};
+
+const PORT: u16 = ports::CONTROL_LAYOUT;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ControlLayout {
+    pub pages: Vec<Page>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Page {
+    pub name: String,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub params: Vec<Param>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Param {
+    /// Human-readable name for display.
+    pub name: String,
+    pub spec: ParamSpec,
+    /// The control that drives this parameter, if any surface has one.
+    pub control: Option<ParamControl>,
+}
+
+impl Group {
+    fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Add every parameter in specs, each driven by the control it maps to.
+    fn with(
+        mut self,
+        specs: &[ParamSpec],
+        control: impl Fn(&ParamSpec) -> Option<ParamControl>,
+    ) -> Self {
+        for spec in specs {
+            self = self.with_indexed(spec, None, control(spec));
+        }
+        self
+    }
+
+    /// Add a parameter, optionally qualified by an index.
+    fn with_indexed(
+        mut self,
+        spec: &ParamSpec,
+        index: Option<usize>,
+        control: Option<ParamControl>,
+    ) -> Self {
+        let name = match index {
+            Some(i) => format!("{} {}", spec.name, i),
+            None => spec.name.to_string(),
+        };
+        self.params.push(Param {
+            name,
+            spec: *spec,
+            control,
+        });
+        self
+    }
+}
+
+impl Page {
+    fn new(name: &str, groups: Vec<Group>) -> Self {
+        Self {
+            name: name.to_string(),
+            groups,
+        }
+    }
+}
+
+impl ControlLayout {
+    /// Generate the layout for a show with the provided number of mixer pages.
+    pub fn for_show(n_pages: usize) -> Self {
+        Self {
+            pages: vec![
+                mixer_page(n_pages),
+                Page::new(
+                    "Beam",
+                    vec![
+                        beam_type_group(),
+                        Group::new("Tunnel").with(params::TUNNEL, tunnel_param_control),
+                        Group::new("Animation").with(params::ANIMATION, |spec| {
+                            animation_param_control(spec).or_else(|| master_ui_param_control(spec))
+                        }),
+                        (0..AnimationPresets::N_PRESETS).fold(
+                            Group::new("Animation presets").with_indexed(
+                                &params::ANIMATION_PRESET_SAVE,
+                                None,
+                                master_ui_param_control(&params::ANIMATION_PRESET_SAVE),
+                            ),
+                            |group, i| {
+                                group.with_indexed(
+                                    &params::ANIMATION_PRESET,
+                                    Some(i),
+                                    animation_preset_control(i),
+                                )
+                            },
+                        ),
+                    ],
+                ),
+                Page::new(
+                    "Clocks",
+                    (0..N_CLOCKS)
+                        .map(|i| {
+                            Group::new(format!("Clock {}", i))
+                                .with(params::CLOCK, |spec| clock_param_control(spec, ClockIdx(i)))
+                        })
+                        .collect(),
+                ),
+                beam_store_page(n_pages),
+                Page::new(
+                    "Video outputs",
+                    vec![
+                        (0..Mixer::N_VIDEO_CHANNELS).fold(Group::new("Geometry"), |group, vc| {
+                            group.with_indexed(
+                                &params::GEOMETRY_PRESET,
+                                Some(vc),
+                                geometry_preset_control(VideoChannel(vc)),
+                            )
+                        }),
+                        Group::new("Output profile").with_indexed(
+                            &params::OUTPUT_PROFILE,
+                            None,
+                            output_profile_control(),
+                        ),
+                    ],
+                ),
+                Page::new(
+                    "Audio",
+                    vec![
+                        // The input level is only a meter.
+                        Group::new("Input").with(params::AUDIO, |_| None),
+                        (0..n_pages * MIXER_CHANNELS_PER_PAGE).fold(
+                            Group::new("Color organ")
+                                .with(params::COLOR_ORGAN, color_organ_param_control),
+                            |group, chan| {
+                                group.with_indexed(
+                                    &params::COLOR_ORGAN_BAND,
+                                    Some(chan),
+                                    color_organ_band_control(ChannelIdx(chan)),
+                                )
+                            },
+                        ),
+                    ],
+                ),
+                Page::new(
+                    "Palettes",
+                    vec![Deck::ALL.iter().enumerate().fold(
+                        Group::new("Palettes").with(params::PALETTE, palette_param_control),
+                        |group, (i, deck)| {
+                            group.with_indexed(
+                                &params::PALETTE_SELECT,
+                                Some(i),
+                                palette_select_control(*deck),
+                            )
+                        },
+                    )],
+                ),
+                Page::new(
+                    "Autopilot",
+                    vec![Group::new("Autopilot").with(params::AUTOPILOT, autopilot_param_control)],
+                ),
+            ],
+        }
+    }
+}
+
+fn mixer_page(n_pages: usize) -> Page {
+    let mut groups = vec![Group::new("Master").with(params::MIXER, |spec| {
+        mixer_param_control(spec).or_else(|| master_ui_param_control(spec))
+    })];
+    for chan in 0..n_pages * MIXER_CHANNELS_PER_PAGE {
+        let chan = ChannelIdx(chan);
+        let mut group = Group::new(format!("Channel {}", chan.0))
+            .with(params::MIXER_CHANNEL, |spec| {
+                channel_param_control(spec, chan)
+            });
+        for vc in 0..Mixer::N_VIDEO_CHANNELS {
+            group = group.with_indexed(
+                &params::VIDEO_CHANNEL,
+                Some(vc),
+                video_channel_control(chan, VideoChannel(vc)),
+            );
+        }
+        groups.push(group);
+    }
+    Page::new("Mixer", groups)
+}
+
+/// One button per selectable beam type, including registered generators.
+fn beam_type_group() -> Group {
+    let mut group = Group::new("Beam type");
+    for (i, name) in Beam::type_names().iter().enumerate() {
+        group.params.push(Param {
+            name: name.to_string(),
+            spec: params::BEAM_TYPE,
+            control: beam_type_control(i),
+        });
+    }
+    group
+}
+
+fn beam_store_page(n_pages: usize) -> Page {
+    let mut groups = vec![
+        Group::new("Mode").with(params::BEAM_STORE_MODES, master_ui_param_control),
+        Group::new("Shuffle").with(params::BEAM_STORE_SHUFFLE, master_ui_param_control),
+        Group::new("Recall filter").with(params::BEAM_STORE_RECALL_FILTER, master_ui_param_control),
+    ];
+    for row in 0..BeamStore::N_ROWS {
+        let mut group = Group::new(format!("Row {}", row));
+        for col in 0..n_pages * BeamStore::COLS_PER_PAGE {
+            group = group.with_indexed(
+                &params::BEAM_STORE_SLOT,
+                Some(col),
+                beam_store_slot_control(BeamStoreAddr { row, col }),
+            );
+        }
+        groups.push(group);
+    }
+    Page::new("Beam store", groups)
+}
+
+/// Serve the control layout to any front end that asks for it.
+pub struct LayoutServer {
+    join_handle: Option<thread::JoinHandle<()>>,
+    run: RunFlag,
+}
+
+impl LayoutServer {
+    /// Start the layout server.
+    /// The server will run until it is dropped.
+    pub fn start(ctx: &mut Context, layout: &ControlLayout) -> Result<Self, Box<dyn Error>> {
+        let mut resp = Vec::new();
+        layout.serialize(&mut Serializer::new(&mut resp).with_struct_map())?;
+
+        let socket = ctx.socket(zmq::REP)?;
+        socket.bind(&format!("tcp://*:{}", PORT))?;
+        // time out once per second
+        socket.set_rcvtimeo(1000)?;
+        let run = RunFlag::new();
+        let run_local = run.clone();
+
+        let jh = thread::Builder::new()
+            .name("control_layout".to_string())
+            .spawn(move || loop {
+                if !run.should_run() {
+                    return;
+                }
+                match socket.recv_bytes(0) {
+                    Err(zmq::Error::EAGAIN) => (),
+                    Err(e) => {
+                        error!("Control layout receive error: {}.", e);
+                    }
+                    Ok(_) => {
+                        if let Err(e) = socket.send(&resp, 0) {
+                            error!("Control layout send error: {}.", e);
+                        }
+                    }
+                }
+            })?;
+        info!("Control layout server started.");
+        Ok(Self {
+            join_handle: Some(jh),
+            run: run_local,
+        })
+    }
+}
+
+impl Drop for LayoutServer {
+    fn drop(&mut self) {
+        self.run.stop();
+        self.join_handle.take().unwrap().join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::ShowConfig,
+        midi::{event, EventType, Manager, Mapping},
+        midi_controls::{Dispatcher, SurfaceControl},
+    };
+    use std::collections::HashSet;
+    use tunnels_lib::control_protocol::EventKind;
+
+    #[test]
+    fn test_layout_matches_show() {
+        for n_pages in 1..=2 {
+            let layout = ControlLayout::for_show(n_pages);
+            let mixer = &layout.pages[0];
+            // Master group plus one per channel.
+            assert_eq!(1 + n_pages * MIXER_CHANNELS_PER_PAGE, mixer.groups.len());
+
+            let beam_store = &layout.pages[3];
+            let row = beam_store.groups.last().unwrap();
+            assert_eq!(n_pages * BeamStore::COLS_PER_PAGE, row.params.len());
+
+            // Every parameter must be distinguishable within its group.
+            for page in &layout.pages {
+                for group in &page.groups {
+                    let mut names = HashSet::new();
+                    for param in &group.params {
+                        assert!(
+                            names.insert(&param.name),
+                            "duplicate name {} in {}",
+                            param.name,
+                            group.name
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_controls_are_mapped() {
+        let mut dispatcher = Dispatcher::new(Manager::new(), &ShowConfig::default());
+        let mut dispatches = |control: &SurfaceControl| {
+            let mapping = Mapping {
+                event_type: match control.kind {
+                    EventKind::NoteOn => EventType::NoteOn,
+                    EventKind::NoteOff => EventType::NoteOff,
+                    EventKind::ControlChange => EventType::ControlChange,
+                },
+                channel: control.channel,
+                control: control.control,
+            };
+            dispatcher
+                .dispatch(control.device, event(mapping, 127))
+                .is_some()
+        };
+        for n_pages in 1..=2 {
+            for page in ControlLayout::for_show(n_pages).pages {
+                for group in page.groups {
+                    for param in group.params {
+                        let context = format!("{} {} {}", page.name, group.name, param.name);
+                        match &param.control {
+                            Some(ParamControl::Value(control)) => {
+                                assert!(dispatches(control), "{} is not mapped", context);
+                            }
+                            Some(ParamControl::Buttons(controls)) => {
+                                let (min, max) = param.spec.kind.range();
+                                assert_eq!(
+                                    (max - min) as usize + 1,
+                                    controls.len(),
+                                    "{} needs a button per value",
+                                    context
+                                );
+                                for control in controls {
+                                    assert!(dispatches(control), "{} is not mapped", context);
+                                }
+                            }
+                            None => {
+                                // Only meters, and the color organ bands
+                                // beyond the first page, have no control.
+                                let meter = page.name == "Audio" && group.name == "Input";
+                                let band = param.spec == params::COLOR_ORGAN_BAND;
+                                assert!(
+                                    meter || (band && n_pages > 1),
+                                    "{} has no control",
+                                    context
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
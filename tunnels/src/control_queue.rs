@@ -0,0 +1,162 @@
+//! Buffer control events between input and the show loop.
+//!
+//! Controls can produce events far faster than the frame rate, especially
+//! when a fader is swept or an encoder twirled.  The show takes every pending
+//! event into this queue at once and handles them at control rate, up until
+//! the next frame is due, so a burst of input never delays a frame.
+//!
+//! A run of changes to the same fader or knob collapses to its latest value,
+//! so sweeping a fader costs one update rather than dozens.  Relative encoder
+//! steps, button presses, and other controls that act on every event, such as
+//! a console's go, are never collapsed, so none are lost.
+//! The queue is bounded; if input outruns the show entirely, new events that
+//! can't be collapsed are dropped and counted.
+//!
+//! Each event keeps the time it arrived.  A collapsed change keeps the time
+//! of the first change it absorbed, since that is when the control started
+//! waiting to take effect.
+
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{device::Device, midi::Event};
+
+pub struct ControlQueue {
+    /// Pending events with the time each arrived, and whether each may
+    /// absorb later changes to the same control.
+    events: VecDeque<(Instant, Device, Event, bool)>,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl ControlQueue {
+    /// Most events to hold at once.
+    pub const CAPACITY: usize = 1024;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Add an event to the back of the queue.
+    ///
+    /// If the event is an absolute value that may be collapsed, and a change
+    /// to the same control is pending with only other collapsible changes
+    /// queued after it, update that change instead.  Collapsing past any
+    /// other event could reorder it with respect to, say, selecting a
+    /// different channel.
+    pub fn push(&mut self, at: Instant, device: Device, event: Event, collapse: bool) {
+        if collapse {
+            let pending = self
+                .events
+                .iter_mut()
+                .rev()
+                .take_while(|(_, _, _, collapsible)| *collapsible)
+                .find(|(_, d, e, _)| *d == device && e.mapping == event.mapping);
+            if let Some((_, _, pending, _)) = pending {
+                pending.value = event.value;
+                return;
+            }
+        }
+        if self.events.len() >= self.capacity {
+            self.dropped += 1;
+            return;
+        }
+        self.events.push_back((at, device, event, collapse));
+    }
+
+    pub fn pop(&mut self) -> Option<(Instant, Device, Event)> {
+        self.events
+            .pop_front()
+            .map(|(at, device, event, _)| (at, device, event))
+    }
+
+    /// Return the number of events dropped since the last call.
+    pub fn take_dropped(&mut self) -> usize {
+        std::mem::take(&mut self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi::{cc, event, note_on};
+    use std::time::Duration;
+
+    #[test]
+    fn test_collapse() {
+        let mut queue = ControlQueue::new(5);
+        let fader = cc(0, 7);
+        let knob = cc(0, 8);
+        let select = note_on(0, 0x33);
+        let apc = Device::AkaiApc40;
+
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        queue.push(at(0), apc, event(fader, 1), true);
+        queue.push(at(1), apc, event(knob, 1), true);
+        queue.push(at(2), apc, event(fader, 2), true);
+        queue.push(at(3), Device::TouchOsc, event(fader, 3), true);
+        queue.push(at(4), apc, event(select, 127), false);
+        // Not collapsed past the button press.
+        queue.push(at(5), apc, event(fader, 4), true);
+        // Full, and nothing to collapse into.
+        queue.push(at(6), apc, event(select, 127), false);
+        queue.push(at(7), apc, event(fader, 5), true);
+        assert_eq!(1, queue.take_dropped());
+        assert_eq!(0, queue.take_dropped());
+
+        // Collapsed changes keep the time of the first.
+        let popped: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(t, d, e)| (t, d, e.mapping, e.value))
+            .collect();
+        assert_eq!(
+            vec![
+                (at(0), apc, fader, 2),
+                (at(1), apc, knob, 1),
+                (at(3), Device::TouchOsc, fader, 3),
+                (at(4), apc, select, 127),
+                (at(5), apc, fader, 5),
+            ],
+            popped
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_dmx_go() {
+        use crate::{config::ShowConfig, dmx, midi::Manager, midi_controls::Dispatcher};
+
+        let config: ShowConfig = serde_yaml::from_str(
+            "dmx: {inputs: [{address: 1, action: GrandMaster}, {address: 2, action: Go}]}",
+        )
+        .unwrap();
+        let dispatcher = Dispatcher::new(Manager::new(), &config);
+        let grand_master = dmx::mapping(1);
+        let go = dmx::mapping(2);
+
+        let mut queue = ControlQueue::new(8);
+        let now = Instant::now();
+        for e in [
+            event(grand_master, 10),
+            event(grand_master, 20),
+            event(go, 255),
+            event(go, 255),
+        ] {
+            queue.push(now, Device::Dmx, e, dispatcher.collapsible(Device::Dmx, &e));
+        }
+
+        // Both gos are kept, so no cue is lost.
+        let popped: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(_, _, e)| (e.mapping, e.value))
+            .collect();
+        assert_eq!(vec![(grand_master, 20), (go, 255), (go, 255)], popped);
+    }
+}
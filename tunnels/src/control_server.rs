@@ -0,0 +1,216 @@
+//! Drive the show from scripts over the network.
+//!
+//! Scripts send requests in the line protocol described in
+//! tunnels_lib::control_protocol, either directly over zmq or through the
+//! Python bindings in tunnels_py.  The events in a request are injected into
+//! the midi input stream as if they came from the named device, so a script
+//! can do anything the device could, using the same mappings.
+
+use std::{error::Error, thread, time::Instant};
+use tracing::{error, info, warn};
+use tunnels_lib::{
+    control_protocol::{self, ControlEvent, EventKind, OK, PORT},
+    queue::DropSender,
+    RunFlag,
+};
+use zmq::Context;
+
+use crate::{
+    device::Device,
+    midi::{event, Event, EventType, Mapping},
+};
+
+/// Resolve a protocol event into an event from a known device.
+fn resolve(e: &ControlEvent) -> Result<(Device, Event), String> {
+    let device = Device::parse_midi(&e.device)?;
+    let event_type = match e.kind {
+        EventKind::NoteOn => EventType::NoteOn,
+        EventKind::NoteOff => EventType::NoteOff,
+        EventKind::ControlChange => EventType::ControlChange,
+    };
+    let mapping = Mapping {
+        event_type,
+        channel: e.channel,
+        control: e.control,
+    };
+    Ok((device, event(mapping, e.value)))
+}
+
+/// Parse every line of a request.
+fn parse_request(request: &str) -> Result<Vec<(Device, Event)>, String> {
+    control_protocol::parse_request(request)?
+        .iter()
+        .map(|e| resolve(e).map_err(|err| format!("\"{}\": {}", e, err)))
+        .collect()
+}
+
+pub struct ControlServer {
+    join_handle: Option<thread::JoinHandle<()>>,
+    run: RunFlag,
+}
+
+impl ControlServer {
+    /// Start serving control requests, injecting events on the provided
+    /// channel.
+    /// The server will run until it is dropped.
+    pub fn start(
+        ctx: &mut Context,
+        sender: DropSender<(Instant, Device, Event)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let socket = ctx.socket(zmq::REP)?;
+        socket.bind(&format!("tcp://*:{}", PORT))?;
+        // time out once per second
+        socket.set_rcvtimeo(1000)?;
+        let run = RunFlag::new();
+        let run_local = run.clone();
+
+        let jh = thread::Builder::new()
+            .name("control_server".to_string())
+            .spawn(move || loop {
+                if !run.should_run() {
+                    return;
+                }
+                let msg = match socket.recv_bytes(0) {
+                    Err(zmq::Error::EAGAIN) => continue,
+                    Err(e) => {
+                        error!("Control server receive error: {}.", e);
+                        continue;
+                    }
+                    Ok(msg) => msg,
+                };
+                let reply = match parse_request(&String::from_utf8_lossy(&msg)) {
+                    Ok(events) => {
+                        for e in events {
+                            if sender.send((Instant::now(), e.0, e.1)).is_err() {
+                                // The show has shut down.
+                                return;
+                            }
+                        }
+                        OK.to_string()
+                    }
+                    Err(e) => {
+                        warn!("Malformed control request: {}.", e);
+                        e
+                    }
+                };
+                if let Err(e) = socket.send(reply.as_bytes(), 0) {
+                    error!("Control server send error: {}.", e);
+                }
+            })?;
+        info!("Control server started.");
+        Ok(Self {
+            join_handle: Some(jh),
+            run: run_local,
+        })
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.run.stop();
+        if let Some(jh) = self.join_handle.take() {
+            if jh.join().is_err() {
+                error!("Control server thread panicked.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi::{cc, note_on};
+
+    #[test]
+    fn test_parse_request() {
+        let events = parse_request("TouchOsc cc 0 21 127\n\n  AkaiApc40 note_on 1 2 0 ").unwrap();
+        assert_eq!(2, events.len());
+        assert_eq!(Device::TouchOsc, events[0].0);
+        assert_eq!(cc(0, 21), events[0].1.mapping);
+        assert_eq!(127, events[0].1.value);
+        assert_eq!(Device::AkaiApc40, events[1].0);
+        assert_eq!(note_on(1, 2), events[1].1.mapping);
+
+        assert!(parse_request("").unwrap().is_empty());
+        // One bad line spoils the request.
+        assert!(parse_request("TouchOsc cc 0 21 127\nTouchOsc cc 0 21").is_err());
+        assert!(parse_request("Keytar cc 0 21 127").is_err());
+        assert!(parse_request("TouchOsc pitch_bend 0 21 127").is_err());
+    }
+
+    #[test]
+    fn test_show_controls() {
+        use crate::{
+            beam_store::BeamStoreAddr,
+            clock::ControlMessage::Tap,
+            clock_bank::{ClockIdx, ControlMessage as ClockMessage},
+            config::ShowConfig,
+            master_ui::{ControlMessage as UIMessage, StateChange as UIChange},
+            midi::Manager,
+            midi_controls::Dispatcher,
+            mixer::{
+                ChannelControlMessage, ChannelIdx, ChannelStateChange,
+                ControlMessage as MixerMessage, StateChange as MixerChange,
+            },
+            show::ControlMessage::{self, *},
+        };
+        use tunnels_lib::show_controls;
+
+        let mut dispatcher = Dispatcher::new(Manager::new(), &ShowConfig::default());
+        let mut dispatch = |e: Result<ControlEvent, Box<dyn Error>>| -> ControlMessage {
+            let (device, event) = resolve(&e.unwrap()).unwrap();
+            dispatcher.dispatch(device, event).unwrap()
+        };
+        let channel = |msg: ControlMessage| match msg {
+            Mixer(MixerMessage::Channel(ChannelIdx(c), msg)) => (c, msg),
+            _ => panic!("not a mixer channel message"),
+        };
+
+        assert!(matches!(
+            dispatch(show_controls::grand_master(1.0)),
+            Mixer(MixerMessage::Set(MixerChange::GrandMaster(v))) if v.val() == 1.0
+        ));
+        assert!(matches!(
+            dispatch(show_controls::toggle_blackout()),
+            Mixer(MixerMessage::ToggleBlackout)
+        ));
+        for chan in [0, 7, 8, 15] {
+            use ChannelControlMessage::*;
+            use ChannelStateChange::*;
+
+            assert!(matches!(
+                channel(dispatch(show_controls::channel_level(chan, 0.0))),
+                (c, Set(Level(v))) if c == chan && v.val() == 0.0
+            ));
+            assert!(matches!(
+                channel(dispatch(show_controls::channel_bump(chan, true))),
+                (c, Set(Bump(true))) if c == chan
+            ));
+            assert!(matches!(
+                channel(dispatch(show_controls::channel_bump(chan, false))),
+                (c, Set(Bump(false))) if c == chan
+            ));
+            assert!(matches!(
+                channel(dispatch(show_controls::toggle_channel_mask(chan))),
+                (c, ToggleMask) if c == chan
+            ));
+            assert!(matches!(
+                dispatch(show_controls::select_channel(chan)),
+                MasterUI(UIMessage::Set(UIChange::Channel(ChannelIdx(c)))) if c == chan
+            ));
+        }
+        // The second page of the grid is only on the APC20.
+        for (row, col) in [(0, 0), (4, 7), (2, 8), (4, 15)] {
+            assert!(matches!(
+                dispatch(show_controls::recall_beam(row, col)),
+                MasterUI(UIMessage::RecallBeam(addr)) if addr == BeamStoreAddr { row, col }
+            ));
+        }
+        for clock in 0..4 {
+            assert!(matches!(
+                dispatch(show_controls::tap_clock(clock)),
+                Clock(ClockMessage { channel: ClockIdx(c), msg: Tap }) if c == clock
+            ));
+        }
+    }
+}
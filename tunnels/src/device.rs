@@ -1,16 +1,24 @@
 use std::fmt;
 
 use crate::midi::{Event, EventType, Mapping, Output};
-use log::debug;
 use midir::SendError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
 
 /// The input device types that tunnels can work with.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum Device {
     AkaiApc40,
     AkaiApc20,
     TouchOsc,
     BehringerCmdMM1,
+    /// Contact-closure triggers arriving over the network.
+    Trigger,
+    /// Any connected game controller.
+    Gamepad,
+    /// A lighting console sending DMX over the network.
+    Dmx,
 }
 
 impl fmt::Display for Device {
@@ -23,12 +31,94 @@ impl fmt::Display for Device {
                 Self::AkaiApc20 => "Akai APC20",
                 Self::TouchOsc => "Touch OSC",
                 Self::BehringerCmdMM1 => "Behringer CMD MM-1",
+                Self::Trigger => "Contact closure triggers",
+                Self::Gamepad => "Game controller",
+                Self::Dmx => "DMX input",
             }
         )
     }
 }
 
+/// The physical controls a device offers.
+/// The show lays out its mixer and beam grid across whichever connected
+/// devices have room for them, rather than each layout naming its devices,
+/// and only echoes state back to the controls that can show it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Channel faders, not counting any master fader.
+    pub faders: usize,
+    /// Knobs with a display of their value, whether endless or not.
+    pub encoders: usize,
+    /// Rows and columns of the pad grid.
+    pub pads: (usize, usize),
+    /// The pads light in a choice of colors, rather than only on or off.
+    pub rgb: bool,
+    /// The faders follow show state, so they should be sent every change.
+    pub motor_faders: bool,
+    /// The device extends a main controller, taking the second page of
+    /// mixer channels and beam store columns.
+    pub wing: bool,
+}
+
+impl Capabilities {
+    /// The page of mixer channels and beam store columns on this device.
+    pub fn page(&self) -> usize {
+        self.wing as usize
+    }
+}
+
 impl Device {
+    /// The devices that can be connected over MIDI.
+    pub const MIDI: [Device; 4] = [
+        Self::TouchOsc,
+        Self::AkaiApc40,
+        Self::BehringerCmdMM1,
+        Self::AkaiApc20,
+    ];
+
+    /// Parse the name of a device that can be connected over MIDI.
+    pub fn parse_midi(name: &str) -> Result<Device, String> {
+        Self::MIDI
+            .iter()
+            .find(|d| format!("{:?}", d).eq_ignore_ascii_case(name.trim()))
+            .copied()
+            .ok_or_else(|| format!("unknown MIDI device \"{}\"", name))
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        match *self {
+            Self::AkaiApc40 => Capabilities {
+                faders: 8,
+                encoders: 16,
+                pads: (5, 8),
+                rgb: true,
+                ..Default::default()
+            },
+            Self::AkaiApc20 => Capabilities {
+                faders: 8,
+                pads: (5, 8),
+                rgb: true,
+                wing: true,
+                ..Default::default()
+            },
+            // As laid out by our TouchOSC template, a mirror of the APC40
+            // whose controls all follow the show.
+            Self::TouchOsc => Capabilities {
+                faders: 8,
+                encoders: 16,
+                pads: (5, 8),
+                motor_faders: true,
+                ..Default::default()
+            },
+            Self::BehringerCmdMM1 => Capabilities {
+                faders: 4,
+                encoders: 12,
+                ..Default::default()
+            },
+            Self::Trigger | Self::Gamepad | Self::Dmx => Capabilities::default(),
+        }
+    }
+
     /// Perform device-specific midi initialization.
     pub fn init_midi(&self, out: &mut Output) -> Result<(), SendError> {
         match *self {
@@ -36,6 +126,9 @@ impl Device {
             Self::AkaiApc20 => init_apc_20(out),
             Self::TouchOsc => Ok(()),
             Self::BehringerCmdMM1 => Ok(()),
+            Self::Trigger => Ok(()),
+            Self::Gamepad => Ok(()),
+            Self::Dmx => Ok(()),
         }
     }
 }
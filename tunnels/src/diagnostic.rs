@@ -0,0 +1,100 @@
+//! A video channel for the operator rather than the audience.
+//!
+//! When enabled, the server publishes an extra video channel after the mixer's
+//! channels, drawing the phase of each clock as a ring that sweeps around once
+//! per cycle, and the audio input level as an outer ring that turns from
+//! green to red as it approaches full scale.  Any client can display it by
+//! running on that video channel, such as a monitor in the booth.
+//!
+//! While the operator is listening to an animation, the current beam is drawn
+//! beneath the rings with that animation at full weight, so that what the
+//! animation does can be seen before it is given any weight in the show.
+
+use std::sync::Arc;
+use tunnels_lib::{
+    number::{Phase, UnipolarFloat},
+    ArcSegment, LayerCollection,
+};
+
+use crate::{
+    clock_bank::{ClockBank, ClockIdx, N_CLOCKS},
+    mixer::Mixer,
+    tunnel::Tunnel,
+};
+
+/// The video channel the diagnostic layer is published on.
+pub const DIAGNOSTIC_CHANNEL: usize = Mixer::N_VIDEO_CHANNELS;
+
+const THICKNESS: f64 = 0.1;
+const CLOCK_RADIUS: f64 = 0.15;
+const RING_SPACING: f64 = 0.06;
+const AUDIO_RADIUS: f64 = CLOCK_RADIUS + RING_SPACING * (N_CLOCKS as f64 + 0.5);
+
+/// Draw the clocks and, if available, the audio input level, over the
+/// preview of the animation being listened to, if any.
+pub fn render(
+    clocks: &ClockBank,
+    audio_level: Option<UnipolarFloat>,
+    listen: Option<&Tunnel>,
+) -> LayerCollection {
+    let mut arcs = (0..N_CLOCKS)
+        .map(|i| {
+            ring(
+                CLOCK_RADIUS + RING_SPACING * i as f64,
+                i as f64 / N_CLOCKS as f64,
+                clocks.phase(ClockIdx(i)).val(),
+            )
+        })
+        .collect::<Vec<_>>();
+    if let Some(level) = audio_level {
+        // Green at the bottom of the scale, red at the top.
+        arcs.push(ring(AUDIO_RADIUS, (1.0 - level.val()) / 3.0, level.val()));
+    }
+    let mut layers = Vec::new();
+    if let Some(tunnel) = listen {
+        layers.push(Arc::new(tunnel.render(
+            UnipolarFloat::ONE,
+            false,
+            1.0,
+            clocks,
+        )));
+    }
+    layers.push(Arc::new(arcs));
+    layers
+}
+
+/// A centered ring swept from the angular origin by the provided fraction of
+/// a turn.
+fn ring(radius: f64, hue: f64, sweep: f64) -> ArcSegment {
+    ArcSegment {
+        level: 1.0,
+        thickness: THICKNESS,
+        hue: Phase::new(hue).val(),
+        sat: 1.0,
+        val: 1.0,
+        x: 0.0,
+        y: 0.0,
+        rad_x: radius,
+        rad_y: radius,
+        start: 0.0,
+        stop: sweep,
+        rot_angle: 0.0,
+        palette: false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let clocks = ClockBank::new();
+        assert_eq!(N_CLOCKS, render(&clocks, None, None)[0].len());
+
+        let layers = render(&clocks, Some(UnipolarFloat::new(0.5)), None);
+        let audio = &layers[0][N_CLOCKS];
+        assert_eq!(0.5, audio.stop);
+        assert_eq!(AUDIO_RADIUS, audio.rad_x);
+    }
+}
@@ -0,0 +1,386 @@
+//! DMX input, so a house lighting console can supervise the show.
+//!
+//! One DMX universe is received over sACN (E1.31) or Art-Net.  Each configured
+//! DMX address drives a show parameter:
+//!
+//! - GrandMaster and Level(channel) follow the address value continuously.
+//! - Palette selects a beam store slot: a value of n recalls slot n, counting
+//!   down each column of the beam store in turn.  Zero selects nothing.
+//! - Go recalls the next occupied beam store slot each time the address rises
+//!   past half.
+//!
+//! Consoles send every address many times a second, so only changes are
+//! passed on.  Like contact-closure triggers, DMX changes are injected into the
+//! midi input stream as if they came from a device, carrying the full 8-bit
+//! DMX value; each address is mapped to a control change.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    error::Error,
+    net::{Ipv4Addr, UdpSocket},
+    thread,
+    time::Instant,
+};
+use tracing::{error, info, warn};
+use tunnels_lib::queue::DropSender;
+
+use crate::{
+    beam_store::{BeamStore, BeamStoreAddr},
+    device::Device,
+    dmx_merge::MergePolicy,
+    midi::{cc, event, Event, Mapping},
+};
+
+/// Number of addresses in a DMX universe.
+pub const UNIVERSE_SIZE: u16 = 512;
+
+pub const SACN_PORT: u16 = 5568;
+pub const ARTNET_PORT: u16 = 6454;
+
+/// Addresses at or above this value count as "on" for edge-triggered actions.
+const GO_THRESHOLD: u8 = 128;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum DmxProtocol {
+    Sacn,
+    ArtNet,
+}
+
+impl Default for DmxProtocol {
+    fn default() -> Self {
+        Self::Sacn
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DmxConfig {
+    #[serde(default)]
+    pub protocol: DmxProtocol,
+    /// Universe to listen to.  sACN universes start at 1, Art-Net at 0.
+    #[serde(default = "default_universe")]
+    pub universe: u16,
+    /// How console levels combine with local control, unless overridden for
+    /// a particular input.
+    #[serde(default)]
+    pub merge: MergePolicy,
+    pub inputs: Vec<DmxInput>,
+}
+
+fn default_universe() -> u16 {
+    1
+}
+
+impl DmxConfig {
+    /// Check that every input refers to something that exists in a show with
+    /// the provided number of mixer channels.
+    pub fn validate(&self, n_channels: usize) -> Result<(), Box<dyn Error>> {
+        match self.protocol {
+            DmxProtocol::Sacn if !(1..=63999).contains(&self.universe) => {
+                bail!("sACN universe {} is out of range.", self.universe);
+            }
+            DmxProtocol::ArtNet if self.universe > 0x7fff => {
+                bail!("Art-Net universe {} is out of range.", self.universe);
+            }
+            _ => (),
+        }
+        let mut seen = Vec::new();
+        for DmxInput {
+            address,
+            action,
+            merge,
+        } in &self.inputs
+        {
+            if !(1..=UNIVERSE_SIZE).contains(address) {
+                bail!("DMX address {} is out of range.", address);
+            }
+            if seen.contains(address) {
+                bail!("DMX address {} is assigned more than once.", address);
+            }
+            seen.push(*address);
+            if merge.is_some() && matches!(action, DmxAction::Palette | DmxAction::Go) {
+                bail!(
+                    "DMX address {} has a merge policy but doesn't control a level.",
+                    address
+                );
+            }
+            if let DmxAction::Level(chan) = *action {
+                if chan >= n_channels {
+                    bail!(
+                        "DMX address {} sets the level of channel {} but the show has {} channels.",
+                        address,
+                        chan,
+                        n_channels
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Assign an action to a single DMX address.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DmxInput {
+    /// DMX address, starting at 1.
+    pub address: u16,
+    pub action: DmxAction,
+    /// How this level combines with local control.  Only applies to the
+    /// grand master and channel levels.
+    #[serde(default)]
+    pub merge: Option<MergePolicy>,
+}
+
+/// Things a DMX address can control.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum DmxAction {
+    GrandMaster,
+    /// Level of a mixer channel.
+    Level(usize),
+    /// Recall a beam store slot into the current channel.
+    Palette,
+    /// Recall the next occupied beam store slot into the current channel.
+    Go,
+}
+
+/// The midi mapping used to inject changes to a DMX address.
+pub const fn mapping(address: u16) -> Mapping {
+    let index = address - 1;
+    cc((index / 128) as u8, (index % 128) as u8)
+}
+
+/// Return the beam store slot selected by a palette value, counting down each
+/// column in turn.
+pub fn palette_slot(value: u8) -> Option<BeamStoreAddr> {
+    let index = (value as usize).checked_sub(1)?;
+    Some(BeamStoreAddr {
+        row: index % BeamStore::N_ROWS,
+        col: index / BeamStore::N_ROWS,
+    })
+}
+
+/// Return the DMX data in an sACN packet if it is for the provided universe.
+pub fn parse_sacn(packet: &[u8], universe: u16) -> Option<&[u8]> {
+    const ACN_ID: &[u8] = b"ASC-E1.17\0\0\0";
+    const VECTOR_ROOT_E131_DATA: u32 = 0x4;
+    const VECTOR_E131_DATA_PACKET: u32 = 0x2;
+    const PREVIEW_DATA: u8 = 0x80;
+    const STREAM_TERMINATED: u8 = 0x40;
+    if packet.len() < 126 || &packet[4..16] != ACN_ID {
+        return None;
+    }
+    let u16_at = |i: usize| u16::from_be_bytes([packet[i], packet[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_be_bytes([packet[i], packet[i + 1], packet[i + 2], packet[i + 3]]);
+    if u32_at(18) != VECTOR_ROOT_E131_DATA || u32_at(40) != VECTOR_E131_DATA_PACKET {
+        return None;
+    }
+    let options = packet[112];
+    if options & (PREVIEW_DATA | STREAM_TERMINATED) != 0 || u16_at(113) != universe {
+        return None;
+    }
+    // The property values start with the DMX start code; only 0 is level data.
+    let count = u16_at(123) as usize;
+    if count == 0 || packet[125] != 0 {
+        return None;
+    }
+    packet.get(126..125 + count)
+}
+
+/// Return the DMX data in an Art-Net packet if it is for the provided universe.
+pub fn parse_artnet(packet: &[u8], universe: u16) -> Option<&[u8]> {
+    const OP_DMX: u16 = 0x5000;
+    if packet.len() < 18 || &packet[0..8] != b"Art-Net\0" {
+        return None;
+    }
+    if u16::from_le_bytes([packet[8], packet[9]]) != OP_DMX
+        || u16::from_le_bytes([packet[14], packet[15]]) != universe
+    {
+        return None;
+    }
+    let length = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+    packet.get(18..18 + length)
+}
+
+/// Extract the DMX data for a universe from a packet.
+type Parser = fn(&[u8], u16) -> Option<&[u8]>;
+
+/// Turn a stream of DMX frames into midi events for the addresses we use.
+struct InputState {
+    inputs: Vec<DmxInput>,
+    /// Last value received for each input, if any.
+    last: Vec<Option<u8>>,
+    n_palette_slots: usize,
+}
+
+impl InputState {
+    fn new(inputs: Vec<DmxInput>, n_palette_slots: usize) -> Self {
+        let last = vec![None; inputs.len()];
+        Self {
+            inputs,
+            last,
+            n_palette_slots,
+        }
+    }
+
+    /// Return the events produced by a DMX frame.
+    fn update(&mut self, data: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (input, last) in self.inputs.iter().zip(self.last.iter_mut()) {
+            let value = match data.get(input.address as usize - 1) {
+                Some(v) => *v,
+                None => continue,
+            };
+            if *last == Some(value) {
+                continue;
+            }
+            let send = match input.action {
+                DmxAction::GrandMaster | DmxAction::Level(_) => true,
+                DmxAction::Palette => (1..=self.n_palette_slots).contains(&(value as usize)),
+                // Don't fire if the address is already up when we first hear it.
+                DmxAction::Go => {
+                    last.map(|l| l < GO_THRESHOLD).unwrap_or(false) && value >= GO_THRESHOLD
+                }
+            };
+            *last = Some(value);
+            if send {
+                events.push(event(mapping(input.address), value));
+            }
+        }
+        events
+    }
+}
+
+/// Listen for DMX in a background thread.
+/// Changes are sent on the provided channel.
+pub fn start_dmx_service(
+    config: &DmxConfig,
+    n_palette_slots: usize,
+    sender: DropSender<(Instant, Device, Event)>,
+) -> Result<(), Box<dyn Error>> {
+    let universe = config.universe;
+    let (socket, parse): (_, Parser) = match config.protocol {
+        DmxProtocol::Sacn => {
+            let socket = UdpSocket::bind(("0.0.0.0", SACN_PORT))?;
+            let [hi, lo] = universe.to_be_bytes();
+            socket.join_multicast_v4(&Ipv4Addr::new(239, 255, hi, lo), &Ipv4Addr::UNSPECIFIED)?;
+            (socket, parse_sacn)
+        }
+        DmxProtocol::ArtNet => (UdpSocket::bind(("0.0.0.0", ARTNET_PORT))?, parse_artnet),
+    };
+    info!(
+        "Listening for {:?} DMX on universe {}.",
+        config.protocol, universe
+    );
+    let mut state = InputState::new(config.inputs.clone(), n_palette_slots);
+    thread::Builder::new()
+        .name("dmx".to_string())
+        .spawn(move || {
+            let mut buf = [0; 1024];
+            let mut receiving = false;
+            loop {
+                let len = match socket.recv(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        error!("DMX receive error: {}.", e);
+                        continue;
+                    }
+                };
+                let data = match parse(&buf[..len], universe) {
+                    Some(data) => data,
+                    None => continue,
+                };
+                if !receiving {
+                    info!("Receiving DMX.");
+                    receiving = true;
+                }
+                if data.len() > UNIVERSE_SIZE as usize {
+                    warn!("Ignoring oversized DMX frame of {} addresses.", data.len());
+                    continue;
+                }
+                for e in state.update(data) {
+                    if sender.send((Instant::now(), Device::Dmx, e)).is_err() {
+                        // The show has shut down.
+                        return;
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sacn(universe: u16, data: &[u8]) -> Vec<u8> {
+        let mut p = vec![0; 126];
+        p[1] = 0x10;
+        p[4..16].copy_from_slice(b"ASC-E1.17\0\0\0");
+        p[21] = 0x4;
+        p[43] = 0x2;
+        p[113..115].copy_from_slice(&universe.to_be_bytes());
+        p[123..125].copy_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        p.extend_from_slice(data);
+        p
+    }
+
+    fn artnet(universe: u16, data: &[u8]) -> Vec<u8> {
+        let mut p = b"Art-Net\0".to_vec();
+        p.extend_from_slice(&[0x00, 0x50, 0, 14, 0, 0]);
+        p.extend_from_slice(&universe.to_le_bytes());
+        p.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        p.extend_from_slice(data);
+        p
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = [1, 2, 3];
+        assert_eq!(Some(&data[..]), parse_sacn(&sacn(1, &data), 1));
+        assert_eq!(None, parse_sacn(&sacn(2, &data), 1));
+        assert_eq!(None, parse_sacn(&artnet(1, &data), 1));
+        assert_eq!(Some(&data[..]), parse_artnet(&artnet(0, &data), 0));
+        assert_eq!(None, parse_artnet(&artnet(1, &data), 0));
+        assert_eq!(None, parse_artnet(&sacn(0, &data), 0));
+    }
+
+    #[test]
+    fn test_update() {
+        let input = |address, action| DmxInput {
+            address,
+            action,
+            merge: None,
+        };
+        let mut state = InputState::new(
+            vec![
+                input(1, DmxAction::GrandMaster),
+                input(2, DmxAction::Go),
+                input(3, DmxAction::Palette),
+            ],
+            10,
+        );
+        let values = |events: Vec<Event>| -> Vec<(Mapping, u8)> {
+            events.into_iter().map(|e| (e.mapping, e.value)).collect()
+        };
+        // Go doesn't fire if it is already up.
+        assert_eq!(
+            vec![(mapping(1), 255), (mapping(3), 4)],
+            values(state.update(&[255, 255, 4]))
+        );
+        assert!(state.update(&[255, 255, 4]).is_empty());
+        assert!(state.update(&[255, 0, 11]).is_empty());
+        assert_eq!(
+            vec![(mapping(1), 10), (mapping(2), 200)],
+            values(state.update(&[10, 200, 11]))
+        );
+        assert_eq!(
+            Some(BeamStoreAddr { row: 0, col: 1 }),
+            palette_slot(BeamStore::N_ROWS as u8 + 1)
+        );
+        assert_eq!(None, palette_slot(0));
+    }
+}
@@ -0,0 +1,195 @@
+//! Combining DMX from a lighting console with local control.
+//!
+//! The grand master and mixer channel levels can be set both by the console
+//! and by local controls.  Each DMX-controlled level has a merge policy:
+//!
+//! - LTP (latest takes precedence): the level follows whichever source moved
+//!   it last.  This is how any two controls for the same parameter behave.
+//! - HTP (highest takes precedence): the level is the higher of the console's
+//!   value and the local value, so neither side can pull the level below what
+//!   the other has set.  This matches how consoles merge intensity.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use tunnels_lib::number::UnipolarFloat;
+
+use crate::{
+    device::Device,
+    dmx::{DmxAction, DmxConfig},
+    mixer::{
+        ChannelControlMessage, ChannelIdx, ChannelStateChange,
+        ControlMessage as MixerControlMessage, Mixer, StateChange as MixerStateChange,
+    },
+    show::ControlMessage,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum MergePolicy {
+    Htp,
+    Ltp,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        Self::Ltp
+    }
+}
+
+/// A level that both the console and local controls can set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Level {
+    GrandMaster,
+    Channel(ChannelIdx),
+}
+
+impl Level {
+    /// Return the level set by a control message, if it sets one.
+    fn from_message(msg: &ControlMessage) -> Option<(Self, UnipolarFloat)> {
+        match msg {
+            ControlMessage::Mixer(MixerControlMessage::Set(MixerStateChange::GrandMaster(v))) => {
+                Some((Self::GrandMaster, *v))
+            }
+            ControlMessage::Mixer(MixerControlMessage::Channel(
+                chan,
+                ChannelControlMessage::Set(ChannelStateChange::Level(v)),
+            )) => Some((Self::Channel(*chan), *v)),
+            _ => None,
+        }
+    }
+
+    fn message(self, v: UnipolarFloat) -> ControlMessage {
+        ControlMessage::Mixer(match self {
+            Self::GrandMaster => MixerControlMessage::Set(MixerStateChange::GrandMaster(v)),
+            Self::Channel(chan) => MixerControlMessage::Channel(
+                chan,
+                ChannelControlMessage::Set(ChannelStateChange::Level(v)),
+            ),
+        })
+    }
+
+    fn current(self, mixer: &Mixer) -> UnipolarFloat {
+        match self {
+            Self::GrandMaster => mixer.grand_master(),
+            Self::Channel(chan) => mixer.level(chan),
+        }
+    }
+}
+
+/// Apply merge policies to incoming level changes.
+pub struct DmxMerge {
+    /// Levels merged HTP.  Every other level is merged LTP.
+    htp: HashSet<Level>,
+    /// The last value of each HTP level from the console.
+    console: HashMap<Level, UnipolarFloat>,
+    /// The last value of each HTP level from every other source.
+    local: HashMap<Level, UnipolarFloat>,
+}
+
+impl DmxMerge {
+    pub fn new(config: Option<&DmxConfig>) -> Self {
+        let htp = config
+            .map(|config| {
+                config
+                    .inputs
+                    .iter()
+                    .filter(|input| input.merge.unwrap_or(config.merge) == MergePolicy::Htp)
+                    .filter_map(|input| match input.action {
+                        DmxAction::GrandMaster => Some(Level::GrandMaster),
+                        DmxAction::Level(chan) => Some(Level::Channel(ChannelIdx(chan))),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            htp,
+            console: HashMap::new(),
+            local: HashMap::new(),
+        }
+    }
+
+    /// Return the message to handle in place of a control message from a
+    /// device.
+    pub fn merge(&mut self, device: Device, msg: ControlMessage, mixer: &Mixer) -> ControlMessage {
+        let (level, value) = match Level::from_message(&msg) {
+            Some((level, value)) if self.htp.contains(&level) => (level, value),
+            _ => return msg,
+        };
+        // Until local controls have moved a level, the local value is
+        // wherever the level was before the console first touched it.
+        let local = *self
+            .local
+            .entry(level)
+            .or_insert_with(|| level.current(mixer));
+        let (console, local) = if device == Device::Dmx {
+            self.console.insert(level, value);
+            (value, local)
+        } else {
+            self.local.insert(level, value);
+            let console = self
+                .console
+                .get(&level)
+                .copied()
+                .unwrap_or(UnipolarFloat::ZERO);
+            (console, value)
+        };
+        level.message(if console > local { console } else { local })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dmx::DmxInput;
+
+    fn grand_master(msg: ControlMessage) -> f64 {
+        match Level::from_message(&msg) {
+            Some((Level::GrandMaster, v)) => v.val(),
+            _ => panic!("Expected a grand master change."),
+        }
+    }
+
+    #[test]
+    fn test_htp() {
+        let config = DmxConfig {
+            protocol: Default::default(),
+            universe: 1,
+            merge: MergePolicy::Htp,
+            inputs: vec![DmxInput {
+                address: 1,
+                action: DmxAction::GrandMaster,
+                merge: None,
+            }],
+        };
+        let mut merge = DmxMerge::new(Some(&config));
+        let mixer = Mixer::new(1);
+        let set = |v| Level::GrandMaster.message(UnipolarFloat::new(v));
+
+        // The local grand master starts at full, so the console can't pull it down.
+        assert_eq!(
+            1.0,
+            grand_master(merge.merge(Device::Dmx, set(0.2), &mixer))
+        );
+        assert_eq!(
+            0.5,
+            grand_master(merge.merge(Device::AkaiApc40, set(0.5), &mixer))
+        );
+        assert_eq!(
+            0.8,
+            grand_master(merge.merge(Device::Dmx, set(0.8), &mixer))
+        );
+        assert_eq!(
+            0.8,
+            grand_master(merge.merge(Device::AkaiApc40, set(0.1), &mixer))
+        );
+        assert_eq!(
+            0.3,
+            grand_master(merge.merge(Device::Dmx, set(0.3), &mixer))
+        );
+
+        // Levels not set up for HTP pass straight through.
+        let mut ltp = DmxMerge::new(None);
+        assert_eq!(0.2, grand_master(ltp.merge(Device::Dmx, set(0.2), &mixer)));
+    }
+}
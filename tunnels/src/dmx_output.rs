@@ -0,0 +1,312 @@
+//! Sending DMX universes over sACN (E1.31) or Art-Net.
+//!
+//! sACN universes are multicast to their standard group unless a destination
+//! is configured; Art-Net is broadcast unless a destination is configured.
+//!
+//! A frame spanning several universes arrives as several packets, so without
+//! synchronization a receiver may output some universes of a new frame
+//! alongside the rest of the previous one, tearing the image wherever a
+//! fixture crosses a universe boundary.  With synchronization enabled, every
+//! universe of a frame is sent and then followed by a sync packet: an ArtSync
+//! for Art-Net, or an E1.31 synchronization packet on the sync universe for
+//! sACN.  Receivers that support it hold each universe until the sync packet
+//! arrives, then output them all at once.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+};
+
+use crate::dmx::{DmxProtocol, ARTNET_PORT, SACN_PORT, UNIVERSE_SIZE};
+
+/// Source name announced in sACN packets.
+const SOURCE_NAME: &str = "tunnels";
+
+/// sACN priority of our data; the protocol default.
+const SACN_PRIORITY: u8 = 100;
+
+const ARTNET_PROTOCOL_VERSION: u16 = 14;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DmxOutputConfig {
+    #[serde(default)]
+    pub protocol: DmxProtocol,
+    /// Send every universe to this address rather than multicasting or
+    /// broadcasting.
+    #[serde(default)]
+    pub destination: Option<Ipv4Addr>,
+    /// Follow each frame with a sync packet.
+    #[serde(default = "default_sync")]
+    pub sync: bool,
+    /// sACN universe to send sync packets on.  Defaults to the first universe
+    /// sent.
+    #[serde(default)]
+    pub sync_universe: Option<u16>,
+}
+
+fn default_sync() -> bool {
+    true
+}
+
+impl DmxOutputConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(universe) = self.sync_universe {
+            if self.protocol != DmxProtocol::Sacn {
+                bail!("A sync universe can only be set for sACN.");
+            }
+            if !(1..=63999).contains(&universe) {
+                bail!("sACN sync universe {} is out of range.", universe);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for DmxOutputConfig {
+    fn default() -> Self {
+        Self {
+            protocol: DmxProtocol::default(),
+            destination: None,
+            sync: default_sync(),
+            sync_universe: None,
+        }
+    }
+}
+
+/// A socket that sends DMX data.
+pub struct DmxOutput {
+    protocol: DmxProtocol,
+    destination: Option<Ipv4Addr>,
+    socket: UdpSocket,
+    /// Identifies us to sACN receivers.
+    cid: [u8; 16],
+    /// Sequence number of the next packet for each universe.
+    sequence: HashMap<u16, u8>,
+    /// The universe sACN sync packets are sent on, or 0 for none.
+    sync_universe: u16,
+    sync_sequence: u8,
+    sync: bool,
+    buf: Vec<u8>,
+}
+
+impl DmxOutput {
+    /// Create an output that sends universes starting from the provided
+    /// universe.
+    pub fn new(config: &DmxOutputConfig, first_universe: u16) -> Result<Self, Box<dyn Error>> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        if config.protocol == DmxProtocol::ArtNet && config.destination.is_none() {
+            socket.set_broadcast(true)?;
+        }
+        Ok(Self {
+            protocol: config.protocol,
+            destination: config.destination,
+            socket,
+            cid: rand::random(),
+            sequence: HashMap::new(),
+            sync_universe: match (config.sync, config.protocol) {
+                (true, DmxProtocol::Sacn) => config.sync_universe.unwrap_or(first_universe),
+                _ => 0,
+            },
+            sync_sequence: 0,
+            sync: config.sync,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Send the data for one universe.
+    pub fn send(&mut self, universe: u16, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let sequence = self.sequence.entry(universe).or_insert(0);
+        let (addr, port) = match self.protocol {
+            DmxProtocol::Sacn => {
+                sacn_packet(
+                    &mut self.buf,
+                    &self.cid,
+                    *sequence,
+                    universe,
+                    self.sync_universe,
+                    data,
+                );
+                (sacn_group(universe), SACN_PORT)
+            }
+            DmxProtocol::ArtNet => {
+                artnet_packet(&mut self.buf, *sequence, universe, data);
+                (Ipv4Addr::BROADCAST, ARTNET_PORT)
+            }
+        };
+        *sequence = match self.protocol {
+            DmxProtocol::Sacn => sequence.wrapping_add(1),
+            // Art-Net reserves sequence 0 to disable reordering.
+            DmxProtocol::ArtNet => sequence.checked_add(1).unwrap_or(1),
+        };
+        self.send_buf(addr, port)
+    }
+
+    /// Tell receivers to output every universe sent since the last sync.
+    /// Does nothing if synchronization is disabled.
+    pub fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.sync {
+            return Ok(());
+        }
+        let (addr, port) = match self.protocol {
+            DmxProtocol::Sacn => {
+                sacn_sync_packet(
+                    &mut self.buf,
+                    &self.cid,
+                    self.sync_sequence,
+                    self.sync_universe,
+                );
+                self.sync_sequence = self.sync_sequence.wrapping_add(1);
+                (sacn_group(self.sync_universe), SACN_PORT)
+            }
+            DmxProtocol::ArtNet => {
+                artsync_packet(&mut self.buf);
+                (Ipv4Addr::BROADCAST, ARTNET_PORT)
+            }
+        };
+        self.send_buf(addr, port)
+    }
+
+    /// Send the packet in the buffer to the destination, if configured, or
+    /// else the provided address.
+    fn send_buf(&self, addr: Ipv4Addr, port: u16) -> Result<(), Box<dyn Error>> {
+        let addr = self.destination.unwrap_or(addr);
+        self.socket
+            .send_to(&self.buf, SocketAddrV4::new(addr, port))?;
+        Ok(())
+    }
+}
+
+/// Return the multicast group for an sACN universe.
+fn sacn_group(universe: u16) -> Ipv4Addr {
+    let [hi, lo] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, hi, lo)
+}
+
+/// Start an sACN packet of the provided total length with its root layer.
+fn sacn_root_layer(buf: &mut Vec<u8>, cid: &[u8; 16], vector: u32, len: usize) {
+    buf.clear();
+    buf.extend_from_slice(&0x0010u16.to_be_bytes());
+    buf.extend_from_slice(&0u16.to_be_bytes());
+    buf.extend_from_slice(b"ASC-E1.17\0\0\0");
+    buf.extend_from_slice(&flags_and_length(len, 16));
+    buf.extend_from_slice(&vector.to_be_bytes());
+    buf.extend_from_slice(cid);
+}
+
+/// Each sACN layer starts with its length from that point, flagged 0x7.
+fn flags_and_length(len: usize, start: usize) -> [u8; 2] {
+    (0x7000 | (len - start) as u16).to_be_bytes()
+}
+
+/// Build an sACN data packet.
+/// A nonzero sync universe tells receivers to hold the data until a sync
+/// packet arrives on that universe.
+fn sacn_packet(
+    buf: &mut Vec<u8>,
+    cid: &[u8; 16],
+    sequence: u8,
+    universe: u16,
+    sync_universe: u16,
+    data: &[u8],
+) {
+    let data = &data[..data.len().min(UNIVERSE_SIZE as usize)];
+    let len = 126 + data.len();
+    let mut name = [0; 64];
+    name[..SOURCE_NAME.len()].copy_from_slice(SOURCE_NAME.as_bytes());
+
+    sacn_root_layer(buf, cid, 0x4, len);
+    // Framing layer.
+    buf.extend_from_slice(&flags_and_length(len, 38));
+    buf.extend_from_slice(&0x2u32.to_be_bytes());
+    buf.extend_from_slice(&name);
+    buf.push(SACN_PRIORITY);
+    buf.extend_from_slice(&sync_universe.to_be_bytes());
+    buf.push(sequence);
+    buf.push(0);
+    buf.extend_from_slice(&universe.to_be_bytes());
+    // DMP layer.
+    buf.extend_from_slice(&flags_and_length(len, 115));
+    buf.push(0x2);
+    buf.push(0xa1);
+    buf.extend_from_slice(&0u16.to_be_bytes());
+    buf.extend_from_slice(&1u16.to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+    buf.push(0);
+    buf.extend_from_slice(data);
+}
+
+/// Build an sACN synchronization packet.
+fn sacn_sync_packet(buf: &mut Vec<u8>, cid: &[u8; 16], sequence: u8, sync_universe: u16) {
+    const LEN: usize = 49;
+    sacn_root_layer(buf, cid, 0x8, LEN);
+    // Framing layer.
+    buf.extend_from_slice(&flags_and_length(LEN, 38));
+    buf.extend_from_slice(&0x1u32.to_be_bytes());
+    buf.push(sequence);
+    buf.extend_from_slice(&sync_universe.to_be_bytes());
+    buf.extend_from_slice(&0u16.to_be_bytes());
+}
+
+/// Build an ArtDmx packet.
+fn artnet_packet(buf: &mut Vec<u8>, sequence: u8, universe: u16, data: &[u8]) {
+    const OP_DMX: u16 = 0x5000;
+    let data = &data[..data.len().min(UNIVERSE_SIZE as usize)];
+    buf.clear();
+    buf.extend_from_slice(b"Art-Net\0");
+    buf.extend_from_slice(&OP_DMX.to_le_bytes());
+    buf.extend_from_slice(&ARTNET_PROTOCOL_VERSION.to_be_bytes());
+    buf.push(sequence);
+    buf.push(0);
+    buf.extend_from_slice(&universe.to_le_bytes());
+    // The data length must be even.
+    let length = data.len() + data.len() % 2;
+    buf.extend_from_slice(&(length as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+    buf.resize(18 + length, 0);
+}
+
+/// Build an ArtSync packet.
+fn artsync_packet(buf: &mut Vec<u8>) {
+    const OP_SYNC: u16 = 0x5200;
+    buf.clear();
+    buf.extend_from_slice(b"Art-Net\0");
+    buf.extend_from_slice(&OP_SYNC.to_le_bytes());
+    buf.extend_from_slice(&ARTNET_PROTOCOL_VERSION.to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dmx::{parse_artnet, parse_sacn};
+
+    #[test]
+    fn test_packets() {
+        let data = [1, 2, 3];
+        let mut buf = Vec::new();
+        sacn_packet(&mut buf, &[0; 16], 0, 7, 3, &data);
+        assert_eq!(Some(&data[..]), parse_sacn(&buf, 7));
+        assert_eq!([0, 3], buf[109..111]);
+        artnet_packet(&mut buf, 1, 7, &data);
+        // Padded to an even length.
+        assert_eq!(Some(&[1, 2, 3, 0][..]), parse_artnet(&buf, 7));
+    }
+
+    #[test]
+    fn test_sync_packets() {
+        let mut buf = Vec::new();
+        sacn_sync_packet(&mut buf, &[0; 16], 0, 3);
+        assert_eq!(49, buf.len());
+        assert_eq!([0x70, 33], buf[16..18]);
+        assert_eq!([0x70, 11], buf[38..40]);
+        assert_eq!([0, 3], buf[45..47]);
+        artsync_packet(&mut buf);
+        assert_eq!(14, buf.len());
+        assert_eq!([0x00, 0x52], buf[8..10]);
+    }
+}
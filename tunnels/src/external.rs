@@ -0,0 +1,211 @@
+//! Mixer channels whose content comes from another program.
+//!
+//! An external source is any program that publishes snapshots over zmq the
+//! same way the server publishes to its clients: a PUB socket sending
+//! two-part messages of a one-byte video channel topic and a msgpack
+//! Snapshot, with very large snapshots split into chunks.  A channel fed by an external source draws the most recent
+//! snapshot received, flattened into a single layer, in place of its beam.
+//! It is otherwise mixed like any other channel, scaled by its level and
+//! drawn black when masked.
+//!
+//! If a source stops publishing, its channel goes dark until it resumes.
+
+use rmp_serde::Deserializer;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+use tunnels_lib::{chunk::Reassembler, number::UnipolarFloat, ArcSegment, Snapshot};
+use zmq::Context;
+
+/// Stop drawing a source that has sent nothing for this long.
+const STALE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalSourceConfig {
+    /// The mixer channel to feed.
+    pub channel: usize,
+    /// zmq endpoint of the source's PUB socket, such as "tcp://10.0.0.5:6000".
+    pub address: String,
+    /// The source's video channel to subscribe to.
+    #[serde(default)]
+    pub video_channel: u8,
+}
+
+impl ExternalSourceConfig {
+    /// Check that every source feeds a distinct channel that exists in a show
+    /// with the provided number of mixer channels.
+    pub fn validate_all(sources: &[Self], n_channels: usize) -> Result<(), Box<dyn Error>> {
+        for (i, source) in sources.iter().enumerate() {
+            if source.channel >= n_channels {
+                bail!(
+                    "External source {} feeds channel {} but the show has {} channels.",
+                    source.address,
+                    source.channel,
+                    n_channels
+                );
+            }
+            if sources[..i].iter().any(|s| s.channel == source.channel) {
+                bail!(
+                    "Channel {} is fed by more than one external source.",
+                    source.channel
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Received {
+    arcs: Arc<Vec<ArcSegment>>,
+    at: Option<Instant>,
+}
+
+/// The latest content received from an external source.
+#[derive(Debug, Clone, Default)]
+pub struct ExternalFeed(Arc<Mutex<Received>>);
+
+impl ExternalFeed {
+    /// Subscribe to an external source.
+    /// The subscription runs for the life of the program.
+    pub fn start(config: &ExternalSourceConfig) -> Result<Self, Box<dyn Error>> {
+        let socket = Context::new().socket(zmq::SUB)?;
+        socket.connect(&config.address)?;
+        socket.set_subscribe(&[config.video_channel])?;
+        let feed = Self::default();
+        let received = feed.0.clone();
+        let address = config.address.clone();
+        let mut reassembler = Reassembler::new();
+        info!(
+            "Feeding channel {} from {}.",
+            config.channel, config.address
+        );
+        thread::Builder::new()
+            .name("external".to_string())
+            .spawn(move || loop {
+                let msg = match socket.recv_multipart(0) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        error!("External source {} receive error: {}.", address, e);
+                        continue;
+                    }
+                };
+                let msg = match reassembler.receive(msg) {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("External source {}: {}", address, e);
+                        continue;
+                    }
+                };
+                match Snapshot::deserialize(&mut Deserializer::new(&msg[..])) {
+                    Ok(snapshot) => {
+                        let arcs = snapshot
+                            .layers
+                            .iter()
+                            .flat_map(|layer| layer.iter().cloned())
+                            .collect();
+                        if let Ok(mut received) = received.lock() {
+                            received.arcs = Arc::new(arcs);
+                            received.at = Some(Instant::now());
+                        }
+                    }
+                    Err(e) => {
+                        error!("External source {} deserialization error: {}.", address, e);
+                    }
+                }
+            })?;
+        Ok(feed)
+    }
+
+    /// Return the current content, unless the source has gone quiet.
+    fn current(&self) -> Arc<Vec<ArcSegment>> {
+        match self.0.lock() {
+            Ok(received) => match received.at {
+                Some(t) if t.elapsed() < STALE_TIMEOUT => received.arcs.clone(),
+                _ => Arc::new(Vec::new()),
+            },
+            Err(_) => Arc::new(Vec::new()),
+        }
+    }
+
+    /// Return the number of arcs in the current content.
+    pub fn arc_count(&self) -> usize {
+        self.current().len()
+    }
+
+    /// Draw the current content at the provided level, or as a mask.
+    pub fn render(&self, level: UnipolarFloat, mask: bool) -> Vec<ArcSegment> {
+        self.current()
+            .iter()
+            .map(|arc| {
+                let mut arc = arc.clone();
+                if mask {
+                    arc.level = 1.0;
+                    arc.hue = 0.0;
+                    arc.sat = 0.0;
+                    arc.val = 0.0;
+                } else {
+                    arc.level *= level.val();
+                }
+                arc
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    fn receive(&self, arcs: Vec<ArcSegment>, at: Instant) {
+        let mut received = self.0.lock().unwrap();
+        received.arcs = Arc::new(arcs);
+        received.at = Some(at);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn arc() -> ArcSegment {
+        ArcSegment {
+            level: 0.5,
+            thickness: 0.1,
+            hue: 0.3,
+            sat: 1.0,
+            val: 1.0,
+            x: 0.0,
+            y: 0.0,
+            rad_x: 0.2,
+            rad_y: 0.2,
+            start: 0.0,
+            stop: 0.5,
+            rot_angle: 0.0,
+            palette: false,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let feed = ExternalFeed::default();
+        assert!(feed.render(UnipolarFloat::ONE, false).is_empty());
+
+        feed.receive(vec![arc(), arc()], Instant::now());
+        assert_eq!(2, feed.arc_count());
+        let rendered = feed.render(UnipolarFloat::new(0.5), false);
+        assert_eq!(0.25, rendered[0].level);
+        assert_eq!(0.3, rendered[0].hue);
+        let masked = feed.render(UnipolarFloat::new(0.5), true);
+        assert_eq!(1.0, masked[0].level);
+        assert_eq!(0.0, masked[0].val);
+
+        feed.receive(vec![arc()], Instant::now() - STALE_TIMEOUT);
+        assert_eq!(0, feed.arc_count());
+    }
+}
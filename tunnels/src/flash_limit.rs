@@ -0,0 +1,457 @@
+//! Limit how fast the show can flash, for the safety of photosensitive viewers.
+//!
+//! Rapid swings between bright and dark across a large part of the field of
+//! view can trigger seizures.  Guidelines such as WCAG count a flash as a rise
+//! in brightness followed by a fall, and call for no more than three flashes
+//! in any one second.  Unless disabled, the render thread measures the
+//! brightness of each video channel and of the LED fixtures every frame, and
+//! counts every rise at least as large as the configured contrast as a
+//! flash.  Once an output has flashed as often as allowed within the last
+//! second, a rise that would start another flash is dimmed until the rate
+//! allows it.  Falls are never held up, since a fall can only finish a flash
+//! that has already started.
+//!
+//! A video channel's brightness is measured on the composited image, sampled
+//! on a grid across the output's screen, so overlapping arcs, additive
+//! layers, and masks all count as they are seen.
+//!
+//! The limit can be overridden, such as for a strobe cue in front of an
+//! audience that has been warned.  The override must first be armed and then
+//! engaged within a few seconds, so that no single stray press can turn it
+//! on.  Panic releases it.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+use tunnels_lib::{sample::Sampler, BlendMode, LayerCollection, Timestamp};
+
+use crate::master_ui::EmitStateChange as EmitShowStateChange;
+
+/// Span of time over which flashes are counted.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Rows of the grid a video channel's brightness is sampled on.  Columns are
+/// spaced the same, across the width of the screen.
+const SAMPLE_ROWS: usize = 18;
+
+/// How long the override stays armed, waiting to be engaged.
+const ARM_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlashLimitConfig {
+    /// Limit flashes.  On unless turned off.
+    pub enabled: bool,
+    /// Most flashes allowed in any one second on each output.
+    pub max_flashes: u32,
+    /// Rise in brightness that counts as a flash, as a fraction of the whole
+    /// field lit at full level.
+    pub contrast: f64,
+}
+
+impl Default for FlashLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_flashes: 3,
+            contrast: 0.1,
+        }
+    }
+}
+
+impl FlashLimitConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.max_flashes == 0 {
+            bail!("Flash limit max_flashes must be at least 1.");
+        }
+        if !(self.contrast > 0.0 && self.contrast <= 1.0) {
+            bail!(
+                "Flash limit contrast is {}; it must be in (0, 1].",
+                self.contrast
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Return how brightly a frame lights a screen with the provided aspect
+/// ratio, from dark at 0 to the whole screen at full level at 1.  The frame
+/// is composited at points on a grid, and the brightness of each point is
+/// that of its brightest component.
+pub fn brightness(layers: &LayerCollection, blend: &[BlendMode], aspect_ratio: f64) -> f64 {
+    if layers.iter().all(|layer| layer.is_empty()) {
+        return 0.0;
+    }
+    let sampler = Sampler::new(aspect_ratio, true);
+    let cols = ((SAMPLE_ROWS as f64 * aspect_ratio).round() as usize).max(1);
+    let mut total = 0.0;
+    for row in 0..SAMPLE_ROWS {
+        for col in 0..cols {
+            let point = [
+                (col as f64 + 0.5) / cols as f64,
+                (row as f64 + 0.5) / SAMPLE_ROWS as f64,
+            ];
+            let color = sampler.sample(layers, blend, point);
+            total += color.iter().copied().fold(0.0, f64::max);
+        }
+    }
+    total / (SAMPLE_ROWS * cols) as f64
+}
+
+/// An output whose flashes are limited.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Output {
+    VideoChannel(usize),
+    /// Every fixture driven by the pixel map, taken together.
+    PixelMap,
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VideoChannel(chan) => write!(f, "Video channel {}", chan),
+            Self::PixelMap => write!(f, "The pixel map"),
+        }
+    }
+}
+
+/// Flash history of one output.
+#[derive(Default)]
+struct Channel {
+    /// Brightness at the most recent turning point.
+    extreme: f64,
+    /// True if brightness last swung up, so a fall is awaited.
+    rising: bool,
+    /// When each flash within the window started.
+    flashes: VecDeque<Timestamp>,
+    /// When the channel was last dimmed, if ever.
+    last_dimmed: Option<Timestamp>,
+}
+
+/// Dim each output as needed to keep it within the flash limit.
+pub struct FlashLimiter {
+    config: FlashLimitConfig,
+    channels: HashMap<Output, Channel>,
+}
+
+impl FlashLimiter {
+    pub fn new(config: FlashLimitConfig) -> Self {
+        Self {
+            config,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Limit a frame for a video channel shown on a screen with the provided
+    /// aspect ratio, dimming it in place if it would flash too often.  If
+    /// overridden, flashes are counted but not limited.
+    pub fn limit(
+        &mut self,
+        video_channel: usize,
+        layers: &mut LayerCollection,
+        blend: &[BlendMode],
+        aspect_ratio: f64,
+        time: Timestamp,
+        overridden: bool,
+    ) {
+        let scale = self.scale(
+            Output::VideoChannel(video_channel),
+            brightness(layers, blend, aspect_ratio),
+            time,
+            overridden,
+        );
+        if scale >= 1.0 {
+            return;
+        }
+        for (i, layer) in layers.iter_mut().enumerate() {
+            if blend.get(i) == Some(&BlendMode::Mask) {
+                continue;
+            }
+            for arc in Arc::make_mut(layer).iter_mut() {
+                arc.level *= scale;
+            }
+        }
+    }
+
+    /// Track the brightness of a frame of an output, and return how much to
+    /// scale its light by.
+    pub fn scale(
+        &mut self,
+        output: Output,
+        brightness: f64,
+        time: Timestamp,
+        overridden: bool,
+    ) -> f64 {
+        let contrast = self.config.contrast;
+        let max_flashes = self.config.max_flashes as usize;
+        let channel = self.channels.entry(output).or_default();
+        while matches!(channel.flashes.front(), Some(t) if time.duration_since(*t) >= WINDOW) {
+            channel.flashes.pop_front();
+        }
+
+        if channel.rising {
+            if brightness > channel.extreme {
+                channel.extreme = brightness;
+            } else if channel.extreme - brightness >= contrast {
+                channel.rising = false;
+                channel.extreme = brightness;
+            }
+            return 1.0;
+        }
+        if brightness - channel.extreme < contrast {
+            channel.extreme = channel.extreme.min(brightness);
+            return 1.0;
+        }
+        if overridden || channel.flashes.len() < max_flashes {
+            channel.flashes.push_back(time);
+            channel.rising = true;
+            channel.extreme = brightness;
+            return 1.0;
+        }
+        // Hold the channel well short of a full swing until the window moves
+        // on far enough to allow another flash.
+        if !matches!(channel.last_dimmed, Some(t) if time.duration_since(t) < WINDOW) {
+            warn!(
+                "{} is flashing more than {} times a second; dimming it.",
+                output, max_flashes
+            );
+        }
+        channel.last_dimmed = Some(time);
+        (channel.extreme + 0.5 * contrast) / brightness
+    }
+}
+
+/// The two step override of the flash limit.
+#[derive(Default)]
+pub struct FlashOverride {
+    armed_at: Option<Instant>,
+    engaged: bool,
+}
+
+impl FlashOverride {
+    pub fn engaged(&self) -> bool {
+        self.engaged
+    }
+
+    /// Emit the current value of all controllable state.
+    pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
+        emitter.emit_flash_limit_state_change(StateChange::Armed(self.armed_at.is_some()));
+        emitter.emit_flash_limit_state_change(StateChange::Engaged(self.engaged));
+    }
+
+    /// Disarm the override if it has waited too long to be engaged.
+    pub fn update_state<E: EmitStateChange>(&mut self, now: Instant, emitter: &mut E) {
+        if matches!(self.armed_at, Some(t) if now - t > ARM_TIMEOUT) {
+            self.armed_at = None;
+            emitter.emit_flash_limit_state_change(StateChange::Armed(false));
+        }
+    }
+
+    /// Handle a control message at the provided time.
+    /// Emit any state changes that have happened as a result of handling.
+    /// Return true if this engaged the override.
+    pub fn control<E: EmitStateChange>(
+        &mut self,
+        msg: ControlMessage,
+        now: Instant,
+        emitter: &mut E,
+    ) -> bool {
+        match msg {
+            ControlMessage::Arm => {
+                info!("Flash limit override armed.");
+                self.armed_at = Some(now);
+                emitter.emit_flash_limit_state_change(StateChange::Armed(true));
+                false
+            }
+            ControlMessage::Engage => {
+                let armed = matches!(self.armed_at, Some(t) if now - t <= ARM_TIMEOUT);
+                self.armed_at = None;
+                emitter.emit_flash_limit_state_change(StateChange::Armed(false));
+                if !armed {
+                    warn!("Flash limit override must be armed before it is engaged.");
+                    return false;
+                }
+                let newly_engaged = !self.engaged;
+                self.engaged = true;
+                emitter.emit_flash_limit_state_change(StateChange::Engaged(true));
+                newly_engaged
+            }
+            ControlMessage::Release => {
+                self.release(emitter);
+                false
+            }
+        }
+    }
+
+    /// Release and disarm the override.
+    pub fn release<E: EmitStateChange>(&mut self, emitter: &mut E) {
+        if self.engaged {
+            info!("Flash limit override released.");
+        }
+        self.armed_at = None;
+        self.engaged = false;
+        self.emit_state(emitter);
+    }
+}
+
+pub enum ControlMessage {
+    /// Allow the override to be engaged for the next few seconds.
+    Arm,
+    /// Engage the override, if it is armed.
+    Engage,
+    Release,
+}
+
+pub enum StateChange {
+    /// The override is armed, waiting to be engaged.
+    Armed(bool),
+    /// The override is engaged, so flashes are not limited.
+    Engaged(bool),
+}
+
+pub trait EmitStateChange {
+    fn emit_flash_limit_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_flash_limit_state_change(&mut self, sc: StateChange) {
+        use crate::show::StateChange as ShowStateChange;
+        self.emit(ShowStateChange::FlashLimit(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::master_ui::DummyEmitter;
+    use tunnels_lib::{assert_almost_eq, ArcSegment};
+
+    const ASPECT_RATIO: f64 = 16.0 / 9.0;
+
+    /// Return a layer covering the whole screen at the provided level.
+    fn fill(level: f64) -> Arc<Vec<ArcSegment>> {
+        let half = |start| ArcSegment {
+            level,
+            thickness: 4.0,
+            hue: 0.0,
+            sat: 1.0,
+            val: 1.0,
+            x: 0.0,
+            y: 0.0,
+            rad_x: 1.0,
+            rad_y: 1.0,
+            start,
+            stop: start + 0.5,
+            rot_angle: 0.0,
+            palette: false,
+        };
+        Arc::new(vec![half(0.0), half(0.5)])
+    }
+
+    /// Return a frame lighting the whole screen at the provided brightness.
+    fn frame(brightness: f64) -> LayerCollection {
+        vec![fill(brightness)]
+    }
+
+    fn limiter() -> FlashLimiter {
+        FlashLimiter::new(FlashLimitConfig::default())
+    }
+
+    /// Run frames at 60 fps alternating between dark and bright every
+    /// period frames, and return the brightness of each frame as limited.
+    fn strobe(limiter: &mut FlashLimiter, period: usize, overridden: bool) -> Vec<f64> {
+        (0..120)
+            .map(|n| {
+                let mut layers = frame(if (n / period) % 2 == 1 { 0.5 } else { 0.0 });
+                let time = Timestamp::from_micros(n as i64 * 1_000_000 / 60);
+                limiter.limit(0, &mut layers, &[], ASPECT_RATIO, time, overridden);
+                brightness(&layers, &[], ASPECT_RATIO)
+            })
+            .collect()
+    }
+
+    /// Count the full swings up in a run of frames.
+    fn flashes(frames: &[f64]) -> usize {
+        frames.windows(2).filter(|w| w[1] - w[0] >= 0.1).count()
+    }
+
+    #[test]
+    fn test_brightness() {
+        assert_almost_eq(0.25, brightness(&frame(0.25), &[], ASPECT_RATIO));
+        assert_almost_eq(0.0, brightness(&Vec::new(), &[], ASPECT_RATIO));
+        let layers = vec![fill(0.5), fill(0.5)];
+        // Overlapping layers are measured as they are seen.
+        assert_almost_eq(0.75, brightness(&layers, &[], ASPECT_RATIO));
+        let additive = [BlendMode::Alpha, BlendMode::Additive];
+        assert_almost_eq(1.0, brightness(&layers, &additive, ASPECT_RATIO));
+        let masked = [BlendMode::Alpha, BlendMode::Mask];
+        assert_almost_eq(0.25, brightness(&layers, &masked, ASPECT_RATIO));
+
+        // Only the part of the screen that is lit counts.
+        let mut quarter = (*fill(1.0)).clone();
+        quarter.truncate(1);
+        quarter[0].stop = 0.25;
+        let lit = brightness(&vec![Arc::new(quarter)], &[], ASPECT_RATIO);
+        assert!(lit > 0.15 && lit < 0.35, "{}", lit);
+    }
+
+    #[test]
+    fn test_slow_flashes_pass() {
+        // One flash a second passes untouched.
+        let frames = strobe(&mut limiter(), 30, false);
+        assert_eq!(2, flashes(&frames));
+        assert!(frames.iter().all(|b| *b == 0.0 || (b - 0.5).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_fast_flashes_limited() {
+        // Ten flashes a second are held to three in any one second.
+        let frames = strobe(&mut limiter(), 3, false);
+        for second in frames.windows(60) {
+            assert!(flashes(second) <= 3);
+        }
+        assert!(flashes(&frames) >= 5);
+        // Dimmed frames stay well short of a flash.
+        assert!(frames
+            .iter()
+            .all(|b| *b == 0.0 || *b <= 0.05 + 1e-9 || (b - 0.5).abs() < 1e-9));
+
+        let frames = strobe(&mut limiter(), 3, true);
+        assert_eq!(20, flashes(&frames));
+    }
+
+    #[test]
+    fn test_override() {
+        let mut o = FlashOverride::default();
+        let e = &mut DummyEmitter;
+        let now = Instant::now();
+        assert!(!o.control(ControlMessage::Engage, now, e));
+        assert!(!o.engaged());
+
+        o.control(ControlMessage::Arm, now, e);
+        assert!(!o.control(ControlMessage::Engage, now + ARM_TIMEOUT * 2, e));
+        assert!(!o.engaged());
+
+        o.control(ControlMessage::Arm, now, e);
+        assert!(o.control(ControlMessage::Engage, now + Duration::from_secs(1), e));
+        assert!(o.engaged());
+
+        o.control(ControlMessage::Release, now, e);
+        assert!(!o.engaged());
+
+        // The arm button goes dark once arming times out.
+        o.control(ControlMessage::Arm, now, e);
+        o.update_state(now + Duration::from_secs(1), e);
+        assert!(o.armed_at.is_some());
+        o.update_state(now + ARM_TIMEOUT * 2, e);
+        assert!(o.armed_at.is_none());
+    }
+}
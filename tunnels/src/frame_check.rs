@@ -0,0 +1,401 @@
+//! Sanity checks applied to every frame before it is published.
+//!
+//! A bad parameter can leave a beam rendering NaN or wildly out-of-range
+//! geometry, which clients draw as garbage or not at all.  Each rendered video
+//! channel is checked before sending; invalid frames are logged along with the
+//! most recent control input, and can optionally be replaced by the last good
+//! frame on that channel.
+//!
+//! Implausibly large jumps between consecutive frames are also logged.  Since
+//! a jump can be intentional, jumps are never held.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::{error, info, warn};
+use tunnels_lib::{ArcSegment, LayerCollection};
+
+use crate::{device::Device, midi::Event};
+
+/// Number of recent control events to report alongside a glitch.
+const CONTROL_HISTORY_LEN: usize = 16;
+
+/// Largest radius or offset we consider plausible, in units of the
+/// half-screen.
+const MAX_EXTENT: f64 = 10.0;
+
+/// Slop allowed on unit-range parameters to absorb rounding error.
+const UNIT_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FrameCheckConfig {
+    /// If true, publish the last good frame in place of an invalid one.
+    #[serde(default)]
+    pub hold_last_good: bool,
+    /// Change in position or radius between consecutive frames, in units of
+    /// the half-screen, above which a jump is logged.
+    #[serde(default = "default_max_jump")]
+    pub max_jump: f64,
+}
+
+fn default_max_jump() -> f64 {
+    0.5
+}
+
+impl Default for FrameCheckConfig {
+    fn default() -> Self {
+        Self {
+            hold_last_good: false,
+            max_jump: default_max_jump(),
+        }
+    }
+}
+
+impl FrameCheckConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !self.max_jump.is_finite() || self.max_jump <= 0.0 {
+            bail!(
+                "Frame check max_jump is {}; it must be positive.",
+                self.max_jump
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The most recent control input received by the show, shared with the
+/// render thread so it can be reported when a glitch occurs.
+#[derive(Clone)]
+pub struct ControlHistory(Arc<Mutex<VecDeque<(Instant, Device, Event)>>>);
+
+impl ControlHistory {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(VecDeque::with_capacity(
+            CONTROL_HISTORY_LEN,
+        ))))
+    }
+
+    /// Remember a control event.
+    pub fn record(&self, device: Device, event: Event) {
+        if let Ok(mut history) = self.0.lock() {
+            if history.len() == CONTROL_HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back((Instant::now(), device, event));
+        }
+    }
+
+    /// Describe the remembered events, oldest first, one per line.
+    fn describe(&self) -> String {
+        let history = match self.0.lock() {
+            Ok(history) => history,
+            Err(_) => return "unavailable".to_string(),
+        };
+        if history.is_empty() {
+            return "none".to_string();
+        }
+        let now = Instant::now();
+        history
+            .iter()
+            .map(|(time, device, event)| {
+                format!(
+                    "\n  {:.3}s ago: {:?} {:?}",
+                    (now - *time).as_secs_f64(),
+                    device,
+                    event
+                )
+            })
+            .collect()
+    }
+}
+
+/// A problem found in a rendered frame.
+#[derive(Debug, PartialEq)]
+enum Glitch {
+    /// A parameter is NaN, infinite, or outside its valid range.
+    Invalid {
+        layer: usize,
+        segment: usize,
+        field: &'static str,
+        value: f64,
+    },
+    /// A parameter changed implausibly far since the previous frame.
+    Jump {
+        layer: usize,
+        segment: usize,
+        field: &'static str,
+        from: f64,
+        to: f64,
+    },
+}
+
+impl fmt::Display for Glitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid {
+                layer,
+                segment,
+                field,
+                value,
+            } => write!(
+                f,
+                "mixer channel {} segment {} has invalid {} {}",
+                layer, segment, field, value
+            ),
+            Self::Jump {
+                layer,
+                segment,
+                field,
+                from,
+                to,
+            } => write!(
+                f,
+                "mixer channel {} segment {} {} jumped from {} to {}",
+                layer, segment, field, from, to
+            ),
+        }
+    }
+}
+
+/// Return the first invalid parameter of an arc, if any.
+fn invalid_field(arc: &ArcSegment) -> Option<(&'static str, f64)> {
+    let unit = |v: f64| (-UNIT_TOLERANCE..=1.0 + UNIT_TOLERANCE).contains(&v);
+    let extent = |v: f64| v.abs() <= MAX_EXTENT;
+    let checks: [(&'static str, f64, bool); 12] = [
+        ("level", arc.level, unit(arc.level)),
+        ("thickness", arc.thickness, arc.thickness >= 0.0),
+        ("hue", arc.hue, arc.hue.is_finite()),
+        ("sat", arc.sat, unit(arc.sat)),
+        ("val", arc.val, unit(arc.val)),
+        ("x", arc.x, extent(arc.x)),
+        ("y", arc.y, extent(arc.y)),
+        ("rad_x", arc.rad_x, extent(arc.rad_x)),
+        ("rad_y", arc.rad_y, extent(arc.rad_y)),
+        ("start", arc.start, arc.start.is_finite()),
+        ("stop", arc.stop, arc.stop.is_finite()),
+        ("rot_angle", arc.rot_angle, arc.rot_angle.is_finite()),
+    ];
+    // NaN fails every comparison above, and infinity fails every range.
+    checks
+        .iter()
+        .find(|(_, value, ok)| !ok || !value.is_finite())
+        .map(|(field, value, _)| (*field, *value))
+}
+
+/// Return the first implausible jump between two renderings of an arc, if any.
+fn jump(prev: &ArcSegment, arc: &ArcSegment, max_jump: f64) -> Option<(&'static str, f64, f64)> {
+    [
+        ("x", prev.x, arc.x),
+        ("y", prev.y, arc.y),
+        ("rad_x", prev.rad_x, arc.rad_x),
+        ("rad_y", prev.rad_y, arc.rad_y),
+    ]
+    .iter()
+    .find(|(_, from, to)| (to - from).abs() > max_jump)
+    .cloned()
+}
+
+/// Check every segment of a frame, comparing against the previous frame if
+/// provided.  Invalid parameters take precedence over jumps.
+fn check(
+    layers: &LayerCollection,
+    prev: Option<&LayerCollection>,
+    max_jump: f64,
+) -> Option<Glitch> {
+    for (layer, segments) in layers.iter().enumerate() {
+        for (segment, arc) in segments.iter().enumerate() {
+            if let Some((field, value)) = invalid_field(arc) {
+                return Some(Glitch::Invalid {
+                    layer,
+                    segment,
+                    field,
+                    value,
+                });
+            }
+        }
+    }
+    let prev = prev?;
+    for (layer, (segments, prev_segments)) in layers.iter().zip(prev.iter()).enumerate() {
+        // A change in segment count is a change of look, not a glitch.
+        if segments.len() != prev_segments.len() {
+            continue;
+        }
+        for (segment, (arc, prev_arc)) in segments.iter().zip(prev_segments.iter()).enumerate() {
+            if let Some((field, from, to)) = jump(prev_arc, arc, max_jump) {
+                return Some(Glitch::Jump {
+                    layer,
+                    segment,
+                    field,
+                    from,
+                    to,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Check each frame as it is rendered, logging glitches and optionally holding
+/// the last good frame.
+pub struct FrameChecker {
+    config: FrameCheckConfig,
+    history: ControlHistory,
+    /// Last frame published on each video channel, and whether it was good.
+    last: Vec<Option<(LayerCollection, bool)>>,
+    /// Number of consecutive invalid frames on each video channel.
+    invalid_count: Vec<u64>,
+}
+
+impl FrameChecker {
+    pub fn new(config: FrameCheckConfig, history: ControlHistory) -> Self {
+        Self {
+            config,
+            history,
+            last: Vec::new(),
+            invalid_count: Vec::new(),
+        }
+    }
+
+    /// Check a rendered frame for a video channel, and return the layers that
+    /// should be published.
+    pub fn check(
+        &mut self,
+        frame_number: u64,
+        video_channel: usize,
+        layers: LayerCollection,
+    ) -> LayerCollection {
+        if self.last.len() <= video_channel {
+            self.last.resize(video_channel + 1, None);
+            self.invalid_count.resize(video_channel + 1, 0);
+        }
+        let last_good = match &self.last[video_channel] {
+            Some((last, true)) => Some(last),
+            _ => None,
+        };
+        let glitch = check(&layers, last_good, self.config.max_jump);
+
+        if let Some(Glitch::Invalid { .. }) = glitch {
+            let count = &mut self.invalid_count[video_channel];
+            *count += 1;
+            // Only log the start of a run of bad frames.
+            if *count == 1 {
+                error!(
+                    "Frame {} video channel {}: {}.{}  Recent control input: {}",
+                    frame_number,
+                    video_channel,
+                    glitch.as_ref().unwrap(),
+                    if self.config.hold_last_good {
+                        "  Holding the last good frame."
+                    } else {
+                        ""
+                    },
+                    self.history.describe(),
+                );
+            }
+            if self.config.hold_last_good {
+                if let Some((last, _)) = &self.last[video_channel] {
+                    return last.clone();
+                }
+            }
+            self.last[video_channel] = Some((layers.clone(), false));
+            return layers;
+        }
+
+        if let Some(glitch) = glitch {
+            warn!(
+                "Frame {} video channel {}: {}.  Recent control input: {}",
+                frame_number,
+                video_channel,
+                glitch,
+                self.history.describe(),
+            );
+        }
+        let count = &mut self.invalid_count[video_channel];
+        if *count > 0 {
+            info!(
+                "Video channel {} recovered after {} invalid frames.",
+                video_channel, count
+            );
+            *count = 0;
+        }
+        self.last[video_channel] = Some((layers.clone(), true));
+        layers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn arc(x: f64) -> ArcSegment {
+        ArcSegment {
+            level: 1.0,
+            thickness: 0.1,
+            hue: 0.0,
+            sat: 1.0,
+            val: 1.0,
+            x,
+            y: 0.0,
+            rad_x: 0.5,
+            rad_y: 0.5,
+            start: 0.0,
+            stop: 1.0,
+            rot_angle: 0.0,
+            palette: false,
+        }
+    }
+
+    fn frame(x: f64) -> LayerCollection {
+        vec![Arc::new(vec![arc(0.0), arc(x)])]
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(None, check(&frame(0.1), Some(&frame(0.0)), 0.5));
+        assert_eq!(
+            Some(Glitch::Invalid {
+                layer: 0,
+                segment: 1,
+                field: "x",
+                value: f64::INFINITY
+            }),
+            check(&frame(f64::INFINITY), None, 0.5)
+        );
+        assert!(matches!(
+            check(&frame(f64::NAN), None, 0.5),
+            Some(Glitch::Invalid { field: "x", .. })
+        ));
+        assert_eq!(
+            Some(Glitch::Jump {
+                layer: 0,
+                segment: 1,
+                field: "x",
+                from: 0.0,
+                to: 0.8
+            }),
+            check(&frame(0.8), Some(&frame(0.0)), 0.5)
+        );
+    }
+
+    #[test]
+    fn test_hold_last_good() {
+        let config = FrameCheckConfig {
+            hold_last_good: true,
+            ..Default::default()
+        };
+        let mut checker = FrameChecker::new(config, ControlHistory::new());
+        assert_eq!(frame(0.0), checker.check(0, 0, frame(0.0)));
+        assert_eq!(frame(0.0), checker.check(1, 0, frame(f64::NAN)));
+        // Jumps are published, and become the new reference.
+        assert_eq!(frame(0.8), checker.check(2, 0, frame(0.8)));
+        assert_eq!(frame(0.8), checker.check(3, 0, frame(f64::NAN)));
+    }
+}
@@ -0,0 +1,123 @@
+//! Absolute deadlines for the frames of the show loop.
+//!
+//! Every frame is due at a fixed multiple of the frame interval after the show
+//! started, rather than one interval after the previous frame, so a late frame
+//! never pushes back the frames that follow.  Each frame's timestamp is its
+//! scheduled time, measured from the same start as the timesync server, so
+//! the two clocks never drift apart.
+
+use std::time::{Duration, Instant};
+use tunnels_lib::Timestamp;
+
+pub struct FrameClock {
+    start: Instant,
+    interval: Duration,
+    /// The number of the next frame to run.
+    next: u64,
+}
+
+/// A frame whose deadline has arrived.
+#[derive(Debug, PartialEq)]
+pub struct DueFrame {
+    pub number: u64,
+    /// Scheduled time of this frame since the show started.
+    pub timestamp: Timestamp,
+    /// How long after its deadline this frame started.
+    pub late: Duration,
+    /// How many overdue frames were skipped to reach this one.
+    pub skipped: u64,
+    /// Scheduled time since the last frame that ran, covering any skipped
+    /// frames.
+    pub delta_t: Duration,
+}
+
+impl FrameClock {
+    pub fn new(start: Instant, interval: Duration) -> Self {
+        Self {
+            start,
+            interval,
+            next: 0,
+        }
+    }
+
+    /// Time since the start that the frame with this number is due.
+    /// Frame 0 is due one interval after the start.
+    fn offset(&self, frame: u64) -> Duration {
+        let nanos = self.interval.as_nanos() * (frame as u128 + 1);
+        Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        )
+    }
+
+    /// When the next frame is due.
+    pub fn deadline(&self) -> Instant {
+        self.start + self.offset(self.next)
+    }
+
+    /// Time remaining until the next frame is due, or zero if it is overdue.
+    pub fn until_deadline(&self, now: Instant) -> Duration {
+        self.deadline().saturating_duration_since(now)
+    }
+
+    /// If the next frame is due, advance past it and return it.
+    /// If several frames are overdue, as after a stall, only the most recent
+    /// is returned and the rest are skipped, so the show jumps straight to
+    /// the present rather than running every missed frame back to back.
+    pub fn poll(&mut self, now: Instant) -> Option<DueFrame> {
+        now.checked_duration_since(self.deadline())?;
+        // Frame n is due n + 1 intervals after the start.
+        let number = ((now - self.start).as_nanos() / self.interval.as_nanos()) as u64 - 1;
+        let offset = self.offset(number);
+        let frame = DueFrame {
+            number,
+            timestamp: Timestamp::from_duration(offset),
+            late: now - (self.start + offset),
+            skipped: number - self.next,
+            delta_t: offset - self.offset(self.next) + self.interval,
+        };
+        self.next = number + 1;
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_poll() {
+        let start = Instant::now();
+        let interval = Duration::from_nanos(16_666_667);
+        let mut clock = FrameClock::new(start, interval);
+
+        assert!(clock.poll(start).is_none());
+        assert_eq!(interval, clock.until_deadline(start));
+
+        let frame = clock.poll(start + interval).unwrap();
+        assert_eq!(0, frame.number);
+        assert_eq!(Timestamp(16_666), frame.timestamp);
+        assert_eq!(Duration::ZERO, frame.late);
+        assert_eq!(0, frame.skipped);
+        assert_eq!(interval, frame.delta_t);
+
+        // Overdue frames are skipped in favor of the most recent one.
+        let now = start + Duration::from_millis(60);
+        let frame = clock.poll(now).unwrap();
+        assert_eq!(2, frame.number);
+        assert_eq!(1, frame.skipped);
+        assert_eq!(interval * 2, frame.delta_t);
+        assert_eq!(Duration::from_nanos(9_999_999), frame.late);
+        assert!(clock.poll(now).is_none());
+        assert_eq!(Duration::from_nanos(6_666_668), clock.until_deadline(now));
+
+        // A long stall produces a single frame, and timestamps don't
+        // accumulate rounding error.
+        let hour = start + Duration::from_secs(3600);
+        let frame = clock.poll(hour).unwrap();
+        assert!(clock.poll(hour).is_none());
+        assert_eq!(215_998, frame.number);
+        assert_eq!(215_995, frame.skipped);
+        assert_eq!(Timestamp(3_599_983_405), frame.timestamp);
+    }
+}
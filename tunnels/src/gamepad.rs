@@ -0,0 +1,203 @@
+//! Game controller input.
+//!
+//! Gamepad events are translated into midi events from the Gamepad device and
+//! injected into the midi input stream, so they are mapped to control
+//! messages just like any other input.  Buttons become note on and note off,
+//! sticks become control changes, and the two analog triggers are combined
+//! into a single control change, the right less the left.  Controls from
+//! every connected gamepad are merged.
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+use std::{
+    collections::HashMap,
+    error::Error,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+use tunnels_lib::queue::DropSender;
+
+use crate::{
+    device::Device,
+    midi::{cc, event, note_off, note_on, Event, Mapping},
+};
+
+/// How long to wait between polls when no gamepad events are pending.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+pub const LEFT_STICK_X: Mapping = cc(0, 0);
+pub const LEFT_STICK_Y: Mapping = cc(0, 1);
+pub const RIGHT_STICK_X: Mapping = cc(0, 2);
+pub const RIGHT_STICK_Y: Mapping = cc(0, 3);
+/// The right trigger less the left, centered on 64 like the sticks.
+pub const TRIGGERS: Mapping = cc(0, 4);
+
+/// Return the note number used for a gamepad button.
+/// The analog triggers are reported as control changes instead.
+pub fn button_note(button: Button) -> Option<u8> {
+    use Button::*;
+    Some(match button {
+        South => 0,
+        East => 1,
+        North => 2,
+        West => 3,
+        LeftTrigger => 4,
+        RightTrigger => 5,
+        Select => 6,
+        Start => 7,
+        Mode => 8,
+        LeftThumb => 9,
+        RightThumb => 10,
+        DPadUp => 11,
+        DPadDown => 12,
+        DPadLeft => 13,
+        DPadRight => 14,
+        _ => return None,
+    })
+}
+
+/// Scale a value in [-1, 1] into a midi value centered on 64, matching
+/// bipolar midi controls.
+fn bipolar_to_midi(v: f32) -> u8 {
+    let scaled = if v < 0.0 { v * 64. } else { v * 63. };
+    (64. + scaled).round().clamp(0., 127.) as u8
+}
+
+/// Translates gamepad events into midi events, keeping track of both
+/// triggers to combine them.
+#[derive(Default)]
+struct Translator {
+    left_trigger: f32,
+    right_trigger: f32,
+}
+
+impl Translator {
+    /// Translate a gamepad event into a midi event, if it is one we use.
+    fn translate(&mut self, ev: EventType) -> Option<Event> {
+        match ev {
+            EventType::ButtonPressed(button, _) => {
+                button_note(button).map(|n| event(note_on(0, n), 127))
+            }
+            EventType::ButtonReleased(button, _) => {
+                button_note(button).map(|n| event(note_off(0, n), 0))
+            }
+            EventType::ButtonChanged(button, v, _) => self.trigger(button, v),
+            EventType::AxisChanged(axis, v, _) => stick(axis, v),
+            _ => None,
+        }
+    }
+
+    /// Note the position of an analog trigger, and return the combined
+    /// position of both.
+    fn trigger(&mut self, button: Button, v: f32) -> Option<Event> {
+        let v = v.clamp(0.0, 1.0);
+        match button {
+            Button::LeftTrigger2 => self.left_trigger = v,
+            Button::RightTrigger2 => self.right_trigger = v,
+            _ => return None,
+        }
+        Some(event(
+            TRIGGERS,
+            bipolar_to_midi(self.right_trigger - self.left_trigger),
+        ))
+    }
+}
+
+fn stick(axis: Axis, v: f32) -> Option<Event> {
+    let mapping = match axis {
+        Axis::LeftStickX => LEFT_STICK_X,
+        Axis::LeftStickY => LEFT_STICK_Y,
+        Axis::RightStickX => RIGHT_STICK_X,
+        Axis::RightStickY => RIGHT_STICK_Y,
+        _ => return None,
+    };
+    Some(event(mapping, bipolar_to_midi(v)))
+}
+
+/// Poll for gamepad input in a background thread.
+/// Gamepad events are sent on the provided channel.
+pub fn start_gamepad_service(
+    sender: DropSender<(Instant, Device, Event)>,
+) -> Result<(), Box<dyn Error>> {
+    thread::Builder::new()
+        .name("gamepad".to_string())
+        .spawn(move || {
+            let mut gilrs = match Gilrs::new() {
+                Ok(g) => g,
+                Err(e) => {
+                    error!("Unable to initialize gamepad input: {}.", e);
+                    return;
+                }
+            };
+            for (_, gamepad) in gilrs.gamepads() {
+                info!("Found gamepad {}.", gamepad.name());
+            }
+            // Only forward control changes whose midi value actually changed,
+            // to avoid flooding the show with stick noise.
+            let mut last_cc = HashMap::new();
+            let mut translator = Translator::default();
+            loop {
+                let ev = match gilrs.next_event() {
+                    Some(ev) => ev,
+                    None => {
+                        thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                };
+                match ev.event {
+                    EventType::Connected => {
+                        info!("Gamepad {} connected.", gilrs.gamepad(ev.id).name())
+                    }
+                    EventType::Disconnected => info!("Gamepad disconnected."),
+                    _ => (),
+                }
+                if let Some(e) = translator.translate(ev.event) {
+                    if let EventType::AxisChanged(..) | EventType::ButtonChanged(..) = ev.event {
+                        if last_cc.insert(e.mapping, e.value) == Some(e.value) {
+                            continue;
+                        }
+                    }
+                    if sender.send((Instant::now(), Device::Gamepad, e)).is_err() {
+                        // The show has shut down.
+                        return;
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn trigger(t: &mut Translator, button: Button, v: f32) -> u8 {
+        let e = t.trigger(button, v).unwrap();
+        assert_eq!(TRIGGERS, e.mapping);
+        e.value
+    }
+
+    #[test]
+    fn test_triggers_combine() {
+        let mut t = Translator::default();
+        assert_eq!(127, trigger(&mut t, Button::RightTrigger2, 1.0));
+        // Both held cancel out.
+        assert_eq!(64, trigger(&mut t, Button::LeftTrigger2, 1.0));
+        // Releasing one leaves the other in effect.
+        assert_eq!(0, trigger(&mut t, Button::RightTrigger2, 0.0));
+        assert_eq!(32, trigger(&mut t, Button::LeftTrigger2, 0.5));
+        assert_eq!(64, trigger(&mut t, Button::LeftTrigger2, 0.0));
+        assert!(t.trigger(Button::DPadUp, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_sticks() {
+        let value = |v| stick(Axis::LeftStickX, v).unwrap().value;
+        assert_eq!((0, 64, 127), (value(-1.0), value(0.0), value(1.0)));
+        assert_eq!(
+            RIGHT_STICK_Y,
+            stick(Axis::RightStickY, 0.0).unwrap().mapping
+        );
+        assert!(stick(Axis::LeftZ, 1.0).is_none());
+    }
+}
@@ -0,0 +1,226 @@
+//! Custom beam types, provided as plugins.
+//!
+//! A generator is a procedural beam defined outside of the mixer: anything
+//! that can advance with time and draw itself as arcs.  Each kind of
+//! generator is registered under a unique name, after which it can be
+//! selected for the current channel from the beam type controls, stored in
+//! the beam store, and saved with the show, just like a tunnel.
+//!
+//! To add a generator, implement Generator in its own module and register it
+//! with `register("Name", || Box::new(MyGenerator::new()))` before the show
+//! is created, since the beam type controls are mapped from the generators
+//! registered by then.  A generator that has state worth keeping should
+//! implement `save` and `restore`; otherwise it is recreated fresh when a
+//! show is loaded.
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use simple_error::{bail, SimpleError};
+use std::{convert::TryFrom, error::Error, fmt, sync::RwLock, time::Duration};
+use tunnels_lib::{number::UnipolarFloat, ArcSegment};
+
+use crate::{clock_bank::ClockBank, random::ShowRng, rings::Rings};
+
+pub trait Generator: Send + Sync {
+    /// Advance the generator by delta_t.
+    /// Draw any random choices from rng, so that the generator plays out the
+    /// same way each time the show is loaded.
+    fn update_state(&mut self, delta_t: Duration, external_clocks: &ClockBank, rng: &mut ShowRng);
+
+    /// Draw the generator at the provided level.
+    fn render(&self, level: UnipolarFloat, external_clocks: &ClockBank) -> Vec<ArcSegment>;
+
+    /// Return the number of arcs this generator currently renders.
+    fn arc_count(&self) -> usize;
+
+    /// Return a copy of this generator.
+    fn box_clone(&self) -> Box<dyn Generator>;
+
+    /// Return the state to save with the show.
+    fn save(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Restore state previously returned by save.
+    fn restore(&mut self, _state: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+type Constructor = fn() -> Box<dyn Generator>;
+
+lazy_static! {
+    /// Every registered kind of generator, in the order they appear on the
+    /// beam type controls, starting with those built in.
+    static ref REGISTRY: RwLock<Vec<(&'static str, Constructor)>> =
+        RwLock::new(vec![("Rings", || Box::new(Rings::new()))]);
+}
+
+/// Make a kind of generator available under the provided name, which must
+/// not already be taken by another beam type.
+#[allow(dead_code)]
+pub fn register(name: &'static str, create: Constructor) -> Result<(), Box<dyn Error>> {
+    let mut registry = REGISTRY.write().map_err(|e| e.to_string())?;
+    if name == "Tunnel" || registry.iter().any(|(n, _)| *n == name) {
+        bail!("A beam type named {} is already registered.", name);
+    }
+    registry.push((name, create));
+    Ok(())
+}
+
+/// Return the names of every registered kind of generator, in the order they
+/// were registered.
+pub fn generator_names() -> Vec<&'static str> {
+    match REGISTRY.read() {
+        Ok(registry) => registry.iter().map(|(name, _)| *name).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// A beam drawn by a generator, along with the kind of generator it is.
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "SavedGenerator", into = "SavedGenerator")]
+pub struct GeneratorBeam {
+    kind: String,
+    generator: Box<dyn Generator>,
+}
+
+impl GeneratorBeam {
+    /// Create a new generator of the named kind, if it is registered.
+    pub fn new(kind: &str) -> Option<Self> {
+        let registry = REGISTRY.read().ok()?;
+        let (kind, create) = registry.iter().find(|(name, _)| *name == kind)?;
+        Some(Self {
+            kind: kind.to_string(),
+            generator: create(),
+        })
+    }
+
+    /// Return the name this kind of generator is registered under.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn update_state(
+        &mut self,
+        delta_t: Duration,
+        external_clocks: &ClockBank,
+        rng: &mut ShowRng,
+    ) {
+        self.generator.update_state(delta_t, external_clocks, rng);
+    }
+
+    /// Draw the generator.  As a mask, the generator is drawn in black at
+    /// full level.
+    pub fn render(
+        &self,
+        level: UnipolarFloat,
+        mask: bool,
+        external_clocks: &ClockBank,
+    ) -> Vec<ArcSegment> {
+        let level = if mask { UnipolarFloat::ONE } else { level };
+        let mut arcs = self.generator.render(level, external_clocks);
+        if mask {
+            for arc in &mut arcs {
+                arc.hue = 0.0;
+                arc.sat = 0.0;
+                arc.val = 0.0;
+            }
+        }
+        arcs
+    }
+
+    pub fn arc_count(&self) -> usize {
+        self.generator.arc_count()
+    }
+}
+
+impl Clone for GeneratorBeam {
+    fn clone(&self) -> Self {
+        Self {
+            kind: self.kind.clone(),
+            generator: self.generator.box_clone(),
+        }
+    }
+}
+
+impl fmt::Debug for GeneratorBeam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GeneratorBeam({})", self.kind)
+    }
+}
+
+/// How a generator beam is saved: its kind, and its state as JSON.
+#[derive(Serialize, Deserialize)]
+struct SavedGenerator {
+    kind: String,
+    state: String,
+}
+
+impl From<GeneratorBeam> for SavedGenerator {
+    fn from(beam: GeneratorBeam) -> Self {
+        Self {
+            kind: beam.kind,
+            state: beam.generator.save().to_string(),
+        }
+    }
+}
+
+impl TryFrom<SavedGenerator> for GeneratorBeam {
+    type Error = SimpleError;
+
+    fn try_from(saved: SavedGenerator) -> Result<Self, Self::Error> {
+        let mut beam = match Self::new(&saved.kind) {
+            Some(beam) => beam,
+            None => bail!("Unknown beam type {}.", saved.kind),
+        };
+        let state = serde_json::from_str(&saved.state).map_err(SimpleError::from)?;
+        if let Err(e) = beam.generator.restore(state) {
+            bail!("Unable to restore {}: {}", saved.kind, e);
+        }
+        Ok(beam)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{beam::Beam, random::stream};
+    use rmp_serde::{Deserializer, Serializer};
+
+    #[test]
+    fn test_register() {
+        assert!(register("Rings", || Box::new(Rings::new())).is_err());
+        assert!(register("Tunnel", || Box::new(Rings::new())).is_err());
+
+        assert!(GeneratorBeam::new("MoreRings").is_none());
+        register("MoreRings", || Box::new(Rings::new())).unwrap();
+        assert_eq!(Some(&"MoreRings"), generator_names().last());
+        assert_eq!("MoreRings", GeneratorBeam::new("MoreRings").unwrap().kind());
+        assert!(register("MoreRings", || Box::new(Rings::new())).is_err());
+    }
+
+    #[test]
+    fn test_generator_beam() {
+        assert!(GeneratorBeam::new("Nonexistent").is_none());
+
+        let clocks = ClockBank::new();
+        let mut beam = GeneratorBeam::new("Rings").unwrap();
+        beam.update_state(Duration::from_millis(100), &clocks, &mut stream(0, 0));
+        let masked = beam.render(UnipolarFloat::new(0.5), true, &clocks);
+        assert_eq!(beam.arc_count(), masked.len());
+        assert!(masked.iter().all(|arc| arc.val == 0.0));
+
+        let mut buf = Vec::new();
+        Beam::Generator(beam.clone())
+            .serialize(&mut Serializer::new(&mut buf))
+            .unwrap();
+        match Beam::deserialize(&mut Deserializer::new(&buf[..])).unwrap() {
+            Beam::Generator(loaded) => assert_eq!(
+                beam.render(UnipolarFloat::ONE, false, &clocks),
+                loaded.render(UnipolarFloat::ONE, false, &clocks)
+            ),
+            other => panic!("Loaded {:?}.", other),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Confirmation of destructive beam store grid presses.
+//!
+//! Saving over an occupied slot or deleting one can't be undone, and a pad is
+//! easily mis-hit in the dark.  The show can be configured to only act on
+//! such a press once it is confirmed, either by pressing the same slot again
+//! quickly or by holding it down.  The slot flashes while it awaits
+//! confirmation.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{error::Error, time::Duration};
+
+use crate::beam_store::BeamStoreAddr;
+
+/// How a destructive grid press is confirmed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+pub enum GridConfirm {
+    /// Act on the first press.
+    #[default]
+    Immediate,
+    /// Act on a second press of the same slot within this many seconds.
+    DoublePress(f64),
+    /// Act once the slot has been held down for this many seconds.
+    Hold(f64),
+}
+
+impl GridConfirm {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        match *self {
+            Self::Immediate => (),
+            Self::DoublePress(secs) | Self::Hold(secs) => {
+                if !secs.is_finite() || secs <= 0.0 {
+                    bail!("Grid confirmation time is {}; it must be positive.", secs);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a press awaiting confirmation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Resolution {
+    Confirmed(BeamStoreAddr),
+    Cancelled(BeamStoreAddr),
+}
+
+/// Track the press awaiting confirmation, if any.
+#[derive(Default)]
+pub struct Confirmation {
+    mode: GridConfirm,
+    /// The slot awaiting confirmation, and how long it has been waiting.
+    pending: Option<(BeamStoreAddr, Duration)>,
+}
+
+impl Confirmation {
+    pub fn new(mode: GridConfirm) -> Self {
+        Self {
+            mode,
+            pending: None,
+        }
+    }
+
+    pub fn pending(&self) -> Option<BeamStoreAddr> {
+        self.pending.map(|(addr, _)| addr)
+    }
+
+    /// Handle a destructive press of a slot.
+    /// Return true if the press should be acted on now.  Otherwise, it
+    /// replaces any other press awaiting confirmation.
+    pub fn press(&mut self, addr: BeamStoreAddr) -> bool {
+        match self.mode {
+            GridConfirm::Immediate => true,
+            GridConfirm::DoublePress(_) if self.pending() == Some(addr) => {
+                self.pending = None;
+                true
+            }
+            GridConfirm::DoublePress(_) | GridConfirm::Hold(_) => {
+                self.pending = Some((addr, Duration::ZERO));
+                false
+            }
+        }
+    }
+
+    /// Handle the release of a slot.
+    /// Letting go of a slot before it has been held long enough cancels it.
+    pub fn release(&mut self, addr: BeamStoreAddr) -> Option<Resolution> {
+        match self.mode {
+            GridConfirm::Hold(_) if self.pending() == Some(addr) => self.cancel(),
+            _ => None,
+        }
+    }
+
+    /// Forget the press awaiting confirmation, if any.
+    pub fn cancel(&mut self) -> Option<Resolution> {
+        self.pending
+            .take()
+            .map(|(addr, _)| Resolution::Cancelled(addr))
+    }
+
+    /// Age the press awaiting confirmation.
+    /// Return a resolution if it was held long enough, or timed out waiting
+    /// for a second press.
+    pub fn update_state(&mut self, delta_t: Duration) -> Option<Resolution> {
+        let (addr, age) = self.pending.as_mut()?;
+        *age += delta_t;
+        let addr = *addr;
+        match self.mode {
+            GridConfirm::DoublePress(timeout) if age.as_secs_f64() > timeout => self.cancel(),
+            GridConfirm::Hold(hold) if age.as_secs_f64() >= hold => {
+                self.pending = None;
+                Some(Resolution::Confirmed(addr))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const A: BeamStoreAddr = BeamStoreAddr { row: 0, col: 0 };
+    const B: BeamStoreAddr = BeamStoreAddr { row: 1, col: 0 };
+
+    #[test]
+    fn test_double_press() {
+        let mut confirm = Confirmation::new(GridConfirm::DoublePress(0.5));
+        let step = Duration::from_millis(200);
+        assert!(!confirm.press(A));
+        assert_eq!(None, confirm.update_state(step));
+        // Pressing another slot starts over.
+        assert!(!confirm.press(B));
+        assert_eq!(Some(B), confirm.pending());
+        assert!(confirm.press(B));
+        assert_eq!(None, confirm.pending());
+
+        assert!(!confirm.press(A));
+        assert_eq!(None, confirm.release(A));
+        assert_eq!(None, confirm.update_state(step));
+        assert_eq!(None, confirm.update_state(step));
+        assert_eq!(Some(Resolution::Cancelled(A)), confirm.update_state(step));
+        assert!(!confirm.press(A));
+    }
+
+    #[test]
+    fn test_hold() {
+        let mut confirm = Confirmation::new(GridConfirm::Hold(0.5));
+        let step = Duration::from_millis(200);
+        assert!(!confirm.press(A));
+        assert_eq!(None, confirm.update_state(step));
+        assert_eq!(Some(Resolution::Cancelled(A)), confirm.release(A));
+        assert_eq!(None, confirm.update_state(step));
+
+        assert!(!confirm.press(A));
+        // Releasing some other slot doesn't matter.
+        assert_eq!(None, confirm.release(B));
+        assert_eq!(None, confirm.update_state(step));
+        assert_eq!(None, confirm.update_state(step));
+        assert_eq!(Some(Resolution::Confirmed(A)), confirm.update_state(step));
+        assert_eq!(None, confirm.release(A));
+
+        assert!(Confirmation::new(GridConfirm::Immediate).press(A));
+    }
+}
@@ -0,0 +1,230 @@
+//! Writing a starter rig for new users to edit.
+//!
+//! `tunnels init` writes three files into a directory: a show config with the
+//! common settings filled in for the chosen control surface, an empty saved
+//! show sized for it, and a script that runs the show with the control
+//! surface connected.  MIDI ports are matched by name against the ports that
+//! are available at the time; a port that isn't found is left for the user
+//! to fill in.
+
+use simple_error::bail;
+use std::{
+    error::Error,
+    fs::{self, create_dir_all},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    clock_bank::ClockBank, device::Device, master_ui::MasterUI, mixer::Mixer, show::ShowState,
+    SHOW_DIR,
+};
+
+/// Name of the show config written into the rig directory.
+const CONFIG_FILE: &str = "config.yaml";
+
+/// Name of the run script written into the rig directory.
+const RUN_FILE: &str = "run.sh";
+
+/// Write a starter rig into dir for the provided control surface, if any.
+/// Existing files are only replaced if force is set.
+/// Return the paths of the files written.
+pub fn write_starter(
+    dir: &Path,
+    device: Option<Device>,
+    show_name: &str,
+    ports: &(Vec<String>, Vec<String>),
+    force: bool,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if show_name.is_empty() || show_name.contains(['/', '\\']) {
+        bail!("\"{}\" can't be used as the name of a show.", show_name);
+    }
+    let config_path = dir.join(CONFIG_FILE);
+    let show_path = dir.join(SHOW_DIR).join(show_name);
+    let run_path = dir.join(RUN_FILE);
+    let paths = vec![config_path, show_path, run_path];
+    if !force {
+        if let Some(existing) = paths.iter().find(|p| p.exists()) {
+            bail!(
+                "{} already exists; pass --force to replace it.",
+                existing.display()
+            );
+        }
+    }
+
+    create_dir_all(dir.join(SHOW_DIR))?;
+    fs::write(&paths[0], config(device))?;
+    let n_pages = device.map_or(1, |d| d.capabilities().page() + 1);
+    ShowState {
+        ui: MasterUI::new(n_pages),
+        mixer: Mixer::new(n_pages),
+        clocks: ClockBank::new(),
+        video_geometry: Vec::new(),
+        output_profile: None,
+    }
+    .save(&paths[1])?;
+    fs::write(&paths[2], run_script(device, show_name, ports))?;
+    make_executable(&paths[2])?;
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+/// Return the starter show config for a control surface.
+fn config(device: Option<Device>) -> String {
+    let mut config = String::from(
+        "\
+# Show config written by `tunnels init`.
+#
+# Every setting is optional.  Run `tunnels export schema` for the full list of
+# settings, and `tunnels validate-config config.yaml` to check your changes.
+
+# Tag published frames with this ID, so that several shows can share a
+# network.  Render clients must be configured with the same ID.
+# show_id: main-room
+
+# Require a second press within half a second before a beam store grid press
+# saves over or deletes an occupied slot.
+# grid_confirm: {DoublePress: 0.5}
+
+# Limit how often each output can flash, for the safety of photosensitive
+# viewers.  The limit is on by default; turn it off with {enabled: false}.
+# flash_limit: {max_flashes: 3, contrast: 0.1}
+
+# Named palettes of colors that tunnels can draw from.
+palettes:
+  - name: warm
+    colors:
+      - {hue: 0.0, sat: 1.0, val: 1.0}
+      - {hue: 0.08, sat: 1.0, val: 1.0}
+      - {hue: 0.15, sat: 0.8, val: 1.0}
+  - name: cool
+    colors:
+      - {hue: 0.5, sat: 1.0, val: 1.0}
+      - {hue: 0.65, sat: 1.0, val: 1.0}
+      - {hue: 0.8, sat: 0.8, val: 1.0}
+",
+    );
+    if let Some(device) = device {
+        if device.capabilities().faders > 0 {
+            config.push_str(&format!(
+                "
+# The {}'s faders can be out of step with the show, so they only take over
+# a level once they have been moved past it.
+soft_takeover: [{:?}]
+",
+                device, device
+            ));
+        }
+    }
+    config
+}
+
+/// Return a script that runs the show from the rig directory.
+fn run_script(
+    device: Option<Device>,
+    show_name: &str,
+    ports: &(Vec<String>, Vec<String>),
+) -> String {
+    let mut command = format!(
+        "exec tunnels run --config {} --open {}",
+        CONFIG_FILE,
+        shell_quote(show_name)
+    );
+    let mut notes = String::new();
+    if let Some(device) = device {
+        let (inputs, outputs) = ports;
+        let input = find_port(device, inputs);
+        let output = find_port(device, outputs);
+        if input.is_none() || output.is_none() {
+            notes.push_str(&format!(
+                "# No MIDI ports for the {} were found; run `tunnels list-ports`\n\
+                 # and replace INPUT and OUTPUT with their names.\n",
+                device
+            ));
+        }
+        let spec = format!(
+            "{:?}={},{}",
+            device,
+            input.unwrap_or("INPUT"),
+            output.unwrap_or("OUTPUT")
+        );
+        command.push_str(&format!(" --midi {}", shell_quote(&spec)));
+    }
+    format!(
+        "#!/bin/sh\n\
+         # Run the show written by `tunnels init`.\n\
+         {}cd \"$(dirname \"$0\")\" || exit 1\n\
+         {}\n",
+        notes, command
+    )
+}
+
+/// Return the first port whose name looks like it belongs to the device.
+fn find_port(device: Device, ports: &[String]) -> Option<&str> {
+    let hint = match device {
+        Device::AkaiApc40 => "apc40",
+        Device::AkaiApc20 => "apc20",
+        Device::TouchOsc => "touchosc",
+        Device::BehringerCmdMM1 => "cmd mm-1",
+        Device::Trigger | Device::Gamepad | Device::Dmx => return None,
+    };
+    ports
+        .iter()
+        .find(|port| port.to_lowercase().contains(hint))
+        .map(String::as_str)
+}
+
+/// Quote a string for the shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ShowConfig;
+
+    #[test]
+    fn test_config() {
+        for device in std::iter::once(None).chain(Device::MIDI.iter().copied().map(Some)) {
+            let config: ShowConfig = serde_yaml::from_str(&config(device)).unwrap();
+            assert_eq!(2, config.palettes.len());
+            let expected = match device {
+                None => vec![],
+                Some(d) => vec![d],
+            };
+            assert_eq!(expected, config.soft_takeover);
+        }
+    }
+
+    #[test]
+    fn test_run_script() {
+        let ports = (
+            vec!["IAC Bus 1".to_string(), "Akai APC40 MIDI 1".to_string()],
+            vec!["Akai APC40 MIDI 1".to_string()],
+        );
+        let script = run_script(Some(Device::AkaiApc40), "it's on", &ports);
+        assert!(script.ends_with(
+            "exec tunnels run --config config.yaml --open 'it'\\''s on' \
+             --midi 'AkaiApc40=Akai APC40 MIDI 1,Akai APC40 MIDI 1'\n"
+        ));
+        assert!(!script.contains("list-ports"));
+
+        let script = run_script(Some(Device::TouchOsc), "show", &ports);
+        assert!(script.contains("--midi 'TouchOsc=INPUT,OUTPUT'"));
+        assert!(script.contains("list-ports"));
+
+        let script = run_script(None, "show", &ports);
+        assert!(!script.contains("--midi"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Structured logging, configured from the show config.
+//!
+//! Every module logs under its own target, so the level of each subsystem can
+//! be set separately, keyed by module name:
+//!
+//! ```yaml
+//! logging:
+//!   level: Warn
+//!   subsystems:
+//!     dmx: Debug
+//!     show: Info
+//!   file:
+//!     dir: logs
+//!     rotation: Daily
+//! ```
+//!
+//! A subsystem's level also applies to modules whose names it prefixes, so
+//! `midi` covers `midi_controls` and `midi_file` unless they are set too.
+//! The RUST_LOG environment variable, if set, overrides the configured levels.
+//!
+//! The frame loop runs inside debug-level spans, "update" in the show thread
+//! and "render" in the render thread.  Set `show` or `send` to Debug to log
+//! how long each frame spent in them, for analysis after the show.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{collections::BTreeMap, error::Error, fs::create_dir_all, path::PathBuf};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{fmt, fmt::format::FmtSpan, prelude::*, EnvFilter};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Level of every subsystem not listed below.
+    #[serde(default = "default_level")]
+    pub level: LogLevel,
+    /// Levels of individual subsystems, keyed by module name.
+    #[serde(default)]
+    pub subsystems: BTreeMap<String, LogLevel>,
+    /// Also log to files in this directory.
+    #[serde(default)]
+    pub file: Option<LogFileConfig>,
+}
+
+fn default_level() -> LogLevel {
+    LogLevel::Info
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_level(),
+            subsystems: BTreeMap::new(),
+            file: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn directive(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LogFileConfig {
+    /// Directory to write logs into, relative to the working directory.
+    #[serde(default = "default_log_dir")]
+    pub dir: PathBuf,
+    /// Name of each log file, before the date and time it was started.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// How often to start a new file.
+    #[serde(default)]
+    pub rotation: LogRotation,
+}
+
+fn default_log_dir() -> PathBuf {
+    PathBuf::from("logs")
+}
+
+fn default_prefix() -> String {
+    "tunnels.log".to_string()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self::Daily
+    }
+}
+
+impl LoggingConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for name in self.subsystems.keys() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("Logging subsystem \"{}\" is not a module name.", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the filter directives for the configured levels.
+    fn directives(&self) -> String {
+        let mut directives = self.level.directive().to_string();
+        for (name, level) in &self.subsystems {
+            directives.push_str(&format!(",tunnels::{}={}", name, level.directive()));
+        }
+        directives
+    }
+}
+
+/// Start logging for the rest of the program.
+/// If logging to a file, the returned guard must be held until exit, so that
+/// buffered events are written out.
+pub fn init(config: &LoggingConfig) -> Result<Option<WorkerGuard>, Box<dyn Error>> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(config.directives())?,
+    };
+    let (file, guard) = match &config.file {
+        Some(file) => {
+            create_dir_all(&file.dir)?;
+            let appender = match file.rotation {
+                LogRotation::Hourly => rolling::hourly(&file.dir, &file.prefix),
+                LogRotation::Daily => rolling::daily(&file.dir, &file.prefix),
+                LogRotation::Never => rolling::never(&file.dir, &file.prefix),
+            };
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer()
+                .with_writer(writer)
+                .with_ansi(false)
+                .with_thread_names(true)
+                .with_span_events(FmtSpan::CLOSE);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_span_events(FmtSpan::CLOSE))
+        .with(file)
+        .try_init()?;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_directives() {
+        let mut config = LoggingConfig::default();
+        config.subsystems.insert("dmx".to_string(), LogLevel::Debug);
+        config.subsystems.insert("show".to_string(), LogLevel::Off);
+        assert_eq!(
+            "info,tunnels::dmx=debug,tunnels::show=off",
+            config.directives()
+        );
+        assert!(EnvFilter::try_new(config.directives()).is_ok());
+    }
+}
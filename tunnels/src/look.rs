@@ -1,4 +1,8 @@
-use crate::{clock_bank::ClockBank, mixer::Channel};
+use crate::{
+    clock_bank::ClockBank,
+    mixer::{in_draw_order, Channel},
+    random::ShowRng,
+};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tunnels_lib::number::UnipolarFloat;
@@ -16,9 +20,14 @@ impl Look {
         Self { channels }
     }
 
-    pub fn update_state(&mut self, delta_t: Duration) {
+    pub fn update_state(
+        &mut self,
+        delta_t: Duration,
+        external_clocks: &ClockBank,
+        rng: &mut ShowRng,
+    ) {
         for channel in &mut self.channels {
-            channel.update_state(delta_t);
+            channel.update_state(delta_t, external_clocks, rng);
         }
     }
 
@@ -30,13 +39,19 @@ impl Look {
         &self,
         level: UnipolarFloat,
         mask: bool,
+        resolution: f64,
         external_clocks: &ClockBank,
     ) -> Vec<ArcSegment> {
         let mut arcs = Vec::new();
-        for channel in &self.channels {
-            let mut rendered = channel.render(level, mask, external_clocks);
+        for channel in in_draw_order(&self.channels) {
+            let mut rendered = channel.render(level, mask, resolution, external_clocks);
             arcs.append(&mut rendered);
         }
         arcs
     }
+
+    /// Return the number of arcs this look renders at full resolution.
+    pub fn arc_count(&self) -> usize {
+        self.channels.iter().map(|c| c.arc_count()).sum()
+    }
 }
@@ -1,195 +1,356 @@
+mod alert;
 mod animation;
+mod animation_presets;
+mod audio;
+mod autopilot;
 mod beam;
 mod beam_store;
+mod client_presence;
+mod client_registry;
 mod clock;
 mod clock_bank;
+mod color_organ;
+mod config;
+mod config_watch;
+mod control_journal;
+mod control_layout;
+mod control_queue;
+mod control_server;
 mod device;
+mod diagnostic;
+mod dmx;
+mod dmx_merge;
+mod dmx_output;
+mod external;
+mod flash_limit;
+mod frame_check;
+mod frame_clock;
+mod gamepad;
+mod generator;
+mod grid_confirm;
+mod init;
+mod logging;
 mod look;
 mod master_ui;
 mod midi;
+mod midi_clock;
 mod midi_controls;
+mod midi_file;
 mod mixer;
+mod output_profile;
+mod palette;
+mod params;
+mod pixel_map;
+mod playback;
+mod random;
+mod recall_filter;
+mod rings;
+mod scheduler;
+mod screensaver;
 mod send;
+mod session_report;
+mod shatter;
 mod show;
+mod silence_gate;
+mod slot_server;
+mod stereo;
 mod test_mode;
+mod throttle;
 mod timesync;
+mod trigger;
 mod tunnel;
+mod validation;
+mod video_out;
 mod waveforms;
+mod white_point;
 
+use clap::{Parser, Subcommand};
+use config::ShowConfig;
+use control_journal::journal_path;
 use device::Device;
-use io::Write;
+use init::write_starter;
+use logging::LoggingConfig;
 use midi::{list_ports, DeviceSpec};
-use show::Show;
-use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
-use std::{env::current_dir, fs::create_dir_all, io, path::PathBuf};
-use std::{error::Error, time::Duration};
+use midi_file::export_journal;
+use playback::run_playback;
+use schemars::schema_for;
+use show::{Show, ShowState};
+use std::{
+    env::current_dir,
+    error::Error,
+    fs::{create_dir_all, File},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use test_mode::{all_video_outputs, stress, TestModeSetup};
 
-fn main() -> Result<(), Box<dyn Error>> {
-    SimpleLogger::init(LevelFilter::Info, LogConfig::default())?;
-    let (inputs, outputs) = list_ports()?;
+#[derive(Parser)]
+#[clap(name = "tunnels", version, about = "Run and manage tunnels shows.")]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
 
-    let test_mode = prompt_test_mode()?;
+#[derive(Subcommand)]
+enum Command {
+    /// Run the show.
+    Run(RunArgs),
+    /// Write a starter show config, saved show, and run script for a control
+    /// surface.
+    Init {
+        /// Directory to write into.
+        #[clap(default_value = ".")]
+        dir: PathBuf,
+        /// Control surface to set up for; TouchOsc, AkaiApc40,
+        /// BehringerCmdMM1, or AkaiApc20.  Sets up for no control surface if
+        /// not provided.
+        #[clap(long, parse(try_from_str = Device::parse_midi))]
+        device: Option<Device>,
+        /// Name of the saved show.
+        #[clap(long, default_value = "starter")]
+        show: String,
+        /// Replace any existing files.
+        #[clap(long)]
+        force: bool,
+    },
+    /// List the available MIDI ports.
+    ListPorts,
+    /// Check a show config, and optionally a saved show, for errors.
+    ValidateConfig {
+        /// Path to the show config.
+        config: PathBuf,
+        /// Name of a saved show to check against the config.
+        #[clap(long)]
+        show: Option<String>,
+    },
+    /// Play back a recorded show.
+    Play {
+        /// Name of the recording or replay buffer.
+        recording: String,
+        /// Scale the original timing; 2.0 plays back twice as fast.
+        #[clap(long, default_value = "1.0")]
+        speed: f64,
+        /// Play the recording forever.
+        #[clap(long = "loop")]
+        looped: bool,
+        /// Publish under this show ID, for clients configured with it.
+        #[clap(long)]
+        show_id: Option<String>,
+    },
+    /// Export show data for use elsewhere.
+    #[clap(subcommand)]
+    Export(Export),
+}
 
-    let devices = if test_mode.is_some() {
-        Vec::new()
-    } else {
-        prompt_midi(&inputs, &outputs)?
-    };
+#[derive(Subcommand)]
+enum Export {
+    /// Write the JSON schema of the show config, for editor validation and
+    /// completion.
+    Schema {
+        /// Write to this file rather than standard output.
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Export the control events of a recorded performance to MIDI files
+    /// alongside the recording.
+    Midi {
+        /// Name of the recording.
+        recording: String,
+    },
+}
 
-    let mut show = Show::new(devices)?;
+#[derive(clap::Args)]
+struct RunArgs {
+    /// Path to the show config.  Uses the default behavior if not provided.
+    #[clap(long)]
+    config: Option<PathBuf>,
+    /// Connect a control surface, as DEVICE=PORT or DEVICE=INPUT,OUTPUT.
+    /// Devices are TouchOsc, AkaiApc40, BehringerCmdMM1, and AkaiApc20.
+    #[clap(long = "midi", value_name = "DEVICE=PORTS", parse(try_from_str = parse_device_spec))]
+    midi: Vec<DeviceSpec>,
+    /// Accept input from any connected game controller.
+    #[clap(long)]
+    gamepad: bool,
+    /// Show a test pattern on every channel instead of running the show.
+    #[clap(long, possible_values = &["video_outs", "stress"])]
+    test_mode: Option<String>,
+    /// Open this saved show, and autosave back to it.
+    #[clap(long, conflicts_with = "new")]
+    open: Option<String>,
+    /// Start a new show, autosaving it under this name.
+    #[clap(long)]
+    new: Option<String>,
+    /// Record the show output under this name.
+    #[clap(long)]
+    record: Option<String>,
+}
 
-    if let Some(setup_test) = test_mode {
-        show.test_mode(setup_test);
-    } else {
-        let paths = prompt_load_save()?;
-        show.save_path = paths.save_path;
-        if let Some(load_path) = paths.load_path {
-            show.load(&load_path)?;
-        }
-    }
+/// Save and load shows from this relative directory.
+const SHOW_DIR: &str = "saved_shows";
 
-    show.run(Duration::from_micros(16667))
-}
+/// Record and play back snapshot archives from this relative directory.
+const RECORDING_DIR: &str = "recordings";
 
-/// Prompt the user to optionally configure a test mode.
-fn prompt_test_mode() -> Result<Option<TestModeSetup>, Box<dyn Error>> {
-    if !prompt_bool("Output test mode?")? {
-        return Ok(None);
-    }
-    Ok(loop {
-        print!("Select test mode ('video_outs', 'stress'): ");
-        io::stdout().flush()?;
-        match &read_string()?[..] {
-            "video_outs" => break Some(all_video_outputs),
-            "stress" => break Some(stress),
-            _ => (),
-        }
-    })
-}
+/// Write session reports into this relative directory.
+const REPORT_DIR: &str = "reports";
 
-/// Prompt the user to configure midi devices.
-fn prompt_midi(
-    input_ports: &Vec<String>,
-    output_ports: &Vec<String>,
-) -> Result<Vec<DeviceSpec>, Box<dyn Error>> {
-    let mut devices = Vec::new();
-    println!("Available devices:");
-    for (i, port) in input_ports.iter().enumerate() {
-        println!("{}: {}", i, port);
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    // The show configures its own logging once its config is loaded.
+    if !matches!(cli.command, Command::Run(_)) {
+        logging::init(&LoggingConfig::default())?;
     }
-    for (i, port) in output_ports.iter().enumerate() {
-        println!("{}: {}", i, port);
+
+    match cli.command {
+        Command::Run(args) => run(args),
+        Command::Init {
+            dir,
+            device,
+            show,
+            force,
+        } => {
+            // Ports are only used to fill in the run script.
+            let ports = match device {
+                Some(_) => list_ports().unwrap_or_else(|e| {
+                    eprintln!("Unable to list MIDI ports: {}.", e);
+                    (Vec::new(), Vec::new())
+                }),
+                None => (Vec::new(), Vec::new()),
+            };
+            for path in write_starter(&dir, device, &show, &ports, force)? {
+                println!("Wrote {}.", path.display());
+            }
+            Ok(())
+        }
+        Command::ListPorts => {
+            let (inputs, outputs) = list_ports()?;
+            println!("MIDI inputs:");
+            for port in inputs {
+                println!("  {}", port);
+            }
+            println!("MIDI outputs:");
+            for port in outputs {
+                println!("  {}", port);
+            }
+            Ok(())
+        }
+        Command::ValidateConfig { config, show } => validate(&config, show.as_deref()),
+        Command::Play {
+            recording,
+            speed,
+            looped,
+            show_id,
+        } => {
+            if speed.is_nan() || speed <= 0.0 {
+                return Err("Playback speed must be positive.".into());
+            }
+            run_playback(
+                &recording_path(&recording)?,
+                speed,
+                looped,
+                show_id.as_deref(),
+            )
+        }
+        Command::Export(Export::Schema { output }) => {
+            let schema = schema_for!(ShowConfig);
+            match output {
+                Some(path) => serde_json::to_writer_pretty(File::create(path)?, &schema)?,
+                None => serde_json::to_writer_pretty(io::stdout(), &schema)?,
+            }
+            Ok(())
+        }
+        Command::Export(Export::Midi { recording }) => {
+            for path in export_journal(&journal_path(&recording_path(&recording)?))? {
+                println!("Wrote {}.", path.display());
+            }
+            Ok(())
+        }
     }
-    println!();
+}
 
-    let mut add_device = |device| -> Result<(), Box<dyn Error>> {
-        if prompt_bool(&format!("Use {}?", device))? {
-            devices.push(prompt_input_output(device, input_ports, output_ports)?);
+fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let config = match &args.config {
+        Some(path) => {
+            println!("Loading show config from {}.", path.display());
+            ShowConfig::load(path)?
         }
-        Ok(())
+        None => ShowConfig::default(),
     };
+    let _log_guard = logging::init(&config.logging)?;
 
-    add_device(Device::TouchOsc)?;
-    add_device(Device::AkaiApc40)?;
-    add_device(Device::BehringerCmdMM1)?;
-    add_device(Device::AkaiApc20)?;
+    let test_mode: Option<TestModeSetup> = match args.test_mode.as_deref() {
+        Some("video_outs") => Some(all_video_outputs),
+        Some("stress") => Some(stress),
+        _ => None,
+    };
+    if test_mode.is_some() && !args.midi.is_empty() {
+        return Err("MIDI devices can't be used in test mode.".into());
+    }
 
-    Ok(devices)
-}
+    let mut show = Show::new(args.midi, &config)?;
 
-/// Prompt the user to select input and output ports for a device.
-fn prompt_input_output(
-    device: Device,
-    input_ports: &Vec<String>,
-    output_ports: &Vec<String>,
-) -> Result<DeviceSpec, Box<dyn Error>> {
-    let input_port_name = prompt_indexed_value("Input port:", input_ports)?;
-    let output_port_name = prompt_indexed_value("Output port:", output_ports)?;
-    Ok(DeviceSpec {
-        device,
-        input_port_name,
-        output_port_name,
-    })
-}
+    if args.gamepad {
+        show.start_gamepad_input()?;
+    }
 
-/// Prompt the user for a unsigned numeric index.
-fn prompt_indexed_value<T: Clone>(msg: &str, options: &Vec<T>) -> Result<T, Box<dyn Error>> {
-    Ok(loop {
-        print!("{} ", msg);
-        io::stdout().flush()?;
-        let input = read_string()?;
-        let index = match input.trim().parse::<usize>() {
-            Ok(num) => num,
-            Err(e) => {
-                println!("{}; please enter an integer.", e);
-                continue;
-            }
-        };
-        match options.get(index) {
-            Some(v) => break v.clone(),
-            None => println!("Please enter a value less than {}.", options.len()),
+    if let Some(setup_test) = test_mode {
+        show.test_mode(setup_test);
+    } else {
+        let save_dir = current_dir()?.join(SHOW_DIR);
+        if let Some(name) = &args.open {
+            let path = save_dir.join(name);
+            show.load(&path)?;
+            show.save_path = Some(path);
+        } else if let Some(name) = &args.new {
+            create_dir_all(&save_dir)?;
+            show.save_path = Some(save_dir.join(name));
         }
-    })
-}
+        if let Some(name) = &args.record {
+            let record_dir = current_dir()?.join(RECORDING_DIR);
+            create_dir_all(&record_dir)?;
+            show.record_path = Some(record_dir.join(name));
+        }
+    }
 
-struct LoadSaveConfig {
-    load_path: Option<PathBuf>,
-    save_path: Option<PathBuf>,
+    show.report_dir = Some(current_dir()?.join(REPORT_DIR));
+    show.config_path = args.config;
+    show.run(Duration::from_micros(16667))
 }
 
-/// Save and load shows from this relative directory.
-const SHOW_DIR: &'static str = "saved_shows";
-
-/// Prompt the user for show load and/or save paths.
-fn prompt_load_save() -> Result<LoadSaveConfig, Box<dyn Error>> {
-    let mut cfg = LoadSaveConfig {
-        load_path: None,
-        save_path: None,
-    };
-    let save_dir = current_dir()?.join(SHOW_DIR);
-    if prompt_bool("Open saved show?")? {
-        let mut name = String::new();
-        while name.len() == 0 {
-            print!("Open this show: ");
-            io::stdout().flush()?;
-            name = read_string()?;
-        }
-        let path = save_dir.join(name);
-        cfg.load_path = Some(path.clone());
-        cfg.save_path = Some(path);
-    } else if prompt_bool("Creating new show; save?")? {
-        let mut name = String::new();
-        while name.len() == 0 {
-            print!("Name this show: ");
-            io::stdout().flush()?;
-            name = read_string()?;
-        }
-        cfg.save_path = Some(save_dir.join(name));
-        create_dir_all(save_dir)?;
+/// Load a show config, and a saved show if provided, reporting any errors.
+fn validate(config: &Path, show: Option<&str>) -> Result<(), Box<dyn Error>> {
+    ShowConfig::load(config)?;
+    println!("Show config {} is valid.", config.display());
+    if let Some(name) = show {
+        let path = current_dir()?.join(SHOW_DIR).join(name);
+        let state = ShowState::load(&path)?;
+        println!(
+            "Saved show {} has {} mixer channels on {} pages.",
+            path.display(),
+            state.mixer.channel_count(),
+            state.ui.n_pages()
+        );
     }
-    Ok(cfg)
+    Ok(())
 }
 
-/// Prompt the user to answer a yes or no question.
-fn prompt_bool(msg: &str) -> Result<bool, Box<dyn Error>> {
-    Ok(loop {
-        print!("{} y/n: ", msg);
-        io::stdout().flush()?;
-        let input = read_string()?;
-        if let Some(first_char) = input.chars().nth(0) {
-            match first_char {
-                'y' | 'Y' => break true,
-                'n' | 'N' => break false,
-                _ => (),
-            }
-        }
+/// Parse a control surface connection from DEVICE=PORT or
+/// DEVICE=INPUT,OUTPUT.
+fn parse_device_spec(spec: &str) -> Result<DeviceSpec, String> {
+    let (name, ports) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected DEVICE=PORTS, got \"{}\"", spec))?;
+    let device = Device::parse_midi(name)?;
+    let (input, output) = ports.split_once(',').unwrap_or((ports, ports));
+    Ok(DeviceSpec {
+        device,
+        input_port_name: input.trim().to_string(),
+        output_port_name: output.trim().to_string(),
     })
 }
 
-/// Read a line of input from stdin.
-fn read_string() -> Result<String, Box<dyn Error>> {
-    let mut line = String::new();
-    io::stdin().read_line(&mut line)?;
-    Ok(line.trim().to_string())
+/// Return the path of a named recording.
+fn recording_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(current_dir()?.join(RECORDING_DIR).join(name))
 }
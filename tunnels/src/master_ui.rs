@@ -1,15 +1,32 @@
 use crate::{
-    animation::Animation,
+    animation::{
+        Animation, ControlMessage as AnimationControlMessage, StateChange as AnimationStateChange,
+    },
+    animation_presets::AnimationPresets,
+    autopilot::{
+        Autopilot, ControlMessage as AutopilotControlMessage, StateChange as AutopilotStateChange,
+    },
     beam::Beam,
-    beam_store::{BeamStore, BeamStoreAddr},
-    clock_bank::ClockBank,
+    beam_store::{BeamStore, BeamStoreAddr, SlotMeta},
+    clock_bank::{ClockBank, ClockIdx},
+    grid_confirm::{Confirmation, GridConfirm, Resolution},
+    look::Look,
     midi_controls::MIXER_CHANNELS_PER_PAGE,
-    mixer::{ChannelIdx, Mixer},
+    mixer::{ChannelIdx, ControlMessage as MixerControlMessage, Mixer},
+    recall_filter::RecallFilter,
     show::{ControlMessage as ShowControlMessage, StateChange as ShowStateChange},
-    tunnel::AnimationIdx,
+    slot_server::Slot,
+    tunnel::{AnimationIdx, Tunnel},
 };
 
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{info, warn};
+use tunnels_lib::number::{BipolarFloat, UnipolarFloat};
+
+/// The most copies a symmetric composition can be made of.
+pub const MAX_SYMMETRY: usize = 8;
 
 /// Manage stateful aspects of the UI.
 /// Mediate between the input systems and the show data.
@@ -23,6 +40,50 @@ pub struct MasterUI {
     animation_clipboard: Animation,
     beam_store: BeamStore,
     beam_store_state: BeamStoreState,
+    #[serde(default)]
+    autopilot: Autopilot,
+    #[serde(default)]
+    animation_presets: AnimationPresets,
+    /// Groups of channels holding rotated copies of one tunnel, edited as
+    /// one.
+    #[serde(default)]
+    symmetry_groups: Vec<Vec<ChannelIdx>>,
+    /// The beam store slot most recently recalled by stepping through the store.
+    #[serde(skip)]
+    last_stepped_recall: Option<BeamStoreAddr>,
+    /// If true, the next channel selection clones the current channel into
+    /// the selected channel.
+    #[serde(skip)]
+    clone_armed: bool,
+    /// If true, the next animation preset button press saves the current
+    /// animation into that preset rather than applying it.
+    #[serde(skip)]
+    preset_save_armed: bool,
+    /// A shuffle waiting for its clock to tick.
+    #[serde(skip)]
+    pending_shuffle: Option<(ShuffleTarget, ClockIdx)>,
+    /// True while an alert has been raised and not yet acknowledged.
+    #[serde(skip)]
+    alerting: bool,
+    #[serde(skip)]
+    beam_store_stats: BeamStoreStats,
+    /// A destructive grid press awaiting confirmation.
+    #[serde(skip)]
+    confirmation: Confirmation,
+    /// If true, the raw output of the current animation is sent to the
+    /// controllers and previewed on the diagnostic layer.
+    #[serde(skip)]
+    listening: bool,
+    /// Last raw output emitted while listening, quantized to avoid flooding
+    /// UIs with updates.
+    #[serde(skip)]
+    listen_emitted: Option<u8>,
+    /// True while the controllers show the clock page.
+    #[serde(skip)]
+    clock_page: bool,
+    /// Parameter classes that beam store recalls leave alone.
+    #[serde(skip)]
+    recall_filter: RecallFilter,
 }
 
 impl MasterUI {
@@ -36,20 +97,86 @@ impl MasterUI {
             animation_clipboard: Animation::new(),
             beam_store: BeamStore::new(n_mixer_pages),
             beam_store_state: BeamStoreState::Idle,
+            autopilot: Autopilot::new(),
+            animation_presets: AnimationPresets::new(),
+            symmetry_groups: Vec::new(),
+            last_stepped_recall: None,
+            clone_armed: false,
+            preset_save_armed: false,
+            pending_shuffle: None,
+            alerting: false,
+            beam_store_stats: BeamStoreStats::default(),
+            confirmation: Confirmation::default(),
+            listening: false,
+            listen_emitted: None,
+            clock_page: false,
+            recall_filter: RecallFilter::default(),
         }
     }
 
+    /// Disarm every armed mode and cancel anything waiting on a clock.
+    /// Emits nothing; the caller is expected to emit the entire state after.
+    pub fn panic(&mut self) {
+        self.beam_store_state = BeamStoreState::Idle;
+        self.clone_armed = false;
+        self.preset_save_armed = false;
+        self.pending_shuffle = None;
+        self.confirmation.cancel();
+        self.listening = false;
+        self.clock_page = false;
+        self.recall_filter.clear();
+    }
+
+    /// Set how destructive beam store grid presses are confirmed.
+    /// Not saved with the show, so must be set again after loading one.
+    pub fn set_grid_confirm(&mut self, mode: GridConfirm) {
+        self.confirmation = Confirmation::new(mode);
+    }
+
+    /// Seed the autopilot's choices.
+    pub fn set_autopilot_seed(&mut self, seed: u64) {
+        self.autopilot.control(
+            AutopilotControlMessage::Set(AutopilotStateChange::Seed(seed)),
+            &mut DummyEmitter,
+        );
+    }
+
     pub fn n_pages(&self) -> usize {
         self.beam_store.n_pages()
     }
 
+    /// Return the metadata of every beam store slot that has any.
+    pub fn slot_listing(&self) -> Vec<Slot> {
+        self.beam_store
+            .meta()
+            .map(|(addr, meta)| Slot {
+                row: addr.row,
+                col: addr.col,
+                meta: meta.clone(),
+            })
+            .collect()
+    }
+
+    /// Return a copy of the look in a beam store slot, if it holds a look.
+    pub fn stored_look(&self, row: usize, col: usize) -> Option<Look> {
+        match self.beam_store.slot(BeamStoreAddr { row, col }) {
+            Some(Beam::Look(look)) => Some(look.clone()),
+            _ => None,
+        }
+    }
+
+    /// Return how the beam store has been used since the show started.
+    pub fn beam_store_stats(&self) -> BeamStoreStats {
+        self.beam_store_stats
+    }
+
     fn current_beam<'m>(&self, mixer: &'m mut Mixer) -> &'m mut Beam {
         mixer.beam(self.current_channel)
     }
 
     fn current_animation<'m>(&self, mixer: &'m mut Mixer) -> Option<&'m mut Animation> {
         match self.current_beam(mixer) {
-            Beam::Look(_) => None,
+            Beam::Look(_) | Beam::Generator(_) => None,
             Beam::Tunnel(t) => Some(t.animation(self.current_animation_idx())),
         }
     }
@@ -58,6 +185,86 @@ impl MasterUI {
         self.current_animation_for_channel[self.current_channel.0]
     }
 
+    /// Update any time-dependent UI state.
+    pub fn update_state<E: EmitStateChange>(
+        &mut self,
+        delta_t: Duration,
+        mixer: &mut Mixer,
+        clocks: &ClockBank,
+        emitter: &mut E,
+    ) {
+        let stored_beams = self
+            .beam_store
+            .items()
+            .filter_map(|(_, beam)| beam.as_ref())
+            .collect::<Vec<_>>();
+        let modified = self
+            .autopilot
+            .update_state(delta_t, &stored_beams, mixer, clocks);
+        if !modified.is_empty() {
+            for chan in modified.iter() {
+                self.unlink(*chan);
+            }
+            mixer.emit_state(emitter);
+            if modified.contains(&self.current_channel) {
+                self.emit_current_channel_state(mixer, emitter);
+            }
+        }
+        if let Some((shuffle, clock)) = self.pending_shuffle {
+            if clocks.ticked(clock) {
+                self.pending_shuffle = None;
+                self.shuffle(shuffle, mixer, emitter);
+            }
+        }
+        if let Some(resolution) = self.confirmation.update_state(delta_t) {
+            self.resolve_confirmation(resolution, mixer, emitter);
+        }
+        if self.listening {
+            self.update_listen(delta_t, mixer, clocks, emitter);
+        }
+    }
+
+    /// Emit the raw output of the current animation if it has visibly
+    /// changed.
+    fn update_listen<E: EmitStateChange>(
+        &mut self,
+        delta_t: Duration,
+        mixer: &mut Mixer,
+        clocks: &ClockBank,
+        emitter: &mut E,
+    ) {
+        const STEPS: f64 = 127.;
+        let value = match self.current_animation(mixer) {
+            Some(a) => a.listen(delta_t, clocks),
+            None => BipolarFloat::new(0.0),
+        };
+        let quantized = ((value.val() + 1.0) / 2.0 * STEPS).round() as u8;
+        if self.listen_emitted != Some(quantized) {
+            self.listen_emitted = Some(quantized);
+            emitter.emit_master_ui_state_change(StateChange::ListenLevel(value));
+        }
+    }
+
+    /// Return a copy of the current beam with the animation being listened
+    /// to at full weight, exaggerating what it does, or None if not
+    /// listening to an animation.
+    pub fn listen_preview(&self, mixer: &mut Mixer) -> Option<Tunnel> {
+        if !self.listening {
+            return None;
+        }
+        match self.current_beam(mixer) {
+            Beam::Look(_) | Beam::Generator(_) => None,
+            Beam::Tunnel(t) => {
+                let mut preview = t.clone();
+                preview.animation(self.current_animation_idx()).control(
+                    AnimationControlMessage::Set(AnimationStateChange::Weight(UnipolarFloat::ONE)),
+                    &mut DummyEmitter,
+                );
+                Some(preview)
+            }
+        }
+    }
+
     pub fn handle_control_message<E: EmitStateChange>(
         &mut self,
         msg: ShowControlMessage,
@@ -66,14 +273,18 @@ impl MasterUI {
         emitter: &mut E,
     ) {
         match msg {
-            ShowControlMessage::Tunnel(tm) => match self.current_beam(mixer) {
-                Beam::Look(_) => (),
-                Beam::Tunnel(t) => t.control(tm, emitter),
-            },
+            ShowControlMessage::Tunnel(tm) => {
+                match self.current_beam(mixer) {
+                    Beam::Look(_) | Beam::Generator(_) => (),
+                    Beam::Tunnel(t) => t.control(tm, emitter),
+                }
+                self.update_symmetry(mixer);
+            }
             ShowControlMessage::Animation(am) => {
                 if let Some(a) = self.current_animation(mixer) {
                     a.control(am, emitter);
                 }
+                self.update_symmetry(mixer);
             }
             ShowControlMessage::Mixer(mm) => {
                 mixer.control(mm, emitter);
@@ -81,7 +292,25 @@ impl MasterUI {
             ShowControlMessage::Clock(cm) => {
                 clocks.control(cm, emitter);
             }
+            ShowControlMessage::MasterUI(ControlMessage::ToggleClockPage) => {
+                self.clock_page = !self.clock_page;
+                // The page shows different state on the same controls.
+                self.emit_state(mixer, clocks, emitter);
+            }
             ShowControlMessage::MasterUI(uim) => self.control(uim, mixer, emitter),
+            ShowControlMessage::RecallFilter(fm) => self.recall_filter.control(fm, emitter),
+            ShowControlMessage::Autopilot(am) => self.autopilot.control(am, emitter),
+            ShowControlMessage::ColorOrgan(cm) => mixer.color_organ().control(cm, emitter),
+            ShowControlMessage::Palette(pm) => mixer.palettes().control(pm, emitter),
+            // Video outputs, MIDI file playback, and the flash limit are
+            // owned by the show, not the UI, and the show handles panics and
+            // unpacks batches.
+            ShowControlMessage::VideoOut(_)
+            | ShowControlMessage::OutputProfile(_)
+            | ShowControlMessage::MidiFile(_)
+            | ShowControlMessage::FlashLimit(_)
+            | ShowControlMessage::Panic
+            | ShowControlMessage::Batch(_) => (),
         }
     }
 
@@ -92,19 +321,49 @@ impl MasterUI {
         clocks: &mut ClockBank,
         emitter: &mut E,
     ) {
+        // Switch pages first, so the rest of the state lands on the right
+        // controls.
+        emitter.emit_master_ui_state_change(StateChange::ClockPage(self.clock_page));
         emitter.emit_master_ui_state_change(StateChange::Channel(self.current_channel));
+        emitter.emit_master_ui_state_change(StateChange::CloneArmed(self.clone_armed));
+        emitter.emit_master_ui_state_change(StateChange::PresetSaveArmed(self.preset_save_armed));
+        self.emit_animation_presets_state(emitter);
+        emitter.emit_master_ui_state_change(StateChange::Alerting(self.alerting));
+        emitter.emit_master_ui_state_change(StateChange::Listening(self.listening));
         self.emit_beam_store_state(emitter);
         self.emit_current_channel_state(mixer, emitter);
         mixer.emit_state(emitter);
         clocks.emit_state(emitter);
+        self.autopilot.emit_state(emitter);
+        self.recall_filter.emit_state(emitter);
+        let n_channels = mixer.channel_count();
+        mixer.color_organ().emit_state(n_channels, emitter);
+        mixer.palettes().emit_state(emitter);
     }
 
     /// Emit state for the beam store.
     fn emit_beam_store_state<E: EmitStateChange>(&self, emitter: &mut E) {
-        for (addr, beam) in self.beam_store.items() {
-            emitter.emit_master_ui_state_change(StateChange::BeamButton((
-                addr,
-                BeamButtonState::from_beam(beam),
+        for (addr, _) in self.beam_store.items() {
+            self.emit_beam_button_state(addr, emitter);
+        }
+    }
+
+    /// Emit the state of a single beam store slot.
+    fn emit_beam_button_state<E: EmitStateChange>(&self, addr: BeamStoreAddr, emitter: &mut E) {
+        let state = if self.confirmation.pending() == Some(addr) {
+            BeamButtonState::Confirming
+        } else {
+            BeamButtonState::new(self.beam_store.slot(addr), self.beam_store.locked(addr))
+        };
+        emitter.emit_master_ui_state_change(StateChange::BeamButton((addr, state)));
+    }
+
+    /// Emit state for the animation preset library.
+    fn emit_animation_presets_state<E: EmitStateChange>(&self, emitter: &mut E) {
+        for (index, preset) in self.animation_presets.items() {
+            emitter.emit_master_ui_state_change(StateChange::AnimationPresetButton((
+                index,
+                preset.is_some(),
             )));
         }
     }
@@ -120,11 +379,13 @@ impl MasterUI {
     /// Emit state for the active beam and animator.
     fn emit_current_channel_state<E: EmitStateChange>(&self, mixer: &mut Mixer, emitter: &mut E) {
         // Emit state for the beam in the current channel.
-        // Do nothing if the beam is a look.
+        // Do nothing if the beam is a look or a generator.
         // FIXME: we should do something nice like turn all the UI LEDs
         // off when the current channel is a look.
-        match self.current_beam(mixer) {
-            Beam::Look(_) => (),
+        let beam = self.current_beam(mixer);
+        emitter.emit_master_ui_state_change(StateChange::BeamType(beam.type_index()));
+        match beam {
+            Beam::Look(_) | Beam::Generator(_) => (),
             Beam::Tunnel(t) => {
                 t.emit_state(emitter);
             }
@@ -132,7 +393,19 @@ impl MasterUI {
         self.emit_animator_state(mixer, emitter);
     }
 
+    /// Flash the alert indicator until the operator acknowledges it.
+    pub fn raise_alert<E: EmitStateChange>(&mut self, emitter: &mut E) {
+        if !self.alerting {
+            self.alerting = true;
+            emitter.emit_master_ui_state_change(StateChange::Alerting(true));
+        }
+    }
+
     fn set_beam_store_state<E: EmitStateChange>(&mut self, state: BeamStoreState, emitter: &mut E) {
+        // A press awaiting confirmation was made in the old mode.
+        if let Some(Resolution::Cancelled(addr)) = self.confirmation.cancel() {
+            self.emit_beam_button_state(addr, emitter);
+        }
         self.beam_store_state = state;
         emitter.emit_master_ui_state_change(StateChange::BeamStoreState(state));
     }
@@ -143,7 +416,7 @@ impl MasterUI {
         beam: Option<Beam>,
         emitter: &mut E,
     ) {
-        let button_state = BeamButtonState::from_beam(&beam);
+        let button_state = BeamButtonState::new(&beam, self.beam_store.locked(addr));
         self.beam_store.put(addr, beam);
         emitter.emit_master_ui_state_change(StateChange::BeamButton((addr, button_state)));
     }
@@ -158,6 +431,8 @@ impl MasterUI {
 
         match msg {
             Set(sc) => self.handle_state_change(sc, mixer, emitter),
+            // Handled with access to the clocks.
+            ToggleClockPage => (),
             AnimationCopy => {
                 if let Some(a) = self.current_animation(mixer) {
                     self.animation_clipboard = a.clone();
@@ -167,36 +442,283 @@ impl MasterUI {
                 if let Some(a) = self.current_animation(mixer) {
                     *a = self.animation_clipboard.clone();
                 }
+                self.update_symmetry(mixer);
                 self.emit_animator_state(mixer, emitter);
             }
             BeamGridButtonPress(addr) => self.handle_beam_grid_button_press(addr, mixer, emitter),
+            BeamGridButtonRelease(addr) => {
+                if let Some(resolution) = self.confirmation.release(addr) {
+                    self.resolve_confirmation(resolution, mixer, emitter);
+                }
+            }
+            ToggleCloneArmed => {
+                self.handle_state_change(StateChange::CloneArmed(!self.clone_armed), mixer, emitter)
+            }
+            RecallBeam(addr) => self.recall_beam(addr, mixer, emitter),
+            RecallNextBeam => self.recall_next_beam(mixer, emitter),
+            ToggleListening => {
+                self.handle_state_change(StateChange::Listening(!self.listening), mixer, emitter)
+            }
+            AcknowledgeAlerts => {
+                self.handle_state_change(StateChange::Alerting(false), mixer, emitter)
+            }
+            ToggleSaveAnimationPreset => self.handle_state_change(
+                StateChange::PresetSaveArmed(!self.preset_save_armed),
+                mixer,
+                emitter,
+            ),
+            AnimationPresetButtonPress(index) => {
+                self.handle_animation_preset_button_press(index, mixer, emitter)
+            }
+            Shuffle(shuffle, None) => self.shuffle(shuffle, mixer, emitter),
+            Shuffle(shuffle, Some(clock)) => self.pending_shuffle = Some((shuffle, clock)),
+            SelectBeamType(index) => {
+                if let Some(beam) = Beam::new_of_type(index) {
+                    self.unlink(self.current_channel);
+                    *self.current_beam(mixer) = beam;
+                    self.emit_current_channel_state(mixer, emitter);
+                }
+            }
+            EditSlot(addr, meta) => self.edit_slot(addr, meta, emitter),
+            Symmetrize(n) => self.symmetrize(n, mixer, emitter),
+            RecallLook(look) => self.recall_look(look, mixer, emitter),
+        }
+    }
+
+    /// Spread rotated copies of the current tunnel across n channels,
+    /// starting with the current channel, evenly around the origin, and link
+    /// them so that editing any one of them edits them all.  A symmetry of
+    /// one unlinks the current channel.
+    fn symmetrize<E: EmitStateChange>(&mut self, n: usize, mixer: &mut Mixer, emitter: &mut E) {
+        self.unlink(self.current_channel);
+        if n < 2 {
+            return;
+        }
+        let base = match self.current_beam(mixer) {
+            Beam::Tunnel(t) => t.rotation_offset(),
+            Beam::Look(_) | Beam::Generator(_) => {
+                warn!("Only a tunnel can be made symmetric.");
+                return;
+            }
+        };
+        let first = self.current_channel.0;
+        if first + n > mixer.channel_count() {
+            warn!(
+                "Not enough channels after channel {} for {} copies.",
+                first, n
+            );
+            return;
+        }
+        let group: Vec<ChannelIdx> = (first..first + n).map(ChannelIdx).collect();
+        for (i, &chan) in group.iter().enumerate() {
+            if i > 0 {
+                self.unlink(chan);
+                mixer.control(
+                    MixerControlMessage::CloneChannel {
+                        from: self.current_channel,
+                        to: chan,
+                    },
+                    emitter,
+                );
+                self.current_animation_for_channel[chan.0] = self.current_animation_idx();
+            }
+            if let Beam::Tunnel(t) = mixer.beam(chan) {
+                t.set_rotation_offset(base + i as f64 / n as f64);
+            }
+        }
+        info!("Linked {} rotated copies of channel {}.", n, first);
+        self.symmetry_groups.push(group);
+    }
+
+    /// Remove a channel from any symmetry group, such as when its beam is
+    /// replaced.
+    fn unlink(&mut self, channel: ChannelIdx) {
+        for group in self.symmetry_groups.iter_mut() {
+            group.retain(|c| *c != channel);
+        }
+        self.symmetry_groups.retain(|group| group.len() > 1);
+    }
+
+    /// If the current channel is in a symmetry group, make the other copies
+    /// follow its tunnel.
+    fn update_symmetry(&self, mixer: &mut Mixer) {
+        let group = match self
+            .symmetry_groups
+            .iter()
+            .find(|group| group.contains(&self.current_channel))
+        {
+            Some(group) => group,
+            None => return,
+        };
+        let leader = match self.current_beam(mixer) {
+            Beam::Tunnel(t) => t.clone(),
+            Beam::Look(_) | Beam::Generator(_) => return,
+        };
+        for &chan in group.iter().filter(|c| **c != self.current_channel) {
+            if let Beam::Tunnel(t) = mixer.beam(chan) {
+                t.follow(&leader);
+            }
+        }
+    }
+
+    /// Replace the metadata of a beam store slot, updating its button in case
+    /// it was locked or unlocked.
+    fn edit_slot<E: EmitStateChange>(
+        &mut self,
+        addr: BeamStoreAddr,
+        meta: SlotMeta,
+        emitter: &mut E,
+    ) {
+        if let Err(e) = self.beam_store.set_meta(addr, meta) {
+            warn!("Unable to edit beam store slot: {}", e);
+            return;
+        }
+        self.emit_beam_button_state(addr, emitter);
+    }
+
+    /// Replace the beam in the current channel with the beam in the next
+    /// occupied beam store slot, stepping through the store in row order and
+    /// wrapping around at the end.
+    fn recall_next_beam<E: EmitStateChange>(&mut self, mixer: &mut Mixer, emitter: &mut E) {
+        let occupied = self
+            .beam_store
+            .items()
+            .filter(|(_, beam)| beam.is_some())
+            .map(|(addr, _)| addr)
+            .collect::<Vec<_>>();
+        let next = match self.last_stepped_recall {
+            Some(last) => occupied
+                .iter()
+                .find(|addr| (addr.row, addr.col) > (last.row, last.col))
+                .or_else(|| occupied.first()),
+            None => occupied.first(),
+        };
+        if let Some(&addr) = next {
+            self.last_stepped_recall = Some(addr);
+            self.recall_beam(addr, mixer, emitter);
         }
     }
 
+    /// Replace the beam in the current channel with the beam in a beam store
+    /// slot, if it holds one.
+    fn recall_beam<E: EmitStateChange>(
+        &mut self,
+        addr: BeamStoreAddr,
+        mixer: &mut Mixer,
+        emitter: &mut E,
+    ) {
+        if addr.row >= BeamStore::N_ROWS
+            || addr.col >= self.beam_store.n_pages() * BeamStore::COLS_PER_PAGE
+        {
+            warn!("There is no beam store slot at {}.", addr);
+            return;
+        }
+        if let Some(beam) = self.beam_store.get(addr) {
+            self.unlink(self.current_channel);
+            let beam = self.recall_filter.apply(beam, self.current_beam(mixer));
+            *self.current_beam(mixer) = beam;
+            self.beam_store_stats.recalled += 1;
+            self.emit_current_channel_state(mixer, emitter);
+        }
+    }
+
+    /// Recall random beams from the beam store page matching the current
+    /// channel's mixer page.
+    fn shuffle<E: EmitStateChange>(
+        &mut self,
+        shuffle: ShuffleTarget,
+        mixer: &mut Mixer,
+        emitter: &mut E,
+    ) {
+        let page = self.current_channel.0 / MIXER_CHANNELS_PER_PAGE;
+        let beams = self.beam_store.occupied_on_page(page);
+        let channels = match shuffle {
+            ShuffleTarget::Current => vec![self.current_channel],
+            ShuffleTarget::All => (0..mixer.channels().count()).map(ChannelIdx).collect(),
+        };
+        let mut replaced = Vec::new();
+        for channel in channels {
+            if let Some(beam) = beams.choose(mixer.rng()).map(|beam| (*beam).clone()) {
+                let beam = self.recall_filter.apply(beam, mixer.beam(channel));
+                *mixer.beam(channel) = beam;
+                replaced.push(channel);
+            }
+        }
+        for channel in replaced {
+            self.unlink(channel);
+            self.beam_store_stats.recalled += 1;
+        }
+        mixer.emit_state(emitter);
+        self.emit_current_channel_state(mixer, emitter);
+    }
+
     fn handle_beam_grid_button_press<E: EmitStateChange>(
         &mut self,
         addr: BeamStoreAddr,
         mixer: &mut Mixer,
         emitter: &mut E,
+    ) {
+        use BeamStoreState::*;
+        if matches!(self.beam_store_state, BeamSave | LookSave | Delete)
+            && self.beam_store.locked(addr)
+        {
+            warn!("Beam store slot at {} is locked.", addr);
+            self.set_beam_store_state(Idle, emitter);
+            return;
+        }
+        // Saving over or deleting a beam can't be undone.
+        let destructive = matches!(self.beam_store_state, BeamSave | LookSave | Delete)
+            && self.beam_store.slot(addr).is_some();
+        if destructive {
+            let previous = self.confirmation.pending();
+            if !self.confirmation.press(addr) {
+                if let Some(previous) = previous.filter(|previous| *previous != addr) {
+                    self.emit_beam_button_state(previous, emitter);
+                }
+                self.emit_beam_button_state(addr, emitter);
+                return;
+            }
+        }
+        self.operate_on_slot(addr, mixer, emitter);
+    }
+
+    /// Act on a confirmed press, or restore the slot of a cancelled one.
+    fn resolve_confirmation<E: EmitStateChange>(
+        &mut self,
+        resolution: Resolution,
+        mixer: &mut Mixer,
+        emitter: &mut E,
+    ) {
+        match resolution {
+            Resolution::Confirmed(addr) => self.operate_on_slot(addr, mixer, emitter),
+            Resolution::Cancelled(addr) => self.emit_beam_button_state(addr, emitter),
+        }
+    }
+
+    /// Perform the operation of the current beam store mode on a slot.
+    fn operate_on_slot<E: EmitStateChange>(
+        &mut self,
+        addr: BeamStoreAddr,
+        mixer: &mut Mixer,
+        emitter: &mut E,
     ) {
         use BeamStoreState::*;
         match self.beam_store_state {
             Idle => {
                 // Request to replace the beam in the current mixer with
                 // the beam in this button.
-                if let Some(beam) = self.beam_store.get(addr) {
-                    *self.current_beam(mixer) = beam;
-                    self.emit_current_channel_state(mixer, emitter);
-                }
+                self.recall_beam(addr, mixer, emitter);
             }
             BeamSave => {
                 // Dump the current beam into the selected slot.
                 self.put_beam_in_store(addr, Some(self.current_beam(mixer).clone()), emitter);
+                self.beam_store_stats.beams_saved += 1;
                 self.set_beam_store_state(Idle, emitter);
             }
             LookSave => {
                 // Dump the whole mixer state.
                 self.put_beam_in_store(addr, Some(Beam::Look(mixer.as_look())), emitter);
+                self.beam_store_stats.looks_saved += 1;
                 self.set_beam_store_state(Idle, emitter);
             }
             Delete => {
@@ -207,14 +729,72 @@ impl MasterUI {
                 // If the beam in the requested slot is a look, explode
                 // it into the mixer.
                 if let Some(Beam::Look(look)) = self.beam_store.get(addr) {
-                    mixer.set_look(look, emitter);
-                    self.emit_current_channel_state(mixer, emitter);
+                    self.recall_look(look, mixer, emitter);
                     self.set_beam_store_state(Idle, emitter);
                 }
             }
         }
     }
 
+    /// Replace the contents of the mixer with a look.  The look's channels
+    /// are unrelated to the current ones, so every symmetry group is unlinked.
+    fn recall_look<E: EmitStateChange>(&mut self, look: Look, mixer: &mut Mixer, emitter: &mut E) {
+        self.symmetry_groups.clear();
+        mixer.set_look(look, emitter);
+        self.emit_current_channel_state(mixer, emitter);
+    }
+
+    /// Save the current animation into the preset if saving is armed;
+    /// otherwise apply the preset to the current animation.
+    fn handle_animation_preset_button_press<E: EmitStateChange>(
+        &mut self,
+        index: usize,
+        mixer: &mut Mixer,
+        emitter: &mut E,
+    ) {
+        if self.preset_save_armed {
+            let animation = match self.current_animation(mixer) {
+                Some(a) => a.clone(),
+                None => return,
+            };
+            if let Some(name) = self.animation_presets.save(index, &animation) {
+                info!("Saved animation preset {}.", name);
+                emitter
+                    .emit_master_ui_state_change(StateChange::AnimationPresetButton((index, true)));
+            }
+            self.preset_save_armed = false;
+            emitter.emit_master_ui_state_change(StateChange::PresetSaveArmed(false));
+        } else if let Some(preset) = self.animation_presets.get(index) {
+            let animation = preset.animation.clone();
+            if let Some(a) = self.current_animation(mixer) {
+                *a = animation;
+            }
+            self.update_symmetry(mixer);
+            self.emit_animator_state(mixer, emitter);
+        }
+    }
+
+    /// Clone the current channel into another channel, along with its
+    /// animation selection, and disarm cloning.
+    fn clone_channel_to<E: EmitStateChange>(
+        &mut self,
+        to: ChannelIdx,
+        mixer: &mut Mixer,
+        emitter: &mut E,
+    ) {
+        self.unlink(to);
+        mixer.control(
+            MixerControlMessage::CloneChannel {
+                from: self.current_channel,
+                to,
+            },
+            emitter,
+        );
+        self.current_animation_for_channel[to.0] = self.current_animation_idx();
+        self.clone_armed = false;
+        emitter.emit_master_ui_state_change(StateChange::CloneArmed(false));
+    }
+
     fn handle_state_change<E: EmitStateChange>(
         &mut self,
         sc: StateChange,
@@ -223,6 +803,9 @@ impl MasterUI {
     ) {
         match sc {
             StateChange::Channel(chan) => {
+                if self.clone_armed {
+                    self.clone_channel_to(chan, mixer, emitter);
+                }
                 // No action if we already have this channel selected.
                 if chan == self.current_channel {
                     return;
@@ -245,8 +828,30 @@ impl MasterUI {
                     emitter,
                 );
             }
+            StateChange::CloneArmed(v) => {
+                self.clone_armed = v;
+                emitter.emit_master_ui_state_change(sc);
+            }
+            StateChange::PresetSaveArmed(v) => {
+                self.preset_save_armed = v;
+                emitter.emit_master_ui_state_change(sc);
+            }
+            StateChange::Alerting(v) => {
+                self.alerting = v;
+                emitter.emit_master_ui_state_change(sc);
+            }
+            StateChange::Listening(v) => {
+                self.listening = v;
+                // Always emit the first reading after listening starts.
+                self.listen_emitted = None;
+                emitter.emit_master_ui_state_change(sc);
+            }
             // Output only.
-            StateChange::BeamButton(_) => (),
+            StateChange::BeamButton(_)
+            | StateChange::BeamType(_)
+            | StateChange::AnimationPresetButton(_)
+            | StateChange::ListenLevel(_)
+            | StateChange::ClockPage(_) => (),
         }
     }
 }
@@ -254,6 +859,14 @@ impl MasterUI {
 pub trait EmitStateChange {
     fn emit(&mut self, sc: ShowStateChange);
 }
+
+/// An emitter that discards every state change.
+/// Useful when modifying show state that isn't currently displayed on any UI.
+pub struct DummyEmitter;
+
+impl EmitStateChange for DummyEmitter {
+    fn emit(&mut self, _: ShowStateChange) {}
+}
 pub trait EmitMasterUIStateChange {
     fn emit_master_ui_state_change(&mut self, sc: StateChange);
 }
@@ -269,6 +882,43 @@ pub enum ControlMessage {
     AnimationCopy,
     AnimationPaste,
     BeamGridButtonPress(BeamStoreAddr),
+    BeamGridButtonRelease(BeamStoreAddr),
+    /// Recall a beam store slot into the current channel, whatever mode the
+    /// beam store is in.  Unlike a grid button press, this never saves,
+    /// deletes, or asks for confirmation.
+    RecallBeam(BeamStoreAddr),
+    /// Recall the next occupied beam store slot into the current channel.
+    RecallNextBeam,
+    ToggleCloneArmed,
+    /// Stop flashing the alert indicator.
+    AcknowledgeAlerts,
+    /// Replace the beam in the current channel with a fresh beam of the type
+    /// at this index into Beam::type_names.
+    SelectBeamType(usize),
+    /// Arm or disarm saving into the animation preset library.
+    ToggleSaveAnimationPreset,
+    AnimationPresetButtonPress(usize),
+    /// Recall random beams from the current page of the beam store,
+    /// immediately or when the provided clock next ticks.
+    Shuffle(ShuffleTarget, Option<ClockIdx>),
+    /// Replace the metadata of a beam store slot.
+    EditSlot(BeamStoreAddr, SlotMeta),
+    /// Start or stop listening to the current animation.
+    ToggleListening,
+    /// Show or leave the clock page.
+    ToggleClockPage,
+    /// Spread rotated copies of the current tunnel across this many channels
+    /// and link them.  One unlinks the current channel.
+    Symmetrize(usize),
+    /// Replace the contents of the mixer with a look.
+    RecallLook(Look),
+}
+
+/// Which channels a shuffle replaces the beams of.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShuffleTarget {
+    Current,
+    All,
 }
 
 pub enum StateChange {
@@ -278,6 +928,29 @@ pub enum StateChange {
     // Note that when provided as a control, this acts like a toggle.
     // One press sets the mode, a second press sets back to idle.
     BeamStoreState(BeamStoreState),
+    /// While armed, selecting a channel first clones the current channel
+    /// into it.
+    CloneArmed(bool),
+    /// True while there are unacknowledged alerts.
+    Alerting(bool),
+    /// The type of the beam in the current channel, as an index into
+    /// Beam::type_names, or None if it is a look.
+    BeamType(Option<usize>),
+    /// While armed, pressing an animation preset button saves the current
+    /// animation into it.
+    PresetSaveArmed(bool),
+    /// Whether the animation preset at this index holds an animation.
+    AnimationPresetButton((usize, bool)),
+    /// While listening, the raw output of the current animation is sent to
+    /// the controllers, whatever its weight, and the current beam is
+    /// previewed on the diagnostic layer with the animation at full weight.
+    Listening(bool),
+    /// The raw output of the current animation, while listening.
+    ListenLevel(BipolarFloat),
+    /// While true, the controllers show the clock page, which puts the rate,
+    /// submaster level, tap and one-shot of every clock on the controls
+    /// otherwise used for tunnels and animations.
+    ClockPage(bool),
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -289,19 +962,156 @@ pub enum BeamStoreState {
     LookEdit,
 }
 
+/// Counts of beam store operations.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct BeamStoreStats {
+    pub beams_saved: u64,
+    pub looks_saved: u64,
+    /// Beams recalled into the mixer, from the grid or by stepping.
+    pub recalled: u64,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum BeamButtonState {
     Empty,
     Beam,
     Look,
+    /// The slot is locked, whatever it holds.
+    Locked,
+    /// A press saving over or deleting the slot awaits confirmation.
+    Confirming,
 }
 
 impl BeamButtonState {
-    pub fn from_beam(beam: &Option<Beam>) -> Self {
+    pub fn new(beam: &Option<Beam>, locked: bool) -> Self {
         match beam {
-            Some(Beam::Tunnel(_)) => Self::Beam,
+            _ if locked => Self::Locked,
+            Some(Beam::Tunnel(_)) | Some(Beam::Generator(_)) => Self::Beam,
             Some(Beam::Look(_)) => Self::Look,
             None => Self::Empty,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tunnel::{ControlMessage as TunnelControlMessage, StateChange as TunnelStateChange};
+    use tunnels_lib::assert_almost_eq;
+
+    fn tunnel(mixer: &mut Mixer, chan: usize) -> Tunnel {
+        match mixer.beam(ChannelIdx(chan)) {
+            Beam::Tunnel(t) => t.clone(),
+            _ => panic!("channel {} is not a tunnel", chan),
+        }
+    }
+
+    fn thickness(mixer: &mut Mixer, chan: usize) -> f64 {
+        tunnel(mixer, chan).render(UnipolarFloat::ONE, false, 1.0, &ClockBank::new())[0].thickness
+    }
+
+    #[test]
+    fn test_symmetrize() {
+        let mut ui = MasterUI::new(1);
+        let mut mixer = Mixer::new(1);
+        let mut clocks = ClockBank::new();
+        let mut control = |ui: &mut MasterUI, mixer: &mut Mixer, msg| {
+            ui.handle_control_message(msg, mixer, &mut clocks, &mut DummyEmitter)
+        };
+        control(
+            &mut ui,
+            &mut mixer,
+            ShowControlMessage::MasterUI(ControlMessage::Symmetrize(4)),
+        );
+        for chan in 0..4 {
+            assert_almost_eq(
+                chan as f64 / 4.0,
+                tunnel(&mut mixer, chan).rotation_offset().val(),
+            );
+        }
+
+        // Editing the current channel edits every copy, but no other channel.
+        control(
+            &mut ui,
+            &mut mixer,
+            ShowControlMessage::Tunnel(TunnelControlMessage::Set(TunnelStateChange::Thickness(
+                UnipolarFloat::ONE,
+            ))),
+        );
+        let edited = thickness(&mut mixer, 0);
+        for chan in 1..4 {
+            assert_eq!(edited, thickness(&mut mixer, chan));
+            assert_almost_eq(
+                chan as f64 / 4.0,
+                tunnel(&mut mixer, chan).rotation_offset().val(),
+            );
+        }
+        assert_ne!(edited, thickness(&mut mixer, 4));
+
+        // Once unlinked, the copies keep their rotation but no longer follow.
+        control(
+            &mut ui,
+            &mut mixer,
+            ShowControlMessage::MasterUI(ControlMessage::Set(StateChange::Channel(ChannelIdx(2)))),
+        );
+        control(
+            &mut ui,
+            &mut mixer,
+            ShowControlMessage::MasterUI(ControlMessage::Symmetrize(1)),
+        );
+        control(
+            &mut ui,
+            &mut mixer,
+            ShowControlMessage::Tunnel(TunnelControlMessage::Set(TunnelStateChange::Thickness(
+                UnipolarFloat::ZERO,
+            ))),
+        );
+        assert_ne!(edited, thickness(&mut mixer, 2));
+        assert_eq!(edited, thickness(&mut mixer, 3));
+        assert_eq!(
+            vec![vec![ChannelIdx(0), ChannelIdx(1), ChannelIdx(3)]],
+            ui.symmetry_groups
+        );
+    }
+
+    #[test]
+    fn test_recall_beam_ignores_beam_store_mode() {
+        let mut ui = MasterUI::new(1);
+        let mut mixer = Mixer::new(1);
+        let mut clocks = ClockBank::new();
+        let mut control = |ui: &mut MasterUI, mixer: &mut Mixer, msg| {
+            ui.handle_control_message(msg, mixer, &mut clocks, &mut DummyEmitter)
+        };
+        let set_thickness = |thickness| {
+            ShowControlMessage::Tunnel(TunnelControlMessage::Set(TunnelStateChange::Thickness(
+                thickness,
+            )))
+        };
+        let addr = BeamStoreAddr { row: 0, col: 0 };
+        control(&mut ui, &mut mixer, set_thickness(UnipolarFloat::ONE));
+        let stored = thickness(&mut mixer, 0);
+        ui.beam_store
+            .put(addr, Some(mixer.beam(ChannelIdx(0)).clone()));
+        control(&mut ui, &mut mixer, set_thickness(UnipolarFloat::ZERO));
+        assert_ne!(stored, thickness(&mut mixer, 0));
+
+        for state in [
+            BeamStoreState::BeamSave,
+            BeamStoreState::LookSave,
+            BeamStoreState::Delete,
+            BeamStoreState::LookEdit,
+        ] {
+            control(&mut ui, &mut mixer, set_thickness(UnipolarFloat::ZERO));
+            ui.beam_store_state = state;
+            control(
+                &mut ui,
+                &mut mixer,
+                ShowControlMessage::MasterUI(ControlMessage::RecallBeam(addr)),
+            );
+            assert_eq!(stored, thickness(&mut mixer, 0));
+            assert!(state == ui.beam_store_state);
+            assert!(ui.beam_store.slot(addr).is_some());
+            assert_eq!(None, ui.confirmation.pending());
+        }
+    }
+}
@@ -1,14 +1,16 @@
-use log::{error, warn};
 use midir::{MidiIO, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection, SendError};
 use serde::{Deserialize, Serialize};
 use simple_error::bail;
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     error::Error,
     fmt,
-    sync::mpsc::{channel, Receiver, Sender},
-    time::Duration,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
 };
+use tracing::{error, warn};
+use tunnels_lib::queue::{bounded, DropSender};
 
 use crate::device::Device;
 
@@ -110,6 +112,31 @@ pub const fn event(mapping: Mapping, value: u8) -> Event {
     Event { mapping, value }
 }
 
+/// A midi realtime message, sent by another device to share its tempo.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Realtime {
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+impl Realtime {
+    /// Return None if the status byte isn't a realtime message we handle.
+    pub fn parse(status: u8) -> Option<Self> {
+        match status {
+            0xF8 => Some(Self::Clock),
+            0xFA => Some(Self::Start),
+            0xFB => Some(Self::Continue),
+            0xFC => Some(Self::Stop),
+            _ => None,
+        }
+    }
+}
+
+/// Status bytes from here up are realtime messages, a single byte long.
+const REALTIME_STATUS: u8 = 0xF8;
+
 #[allow(dead_code)]
 // Return the available ports by name,
 pub fn list_ports() -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
@@ -143,6 +170,8 @@ pub struct Output {
     name: String,
     conn: MidiOutputConnection,
     device: Device,
+    /// True once a send to this output has failed.
+    lost: bool,
 }
 
 impl Output {
@@ -150,7 +179,12 @@ impl Output {
         let output = MidiOutput::new("tunnels")?;
         let port = get_named_port(&output, &name)?;
         let conn = output.connect(&port, &name)?;
-        Ok(Self { name, conn, device })
+        Ok(Self {
+            name,
+            conn,
+            device,
+            lost: false,
+        })
     }
 
     pub fn send(&mut self, event: Event) -> Result<(), SendError> {
@@ -178,7 +212,7 @@ impl Input {
     pub fn new(
         name: String,
         device: Device,
-        sender: Sender<(Device, Event)>,
+        sender: DropSender<(Instant, Device, Event)>,
     ) -> Result<Self, Box<dyn Error>> {
         let input = MidiInput::new("tunnels")?;
         let port = get_named_port(&input, &name)?;
@@ -188,6 +222,10 @@ impl Input {
             &port,
             &name,
             move |_, msg: &[u8], _| {
+                // Ignore clock and active sensing from control surfaces.
+                if msg[0] >= REALTIME_STATUS {
+                    return;
+                }
                 let event_type = match msg[0] >> 4 {
                     8 => EventType::NoteOff,
                     9 => EventType::NoteOn,
@@ -203,6 +241,7 @@ impl Input {
                 let channel = msg[0] & 15;
                 sender
                     .send((
+                        Instant::now(),
                         device,
                         Event {
                             mapping: Mapping {
@@ -221,24 +260,68 @@ impl Input {
     }
 }
 
+/// An input that only listens for midi clock.
+pub struct ClockInput {
+    _conn: MidiInputConnection<()>,
+}
+
+impl ClockInput {
+    pub fn new(
+        name: String,
+        sender: DropSender<(Instant, Realtime)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let input = MidiInput::new("tunnels")?;
+        let port = get_named_port(&input, &name)?;
+        let conn = input.connect(
+            &port,
+            &name,
+            move |_, msg: &[u8], _| {
+                if let Some(msg) = msg.first().copied().and_then(Realtime::parse) {
+                    // Nothing to do if the show has gone.
+                    let _ = sender.send((Instant::now(), msg));
+                }
+            },
+            (),
+        )?;
+        Ok(Self { _conn: conn })
+    }
+}
+
 /// Maintain midi inputs and outputs.
 /// Aggregate input messages on a channel.
 /// Provide synchronous dispatch for outgoing messages based on device type.
 pub struct Manager {
     inputs: Vec<Input>,
     outputs: Vec<Output>,
-    send: Sender<(Device, Event)>,
-    recv: Receiver<(Device, Event)>,
+    send: DropSender<(Instant, Device, Event)>,
+    recv: Receiver<(Instant, Device, Event)>,
+    clock_inputs: Vec<ClockInput>,
+    send_realtime: DropSender<(Instant, Realtime)>,
+    recv_realtime: Receiver<(Instant, Realtime)>,
+    /// The most recent value sent to each control on each device type.
+    /// This is our best knowledge of what each control is displaying.
+    last_sent: HashMap<(Device, Mapping), u8>,
+    /// Names of outputs that have failed since last checked.
+    lost: Vec<String>,
 }
 
 impl Manager {
+    /// Most input events to hold for the show at once.
+    const INPUT_CAPACITY: usize = 1024;
+
     pub fn new() -> Self {
-        let (send, recv) = channel();
+        let (send, recv) = bounded(Self::INPUT_CAPACITY);
+        let (send_realtime, recv_realtime) = bounded(Self::INPUT_CAPACITY);
         Self {
             inputs: Vec::new(),
             outputs: Vec::new(),
             send,
             recv,
+            clock_inputs: Vec::new(),
+            send_realtime,
+            recv_realtime,
+            last_sent: HashMap::new(),
+            lost: Vec::new(),
         }
     }
 
@@ -255,19 +338,64 @@ impl Manager {
         Ok(())
     }
 
-    // Return a message if there is one pending on the receiver.
-    // Wait at most timeout for the message to appear.
-    pub fn receive(&self, timeout: Duration) -> Option<(Device, Event)> {
+    /// Listen for midi clock on the named input port.
+    pub fn add_clock_input(&mut self, name: String) -> Result<(), Box<dyn Error>> {
+        let input = ClockInput::new(name, self.send_realtime.clone())?;
+        self.clock_inputs.push(input);
+        Ok(())
+    }
+
+    /// Return every realtime message received since the last call, with the
+    /// time it arrived.
+    pub fn take_realtime(&self) -> impl Iterator<Item = (Instant, Realtime)> + '_ {
+        self.recv_realtime.try_iter()
+    }
+
+    /// Return a sender that injects events into the input stream.
+    /// This allows non-midi inputs to share the midi control path.
+    pub fn sender(&self) -> DropSender<(Instant, Device, Event)> {
+        self.send.clone()
+    }
+
+    // Return a message if there is one pending on the receiver, with the time
+    // it arrived.  Wait at most timeout for the message to appear.
+    pub fn receive(&self, timeout: Duration) -> Option<(Instant, Device, Event)> {
         self.recv.recv_timeout(timeout).ok()
     }
 
+    // Return a message if there is one pending on the receiver, without waiting.
+    pub fn try_receive(&self) -> Option<(Instant, Device, Event)> {
+        self.recv.try_recv().ok()
+    }
+
+    /// Return the number of input events dropped because the show wasn't
+    /// keeping up, since the last call.
+    pub fn take_dropped(&self) -> usize {
+        self.send.take_dropped() + self.send_realtime.take_dropped()
+    }
+
+    /// Return the value most recently sent to a control, if any.
+    pub fn last_sent(&self, device: Device, mapping: Mapping) -> Option<u8> {
+        self.last_sent.get(&(device, mapping)).copied()
+    }
+
+    /// Return the names of any outputs that have failed since the last call.
+    pub fn take_lost(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lost)
+    }
+
     // Send a message to the specified device type.
     // Error conditions are logged rather than returned.
     pub fn send(&mut self, device: Device, event: Event) {
+        self.last_sent.insert((device, event.mapping), event.value);
         for output in &mut self.outputs {
             if output.device == device {
                 if let Err(e) = output.send(event) {
                     error!("Failed to send midi event to {}: {}.", output.name, e);
+                    if !output.lost {
+                        output.lost = true;
+                        self.lost.push(output.name.clone());
+                    }
                 }
             }
         }
@@ -0,0 +1,140 @@
+//! Follow the tempo of another device from the midi clock it sends.
+//!
+//! Midi clock is a stream of pulses, 24 to a beat, along with start, stop,
+//! and continue messages for the sender's transport.  A clock synced to midi
+//! clock completes one cycle per beat, with its phase set from the count of
+//! pulses since the transport started rather than from an estimated tempo,
+//! so it stays locked to the beat however long the set runs.
+
+use std::time::{Duration, Instant};
+use tunnels_lib::number::Phase;
+
+use crate::midi::Realtime;
+
+/// Midi clock pulses per beat, fixed by the midi spec.
+pub const PULSES_PER_BEAT: u64 = 24;
+
+/// Where a synced clock should be.
+#[derive(Debug, Copy, Clone)]
+pub struct MidiSync {
+    pub phase: Phase,
+    /// Beats per second, or None while the transport is stopped.
+    pub rate: Option<f64>,
+}
+
+/// Track the transport and tempo of incoming midi clock.
+#[derive(Debug, Clone, Default)]
+pub struct MidiClock {
+    /// Pulses since the transport started.
+    pulses: u64,
+    last_pulse: Option<Instant>,
+    /// Smoothed time between pulses, in seconds.
+    pulse_period: Option<f64>,
+    stopped: bool,
+}
+
+impl MidiClock {
+    /// Once pulses have stopped arriving for this long, stop following them.
+    const TIMEOUT: Duration = Duration::from_millis(500);
+    /// Pulses further apart than this, in seconds, are slower than 20 BPM
+    /// and don't count towards the tempo.
+    const MAX_PULSE_PERIOD: f64 = 0.125;
+    /// How much of each new pulse period goes into the tempo estimate.
+    const SMOOTHING: f64 = 0.1;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle a realtime message received at the given time.
+    pub fn receive(&mut self, msg: Realtime, at: Instant) {
+        match msg {
+            Realtime::Clock => {
+                if let Some(last) = self.last_pulse {
+                    let period = at.saturating_duration_since(last).as_secs_f64();
+                    if period < Self::MAX_PULSE_PERIOD {
+                        self.pulse_period = Some(match self.pulse_period {
+                            Some(p) => p + Self::SMOOTHING * (period - p),
+                            None => period,
+                        });
+                    }
+                }
+                self.last_pulse = Some(at);
+                if !self.stopped {
+                    self.pulses += 1;
+                }
+            }
+            Realtime::Start => {
+                self.pulses = 0;
+                self.stopped = false;
+            }
+            Realtime::Continue => self.stopped = false,
+            Realtime::Stop => self.stopped = true,
+        }
+    }
+
+    /// Return where synced clocks should be now, or None if there is no midi
+    /// clock to follow.
+    pub fn sync(&self, now: Instant) -> Option<MidiSync> {
+        let last = self.last_pulse?;
+        let period = self.pulse_period?;
+        let pulse = (self.pulses % PULSES_PER_BEAT) as f64;
+        if self.stopped {
+            return Some(MidiSync {
+                phase: Phase::new(pulse / PULSES_PER_BEAT as f64),
+                rate: None,
+            });
+        }
+        let since = now.saturating_duration_since(last);
+        if since > Self::TIMEOUT {
+            return None;
+        }
+        // Move smoothly between pulses, but never past the next one.
+        let fraction = (since.as_secs_f64() / period).min(1.);
+        Some(MidiSync {
+            phase: Phase::new((pulse + fraction) / PULSES_PER_BEAT as f64),
+            rate: Some(1. / (period * PULSES_PER_BEAT as f64)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_midi_clock() {
+        let t0 = Instant::now();
+        // 125 BPM is 20 ms per pulse.
+        let pulse = Duration::from_millis(20);
+        let mut clock = MidiClock::new();
+        assert!(clock.sync(t0).is_none());
+
+        clock.receive(Realtime::Start, t0);
+        for i in 1..=30 {
+            clock.receive(Realtime::Clock, t0 + pulse * i);
+        }
+        let now = t0 + pulse * 30 + pulse / 2;
+        let sync = clock.sync(now).unwrap();
+        assert!((sync.phase.val() - 6.5 / 24.).abs() < 1e-9);
+        assert!((sync.rate.unwrap() - 125. / 60.).abs() < 1e-9);
+
+        // Stopped, the phase holds.
+        clock.receive(Realtime::Stop, now);
+        clock.receive(Realtime::Clock, t0 + pulse * 31);
+        let sync = clock.sync(t0 + pulse * 40).unwrap();
+        assert!((sync.phase.val() - 6. / 24.).abs() < 1e-9);
+        assert!(sync.rate.is_none());
+
+        // Start goes back to the top of the beat.
+        clock.receive(Realtime::Start, t0 + pulse * 32);
+        clock.receive(Realtime::Clock, t0 + pulse * 33);
+        let sync = clock.sync(t0 + pulse * 33).unwrap();
+        assert!((sync.phase.val() - 1. / 24.).abs() < 1e-9);
+
+        // Losing the clock stops syncing.
+        assert!(clock
+            .sync(t0 + pulse * 33 + MidiClock::TIMEOUT * 2)
+            .is_none());
+    }
+}
@@ -1,48 +1,129 @@
 mod animation;
+mod audio;
+mod autopilot;
 mod clock;
+mod color_organ;
+mod dmx;
+mod encoder;
+mod gamepad;
 mod master_ui;
 mod mixer;
+mod palette;
+mod takeover;
+mod trigger;
 mod tunnel;
+mod video_out;
 
-use std::{collections::HashMap, time::Duration};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
 
 use crate::{
+    beam_store::BeamStore,
+    config::ShowConfig,
     device::Device,
-    master_ui::EmitStateChange,
-    midi::{Event, Manager, Mapping},
+    master_ui::{EmitStateChange, StateChange as MasterUIStateChange},
+    midi::{Event, EventType, Manager, Mapping},
     show::ControlMessage,
     show::StateChange,
 };
 
-use tunnels_lib::number::{BipolarFloat, UnipolarFloat};
+use tunnels_lib::{
+    control_protocol::EventKind,
+    number::{BipolarFloat, UnipolarFloat},
+};
 
 use self::animation::{map_animation_controls, update_animation_control};
-use self::clock::{map_clock_controls, update_clock_control};
-use self::master_ui::{map_master_ui_controls, update_master_ui_control};
+use self::audio::update_audio_control;
+use self::autopilot::{map_autopilot_controls, update_autopilot_control};
+use self::clock::{map_clock_controls, map_clock_page_controls, update_clock_control};
+use self::color_organ::{map_color_organ_controls, update_color_organ_control};
+use self::dmx::map_dmx_controls;
+use self::encoder::RelativeEncoder;
+use self::gamepad::map_gamepad_controls;
+use self::master_ui::{
+    map_master_ui_controls, update_flash_limit_control, update_master_ui_control,
+    update_recall_filter_control,
+};
 use self::mixer::{map_mixer_controls, update_mixer_control};
+use self::palette::{map_palette_controls, update_palette_control};
+use self::takeover::SoftTakeover;
+use self::trigger::map_trigger_controls;
 use self::tunnel::{map_tunnel_controls, update_tunnel_control};
+use self::video_out::{
+    map_video_out_controls, update_output_profile_control, update_video_out_control,
+};
 
+pub use self::animation::animation_param_control;
+pub use self::autopilot::autopilot_param_control;
+pub use self::clock::clock_param_control;
+pub use self::color_organ::{color_organ_band_control, color_organ_param_control};
+pub use self::encoder::EncoderConfig;
+pub use self::master_ui::{
+    animation_preset_control, beam_store_slot_control, beam_type_control,
+    master_ui_param_control,
+};
 pub use self::mixer::PAGE_SIZE as MIXER_CHANNELS_PER_PAGE;
+pub use self::mixer::{channel_param_control, mixer_param_control, video_channel_control};
+pub use self::palette::{palette_param_control, palette_select_control};
+pub use self::tunnel::tunnel_param_control;
+pub use self::video_out::{geometry_preset_control, output_profile_control};
 
 type ControlMessageCreator = Box<dyn Fn(u8) -> ControlMessage>;
 
-pub struct ControlMap(pub HashMap<(Device, Mapping), ControlMessageCreator>);
+pub struct ControlMap {
+    creators: HashMap<(Device, Mapping), ControlMessageCreator>,
+    /// Faders and knobs, whose bursts of changes may collapse into the latest
+    /// one.  Every event from any other control counts.
+    continuous: HashSet<(Device, Mapping)>,
+}
 
 impl ControlMap {
     fn new() -> Self {
-        Self(HashMap::new())
+        Self {
+            creators: HashMap::new(),
+            continuous: HashSet::new(),
+        }
     }
+
+    /// Add a control every event of which counts, such as a button.
     pub fn add(&mut self, device: Device, mapping: Mapping, creator: ControlMessageCreator) {
-        if self.0.insert((device, mapping), creator).is_some() {
+        if self.creators.insert((device, mapping), creator).is_some() {
             panic!("duplicate control definition: {:?} {:?}", device, mapping);
         }
     }
 
+    /// Add a fader or knob, where only the latest of several changes counts.
+    pub fn add_continuous(
+        &mut self,
+        device: Device,
+        mapping: Mapping,
+        creator: ControlMessageCreator,
+    ) {
+        self.add(device, mapping, creator);
+        self.continuous.insert((device, mapping));
+    }
+
+    /// Add controls for a single device.
+    pub fn device(&mut self, device: Device) -> DeviceControlMap<'_> {
+        DeviceControlMap { map: self, device }
+    }
+
+    fn get(&self, device: Device, mapping: Mapping) -> Option<&ControlMessageCreator> {
+        self.creators.get(&(device, mapping))
+    }
+
+    fn is_continuous(&self, device: Device, mapping: Mapping) -> bool {
+        self.continuous.contains(&(device, mapping))
+    }
+
     #[allow(unused)]
     // Produce a report describing all controls bound to all devices.
     pub fn report(&self) -> String {
         let mut controls: HashMap<Device, Vec<Mapping>> = HashMap::new();
-        for ((device, mapping), _) in self.0.iter() {
+        for (device, mapping) in self.creators.keys() {
             match controls.get_mut(device) {
                 Some(mappings) => {
                     mappings.push(*mapping);
@@ -66,14 +147,52 @@ impl ControlMap {
         report.join("\n")
     }
 }
+
+/// Adds controls to a map for a single device.
+pub struct DeviceControlMap<'a> {
+    map: &'a mut ControlMap,
+    device: Device,
+}
+
+impl DeviceControlMap<'_> {
+    pub fn add(&mut self, mapping: Mapping, creator: ControlMessageCreator) {
+        self.map.add(self.device, mapping, creator);
+    }
+
+    pub fn add_continuous(&mut self, mapping: Mapping, creator: ControlMessageCreator) {
+        self.map.add_continuous(self.device, mapping, creator);
+    }
+}
+
 pub struct Dispatcher {
     map: ControlMap,
+    /// Controls that replace those in map while the clock page is showing.
+    clock_page_map: ControlMap,
+    /// True while the clock page is showing, following the master UI.
+    clock_page: bool,
+    /// Controls that should be interpreted as relative encoders.
+    encoders: HashMap<(Device, Mapping), RelativeEncoder>,
+    soft_takeover: SoftTakeover,
     pub manager: Manager,
 }
 
 impl Dispatcher {
     /// Instantiate the master midi control dispatcher.
-    pub fn new(manager: Manager) -> Self {
+    pub fn new(manager: Manager, config: &ShowConfig) -> Self {
+        let mut dispatcher = Self {
+            map: ControlMap::new(),
+            clock_page_map: ControlMap::new(),
+            clock_page: false,
+            encoders: HashMap::new(),
+            soft_takeover: SoftTakeover::new(Vec::new()),
+            manager,
+        };
+        dispatcher.configure(config);
+        dispatcher
+    }
+
+    /// Replace the control mappings with those described by a config.
+    pub fn configure(&mut self, config: &ShowConfig) {
         let mut map = ControlMap::new();
         map_tunnel_controls(Device::AkaiApc40, &mut map);
         map_tunnel_controls(Device::TouchOsc, &mut map);
@@ -81,33 +200,123 @@ impl Dispatcher {
         map_animation_controls(Device::AkaiApc40, &mut map);
         map_animation_controls(Device::TouchOsc, &mut map);
 
-        map_mixer_controls(Device::AkaiApc40, 0, &mut map);
-        map_mixer_controls(Device::AkaiApc20, 1, &mut map);
-        map_mixer_controls(Device::TouchOsc, 0, &mut map);
         // FIXME: need to split out the video controls from the mixer controls
-        // map_mixer_controls(Device::TouchOsc, 1, &mut map);
+        // to put a second page on TouchOSC.
+        for (device, page) in mixer_surfaces() {
+            map_mixer_controls(device, page, &mut map);
+        }
 
-        map_master_ui_controls(Device::AkaiApc40, 0, &mut map);
-        map_master_ui_controls(Device::AkaiApc20, 1, &mut map);
-        map_master_ui_controls(Device::TouchOsc, 0, &mut map);
-        // FIXME: need to split out the pagewise controls from the non-pagewise controls
-        // map_master_ui_controls(Device::TouchOsc, 1, &mut map);
+        // FIXME: need to split out the pagewise controls from the non-pagewise
+        // controls to put a second page on TouchOSC.
+        for (device, page) in beam_grid_surfaces() {
+            map_master_ui_controls(device, page, &mut map);
+        }
 
         map_clock_controls(Device::BehringerCmdMM1, &mut map);
-        Self { map, manager }
+
+        let mut clock_page_map = ControlMap::new();
+        map_clock_page_controls(Device::AkaiApc40, &mut clock_page_map);
+        map_clock_page_controls(Device::TouchOsc, &mut clock_page_map);
+
+        map_autopilot_controls(Device::AkaiApc40, &mut map);
+        map_autopilot_controls(Device::TouchOsc, &mut map);
+
+        map_color_organ_controls(Device::TouchOsc, &mut map);
+        map_palette_controls(Device::TouchOsc, &mut map);
+
+        map_video_out_controls(Device::TouchOsc, &mut map);
+
+        map_gamepad_controls(&mut map);
+
+        if let Some(triggers) = &config.triggers {
+            map_trigger_controls(&triggers.inputs, &mut map);
+        }
+
+        if let Some(dmx) = &config.dmx {
+            map_dmx_controls(&dmx.inputs, &mut map);
+        }
+
+        let encoders: HashMap<_, _> = config
+            .encoders
+            .iter()
+            .map(|cfg| ((cfg.device, cfg.mapping()), RelativeEncoder::from(cfg)))
+            .collect();
+        // Every absolute control on the listed devices, on either page.
+        let takeover_controls = map
+            .creators
+            .keys()
+            .chain(clock_page_map.creators.keys())
+            .filter(|(device, mapping)| {
+                config.soft_takeover.contains(device)
+                    && mapping.event_type == EventType::ControlChange
+                    && !encoders.contains_key(&(*device, *mapping))
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        self.soft_takeover = SoftTakeover::new(takeover_controls);
+        self.map = map;
+        self.clock_page_map = clock_page_map;
+        self.encoders = encoders;
     }
 
-    pub fn receive(&self, timeout: Duration) -> Option<(Device, Event)> {
+    pub fn receive(&self, timeout: Duration) -> Option<(Instant, Device, Event)> {
         self.manager.receive(timeout)
     }
 
+    pub fn try_receive(&self) -> Option<(Instant, Device, Event)> {
+        self.manager.try_receive()
+    }
+
+    /// Return true if a burst of events from this control can be collapsed
+    /// into the latest one without changing their effect.
+    /// Only faders and knobs qualify; every encoder step counts, and soft
+    /// takeover needs to see a control pass through the current value.
+    pub fn collapsible(&self, device: Device, event: &Event) -> bool {
+        let map = match self.clock_page_map.get(device, event.mapping) {
+            Some(_) if self.clock_page => &self.clock_page_map,
+            _ => &self.map,
+        };
+        map.is_continuous(device, event.mapping)
+            && !self.encoders.contains_key(&(device, event.mapping))
+            && !self.soft_takeover.applies_to(device, event.mapping)
+    }
+
     /// Map a midi source device and event into a tunnels control message.
     /// Return None if no mapping is registered.
-    pub fn dispatch(&self, device: Device, event: Event) -> Option<ControlMessage> {
-        self.map
-            .0
-            .get(&(device, event.mapping))
-            .map(|c| c(event.value))
+    pub fn dispatch(&mut self, device: Device, event: Event) -> Option<ControlMessage> {
+        let key = (device, event.mapping);
+        let creator = match self.clock_page_map.get(device, event.mapping) {
+            Some(creator) if self.clock_page => creator,
+            _ => self.map.get(device, event.mapping)?,
+        };
+        let value = match self.encoders.get(&key) {
+            Some(encoder) => {
+                let current = self.manager.last_sent(device, event.mapping).unwrap_or(0);
+                let value = encoder.apply(current, event.value);
+                // Echo the new position, so we remember where we are even if
+                // the control isn't otherwise updated by state changes.
+                self.manager.send(
+                    device,
+                    Event {
+                        mapping: event.mapping,
+                        value,
+                    },
+                );
+                value
+            }
+            None => {
+                if !self.soft_takeover.accept(
+                    device,
+                    event.mapping,
+                    event.value,
+                    self.manager.last_sent(device, event.mapping),
+                ) {
+                    return None;
+                }
+                event.value
+            }
+        };
+        Some(creator(value))
     }
 }
 
@@ -115,15 +324,52 @@ impl EmitStateChange for Dispatcher {
     /// Map application state changes into UI update midi messages.
     fn emit(&mut self, sc: StateChange) {
         match sc {
+            // The clock page reuses the tunnel and animation controls, so
+            // hold back their state until the page is left, when the master
+            // UI emits everything again.
+            StateChange::Tunnel(_) | StateChange::Animation(_) if self.clock_page => (),
             StateChange::Tunnel(sc) => update_tunnel_control(sc, &mut self.manager),
             StateChange::Animation(sc) => update_animation_control(sc, &mut self.manager),
             StateChange::Mixer(sc) => update_mixer_control(sc, &mut self.manager),
-            StateChange::Clock(sc) => update_clock_control(sc, &mut self.manager),
-            StateChange::MasterUI(sc) => update_master_ui_control(sc, &mut self.manager),
+            StateChange::Clock(sc) => update_clock_control(sc, self.clock_page, &mut self.manager),
+            StateChange::MasterUI(sc) => {
+                if let MasterUIStateChange::ClockPage(v) = sc {
+                    self.clock_page = v;
+                }
+                update_master_ui_control(sc, &mut self.manager)
+            }
+            StateChange::Autopilot(sc) => update_autopilot_control(sc, &mut self.manager),
+            StateChange::ColorOrgan(sc) => update_color_organ_control(sc, &mut self.manager),
+            StateChange::Palette(sc) => update_palette_control(sc, &mut self.manager),
+            StateChange::VideoOut(sc) => update_video_out_control(sc, &mut self.manager),
+            StateChange::OutputProfile(sc) => update_output_profile_control(sc, &mut self.manager),
+            StateChange::RecallFilter(sc) => update_recall_filter_control(sc, &mut self.manager),
+            StateChange::Audio(sc) => update_audio_control(sc, &mut self.manager),
+            StateChange::FlashLimit(sc) => update_flash_limit_control(sc, &mut self.manager),
         }
     }
 }
 
+/// The MIDI devices with room for a page of mixer channels, and the page
+/// each one controls.
+fn mixer_surfaces() -> impl Iterator<Item = (Device, usize)> {
+    Device::MIDI.iter().filter_map(|device| {
+        let caps = device.capabilities();
+        (caps.faders >= MIXER_CHANNELS_PER_PAGE).then(|| (*device, caps.page()))
+    })
+}
+
+/// The MIDI devices with room for a page of the beam store grid, and the page
+/// each one controls.
+fn beam_grid_surfaces() -> impl Iterator<Item = (Device, usize)> {
+    Device::MIDI.iter().filter_map(|device| {
+        let caps = device.capabilities();
+        let (rows, cols) = caps.pads;
+        (rows >= BeamStore::N_ROWS && cols >= BeamStore::COLS_PER_PAGE)
+            .then(|| (*device, caps.page()))
+    })
+}
+
 fn bipolar_from_midi(val: u8) -> BipolarFloat {
     let denom = if val > 64 { 63. } else { 64. };
     BipolarFloat::new((val as f64 - 64.) / denom)
@@ -176,3 +422,107 @@ impl RadioButtons {
         }
     }
 }
+
+/// How a front end drives a parameter, by sending events to the control
+/// server as if they came from a control surface.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum ParamControl {
+    /// A single control whose value sets the parameter.  Unipolar values
+    /// span 0 to 127, bipolar values are centered on 64, and integers count
+    /// up from their minimum.  Buttons and toggles act on any press.
+    Value(SurfaceControl),
+    /// One button per value of a choice or integer parameter, in order.
+    Buttons(Vec<SurfaceControl>),
+}
+
+impl ParamControl {
+    fn value(device: Device, mapping: Mapping) -> Self {
+        Self::Value(SurfaceControl::new(device, mapping))
+    }
+
+    fn buttons(device: Device, mappings: impl IntoIterator<Item = Mapping>) -> Self {
+        Self::Buttons(
+            mappings
+                .into_iter()
+                .map(|mapping| SurfaceControl::new(device, mapping))
+                .collect(),
+        )
+    }
+}
+
+/// A control on a surface, in the terms of the control server protocol.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct SurfaceControl {
+    pub device: Device,
+    pub kind: EventKind,
+    pub channel: u8,
+    pub control: u8,
+}
+
+impl SurfaceControl {
+    fn new(device: Device, mapping: Mapping) -> Self {
+        Self {
+            device,
+            kind: match mapping.event_type {
+                EventType::NoteOn => EventKind::NoteOn,
+                EventType::NoteOff => EventKind::NoteOff,
+                EventType::ControlChange => EventKind::ControlChange,
+            },
+            channel: mapping.channel,
+            control: mapping.control,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_surfaces() {
+        // The CMD MM-1 is too small for a page of the mixer.
+        let expected = vec![
+            (Device::TouchOsc, 0),
+            (Device::AkaiApc40, 0),
+            (Device::AkaiApc20, 1),
+        ];
+        assert_eq!(expected, mixer_surfaces().collect::<Vec<_>>());
+        assert_eq!(expected, beam_grid_surfaces().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_clock_page() {
+        use crate::{
+            clock::{ControlMessage as ClockMessage, StateChange as ClockChange},
+            clock_bank::{ClockIdx, ControlMessage as ClockBankMessage},
+            midi::{cc_ch0, event},
+        };
+
+        let mut dispatcher = Dispatcher::new(Manager::new(), &ShowConfig::default());
+        let knob = event(cc_ch0(17), 127);
+        assert!(matches!(
+            dispatcher.dispatch(Device::AkaiApc40, knob),
+            Some(ControlMessage::Tunnel(_))
+        ));
+
+        dispatcher.emit(StateChange::MasterUI(MasterUIStateChange::ClockPage(true)));
+        assert!(matches!(
+            dispatcher.dispatch(Device::AkaiApc40, knob),
+            Some(ControlMessage::Clock(ClockBankMessage {
+                channel: ClockIdx(1),
+                msg: ClockMessage::Set(ClockChange::Rate(_)),
+            }))
+        ));
+        // Controls the page doesn't use keep working.
+        assert!(matches!(
+            dispatcher.dispatch(Device::AkaiApc40, event(cc_ch0(49), 127)),
+            Some(ControlMessage::Animation(_))
+        ));
+
+        dispatcher.emit(StateChange::MasterUI(MasterUIStateChange::ClockPage(false)));
+        assert!(matches!(
+            dispatcher.dispatch(Device::AkaiApc40, knob),
+            Some(ControlMessage::Tunnel(_))
+        ));
+    }
+}
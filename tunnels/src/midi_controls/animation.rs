@@ -3,16 +3,18 @@ use crate::{
     animation::StateChange,
     animation::Target as AnimationTarget,
     animation::Waveform as WaveformType,
+    audio::AudioSource as AudioSourceType,
     clock_bank::{ClockIdx, N_CLOCKS},
     device::Device,
     midi::{cc_ch0, event, note_on_ch0, note_on_ch1, Manager, Mapping},
+    params::ParamSpec,
     show::ControlMessage::Animation,
 };
 use lazy_static::lazy_static;
 
 use super::{
     bipolar_from_midi, bipolar_to_midi, unipolar_from_midi, unipolar_to_midi, ControlMap,
-    RadioButtons,
+    ParamControl, RadioButtons,
 };
 
 // knobs
@@ -20,6 +22,7 @@ const SPEED: Mapping = cc_ch0(48);
 const WEIGHT: Mapping = cc_ch0(49);
 const DUTY_CYCLE: Mapping = cc_ch0(50);
 const SMOOTHING: Mapping = cc_ch0(51);
+const PROBABILITY: Mapping = cc_ch0(55);
 
 // waveform type buttons
 const SINE: Mapping = note_on_ch0(24);
@@ -48,6 +51,24 @@ const INVERT: Mapping = note_on_ch1(1);
 
 const CLOCK_SELECT_CONTROL_OFFSET: i32 = 112;
 
+/// Audio source buttons start from this note, with following the waveform
+/// first and then each source in order.
+const AUDIO_SELECT_CONTROL_OFFSET: u8 = 8;
+
+fn audio_select_button(source: Option<AudioSourceType>) -> Mapping {
+    let index = match source {
+        None => 0,
+        Some(source) => {
+            AudioSourceType::ALL
+                .iter()
+                .position(|s| *s == source)
+                .unwrap()
+                + 1
+        }
+    };
+    note_on_ch1(AUDIO_SELECT_CONTROL_OFFSET + index as u8)
+}
+
 lazy_static! {
     static ref WAVEFORM_SELECT_BUTTONS: RadioButtons = RadioButtons {
         mappings: vec!(SINE, TRIANGLE, SQUARE, SAWTOOTH), off: 0, on: 1,
@@ -81,6 +102,14 @@ lazy_static! {
         off: 0,
         on: 1,
     };
+    static ref AUDIO_SELECT_BUTTONS: RadioButtons = RadioButtons {
+        mappings: std::iter::once(None)
+            .chain(AudioSourceType::ALL.iter().copied().map(Some))
+            .map(audio_select_button)
+            .collect(),
+        off: 0,
+        on: 1,
+    };
 }
 
 pub fn map_animation_controls(device: Device, map: &mut ControlMap) {
@@ -89,84 +118,127 @@ pub fn map_animation_controls(device: Device, map: &mut ControlMap) {
     use StateChange::*;
     use WaveformType::*;
 
-    let mut add = |mapping, creator| map.add(device, mapping, creator);
+    let mut map = map.device(device);
 
-    add(
+    map.add_continuous(
         SPEED,
         Box::new(|v| Animation(Set(Speed(bipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         WEIGHT,
         Box::new(|v| Animation(Set(Weight(unipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         DUTY_CYCLE,
         Box::new(|v| Animation(Set(DutyCycle(unipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         SMOOTHING,
         Box::new(|v| Animation(Set(Smoothing(unipolar_from_midi(v))))),
     );
+    map.add_continuous(
+        PROBABILITY,
+        Box::new(|v| Animation(Set(Probability(unipolar_from_midi(v))))),
+    );
 
     // waveform select
-    add(SINE, Box::new(|_| Animation(Set(Waveform(Sine)))));
-    add(TRIANGLE, Box::new(|_| Animation(Set(Waveform(Triangle)))));
-    add(SQUARE, Box::new(|_| Animation(Set(Waveform(Square)))));
-    add(SAWTOOTH, Box::new(|_| Animation(Set(Waveform(Sawtooth)))));
+    map.add(SINE, Box::new(|_| Animation(Set(Waveform(Sine)))));
+    map.add(TRIANGLE, Box::new(|_| Animation(Set(Waveform(Triangle)))));
+    map.add(SQUARE, Box::new(|_| Animation(Set(Waveform(Square)))));
+    map.add(SAWTOOTH, Box::new(|_| Animation(Set(Waveform(Sawtooth)))));
 
     // n periods select
     for n_periods in 0..16 {
-        add(
+        map.add(
             note_on_ch0(n_periods as u8),
             Box::new(move |_| Animation(Set(NPeriods(n_periods)))),
         );
     }
 
     // target select
-    add(ROTATION, Box::new(|_| Animation(Set(Target(Rotation)))));
-    add(THICKNESS, Box::new(|_| Animation(Set(Target(Thickness)))));
-    add(SIZE, Box::new(|_| Animation(Set(Target(Size)))));
-    add(
+    map.add(ROTATION, Box::new(|_| Animation(Set(Target(Rotation)))));
+    map.add(THICKNESS, Box::new(|_| Animation(Set(Target(Thickness)))));
+    map.add(SIZE, Box::new(|_| Animation(Set(Target(Size)))));
+    map.add(
         ASPECT_RATIO,
         Box::new(|_| Animation(Set(Target(AspectRatio)))),
     );
-    add(COLOR, Box::new(|_| Animation(Set(Target(Color)))));
-    add(
+    map.add(COLOR, Box::new(|_| Animation(Set(Target(Color)))));
+    map.add(
         COLOR_SPREAD,
         Box::new(|_| Animation(Set(Target(ColorSpread)))),
     );
-    add(
+    map.add(
         COLOR_PERIODICITY,
         Box::new(|_| Animation(Set(Target(ColorPeriodicity)))),
     );
-    add(
+    map.add(
         COLOR_SATURATION,
         Box::new(|_| Animation(Set(Target(ColorSaturation)))),
     );
-    add(
+    map.add(
         MARQUEE,
         Box::new(|_| Animation(Set(Target(MarqueeRotation)))),
     );
-    add(SEGMENTS, Box::new(|_| Animation(Set(Target(Segments)))));
-    add(BLACKING, Box::new(|_| Animation(Set(Target(Blacking)))));
-    add(POSITIONX, Box::new(|_| Animation(Set(Target(PositionX)))));
-    add(POSITIONY, Box::new(|_| Animation(Set(Target(PositionY)))));
+    map.add(SEGMENTS, Box::new(|_| Animation(Set(Target(Segments)))));
+    map.add(BLACKING, Box::new(|_| Animation(Set(Target(Blacking)))));
+    map.add(POSITIONX, Box::new(|_| Animation(Set(Target(PositionX)))));
+    map.add(POSITIONY, Box::new(|_| Animation(Set(Target(PositionY)))));
 
     // pulse/invert
-    add(PULSE, Box::new(|_| Animation(TogglePulse)));
-    add(INVERT, Box::new(|_| Animation(ToggleInvert)));
+    map.add(PULSE, Box::new(|_| Animation(TogglePulse)));
+    map.add(INVERT, Box::new(|_| Animation(ToggleInvert)));
 
     // clock select
-    add(
+    map.add(
         note_on_ch0((CLOCK_SELECT_CONTROL_OFFSET - 1) as u8),
         Box::new(|_| Animation(Set(ClockSource(None)))),
     );
     for clock_num in 0..N_CLOCKS as i32 {
-        add(
+        map.add(
             note_on_ch0((CLOCK_SELECT_CONTROL_OFFSET + clock_num) as u8),
             Box::new(move |_| Animation(Set(ClockSource(Some(ClockIdx(clock_num as usize)))))),
         );
     }
+
+    // audio source select
+    map.add(
+        audio_select_button(None),
+        Box::new(|_| Animation(Set(AudioSource(None)))),
+    );
+    for source in AudioSourceType::ALL.iter().copied() {
+        map.add(
+            audio_select_button(Some(source)),
+            Box::new(move |_| Animation(Set(AudioSource(Some(source))))),
+        );
+    }
+}
+
+/// The TouchOSC control that drives an animation parameter.
+/// Selecting, copying, and pasting animations belong to the master UI.
+pub fn animation_param_control(spec: &ParamSpec) -> Option<ParamControl> {
+    let value = |mapping| Some(ParamControl::value(Device::TouchOsc, mapping));
+    let buttons = |radio: &RadioButtons| {
+        Some(ParamControl::buttons(
+            Device::TouchOsc,
+            radio.mappings.iter().copied(),
+        ))
+    };
+    match spec.address {
+        "waveform" => buttons(&WAVEFORM_SELECT_BUTTONS),
+        "target" => buttons(&TARGET_SELECT_BUTTONS),
+        "speed" => value(SPEED),
+        "weight" => value(WEIGHT),
+        "duty_cycle" => value(DUTY_CYCLE),
+        "smoothing" => value(SMOOTHING),
+        "n_periods" => buttons(&N_PERIODS_SELECT_BUTTONS),
+        "pulse" => value(PULSE),
+        "invert" => value(INVERT),
+        "clock_source" => buttons(&CLOCK_SELECT_BUTTONS),
+        "audio_source" => buttons(&AUDIO_SELECT_BUTTONS),
+        "probability" => value(PROBABILITY),
+        _ => None,
+    }
 }
 
 /// Emit midi messages to update UIs given the provided state change.
@@ -183,6 +255,7 @@ pub fn update_animation_control(sc: StateChange, manager: &mut Manager) {
         Weight(v) => send(event(WEIGHT, unipolar_to_midi(v))),
         DutyCycle(v) => send(event(DUTY_CYCLE, unipolar_to_midi(v))),
         Smoothing(v) => send(event(SMOOTHING, unipolar_to_midi(v))),
+        Probability(v) => send(event(PROBABILITY, unipolar_to_midi(v))),
         Waveform(v) => {
             use WaveformType::*;
             WAVEFORM_SELECT_BUTTONS.select(
@@ -229,5 +302,6 @@ pub fn update_animation_control(sc: StateChange, manager: &mut Manager) {
                 send,
             );
         }
+        AudioSource(v) => AUDIO_SELECT_BUTTONS.select(audio_select_button(v), send),
     }
 }
@@ -0,0 +1,21 @@
+//! Midi control declarations for audio input monitoring.
+
+use super::unipolar_to_midi;
+use crate::{
+    audio::StateChange,
+    device::Device,
+    midi::{cc, event, Manager, Mapping},
+};
+
+const MIDI_CHANNEL: u8 = 10;
+
+const LEVEL: Mapping = cc(MIDI_CHANNEL, 0);
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_audio_control(sc: StateChange, manager: &mut Manager) {
+    match sc {
+        StateChange::Level(v) => {
+            manager.send(Device::TouchOsc, event(LEVEL, unipolar_to_midi(v)));
+        }
+    }
+}
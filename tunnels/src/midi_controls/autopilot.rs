@@ -0,0 +1,42 @@
+//! Midi control declarations for the autopilot.
+
+use super::{ControlMap, ParamControl};
+use crate::{
+    autopilot::ControlMessage,
+    autopilot::StateChange,
+    device::Device,
+    midi::{event, note_on_ch1, Manager, Mapping},
+    params::ParamSpec,
+    show::ControlMessage::Autopilot,
+};
+
+const TOGGLE_ENABLED: Mapping = note_on_ch1(2);
+
+pub fn map_autopilot_controls(device: Device, map: &mut ControlMap) {
+    map.add(
+        device,
+        TOGGLE_ENABLED,
+        Box::new(|_| Autopilot(ControlMessage::ToggleEnabled)),
+    );
+}
+
+/// The TouchOSC control that drives an autopilot parameter.
+pub fn autopilot_param_control(spec: &ParamSpec) -> Option<ParamControl> {
+    match spec.address {
+        "enabled" => Some(ParamControl::value(Device::TouchOsc, TOGGLE_ENABLED)),
+        _ => None,
+    }
+}
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_autopilot_control(sc: StateChange, manager: &mut Manager) {
+    use StateChange::*;
+    match sc {
+        Enabled(v) => {
+            let e = event(TOGGLE_ENABLED, v as u8);
+            manager.send(Device::AkaiApc40, e);
+            manager.send(Device::TouchOsc, e);
+        }
+        Seed(_) => (),
+    }
+}
@@ -8,11 +8,15 @@ use crate::{
     clock_bank::StateChange,
     clock_bank::N_CLOCKS,
     device::Device,
-    midi::{cc, event, note_on, Manager},
+    midi::{cc, cc_ch0, event, note_on, note_on_ch0, Manager, Mapping},
+    params::ParamSpec,
     show::ControlMessage::Clock,
 };
 
-use super::{bipolar_from_midi, unipolar_from_midi, ControlMap};
+use super::{
+    bipolar_from_midi, bipolar_to_midi, unipolar_from_midi, unipolar_to_midi, ControlMap,
+    ParamControl,
+};
 
 const RATE_CH_0: u8 = 6;
 const LEVEL_CH_0: u8 = 48;
@@ -21,21 +25,34 @@ const TAP_CH_0: u8 = 48;
 
 const ONESHOTS: [u8; N_CLOCKS] = [19, 23, 27, 31];
 const RETRIGGERS: [u8; N_CLOCKS] = [20, 24, 28, 32];
+const MIDI_SYNCS: [u8; N_CLOCKS] = [21, 25, 29, 33];
 
 const LED_OFF: u8 = 0;
 const LED_ON: u8 = 1;
 #[allow(unused)]
 const LED_BLINK: u8 = 2;
 
+// The clock page of the APC40 and TouchOSC, which takes over the track knobs
+// and the waveform and target buttons.  Rates are on the first four knobs and
+// submaster levels on the last four; the waveform buttons tap and flash with
+// each clock's beat, and the target buttons beneath them toggle one-shot.
+const PAGE_RATE_0: u8 = 16;
+const PAGE_LEVEL_0: u8 = 20;
+const PAGE_TAP_0: u8 = 24;
+const PAGE_ONESHOT_0: u8 = 35;
+
+/// The devices with a clock page.
+const PAGE_DEVICES: [Device; 2] = [Device::AkaiApc40, Device::TouchOsc];
+
 pub fn map_clock_controls(device: Device, map: &mut ControlMap) {
     use ClockControlMessage::*;
     use ClockStateChange::*;
 
-    let mut add = |mapping, creator| map.add(device, mapping, creator);
+    let mut map = map.device(device);
 
     assert!(N_CLOCKS <= 4, "The CMD MM-1 only has 4 channel rows.");
     for i in 0..N_CLOCKS {
-        add(
+        map.add_continuous(
             cc(MIDI_CHANNEL, RATE_CH_0 + i as u8),
             Box::new(move |v| {
                 Clock(ControlMessage {
@@ -44,7 +61,7 @@ pub fn map_clock_controls(device: Device, map: &mut ControlMap) {
                 })
             }),
         );
-        add(
+        map.add_continuous(
             cc(MIDI_CHANNEL, LEVEL_CH_0 + i as u8),
             Box::new(move |v| {
                 Clock(ControlMessage {
@@ -53,7 +70,7 @@ pub fn map_clock_controls(device: Device, map: &mut ControlMap) {
                 })
             }),
         );
-        add(
+        map.add(
             note_on(MIDI_CHANNEL, TAP_CH_0 + i as u8),
             Box::new(move |_| {
                 Clock(ControlMessage {
@@ -62,7 +79,7 @@ pub fn map_clock_controls(device: Device, map: &mut ControlMap) {
                 })
             }),
         );
-        add(
+        map.add(
             note_on(MIDI_CHANNEL, ONESHOTS[i]),
             Box::new(move |_| {
                 Clock(ControlMessage {
@@ -71,7 +88,7 @@ pub fn map_clock_controls(device: Device, map: &mut ControlMap) {
                 })
             }),
         );
-        add(
+        map.add(
             note_on(MIDI_CHANNEL, RETRIGGERS[i]),
             Box::new(move |_| {
                 Clock(ControlMessage {
@@ -80,13 +97,114 @@ pub fn map_clock_controls(device: Device, map: &mut ControlMap) {
                 })
             }),
         );
+        map.add(
+            note_on(MIDI_CHANNEL, MIDI_SYNCS[i]),
+            Box::new(move |_| {
+                Clock(ControlMessage {
+                    channel: ClockIdx(i),
+                    msg: ToggleMidiSync,
+                })
+            }),
+        );
     }
 }
 
+/// The CMD MM-1 control that drives a parameter of a clock.  Unlike the
+/// clock page, its controls are always live.
+pub fn clock_param_control(spec: &ParamSpec, clock: ClockIdx) -> Option<ParamControl> {
+    let i = clock.0;
+    let mapping = match spec.address {
+        "rate" => cc(MIDI_CHANNEL, RATE_CH_0 + i as u8),
+        "level" => cc(MIDI_CHANNEL, LEVEL_CH_0 + i as u8),
+        "tap" => note_on(MIDI_CHANNEL, TAP_CH_0 + i as u8),
+        "one_shot" => note_on(MIDI_CHANNEL, ONESHOTS[i]),
+        "retrigger" => note_on(MIDI_CHANNEL, RETRIGGERS[i]),
+        "midi_sync" => note_on(MIDI_CHANNEL, MIDI_SYNCS[i]),
+        _ => return None,
+    };
+    Some(ParamControl::value(Device::BehringerCmdMM1, mapping))
+}
+
+/// Map the controls of the clock page, which replace the device's usual
+/// controls while the page is showing.
+pub fn map_clock_page_controls(device: Device, map: &mut ControlMap) {
+    use ClockControlMessage::*;
+    use ClockStateChange::*;
+
+    let mut map = map.device(device);
+
+    for i in 0..N_CLOCKS {
+        let channel = ClockIdx(i);
+        map.add_continuous(
+            page_rate(channel),
+            Box::new(move |v| {
+                Clock(ControlMessage {
+                    channel,
+                    msg: Set(Rate(bipolar_from_midi(v))),
+                })
+            }),
+        );
+        map.add_continuous(
+            page_level(channel),
+            Box::new(move |v| {
+                Clock(ControlMessage {
+                    channel,
+                    msg: Set(SubmasterLevel(unipolar_from_midi(v))),
+                })
+            }),
+        );
+        map.add(
+            page_tap(channel),
+            Box::new(move |_| Clock(ControlMessage { channel, msg: Tap })),
+        );
+        map.add(
+            page_oneshot(channel),
+            Box::new(move |_| {
+                Clock(ControlMessage {
+                    channel,
+                    msg: ToggleOneShot,
+                })
+            }),
+        );
+    }
+}
+
+fn page_rate(channel: ClockIdx) -> Mapping {
+    cc_ch0(PAGE_RATE_0 + channel.0 as u8)
+}
+
+fn page_level(channel: ClockIdx) -> Mapping {
+    cc_ch0(PAGE_LEVEL_0 + channel.0 as u8)
+}
+
+fn page_tap(channel: ClockIdx) -> Mapping {
+    note_on_ch0(PAGE_TAP_0 + channel.0 as u8)
+}
+
+fn page_oneshot(channel: ClockIdx) -> Mapping {
+    note_on_ch0(PAGE_ONESHOT_0 + channel.0 as u8)
+}
+
 /// Emit midi messages to update UIs given the provided state change.
-pub fn update_clock_control(sc: StateChange, manager: &mut Manager) {
+/// If clock_page is true, also update the clock page.
+pub fn update_clock_control(sc: StateChange, clock_page: bool, manager: &mut Manager) {
     use ClockStateChange::*;
 
+    if clock_page {
+        let page_event = match sc.change {
+            Rate(v) => Some(event(page_rate(sc.channel), bipolar_to_midi(v))),
+            SubmasterLevel(v) => Some(event(page_level(sc.channel), unipolar_to_midi(v))),
+            OneShot(v) => Some(event(page_oneshot(sc.channel), v as u8)),
+            Ticked(v) => Some(event(page_tap(sc.channel), v as u8)),
+            Retrigger(_) | MidiSync(_) => None,
+        };
+        if let Some(e) = page_event {
+            for device in PAGE_DEVICES {
+                manager.send(device, e);
+            }
+        }
+    }
+
     let mut send = |event| {
         manager.send(Device::BehringerCmdMM1, event);
     };
@@ -100,6 +218,10 @@ pub fn update_clock_control(sc: StateChange, manager: &mut Manager) {
             note_on(MIDI_CHANNEL, ONESHOTS[sc.channel.0]),
             if v { LED_ON } else { LED_OFF },
         )),
+        MidiSync(v) => send(event(
+            note_on(MIDI_CHANNEL, MIDI_SYNCS[sc.channel.0]),
+            if v { LED_ON } else { LED_OFF },
+        )),
         Ticked(v) => send(event(
             note_on(MIDI_CHANNEL, TAP_CH_0 + sc.channel.0 as u8),
             if v { LED_ON } else { LED_OFF },
@@ -0,0 +1,123 @@
+//! Midi control declarations for the color organ.
+//! These live alongside the audio input meter on TouchOSC.
+
+use super::{
+    unipolar_from_midi, unipolar_to_midi, ControlMap, ParamControl, RadioButtons,
+    MIXER_CHANNELS_PER_PAGE,
+};
+use crate::{
+    audio::Band,
+    color_organ::{ControlMessage, StateChange},
+    device::Device,
+    midi::{cc, event, note_on, Manager, Mapping},
+    mixer::ChannelIdx,
+    params::ParamSpec,
+    show::ControlMessage::ColorOrgan,
+};
+
+const MIDI_CHANNEL: u8 = 10;
+
+const TOGGLE_ENABLED: Mapping = note_on(MIDI_CHANNEL, 0);
+const ATTACK: Mapping = cc(MIDI_CHANNEL, 1);
+const RELEASE: Mapping = cc(MIDI_CHANNEL, 2);
+const GAIN: Mapping = cc(MIDI_CHANNEL, 3);
+
+/// Each mixer channel has a row of band buttons, starting from this note.
+/// The first button in each row stops following a band.
+const BAND_OFFSET: u8 = 16;
+const BANDS_PER_ROW: u8 = Band::ALL.len() as u8 + 1;
+
+fn band_button(channel: usize, band: Option<Band>) -> Mapping {
+    let column = match band {
+        None => 0,
+        Some(band) => band as u8 + 1,
+    };
+    note_on(
+        MIDI_CHANNEL,
+        BAND_OFFSET + channel as u8 * BANDS_PER_ROW + column,
+    )
+}
+
+fn band_buttons(channel: usize) -> RadioButtons {
+    RadioButtons {
+        mappings: std::iter::once(None)
+            .chain(Band::ALL.iter().copied().map(Some))
+            .map(|band| band_button(channel, band))
+            .collect(),
+        off: 0,
+        on: 1,
+    }
+}
+
+pub fn map_color_organ_controls(device: Device, map: &mut ControlMap) {
+    use ControlMessage::*;
+    use StateChange::*;
+    let mut map = map.device(device);
+
+    map.add(TOGGLE_ENABLED, Box::new(|_| ColorOrgan(ToggleEnabled)));
+    map.add_continuous(
+        ATTACK,
+        Box::new(|v| ColorOrgan(Set(Attack(unipolar_from_midi(v))))),
+    );
+    map.add_continuous(
+        RELEASE,
+        Box::new(|v| ColorOrgan(Set(Release(unipolar_from_midi(v))))),
+    );
+    map.add_continuous(
+        GAIN,
+        Box::new(|v| ColorOrgan(Set(Gain(unipolar_from_midi(v))))),
+    );
+    for channel in 0..MIXER_CHANNELS_PER_PAGE {
+        for band in std::iter::once(None).chain(Band::ALL.iter().copied().map(Some)) {
+            map.add(
+                band_button(channel, band),
+                Box::new(move |_| ColorOrgan(Set(ChannelBand(ChannelIdx(channel), band)))),
+            );
+        }
+    }
+}
+
+/// The TouchOSC control that drives a color organ parameter.
+pub fn color_organ_param_control(spec: &ParamSpec) -> Option<ParamControl> {
+    let mapping = match spec.address {
+        "enabled" => TOGGLE_ENABLED,
+        "attack" => ATTACK,
+        "release" => RELEASE,
+        "gain" => GAIN,
+        _ => return None,
+    };
+    Some(ParamControl::value(Device::TouchOsc, mapping))
+}
+
+/// The TouchOSC buttons that select the band a mixer channel follows,
+/// starting with none.  Only the first page of channels has them.
+pub fn color_organ_band_control(channel: ChannelIdx) -> Option<ParamControl> {
+    if channel.0 >= MIXER_CHANNELS_PER_PAGE {
+        return None;
+    }
+    Some(ParamControl::buttons(
+        Device::TouchOsc,
+        band_buttons(channel.0).mappings,
+    ))
+}
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_color_organ_control(sc: StateChange, manager: &mut Manager) {
+    use StateChange::*;
+    let event = match sc {
+        Enabled(v) => event(TOGGLE_ENABLED, v as u8),
+        Attack(v) => event(ATTACK, unipolar_to_midi(v)),
+        Release(v) => event(RELEASE, unipolar_to_midi(v)),
+        Gain(v) => event(GAIN, unipolar_to_midi(v)),
+        ChannelBand(channel, band) => {
+            // Only the first page of channels has band buttons.
+            if channel.0 < MIXER_CHANNELS_PER_PAGE {
+                band_buttons(channel.0).select(band_button(channel.0, band), |event| {
+                    manager.send(Device::TouchOsc, event)
+                });
+            }
+            return;
+        }
+    };
+    manager.send(Device::TouchOsc, event);
+}
@@ -0,0 +1,52 @@
+//! Control declarations for DMX input from a lighting console.
+
+use super::ControlMap;
+use crate::{
+    device::Device,
+    dmx::{mapping, palette_slot, DmxAction, DmxInput},
+    master_ui::ControlMessage as MasterUIControlMessage,
+    mixer::{ChannelControlMessage, ChannelIdx, ChannelStateChange, ControlMessage, StateChange},
+    show::ControlMessage as ShowControlMessage,
+};
+use tunnels_lib::number::UnipolarFloat;
+
+fn unipolar_from_dmx(val: u8) -> UnipolarFloat {
+    UnipolarFloat::new(val as f64 / 255.)
+}
+
+pub fn map_dmx_controls(inputs: &[DmxInput], map: &mut ControlMap) {
+    for &DmxInput {
+        address, action, ..
+    } in inputs
+    {
+        let creator: Box<dyn Fn(u8) -> ShowControlMessage> = match action {
+            DmxAction::GrandMaster => Box::new(|v| {
+                ShowControlMessage::Mixer(ControlMessage::Set(StateChange::GrandMaster(
+                    unipolar_from_dmx(v),
+                )))
+            }),
+            DmxAction::Level(chan) => Box::new(move |v| {
+                ShowControlMessage::Mixer(ControlMessage::Channel(
+                    ChannelIdx(chan),
+                    ChannelControlMessage::Set(ChannelStateChange::Level(unipolar_from_dmx(v))),
+                ))
+            }),
+            // The DMX service only passes on values that select a slot.
+            DmxAction::Palette => Box::new(|v| {
+                ShowControlMessage::MasterUI(MasterUIControlMessage::RecallBeam(
+                    palette_slot(v).expect("DMX palette value selects no slot"),
+                ))
+            }),
+            DmxAction::Go => {
+                Box::new(|_| ShowControlMessage::MasterUI(MasterUIControlMessage::RecallNextBeam))
+            }
+        };
+        // Levels follow the console's faders; every palette selection and go
+        // must be acted on.
+        if matches!(action, DmxAction::GrandMaster | DmxAction::Level(_)) {
+            map.add_continuous(Device::Dmx, mapping(address), creator);
+        } else {
+            map.add(Device::Dmx, mapping(address), creator);
+        }
+    }
+}
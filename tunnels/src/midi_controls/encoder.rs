@@ -0,0 +1,142 @@
+//! Support for endless encoders that send relative values.
+//!
+//! A relative encoder sends a signed step rather than an absolute position.
+//! We accumulate steps onto the value most recently sent to that control, and
+//! dispatch the result as if it were an absolute control change, so every
+//! existing control mapping works unmodified.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::error::Error;
+
+use crate::{
+    device::Device,
+    midi::{cc, Mapping},
+};
+
+/// How a relative encoder encodes its step.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum EncoderMode {
+    /// 1 to 63 is an increment, 127 down to 64 is a decrement of 1 to 64.
+    TwosComplement,
+    /// 64 is no change; values above increment and values below decrement.
+    Offset64,
+}
+
+/// Declare a control change as a relative encoder.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EncoderConfig {
+    pub device: Device,
+    pub channel: u8,
+    pub control: u8,
+    pub mode: EncoderMode,
+    /// Exponent applied to the size of each step.
+    /// 1.0 is linear; larger values make fast turns cover more range.
+    #[serde(default = "default_acceleration")]
+    pub acceleration: f64,
+}
+
+fn default_acceleration() -> f64 {
+    1.0
+}
+
+impl EncoderConfig {
+    pub fn mapping(&self) -> Mapping {
+        cc(self.channel, self.control)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.acceleration.is_nan() || self.acceleration <= 0.0 {
+            bail!(
+                "Encoder {} on {} has acceleration {}; it must be positive.",
+                self.mapping(),
+                self.device,
+                self.acceleration
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RelativeEncoder {
+    mode: EncoderMode,
+    acceleration: f64,
+}
+
+impl From<&EncoderConfig> for RelativeEncoder {
+    fn from(cfg: &EncoderConfig) -> Self {
+        Self {
+            mode: cfg.mode,
+            acceleration: cfg.acceleration,
+        }
+    }
+}
+
+impl RelativeEncoder {
+    /// Decode a raw midi value into a signed step.
+    fn step(&self, raw: u8) -> i32 {
+        let raw = (raw & 0x7F) as i32;
+        match self.mode {
+            EncoderMode::TwosComplement => {
+                if raw < 64 {
+                    raw
+                } else {
+                    raw - 128
+                }
+            }
+            EncoderMode::Offset64 => raw - 64,
+        }
+    }
+
+    /// Apply a raw relative value to the current absolute value.
+    pub fn apply(&self, current: u8, raw: u8) -> u8 {
+        let step = self.step(raw);
+        if step == 0 {
+            return current;
+        }
+        let magnitude = (step.abs() as f64).powf(self.acceleration).round().max(1.0);
+        let delta = magnitude.copysign(step as f64);
+        (current as f64 + delta).clamp(0.0, 127.0) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encoder(mode: EncoderMode, acceleration: f64) -> RelativeEncoder {
+        RelativeEncoder { mode, acceleration }
+    }
+
+    #[test]
+    fn test_twos_complement() {
+        let e = encoder(EncoderMode::TwosComplement, 1.0);
+        assert_eq!(65, e.apply(64, 1));
+        assert_eq!(63, e.apply(64, 127));
+        assert_eq!(54, e.apply(64, 118));
+        assert_eq!(127, e.apply(120, 20));
+        assert_eq!(0, e.apply(3, 120));
+        assert_eq!(64, e.apply(64, 0));
+    }
+
+    #[test]
+    fn test_offset_64() {
+        let e = encoder(EncoderMode::Offset64, 1.0);
+        assert_eq!(64, e.apply(64, 64));
+        assert_eq!(66, e.apply(64, 66));
+        assert_eq!(61, e.apply(64, 61));
+    }
+
+    #[test]
+    fn test_acceleration() {
+        let e = encoder(EncoderMode::Offset64, 2.0);
+        // Single steps stay fine-grained.
+        assert_eq!(65, e.apply(64, 65));
+        // Larger steps cover more ground.
+        assert_eq!(73, e.apply(64, 67));
+        assert_eq!(55, e.apply(64, 61));
+    }
+}
@@ -0,0 +1,89 @@
+//! Control declarations for game controllers.
+//!
+//! The left stick moves the tunnel in the current channel and the analog
+//! triggers spin it, the right clockwise and the left counterclockwise.  The
+//! face and shoulder buttons recall the first six slots in the top row of the
+//! beam store, start steps through the beam store, and select toggles
+//! blackout.
+
+use super::{bipolar_from_midi, ControlMap};
+use crate::{
+    beam_store::BeamStoreAddr,
+    device::Device,
+    gamepad::{button_note, LEFT_STICK_X, LEFT_STICK_Y, TRIGGERS},
+    master_ui::ControlMessage as MasterUIControlMessage,
+    midi::{note_on, Mapping},
+    mixer::ControlMessage as MixerControlMessage,
+    show::ControlMessage::{MasterUI, Mixer, Tunnel},
+    tunnel::{ControlMessage, StateChange},
+};
+use gilrs::Button;
+
+/// Buttons that recall beam store slots, in column order.
+const RECALL_BUTTONS: [Button; 6] = [
+    Button::West,
+    Button::North,
+    Button::East,
+    Button::South,
+    Button::LeftTrigger,
+    Button::RightTrigger,
+];
+
+fn button(b: Button) -> Mapping {
+    note_on(
+        0,
+        button_note(b).expect("gamepad button has no note assigned"),
+    )
+}
+
+pub fn map_gamepad_controls(map: &mut ControlMap) {
+    use ControlMessage::*;
+    use StateChange::*;
+
+    let mut map = map.device(Device::Gamepad);
+
+    map.add_continuous(
+        LEFT_STICK_X,
+        Box::new(|v| Tunnel(Set(PositionX(bipolar_from_midi(v).val())))),
+    );
+    map.add_continuous(
+        LEFT_STICK_Y,
+        Box::new(|v| Tunnel(Set(PositionY(bipolar_from_midi(v).val())))),
+    );
+
+    // Each trigger spins the tunnel in one direction; held together, they
+    // cancel out.
+    map.add_continuous(
+        TRIGGERS,
+        Box::new(|v| Tunnel(Set(RotationSpeed(bipolar_from_midi(v))))),
+    );
+
+    map.add(
+        button(Button::LeftThumb),
+        Box::new(|_| Tunnel(ResetPosition)),
+    );
+    map.add(
+        button(Button::RightThumb),
+        Box::new(|_| Tunnel(ResetRotation)),
+    );
+
+    for (col, b) in RECALL_BUTTONS.iter().enumerate() {
+        map.add(
+            button(*b),
+            Box::new(move |_| {
+                MasterUI(MasterUIControlMessage::RecallBeam(BeamStoreAddr {
+                    row: 0,
+                    col,
+                }))
+            }),
+        );
+    }
+    map.add(
+        button(Button::Start),
+        Box::new(|_| MasterUI(MasterUIControlMessage::RecallNextBeam)),
+    );
+    map.add(
+        button(Button::Select),
+        Box::new(|_| Mixer(MixerControlMessage::ToggleBlackout)),
+    );
+}
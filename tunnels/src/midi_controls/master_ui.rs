@@ -1,13 +1,23 @@
-use super::{mixer::PAGE_SIZE, ControlMap, RadioButtons};
+use super::{
+    beam_grid_surfaces, bipolar_to_midi, mixer::PAGE_SIZE, ControlMap, ParamControl, RadioButtons,
+};
 use crate::{
+    animation_presets::AnimationPresets,
+    beam::Beam,
     beam_store::{BeamStore, BeamStoreAddr},
+    clock_bank::ClockIdx,
     device::Device,
+    flash_limit::{self, ControlMessage as FlashLimitControlMessage},
     master_ui::ControlMessage,
     master_ui::StateChange,
-    master_ui::{BeamButtonState, BeamStoreState as BeamStoreStatePayload},
-    midi::{event, note_on, note_on_ch0, Manager, Mapping},
+    master_ui::{
+        BeamButtonState, BeamStoreState as BeamStoreStatePayload, ShuffleTarget, MAX_SYMMETRY,
+    },
+    midi::{cc, event, note_off, note_on, note_on_ch0, note_on_ch1, Event, Manager, Mapping},
     mixer::ChannelIdx,
-    show::ControlMessage::MasterUI,
+    params::ParamSpec,
+    recall_filter::{self, ParamClass},
+    show::ControlMessage::{FlashLimit, MasterUI, Panic, RecallFilter},
     tunnel::{AnimationIdx, N_ANIM},
 };
 use lazy_static::lazy_static;
@@ -16,22 +26,72 @@ const CHANNEL_SELECT: u8 = 0x33;
 const ANIM_0_BUTTON: u8 = 0x57;
 const ANIM_COPY: Mapping = note_on_ch0(0x65);
 const ANIM_PASTE: Mapping = note_on_ch0(0x64);
+/// Listen to the current animation, beside the pulse and invert buttons.
+const LISTEN: Mapping = note_on_ch1(3);
+/// Raw output of the current animation while listening, on TouchOSC only,
+/// beside the weight knob.
+const LISTEN_LEVEL: Mapping = cc(1, 49);
+/// Show or leave the clock page, beside listen.
+const CLOCK_PAGE: Mapping = note_on_ch1(4);
 
 const BEAM_SAVE: Mapping = note_on_ch0(0x52);
 const LOOK_SAVE: Mapping = note_on_ch0(0x53);
 const BEAM_DELETE: Mapping = note_on_ch0(0x54);
 const LOOK_EDIT: Mapping = note_on_ch0(0x56);
 
+const CLONE_CHANNEL: Mapping = note_on_ch0(0x55);
+
+/// Flashes while there are unacknowledged alerts; press to acknowledge.
+const ALERT: Mapping = note_on_ch0(0x50);
+const ALERT_LED_BLINK: u8 = 2;
+
 const BEAM_GRID_ROW_0: u8 = 0x35;
 
-// APC40 main button grid LED states
+/// Beam type selection buttons, one note per type.
+const BEAM_TYPE_CHANNEL: u8 = 11;
+
+/// Symmetry buttons, for one through MAX_SYMMETRY copies of the current
+/// tunnel, share the beam type channel, well after the beam types.
+const SYMMETRY_0: u8 = 64;
+
+/// Shuffle buttons for the current channel and for every channel, each
+/// either immediate or quantized.
+const SHUFFLE_CHANNEL: u8 = 13;
+/// Quantized shuffles wait for this clock to tick; run it at one tick per
+/// bar to shuffle on the downbeat.
+const SHUFFLE_CLOCK: ClockIdx = ClockIdx(0);
+
+/// Recall filter toggles, one note per parameter class, share the shuffle
+/// channel after the shuffle buttons.
+const RECALL_FILTER_0: u8 = 8;
+
+/// Recall buttons, one note per beam store slot of the page in row order,
+/// share the shuffle channel after the recall filter toggles.  Unlike the
+/// grid, they recall whatever mode the beam store is in.
+const RECALL_0: u8 = 32;
+
+/// Restore sane global state in one action.  Alone on its own channel so
+/// that templates can keep it well away from everything else.
+const PANIC: Mapping = note_on(14, 0);
+
+/// Overriding the flash limit takes a press of arm and then engage, kept
+/// beside panic for the same reason.
+const FLASH_LIMIT_ARM: Mapping = note_on(14, 1);
+const FLASH_LIMIT_ENGAGE: Mapping = note_on(14, 2);
+const FLASH_LIMIT_RELEASE: Mapping = note_on(14, 3);
+
+/// Animation preset buttons, one note per preset, followed by save.
+const ANIMATION_PRESET_CHANNEL: u8 = 12;
+const ANIMATION_PRESET_SAVE: Mapping =
+    note_on(ANIMATION_PRESET_CHANNEL, AnimationPresets::N_PRESETS as u8);
+
+// APC40 main button grid LED states.  Devices whose pads can't show
+// colors light up for any state other than off.
 const LED_OFF: u8 = 0;
-#[allow(unused)]
 const LED_SOLID_GREEN: u8 = 1;
 #[allow(unused)]
 const LED_BLINK_GREEN: u8 = 2;
 const LED_SOLID_RED: u8 = 3;
-#[allow(unused)]
 const LED_BLINK_RED: u8 = 4;
 const LED_SOLID_ORANGE: u8 = 5;
 #[allow(unused)]
@@ -80,6 +140,67 @@ pub fn map_master_ui_controls(device: Device, page: usize, map: &mut ControlMap)
     }
     add(ANIM_COPY, Box::new(|_| MasterUI(AnimationCopy)));
     add(ANIM_PASTE, Box::new(|_| MasterUI(AnimationPaste)));
+    if page == 0 {
+        add(CLONE_CHANNEL, Box::new(|_| MasterUI(ToggleCloneArmed)));
+        add(ALERT, Box::new(|_| MasterUI(AcknowledgeAlerts)));
+        add(LISTEN, Box::new(|_| MasterUI(ToggleListening)));
+        add(CLOCK_PAGE, Box::new(|_| MasterUI(ToggleClockPage)));
+        add(PANIC, Box::new(|_| Panic));
+        add(
+            FLASH_LIMIT_ARM,
+            Box::new(|_| FlashLimit(FlashLimitControlMessage::Arm)),
+        );
+        add(
+            FLASH_LIMIT_ENGAGE,
+            Box::new(|_| FlashLimit(FlashLimitControlMessage::Engage)),
+        );
+        add(
+            FLASH_LIMIT_RELEASE,
+            Box::new(|_| FlashLimit(FlashLimitControlMessage::Release)),
+        );
+        for i in 0..Beam::type_names().len() {
+            add(
+                note_on(BEAM_TYPE_CHANNEL, i as u8),
+                Box::new(move |_| MasterUI(SelectBeamType(i))),
+            );
+        }
+        for n in 1..=MAX_SYMMETRY {
+            add(
+                note_on(BEAM_TYPE_CHANNEL, SYMMETRY_0 + n as u8 - 1),
+                Box::new(move |_| MasterUI(Symmetrize(n))),
+            );
+        }
+        for i in 0..AnimationPresets::N_PRESETS {
+            add(
+                note_on(ANIMATION_PRESET_CHANNEL, i as u8),
+                Box::new(move |_| MasterUI(AnimationPresetButtonPress(i))),
+            );
+        }
+        for (i, &(target, clock)) in [
+            (ShuffleTarget::Current, None),
+            (ShuffleTarget::All, None),
+            (ShuffleTarget::Current, Some(SHUFFLE_CLOCK)),
+            (ShuffleTarget::All, Some(SHUFFLE_CLOCK)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            add(
+                note_on(SHUFFLE_CHANNEL, i as u8),
+                Box::new(move |_| MasterUI(Shuffle(target, clock))),
+            );
+        }
+        for &class in ParamClass::ALL.iter() {
+            add(
+                recall_filter_button(class),
+                Box::new(move |_| RecallFilter(recall_filter::ControlMessage::Toggle(class))),
+            );
+        }
+        add(
+            ANIMATION_PRESET_SAVE,
+            Box::new(|_| MasterUI(ToggleSaveAnimationPreset)),
+        );
+    }
     add(
         BEAM_SAVE,
         Box::new(|_| MasterUI(Set(BeamStoreState(BeamStoreStatePayload::BeamSave)))),
@@ -100,27 +221,116 @@ pub fn map_master_ui_controls(device: Device, page: usize, map: &mut ControlMap)
     let col_offset = BeamStore::COLS_PER_PAGE * page;
     for row in 0..BeamStore::N_ROWS {
         for col in 0..BeamStore::COLS_PER_PAGE {
+            let addr = BeamStoreAddr {
+                row,
+                col: col + col_offset,
+            };
             add(
                 note_on(col as u8, row as u8 + BEAM_GRID_ROW_0),
-                Box::new(move |_| {
-                    MasterUI(BeamGridButtonPress(BeamStoreAddr {
-                        row,
-                        col: col + col_offset,
-                    }))
-                }),
-            )
+                Box::new(move |_| MasterUI(BeamGridButtonPress(addr))),
+            );
+            add(
+                note_off(col as u8, row as u8 + BEAM_GRID_ROW_0),
+                Box::new(move |_| MasterUI(BeamGridButtonRelease(addr))),
+            );
+            add(
+                note_on(
+                    SHUFFLE_CHANNEL,
+                    RECALL_0 + (row * BeamStore::COLS_PER_PAGE + col) as u8,
+                ),
+                Box::new(move |_| MasterUI(RecallBeam(addr))),
+            );
         }
     }
 }
 
+/// The first surface showing this page of the beam store grid.
+fn grid_surface(page: usize) -> Option<Device> {
+    beam_grid_surfaces()
+        .find(|(_, p)| *p == page)
+        .map(|(device, _)| device)
+}
+
+/// The control that drives a master UI parameter without an index, on the
+/// first surface showing the first page.
+pub fn master_ui_param_control(spec: &ParamSpec) -> Option<ParamControl> {
+    let device = grid_surface(0)?;
+    let mapping = match spec.address {
+        "select" => {
+            return Some(ParamControl::buttons(
+                device,
+                ANIMATION_SELECT_BUTTONS.mappings.iter().copied(),
+            ))
+        }
+        "copy" => ANIM_COPY,
+        "paste" => ANIM_PASTE,
+        "clone_channel" => CLONE_CHANNEL,
+        "beam_save" => BEAM_SAVE,
+        "look_save" => LOOK_SAVE,
+        "delete" => BEAM_DELETE,
+        "look_edit" => LOOK_EDIT,
+        "shuffle_channel" => note_on(SHUFFLE_CHANNEL, 0),
+        "shuffle_all" => note_on(SHUFFLE_CHANNEL, 1),
+        "shuffle_channel_on_bar" => note_on(SHUFFLE_CHANNEL, 2),
+        "shuffle_all_on_bar" => note_on(SHUFFLE_CHANNEL, 3),
+        "keep_geometry" => recall_filter_button(ParamClass::Geometry),
+        "keep_color" => recall_filter_button(ParamClass::Color),
+        "keep_position" => recall_filter_button(ParamClass::Position),
+        "keep_motion" => recall_filter_button(ParamClass::Motion),
+        "keep_animations" => recall_filter_button(ParamClass::Animations),
+        "save" => ANIMATION_PRESET_SAVE,
+        _ => return None,
+    };
+    Some(ParamControl::value(device, mapping))
+}
+
+fn recall_filter_button(class: ParamClass) -> Mapping {
+    note_on(SHUFFLE_CHANNEL, RECALL_FILTER_0 + class.index() as u8)
+}
+
+/// The button that selects a mixer channel, on the first surface showing
+/// its page.
+pub fn channel_select_control(channel: ChannelIdx) -> Option<ParamControl> {
+    let page = channel.0 / PAGE_SIZE;
+    let midi_channel = (channel.0 - page * PAGE_SIZE) as u8;
+    Some(ParamControl::value(
+        grid_surface(page)?,
+        note_on(midi_channel, CHANNEL_SELECT),
+    ))
+}
+
+/// The button that recalls or saves an animation preset.
+pub fn animation_preset_control(index: usize) -> Option<ParamControl> {
+    Some(ParamControl::value(
+        grid_surface(0)?,
+        note_on(ANIMATION_PRESET_CHANNEL, index as u8),
+    ))
+}
+
+/// The button that selects a beam type, by its index into Beam::type_names.
+pub fn beam_type_control(index: usize) -> Option<ParamControl> {
+    Some(ParamControl::value(
+        grid_surface(0)?,
+        note_on(BEAM_TYPE_CHANNEL, index as u8),
+    ))
+}
+
+/// The grid button for a beam store slot, on the first surface showing its
+/// page.
+pub fn beam_store_slot_control(addr: BeamStoreAddr) -> Option<ParamControl> {
+    let page = addr.col / BeamStore::COLS_PER_PAGE;
+    let midi_channel = (addr.col - page * BeamStore::COLS_PER_PAGE) as u8;
+    Some(ParamControl::value(
+        grid_surface(page)?,
+        note_on(midi_channel, BEAM_GRID_ROW_0 + addr.row as u8),
+    ))
+}
+
 /// Emit midi messages to update UIs given the provided state change.
 pub fn update_master_ui_control(sc: StateChange, manager: &mut Manager) {
     use StateChange::*;
 
-    let mut send_main = |event| {
-        manager.send(Device::TouchOsc, event);
-        manager.send(Device::AkaiApc40, event);
-    };
+    let mut send_main = |event| send_page(0, event, manager);
 
     match sc {
         Animation(a) => {
@@ -131,17 +341,15 @@ pub fn update_master_ui_control(sc: StateChange, manager: &mut Manager) {
             let channel_offset = page * PAGE_SIZE;
             let midi_channel = (c.0 - channel_offset) as u8;
 
-            // Send to the appropriate device based on page.
-            // If this channel is on page 0, disable all channel buttons on APC20.
-            // If page 1, disable all buttons on APC40/TouchOSC.
-            if page == 0 {
-                CHANNEL_SELECT_BUTTONS.select(note_on(midi_channel, CHANNEL_SELECT), send_main);
-                CHANNEL_SELECT_BUTTONS.all_off(|event| manager.send(Device::AkaiApc20, event));
-            } else {
-                CHANNEL_SELECT_BUTTONS.all_off(send_main);
-                CHANNEL_SELECT_BUTTONS.select(note_on(midi_channel, CHANNEL_SELECT), |event| {
-                    manager.send(Device::AkaiApc20, event)
-                });
+            // Select the channel on the devices showing its page, and disable
+            // all channel buttons on the devices showing other pages.
+            for (device, device_page) in beam_grid_surfaces() {
+                let send = |event| manager.send(device, event);
+                if device_page == page {
+                    CHANNEL_SELECT_BUTTONS.select(note_on(midi_channel, CHANNEL_SELECT), send);
+                } else {
+                    CHANNEL_SELECT_BUTTONS.all_off(send);
+                }
             }
         }
         BeamButton((addr, state)) => {
@@ -156,20 +364,46 @@ pub fn update_master_ui_control(sc: StateChange, manager: &mut Manager) {
                     Empty => LED_OFF,
                     Beam => LED_SOLID_ORANGE,
                     Look => LED_SOLID_RED,
+                    Locked => LED_SOLID_GREEN,
+                    Confirming => LED_BLINK_RED,
                 },
             );
 
-            if page == 0 {
-                send_main(e);
-            } else {
-                manager.send(Device::AkaiApc20, e);
+            send_page(page, e, manager);
+        }
+        CloneArmed(v) => send_main(event(CLONE_CHANNEL, v as u8)),
+        Alerting(v) => send_main(event(ALERT, if v { ALERT_LED_BLINK } else { LED_OFF })),
+        Listening(v) => send_main(event(LISTEN, v as u8)),
+        ClockPage(v) => send_main(event(CLOCK_PAGE, v as u8)),
+        ListenLevel(v) => manager.send(Device::TouchOsc, event(LISTEN_LEVEL, bipolar_to_midi(v))),
+        PresetSaveArmed(v) => manager.send(Device::TouchOsc, event(ANIMATION_PRESET_SAVE, v as u8)),
+        AnimationPresetButton((index, occupied)) => manager.send(
+            Device::TouchOsc,
+            event(
+                note_on(ANIMATION_PRESET_CHANNEL, index as u8),
+                occupied as u8,
+            ),
+        ),
+        BeamType(index) => {
+            // Build the buttons for every registered beam type.
+            let buttons = RadioButtons {
+                mappings: (0..Beam::type_names().len())
+                    .map(|i| note_on(BEAM_TYPE_CHANNEL, i as u8))
+                    .collect(),
+                off: 0,
+                on: 1,
+            };
+            let send = |event| manager.send(Device::TouchOsc, event);
+            match index {
+                Some(i) => buttons.select(note_on(BEAM_TYPE_CHANNEL, i as u8), send),
+                None => buttons.all_off(send),
             }
         }
         BeamStoreState(state) => {
             let send_all = |event| {
-                manager.send(Device::TouchOsc, event);
-                manager.send(Device::AkaiApc40, event);
-                manager.send(Device::AkaiApc20, event);
+                for (device, _) in beam_grid_surfaces() {
+                    manager.send(device, led(device, event));
+                }
             };
             use BeamStoreStatePayload::*;
             match state {
@@ -182,3 +416,56 @@ pub fn update_master_ui_control(sc: StateChange, manager: &mut Manager) {
         }
     }
 }
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_recall_filter_control(sc: recall_filter::StateChange, manager: &mut Manager) {
+    match sc {
+        recall_filter::StateChange::Locked(class, v) => manager.send(
+            Device::TouchOsc,
+            event(recall_filter_button(class), v as u8),
+        ),
+    }
+}
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_flash_limit_control(sc: flash_limit::StateChange, manager: &mut Manager) {
+    let mut send_main = |event| send_page(0, event, manager);
+    match sc {
+        flash_limit::StateChange::Armed(v) => send_main(event(FLASH_LIMIT_ARM, v as u8)),
+        flash_limit::StateChange::Engaged(v) => {
+            send_main(event(FLASH_LIMIT_ENGAGE, v as u8));
+            // Release is lit while there is something to release.
+            send_main(event(FLASH_LIMIT_RELEASE, v as u8));
+        }
+    }
+}
+
+/// Send to every device showing this page of the beam store grid.
+fn send_page(page: usize, event: Event, manager: &mut Manager) {
+    for (device, _) in beam_grid_surfaces().filter(|(_, p)| *p == page) {
+        manager.send(device, led(device, event));
+    }
+}
+
+/// Adapt an LED state to the pads of this device.
+fn led(device: Device, event: Event) -> Event {
+    if device.capabilities().rgb || event.value == LED_OFF {
+        event
+    } else {
+        Event { value: 1, ..event }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_led() {
+        let confirming = event(note_on_ch0(BEAM_GRID_ROW_0), LED_BLINK_RED);
+        assert_eq!(LED_BLINK_RED, led(Device::AkaiApc40, confirming).value);
+        assert_eq!(1, led(Device::TouchOsc, confirming).value);
+        let empty = event(note_on_ch0(BEAM_GRID_ROW_0), LED_OFF);
+        assert_eq!(LED_OFF, led(Device::TouchOsc, empty).value);
+    }
+}
@@ -1,21 +1,44 @@
 use crate::{
-    device::Device,
-    midi::{cc, event, note_off, note_on, Manager},
+    device::{Capabilities, Device},
+    midi::{cc, cc_ch0, event, note_off, note_on, note_on_ch0, Event, Manager, Mapping},
     mixer::ControlMessage,
     mixer::StateChange,
     mixer::{
         ChannelControlMessage, ChannelIdx, ChannelStateChange, Mixer,
         VideoChannel as VideoChannelIdx,
     },
+    params::ParamSpec,
     show::ControlMessage as ShowControlMessage,
 };
+use tunnels_lib::number::UnipolarFloat;
 
-use super::{unipolar_from_midi, unipolar_to_midi, ControlMap};
+use super::{
+    bipolar_from_midi, bipolar_to_midi, master_ui::channel_select_control, mixer_surfaces,
+    unipolar_from_midi, unipolar_to_midi, ControlMap, ParamControl,
+};
 
 const FADER: u8 = 0x7;
+const TRIM: u8 = 0x8;
+/// Channel level meters, on TouchOSC only.
+const METER: u8 = 0xB;
 const BUMP: u8 = 0x32;
 const MASK: u8 = 0x31;
 const LOOK: u8 = 0x30;
+const DRAW_ORDER: u8 = 0x9;
+const DEPTH: u8 = 0xA;
+const BRING_TO_FRONT: u8 = 0x34;
+/// Additive blending, on TouchOSC only.
+const ADDITIVE: u8 = 0x4A;
+
+const GRAND_MASTER: Mapping = cc_ch0(0x0E);
+const BLACKOUT: Mapping = note_on_ch0(0x51);
+/// Draw a new random seed for the show.  Alone on its own channel, since
+/// there's no taking it back.
+const REROLL_SEED: Mapping = note_on(15, 0);
+/// Master color correction, on TouchOSC only, alongside the palette controls.
+const HUE_SHIFT: Mapping = cc(10, 5);
+const SATURATION: Mapping = cc(10, 6);
+const BRIGHTNESS: Mapping = cc(10, 7);
 
 /// The midi note value for the 0th video channel selector.
 const VIDEO_CHAN_0: u8 = 66;
@@ -27,38 +50,90 @@ pub fn map_mixer_controls(device: Device, page: usize, map: &mut ControlMap) {
     use ChannelControlMessage::*;
     use ChannelStateChange::*;
 
-    let mut add = |mapping, creator| map.add(device, mapping, creator);
+    let mut map = map.device(device);
+
+    // Only one set of global mixer controls, on the first page.
+    if page == 0 {
+        map.add_continuous(
+            GRAND_MASTER,
+            Box::new(|v| {
+                ShowControlMessage::Mixer(ControlMessage::Set(StateChange::GrandMaster(
+                    unipolar_from_midi(v),
+                )))
+            }),
+        );
+        map.add(
+            BLACKOUT,
+            Box::new(|_| ShowControlMessage::Mixer(ControlMessage::ToggleBlackout)),
+        );
+        map.add(
+            REROLL_SEED,
+            Box::new(|_| ShowControlMessage::Mixer(ControlMessage::RerollSeed)),
+        );
+        if device == Device::TouchOsc {
+            let set = |sc: fn(UnipolarFloat) -> StateChange| -> Box<_> {
+                Box::new(move |v| {
+                    ShowControlMessage::Mixer(ControlMessage::Set(sc(unipolar_from_midi(v))))
+                })
+            };
+            map.add_continuous(HUE_SHIFT, set(StateChange::HueShift));
+            map.add_continuous(SATURATION, set(StateChange::Saturation));
+            map.add_continuous(BRIGHTNESS, set(StateChange::Brightness));
+        }
+    }
 
     // Offset the mixer channels to correspond to this page.
     let channel_offset = page * PAGE_SIZE;
 
     for chan in 0..PAGE_SIZE {
         let mkmsg = move |ccm: ChannelControlMessage| -> ShowControlMessage {
-            ShowControlMessage::Mixer(ControlMessage {
-                channel: ChannelIdx(chan + channel_offset),
-                msg: ccm,
-            })
+            ShowControlMessage::Mixer(ControlMessage::Channel(
+                ChannelIdx(chan + channel_offset),
+                ccm,
+            ))
         };
-        add(
+        map.add_continuous(
             cc(chan as u8, FADER),
             Box::new(move |v| mkmsg(Set(Level(unipolar_from_midi(v))))),
         );
-        add(
+        map.add_continuous(
+            cc(chan as u8, TRIM),
+            Box::new(move |v| mkmsg(Set(Trim(unipolar_from_midi(v))))),
+        );
+        map.add(
             note_on(chan as u8, BUMP),
             Box::new(move |_| mkmsg(Set(Bump(true)))),
         );
-        add(
+        map.add(
             note_off(chan as u8, BUMP),
             Box::new(move |_| mkmsg(Set(Bump(false)))),
         );
-        add(
+        map.add(
             note_on(chan as u8, MASK),
             Box::new(move |_| mkmsg(ToggleMask)),
         );
+        if device == Device::TouchOsc {
+            map.add(
+                note_on(chan as u8, ADDITIVE),
+                Box::new(move |_| mkmsg(ToggleAdditive)),
+            );
+        }
+        map.add_continuous(
+            cc(chan as u8, DRAW_ORDER),
+            Box::new(move |v| mkmsg(Set(DrawOrder(v)))),
+        );
+        map.add_continuous(
+            cc(chan as u8, DEPTH),
+            Box::new(move |v| mkmsg(Set(Depth(bipolar_from_midi(v))))),
+        );
+        map.add(
+            note_on(chan as u8, BRING_TO_FRONT),
+            Box::new(move |_| mkmsg(BringToFront)),
+        );
 
         // Configure the video channel selectors.
         for vc in 0..Mixer::N_VIDEO_CHANNELS {
-            add(
+            map.add(
                 note_on(chan as u8, vc as u8 + VIDEO_CHAN_0),
                 Box::new(move |_| mkmsg(ToggleVideoChannel(VideoChannelIdx(vc)))),
             );
@@ -66,35 +141,151 @@ pub fn map_mixer_controls(device: Device, page: usize, map: &mut ControlMap) {
     }
 }
 
+/// The control that drives a global mixer parameter, on the first surface
+/// showing the first page.  Arming channel cloning belongs to the master UI.
+pub fn mixer_param_control(spec: &ParamSpec) -> Option<ParamControl> {
+    let device = page_surfaces(0, any).next()?;
+    let mapping = match spec.address {
+        "grand_master" => GRAND_MASTER,
+        "blackout" => BLACKOUT,
+        _ => return None,
+    };
+    Some(ParamControl::value(device, mapping))
+}
+
+/// The control that drives a parameter of a mixer channel, on the first
+/// surface showing its page.
+pub fn channel_param_control(spec: &ParamSpec, channel: ChannelIdx) -> Option<ParamControl> {
+    let page = channel.0 / PAGE_SIZE;
+    let midi_channel = (channel.0 - page * PAGE_SIZE) as u8;
+    let device = page_surfaces(page, any).next()?;
+    let mapping = match spec.address {
+        "select" => return channel_select_control(channel),
+        "level" => cc(midi_channel, FADER),
+        "trim" => cc(midi_channel, TRIM),
+        "bump" => note_on(midi_channel, BUMP),
+        "mask" => note_on(midi_channel, MASK),
+        "additive" if device == Device::TouchOsc => note_on(midi_channel, ADDITIVE),
+        "draw_order" => cc(midi_channel, DRAW_ORDER),
+        "bring_to_front" => note_on(midi_channel, BRING_TO_FRONT),
+        _ => return None,
+    };
+    Some(ParamControl::value(device, mapping))
+}
+
+/// The button that sends a mixer channel to a video channel.
+pub fn video_channel_control(channel: ChannelIdx, vc: VideoChannelIdx) -> Option<ParamControl> {
+    let page = channel.0 / PAGE_SIZE;
+    let midi_channel = (channel.0 - page * PAGE_SIZE) as u8;
+    let device = page_surfaces(page, any).next()?;
+    Some(ParamControl::value(
+        device,
+        note_on(midi_channel, vc.0 as u8 + VIDEO_CHAN_0),
+    ))
+}
+
 /// Emit midi messages to update UIs given the provided state change.
 pub fn update_mixer_control(sc: StateChange, manager: &mut Manager) {
     use ChannelStateChange::*;
 
-    let page = sc.channel.0 / PAGE_SIZE;
-    let channel_offset = page * PAGE_SIZE;
-    let midi_channel = (sc.channel.0 - channel_offset) as u8;
-
-    let mut send = |event| {
-        // Send page 0 to the APC40, page 1 to APC20
-        manager.send(
-            if page == 0 {
-                Device::AkaiApc40
-            } else {
-                Device::AkaiApc20
-            },
-            event,
-        );
-        manager.send(Device::TouchOsc, event);
+    let (channel, change) = match sc {
+        StateChange::GrandMaster(v) => {
+            send_page(0, fader, event(GRAND_MASTER, unipolar_to_midi(v)), manager);
+            return;
+        }
+        StateChange::Blackout(v) => {
+            send_page(0, any, event(BLACKOUT, v as u8), manager);
+            return;
+        }
+        StateChange::HueShift(v) => {
+            manager.send(Device::TouchOsc, event(HUE_SHIFT, unipolar_to_midi(v)));
+            return;
+        }
+        StateChange::Saturation(v) => {
+            manager.send(Device::TouchOsc, event(SATURATION, unipolar_to_midi(v)));
+            return;
+        }
+        StateChange::Brightness(v) => {
+            manager.send(Device::TouchOsc, event(BRIGHTNESS, unipolar_to_midi(v)));
+            return;
+        }
+        StateChange::Channel(channel, change) => (channel, change),
     };
 
-    match sc.change {
-        Level(v) => send(event(cc(midi_channel, FADER), unipolar_to_midi(v))),
-        Bump(v) => send(event(note_on(midi_channel, BUMP), v as u8)),
-        Mask(v) => send(event(note_on(midi_channel, MASK), v as u8)),
-        ContainsLook(v) => send(event(note_on(midi_channel, LOOK), v as u8)),
-        VideoChannel((vc, v)) => send(event(
-            note_on(midi_channel, vc.0 as u8 + VIDEO_CHAN_0),
-            v as u8,
-        )),
+    let page = channel.0 / PAGE_SIZE;
+    let channel_offset = page * PAGE_SIZE;
+    let midi_channel = (channel.0 - channel_offset) as u8;
+
+    let mut send = |shows: Shows, event| send_page(page, shows, event, manager);
+
+    match change {
+        Level(v) => send(fader, event(cc(midi_channel, FADER), unipolar_to_midi(v))),
+        Trim(v) => send(knob, event(cc(midi_channel, TRIM), unipolar_to_midi(v))),
+        Meter(v) => send(
+            touch_osc,
+            event(cc(midi_channel, METER), unipolar_to_midi(v)),
+        ),
+        Bump(v) => send(any, event(note_on(midi_channel, BUMP), v as u8)),
+        Mask(v) => send(any, event(note_on(midi_channel, MASK), v as u8)),
+        Additive(v) => send(touch_osc, event(note_on(midi_channel, ADDITIVE), v as u8)),
+        Depth(v) => send(knob, event(cc(midi_channel, DEPTH), bipolar_to_midi(v))),
+        DrawOrder(v) => send(knob, event(cc(midi_channel, DRAW_ORDER), v)),
+        ContainsLook(v) => send(any, event(note_on(midi_channel, LOOK), v as u8)),
+        VideoChannel((vc, v)) => send(
+            any,
+            event(note_on(midi_channel, vc.0 as u8 + VIDEO_CHAN_0), v as u8),
+        ),
+    }
+}
+
+/// Which devices can show a mixer control's state.
+type Shows = fn(Device, Capabilities) -> bool;
+
+fn any(_: Device, _: Capabilities) -> bool {
+    true
+}
+
+/// Faders only need to hear about changes if they can move to follow them.
+fn fader(_: Device, caps: Capabilities) -> bool {
+    caps.motor_faders
+}
+
+fn knob(_: Device, caps: Capabilities) -> bool {
+    caps.encoders > 0
+}
+
+/// Controls that only exist in our TouchOSC template.
+fn touch_osc(device: Device, _: Capabilities) -> bool {
+    device == Device::TouchOsc
+}
+
+/// Send to every device showing this page of mixer channels that can show
+/// the control.  The global mixer controls live on the first page.
+fn send_page(page: usize, shows: Shows, event: Event, manager: &mut Manager) {
+    for device in page_surfaces(page, shows) {
+        manager.send(device, event);
+    }
+}
+
+fn page_surfaces(page: usize, shows: Shows) -> impl Iterator<Item = Device> {
+    mixer_surfaces()
+        .filter(move |(device, p)| *p == page && shows(*device, device.capabilities()))
+        .map(|(device, _)| device)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_page_surfaces() {
+        let surfaces = |page, shows| page_surfaces(page, shows).collect::<Vec<_>>();
+        assert_eq!(vec![Device::TouchOsc, Device::AkaiApc40], surfaces(0, any));
+        assert_eq!(vec![Device::AkaiApc20], surfaces(1, any));
+        // Only TouchOSC's faders follow the show, and the APC20 has no knobs.
+        assert_eq!(vec![Device::TouchOsc], surfaces(0, fader));
+        assert!(surfaces(1, fader).is_empty());
+        assert!(surfaces(1, knob).is_empty());
+        assert!(surfaces(1, touch_osc).is_empty());
     }
 }
@@ -0,0 +1,89 @@
+//! Midi control declarations for palette selection.
+//! These live alongside the color organ on TouchOSC.
+
+use super::{unipolar_from_midi, unipolar_to_midi, ControlMap, ParamControl, RadioButtons};
+use crate::{
+    device::Device,
+    midi::{cc, event, note_on, Manager, Mapping},
+    palette::{ControlMessage, Deck, PaletteConfig, StateChange},
+    params::ParamSpec,
+    show::ControlMessage::Palette,
+};
+
+const MIDI_CHANNEL: u8 = 10;
+
+const CROSSFADE: Mapping = cc(MIDI_CHANNEL, 4);
+
+/// Each deck has a row of palette buttons, starting from this note.
+/// The first button in each row empties the deck.
+const SELECT_OFFSET: u8 = 64;
+const ROW_SIZE: u8 = PaletteConfig::MAX_COUNT as u8 + 1;
+
+fn select_button(deck: Deck, palette: Option<usize>) -> Mapping {
+    let row = match deck {
+        Deck::A => 0,
+        Deck::B => 1,
+    };
+    let column = palette.map_or(0, |i| i as u8 + 1);
+    note_on(MIDI_CHANNEL, SELECT_OFFSET + row * ROW_SIZE + column)
+}
+
+fn select_buttons(deck: Deck) -> RadioButtons {
+    RadioButtons {
+        mappings: std::iter::once(None)
+            .chain((0..PaletteConfig::MAX_COUNT).map(Some))
+            .map(|palette| select_button(deck, palette))
+            .collect(),
+        off: 0,
+        on: 1,
+    }
+}
+
+pub fn map_palette_controls(device: Device, map: &mut ControlMap) {
+    use ControlMessage::*;
+    use StateChange::*;
+    let mut map = map.device(device);
+
+    map.add_continuous(
+        CROSSFADE,
+        Box::new(|v| Palette(Set(Crossfade(unipolar_from_midi(v))))),
+    );
+    for deck in Deck::ALL {
+        for palette in std::iter::once(None).chain((0..PaletteConfig::MAX_COUNT).map(Some)) {
+            map.add(
+                select_button(deck, palette),
+                Box::new(move |_| Palette(Set(Select(deck, palette)))),
+            );
+        }
+    }
+}
+
+/// The TouchOSC control that drives a palette parameter.
+pub fn palette_param_control(spec: &ParamSpec) -> Option<ParamControl> {
+    match spec.address {
+        "crossfade" => Some(ParamControl::value(Device::TouchOsc, CROSSFADE)),
+        _ => None,
+    }
+}
+
+/// The TouchOSC buttons that select each palette for a deck, starting with
+/// an empty deck.
+pub fn palette_select_control(deck: Deck) -> Option<ParamControl> {
+    Some(ParamControl::buttons(
+        Device::TouchOsc,
+        select_buttons(deck).mappings,
+    ))
+}
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_palette_control(sc: StateChange, manager: &mut Manager) {
+    use StateChange::*;
+    match sc {
+        Select(deck, palette) => {
+            select_buttons(deck).select(select_button(deck, palette), |event| {
+                manager.send(Device::TouchOsc, event)
+            });
+        }
+        Crossfade(v) => manager.send(Device::TouchOsc, event(CROSSFADE, unipolar_to_midi(v))),
+    }
+}
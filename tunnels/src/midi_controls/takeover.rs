@@ -0,0 +1,120 @@
+//! Soft takeover for absolute controls that can't be moved by the show.
+//!
+//! When show state changes underneath a physical fader or knob, such as when
+//! a look is recalled, the control no longer matches the value it drives.
+//! Rather than letting the next touch make the value jump, we ignore input
+//! from the control until it reaches or crosses the current value.
+
+use std::collections::HashMap;
+
+use crate::{device::Device, midi::Mapping};
+
+/// Input within this distance of the current value is considered a match.
+/// Allows for rounding when values pass through the show and back.
+const TOLERANCE: i16 = 1;
+
+pub struct SoftTakeover {
+    /// Every control with soft takeover, and the most recent value received
+    /// from it, if any.
+    controls: HashMap<(Device, Mapping), Option<u8>>,
+}
+
+impl SoftTakeover {
+    pub fn new(controls: impl IntoIterator<Item = (Device, Mapping)>) -> Self {
+        Self {
+            controls: controls
+                .into_iter()
+                .map(|control| (control, None))
+                .collect(),
+        }
+    }
+
+    /// Return true if soft takeover is enabled for this control.
+    pub fn applies_to(&self, device: Device, mapping: Mapping) -> bool {
+        self.controls.contains_key(&(device, mapping))
+    }
+
+    /// Return true if the input value should take effect, given the current
+    /// value of the control, if known.  Input from controls without soft
+    /// takeover always takes effect.
+    pub fn accept(
+        &mut self,
+        device: Device,
+        mapping: Mapping,
+        value: u8,
+        current: Option<u8>,
+    ) -> bool {
+        let last = match self.controls.get_mut(&(device, mapping)) {
+            Some(last) => last.replace(value),
+            None => return true,
+        };
+        let current = match current {
+            Some(c) => c,
+            None => return true,
+        };
+        let near = |v: u8| (v as i16 - current as i16).abs() <= TOLERANCE;
+        if near(value) {
+            return true;
+        }
+        match last {
+            // The control was in sync with the value, so it still has it.
+            Some(last) if near(last) => true,
+            // Take over if the control crossed the value since the last input.
+            Some(last) => (last < current) != (value < current),
+            // No idea where the control was before; wait for it to come around.
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi::cc;
+
+    const FADER: Mapping = cc(0, 7);
+    const OTHER_FADER: Mapping = cc(1, 7);
+
+    #[test]
+    fn test_takeover() {
+        let mut st = SoftTakeover::new(vec![(Device::AkaiApc40, FADER)]);
+        let mut accept = |v, current| st.accept(Device::AkaiApc40, FADER, v, Some(current));
+
+        // First touch far from the current value is ignored.
+        assert!(!accept(10, 100));
+        assert!(!accept(50, 100));
+        // Crossing the value takes over.
+        assert!(accept(105, 100));
+        // Once in control, stay in control.
+        assert!(accept(110, 105));
+        assert!(accept(20, 110));
+
+        // The value changes out from under the fader.
+        assert!(!accept(25, 80));
+        assert!(!accept(60, 80));
+        // Landing close enough takes over.
+        assert!(accept(79, 80));
+    }
+
+    #[test]
+    fn test_unknown_value() {
+        let mut st = SoftTakeover::new(vec![(Device::AkaiApc40, FADER)]);
+        assert!(st.accept(Device::AkaiApc40, FADER, 10, None));
+    }
+
+    #[test]
+    fn test_controls_independent() {
+        let mut st = SoftTakeover::new(vec![
+            (Device::AkaiApc40, FADER),
+            (Device::AkaiApc40, OTHER_FADER),
+        ]);
+        // One fader in control says nothing about where the other one is.
+        assert!(st.accept(Device::AkaiApc40, FADER, 100, Some(100)));
+        assert!(!st.accept(Device::AkaiApc40, OTHER_FADER, 10, Some(100)));
+        assert!(st.accept(Device::AkaiApc40, FADER, 20, Some(100)));
+        assert!(!st.accept(Device::AkaiApc40, OTHER_FADER, 20, Some(100)));
+        // Controls without soft takeover are never held back.
+        assert!(!st.applies_to(Device::TouchOsc, FADER));
+        assert!(st.accept(Device::TouchOsc, FADER, 10, Some(100)));
+    }
+}
@@ -0,0 +1,67 @@
+//! Control declarations for contact-closure trigger inputs.
+
+use super::ControlMap;
+use crate::{
+    beam_store::BeamStoreAddr,
+    clock::ControlMessage as ClockControlMessage,
+    clock_bank::{ClockIdx, ControlMessage as ClockBankControlMessage},
+    device::Device,
+    master_ui::ControlMessage as MasterUIControlMessage,
+    midi_file::ControlMessage as MidiFileControlMessage,
+    mixer::{ChannelControlMessage, ChannelIdx, ChannelStateChange, ControlMessage},
+    show::ControlMessage as ShowControlMessage,
+    trigger::{close_mapping, open_mapping, TriggerAction, TriggerInput},
+};
+
+pub fn map_trigger_controls(inputs: &[TriggerInput], map: &mut ControlMap) {
+    let mut add = |mapping, creator| map.add(Device::Trigger, mapping, creator);
+
+    for &TriggerInput { input, action } in inputs {
+        let close = close_mapping(input);
+        match action {
+            TriggerAction::Blackout => add(
+                close,
+                Box::new(|_| ShowControlMessage::Mixer(ControlMessage::ToggleBlackout)),
+            ),
+            TriggerAction::Bump(chan) => {
+                let bump = move |v| {
+                    ShowControlMessage::Mixer(ControlMessage::Channel(
+                        ChannelIdx(chan),
+                        ChannelControlMessage::Set(ChannelStateChange::Bump(v)),
+                    ))
+                };
+                add(close, Box::new(move |_| bump(true)));
+                add(open_mapping(input), Box::new(move |_| bump(false)));
+            }
+            TriggerAction::Tap(clock) => add(
+                close,
+                Box::new(move |_| {
+                    ShowControlMessage::Clock(ClockBankControlMessage {
+                        channel: ClockIdx(clock),
+                        msg: ClockControlMessage::Tap,
+                    })
+                }),
+            ),
+            TriggerAction::Recall { row, col } => add(
+                close,
+                Box::new(move |_| {
+                    ShowControlMessage::MasterUI(MasterUIControlMessage::RecallBeam(
+                        BeamStoreAddr { row, col },
+                    ))
+                }),
+            ),
+            TriggerAction::NextBeam => add(
+                close,
+                Box::new(|_| ShowControlMessage::MasterUI(MasterUIControlMessage::RecallNextBeam)),
+            ),
+            TriggerAction::PlayMidiFile(file) => add(
+                close,
+                Box::new(move |_| ShowControlMessage::MidiFile(MidiFileControlMessage::Play(file))),
+            ),
+            TriggerAction::StopMidiFile => add(
+                close,
+                Box::new(|_| ShowControlMessage::MidiFile(MidiFileControlMessage::Stop)),
+            ),
+        }
+    }
+}
@@ -1,11 +1,17 @@
-use super::{bipolar_from_midi, bipolar_to_midi, unipolar_from_midi, unipolar_to_midi, ControlMap};
+use super::{
+    bipolar_from_midi, bipolar_to_midi, unipolar_from_midi, unipolar_to_midi, ControlMap,
+    ParamControl, RadioButtons,
+};
 use crate::{
+    clock_bank::{ClockIdx, N_CLOCKS},
     device::Device,
-    midi::{cc, cc_ch0, event, note_on_ch0, Manager, Mapping},
+    midi::{cc, cc_ch0, event, note_on, note_on_ch0, Manager, Mapping},
+    params::{self, ParamSpec},
     show::ControlMessage::Tunnel,
     tunnel::ControlMessage,
     tunnel::StateChange,
 };
+use lazy_static::lazy_static;
 use tunnels_lib::number::BipolarFloat;
 
 // Knobs
@@ -29,73 +35,221 @@ const NUDGE_DOWN: Mapping = note_on_ch0(0x5E);
 const RESET_POSITION: Mapping = note_on_ch0(0x62);
 const RESET_ROTATION: Mapping = note_on_ch0(120);
 const RESET_MARQUEE: Mapping = note_on_ch0(121);
+const ZERO_ROTATION: Mapping = note_on_ch0(122);
+const ZERO_MARQUEE: Mapping = note_on_ch0(123);
+const ZERO_ROTATION_ON_BEAT: Mapping = note_on_ch0(124);
+const ZERO_MARQUEE_ON_BEAT: Mapping = note_on_ch0(125);
+
+/// Quantized resets wait for this clock to tick.
+const BEAT_CLOCK: ClockIdx = ClockIdx(0);
 
 // TouchOSC XY position pad.
 const POSITION_X: Mapping = cc(8, 1);
 const POSITION_Y: Mapping = cc(8, 0);
 
+// TouchOSC color flip controls.
+const COLOR_FLIP_PROBABILITY: Mapping = cc(8, 2);
+/// The first button turns color flip off; the rest select a clock.
+const COLOR_FLIP_CLOCK_OFFSET: u8 = 0;
+
+// TouchOSC wobble controls.
+const WOBBLE_AMOUNT: Mapping = cc(8, 3);
+const WOBBLE_FREQUENCY: Mapping = cc(8, 4);
+/// The first button makes the wobble free-running; the rest select a clock.
+const WOBBLE_CLOCK_OFFSET: u8 = 8;
+
+// TouchOSC shatter controls.
+const SHATTER: Mapping = note_on(8, 16);
+const SHATTER_DURATION: Mapping = cc(8, 5);
+
+// TouchOSC palette controls.
+const USE_PALETTE: Mapping = note_on(8, 17);
+
+lazy_static! {
+    static ref WOBBLE_CLOCK_BUTTONS: RadioButtons = RadioButtons {
+        mappings: (0..=N_CLOCKS)
+            .map(|i| note_on(8, WOBBLE_CLOCK_OFFSET + i as u8))
+            .collect(),
+        off: 0,
+        on: 1,
+    };
+    static ref COLOR_FLIP_CLOCK_BUTTONS: RadioButtons = RadioButtons {
+        mappings: (0..=N_CLOCKS)
+            .map(|i| note_on(8, COLOR_FLIP_CLOCK_OFFSET + i as u8))
+            .collect(),
+        off: 0,
+        on: 1,
+    };
+}
+
+fn color_flip_clock_button(clock: Option<ClockIdx>) -> Mapping {
+    note_on(
+        8,
+        COLOR_FLIP_CLOCK_OFFSET + clock.map_or(0, |c| c.0 as u8 + 1),
+    )
+}
+
+fn wobble_clock_button(clock: Option<ClockIdx>) -> Mapping {
+    note_on(8, WOBBLE_CLOCK_OFFSET + clock.map_or(0, |c| c.0 as u8 + 1))
+}
+
 pub fn map_tunnel_controls(device: Device, map: &mut ControlMap) {
     use ControlMessage::*;
     use StateChange::*;
-    let mut add = |mapping, creator| map.add(device, mapping, creator);
+    let mut map = map.device(device);
 
     // unipolar knobs
-    add(
+    map.add_continuous(
         THICKNESS,
         Box::new(|v| Tunnel(Set(Thickness(unipolar_from_midi(v))))),
     );
-    add(SIZE, Box::new(|v| Tunnel(Set(Size(unipolar_from_midi(v))))));
-    add(
+    map.add_continuous(SIZE, Box::new(|v| Tunnel(Set(Size(unipolar_from_midi(v))))));
+    map.add_continuous(
         COL_CENTER,
         Box::new(|v| Tunnel(Set(ColorCenter(unipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         COL_WIDTH,
         Box::new(|v| Tunnel(Set(ColorWidth(unipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         COL_SPREAD,
         Box::new(|v| Tunnel(Set(ColorSpread(unipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         COL_SAT,
         Box::new(|v| Tunnel(Set(ColorSaturation(unipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         ASPECT_RATIO,
         Box::new(|v| Tunnel(Set(AspectRatio(unipolar_from_midi(v))))),
     );
     // bipolar knobs
-    add(
+    map.add_continuous(
         ROT_SPEED,
         Box::new(|v| Tunnel(Set(RotationSpeed(bipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         MARQUEE_SPEED,
         Box::new(|v| Tunnel(Set(MarqueeSpeed(bipolar_from_midi(v))))),
     );
-    add(
+    map.add_continuous(
         BLACKING,
         Box::new(|v| Tunnel(Set(Blacking(bipolar_from_midi(v))))),
     );
     // FIXME segments tied to midi value
-    add(SEGMENTS, Box::new(|v| Tunnel(Set(Segments(v + 1)))));
-
-    add(NUDGE_RIGHT, Box::new(|_| Tunnel(NudgeRight)));
-    add(NUDGE_LEFT, Box::new(|_| Tunnel(NudgeLeft)));
-    add(NUDGE_UP, Box::new(|_| Tunnel(NudgeUp)));
-    add(NUDGE_DOWN, Box::new(|_| Tunnel(NudgeDown)));
-    add(RESET_POSITION, Box::new(|_| Tunnel(ResetPosition)));
-    add(RESET_ROTATION, Box::new(|_| Tunnel(ResetRotation)));
-    add(RESET_MARQUEE, Box::new(|_| Tunnel(ResetMarquee)));
-    add(
+    map.add_continuous(
+        SEGMENTS,
+        Box::new(|v| Tunnel(Set(Segments(params::SEGMENTS.integer_from_midi(v) as u8)))),
+    );
+
+    map.add(NUDGE_RIGHT, Box::new(|_| Tunnel(NudgeRight)));
+    map.add(NUDGE_LEFT, Box::new(|_| Tunnel(NudgeLeft)));
+    map.add(NUDGE_UP, Box::new(|_| Tunnel(NudgeUp)));
+    map.add(NUDGE_DOWN, Box::new(|_| Tunnel(NudgeDown)));
+    map.add(RESET_POSITION, Box::new(|_| Tunnel(ResetPosition)));
+    map.add(RESET_ROTATION, Box::new(|_| Tunnel(ResetRotation)));
+    map.add(RESET_MARQUEE, Box::new(|_| Tunnel(ResetMarquee)));
+    map.add(ZERO_ROTATION, Box::new(|_| Tunnel(ZeroRotationAngle(None))));
+    map.add(ZERO_MARQUEE, Box::new(|_| Tunnel(ZeroMarqueeAngle(None))));
+    map.add(
+        ZERO_ROTATION_ON_BEAT,
+        Box::new(|_| Tunnel(ZeroRotationAngle(Some(BEAT_CLOCK)))),
+    );
+    map.add(
+        ZERO_MARQUEE_ON_BEAT,
+        Box::new(|_| Tunnel(ZeroMarqueeAngle(Some(BEAT_CLOCK)))),
+    );
+    map.add_continuous(
         POSITION_X,
         Box::new(|v| Tunnel(Set(PositionX(bipolar_from_midi(v).val())))),
     );
-    add(
+    map.add_continuous(
         POSITION_Y,
         Box::new(|v| Tunnel(Set(PositionY(bipolar_from_midi(v).val())))),
     );
+    map.add_continuous(
+        COLOR_FLIP_PROBABILITY,
+        Box::new(|v| Tunnel(Set(ColorFlipProbability(unipolar_from_midi(v))))),
+    );
+    map.add(
+        color_flip_clock_button(None),
+        Box::new(|_| Tunnel(Set(ColorFlipClock(None)))),
+    );
+    for clock in 0..N_CLOCKS {
+        map.add(
+            color_flip_clock_button(Some(ClockIdx(clock))),
+            Box::new(move |_| Tunnel(Set(ColorFlipClock(Some(ClockIdx(clock)))))),
+        );
+    }
+    map.add_continuous(
+        WOBBLE_AMOUNT,
+        Box::new(|v| Tunnel(Set(WobbleAmount(unipolar_from_midi(v))))),
+    );
+    map.add_continuous(
+        WOBBLE_FREQUENCY,
+        Box::new(|v| Tunnel(Set(WobbleFrequency(unipolar_from_midi(v))))),
+    );
+    map.add(
+        wobble_clock_button(None),
+        Box::new(|_| Tunnel(Set(WobbleClock(None)))),
+    );
+    for clock in 0..N_CLOCKS {
+        map.add(
+            wobble_clock_button(Some(ClockIdx(clock))),
+            Box::new(move |_| Tunnel(Set(WobbleClock(Some(ClockIdx(clock)))))),
+        );
+    }
+    map.add(SHATTER, Box::new(|_| Tunnel(Shatter)));
+    map.add_continuous(
+        SHATTER_DURATION,
+        Box::new(|v| Tunnel(Set(ShatterDuration(unipolar_from_midi(v))))),
+    );
+    map.add(USE_PALETTE, Box::new(|_| Tunnel(TogglePalette)));
+}
+
+/// The TouchOSC control that drives a tunnel parameter.
+pub fn tunnel_param_control(spec: &ParamSpec) -> Option<ParamControl> {
+    let value = |mapping| Some(ParamControl::value(Device::TouchOsc, mapping));
+    let clock_buttons = |button: fn(Option<ClockIdx>) -> Mapping| {
+        let clocks = std::iter::once(None).chain((0..N_CLOCKS).map(|c| Some(ClockIdx(c))));
+        Some(ParamControl::buttons(Device::TouchOsc, clocks.map(button)))
+    };
+    match spec.address {
+        "thickness" => value(THICKNESS),
+        "size" => value(SIZE),
+        "aspect_ratio" => value(ASPECT_RATIO),
+        "color_center" => value(COL_CENTER),
+        "color_width" => value(COL_WIDTH),
+        "color_spread" => value(COL_SPREAD),
+        "color_saturation" => value(COL_SAT),
+        "palette" => value(USE_PALETTE),
+        "segments" => value(SEGMENTS),
+        "blacking" => value(BLACKING),
+        "rotation_speed" => value(ROT_SPEED),
+        "marquee_speed" => value(MARQUEE_SPEED),
+        "position_x" => value(POSITION_X),
+        "position_y" => value(POSITION_Y),
+        "color_flip_clock" => clock_buttons(color_flip_clock_button),
+        "color_flip_probability" => value(COLOR_FLIP_PROBABILITY),
+        "wobble_amount" => value(WOBBLE_AMOUNT),
+        "wobble_frequency" => value(WOBBLE_FREQUENCY),
+        "wobble_clock" => clock_buttons(wobble_clock_button),
+        "shatter_duration" => value(SHATTER_DURATION),
+        "shatter" => value(SHATTER),
+        "nudge_left" => value(NUDGE_LEFT),
+        "nudge_right" => value(NUDGE_RIGHT),
+        "nudge_up" => value(NUDGE_UP),
+        "nudge_down" => value(NUDGE_DOWN),
+        "reset_position" => value(RESET_POSITION),
+        "reset_rotation" => value(RESET_ROTATION),
+        "reset_marquee" => value(RESET_MARQUEE),
+        "zero_rotation" => value(ZERO_ROTATION),
+        "zero_marquee" => value(ZERO_MARQUEE),
+        "zero_rotation_on_beat" => value(ZERO_ROTATION_ON_BEAT),
+        "zero_marquee_on_beat" => value(ZERO_MARQUEE_ON_BEAT),
+        _ => None,
+    }
 }
 
 /// Emit midi messages to update UIs given the provided tunnel state change.
@@ -110,13 +264,55 @@ pub fn update_tunnel_control(sc: StateChange, manager: &mut Manager) {
         ColorWidth(v) => event(COL_WIDTH, unipolar_to_midi(v)),
         ColorSpread(v) => event(COL_SPREAD, unipolar_to_midi(v)),
         ColorSaturation(v) => event(COL_SAT, unipolar_to_midi(v)),
-        Segments(v) => event(SEGMENTS, v - 1),
+        Segments(v) => event(SEGMENTS, params::SEGMENTS.integer_to_midi(v as i64)),
         Blacking(v) => event(BLACKING, bipolar_to_midi(v)),
         MarqueeSpeed(v) => event(MARQUEE_SPEED, bipolar_to_midi(v)),
         RotationSpeed(v) => event(ROT_SPEED, bipolar_to_midi(v)),
         // Clamp outgoing tunnel position messages to regular midi range.
         PositionX(v) => event(POSITION_X, bipolar_to_midi(BipolarFloat::new(v))),
         PositionY(v) => event(POSITION_Y, bipolar_to_midi(BipolarFloat::new(v))),
+        // Color flip, wobble, shatter, and palette controls only exist on TouchOSC.
+        ColorFlipProbability(v) => {
+            manager.send(
+                Device::TouchOsc,
+                event(COLOR_FLIP_PROBABILITY, unipolar_to_midi(v)),
+            );
+            return;
+        }
+        ColorFlipClock(v) => {
+            COLOR_FLIP_CLOCK_BUTTONS.select(color_flip_clock_button(v), |event| {
+                manager.send(Device::TouchOsc, event)
+            });
+            return;
+        }
+        WobbleAmount(v) => {
+            manager.send(Device::TouchOsc, event(WOBBLE_AMOUNT, unipolar_to_midi(v)));
+            return;
+        }
+        WobbleFrequency(v) => {
+            manager.send(
+                Device::TouchOsc,
+                event(WOBBLE_FREQUENCY, unipolar_to_midi(v)),
+            );
+            return;
+        }
+        WobbleClock(v) => {
+            WOBBLE_CLOCK_BUTTONS.select(wobble_clock_button(v), |event| {
+                manager.send(Device::TouchOsc, event)
+            });
+            return;
+        }
+        UsePalette(v) => {
+            manager.send(Device::TouchOsc, event(USE_PALETTE, v as u8));
+            return;
+        }
+        ShatterDuration(v) => {
+            manager.send(
+                Device::TouchOsc,
+                event(SHATTER_DURATION, unipolar_to_midi(v)),
+            );
+            return;
+        }
     };
     manager.send(Device::AkaiApc40, event);
     manager.send(Device::TouchOsc, event);
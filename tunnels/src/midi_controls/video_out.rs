@@ -0,0 +1,113 @@
+//! Midi control declarations for video output adjustments and output
+//! profiles.
+
+use super::{ControlMap, ParamControl, RadioButtons};
+use crate::{
+    device::Device,
+    midi::{note_on, Manager},
+    mixer::{Mixer, VideoChannel},
+    output_profile::{self, OutputProfileConfig},
+    show::ControlMessage::{OutputProfile, VideoOut},
+    video_out::{ControlMessage, GeometryPreset, StateChange},
+};
+use lazy_static::lazy_static;
+
+const MIDI_CHANNEL: u8 = 9;
+
+/// Each video channel has a row of geometry preset buttons.
+/// The first button in each row deselects any preset.
+const ROW_SIZE: usize = GeometryPreset::MAX_COUNT + 1;
+
+/// Output profile buttons, one note per profile.  The geometry rows fill
+/// every note of their channel, so these share a channel with reroll seed.
+const PROFILE_CHANNEL: u8 = 15;
+const PROFILE_0: u8 = 16;
+
+lazy_static! {
+    static ref GEOMETRY_BUTTONS: Vec<RadioButtons> = (0..Mixer::N_VIDEO_CHANNELS)
+        .map(|vc| RadioButtons {
+            mappings: (0..ROW_SIZE)
+                .map(|button| note_on(MIDI_CHANNEL, (vc * ROW_SIZE + button) as u8))
+                .collect(),
+            off: 0,
+            on: 1,
+        })
+        .collect();
+    static ref PROFILE_BUTTONS: RadioButtons = RadioButtons {
+        mappings: (0..OutputProfileConfig::MAX_COUNT)
+            .map(|i| note_on(PROFILE_CHANNEL, PROFILE_0 + i as u8))
+            .collect(),
+        off: 0,
+        on: 1,
+    };
+}
+
+pub fn map_video_out_controls(device: Device, map: &mut ControlMap) {
+    for vc in 0..Mixer::N_VIDEO_CHANNELS {
+        for button in 0..ROW_SIZE {
+            let preset = button.checked_sub(1);
+            map.add(
+                device,
+                note_on(MIDI_CHANNEL, (vc * ROW_SIZE + button) as u8),
+                Box::new(move |_| {
+                    VideoOut(ControlMessage::Set(StateChange::Geometry(
+                        VideoChannel(vc),
+                        preset,
+                    )))
+                }),
+            );
+        }
+    }
+    for i in 0..OutputProfileConfig::MAX_COUNT {
+        map.add(
+            device,
+            note_on(PROFILE_CHANNEL, PROFILE_0 + i as u8),
+            Box::new(move |_| {
+                OutputProfile(output_profile::ControlMessage::Set(
+                    output_profile::StateChange::Profile(i),
+                ))
+            }),
+        );
+    }
+}
+
+/// The TouchOSC buttons that select each geometry preset for a video channel,
+/// starting with no preset.
+pub fn geometry_preset_control(vc: VideoChannel) -> Option<ParamControl> {
+    Some(ParamControl::buttons(
+        Device::TouchOsc,
+        GEOMETRY_BUTTONS.get(vc.0)?.mappings.iter().copied(),
+    ))
+}
+
+/// The TouchOSC buttons that select each output profile.
+pub fn output_profile_control() -> Option<ParamControl> {
+    Some(ParamControl::buttons(
+        Device::TouchOsc,
+        PROFILE_BUTTONS.mappings.iter().copied(),
+    ))
+}
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_video_out_control(sc: StateChange, manager: &mut Manager) {
+    match sc {
+        StateChange::Geometry(VideoChannel(vc), preset) => {
+            let button = preset.map_or(0, |p| p + 1);
+            GEOMETRY_BUTTONS[vc].select(
+                note_on(MIDI_CHANNEL, (vc * ROW_SIZE + button) as u8),
+                |event| manager.send(Device::TouchOsc, event),
+            );
+        }
+    }
+}
+
+/// Emit midi messages to update UIs given the provided state change.
+pub fn update_output_profile_control(sc: output_profile::StateChange, manager: &mut Manager) {
+    match sc {
+        output_profile::StateChange::Profile(i) => {
+            PROFILE_BUTTONS.select(note_on(PROFILE_CHANNEL, PROFILE_0 + i as u8), |event| {
+                manager.send(Device::TouchOsc, event)
+            });
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Play standard MIDI files into the show, for pre-programmed segments.
+//!
+//! A segment is authored in a DAW against the midi mapping of one of the
+//! supported devices.  When played, its note and control change events are
+//! injected into the midi input stream as if they came from that device.
+//!
+//! Playback follows the master clock rather than the file's tempo map: one
+//! quarter note in the file lasts one beat of the master clock, so a segment
+//! stays in time with whatever tempo has been tapped in.  Playback begins on
+//! the next beat after it is triggered.
+//!
+//! A recorded performance can be exported to MIDI files for editing, one file
+//! per device that was played, in the same form that the player reads.
+
+use midly::{
+    num::{u28, u4, u7},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tracing::{error, info, warn};
+use tunnels_lib::queue::DropSender;
+
+use crate::{
+    clock_bank::{ClockBank, ClockIdx},
+    control_journal::{read_journal, JournalEntry},
+    device::Device,
+    midi::{event, note_off, Event, EventType, Mapping},
+};
+
+/// Playback follows this clock, like the autopilot.
+const MASTER_CLOCK: ClockIdx = ClockIdx(0);
+
+/// Resolution of exported files.
+const EXPORT_TICKS_PER_BEAT: u16 = 480;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MidiFileConfig {
+    pub path: PathBuf,
+    /// Events in the file are played as if they came from this device.
+    pub device: Device,
+}
+
+/// A loaded file, ready to play.
+struct MidiFile {
+    name: String,
+    device: Device,
+    ticks_per_beat: u64,
+    /// Every event in the file in order, with its time in ticks.
+    events: Vec<(u64, Event)>,
+}
+
+impl MidiFile {
+    fn load(config: &MidiFileConfig) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(&config.path)?;
+        let smf = Smf::parse(&bytes)?;
+        Self::from_smf(&smf, &config.path, config.device)
+    }
+
+    fn from_smf(smf: &Smf, path: &Path, device: Device) -> Result<Self, Box<dyn Error>> {
+        let ticks_per_beat = match smf.header.timing {
+            Timing::Metrical(tpb) if tpb.as_int() > 0 => tpb.as_int() as u64,
+            _ => bail!(
+                "MIDI file {} must use metrical timing to follow the master clock.",
+                path.display()
+            ),
+        };
+        let mut events = Vec::new();
+        // Sequential tracks play one after the other, others all at once.
+        let mut track_start = 0;
+        for track in &smf.tracks {
+            let mut tick = track_start;
+            for track_event in track {
+                tick += track_event.delta.as_int() as u64;
+                if let TrackEventKind::Midi { channel, message } = track_event.kind {
+                    let (event_type, control, value) = match message {
+                        MidiMessage::NoteOn { key, vel } => (EventType::NoteOn, key, vel),
+                        MidiMessage::NoteOff { key, vel } => (EventType::NoteOff, key, vel),
+                        MidiMessage::Controller { controller, value } => {
+                            (EventType::ControlChange, controller, value)
+                        }
+                        _ => continue,
+                    };
+                    let mapping = Mapping {
+                        event_type,
+                        channel: channel.as_int(),
+                        control: control.as_int(),
+                    };
+                    events.push((tick, event(mapping, value.as_int())));
+                }
+            }
+            if smf.header.format == Format::Sequential {
+                track_start = tick;
+            }
+        }
+        // Stable, so simultaneous events keep their order within a track.
+        events.sort_by_key(|(tick, _)| *tick);
+        Ok(Self {
+            name: path.display().to_string(),
+            device,
+            ticks_per_beat,
+            events,
+        })
+    }
+}
+
+/// Progress through the file being played.
+struct Playback {
+    file: usize,
+    /// Whole beats of the master clock elapsed since playback started.
+    beats: u64,
+    /// Index of the next event to play.
+    next: usize,
+    /// Notes that have been turned on and not yet off.
+    held: HashSet<Mapping>,
+}
+
+enum State {
+    Idle,
+    /// Waiting for the next beat to start playing a file.
+    Armed(usize),
+    Playing(Playback),
+}
+
+pub struct MidiFilePlayer {
+    files: Vec<MidiFile>,
+    sender: DropSender<(Instant, Device, Event)>,
+    state: State,
+}
+
+impl MidiFilePlayer {
+    /// Load every configured file.
+    /// Played events are sent on the provided channel.
+    pub fn new(
+        configs: &[MidiFileConfig],
+        sender: DropSender<(Instant, Device, Event)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let files = configs
+            .iter()
+            .map(|config| {
+                MidiFile::load(config).map_err(|e| {
+                    format!("Unable to load MIDI file {}: {}", config.path.display(), e).into()
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        Ok(Self {
+            files,
+            sender,
+            state: State::Idle,
+        })
+    }
+
+    /// The number of loaded files.
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Play any events that have come due.
+    pub fn update_state(&mut self, clocks: &ClockBank) {
+        let events = self.advance(
+            clocks.ticked(MASTER_CLOCK),
+            clocks.phase(MASTER_CLOCK).val(),
+        );
+        self.send(events);
+    }
+
+    /// Advance playback given the state of the master clock, returning the
+    /// events that have come due.
+    fn advance(&mut self, ticked: bool, phase: f64) -> Vec<(Device, Event)> {
+        match self.state {
+            State::Armed(file) if ticked => {
+                info!("Playing MIDI file {}.", self.files[file].name);
+                self.state = State::Playing(Playback {
+                    file,
+                    beats: 0,
+                    next: 0,
+                    held: HashSet::new(),
+                });
+            }
+            State::Playing(ref mut playback) if ticked => playback.beats += 1,
+            _ => (),
+        }
+        let playback = match &mut self.state {
+            State::Playing(playback) => playback,
+            _ => return Vec::new(),
+        };
+        let file = &self.files[playback.file];
+        let now = ((playback.beats as f64 + phase) * file.ticks_per_beat as f64) as u64;
+        let mut due = Vec::new();
+        while let Some((tick, e)) = file.events.get(playback.next) {
+            if *tick > now {
+                break;
+            }
+            match e.mapping.event_type {
+                EventType::NoteOn if e.value > 0 => {
+                    playback.held.insert(e.mapping);
+                }
+                EventType::NoteOn | EventType::NoteOff => {
+                    playback.held.remove(&Mapping {
+                        event_type: EventType::NoteOn,
+                        ..e.mapping
+                    });
+                }
+                EventType::ControlChange => (),
+            }
+            due.push((file.device, *e));
+            playback.next += 1;
+        }
+        if playback.next == file.events.len() {
+            info!("Finished playing MIDI file {}.", file.name);
+            self.state = State::Idle;
+        }
+        due
+    }
+
+    /// Stop playing, releasing any notes that are held.
+    fn stop(&mut self) -> Vec<(Device, Event)> {
+        let released = match &self.state {
+            State::Playing(playback) => {
+                let device = self.files[playback.file].device;
+                info!("Stopped MIDI file {}.", self.files[playback.file].name);
+                playback
+                    .held
+                    .iter()
+                    .map(|m| (device, event(note_off(m.channel, m.control), 0)))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        self.state = State::Idle;
+        released
+    }
+
+    fn send(&self, events: Vec<(Device, Event)>) {
+        for e in events {
+            if self.sender.send((Instant::now(), e.0, e.1)).is_err() {
+                error!("Unable to inject MIDI file event; the show has shut down.");
+                return;
+            }
+        }
+    }
+
+    pub fn control(&mut self, msg: ControlMessage) {
+        match msg {
+            ControlMessage::Play(file) => {
+                if file >= self.files.len() {
+                    warn!("There is no MIDI file {} to play.", file);
+                    return;
+                }
+                let released = self.stop();
+                self.send(released);
+                self.state = State::Armed(file);
+            }
+            ControlMessage::Stop => {
+                let released = self.stop();
+                self.send(released);
+            }
+        }
+    }
+}
+
+/// Export the control journal at the provided path to one MIDI file per
+/// device that appears in it.  Each file is named for its device, in the form
+/// used to configure playback.
+/// Return the paths of the files written.
+pub fn export_journal(journal: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let entries = read_journal(journal)?;
+    let mut devices = Vec::new();
+    for entry in &entries {
+        if !devices.contains(&entry.device) {
+            devices.push(entry.device);
+        }
+    }
+    devices
+        .into_iter()
+        .map(|device| {
+            let path = journal.with_extension(format!("{:?}.mid", device));
+            to_smf(&entries, device)?.save(&path)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Convert the journal entries from one device into a single-track file.
+/// Fail if an entry doesn't fit in a MIDI message, rather than write a
+/// corrupted one.
+fn to_smf(entries: &[JournalEntry], device: Device) -> Result<Smf<'static>, Box<dyn Error>> {
+    let mut track = Vec::new();
+    let mut last_tick = 0;
+    for entry in entries.iter().filter(|entry| entry.device == device) {
+        // Entries are written in order, but guard against rounding.
+        let tick = ((entry.beats * EXPORT_TICKS_PER_BEAT as f64).round() as u32).max(last_tick);
+        let Mapping {
+            event_type,
+            channel,
+            control,
+        } = entry.event.mapping;
+        let out_of_range = |name, v: u32| {
+            format!(
+                "Journal entry at beat {} has {} {}, which is out of range for MIDI.",
+                entry.beats, name, v
+            )
+        };
+        let key = u7::try_from(control).ok_or_else(|| out_of_range("control", control.into()))?;
+        let value = u7::try_from(entry.event.value)
+            .ok_or_else(|| out_of_range("value", entry.event.value.into()))?;
+        let channel =
+            u4::try_from(channel).ok_or_else(|| out_of_range("channel", channel.into()))?;
+        let message = match event_type {
+            EventType::NoteOn => MidiMessage::NoteOn { key, vel: value },
+            EventType::NoteOff => MidiMessage::NoteOff { key, vel: value },
+            EventType::ControlChange => MidiMessage::Controller {
+                controller: key,
+                value,
+            },
+        };
+        track.push(TrackEvent {
+            delta: u28::try_from(tick - last_tick)
+                .ok_or_else(|| out_of_range("delta ticks", tick - last_tick))?,
+            kind: TrackEventKind::Midi { channel, message },
+        });
+        last_tick = tick;
+    }
+    track.push(TrackEvent {
+        delta: 0.into(),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    let mut smf = Smf::new(Header::new(
+        Format::SingleTrack,
+        Timing::Metrical(EXPORT_TICKS_PER_BEAT.into()),
+    ));
+    smf.tracks.push(track);
+    Ok(smf)
+}
+
+pub enum ControlMessage {
+    /// Start playing a file on the next beat, stopping any file that is playing.
+    Play(usize),
+    Stop,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi::{cc, note_on};
+    use tunnels_lib::queue::bounded;
+
+    fn track_event(delta: u32, message: MidiMessage) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 1.into(),
+                message,
+            },
+        }
+    }
+
+    fn player() -> MidiFilePlayer {
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(4.into())));
+        smf.tracks.push(vec![
+            track_event(
+                0,
+                MidiMessage::NoteOn {
+                    key: 3.into(),
+                    vel: 127.into(),
+                },
+            ),
+            track_event(
+                6,
+                MidiMessage::Controller {
+                    controller: 7.into(),
+                    value: 64.into(),
+                },
+            ),
+            track_event(
+                2,
+                MidiMessage::NoteOff {
+                    key: 3.into(),
+                    vel: 0.into(),
+                },
+            ),
+        ]);
+        let file = MidiFile::from_smf(&smf, Path::new("test.mid"), Device::AkaiApc40).unwrap();
+        MidiFilePlayer {
+            files: vec![file],
+            sender: bounded(1).0,
+            state: State::Idle,
+        }
+    }
+
+    fn mappings(events: Vec<(Device, Event)>) -> Vec<(Device, Mapping, u8)> {
+        events
+            .into_iter()
+            .map(|(d, e)| (d, e.mapping, e.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_playback() {
+        let mut player = player();
+        player.control(ControlMessage::Play(0));
+        // Wait for the next beat.
+        assert!(player.advance(false, 0.5).is_empty());
+        let due = player.advance(true, 0.0);
+        assert_eq!(vec![(Device::AkaiApc40, note_on(1, 3), 127)], mappings(due));
+        assert!(player.advance(false, 0.9).is_empty());
+        let due = player.advance(true, 0.5);
+        assert_eq!(vec![(Device::AkaiApc40, cc(1, 7), 64)], mappings(due));
+        assert!(player.advance(false, 0.99).is_empty());
+        let due = player.advance(true, 0.0);
+        assert_eq!(vec![(Device::AkaiApc40, note_off(1, 3), 0)], mappings(due));
+        assert!(matches!(player.state, State::Idle));
+    }
+
+    #[test]
+    fn test_stop_releases_notes() {
+        let mut player = player();
+        player.control(ControlMessage::Play(0));
+        player.advance(true, 0.0);
+        assert_eq!(
+            vec![(Device::AkaiApc40, note_off(1, 3), 0)],
+            mappings(player.stop())
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let entry = |beats, device, mapping, value| JournalEntry {
+            beats,
+            device,
+            event: event(mapping, value),
+        };
+        let entries = vec![
+            entry(0.5, Device::AkaiApc40, note_on(0, 1), 127),
+            entry(0.75, Device::Gamepad, cc(0, 1), 3),
+            entry(1.25, Device::AkaiApc40, cc(2, 7), 64),
+        ];
+        let smf = to_smf(&entries, Device::AkaiApc40).unwrap();
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        let file = MidiFile::from_smf(&smf, Path::new("test.mid"), Device::AkaiApc40).unwrap();
+        let events: Vec<_> = file
+            .events
+            .iter()
+            .map(|(tick, e)| (*tick, e.mapping, e.value))
+            .collect();
+        assert_eq!(vec![(240, note_on(0, 1), 127), (600, cc(2, 7), 64)], events);
+    }
+
+    #[test]
+    fn test_export_out_of_range() {
+        let export = |mapping, value| {
+            let entries = vec![JournalEntry {
+                beats: 0.0,
+                device: Device::AkaiApc40,
+                event: event(mapping, value),
+            }];
+            to_smf(&entries, Device::AkaiApc40)
+        };
+        // The largest values MIDI allows survive a round trip.
+        let smf = export(cc(15, 127), 127).unwrap();
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        let file = MidiFile::from_smf(&smf, Path::new("test.mid"), Device::AkaiApc40).unwrap();
+        assert_eq!(
+            (cc(15, 127), 127),
+            (file.events[0].1.mapping, file.events[0].1.value)
+        );
+        // Anything larger is refused rather than wrapped.
+        assert!(export(cc(15, 127), 128).is_err());
+        assert!(export(cc(15, 128), 127).is_err());
+        assert!(export(cc(16, 127), 127).is_err());
+        assert!(export(note_on(0, 255), 127).is_err());
+    }
+}
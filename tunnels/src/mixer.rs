@@ -1,16 +1,88 @@
+use crate::color_organ::ColorOrgan;
+use crate::external::ExternalFeed;
 use crate::midi_controls::MIXER_CHANNELS_PER_PAGE;
+use crate::palette::{PaletteConfig, Palettes};
+use crate::random::{self, ShowRng};
+use crate::stereo::{eye_offsets, shift, StereoPair};
+use crate::video_out::IdlePolicy;
 use crate::{beam::Beam, look::Look, tunnel::Tunnel};
 use crate::{clock_bank::ClockBank, master_ui::EmitStateChange as EmitShowStateChange};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, sync::Arc, time::Duration};
-use tunnels_lib::number::UnipolarFloat;
-use tunnels_lib::{ArcSegment, LayerCollection};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::info;
+use tunnels_lib::number::{BipolarFloat, UnipolarFloat};
+use tunnels_lib::{
+    color::ColorCorrection, palette::Palette, ArcSegment, BlendMode, LayerCollection,
+};
 use typed_index_derive::TypedIndex;
 
 /// Holds a collection of beams in channels, and understands how they are mixed.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Mixer {
     channels: Vec<Channel>,
+    /// Scale the level of every channel.
+    #[serde(default = "default_grand_master")]
+    grand_master: UnipolarFloat,
+    /// If true, the mixer renders nothing at all.
+    #[serde(default)]
+    blackout: bool,
+    /// Rotate the hue of every arc, as a fraction of the way around the wheel.
+    #[serde(default)]
+    hue_shift: UnipolarFloat,
+    /// Scale the saturation of every arc.
+    #[serde(default = "default_grand_master")]
+    saturation: UnipolarFloat,
+    /// Scale the brightness of every arc.
+    #[serde(default = "default_grand_master")]
+    brightness: UnipolarFloat,
+    /// Optionally drives channel levels from the audio input.
+    #[serde(default)]
+    color_organ: ColorOrgan,
+    /// The palettes that tunnels draw from.
+    #[serde(default)]
+    palettes: Palettes,
+    /// Every random choice made by the mixer's beams derives from this.
+    #[serde(default)]
+    seed: u64,
+    /// The random generator for each channel, derived from the seed.
+    #[serde(skip)]
+    rngs: Vec<ShowRng>,
+    /// The random generator for choices made across channels, derived from
+    /// the seed.
+    #[serde(skip)]
+    rng: Option<ShowRng>,
+    /// Scale the level of every channel, under automatic control.
+    #[serde(skip, default = "default_grand_master")]
+    gate: UnipolarFloat,
+    /// Video channels with no client connected, and what to do about them.
+    #[serde(skip)]
+    idle_outputs: HashMap<VideoChannel, IdlePolicy>,
+    /// Most arcs to send each frame, summed over every video channel.
+    #[serde(skip)]
+    arc_budget: Option<usize>,
+    /// Pairs of video channels that show the same mix to each eye.
+    #[serde(skip)]
+    stereo: Vec<StereoPair>,
+    /// Channels fed by external sources instead of their beams.
+    #[serde(skip)]
+    external: HashMap<ChannelIdx, ExternalFeed>,
+}
+
+fn default_grand_master() -> UnipolarFloat {
+    UnipolarFloat::ONE
+}
+
+fn default_depth() -> BipolarFloat {
+    BipolarFloat::ZERO
+}
+
+fn default_trim() -> UnipolarFloat {
+    UnipolarFloat::ONE
 }
 
 impl Mixer {
@@ -22,6 +94,21 @@ impl Mixer {
             channels: (0..n_channels)
                 .map(|_| Channel::new(Beam::Tunnel(Tunnel::new())))
                 .collect(),
+            grand_master: UnipolarFloat::ONE,
+            blackout: false,
+            hue_shift: UnipolarFloat::ZERO,
+            saturation: UnipolarFloat::ONE,
+            brightness: UnipolarFloat::ONE,
+            color_organ: ColorOrgan::new(),
+            palettes: Palettes::default(),
+            seed: rand::random(),
+            rngs: Vec::new(),
+            rng: None,
+            gate: UnipolarFloat::ONE,
+            idle_outputs: HashMap::new(),
+            arc_budget: None,
+            stereo: Vec::new(),
+            external: HashMap::new(),
         }
     }
 
@@ -33,128 +120,484 @@ impl Mixer {
     /// Clobber the state of this mixer with the provided look.
     pub fn set_look<E: EmitStateChange>(&mut self, look: Look, emitter: &mut E) {
         self.channels = look.channels;
+        self.apply_external_sources();
         self.emit_state(emitter);
     }
 
     /// Update the state of all of the beams contained in this mixer.
-    pub fn update_state(&mut self, delta_t: Duration) {
-        for channel in &mut self.channels {
-            channel.update_state(delta_t);
+    /// Beams that only feed paused outputs are left as they are.
+    /// Channels are independent, so they are updated in parallel.
+    pub fn update_state(&mut self, delta_t: Duration, external_clocks: &ClockBank) {
+        if self.rngs.len() != self.channels.len() {
+            let seed = self.seed;
+            self.rngs = (0..self.channels.len())
+                .map(|i| random::stream(seed, i as u64))
+                .collect();
         }
+        let idle_outputs = &self.idle_outputs;
+        self.channels
+            .par_iter_mut()
+            .zip(self.rngs.par_iter_mut())
+            .filter(|(channel, _)| {
+                !channel.only_feeds(|vc| idle_outputs.get(vc) == Some(&IdlePolicy::Pause))
+            })
+            .for_each(|(channel, rng)| channel.update_state(delta_t, external_clocks, rng));
+    }
+
+    /// The seed that every random choice in the show derives from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The random generator for choices made across channels.
+    pub fn rng(&mut self) -> &mut ShowRng {
+        let seed = self.seed;
+        // Past the stream of every channel.
+        self.rng
+            .get_or_insert_with(|| random::stream(seed, u64::MAX))
+    }
+
+    /// Draw a new random seed, giving the show a fresh set of random choices.
+    pub fn reroll_seed(&mut self) {
+        self.seed = rand::random();
+        self.rngs.clear();
+        self.rng = None;
+        info!("Rerolled the random seed to {}.", self.seed);
     }
 
     pub fn beam(&mut self, channel: ChannelIdx) -> &mut Beam {
         &mut self.channels[channel].beam
     }
 
+    pub fn grand_master(&self) -> UnipolarFloat {
+        self.grand_master
+    }
+
+    /// Return the master color correction of every arc, if it does anything.
+    pub fn color_correction(&self) -> Option<ColorCorrection> {
+        let color = ColorCorrection {
+            hue_shift: self.hue_shift.val(),
+            saturation: self.saturation.val(),
+            brightness: self.brightness.val(),
+        };
+        (!color.is_identity()).then_some(color)
+    }
+
+    /// Restore normal global state: the grand master at full, no blackout,
+    /// no master color correction, and no channel bumped, including inside
+    /// looks.  Beams forget anything they were waiting on a clock to do.
+    /// Emits nothing; the caller is expected to emit the entire state after.
+    pub fn panic(&mut self) {
+        self.grand_master = UnipolarFloat::ONE;
+        self.blackout = false;
+        self.hue_shift = UnipolarFloat::ZERO;
+        self.saturation = UnipolarFloat::ONE;
+        self.brightness = UnipolarFloat::ONE;
+        for channel in &mut self.channels {
+            channel.panic();
+        }
+    }
+
+    /// Return the level of a channel.
+    pub fn level(&self, channel: ChannelIdx) -> UnipolarFloat {
+        self.channels[channel].level
+    }
+
     pub fn channels(&mut self) -> impl Iterator<Item = &mut Channel> {
         self.channels.iter_mut()
     }
 
+    pub fn color_organ(&mut self) -> &mut ColorOrgan {
+        &mut self.color_organ
+    }
+
+    pub fn palettes(&mut self) -> &mut Palettes {
+        &mut self.palettes
+    }
+
+    /// Configure the palettes available for selection.
+    pub fn set_palettes(&mut self, palettes: Vec<PaletteConfig>) {
+        self.palettes.set_defined(palettes);
+    }
+
+    /// Return the palette that arcs in palette mode draw from, if any.
+    pub fn palette(&self) -> Option<Palette> {
+        self.palettes.current()
+    }
+
+    /// Set the automatic gate level, which scales the mixer output along
+    /// with the grand master.
+    pub fn set_gate(&mut self, level: UnipolarFloat) {
+        self.gate = level;
+    }
+
+    /// Set which video channels have no client connected, and the policy to
+    /// apply to each of them.
+    pub fn set_idle_outputs(&mut self, idle_outputs: HashMap<VideoChannel, IdlePolicy>) {
+        self.idle_outputs = idle_outputs;
+    }
+
+    /// Limit the number of arcs sent each frame.
+    /// When the limit is exceeded, the channels drawn lowest are rendered with
+    /// fewer segments first.
+    pub fn set_arc_budget(&mut self, arc_budget: Option<usize>) {
+        self.arc_budget = arc_budget;
+    }
+
+    /// Configure the video channels that are rendered as stereo pairs.
+    pub fn set_stereo(&mut self, stereo: Vec<StereoPair>) {
+        self.stereo = stereo;
+    }
+
+    /// Configure the channels that are fed by external sources.
+    pub fn set_external_sources(&mut self, external: HashMap<ChannelIdx, ExternalFeed>) {
+        self.external = external;
+        self.apply_external_sources();
+    }
+
+    /// Feed each channel from its external source, if it has one.
+    fn apply_external_sources(&mut self) {
+        for (i, channel) in self.channels.iter_mut().enumerate() {
+            channel.source = self.external.get(&ChannelIdx(i)).cloned();
+        }
+    }
+
+    /// Return the video channels a mixer channel is drawn on, with the
+    /// horizontal offset to draw it at on each.
+    fn targets(
+        &self,
+        channel: &Channel,
+        dark: impl Fn(&VideoChannel) -> bool,
+    ) -> Vec<(VideoChannel, f64)> {
+        let mut targets = eye_offsets(&self.stereo, channel.video_outs.iter(), channel.depth);
+        targets.retain(|(vc, _)| !dark(vc));
+        targets
+    }
+
     pub fn channel_count(&self) -> usize {
         self.channels.len()
     }
 
     /// Render the current state of the mixer.
     /// Each inner vector represents one virtual video channel.
+    /// Channels are rendered in parallel, then layered in draw order.
+    /// Channels feeding a stereo pair are offset by their depth for each eye.
+    #[allow(unused)]
     pub fn render(&self, external_clocks: &ClockBank) -> Vec<LayerCollection> {
-        let mut video_outs = Vec::with_capacity(Self::N_VIDEO_CHANNELS);
-        for _ in 0..Self::N_VIDEO_CHANNELS {
-            video_outs.push(Vec::new());
+        self.render_metered(external_clocks).video_outs
+    }
+
+    /// Render the current state of the mixer, along with how each layer is
+    /// blended, the peak level of light each channel drew, and the layer
+    /// each channel rendered.
+    pub fn render_metered(&self, external_clocks: &ClockBank) -> Render {
+        let mut render = self.start_render();
+        let all: Vec<VideoChannel> = (0..Self::N_VIDEO_CHANNELS).map(VideoChannel).collect();
+        self.render_into(external_clocks, &all, &mut render);
+        render
+    }
+
+    /// Start a render that video channels are added to one at a time, with
+    /// render_into.
+    pub fn start_render(&self) -> Render {
+        Render {
+            video_outs: vec![Vec::new(); Self::N_VIDEO_CHANNELS],
+            blend: vec![Vec::new(); Self::N_VIDEO_CHANNELS],
+            peaks: vec![UnipolarFloat::ZERO; self.channels.len()],
+            channel_layers: (0..self.channels.len())
+                .map(|_| Arc::new(Vec::new()))
+                .collect(),
+            rendered: vec![false; self.channels.len()],
+            started: false,
+        }
+    }
+
+    /// Return the video channels that at least one channel draws on.
+    pub fn live_video_channels(&self) -> Vec<VideoChannel> {
+        if self.blackout {
+            return Vec::new();
+        }
+        let dark = |vc: &VideoChannel| self.idle_outputs.get(vc) == Some(&IdlePolicy::Blackout);
+        let mut live: Vec<VideoChannel> = self
+            .channels
+            .iter()
+            .filter(|channel| channel.arc_count() > 0)
+            .flat_map(|channel| self.targets(channel, dark))
+            .map(|(vc, _)| vc)
+            .collect();
+        live.sort();
+        live.dedup();
+        live
+    }
+
+    /// Render the layers of the provided video channels into a render in
+    /// progress, so that the work of a frame can be spread out.  Each video
+    /// channel should be rendered once.  Channels that feed a video channel
+    /// rendered earlier are not rendered again.  Channels that feed no video
+    /// channel are rendered along with the first video channels, for their
+    /// layers alone.
+    pub fn render_into(
+        &self,
+        external_clocks: &ClockBank,
+        video_channels: &[VideoChannel],
+        render: &mut Render,
+    ) {
+        let first = !render.started;
+        render.started = true;
+        if self.blackout {
+            return;
+        }
+        let dark = |vc: &VideoChannel| self.idle_outputs.get(vc) == Some(&IdlePolicy::Blackout);
+        let level_scale = self.grand_master * self.gate;
+        let channels: Vec<(ChannelIdx, &Channel)> = indexed_in_draw_order(&self.channels)
+            .filter(|(_, channel)| !channel.only_feeds(dark))
+            .collect();
+        // Share the arc budget across every channel, not just those rendered
+        // now, so that a channel renders the same whenever it is rendered.
+        let resolutions = match self.arc_budget {
+            Some(budget) => {
+                let costs: Vec<usize> = channels
+                    .iter()
+                    .map(|(_, c)| c.arc_count() * self.targets(c, dark).len())
+                    .collect();
+                allocate_arcs(budget, &costs)
+            }
+            None => vec![1.0; channels.len()],
+        };
+        let wanted = |targets: &[(VideoChannel, f64)]| {
+            targets.iter().any(|(vc, _)| video_channels.contains(vc))
+                || (first && targets.is_empty())
+        };
+        let pending: Vec<(ChannelIdx, &Channel, f64)> = channels
+            .iter()
+            .zip(resolutions)
+            .filter(|((index, channel), _)| {
+                !render.rendered[index.0] && wanted(&self.targets(channel, dark))
+            })
+            .map(|((index, channel), resolution)| (*index, *channel, resolution))
+            .collect();
+        let rendered: Vec<(ChannelIdx, &Channel, Vec<ArcSegment>)> = pending
+            .into_par_iter()
+            .map(|(index, channel, resolution)| {
+                let rendered_beam = channel.render(
+                    level_scale * self.color_organ.level(index),
+                    false,
+                    resolution,
+                    external_clocks,
+                );
+                (index, channel, rendered_beam)
+            })
+            .collect();
+        for (index, channel, rendered_beam) in rendered {
+            render.rendered[index.0] = true;
+            if rendered_beam.is_empty() {
+                continue;
+            }
+            if !self.targets(channel, dark).is_empty() {
+                render.peaks[index.0] = peak_level(&rendered_beam);
+            }
+            render.channel_layers[index.0] = Arc::new(rendered_beam);
         }
-        for channel in &self.channels {
-            let rendered_beam = channel.render(UnipolarFloat::ONE, false, external_clocks);
-            if rendered_beam.len() == 0 {
+        for (index, channel) in channels {
+            let rendered_ptr = &render.channel_layers[index.0];
+            if rendered_ptr.is_empty() {
                 continue;
             }
-            let rendered_ptr = Arc::new(rendered_beam);
-            for video_chan in &channel.video_outs {
-                video_outs[video_chan.0].push(rendered_ptr.clone());
+            for (video_chan, offset) in self.targets(channel, dark) {
+                if !video_channels.contains(&video_chan) {
+                    continue;
+                }
+                render.video_outs[video_chan.0].push(if offset == 0. {
+                    rendered_ptr.clone()
+                } else {
+                    Arc::new(shift(rendered_ptr, offset))
+                });
+                render.blend[video_chan.0].push(channel.blend_mode());
             }
         }
-        video_outs
     }
 
     /// Emit the current value of all controllable mixer state.
     pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
-        for (index, channel) in self.channels.iter().enumerate() {
-            let mut emit = |csc| {
-                emitter.emit_mixer_state_change(StateChange {
-                    channel: ChannelIdx(index),
-                    change: csc,
-                })
-            };
-            emit(ChannelStateChange::Level(channel.level));
-            emit(ChannelStateChange::Bump(channel.bump));
-            emit(ChannelStateChange::Mask(channel.mask));
-            emit(ChannelStateChange::ContainsLook(match channel.beam {
-                Beam::Look(_) => true,
-                _ => false,
-            }));
-            for video_chan in 0..Self::N_VIDEO_CHANNELS {
-                let vc = VideoChannel(video_chan);
-                emit(ChannelStateChange::VideoChannel((
-                    vc,
-                    channel.video_outs.contains(&vc),
-                )));
-            }
+        emitter.emit_mixer_state_change(StateChange::GrandMaster(self.grand_master));
+        emitter.emit_mixer_state_change(StateChange::Blackout(self.blackout));
+        emitter.emit_mixer_state_change(StateChange::HueShift(self.hue_shift));
+        emitter.emit_mixer_state_change(StateChange::Saturation(self.saturation));
+        emitter.emit_mixer_state_change(StateChange::Brightness(self.brightness));
+        for index in 0..self.channels.len() {
+            self.emit_channel_state(ChannelIdx(index), emitter);
+        }
+    }
+
+    /// Emit the current value of all controllable state for one channel.
+    fn emit_channel_state<E: EmitStateChange>(&self, index: ChannelIdx, emitter: &mut E) {
+        let channel = &self.channels[index];
+        let mut emit = |csc| emitter.emit_mixer_state_change(StateChange::Channel(index, csc));
+        emit(ChannelStateChange::Level(channel.level));
+        emit(ChannelStateChange::Trim(channel.trim));
+        emit(ChannelStateChange::Bump(channel.bump));
+        emit(ChannelStateChange::Mask(channel.mask));
+        emit(ChannelStateChange::Additive(channel.additive));
+        emit(ChannelStateChange::Depth(channel.depth));
+        emit(ChannelStateChange::DrawOrder(channel.draw_order));
+        emit(ChannelStateChange::ContainsLook(match channel.beam {
+            Beam::Look(_) => true,
+            _ => false,
+        }));
+        for video_chan in 0..Self::N_VIDEO_CHANNELS {
+            let vc = VideoChannel(video_chan);
+            emit(ChannelStateChange::VideoChannel((
+                vc,
+                channel.video_outs.contains(&vc),
+            )));
         }
     }
 
     /// Handle a control event.
     /// Emit any state changes that have happened as a result of handling.
     pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) {
-        use ChannelControlMessage::*;
-        match msg.msg {
-            Set(sc) => self.handle_state_change(
-                StateChange {
-                    channel: msg.channel,
-                    change: sc,
-                },
-                emitter,
-            ),
-            ToggleMask => {
-                let toggled = !self.channels[msg.channel].mask;
-                self.handle_state_change(
-                    StateChange {
-                        channel: msg.channel,
-                        change: ChannelStateChange::Mask(toggled),
-                    },
-                    emitter,
-                )
+        match msg {
+            ControlMessage::Set(sc) => self.handle_state_change(sc, emitter),
+            ControlMessage::ToggleBlackout => {
+                self.handle_state_change(StateChange::Blackout(!self.blackout), emitter)
             }
-            ToggleVideoChannel(vc) => {
-                let toggled = !self.channels[msg.channel].video_outs.contains(&vc);
-                self.handle_state_change(
-                    StateChange {
-                        channel: msg.channel,
-                        change: ChannelStateChange::VideoChannel((vc, toggled)),
-                    },
-                    emitter,
-                )
+            ControlMessage::Channel(channel, msg) => self.control_channel(channel, msg, emitter),
+            ControlMessage::CloneChannel { from, to } => self.clone_channel(from, to, emitter),
+            ControlMessage::RerollSeed => self.reroll_seed(),
+        }
+    }
+
+    /// Replace the entire contents of one channel with a copy of another.
+    /// The beam, level, blending, draw order, and video routing are all
+    /// copied.
+    fn clone_channel<E: EmitStateChange>(
+        &mut self,
+        from: ChannelIdx,
+        to: ChannelIdx,
+        emitter: &mut E,
+    ) {
+        if from == to {
+            return;
+        }
+        let mut channel = self.channels[from].clone();
+        // Bump is momentary; it belongs to the button being held, not the beam.
+        channel.bump = false;
+        // External sources stay with their channel.
+        channel.source = self.external.get(&to).cloned();
+        self.channels[to] = channel;
+        self.emit_channel_state(to, emitter);
+    }
+
+    fn control_channel<E: EmitStateChange>(
+        &mut self,
+        channel: ChannelIdx,
+        msg: ChannelControlMessage,
+        emitter: &mut E,
+    ) {
+        use ChannelControlMessage::*;
+        let change = match msg {
+            Set(sc) => sc,
+            ToggleMask => ChannelStateChange::Mask(!self.channels[channel].mask),
+            ToggleAdditive => ChannelStateChange::Additive(!self.channels[channel].additive),
+            BringToFront => {
+                self.bring_to_front(channel, emitter);
+                return;
             }
+            ToggleVideoChannel(vc) => ChannelStateChange::VideoChannel((
+                vc,
+                !self.channels[channel].video_outs.contains(&vc),
+            )),
+        };
+        self.handle_state_change(StateChange::Channel(channel, change), emitter);
+    }
+
+    /// Draw this channel on top of every other channel.
+    ///
+    /// If the channel is already in front, or there is no room above the
+    /// frontmost channel, the other channels are first renumbered to close up
+    /// any gaps in their draw order, preserving their relative order.
+    fn bring_to_front<E: EmitStateChange>(&mut self, channel: ChannelIdx, emitter: &mut E) {
+        let front = self
+            .channels
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != channel.0)
+            .map(|(_, c)| c.draw_order)
+            .max()
+            .unwrap_or(0);
+        if front < Channel::MAX_DRAW_ORDER {
+            self.set_draw_order(channel, front + 1, emitter);
+            return;
+        }
+        let mut others: Vec<usize> = (0..self.channels.len())
+            .filter(|i| *i != channel.0)
+            .collect();
+        others.sort_by_key(|i| self.channels[*i].draw_order);
+        for (order, i) in others.iter().enumerate() {
+            self.set_draw_order(ChannelIdx(*i), order as u8, emitter);
+        }
+        self.set_draw_order(channel, others.len() as u8, emitter);
+    }
+
+    fn set_draw_order<E: EmitStateChange>(&mut self, channel: ChannelIdx, v: u8, emitter: &mut E) {
+        if self.channels[channel].draw_order != v {
+            self.handle_state_change(
+                StateChange::Channel(channel, ChannelStateChange::DrawOrder(v)),
+                emitter,
+            );
         }
     }
 
     fn handle_state_change<E: EmitStateChange>(&mut self, sc: StateChange, emitter: &mut E) {
-        use ChannelStateChange::*;
-        match sc.change {
-            Level(v) => self.channels[sc.channel].level = v,
-            Bump(v) => self.channels[sc.channel].bump = v,
-            Mask(v) => self.channels[sc.channel].mask = v,
-            VideoChannel((vc, active)) => {
-                if active {
-                    self.channels[sc.channel].video_outs.insert(vc);
-                } else {
-                    self.channels[sc.channel].video_outs.remove(&vc);
+        match sc {
+            StateChange::GrandMaster(v) => self.grand_master = v,
+            StateChange::Blackout(v) => self.blackout = v,
+            StateChange::HueShift(v) => self.hue_shift = v,
+            StateChange::Saturation(v) => self.saturation = v,
+            StateChange::Brightness(v) => self.brightness = v,
+            StateChange::Channel(channel, ref change) => {
+                use ChannelStateChange::*;
+                match *change {
+                    Level(v) => self.channels[channel].level = v,
+                    Trim(v) => self.channels[channel].trim = v,
+                    Bump(v) => self.channels[channel].bump = v,
+                    Mask(v) => self.channels[channel].mask = v,
+                    Additive(v) => self.channels[channel].additive = v,
+                    Depth(v) => self.channels[channel].depth = v,
+                    DrawOrder(v) => {
+                        self.channels[channel].draw_order = v.min(Channel::MAX_DRAW_ORDER)
+                    }
+                    VideoChannel((vc, active)) => {
+                        if active {
+                            self.channels[channel].video_outs.insert(vc);
+                        } else {
+                            self.channels[channel].video_outs.remove(&vc);
+                        }
+                    }
+                    ContainsLook(_) | Meter(_) => (),
                 }
             }
-            ContainsLook(_) => (),
         };
         emitter.emit_mixer_state_change(sc);
     }
 }
 
+/// Everything the mixer renders for a frame.
+pub struct Render {
+    /// The layers of each video channel, in draw order.
+    pub video_outs: Vec<LayerCollection>,
+    /// How each layer of each video channel is blended.
+    pub blend: Vec<Vec<BlendMode>>,
+    /// Peak level of light each channel drew, indexed by channel.
+    pub peaks: Vec<UnipolarFloat>,
+    /// The layer each channel rendered, indexed by channel.
+    pub channel_layers: LayerCollection,
+    /// Which channels have been rendered so far, indexed by channel.
+    rendered: Vec<bool>,
+    /// True once any video channels have been rendered.
+    started: bool,
+}
+
 /// The contents of a mixer channel.
 ///
 /// By default, outputs to video feed 0.
@@ -165,9 +608,31 @@ pub struct Channel {
     pub bump: bool,
     pub mask: bool,
     pub video_outs: HashSet<VideoChannel>,
+    /// Channels are drawn in ascending draw order, so higher values are drawn
+    /// on top.  Ties are broken by channel index.
+    #[serde(default)]
+    pub draw_order: u8,
+    /// Distance in front of the screen when shown in stereo.
+    /// Negative values are behind the screen.
+    #[serde(default = "default_depth")]
+    pub depth: BipolarFloat,
+    /// Scales the channel's level before the fader, to balance beams that
+    /// are brighter than others.
+    #[serde(default = "default_trim")]
+    pub trim: UnipolarFloat,
+    /// Add the channel's light to the channels beneath it rather than
+    /// covering them.
+    #[serde(default)]
+    pub additive: bool,
+    /// If set, this channel draws content from another program rather than
+    /// its beam.
+    #[serde(skip)]
+    pub source: Option<ExternalFeed>,
 }
 
 impl Channel {
+    pub const MAX_DRAW_ORDER: u8 = 127;
+
     fn new(beam: Beam) -> Self {
         let mut video_outs = HashSet::new();
         video_outs.insert(VideoChannel(0));
@@ -177,12 +642,59 @@ impl Channel {
             bump: false,
             mask: false,
             video_outs,
+            draw_order: 0,
+            depth: BipolarFloat::ZERO,
+            trim: UnipolarFloat::ONE,
+            additive: false,
+            source: None,
         }
     }
 
     /// Update the state of the beam in this channel.
-    pub fn update_state(&mut self, delta_t: Duration) {
-        self.beam.update_state(delta_t);
+    pub fn update_state(
+        &mut self,
+        delta_t: Duration,
+        external_clocks: &ClockBank,
+        rng: &mut ShowRng,
+    ) {
+        self.beam.update_state(delta_t, external_clocks, rng);
+    }
+
+    fn panic(&mut self) {
+        self.bump = false;
+        match &mut self.beam {
+            Beam::Tunnel(tunnel) => tunnel.cancel_pending(),
+            Beam::Look(look) => look.channels.iter_mut().for_each(Channel::panic),
+            Beam::Generator(_) => (),
+        }
+    }
+
+    /// Return true if this channel feeds at least one video channel, and
+    /// every video channel it feeds matches the predicate.
+    fn only_feeds(&self, predicate: impl Fn(&VideoChannel) -> bool) -> bool {
+        !self.video_outs.is_empty() && self.video_outs.iter().all(predicate)
+    }
+
+    /// Return how the channel's layer is blended with the channels beneath.
+    fn blend_mode(&self) -> BlendMode {
+        if self.mask {
+            BlendMode::Mask
+        } else if self.additive {
+            BlendMode::Additive
+        } else {
+            BlendMode::Alpha
+        }
+    }
+
+    /// Return the number of arcs this channel renders at full resolution.
+    pub fn arc_count(&self) -> usize {
+        if (!self.bump && self.level == 0.) || self.trim == 0. {
+            return 0;
+        }
+        match &self.source {
+            Some(source) => source.arc_count(),
+            None => self.beam.arc_count(),
+        }
     }
 
     /// Render the beam in this channel.
@@ -190,6 +702,7 @@ impl Channel {
         &self,
         level_scale: UnipolarFloat,
         mask: bool,
+        resolution: f64,
         external_clocks: &ClockBank,
     ) -> Vec<ArcSegment> {
         let mut level: UnipolarFloat = if self.bump {
@@ -197,12 +710,97 @@ impl Channel {
         } else {
             self.level
         };
-        level = level * level_scale;
+        level = level * self.trim * level_scale;
         // if this channel is off, don't render at all
         if level == 0. {
             return Vec::new();
         }
-        self.beam.render(level, self.mask || mask, external_clocks)
+        // External content can't be rendered at reduced resolution.
+        if let Some(source) = &self.source {
+            return source.render(level, self.mask || mask);
+        }
+        self.beam
+            .render(level, self.mask || mask, resolution, external_clocks)
+    }
+}
+
+/// Lowest fraction of full resolution a channel is reduced to in order to
+/// meet the arc budget.
+const MIN_RESOLUTION: f64 = 0.125;
+
+/// Choose the resolution to render each channel at, so that the total cost
+/// fits within the budget if possible.
+/// Costs are in order of ascending priority; the lowest priority channels are
+/// reduced first, each no further than MIN_RESOLUTION.
+fn allocate_arcs(budget: usize, costs: &[usize]) -> Vec<f64> {
+    let mut excess = costs.iter().sum::<usize>().saturating_sub(budget) as f64;
+    costs
+        .iter()
+        .map(|&cost| {
+            if excess <= 0. || cost == 0 {
+                return 1.0;
+            }
+            let cost = cost as f64;
+            let cut = excess.min(cost * (1. - MIN_RESOLUTION));
+            excess -= cut;
+            1. - cut / cost
+        })
+        .collect()
+}
+
+/// Iterate over channels in the order they should be drawn.
+pub fn in_draw_order(channels: &[Channel]) -> impl Iterator<Item = &Channel> {
+    indexed_in_draw_order(channels).map(|(_, channel)| channel)
+}
+
+/// Iterate over channels and their indices in the order they should be drawn.
+fn indexed_in_draw_order(channels: &[Channel]) -> impl Iterator<Item = (ChannelIdx, &Channel)> {
+    let mut ordered: Vec<(ChannelIdx, &Channel)> = channels
+        .iter()
+        .enumerate()
+        .map(|(i, channel)| (ChannelIdx(i), channel))
+        .collect();
+    // Stable sort, so channels with the same draw order stay in index order.
+    ordered.sort_by_key(|(_, c)| c.draw_order);
+    ordered.into_iter()
+}
+
+/// Return the brightest level of light drawn by any of the arcs.
+/// Masking arcs are drawn in black and so contribute nothing.
+fn peak_level(arcs: &[ArcSegment]) -> UnipolarFloat {
+    UnipolarFloat::new(
+        arcs.iter()
+            .map(|arc| arc.level * arc.val)
+            .fold(0., f64::max),
+    )
+}
+
+/// Emits the level meter reading for each channel when it visibly changes.
+#[derive(Default)]
+pub struct ChannelMeters {
+    /// Last reading emitted for each channel, quantized to avoid flooding UIs
+    /// with updates.
+    emitted: Vec<Option<u8>>,
+}
+
+impl ChannelMeters {
+    /// Resolution of emitted meter updates.
+    const STEPS: f64 = 127.;
+
+    /// Update the meters with the peak level drawn by each channel in the
+    /// most recently rendered frame.
+    pub fn update<E: EmitStateChange>(&mut self, peaks: &[UnipolarFloat], emitter: &mut E) {
+        self.emitted.resize(peaks.len(), None);
+        for (i, (peak, emitted)) in peaks.iter().zip(self.emitted.iter_mut()).enumerate() {
+            let quantized = (peak.val() * Self::STEPS).round() as u8;
+            if *emitted != Some(quantized) {
+                *emitted = Some(quantized);
+                emitter.emit_mixer_state_change(StateChange::Channel(
+                    ChannelIdx(i),
+                    ChannelStateChange::Meter(*peak),
+                ));
+            }
+        }
     }
 }
 
@@ -223,26 +821,48 @@ impl Default for ChannelIdx {
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct VideoChannel(pub usize);
 
-pub struct ControlMessage {
-    pub channel: ChannelIdx,
-    pub msg: ChannelControlMessage,
+pub enum ControlMessage {
+    Set(StateChange),
+    ToggleBlackout,
+    Channel(ChannelIdx, ChannelControlMessage),
+    /// Copy the entire contents of one channel into another.
+    CloneChannel {
+        from: ChannelIdx,
+        to: ChannelIdx,
+    },
+    /// Draw a new random seed.
+    RerollSeed,
 }
+
 pub enum ChannelControlMessage {
     Set(ChannelStateChange),
     ToggleMask,
+    ToggleAdditive,
     ToggleVideoChannel(VideoChannel),
+    BringToFront,
 }
 
-pub struct StateChange {
-    pub channel: ChannelIdx,
-    pub change: ChannelStateChange,
+pub enum StateChange {
+    GrandMaster(UnipolarFloat),
+    Blackout(bool),
+    HueShift(UnipolarFloat),
+    Saturation(UnipolarFloat),
+    Brightness(UnipolarFloat),
+    Channel(ChannelIdx, ChannelStateChange),
 }
+
 pub enum ChannelStateChange {
     Level(UnipolarFloat),
+    Trim(UnipolarFloat),
     Bump(bool),
     Mask(bool),
+    Additive(bool),
+    Depth(BipolarFloat),
+    DrawOrder(u8),
     VideoChannel((VideoChannel, bool)),
     ContainsLook(bool),
+    /// Peak level of light drawn by the channel in the last frame.
+    Meter(UnipolarFloat),
 }
 
 pub trait EmitStateChange {
@@ -255,3 +875,271 @@ impl<T: EmitShowStateChange> EmitStateChange for T {
         self.emit(ShowStateChange::Mixer(sc))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::show::StateChange as ShowStateChange;
+
+    struct Discard;
+
+    impl EmitShowStateChange for Discard {
+        fn emit(&mut self, _: ShowStateChange) {}
+    }
+
+    fn draw_orders(mixer: &Mixer) -> Vec<u8> {
+        mixer.channels.iter().map(|c| c.draw_order).collect()
+    }
+
+    #[test]
+    fn test_bring_to_front() {
+        let mut mixer = Mixer::new(1);
+        mixer.bring_to_front(ChannelIdx(3), &mut Discard);
+        assert_eq!(vec![0, 0, 0, 1, 0, 0, 0, 0], draw_orders(&mixer));
+        mixer.bring_to_front(ChannelIdx(0), &mut Discard);
+        assert_eq!(vec![2, 0, 0, 1, 0, 0, 0, 0], draw_orders(&mixer));
+
+        // Renumber when there is no more room at the top.
+        mixer.handle_state_change(
+            StateChange::Channel(
+                ChannelIdx(5),
+                ChannelStateChange::DrawOrder(Channel::MAX_DRAW_ORDER),
+            ),
+            &mut Discard,
+        );
+        mixer.bring_to_front(ChannelIdx(1), &mut Discard);
+        assert_eq!(vec![5, 7, 0, 4, 1, 6, 2, 3], draw_orders(&mixer));
+
+        let order: Vec<u8> = in_draw_order(&mixer.channels)
+            .map(|c| c.draw_order)
+            .collect();
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7], order);
+    }
+
+    #[test]
+    fn test_clone_channel() {
+        let mut mixer = Mixer::new(1);
+        let from = ChannelIdx(2);
+        let to = ChannelIdx(6);
+        mixer.channels[from].level = UnipolarFloat::new(0.5);
+        mixer.channels[from].bump = true;
+        mixer.channels[from].video_outs.insert(VideoChannel(3));
+        mixer.control(ControlMessage::CloneChannel { from, to }, &mut Discard);
+
+        let cloned = &mixer.channels[to];
+        assert_eq!(0.5, cloned.level.val());
+        assert!(!cloned.bump);
+        assert_eq!(mixer.channels[from].video_outs, cloned.video_outs);
+    }
+
+    #[test]
+    fn test_panic() {
+        let mut mixer = Mixer::new(1);
+        mixer.handle_state_change(StateChange::GrandMaster(UnipolarFloat::ZERO), &mut Discard);
+        mixer.control(ControlMessage::ToggleBlackout, &mut Discard);
+        mixer.handle_state_change(StateChange::HueShift(UnipolarFloat::new(0.5)), &mut Discard);
+        assert!(mixer.color_correction().is_some());
+        mixer.channels[1].bump = true;
+        let mut look = mixer.as_look();
+        look.channels[2].bump = true;
+        mixer.channels[3].beam = Beam::Look(look);
+
+        mixer.panic();
+        assert_eq!(UnipolarFloat::ONE, mixer.grand_master());
+        assert!(!mixer.blackout);
+        assert!(mixer.color_correction().is_none());
+        assert!(mixer.channels.iter().all(|c| !c.bump));
+        match &mixer.channels[3].beam {
+            Beam::Look(look) => assert!(look.channels.iter().all(|c| !c.bump)),
+            _ => panic!("channel 3 should hold a look"),
+        }
+    }
+
+    /// Compare the time taken to evaluate frames of increasingly large mixers
+    /// using increasing numbers of threads.
+    /// Run with cargo test --release -- --ignored --nocapture bench_parallel
+    #[test]
+    #[ignore]
+    fn bench_parallel_scaling() {
+        const FRAMES: u32 = 500;
+        let clocks = ClockBank::new();
+        let delta_t = Duration::from_micros(16667);
+        println!("channels  threads  ms/frame");
+        for n_pages in &[1, 2, 4] {
+            let mut mixer = Mixer::new(*n_pages);
+            for channel in &mut mixer.channels {
+                channel.level = UnipolarFloat::ONE;
+            }
+            for n_threads in &[1, 2, 4, 8] {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(*n_threads)
+                    .build()
+                    .unwrap();
+                let start = std::time::Instant::now();
+                pool.install(|| {
+                    for _ in 0..FRAMES {
+                        mixer.update_state(delta_t, &clocks);
+                        mixer.render(&clocks);
+                    }
+                });
+                println!(
+                    "{:8}  {:7}  {:8.3}",
+                    mixer.channel_count(),
+                    n_threads,
+                    start.elapsed().as_secs_f64() * 1000. / FRAMES as f64
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_allocate_arcs() {
+        assert_eq!(vec![1.0, 1.0], allocate_arcs(100, &[40, 60]));
+        assert_eq!(vec![0.5, 1.0], allocate_arcs(80, &[40, 60]));
+        assert_eq!(vec![0.125, 0.75], allocate_arcs(50, &[40, 60]));
+        assert_eq!(vec![1.0, 0.125, 0.125], allocate_arcs(0, &[0, 40, 60]));
+    }
+
+    #[test]
+    fn test_arc_budget() {
+        let mut mixer = Mixer::new(1);
+        let clocks = ClockBank::new();
+        for channel in &mut mixer.channels {
+            channel.level = UnipolarFloat::ONE;
+        }
+        let full = mixer.channels[ChannelIdx(0)].arc_count();
+        mixer.set_arc_budget(Some(full * 6));
+        let layers = &mixer.render(&clocks)[0];
+        let total: usize = layers.iter().map(|l| l.len()).sum();
+        assert!(total <= full * 6 + layers.len());
+        // The topmost channel is drawn last and reduced last.
+        assert_eq!(full, layers[layers.len() - 1].len());
+        assert!(layers[0].len() < full);
+    }
+
+    #[test]
+    fn test_trim_and_meter() {
+        let mut mixer = Mixer::new(1);
+        let clocks = ClockBank::new();
+        mixer.channels[ChannelIdx(0)].level = UnipolarFloat::new(0.5);
+        let peaks = mixer.render_metered(&clocks).peaks;
+        let untrimmed = peaks[0].val();
+        assert!(untrimmed > 0.);
+        assert_eq!(0., peaks[1].val());
+
+        mixer.channels[ChannelIdx(0)].trim = UnipolarFloat::new(0.5);
+        let peaks = mixer.render_metered(&clocks).peaks;
+        assert_eq!(untrimmed / 2., peaks[0].val());
+
+        // Masks draw no light, and neither do channels that feed no output.
+        mixer.channels[ChannelIdx(0)].mask = true;
+        assert_eq!(0., mixer.render_metered(&clocks).peaks[0].val());
+        mixer.channels[ChannelIdx(0)].mask = false;
+        mixer.channels[ChannelIdx(0)].video_outs.clear();
+        assert_eq!(0., mixer.render_metered(&clocks).peaks[0].val());
+    }
+
+    #[test]
+    fn test_blend_modes() {
+        let mut mixer = Mixer::new(1);
+        let clocks = ClockBank::new();
+        for i in 0..3 {
+            mixer.channels[ChannelIdx(i)].level = UnipolarFloat::ONE;
+        }
+        mixer.channels[ChannelIdx(1)].additive = true;
+        // Masking wins out over additive blending.
+        mixer.channels[ChannelIdx(2)].additive = true;
+        mixer.channels[ChannelIdx(2)].mask = true;
+        let render = mixer.render_metered(&clocks);
+        assert_eq!(3, render.video_outs[0].len());
+        assert_eq!(
+            vec![BlendMode::Alpha, BlendMode::Additive, BlendMode::Mask],
+            render.blend[0]
+        );
+        assert!(render.blend[1].is_empty());
+    }
+
+    #[test]
+    fn test_render_into() {
+        let mut mixer = Mixer::new(1);
+        let clocks = ClockBank::new();
+        for i in 0..3 {
+            mixer.channels[ChannelIdx(i)].level = UnipolarFloat::ONE;
+        }
+        mixer.channels[ChannelIdx(0)]
+            .video_outs
+            .insert(VideoChannel(1));
+        mixer.channels[ChannelIdx(1)].video_outs = HashSet::from([VideoChannel(1)]);
+        mixer.channels[ChannelIdx(2)].video_outs.clear();
+        assert_eq!(
+            vec![VideoChannel(0), VideoChannel(1)],
+            mixer.live_video_channels()
+        );
+
+        // Rendering video channels one at a time matches rendering them all
+        // at once.
+        let whole = mixer.render_metered(&clocks);
+        let mut parts = mixer.start_render();
+        mixer.render_into(&clocks, &[VideoChannel(1)], &mut parts);
+        assert_eq!(2, parts.video_outs[1].len());
+        assert!(parts.video_outs[0].is_empty());
+        // A channel that feeds no video channel is still rendered.
+        assert!(!parts.channel_layers[2].is_empty());
+        mixer.render_into(&clocks, &[VideoChannel(0)], &mut parts);
+        assert_eq!(whole.video_outs, parts.video_outs);
+        assert_eq!(whole.blend, parts.blend);
+        assert_eq!(whole.peaks, parts.peaks);
+        assert_eq!(whole.channel_layers, parts.channel_layers);
+    }
+
+    #[test]
+    fn test_blackout_idle_outputs() {
+        let mut mixer = Mixer::new(1);
+        let clocks = ClockBank::new();
+        mixer.channels[ChannelIdx(0)].level = UnipolarFloat::ONE;
+        mixer.channels[ChannelIdx(0)]
+            .video_outs
+            .insert(VideoChannel(1));
+        assert!(!mixer.render(&clocks)[0].is_empty());
+
+        let mut idle = HashMap::new();
+        idle.insert(VideoChannel(0), IdlePolicy::Blackout);
+        mixer.set_idle_outputs(idle);
+        let video_outs = mixer.render(&clocks);
+        assert!(video_outs[0].is_empty());
+        assert!(!video_outs[1].is_empty());
+    }
+
+    #[test]
+    fn test_seed() {
+        use rmp_serde::{Deserializer, Serializer};
+
+        let clocks = ClockBank::new();
+        let shatter = |mixer: &mut Mixer| {
+            for channel in &mut mixer.channels {
+                if let Beam::Tunnel(tunnel) = &mut channel.beam {
+                    tunnel.control(crate::tunnel::ControlMessage::Shatter, &mut Discard);
+                }
+            }
+            mixer.update_state(Duration::from_millis(100), &clocks);
+            mixer
+                .channels
+                .iter()
+                .map(|channel| format!("{:?}", channel))
+                .collect::<Vec<_>>()
+        };
+
+        let mut mixer = Mixer::new(1);
+        let mut buf = Vec::new();
+        mixer.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        let mut loaded = Mixer::deserialize(&mut Deserializer::new(&buf[..])).unwrap();
+
+        // A loaded mixer makes the same choices, and each channel its own.
+        let shattered = shatter(&mut mixer);
+        assert_eq!(shattered, shatter(&mut loaded));
+        assert_ne!(shattered[0], shattered[1]);
+
+        loaded.reroll_seed();
+        assert_ne!(shatter(&mut mixer), shatter(&mut loaded));
+    }
+}
@@ -0,0 +1,243 @@
+//! Named sets of output settings, for tours that alternate between a few
+//! recurring venues.
+//!
+//! Each venue has its own projectors, LED rig, and cabling.  An output profile
+//! gathers everything about the outputs that differs between venues: which
+//! output each of the mixer's video channels is sent to, the physical
+//! properties of each output, such as aspect compensation and latency, and
+//! the LED patch.  Every profile lives in the one show config, and the active
+//! profile can be switched while the show runs.  The name of the active
+//! profile is saved with the show.
+//!
+//! A show config without profiles has a single implicit profile built from
+//! its top-level video outputs and pixel map.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+};
+use tracing::{info, warn};
+
+use crate::{
+    master_ui::EmitStateChange as EmitShowStateChange, mixer::Mixer, pixel_map::PixelMapConfig,
+    video_out::VideoOutputConfig,
+};
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OutputProfileConfig {
+    pub name: String,
+    /// The output to send each of the mixer's video channels to, keyed by
+    /// video channel.  Unlisted video channels are sent to the output with
+    /// the same number.  Every output is then configured and subscribed to
+    /// by its number, as if it were a video channel.
+    #[serde(default)]
+    pub routing: BTreeMap<usize, usize>,
+    /// Physical properties of the display attached to each output.
+    #[serde(default)]
+    pub video_outputs: Vec<VideoOutputConfig>,
+    /// LED fixtures driven from the image of an output.
+    #[serde(default)]
+    pub pixel_map: Option<PixelMapConfig>,
+}
+
+impl OutputProfileConfig {
+    /// The most profiles that can be selected from a control surface.
+    pub const MAX_COUNT: usize = 8;
+
+    /// The name of the profile implied by a show config without profiles.
+    pub const DEFAULT_NAME: &'static str = "default";
+
+    /// Check a collection of profiles for consistency.
+    pub fn validate_all(profiles: &[Self]) -> Result<(), Box<dyn Error>> {
+        if profiles.len() > Self::MAX_COUNT {
+            bail!(
+                "{} output profiles are defined; at most {} are supported.",
+                profiles.len(),
+                Self::MAX_COUNT
+            );
+        }
+        let mut names = HashSet::new();
+        for profile in profiles {
+            if !names.insert(&profile.name) {
+                bail!(
+                    "Output profile name {} is used more than once.",
+                    profile.name
+                );
+            }
+            if let Err(e) = profile.validate() {
+                bail!("Output profile {}: {}", profile.name, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for (&chan, &output) in &self.routing {
+            if chan >= Mixer::N_VIDEO_CHANNELS || output >= Mixer::N_VIDEO_CHANNELS {
+                bail!(
+                    "Routing of video channel {} to output {} is out of range; there are {} video channels.",
+                    chan,
+                    output,
+                    Mixer::N_VIDEO_CHANNELS
+                );
+            }
+        }
+        let mut sources = [None; Mixer::N_VIDEO_CHANNELS];
+        for (chan, output) in self.routes().into_iter().enumerate() {
+            if let Some(other) = sources[output].replace(chan) {
+                bail!(
+                    "Video channels {} and {} are both sent to output {}.",
+                    other,
+                    chan,
+                    output
+                );
+            }
+        }
+        for output in &self.video_outputs {
+            output.validate()?;
+        }
+        if let Some(pixel_map) = &self.pixel_map {
+            pixel_map.validate()?;
+        }
+        Ok(())
+    }
+
+    /// The output each of the mixer's video channels is sent to.
+    pub fn routes(&self) -> Vec<usize> {
+        (0..Mixer::N_VIDEO_CHANNELS)
+            .map(|chan| self.routing.get(&chan).copied().unwrap_or(chan))
+            .collect()
+    }
+}
+
+/// Every output profile of the show, and which one is active.
+pub struct OutputProfiles {
+    profiles: Vec<OutputProfileConfig>,
+    active: usize,
+}
+
+impl OutputProfiles {
+    /// There must be at least one profile.
+    pub fn new(profiles: Vec<OutputProfileConfig>) -> Self {
+        assert!(!profiles.is_empty());
+        Self {
+            profiles,
+            active: 0,
+        }
+    }
+
+    pub fn active(&self) -> &OutputProfileConfig {
+        &self.profiles[self.active]
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &OutputProfileConfig> {
+        self.profiles.iter()
+    }
+
+    /// Return the message that activates a profile by name, such as when
+    /// loading a saved show.  A profile that no longer exists is ignored.
+    pub fn restore(&self, name: &str) -> Option<ControlMessage> {
+        match self.profiles.iter().position(|p| p.name == name) {
+            Some(i) => Some(ControlMessage::Set(StateChange::Profile(i))),
+            None => {
+                warn!("Saved output profile {} is not defined.", name);
+                None
+            }
+        }
+    }
+
+    /// Emit the current value of all controllable state.
+    pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
+        emitter.emit_output_profile_state_change(StateChange::Profile(self.active));
+    }
+
+    /// Handle a control event.
+    /// Emit any state changes that have happened as a result of handling.
+    /// Return true if the active profile changed.
+    pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) -> bool {
+        match msg {
+            ControlMessage::Set(sc) => self.handle_state_change(sc, emitter),
+        }
+    }
+
+    fn handle_state_change<E: EmitStateChange>(
+        &mut self,
+        sc: StateChange,
+        emitter: &mut E,
+    ) -> bool {
+        let changed = match sc {
+            StateChange::Profile(i) => {
+                if i >= self.profiles.len() {
+                    return false;
+                }
+                let changed = i != self.active;
+                self.active = i;
+                if changed {
+                    info!("Switched to output profile {}.", self.profiles[i].name);
+                }
+                changed
+            }
+        };
+        emitter.emit_output_profile_state_change(sc);
+        changed
+    }
+}
+
+pub enum ControlMessage {
+    Set(StateChange),
+}
+
+pub enum StateChange {
+    /// Activate a profile by index.
+    Profile(usize),
+}
+
+pub trait EmitStateChange {
+    fn emit_output_profile_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_output_profile_state_change(&mut self, sc: StateChange) {
+        use crate::show::StateChange as ShowStateChange;
+        self.emit(ShowStateChange::OutputProfile(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile(name: &str, routing: &[(usize, usize)]) -> OutputProfileConfig {
+        OutputProfileConfig {
+            name: name.to_string(),
+            routing: routing.iter().copied().collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_routing() {
+        let swapped = profile("swapped", &[(0, 1), (1, 0)]);
+        assert!(OutputProfileConfig::validate_all(std::slice::from_ref(&swapped)).is_ok());
+        assert_eq!(&[1, 0, 2], &swapped.routes()[..3]);
+
+        // Video channel 1 would also land on output 1.
+        assert!(OutputProfileConfig::validate_all(&[profile("clash", &[(0, 1)])]).is_err());
+        assert!(OutputProfileConfig::validate_all(&[profile(
+            "range",
+            &[(0, Mixer::N_VIDEO_CHANNELS)]
+        )])
+        .is_err());
+        assert!(
+            OutputProfileConfig::validate_all(&[profile("a", &[]), profile("a", &[])]).is_err()
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Named palettes of colors for tunnels to draw from.
+//!
+//! Palettes are defined in the show config.  Two decks each hold a selected
+//! palette, and a crossfader blends from the palette on deck A to the palette
+//! on deck B, so the look of the whole show can be recolored live.  With only
+//! one deck loaded, its palette is used whatever the crossfader says.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::{collections::HashSet, error::Error, sync::Arc};
+use tunnels_lib::{
+    number::UnipolarFloat,
+    palette::{Hsv, Palette},
+};
+
+use crate::{master_ui::EmitStateChange as EmitShowStateChange, validation};
+
+/// A named palette of anchor colors.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PaletteConfig {
+    pub name: String,
+    /// Anchor colors, spread evenly around the palette.
+    pub colors: Vec<Hsv>,
+}
+
+impl PaletteConfig {
+    /// The most palettes that can be selected from a control surface.
+    pub const MAX_COUNT: usize = 15;
+
+    /// Check a collection of palettes for consistency.
+    pub fn validate_all(palettes: &[Self]) -> Result<(), Box<dyn Error>> {
+        if palettes.len() > Self::MAX_COUNT {
+            bail!(
+                "{} palettes are defined; at most {} are supported.",
+                palettes.len(),
+                Self::MAX_COUNT
+            );
+        }
+        let mut names = HashSet::new();
+        for palette in palettes {
+            if !names.insert(&palette.name) {
+                bail!("Palette name {} is used more than once.", palette.name);
+            }
+            if palette.colors.is_empty() {
+                bail!("Palette {} has no colors.", palette.name);
+            }
+            let unit = |v: f64| (0.0..=1.0).contains(&v);
+            if palette
+                .colors
+                .iter()
+                .any(|c| !unit(c.hue) || !unit(c.sat) || !unit(c.val))
+            {
+                bail!(
+                    "Palette {} has a color component outside of [0, 1].",
+                    palette.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One of the two palette selections that the crossfader blends between.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Deck {
+    A,
+    B,
+}
+
+impl Deck {
+    pub const ALL: [Self; 2] = [Self::A, Self::B];
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Palettes {
+    /// Name of the palette on each deck, if any.
+    /// Saved by name so that shows survive reordering the config.
+    a: Option<String>,
+    b: Option<String>,
+    /// How far from deck A to deck B the show palette is.
+    crossfade: UnipolarFloat,
+    /// The palettes defined in the show config.
+    #[serde(skip)]
+    defined: Arc<Vec<PaletteConfig>>,
+}
+
+impl Default for Palettes {
+    fn default() -> Self {
+        Self {
+            a: None,
+            b: None,
+            crossfade: UnipolarFloat::ZERO,
+            defined: Arc::new(Vec::new()),
+        }
+    }
+}
+
+impl Palettes {
+    /// Configure the palettes available for selection.
+    /// Selections of palettes that no longer exist are cleared.
+    pub fn set_defined(&mut self, defined: Vec<PaletteConfig>) {
+        self.defined = Arc::new(defined);
+        for deck in Deck::ALL {
+            if self.index(deck).is_none() {
+                *self.deck_mut(deck) = None;
+            }
+        }
+    }
+
+    /// Return the palette to draw from, if either deck holds one.
+    pub fn current(&self) -> Option<Palette> {
+        let get = |deck| {
+            self.index(deck)
+                .map(|i| Palette(self.defined[i].colors.clone()))
+        };
+        match (get(Deck::A), get(Deck::B)) {
+            (Some(a), Some(b)) => Some(a.crossfade(&b, self.crossfade.val())),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn deck(&self, deck: Deck) -> &Option<String> {
+        match deck {
+            Deck::A => &self.a,
+            Deck::B => &self.b,
+        }
+    }
+
+    fn deck_mut(&mut self, deck: Deck) -> &mut Option<String> {
+        match deck {
+            Deck::A => &mut self.a,
+            Deck::B => &mut self.b,
+        }
+    }
+
+    /// Return the index of the palette on a deck, if it holds one.
+    fn index(&self, deck: Deck) -> Option<usize> {
+        let name = self.deck(deck).as_ref()?;
+        self.defined.iter().position(|p| &p.name == name)
+    }
+
+    /// Emit the current value of all controllable palette state.
+    pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
+        use StateChange::*;
+        for deck in Deck::ALL {
+            emitter.emit_palette_state_change(Select(deck, self.index(deck)));
+        }
+        emitter.emit_palette_state_change(Crossfade(self.crossfade));
+    }
+
+    /// Handle a control event.
+    /// Emit any state changes that have happened as a result of handling.
+    pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) {
+        use ControlMessage::*;
+        match msg {
+            Set(sc) => self.handle_state_change(sc, emitter),
+        }
+    }
+
+    fn handle_state_change<E: EmitStateChange>(&mut self, sc: StateChange, emitter: &mut E) {
+        use StateChange::*;
+        let sc = match validation::palette(sc, self.defined.len()) {
+            Some(sc) => sc,
+            None => return,
+        };
+        match sc {
+            Select(deck, v) => *self.deck_mut(deck) = v.map(|i| self.defined[i].name.clone()),
+            Crossfade(v) => self.crossfade = v,
+        };
+        emitter.emit_palette_state_change(sc);
+    }
+}
+
+pub enum ControlMessage {
+    Set(StateChange),
+}
+
+pub enum StateChange {
+    /// Load the palette with this index onto a deck, or empty the deck.
+    Select(Deck, Option<usize>),
+    Crossfade(UnipolarFloat),
+}
+
+pub trait EmitStateChange {
+    fn emit_palette_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_palette_state_change(&mut self, sc: StateChange) {
+        use crate::show::StateChange as ShowStateChange;
+        self.emit(ShowStateChange::Palette(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::master_ui::DummyEmitter;
+    use tunnels_lib::assert_almost_eq;
+
+    fn config(name: &str, hue: f64) -> PaletteConfig {
+        PaletteConfig {
+            name: name.to_string(),
+            colors: vec![Hsv {
+                hue,
+                sat: 1.,
+                val: 1.,
+            }],
+        }
+    }
+
+    fn set(palettes: &mut Palettes, sc: StateChange) {
+        palettes.control(ControlMessage::Set(sc), &mut DummyEmitter);
+    }
+
+    fn hue(palettes: &Palettes) -> f64 {
+        palettes.current().unwrap().sample(0.).unwrap().hue
+    }
+
+    #[test]
+    fn test_palettes() {
+        let mut palettes = Palettes::default();
+        palettes.set_defined(vec![config("red", 0.), config("green", 0.3)]);
+        assert!(palettes.current().is_none());
+
+        // A lone deck is used whatever the crossfader says.
+        set(&mut palettes, StateChange::Select(Deck::B, Some(1)));
+        assert_almost_eq(0.3, hue(&palettes));
+
+        set(&mut palettes, StateChange::Select(Deck::A, Some(0)));
+        assert_almost_eq(0., hue(&palettes));
+        set(
+            &mut palettes,
+            StateChange::Crossfade(UnipolarFloat::new(0.5)),
+        );
+        assert_almost_eq(0.15, hue(&palettes));
+
+        // Palettes that don't exist can't be selected.
+        set(&mut palettes, StateChange::Select(Deck::A, Some(2)));
+        assert_eq!(Some("red"), palettes.a.as_deref());
+
+        // Selections follow palettes by name, and are cleared if they're gone.
+        palettes.set_defined(vec![config("green", 0.3), config("blue", 0.6)]);
+        assert_eq!(None, palettes.a);
+        assert_eq!(Some(0), palettes.index(Deck::B));
+        assert_almost_eq(0.3, hue(&palettes));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(PaletteConfig::validate_all(&[config("red", 0.), config("green", 0.3)]).is_ok());
+        assert!(PaletteConfig::validate_all(&[config("red", 0.), config("red", 0.3)]).is_err());
+        assert!(PaletteConfig::validate_all(&[config("red", 1.5)]).is_err());
+        let mut empty = config("empty", 0.);
+        empty.colors.clear();
+        assert!(PaletteConfig::validate_all(&[empty]).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+//! Central registry describing every controllable parameter.
+//!
+//! Each parameter has a display name, a kind that determines its range, a
+//! default value, and a taper describing how the control value maps onto its
+//! effect.  Control surfaces, the generated control layout, and the show
+//! objects themselves all read from here rather than keeping their own copies
+//! of ranges and defaults.
+
+use serde::Serialize;
+use tunnels_lib::number::BipolarFloat;
+
+use crate::{
+    animation::{Target, Waveform},
+    clock::ControllableClock,
+    mixer::Channel,
+    output_profile::OutputProfileConfig,
+    palette::PaletteConfig,
+    shatter,
+    tunnel::N_ANIM,
+    video_out::GeometryPreset,
+};
+
+/// Specification of a single controllable parameter.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct ParamSpec {
+    /// Identifier of this parameter, unique within its group.
+    pub address: &'static str,
+    /// Human-readable name for display.
+    pub name: &'static str,
+    pub kind: ParamKind,
+    /// Value of this parameter in a freshly-created object.
+    pub default: f64,
+    pub taper: Taper,
+    /// Physical unit of this parameter, and the value in that unit when the
+    /// tapered control is at full scale.
+    pub unit: Option<(&'static str, f64)>,
+}
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub enum ParamKind {
+    /// Continuous value in [0, 1].
+    Unipolar,
+    /// Continuous value in [-1, 1].
+    Bipolar,
+    /// On/off state.
+    Toggle,
+    /// Momentary action with no state.
+    Button,
+    /// Integer value in [min, max].
+    Integer { min: i64, max: i64 },
+    /// Exactly one of the named options, represented by its index.
+    Choice(&'static [&'static str]),
+}
+
+/// How a control value is shaped before it takes effect.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub enum Taper {
+    Linear,
+    /// Square the magnitude, preserving sign.
+    /// This provides more resolution near zero.
+    Quadratic,
+}
+
+impl Taper {
+    pub fn apply(self, v: BipolarFloat) -> BipolarFloat {
+        match self {
+            Self::Linear => v,
+            Self::Quadratic => {
+                let mut scaled = f64::powi(v.val(), 2);
+                if v < 0. {
+                    scaled *= -1.
+                }
+                BipolarFloat::new(scaled)
+            }
+        }
+    }
+}
+
+impl ParamKind {
+    /// The inclusive range of values this kind of parameter can take.
+    pub fn range(&self) -> (f64, f64) {
+        match *self {
+            Self::Unipolar | Self::Toggle | Self::Button => (0., 1.),
+            Self::Bipolar => (-1., 1.),
+            Self::Integer { min, max } => (min as f64, max as f64),
+            Self::Choice(options) => (0., options.len().saturating_sub(1) as f64),
+        }
+    }
+}
+
+impl ParamSpec {
+    /// Interpret a value in [min, max] for an integer parameter as an offset
+    /// from the minimum, as sent by a 7-bit control.
+    pub fn integer_from_midi(&self, v: u8) -> i64 {
+        let (min, max) = self.kind.range();
+        (min as i64 + v as i64).min(max as i64)
+    }
+
+    /// Inverse of integer_from_midi.
+    pub fn integer_to_midi(&self, v: i64) -> u8 {
+        let (min, _) = self.kind.range();
+        (v - min as i64).clamp(0, 127) as u8
+    }
+}
+
+const fn spec(
+    address: &'static str,
+    name: &'static str,
+    kind: ParamKind,
+    default: f64,
+) -> ParamSpec {
+    ParamSpec {
+        address,
+        name,
+        kind,
+        default,
+        taper: Taper::Linear,
+        unit: None,
+    }
+}
+
+const fn button(address: &'static str, name: &'static str) -> ParamSpec {
+    spec(address, name, ParamKind::Button, 0.)
+}
+
+const fn toggle(address: &'static str, name: &'static str) -> ParamSpec {
+    spec(address, name, ParamKind::Toggle, 0.)
+}
+
+/// Tunnel rotation and marquee speeds are expressed in revolutions per frame
+/// at 30 fps; this is the full-scale speed in revolutions per second.
+const TUNNEL_SPEED_FULL_SCALE: f64 = 30. * crate::tunnel::ROT_SPEED_SCALE;
+
+const fn speed(address: &'static str, name: &'static str) -> ParamSpec {
+    ParamSpec {
+        taper: Taper::Quadratic,
+        unit: Some(("rev/s", TUNNEL_SPEED_FULL_SCALE)),
+        ..spec(address, name, ParamKind::Bipolar, 0.)
+    }
+}
+
+// Tunnel parameters.
+pub const THICKNESS: ParamSpec = spec("thickness", "Thickness", ParamKind::Unipolar, 0.1);
+pub const SIZE: ParamSpec = spec("size", "Size", ParamKind::Unipolar, 0.5);
+pub const ASPECT_RATIO: ParamSpec = spec("aspect_ratio", "Aspect ratio", ParamKind::Unipolar, 0.5);
+pub const COLOR_CENTER: ParamSpec = spec("color_center", "Color center", ParamKind::Unipolar, 0.);
+pub const COLOR_WIDTH: ParamSpec = spec("color_width", "Color width", ParamKind::Unipolar, 0.);
+pub const COLOR_SPREAD: ParamSpec = spec("color_spread", "Color spread", ParamKind::Unipolar, 0.);
+pub const COLOR_SATURATION: ParamSpec =
+    spec("color_saturation", "Saturation", ParamKind::Unipolar, 0.);
+pub const USE_PALETTE: ParamSpec = toggle("palette", "Use palette");
+pub const SEGMENTS: ParamSpec = spec(
+    "segments",
+    "Segments",
+    ParamKind::Integer { min: 1, max: 128 },
+    126.,
+);
+pub const BLACKING: ParamSpec = spec("blacking", "Blacking", ParamKind::Bipolar, 0.15);
+pub const ROTATION_SPEED: ParamSpec = speed("rotation_speed", "Rotation speed");
+pub const MARQUEE_SPEED: ParamSpec = speed("marquee_speed", "Marquee speed");
+pub const POSITION_X: ParamSpec = spec("position_x", "Position X", ParamKind::Bipolar, 0.);
+pub const POSITION_Y: ParamSpec = spec("position_y", "Position Y", ParamKind::Bipolar, 0.);
+pub const COLOR_FLIP_SOURCES: &[&str] = &["Off", "Clock 0", "Clock 1", "Clock 2", "Clock 3"];
+pub const COLOR_FLIP_CLOCK: ParamSpec = spec(
+    "color_flip_clock",
+    "Color flip clock",
+    ParamKind::Choice(COLOR_FLIP_SOURCES),
+    0.,
+);
+pub const COLOR_FLIP_PROBABILITY: ParamSpec = spec(
+    "color_flip_probability",
+    "Color flip probability",
+    ParamKind::Unipolar,
+    1.,
+);
+pub const WOBBLE_AMOUNT: ParamSpec = spec("wobble_amount", "Wobble", ParamKind::Unipolar, 0.);
+pub const WOBBLE_FREQUENCY: ParamSpec = spec(
+    "wobble_frequency",
+    "Wobble frequency",
+    ParamKind::Unipolar,
+    0.25,
+);
+pub const WOBBLE_SOURCES: &[&str] = &["Free", "Clock 0", "Clock 1", "Clock 2", "Clock 3"];
+pub const WOBBLE_CLOCK: ParamSpec = spec(
+    "wobble_clock",
+    "Wobble clock",
+    ParamKind::Choice(WOBBLE_SOURCES),
+    0.,
+);
+pub const SHATTER_DURATION: ParamSpec = ParamSpec {
+    unit: Some(("s", shatter::MAX_DURATION)),
+    ..spec(
+        "shatter_duration",
+        "Shatter duration",
+        ParamKind::Unipolar,
+        0.25,
+    )
+};
+
+pub const TUNNEL: &[ParamSpec] = &[
+    THICKNESS,
+    SIZE,
+    ASPECT_RATIO,
+    COLOR_CENTER,
+    COLOR_WIDTH,
+    COLOR_SPREAD,
+    COLOR_SATURATION,
+    USE_PALETTE,
+    SEGMENTS,
+    BLACKING,
+    ROTATION_SPEED,
+    MARQUEE_SPEED,
+    POSITION_X,
+    POSITION_Y,
+    COLOR_FLIP_CLOCK,
+    COLOR_FLIP_PROBABILITY,
+    WOBBLE_AMOUNT,
+    WOBBLE_FREQUENCY,
+    WOBBLE_CLOCK,
+    SHATTER_DURATION,
+    button("shatter", "Shatter"),
+    button("nudge_left", "Nudge left"),
+    button("nudge_right", "Nudge right"),
+    button("nudge_up", "Nudge up"),
+    button("nudge_down", "Nudge down"),
+    button("reset_position", "Reset position"),
+    button("reset_rotation", "Reset rotation"),
+    button("reset_marquee", "Reset marquee"),
+    button("zero_rotation", "Zero rotation angle"),
+    button("zero_marquee", "Zero marquee angle"),
+    button("zero_rotation_on_beat", "Zero rotation angle on beat"),
+    button("zero_marquee_on_beat", "Zero marquee angle on beat"),
+];
+
+// Animation parameters.
+pub const WAVEFORMS: &[&str] = Waveform::NAMES;
+pub const TARGETS: &[&str] = Target::NAMES;
+
+pub const CLOCK_SOURCES: &[&str] = &["Internal", "Clock 0", "Clock 1", "Clock 2", "Clock 3"];
+
+pub const AUDIO_SOURCES: &[&str] = &["Waveform", "Envelope", "Beat", "Low", "Mid", "High"];
+
+pub const ANIMATION_SELECT: ParamSpec = spec(
+    "select",
+    "Animation",
+    ParamKind::Integer {
+        min: 0,
+        max: N_ANIM as i64 - 1,
+    },
+    0.,
+);
+
+pub const ANIMATION_PROBABILITY: ParamSpec =
+    spec("probability", "Probability", ParamKind::Unipolar, 1.);
+
+pub const ANIMATION_SPEED: ParamSpec = ParamSpec {
+    unit: Some(("Hz", -ControllableClock::RATE_SCALE)),
+    ..spec("speed", "Speed", ParamKind::Bipolar, 0.)
+};
+pub const ANIMATION_WEIGHT: ParamSpec = spec("weight", "Weight", ParamKind::Unipolar, 0.);
+pub const ANIMATION_DUTY_CYCLE: ParamSpec =
+    spec("duty_cycle", "Duty cycle", ParamKind::Unipolar, 1.);
+pub const ANIMATION_SMOOTHING: ParamSpec =
+    spec("smoothing", "Smoothing", ParamKind::Unipolar, 0.25);
+pub const ANIMATION_N_PERIODS: ParamSpec = spec(
+    "n_periods",
+    "Periods",
+    ParamKind::Integer { min: 0, max: 14 },
+    0.,
+);
+pub const ANIMATION_CLOCK_SOURCE: ParamSpec = spec(
+    "clock_source",
+    "Clock",
+    ParamKind::Choice(CLOCK_SOURCES),
+    0.,
+);
+pub const ANIMATION_AUDIO_SOURCE: ParamSpec = spec(
+    "audio_source",
+    "Audio",
+    ParamKind::Choice(AUDIO_SOURCES),
+    0.,
+);
+
+pub const ANIMATION: &[ParamSpec] = &[
+    ANIMATION_SELECT,
+    spec("waveform", "Waveform", ParamKind::Choice(WAVEFORMS), 0.),
+    spec("target", "Target", ParamKind::Choice(TARGETS), 2.),
+    ANIMATION_SPEED,
+    ANIMATION_WEIGHT,
+    ANIMATION_DUTY_CYCLE,
+    ANIMATION_SMOOTHING,
+    ANIMATION_N_PERIODS,
+    toggle("pulse", "Pulse"),
+    toggle("invert", "Invert"),
+    ANIMATION_CLOCK_SOURCE,
+    ANIMATION_AUDIO_SOURCE,
+    ANIMATION_PROBABILITY,
+    button("copy", "Copy"),
+    button("paste", "Paste"),
+];
+
+// Mixer parameters.
+pub const MIXER: &[ParamSpec] = &[
+    spec("grand_master", "Grand master", ParamKind::Unipolar, 1.),
+    toggle("blackout", "Blackout"),
+    toggle("clone_channel", "Clone channel"),
+];
+
+pub const MIXER_CHANNEL: &[ParamSpec] = &[
+    button("select", "Select"),
+    spec("level", "Level", ParamKind::Unipolar, 0.),
+    spec("trim", "Trim", ParamKind::Unipolar, 1.),
+    button("bump", "Bump"),
+    toggle("mask", "Mask"),
+    toggle("additive", "Additive"),
+    spec(
+        "draw_order",
+        "Draw order",
+        ParamKind::Integer {
+            min: 0,
+            max: Channel::MAX_DRAW_ORDER as i64,
+        },
+        0.,
+    ),
+    button("bring_to_front", "Bring to front"),
+];
+
+/// One of these per video channel, in each mixer channel.
+pub const VIDEO_CHANNEL: ParamSpec = toggle("video_channel", "Video");
+
+// Clock parameters.
+pub const CLOCK: &[ParamSpec] = &[
+    ParamSpec {
+        unit: Some(("Hz", -ControllableClock::RATE_SCALE)),
+        ..spec("rate", "Rate", ParamKind::Bipolar, 0.)
+    },
+    spec("level", "Level", ParamKind::Unipolar, 1.),
+    button("tap", "Tap"),
+    toggle("one_shot", "One shot"),
+    toggle("retrigger", "Retrigger"),
+    toggle("midi_sync", "Midi sync"),
+];
+
+pub const ANIMATION_PRESET_SAVE: ParamSpec = toggle("save", "Save preset");
+
+/// One of these per animation preset.
+pub const ANIMATION_PRESET: ParamSpec = button("preset", "Preset");
+
+/// One of these per beam type, named after the type.
+pub const BEAM_TYPE: ParamSpec = button("type", "Beam type");
+
+// Beam store parameters.
+pub const BEAM_STORE_MODES: &[ParamSpec] = &[
+    toggle("beam_save", "Save beam"),
+    toggle("look_save", "Save look"),
+    toggle("delete", "Delete"),
+    toggle("look_edit", "Edit look"),
+];
+
+/// Recall random beams from the current page of the beam store.
+pub const BEAM_STORE_SHUFFLE: &[ParamSpec] = &[
+    button("shuffle_channel", "Shuffle channel"),
+    button("shuffle_all", "Shuffle all"),
+    button("shuffle_channel_on_bar", "Shuffle channel on bar"),
+    button("shuffle_all_on_bar", "Shuffle all on bar"),
+];
+
+/// Keep these parameter classes of the current tunnel through recalls, in
+/// the order of recall_filter::ParamClass::ALL.
+pub const BEAM_STORE_RECALL_FILTER: &[ParamSpec] = &[
+    toggle("keep_geometry", "Keep geometry"),
+    toggle("keep_color", "Keep color"),
+    toggle("keep_position", "Keep position"),
+    toggle("keep_motion", "Keep motion"),
+    toggle("keep_animations", "Keep animations"),
+];
+
+/// One of these per beam store slot.
+pub const BEAM_STORE_SLOT: ParamSpec = button("slot", "Slot");
+
+// Video output parameters.
+/// One of these per video channel; zero selects no preset.
+pub const GEOMETRY_PRESET: ParamSpec = spec(
+    "geometry",
+    "Geometry preset",
+    ParamKind::Integer {
+        min: 0,
+        max: GeometryPreset::MAX_COUNT as i64,
+    },
+    0.,
+);
+/// The active output profile, by index.
+pub const OUTPUT_PROFILE: ParamSpec = spec(
+    "output_profile",
+    "Output profile",
+    ParamKind::Integer {
+        min: 0,
+        max: OutputProfileConfig::MAX_COUNT as i64 - 1,
+    },
+    0.,
+);
+
+// Palette parameters.
+/// One of these per palette deck; zero empties the deck.
+pub const PALETTE_SELECT: ParamSpec = spec(
+    "deck",
+    "Palette",
+    ParamKind::Integer {
+        min: 0,
+        max: PaletteConfig::MAX_COUNT as i64,
+    },
+    0.,
+);
+pub const PALETTE_CROSSFADE: ParamSpec = spec("crossfade", "Crossfade", ParamKind::Unipolar, 0.);
+pub const PALETTE: &[ParamSpec] = &[PALETTE_CROSSFADE];
+
+// Audio input parameters.
+pub const AUDIO: &[ParamSpec] = &[spec("level", "Input level", ParamKind::Unipolar, 0.)];
+
+// Color organ parameters.
+pub const COLOR_ORGAN_ATTACK: ParamSpec = ParamSpec {
+    unit: Some(("s", 0.5)),
+    ..spec("attack", "Attack", ParamKind::Unipolar, 0.1)
+};
+pub const COLOR_ORGAN_RELEASE: ParamSpec = ParamSpec {
+    unit: Some(("s", 2.)),
+    ..spec("release", "Release", ParamKind::Unipolar, 0.15)
+};
+pub const COLOR_ORGAN_GAIN: ParamSpec = ParamSpec {
+    unit: Some(("dB", 36.)),
+    ..spec("gain", "Gain", ParamKind::Unipolar, 0.5)
+};
+pub const COLOR_ORGAN: &[ParamSpec] = &[
+    toggle("enabled", "Enabled"),
+    COLOR_ORGAN_ATTACK,
+    COLOR_ORGAN_RELEASE,
+    COLOR_ORGAN_GAIN,
+];
+
+/// One of these per mixer channel.
+pub const COLOR_ORGAN_BANDS: &[&str] = &["Off", "Low", "Mid", "High"];
+pub const COLOR_ORGAN_BAND: ParamSpec =
+    spec("band", "Band", ParamKind::Choice(COLOR_ORGAN_BANDS), 0.);
+
+// Autopilot parameters.
+pub const AUTOPILOT: &[ParamSpec] = &[toggle("enabled", "Enabled")];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::AudioSource;
+    use crate::clock_bank::N_CLOCKS;
+    use crate::tunnel::{StateChange, Tunnel};
+    use crate::{master_ui::EmitStateChange, show::StateChange as ShowStateChange};
+
+    struct Capture(Vec<StateChange>);
+
+    impl EmitStateChange for Capture {
+        fn emit(&mut self, sc: ShowStateChange) {
+            if let ShowStateChange::Tunnel(sc) = sc {
+                self.0.push(sc);
+            }
+        }
+    }
+
+    /// A new tunnel should come up with the registered defaults.
+    #[test]
+    fn test_tunnel_defaults() {
+        let mut capture = Capture(Vec::new());
+        Tunnel::new().emit_state(&mut capture);
+        for sc in capture.0 {
+            use StateChange::*;
+            let (spec, val) = match sc {
+                Thickness(v) => (THICKNESS, v.val()),
+                Size(v) => (SIZE, v.val()),
+                AspectRatio(v) => (ASPECT_RATIO, v.val()),
+                Segments(v) => (SEGMENTS, v as f64),
+                Blacking(v) => (BLACKING, v.val()),
+                RotationSpeed(v) => (ROTATION_SPEED, v.val()),
+                PositionX(v) => (POSITION_X, v),
+                ColorFlipProbability(v) => (COLOR_FLIP_PROBABILITY, v.val()),
+                WobbleAmount(v) => (WOBBLE_AMOUNT, v.val()),
+                WobbleFrequency(v) => (WOBBLE_FREQUENCY, v.val()),
+                ShatterDuration(v) => (SHATTER_DURATION, v.val()),
+                _ => continue,
+            };
+            assert_eq!(spec.default, val, "{}", spec.name);
+        }
+    }
+
+    #[test]
+    fn test_clock_sources() {
+        assert_eq!(N_CLOCKS + 1, CLOCK_SOURCES.len());
+        assert_eq!(N_CLOCKS + 1, COLOR_FLIP_SOURCES.len());
+        assert_eq!(N_CLOCKS + 1, WOBBLE_SOURCES.len());
+        assert_eq!(AudioSource::ALL.len() + 1, AUDIO_SOURCES.len());
+    }
+
+    #[test]
+    fn test_animation_choices() {
+        assert_eq!(
+            format!("{:?}", Target::PositionY),
+            TARGETS[Target::PositionY as usize]
+        );
+        assert_eq!(
+            format!("{:?}", Waveform::Sawtooth),
+            WAVEFORMS[Waveform::Sawtooth as usize]
+        );
+    }
+
+    #[test]
+    fn test_integer_midi() {
+        assert_eq!(1, SEGMENTS.integer_from_midi(0));
+        assert_eq!(128, SEGMENTS.integer_from_midi(127));
+        assert_eq!(125, SEGMENTS.integer_to_midi(126));
+    }
+}
@@ -0,0 +1,764 @@
+//! Driving LED fixtures from the same composition as the projectors.
+//!
+//! Each fixture is a strip or ring of RGB pixels laid over the image of a
+//! video channel.  Every frame, the color under each pixel is sampled from
+//! the rendered layers, corrected for the fixture, and sent as DMX.
+//!
+//! Correction is applied per fixture, in this order:
+//!
+//! - Gamma converts sampled colors into LED drive levels.  LEDs respond
+//!   linearly to their drive level, so a gamma around 2.2 makes fades look
+//!   like they do on a projector.
+//! - White balance scales the red, green, and blue drive levels, to match the
+//!   fixture's white to the rest of the rig.
+//! - Power limiting scales the whole fixture down whenever its total drive
+//!   would exceed a fraction of its draw at full white, for installs with a
+//!   limited power budget.
+//!
+//! Pixels are packed three addresses at a time from the fixture's first
+//! address.  A pixel that doesn't fit in the rest of a universe starts the
+//! next universe.
+//!
+//! Beam fixtures, such as moving heads and washes, show a single color.  Each
+//! follows the layer of one mixer channel, downsampled to the brightest level
+//! drawn, the average color drawn, and the average position drawn at.  Their
+//! DMX channels are patched from a list of the parameters they carry.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    f64::consts::PI,
+};
+use tracing::error;
+use tunnels_lib::{color::hsv_to_rgb, sample::Sampler, ArcSegment, BlendMode, LayerCollection};
+
+use crate::{
+    dmx::{DmxProtocol, UNIVERSE_SIZE},
+    dmx_output::{DmxOutput, DmxOutputConfig},
+    mixer::Mixer,
+};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PixelMapConfig {
+    #[serde(default)]
+    pub output: DmxOutputConfig,
+    #[serde(default)]
+    pub fixtures: Vec<LedFixture>,
+    /// Single color fixtures driven by a mixer channel.
+    #[serde(default)]
+    pub beams: Vec<BeamFixture>,
+}
+
+impl PixelMapConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.output.validate()?;
+        let mut used = HashSet::new();
+        for (i, fixture) in self.fixtures.iter().enumerate() {
+            if let Err(e) = fixture.validate(self.output.protocol) {
+                bail!("LED fixture {}: {}", i, e);
+            }
+            for (universe, address) in fixture.addresses() {
+                if !used.insert((universe, address)) {
+                    bail!(
+                        "LED fixture {} overlaps another fixture at universe {} address {}.",
+                        i,
+                        universe,
+                        address + 1
+                    );
+                }
+            }
+        }
+        for (i, beam) in self.beams.iter().enumerate() {
+            if let Err(e) = beam.validate(self.output.protocol) {
+                bail!("Beam fixture {}: {}", i, e);
+            }
+            for (universe, address) in beam.addresses() {
+                if !used.insert((universe, address)) {
+                    bail!(
+                        "Beam fixture {} overlaps another fixture at universe {} address {}.",
+                        i,
+                        universe,
+                        address + 1
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every beam fixture follows a mixer channel that exists.
+    pub fn validate_channels(&self, n_channels: usize) -> Result<(), Box<dyn Error>> {
+        for (i, beam) in self.beams.iter().enumerate() {
+            if beam.mixer_channel >= n_channels {
+                bail!(
+                    "Beam fixture {} mixer channel {} is out of range; there are {} mixer channels.",
+                    i,
+                    beam.mixer_channel,
+                    n_channels
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return the universes the protocol can address.
+fn universe_range(protocol: DmxProtocol) -> std::ops::RangeInclusive<u16> {
+    match protocol {
+        DmxProtocol::Sacn => 1..=63999,
+        DmxProtocol::ArtNet => 0..=0x7fff,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LedFixture {
+    /// Video channel whose image the fixture shows.
+    pub video_channel: usize,
+    pub layout: PixelLayout,
+    /// Universe of the first pixel.
+    pub universe: u16,
+    /// DMX address of the first pixel, starting at 1.
+    #[serde(default = "default_address")]
+    pub address: u16,
+    /// Width of the video channel's image relative to its height.
+    #[serde(default = "default_aspect_ratio")]
+    pub aspect_ratio: f64,
+    /// Composite layers as a client with alpha blending enabled does.
+    #[serde(default = "default_alpha_blend")]
+    pub alpha_blend: bool,
+    #[serde(default = "default_gamma")]
+    pub gamma: f64,
+    /// Gains applied to the red, green, and blue drive levels.
+    #[serde(default = "default_white_balance")]
+    pub white_balance: [f64; 3],
+    /// Largest fraction of the fixture's draw at full white to allow.
+    #[serde(default)]
+    pub max_power: Option<f64>,
+}
+
+fn default_address() -> u16 {
+    1
+}
+
+fn default_aspect_ratio() -> f64 {
+    16.0 / 9.0
+}
+
+fn default_alpha_blend() -> bool {
+    true
+}
+
+fn default_gamma() -> f64 {
+    1.0
+}
+
+fn default_white_balance() -> [f64; 3] {
+    [1.0; 3]
+}
+
+/// Positions of a fixture's pixels, as fractions of the window size with the
+/// origin at the top left.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub enum PixelLayout {
+    /// Pixels evenly spaced from start to end, inclusive.
+    Line {
+        start: [f64; 2],
+        end: [f64; 2],
+        pixels: usize,
+    },
+    /// Pixels evenly spaced clockwise around an ellipse, starting from the
+    /// right.
+    Ring {
+        center: [f64; 2],
+        radius: [f64; 2],
+        pixels: usize,
+    },
+}
+
+impl PixelLayout {
+    fn pixel_count(&self) -> usize {
+        match self {
+            Self::Line { pixels, .. } | Self::Ring { pixels, .. } => *pixels,
+        }
+    }
+
+    fn points(&self) -> Vec<[f64; 2]> {
+        match self {
+            Self::Line { start, end, pixels } => {
+                let steps = (*pixels as f64 - 1.0).max(1.0);
+                (0..*pixels)
+                    .map(|i| {
+                        let t = i as f64 / steps;
+                        [
+                            start[0] + (end[0] - start[0]) * t,
+                            start[1] + (end[1] - start[1]) * t,
+                        ]
+                    })
+                    .collect()
+            }
+            Self::Ring {
+                center,
+                radius,
+                pixels,
+            } => (0..*pixels)
+                .map(|i| {
+                    let angle = 2.0 * PI * i as f64 / *pixels as f64;
+                    [
+                        center[0] + radius[0] * angle.cos(),
+                        center[1] + radius[1] * angle.sin(),
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
+
+impl LedFixture {
+    fn validate(&self, protocol: DmxProtocol) -> Result<(), Box<dyn Error>> {
+        if self.video_channel >= Mixer::N_VIDEO_CHANNELS {
+            bail!(
+                "Video channel {} is out of range; there are {} video channels.",
+                self.video_channel,
+                Mixer::N_VIDEO_CHANNELS
+            );
+        }
+        if self.layout.pixel_count() == 0 {
+            bail!("The fixture has no pixels.");
+        }
+        if !(1..=UNIVERSE_SIZE).contains(&self.address) {
+            bail!("DMX address {} is out of range.", self.address);
+        }
+        let last_universe = self.addresses().last().map(|(u, _)| *u).unwrap_or(0);
+        let universes = universe_range(protocol);
+        if !universes.contains(&self.universe) || !universes.contains(&last_universe) {
+            bail!(
+                "Universes {} to {} are not all valid {:?} universes.",
+                self.universe,
+                last_universe,
+                protocol
+            );
+        }
+        if !self.aspect_ratio.is_finite() || self.aspect_ratio <= 0.0 {
+            bail!(
+                "Aspect ratio is {}; it must be positive.",
+                self.aspect_ratio
+            );
+        }
+        if !self.gamma.is_finite() || self.gamma <= 0.0 {
+            bail!("Gamma is {}; it must be positive.", self.gamma);
+        }
+        if !self.white_balance.iter().all(|g| (0.0..=1.0).contains(g)) {
+            bail!(
+                "White balance is {:?}; each gain must be between 0 and 1.",
+                self.white_balance
+            );
+        }
+        if let Some(max_power) = self.max_power {
+            if !(max_power > 0.0 && max_power <= 1.0) {
+                bail!(
+                    "Max power is {}; it must be greater than 0 and at most 1.",
+                    max_power
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the universe and zero-indexed address of each component of
+    /// each pixel, in order.
+    fn addresses(&self) -> Vec<(u16, usize)> {
+        let mut universe = self.universe;
+        let mut address = self.address as usize - 1;
+        let mut addresses = Vec::with_capacity(3 * self.layout.pixel_count());
+        for _ in 0..self.layout.pixel_count() {
+            if address + 3 > UNIVERSE_SIZE as usize {
+                universe = universe.saturating_add(1);
+                address = 0;
+            }
+            for component in 0..3 {
+                addresses.push((universe, address + component));
+            }
+            address += 3;
+        }
+        addresses
+    }
+
+    /// Apply the fixture's correction to sampled colors.
+    fn correct(&self, colors: &mut [[f64; 3]]) {
+        for color in colors.iter_mut() {
+            for (c, gain) in color.iter_mut().zip(self.white_balance.iter()) {
+                *c = c.clamp(0.0, 1.0).powf(self.gamma) * gain;
+            }
+        }
+        let max_power = match self.max_power {
+            Some(max_power) => max_power,
+            None => return,
+        };
+        let full = 3.0 * colors.len() as f64;
+        let power = colors.iter().flatten().sum::<f64>() / full;
+        if power > max_power {
+            let scale = max_power / power;
+            colors.iter_mut().flatten().for_each(|c| *c *= scale);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BeamFixture {
+    /// Mixer channel whose layer the fixture follows.
+    pub mixer_channel: usize,
+    pub universe: u16,
+    /// DMX address of the fixture, starting at 1.
+    #[serde(default = "default_address")]
+    pub address: u16,
+    /// Parameter carried by each of the fixture's DMX channels, in order.
+    pub channels: Vec<BeamParameter>,
+    /// Applied to intensity, or to color if the fixture has no intensity
+    /// channel.
+    #[serde(default = "default_gamma")]
+    pub gamma: f64,
+    /// Gains applied to the red, green, and blue drive levels.
+    #[serde(default = "default_white_balance")]
+    pub white_balance: [f64; 3],
+}
+
+/// What a DMX channel of a beam fixture carries.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum BeamParameter {
+    Intensity,
+    Red,
+    Green,
+    Blue,
+    /// Horizontal position drawn at, from the left edge to the right edge.
+    Pan,
+    /// Vertical position drawn at, from the top edge to the bottom edge.
+    Tilt,
+    /// A constant level, such as to open a shutter or select a mode.
+    Fixed(u8),
+}
+
+/// The single look a beam fixture takes from a layer.
+#[derive(Debug, Clone, PartialEq)]
+struct Downsampled {
+    /// Brightest level drawn.
+    intensity: f64,
+    /// Average color drawn, at full brightness.
+    color: [f64; 3],
+    /// Average position drawn at, as fractions of the window size, or None
+    /// if nothing was drawn.
+    position: Option<[f64; 2]>,
+}
+
+/// Downsample the arcs of a layer into a single look.
+/// Arcs contribute to color and position in proportion to their brightness.
+fn downsample(arcs: &[ArcSegment]) -> Downsampled {
+    let mut intensity: f64 = 0.0;
+    let mut total = 0.0;
+    let mut color = [0.0; 3];
+    let mut position = [0.0; 2];
+    for arc in arcs {
+        let weight = arc.level * arc.val;
+        if weight <= 0.0 {
+            continue;
+        }
+        intensity = intensity.max(weight);
+        total += weight;
+        let rgb = hsv_to_rgb(arc.hue, arc.sat, 1.0);
+        for (c, v) in color.iter_mut().zip(rgb.iter()) {
+            *c += v * weight;
+        }
+        position[0] += (arc.x + 0.5) * weight;
+        position[1] += (arc.y + 0.5) * weight;
+    }
+    if total == 0.0 {
+        return Downsampled {
+            intensity: 0.0,
+            color: [0.0; 3],
+            position: None,
+        };
+    }
+    color.iter_mut().for_each(|c| *c /= total);
+    // Averaging hues desaturates, but shouldn't darken.
+    let brightest = color.iter().copied().fold(0.0, f64::max);
+    if brightest > 0.0 {
+        color.iter_mut().for_each(|c| *c /= brightest);
+    }
+    Downsampled {
+        intensity: intensity.min(1.0),
+        color,
+        position: Some([
+            (position[0] / total).clamp(0.0, 1.0),
+            (position[1] / total).clamp(0.0, 1.0),
+        ]),
+    }
+}
+
+/// Return how brightly the fixtures are lit, given the colors sampled for
+/// each LED fixture and the look of each beam fixture, from dark at 0 to
+/// every fixture at full at 1.  Each fixture counts the same, however many
+/// pixels it has.
+fn brightness(colors: &[Option<Vec<[f64; 3]>>], looks: &[Option<Downsampled>]) -> f64 {
+    let leds = colors.iter().flatten().map(|pixels| {
+        let lit: f64 = pixels
+            .iter()
+            .map(|c| c.iter().copied().fold(0.0, f64::max))
+            .sum();
+        lit / pixels.len().max(1) as f64
+    });
+    let beams = looks.iter().flatten().map(|look| look.intensity);
+    let levels: Vec<f64> = leds.chain(beams).collect();
+    if levels.is_empty() {
+        return 0.0;
+    }
+    levels.iter().sum::<f64>() / levels.len() as f64
+}
+
+impl BeamFixture {
+    fn validate(&self, protocol: DmxProtocol) -> Result<(), Box<dyn Error>> {
+        if self.channels.is_empty() {
+            bail!("The fixture has no channels.");
+        }
+        let last = self.address as usize + self.channels.len() - 1;
+        if self.address < 1 || last > UNIVERSE_SIZE as usize {
+            bail!(
+                "DMX addresses {} to {} are out of range.",
+                self.address,
+                last
+            );
+        }
+        if !universe_range(protocol).contains(&self.universe) {
+            bail!(
+                "Universe {} is not a valid {:?} universe.",
+                self.universe,
+                protocol
+            );
+        }
+        if !self.gamma.is_finite() || self.gamma <= 0.0 {
+            bail!("Gamma is {}; it must be positive.", self.gamma);
+        }
+        if !self.white_balance.iter().all(|g| (0.0..=1.0).contains(g)) {
+            bail!(
+                "White balance is {:?}; each gain must be between 0 and 1.",
+                self.white_balance
+            );
+        }
+        Ok(())
+    }
+
+    /// Return the universe and zero-indexed address of each channel.
+    /// A beam fixture never spans universes.
+    fn addresses(&self) -> Vec<(u16, usize)> {
+        (0..self.channels.len())
+            .map(|i| (self.universe, self.address as usize - 1 + i))
+            .collect()
+    }
+
+    /// Return the level of each channel, given the look of the layer and the
+    /// last position the layer was drawn at.
+    fn levels(&self, look: &Downsampled, position: [f64; 2]) -> Vec<f64> {
+        let dimmer = self.channels.contains(&BeamParameter::Intensity);
+        let intensity = look.intensity.powf(self.gamma);
+        let mut color = look.color;
+        for (c, gain) in color.iter_mut().zip(self.white_balance.iter()) {
+            // Without a dimmer, the color channels dim the fixture.
+            *c = if dimmer {
+                *c * gain
+            } else {
+                (*c * look.intensity).powf(self.gamma) * gain
+            };
+        }
+        self.channels
+            .iter()
+            .map(|parameter| match parameter {
+                BeamParameter::Intensity => intensity,
+                BeamParameter::Red => color[0],
+                BeamParameter::Green => color[1],
+                BeamParameter::Blue => color[2],
+                BeamParameter::Pan => position[0],
+                BeamParameter::Tilt => position[1],
+                BeamParameter::Fixed(level) => *level as f64 / 255.0,
+            })
+            .collect()
+    }
+}
+
+/// A beam fixture ready to render.
+struct Beam {
+    addresses: Vec<(u16, usize)>,
+    /// Where the layer was last drawn, held while it is dark so that moving
+    /// heads don't swing home between looks.
+    position: [f64; 2],
+    config: BeamFixture,
+}
+
+/// A fixture ready to render.
+struct Fixture {
+    video_channel: usize,
+    sampler: Sampler,
+    points: Vec<[f64; 2]>,
+    addresses: Vec<(u16, usize)>,
+    config: LedFixture,
+}
+
+/// Renders every fixture into DMX and sends it.
+pub struct PixelMap {
+    fixtures: Vec<Fixture>,
+    beams: Vec<Beam>,
+    universes: BTreeMap<u16, Vec<u8>>,
+    output: DmxOutput,
+}
+
+impl PixelMap {
+    pub fn new(config: &PixelMapConfig) -> Result<Self, Box<dyn Error>> {
+        let fixtures: Vec<Fixture> = config
+            .fixtures
+            .iter()
+            .map(|fixture| Fixture {
+                video_channel: fixture.video_channel,
+                sampler: Sampler::new(fixture.aspect_ratio, fixture.alpha_blend),
+                points: fixture.layout.points(),
+                addresses: fixture.addresses(),
+                config: fixture.clone(),
+            })
+            .collect();
+        let beams: Vec<Beam> = config
+            .beams
+            .iter()
+            .map(|beam| Beam {
+                addresses: beam.addresses(),
+                position: [0.5; 2],
+                config: beam.clone(),
+            })
+            .collect();
+        let universes: BTreeMap<u16, Vec<u8>> = fixtures
+            .iter()
+            .flat_map(|fixture| fixture.addresses.iter())
+            .chain(beams.iter().flat_map(|beam| beam.addresses.iter()))
+            .map(|(universe, _)| (*universe, vec![0; UNIVERSE_SIZE as usize]))
+            .collect();
+        let first_universe = universes.keys().next().copied().unwrap_or(1);
+        Ok(Self {
+            fixtures,
+            beams,
+            universes,
+            output: DmxOutput::new(&config.output, first_universe)?,
+        })
+    }
+
+    /// Render the fixtures from the layers of every video channel, blended
+    /// as they are on the projectors, and the layer of every mixer channel,
+    /// and send the universes they are patched into as a single frame.
+    /// The light of every fixture is scaled by what limit returns, given how
+    /// brightly the fixtures are lit, to keep them within the flash limit.
+    pub fn render(
+        &mut self,
+        video_outs: &[LayerCollection],
+        blend: &[Vec<BlendMode>],
+        channels: &LayerCollection,
+        limit: impl FnOnce(f64) -> f64,
+    ) {
+        let mut colors: Vec<Option<Vec<[f64; 3]>>> = self
+            .fixtures
+            .iter()
+            .map(|fixture| {
+                let layers = video_outs.get(fixture.video_channel)?;
+                let blend = blend.get(fixture.video_channel).map_or(&[][..], |b| &b[..]);
+                Some(fixture.sampler.sample_all(layers, blend, &fixture.points))
+            })
+            .collect();
+        let mut looks: Vec<Option<Downsampled>> = self
+            .beams
+            .iter()
+            .map(|beam| {
+                channels
+                    .get(beam.config.mixer_channel)
+                    .map(|arcs| downsample(arcs))
+            })
+            .collect();
+        let scale = limit(brightness(&colors, &looks));
+        if scale < 1.0 {
+            colors
+                .iter_mut()
+                .flatten()
+                .flatten()
+                .flatten()
+                .for_each(|c| *c *= scale);
+            looks
+                .iter_mut()
+                .flatten()
+                .for_each(|look| look.intensity *= scale);
+        }
+        for (fixture, colors) in self.fixtures.iter().zip(colors) {
+            let mut colors = match colors {
+                Some(colors) => colors,
+                None => continue,
+            };
+            fixture.config.correct(&mut colors);
+            let levels = colors.iter().flatten();
+            for ((universe, address), level) in fixture.addresses.iter().zip(levels) {
+                if let Some(data) = self.universes.get_mut(universe) {
+                    data[*address] = (level * 255.0).round() as u8;
+                }
+            }
+        }
+        for (beam, look) in self.beams.iter_mut().zip(looks) {
+            let look = match look {
+                Some(look) => look,
+                None => continue,
+            };
+            if let Some(position) = look.position {
+                beam.position = position;
+            }
+            let levels = beam.config.levels(&look, beam.position);
+            for ((universe, address), level) in beam.addresses.iter().zip(levels) {
+                if let Some(data) = self.universes.get_mut(universe) {
+                    data[*address] = (level.clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+            }
+        }
+        for (universe, data) in &self.universes {
+            if let Err(e) = self.output.send(*universe, data) {
+                error!("Unable to send DMX universe {}: {}.", universe, e);
+            }
+        }
+        if let Err(e) = self.output.sync() {
+            error!("Unable to send DMX sync: {}.", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tunnels_lib::assert_almost_eq;
+
+    fn fixture(pixels: usize) -> LedFixture {
+        LedFixture {
+            video_channel: 0,
+            layout: PixelLayout::Line {
+                start: [0.0, 0.5],
+                end: [1.0, 0.5],
+                pixels,
+            },
+            universe: 1,
+            address: 508,
+            aspect_ratio: default_aspect_ratio(),
+            alpha_blend: true,
+            gamma: 2.0,
+            white_balance: [1.0, 1.0, 0.5],
+            max_power: Some(0.25),
+        }
+    }
+
+    #[test]
+    fn test_correct() {
+        let fixture = fixture(2);
+        let mut colors = [[0.5, 0.5, 0.5], [0.0, 0.0, 0.0]];
+        fixture.correct(&mut colors);
+        // Under the power limit, only gamma and white balance apply.
+        assert_eq!([[0.25, 0.25, 0.125], [0.0, 0.0, 0.0]], colors);
+
+        let mut colors = [[1.0, 1.0, 1.0], [0.0, 0.0, 0.0]];
+        fixture.correct(&mut colors);
+        // Drawing 2.5 of 6 is scaled down to 1.5.
+        for (expected, c) in [0.6, 0.6, 0.3].iter().zip(colors[0].iter()) {
+            assert_almost_eq(*expected, *c);
+        }
+    }
+
+    fn arc(level: f64, hue: f64, x: f64) -> ArcSegment {
+        ArcSegment {
+            level,
+            thickness: 0.1,
+            hue,
+            sat: 1.0,
+            val: 1.0,
+            x,
+            y: 0.0,
+            rad_x: 0.5,
+            rad_y: 0.5,
+            start: 0.0,
+            stop: 0.5,
+            rot_angle: 0.0,
+            palette: false,
+        }
+    }
+
+    #[test]
+    fn test_downsample() {
+        // Red at full and green at half average to orange, at full intensity.
+        let look = downsample(&[arc(1.0, 0.0, 0.0), arc(0.5, 1.0 / 3.0, 0.3)]);
+        assert_almost_eq(1.0, look.intensity);
+        for (expected, c) in [1.0, 0.5, 0.0].iter().zip(look.color.iter()) {
+            assert_almost_eq(*expected, *c);
+        }
+        let position = look.position.unwrap();
+        assert_almost_eq(0.6, position[0]);
+        assert_almost_eq(0.5, position[1]);
+
+        assert_eq!(None, downsample(&[arc(0.0, 0.0, 0.0)]).position);
+    }
+
+    #[test]
+    fn test_beam_levels() {
+        let mut beam = BeamFixture {
+            mixer_channel: 0,
+            universe: 1,
+            address: 1,
+            channels: vec![
+                BeamParameter::Pan,
+                BeamParameter::Intensity,
+                BeamParameter::Red,
+                BeamParameter::Blue,
+                BeamParameter::Fixed(255),
+            ],
+            gamma: 2.0,
+            white_balance: [1.0, 1.0, 0.5],
+        };
+        let look = Downsampled {
+            intensity: 0.5,
+            color: [1.0, 0.0, 1.0],
+            position: None,
+        };
+        // The dimmer takes the gamma, and color stays at full.
+        assert_eq!(
+            vec![0.25, 0.25, 1.0, 0.5, 1.0],
+            beam.levels(&look, [0.25, 0.75])
+        );
+
+        // Without a dimmer, color dims the fixture.
+        beam.channels.retain(|p| *p != BeamParameter::Intensity);
+        assert_eq!(
+            vec![0.25, 0.25, 0.125, 1.0],
+            beam.levels(&look, [0.25, 0.75])
+        );
+    }
+
+    #[test]
+    fn test_brightness() {
+        let colors = vec![Some(vec![[1.0, 0.0, 0.0], [0.0, 0.0, 0.0]]), None];
+        let looks = vec![Some(Downsampled {
+            intensity: 0.25,
+            color: [1.0; 3],
+            position: None,
+        })];
+        // Half of the strip at full, and the beam at a quarter.
+        assert_almost_eq(0.375, brightness(&colors, &looks));
+        assert_eq!(0.0, brightness(&[], &[]));
+    }
+
+    #[test]
+    fn test_addresses() {
+        // The second pixel doesn't fit in the first universe.
+        assert_eq!(
+            vec![(1, 507), (1, 508), (1, 509), (2, 0), (2, 1), (2, 2)],
+            fixture(2).addresses()
+        );
+    }
+}
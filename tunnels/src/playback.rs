@@ -0,0 +1,103 @@
+//! Replay a recorded snapshot archive as if it were a live show.
+//!
+//! Snapshots are republished on the normal render socket, with timestamps and
+//! frame numbers rewritten relative to the start of playback so that clients
+//! sync to the playback server exactly as they would to a live show.
+
+use simple_error::bail;
+use std::{
+    error::Error,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::info;
+use tunnels_lib::{
+    archive::{self, ArchiveFrame},
+    show_id, Timestamp,
+};
+
+use crate::{
+    send::{bind_publisher, send_snapshot},
+    timesync::TimesyncServer,
+};
+
+/// Pause inserted between the end of an archive and the start of the next loop.
+const LOOP_GAP: Duration = Duration::from_micros(16667);
+
+/// Play back the archive at path in the current thread.
+/// The path may also be a replay buffer directory.
+/// Speed scales the original timing; 2.0 plays back twice as fast.
+/// If looped, play the archive forever.
+/// Snapshots are published under the provided show ID, if any.
+pub fn run_playback(
+    path: &Path,
+    speed: f64,
+    looped: bool,
+    show_id: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    if speed.is_nan() || speed <= 0.0 {
+        bail!("Playback speed must be positive, got {}.", speed);
+    }
+    if let Some(id) = show_id {
+        show_id::validate(id)?;
+    }
+    let mut ctx = zmq::Context::new();
+    let start = Instant::now();
+
+    let _timesync = TimesyncServer::start(&mut ctx, start)?;
+    let socket = bind_publisher(&mut ctx)?;
+    let mut send_buf = Vec::new();
+
+    // Playback time at which the current pass through the archive started.
+    let mut pass_start = Duration::from_secs(0);
+    // Frame number of the first frame in the current pass.
+    let mut frame_offset = 0;
+
+    info!("Starting playback of {}.", path.display());
+    loop {
+        let mut first_frame = None;
+        let mut last_publish = pass_start;
+        let mut last_frame_number = frame_offset;
+
+        for frame in archive::open(path)? {
+            let ArchiveFrame {
+                video_channel,
+                mut snapshot,
+            } = frame?;
+            let (first_time, first_number) =
+                *first_frame.get_or_insert((snapshot.time, snapshot.frame_number));
+
+            let archive_elapsed = (snapshot.time - first_time).0.max(0) as f64 / 1_000_000.;
+            let publish_at = pass_start + Duration::from_secs_f64(archive_elapsed / speed);
+
+            if let Some(wait) = publish_at.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+
+            snapshot.time = Timestamp::from_duration(publish_at);
+            snapshot.frame_number =
+                frame_offset + snapshot.frame_number.saturating_sub(first_number);
+            send_snapshot(
+                &mut send_buf,
+                &socket,
+                show_id,
+                video_channel as usize,
+                &snapshot,
+            );
+
+            last_publish = publish_at;
+            last_frame_number = snapshot.frame_number;
+        }
+
+        if first_frame.is_none() {
+            bail!("Archive {} contains no frames.", path.display());
+        }
+        if !looped {
+            info!("Playback complete.");
+            return Ok(());
+        }
+        pass_start = last_publish + LOOP_GAP;
+        frame_offset = last_frame_number + 1;
+    }
+}
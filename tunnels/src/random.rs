@@ -0,0 +1,49 @@
+//! The show's source of randomness.
+//!
+//! Everything random that shapes the show, from animation skips and color
+//! flips to shatters and shuffles, is drawn from generators derived from one
+//! seed saved with the show.  Loading a show and performing the same actions
+//! plays out the same way every time, so a procedural look found in rehearsal
+//! can be brought back exactly.  Rerolling the seed gives the show a fresh set
+//! of choices.
+//!
+//! Mixer channels are updated in parallel, so each channel draws from its own
+//! stream of the seed, and what one channel draws never depends on another.
+//! The autopilot draws from a stream of its own, varied by the autopilot's
+//! seed, so that its choices can be changed without rerolling the show's.
+
+use rand::{rngs::StdRng, SeedableRng};
+
+pub type ShowRng = StdRng;
+
+/// Return the generator for one stream of a seed.
+pub fn stream(seed: u64, stream: u64) -> ShowRng {
+    variant(seed, stream, 0)
+}
+
+/// Return the generator for one variant of a stream of a seed.
+/// Variant 0 is the stream itself.
+pub fn variant(seed: u64, stream: u64, variant: u64) -> ShowRng {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    key[8..16].copy_from_slice(&stream.to_le_bytes());
+    key[16..24].copy_from_slice(&variant.to_le_bytes());
+    ShowRng::from_seed(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_stream() {
+        let draw = |seed, s| stream(seed, s).gen::<u64>();
+        assert_eq!(draw(1, 2), draw(1, 2));
+        assert_ne!(draw(1, 2), draw(2, 1));
+        assert_ne!(draw(1, 2), draw(1, 3));
+        let draw_variant = |v| variant(1, 2, v).gen::<u64>();
+        assert_eq!(draw(1, 2), draw_variant(0));
+        assert_ne!(draw(1, 2), draw_variant(1));
+    }
+}
@@ -0,0 +1,179 @@
+//! Lock classes of tunnel parameters against beam store recalls.
+//!
+//! With a class locked, recalling a tunnel from the beam store keeps the
+//! current channel's values for that class and takes everything else from
+//! the stored tunnel.  This lets a stored beam be blended with the current
+//! colors or placement, such as recalling its geometry but keeping the
+//! current colors, or recalling everything except position.  Looks and
+//! generator beams are always recalled whole.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{beam::Beam, master_ui::EmitStateChange as EmitShowStateChange};
+
+/// A class of tunnel parameters that can be locked.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ParamClass {
+    /// Size, thickness, aspect ratio, segments, blacking, wobble, and shatter
+    /// duration.
+    Geometry,
+    /// Color center, width, spread, saturation, palette use, and color flip.
+    Color,
+    /// Position, and rotation about the origin.
+    Position,
+    /// Rotation and marquee speeds and angles.
+    Motion,
+    /// Every animation.
+    Animations,
+}
+
+impl ParamClass {
+    pub const ALL: [Self; 5] = [
+        Self::Geometry,
+        Self::Color,
+        Self::Position,
+        Self::Motion,
+        Self::Animations,
+    ];
+
+    pub fn index(self) -> usize {
+        Self::ALL.iter().position(|c| *c == self).unwrap()
+    }
+}
+
+/// Which parameter classes are locked against recall.
+#[derive(Debug, Clone, Default)]
+pub struct RecallFilter {
+    locked: [bool; ParamClass::ALL.len()],
+}
+
+impl RecallFilter {
+    pub fn locked(&self, class: ParamClass) -> bool {
+        self.locked[class.index()]
+    }
+
+    /// Unlock every class.
+    /// Emits nothing; the caller is expected to emit the entire state after.
+    pub fn clear(&mut self) {
+        self.locked = Default::default();
+    }
+
+    /// Return the beam to put into a channel in place of the current beam
+    /// when recalling a beam from the store.
+    pub fn apply(&self, mut recalled: Beam, current: &Beam) -> Beam {
+        if let (Beam::Tunnel(recalled), Beam::Tunnel(current)) = (&mut recalled, current) {
+            for class in ParamClass::ALL {
+                if self.locked(class) {
+                    recalled.keep(current, class);
+                }
+            }
+        }
+        recalled
+    }
+
+    /// Emit the current value of all controllable state.
+    pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
+        for class in ParamClass::ALL {
+            emitter.emit_recall_filter_state_change(StateChange::Locked(class, self.locked(class)));
+        }
+    }
+
+    /// Handle a control event.
+    /// Emit any state changes that have happened as a result of handling.
+    pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) {
+        match msg {
+            ControlMessage::Toggle(class) => {
+                let locked = &mut self.locked[class.index()];
+                *locked = !*locked;
+                emitter.emit_recall_filter_state_change(StateChange::Locked(class, *locked));
+            }
+        }
+    }
+}
+
+pub enum ControlMessage {
+    /// Lock or unlock a parameter class.
+    Toggle(ParamClass),
+}
+
+pub enum StateChange {
+    /// While locked, recalls keep the current values of a parameter class.
+    Locked(ParamClass, bool),
+}
+
+pub trait EmitStateChange {
+    fn emit_recall_filter_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_recall_filter_state_change(&mut self, sc: StateChange) {
+        use crate::show::StateChange as ShowStateChange;
+        self.emit(ShowStateChange::RecallFilter(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        clock_bank::ClockBank,
+        master_ui::DummyEmitter,
+        random,
+        tunnel::{
+            ControlMessage as TunnelControlMessage, StateChange as TunnelStateChange, Tunnel,
+        },
+    };
+    use tunnels_lib::{number::UnipolarFloat, ArcSegment};
+
+    fn tunnel(thickness: f64, color: f64, x: f64) -> Beam {
+        let mut t = Tunnel::new();
+        for sc in [
+            TunnelStateChange::Thickness(UnipolarFloat::new(thickness)),
+            TunnelStateChange::ColorCenter(UnipolarFloat::new(color)),
+            TunnelStateChange::PositionX(x),
+        ] {
+            t.control(TunnelControlMessage::Set(sc), &mut DummyEmitter);
+        }
+        // Finish moving to the new position.
+        t.update_state(
+            std::time::Duration::from_secs(1),
+            &ClockBank::new(),
+            &mut random::stream(0, 0),
+        );
+        Beam::Tunnel(t)
+    }
+
+    fn first_arc(beam: &Beam) -> ArcSegment {
+        match beam {
+            Beam::Tunnel(t) => {
+                t.render(UnipolarFloat::ONE, false, 1.0, &ClockBank::new())[0].clone()
+            }
+            _ => panic!("not a tunnel"),
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let current = tunnel(0.1, 0.2, 0.3);
+        let stored = tunnel(0.5, 0.6, -0.3);
+        let (current_arc, stored_arc) = (first_arc(&current), first_arc(&stored));
+
+        let mut filter = RecallFilter::default();
+        let recalled = first_arc(&filter.apply(stored.clone(), &current));
+        assert_eq!(stored_arc.hue, recalled.hue);
+
+        // Recall geometry but keep the current colors and position.
+        filter.control(ControlMessage::Toggle(ParamClass::Color), &mut DummyEmitter);
+        filter.control(
+            ControlMessage::Toggle(ParamClass::Position),
+            &mut DummyEmitter,
+        );
+        let recalled = first_arc(&filter.apply(stored.clone(), &current));
+        assert_eq!(stored_arc.thickness, recalled.thickness);
+        assert_eq!(current_arc.hue, recalled.hue);
+        assert_eq!(current_arc.x, recalled.x);
+
+        filter.clear();
+        assert!(!filter.locked(ParamClass::Color));
+    }
+}
@@ -0,0 +1,88 @@
+//! A built-in generator: concentric rings that expand outward from the
+//! center, and doubles as an example of implementing a generator.
+
+use serde::{Deserialize, Serialize};
+use std::{error::Error, time::Duration};
+use tunnels_lib::{
+    number::{Phase, UnipolarFloat},
+    ArcSegment,
+};
+
+use crate::{clock_bank::ClockBank, generator::Generator, random::ShowRng};
+
+const N_RINGS: usize = 6;
+const MAX_RADIUS: f64 = 1.0;
+const THICKNESS: f64 = 0.05;
+/// Rings expand by one ring spacing per this many seconds.
+const PERIOD: f64 = 0.5;
+/// The rings cycle through every hue over this many seconds.
+const HUE_PERIOD: f64 = 20.0;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rings {
+    phase: Phase,
+    hue: Phase,
+}
+
+impl Rings {
+    pub fn new() -> Self {
+        Self {
+            phase: Phase::ZERO,
+            hue: Phase::ZERO,
+        }
+    }
+}
+
+impl Generator for Rings {
+    fn update_state(
+        &mut self,
+        delta_t: Duration,
+        _external_clocks: &ClockBank,
+        _rng: &mut ShowRng,
+    ) {
+        self.phase += delta_t.as_secs_f64() / PERIOD;
+        self.hue += delta_t.as_secs_f64() / HUE_PERIOD;
+    }
+
+    fn render(&self, level: UnipolarFloat, _external_clocks: &ClockBank) -> Vec<ArcSegment> {
+        let spacing = MAX_RADIUS / N_RINGS as f64;
+        (0..N_RINGS)
+            .map(|i| {
+                let radius = spacing * (i as f64 + self.phase.val());
+                ArcSegment {
+                    // Fade rings out as they approach the edge.
+                    level: level.val() * (1.0 - radius / MAX_RADIUS),
+                    thickness: THICKNESS,
+                    hue: self.hue.val(),
+                    sat: 1.0,
+                    val: 1.0,
+                    x: 0.0,
+                    y: 0.0,
+                    rad_x: radius,
+                    rad_y: radius,
+                    start: 0.0,
+                    stop: 1.0,
+                    rot_angle: 0.0,
+                    palette: false,
+                }
+            })
+            .collect()
+    }
+
+    fn arc_count(&self) -> usize {
+        N_RINGS
+    }
+
+    fn box_clone(&self) -> Box<dyn Generator> {
+        Box::new(self.clone())
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+//! Time-of-day automation for permanent installations.
+//!
+//! Rules are declared in the show config, and fire at a local time of day,
+//! optionally only on certain days of the week:
+//!
+//! ```yaml
+//! schedule:
+//!   - at: "07:30"
+//!     action: StartOutput
+//!   - at: "19:00"
+//!     days: [Fri, Sat]
+//!     action: {GrandMaster: 0.6}
+//!   - at: "21:00"
+//!     action: {Palette: 2}
+//!   - at: "21:00"
+//!     action: {Recall: {row: 0, col: 3}}
+//!   - at: "23:30"
+//!     action: StopOutput
+//! ```
+//!
+//! Palettes are counted in the order the show config defines them.  A
+//! recall puts the contents of that beam store slot into the current channel,
+//! whatever mode the beam store is in.
+
+use chrono::{Datelike, Duration as ChronoDuration, NaiveDateTime, NaiveTime, Weekday};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{convert::TryFrom, error::Error};
+use tracing::info;
+use tunnels_lib::number::UnipolarFloat;
+
+use crate::{
+    autopilot::{ControlMessage as AutopilotControlMessage, StateChange as AutopilotStateChange},
+    beam_store::{BeamStore, BeamStoreAddr},
+    master_ui::ControlMessage as MasterUIControlMessage,
+    mixer::{ControlMessage as MixerControlMessage, StateChange as MixerStateChange},
+    palette::{ControlMessage as PaletteControlMessage, Deck, StateChange as PaletteStateChange},
+    show::ControlMessage as ShowControlMessage,
+};
+
+/// A single scheduled action.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRule {
+    /// Local time of day at which to fire.
+    #[schemars(with = "String")]
+    pub at: TimeOfDay,
+    /// Only fire on these days.  Fire every day if empty.
+    #[serde(default)]
+    pub days: Vec<Day>,
+    pub action: ScheduledAction,
+}
+
+impl ScheduleRule {
+    /// Check the rule against the provided number of palettes.
+    pub fn validate(&self, n_palettes: usize) -> Result<(), Box<dyn Error>> {
+        match self.action {
+            ScheduledAction::GrandMaster(level) if !(0.0..=1.0).contains(&level) => {
+                bail!(
+                    "Scheduled grand master level at {} must be in [0, 1], got {}.",
+                    self.at.0,
+                    level
+                );
+            }
+            ScheduledAction::Palette(index) if index >= n_palettes => {
+                bail!(
+                    "Scheduled palette at {} is {} but the show has {} palettes.",
+                    self.at.0,
+                    index,
+                    n_palettes
+                );
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Check that a scheduled recall names a slot in a beam store with the
+    /// provided number of columns.
+    pub fn validate_slot(&self, n_beam_store_cols: usize) -> Result<(), Box<dyn Error>> {
+        if let ScheduledAction::Recall(addr) = self.action {
+            if addr.row >= BeamStore::N_ROWS || addr.col >= n_beam_store_cols {
+                bail!(
+                    "Scheduled recall at {} of {} is outside the {} by {} beam store.",
+                    self.at.0,
+                    addr,
+                    BeamStore::N_ROWS,
+                    n_beam_store_cols
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn fires_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.iter().any(|d| d.weekday() == day)
+    }
+}
+
+/// Things a schedule rule can do.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum ScheduledAction {
+    StartOutput,
+    StopOutput,
+    GrandMaster(f64),
+    Autopilot(bool),
+    /// Color the whole show with the palette at this index.
+    Palette(usize),
+    /// Recall a beam store slot into the current channel.
+    Recall(BeamStoreAddr),
+}
+
+impl ScheduledAction {
+    fn as_control_message(self) -> ShowControlMessage {
+        use ScheduledAction::*;
+        match self {
+            StartOutput => ShowControlMessage::Mixer(MixerControlMessage::Set(
+                MixerStateChange::Blackout(false),
+            )),
+            StopOutput => ShowControlMessage::Mixer(MixerControlMessage::Set(
+                MixerStateChange::Blackout(true),
+            )),
+            GrandMaster(level) => ShowControlMessage::Mixer(MixerControlMessage::Set(
+                MixerStateChange::GrandMaster(UnipolarFloat::new(level)),
+            )),
+            Autopilot(enabled) => ShowControlMessage::Autopilot(AutopilotControlMessage::Set(
+                AutopilotStateChange::Enabled(enabled),
+            )),
+            // A lone deck is used whatever the crossfader says.
+            Palette(index) => ShowControlMessage::Batch(
+                [(Deck::A, Some(index)), (Deck::B, None)]
+                    .iter()
+                    .map(|&(deck, index)| {
+                        ShowControlMessage::Palette(PaletteControlMessage::Set(
+                            PaletteStateChange::Select(deck, index),
+                        ))
+                    })
+                    .collect(),
+            ),
+            Recall(addr) => ShowControlMessage::MasterUI(MasterUIControlMessage::RecallBeam(addr)),
+        }
+    }
+}
+
+/// A local time of day, parsed from "HH:MM" or "HH:MM:SS".
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(NaiveTime);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        NaiveTime::parse_from_str(&s, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(&s, "%H:%M"))
+            .map(TimeOfDay)
+            .map_err(|e| format!("invalid time of day \"{}\": {}", s, e))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Day {
+    fn weekday(self) -> Weekday {
+        match self {
+            Self::Mon => Weekday::Mon,
+            Self::Tue => Weekday::Tue,
+            Self::Wed => Weekday::Wed,
+            Self::Thu => Weekday::Thu,
+            Self::Fri => Weekday::Fri,
+            Self::Sat => Weekday::Sat,
+            Self::Sun => Weekday::Sun,
+        }
+    }
+}
+
+/// Fire schedule rules as their times come due.
+pub struct Scheduler {
+    rules: Vec<ScheduleRule>,
+    last_poll: Option<NaiveDateTime>,
+}
+
+impl Scheduler {
+    /// On startup, replay everything that would have fired within this window,
+    /// so the show comes up in the state the schedule says it should be in.
+    const CATCH_UP_HOURS: i64 = 24;
+
+    pub fn new(rules: Vec<ScheduleRule>) -> Self {
+        Self {
+            rules,
+            last_poll: None,
+        }
+    }
+
+    /// Replace the rules, without replaying any that came due before now.
+    pub fn set_rules(&mut self, rules: Vec<ScheduleRule>) {
+        self.rules = rules;
+    }
+
+    /// Return a batch of control messages for every rule that has come due
+    /// since the last poll, in the order they came due, or None if no rules
+    /// are due.
+    /// Batching means a catch-up replay on startup is applied all at once,
+    /// rather than drawing the intermediate states.
+    pub fn poll(&mut self, now: NaiveDateTime) -> Option<ShowControlMessage> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let since = self
+            .last_poll
+            .unwrap_or_else(|| now - ChronoDuration::hours(Self::CATCH_UP_HOURS));
+        self.last_poll = Some(now);
+        let due = self.due(since, now);
+        if due.is_empty() {
+            return None;
+        }
+        Some(ShowControlMessage::Batch(
+            due.into_iter()
+                .map(|action| {
+                    info!("Running scheduled action {:?}.", action);
+                    action.as_control_message()
+                })
+                .collect(),
+        ))
+    }
+
+    /// Return the actions of all rules that fire in the window (since, until].
+    fn due(&self, since: NaiveDateTime, until: NaiveDateTime) -> Vec<ScheduledAction> {
+        // If the clock has gone backwards, there's nothing sensible to fire.
+        if until <= since {
+            return Vec::new();
+        }
+        let mut due = Vec::new();
+        let mut next_date = Some(since.date());
+        while let Some(date) = next_date.filter(|d| *d <= until.date()) {
+            for rule in &self.rules {
+                if !rule.fires_on(date.weekday()) {
+                    continue;
+                }
+                let fire_at = date.and_time(rule.at.0);
+                if since < fire_at && fire_at <= until {
+                    due.push((fire_at, rule.action));
+                }
+            }
+            next_date = date.succ_opt();
+        }
+        // Stable sort preserves config order for rules that fire at the same time.
+        due.sort_by_key(|(fire_at, _)| *fire_at);
+        due.into_iter().map(|(_, action)| action).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn rule(at: &str, days: Vec<Day>, action: ScheduledAction) -> ScheduleRule {
+        ScheduleRule {
+            at: TimeOfDay::try_from(at.to_string()).unwrap(),
+            days,
+            action,
+        }
+    }
+
+    fn datetime(day: u32, h: u32, m: u32) -> NaiveDateTime {
+        // 2021-03-01 was a Monday.
+        NaiveDate::from_ymd_opt(2021, 3, day)
+            .and_then(|d| d.and_hms_opt(h, m, 0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_due() {
+        use ScheduledAction::*;
+        let scheduler = Scheduler::new(vec![
+            rule("23:00", vec![], StopOutput),
+            rule("07:30", vec![], StartOutput),
+            rule("12:00", vec![Day::Tue], GrandMaster(0.5)),
+        ]);
+
+        // Window spanning midnight, in chronological order.
+        assert_eq!(
+            vec![StopOutput, StartOutput],
+            scheduler.due(datetime(1, 22, 0), datetime(2, 8, 0))
+        );
+        // Window end is inclusive, start is exclusive.
+        assert_eq!(
+            vec![StartOutput],
+            scheduler.due(datetime(2, 7, 29), datetime(2, 7, 30))
+        );
+        assert!(scheduler
+            .due(datetime(2, 7, 30), datetime(2, 7, 31))
+            .is_empty());
+        // Day restriction.
+        assert!(scheduler
+            .due(datetime(1, 11, 0), datetime(1, 13, 0))
+            .is_empty());
+        assert_eq!(
+            vec![GrandMaster(0.5)],
+            scheduler.due(datetime(2, 11, 0), datetime(2, 13, 0))
+        );
+        // Clock going backwards.
+        assert!(scheduler
+            .due(datetime(2, 13, 0), datetime(1, 13, 0))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_poll() {
+        use ScheduledAction::*;
+        let mut scheduler = Scheduler::new(vec![
+            rule("07:30", vec![], StartOutput),
+            rule("23:00", vec![], StopOutput),
+        ]);
+
+        // Everything in the catch-up window arrives in one batch.
+        match scheduler.poll(datetime(2, 8, 0)) {
+            Some(ShowControlMessage::Batch(msgs)) => assert_eq!(2, msgs.len()),
+            _ => panic!("expected a batch"),
+        }
+        assert!(scheduler.poll(datetime(2, 9, 0)).is_none());
+        assert!(Scheduler::new(vec![]).poll(datetime(2, 9, 0)).is_none());
+    }
+
+    #[test]
+    fn test_palette_and_recall() {
+        use ScheduledAction::*;
+        let slot = BeamStoreAddr { row: 1, col: 2 };
+        let scheduler = Scheduler::new(vec![
+            rule("21:00", vec![], Palette(1)),
+            rule("21:00", vec![], Recall(slot)),
+        ]);
+        assert_eq!(
+            vec![Palette(1), Recall(slot)],
+            scheduler.due(datetime(1, 20, 0), datetime(1, 22, 0))
+        );
+
+        // Emptying deck B leaves the palette on deck A showing.
+        match Palette(1).as_control_message() {
+            ShowControlMessage::Batch(msgs) => assert!(matches!(
+                msgs[..],
+                [
+                    ShowControlMessage::Palette(PaletteControlMessage::Set(
+                        PaletteStateChange::Select(Deck::A, Some(1))
+                    )),
+                    ShowControlMessage::Palette(PaletteControlMessage::Set(
+                        PaletteStateChange::Select(Deck::B, None)
+                    )),
+                ]
+            )),
+            _ => panic!("expected a batch"),
+        }
+        assert!(matches!(
+            Recall(slot).as_control_message(),
+            ShowControlMessage::MasterUI(MasterUIControlMessage::RecallBeam(addr))
+                if addr == slot
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        use ScheduledAction::*;
+        assert!(rule("21:00", vec![], GrandMaster(0.5)).validate(0).is_ok());
+        assert!(rule("21:00", vec![], GrandMaster(1.5)).validate(0).is_err());
+        assert!(rule("21:00", vec![], Palette(1)).validate(2).is_ok());
+        assert!(rule("21:00", vec![], Palette(2)).validate(2).is_err());
+
+        let recall = |row, col| rule("21:00", vec![], Recall(BeamStoreAddr { row, col }));
+        assert!(recall(BeamStore::N_ROWS - 1, 7).validate_slot(8).is_ok());
+        assert!(recall(BeamStore::N_ROWS, 0).validate_slot(8).is_err());
+        assert!(recall(0, 8).validate_slot(8).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let cfg: Vec<ScheduleRule> = serde_yaml::from_str(
+            r#"
+- at: "07:30"
+  action: StartOutput
+- at: "19:00:30"
+  days: [Fri, Sat]
+  action: {GrandMaster: 0.6}
+- at: "21:00"
+  action: {Recall: {row: 0, col: 3}}
+"#,
+        )
+        .unwrap();
+        assert_eq!(3, cfg.len());
+        assert_eq!(vec![Day::Fri, Day::Sat], cfg[1].days);
+        assert_eq!(ScheduledAction::GrandMaster(0.6), cfg[1].action);
+        assert_eq!(
+            ScheduledAction::Recall(BeamStoreAddr { row: 0, col: 3 }),
+            cfg[2].action
+        );
+        assert!(
+            serde_yaml::from_str::<ScheduleRule>("{at: \"25:00\", action: StopOutput}").is_err()
+        );
+    }
+}
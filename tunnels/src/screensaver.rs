@@ -0,0 +1,207 @@
+//! Show a dim look while nobody is at the controls.
+//!
+//! Intended for semi-attended installations.  Once no control input has
+//! arrived for a while, the show fades out, the look in a designated beam
+//! store slot is put into the mixer, and that look fades in at low intensity.
+//! The next control input puts back the look that was showing before and
+//! fades it back up to full.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{error::Error, time::Duration};
+use tunnels_lib::number::UnipolarFloat;
+
+use crate::{beam_store::BeamStore, look::Look};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScreensaverConfig {
+    /// Minutes without control input before the screensaver starts.
+    pub idle_minutes: f64,
+    /// Beam store row of the slot holding the screensaver look.
+    pub row: usize,
+    /// Beam store column of the slot holding the screensaver look.
+    pub col: usize,
+    /// Level to show the screensaver look at.
+    #[serde(default = "default_level")]
+    pub level: f64,
+    /// Seconds taken to fade out of the show, into the screensaver, and back.
+    #[serde(default = "default_fade")]
+    pub fade: f64,
+}
+
+fn default_level() -> f64 {
+    0.3
+}
+
+fn default_fade() -> f64 {
+    5.0
+}
+
+impl ScreensaverConfig {
+    /// Check the config against the provided number of beam store columns.
+    pub fn validate(&self, n_beam_store_cols: usize) -> Result<(), Box<dyn Error>> {
+        if self.row >= BeamStore::N_ROWS || self.col >= n_beam_store_cols {
+            bail!(
+                "Screensaver slot row {}, column {} is outside the {} by {} beam store.",
+                self.row,
+                self.col,
+                BeamStore::N_ROWS,
+                n_beam_store_cols
+            );
+        }
+        if !(0.0..=1.0).contains(&self.level) {
+            bail!(
+                "Screensaver level is {}; it must be between 0 and 1.",
+                self.level
+            );
+        }
+        for (name, v) in &[("idle_minutes", self.idle_minutes), ("fade", self.fade)] {
+            if !v.is_finite() || *v < 0.0 {
+                bail!("Screensaver {} is {}; it must not be negative.", name, v);
+            }
+        }
+        Ok(())
+    }
+}
+
+enum State {
+    Awake,
+    /// Showing the screensaver, holding the look to return to, if the
+    /// screensaver look replaced it.
+    Asleep(Option<Look>),
+}
+
+pub struct Screensaver {
+    idle: Duration,
+    row: usize,
+    col: usize,
+    sleep_level: UnipolarFloat,
+    fade: Duration,
+    idle_for: Duration,
+    state: State,
+    level: UnipolarFloat,
+}
+
+impl Screensaver {
+    pub fn new(cfg: &ScreensaverConfig) -> Self {
+        Self {
+            idle: Duration::from_secs_f64(cfg.idle_minutes * 60.0),
+            row: cfg.row,
+            col: cfg.col,
+            sleep_level: UnipolarFloat::new(cfg.level),
+            fade: Duration::from_secs_f64(cfg.fade),
+            idle_for: Duration::ZERO,
+            state: State::Awake,
+            level: UnipolarFloat::ONE,
+        }
+    }
+
+    /// The beam store row and column of the screensaver look.
+    pub fn slot(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    pub fn asleep(&self) -> bool {
+        matches!(self.state, State::Asleep(_))
+    }
+
+    /// True once the show has faded out for the screensaver, which should
+    /// then be started with sleep.
+    pub fn due(&self) -> bool {
+        !self.asleep() && self.idle_for > self.idle && self.level == UnipolarFloat::ZERO
+    }
+
+    /// Count time without input, and return the level the show should be
+    /// scaled by.
+    pub fn update(&mut self, delta_t: Duration) -> UnipolarFloat {
+        self.idle_for += delta_t;
+        let target = match self.state {
+            State::Asleep(_) => self.sleep_level.val(),
+            State::Awake if self.idle_for > self.idle => 0.0,
+            State::Awake => 1.0,
+        };
+        let step = if self.fade.as_secs_f64() == 0.0 {
+            1.0
+        } else {
+            delta_t.as_secs_f64() / self.fade.as_secs_f64()
+        };
+        let current = self.level.val();
+        self.level = UnipolarFloat::new(if target > current {
+            (current + step).min(target)
+        } else {
+            (current - step).max(target)
+        });
+        self.level
+    }
+
+    /// Start the screensaver, holding on to the look it replaced, if any.
+    pub fn sleep(&mut self, replaced: Option<Look>) {
+        self.state = State::Asleep(replaced);
+    }
+
+    /// Note that control input has arrived.
+    /// If the screensaver was showing, stop it, and return the look it
+    /// replaced, if any.  The show fades back up from the screensaver level.
+    pub fn wake(&mut self) -> Option<Option<Look>> {
+        self.idle_for = Duration::ZERO;
+        match std::mem::replace(&mut self.state, State::Awake) {
+            State::Asleep(replaced) => Some(replaced),
+            State::Awake => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn screensaver() -> Screensaver {
+        Screensaver::new(&ScreensaverConfig {
+            idle_minutes: 1.0,
+            row: 0,
+            col: 0,
+            level: 0.25,
+            fade: 1.0,
+        })
+    }
+
+    /// Run the screensaver for a number of seconds in 0.1 s steps.
+    fn run(s: &mut Screensaver, seconds: f64) -> f64 {
+        let mut level = s.level;
+        for _ in 0..(seconds * 10.0).round() as usize {
+            level = s.update(Duration::from_millis(100));
+        }
+        level.val()
+    }
+
+    #[test]
+    fn test_sleep_and_wake() {
+        let mut s = screensaver();
+        assert_eq!(1.0, run(&mut s, 60.0));
+        assert!(!s.due());
+        assert!(run(&mut s, 0.5) < 1.0);
+        assert_eq!(0.0, run(&mut s, 1.0));
+        assert!(s.due());
+
+        s.sleep(Some(Look::from_channels(Vec::new())));
+        assert!(!s.due());
+        assert_eq!(0.25, run(&mut s, 1.0));
+        // Still showing the screensaver long after.
+        assert_eq!(0.25, run(&mut s, 120.0));
+
+        assert!(matches!(s.wake(), Some(Some(_))));
+        assert_eq!(1.0, run(&mut s, 1.0));
+        assert!(s.wake().is_none());
+    }
+
+    #[test]
+    fn test_input_cancels_fade() {
+        let mut s = screensaver();
+        assert!(run(&mut s, 60.5) < 1.0);
+        assert!(s.wake().is_none());
+        assert_eq!(1.0, run(&mut s, 1.0));
+        assert!(!s.due());
+    }
+}
@@ -1,118 +1,563 @@
 use std::{
     error::Error,
-    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 
-use log::{error, info, warn};
 use rmp_serde::Serializer;
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
 use std::thread;
-use tunnels_lib::{Snapshot, Timestamp};
+use tracing::{debug_span, error, info, warn};
+use tunnels_lib::{
+    archive::{ArchiveFrame, Record, RollingArchiveWriter},
+    chunk::{self, CHUNK_SIZE},
+    color::ColorCorrection,
+    number::UnipolarFloat,
+    palette::{resolve_layers, Palette},
+    ports,
+    queue::{latest, LatestReceiver, LatestSender},
+    show_id::{frame_topic, keepalive_topic},
+    thread_config::ThreadConfig,
+    BlendMode, LayerCollection, Snapshot, Timestamp,
+};
 use zmq::{Context, Socket};
 
-use crate::{clock_bank::ClockBank, mixer::Mixer};
+use crate::{
+    clock_bank::ClockBank,
+    diagnostic::{self, DIAGNOSTIC_CHANNEL},
+    flash_limit::{self, FlashLimiter},
+    frame_check::FrameChecker,
+    mixer::{Mixer, VideoChannel},
+    pixel_map::PixelMap,
+    throttle::Throttle,
+    tunnel::Tunnel,
+    video_out::VideoOutputs,
+};
+
+const PORT: u16 = ports::SNAPSHOTS;
 
-const PORT: u16 = 6000;
+/// How often to publish a keepalive for clients.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
 
-/// Renders the show state and sends it to all connected clients.
-/// Returns a channel for sending frames to be rendered.
-/// The service runs until the channel is dropped.
-pub fn start_render_service(ctx: &mut Context) -> Result<Sender<Frame>, Box<dyn Error>> {
+/// Bind the PUB socket that clients subscribe to for snapshots.
+pub fn bind_publisher(ctx: &mut Context) -> Result<Socket, Box<dyn Error>> {
     let socket = ctx.socket(zmq::PUB)?;
     let addr = format!("tcp://*:{}", PORT);
     socket.bind(&addr)?;
+    Ok(socket)
+}
+
+/// Continuously record the published show to disk, keeping only the most
+/// recent few minutes, so that a glitch can be replayed after the fact.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayBufferConfig {
+    /// Directory to write the buffer into, relative to the working directory.
+    #[serde(default = "default_replay_buffer_dir")]
+    pub dir: PathBuf,
+    /// Minutes of history to keep.
+    pub minutes: f64,
+    /// Seconds of history stored in each file of the buffer.
+    #[serde(default = "default_segment_seconds")]
+    pub segment_seconds: f64,
+}
 
-    let (send, mut recv) = channel();
+fn default_replay_buffer_dir() -> PathBuf {
+    PathBuf::from("recordings/replay_buffer")
+}
+
+fn default_segment_seconds() -> f64 {
+    30.0
+}
 
-    let mut send_buf = Vec::new();
+impl ReplayBufferConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !self.minutes.is_finite() || self.minutes <= 0.0 {
+            bail!(
+                "Replay buffer minutes is {}; it must be positive.",
+                self.minutes
+            );
+        }
+        if !self.segment_seconds.is_finite() || self.segment_seconds <= 0.0 {
+            bail!(
+                "Replay buffer segment_seconds is {}; it must be positive.",
+                self.segment_seconds
+            );
+        }
+        Ok(())
+    }
+
+    /// Start writing the replay buffer.
+    pub fn create(&self) -> Result<RollingArchiveWriter, Box<dyn Error>> {
+        RollingArchiveWriter::create(
+            &self.dir,
+            Duration::from_secs_f64(self.minutes * 60.0),
+            Duration::from_secs_f64(self.segment_seconds),
+        )
+    }
+}
+
+/// The peak level drawn by each mixer channel in one frame.
+pub type ChannelPeaks = Vec<UnipolarFloat>;
+
+/// Renders the show state and publishes it to all connected clients on the
+/// provided socket.
+/// The render thread is scheduled according to the provided config.
+/// Each rendered frame is sanity-checked before it is sent, and then dimmed
+/// if needed to keep within the flash limit, if any.
+/// Every published snapshot is also recorded to each of the provided archives.
+/// Video channels are routed to outputs by the frame's output profile, and
+/// LED fixtures are driven from the outputs by that profile's pixel map, if
+/// any; pixel maps are provided in the same order as the profiles.
+/// Snapshots for outputs with display latency are timestamped early.
+/// If requested, the diagnostic layer is also sent, but not checked or
+/// recorded.
+/// Snapshots are published under topics tagged with the show ID, if any.
+/// A keepalive is published with the first frame each second.
+/// Video channels with a bandwidth budget are sent at a reduced frame rate
+/// when their frames exceed it.
+/// If an interleave period is provided, the video channels with content are
+/// rendered, checked, and sent at even intervals across it, instead of all at
+/// once, to spread the load and the burst of traffic at the end of each frame.
+/// Returns a slot for sending frames to be rendered, and a slot that receives
+/// the peak level drawn by each mixer channel in the latest rendered frame.
+/// The service runs until the frame sender is dropped.
+#[allow(clippy::too_many_arguments)]
+pub fn start_render_service(
+    socket: Socket,
+    thread_config: ThreadConfig,
+    checker: FrameChecker,
+    flash_limiter: Option<FlashLimiter>,
+    archives: Vec<Box<dyn Record + Send>>,
+    pixel_maps: Vec<Option<PixelMap>>,
+    diagnostic_layer: bool,
+    interleave: Option<Duration>,
+    show_id: Option<String>,
+) -> Result<(LatestSender<Frame>, LatestReceiver<ChannelPeaks>), Box<dyn Error>> {
+    // Only the newest frame is ever rendered, and only the newest peaks are
+    // metered, so a stalled consumer skips straight to the newest.
+    let (send, recv) = latest::<Frame>();
+    let (send_peaks, recv_peaks) = latest();
+
+    let mut service = RenderService {
+        socket,
+        checker,
+        flash_limiter,
+        archives,
+        pixel_maps,
+        diagnostic_layer,
+        interleave,
+        keepalive: keepalive_topic(show_id.as_deref()),
+        show_id,
+        last_keepalive: None,
+        send_buf: Vec::new(),
+        throttles: (0..Mixer::N_VIDEO_CHANNELS)
+            .map(|_| Throttle::new())
+            .collect(),
+        peaks: send_peaks,
+    };
     thread::Builder::new()
         .name("render".to_string())
-        .spawn(move || loop {
-            match get_frame(&mut recv) {
-                None => {
-                    info!("Render server shutting down.");
-                    return;
-                }
-                Some((dropped_frames, frame)) => {
-                    if dropped_frames > 0 {
-                        warn!("Render server dropped {} frames.", dropped_frames);
-                    }
-
-                    let video_outs = frame.mixer.render(&frame.clocks);
-                    for (video_chan, draw_commands) in video_outs.into_iter().enumerate() {
-                        let snapshot = Snapshot {
-                            frame_number: frame.number,
-                            time: frame.timestamp,
-                            layers: draw_commands,
-                        };
-                        send_snapshot(&mut send_buf, &socket, video_chan, snapshot);
-                    }
+        .spawn(move || {
+            if let Err(e) = thread_config.apply_to_current() {
+                error!("Unable to configure render thread: {}", e);
+            }
+            let mut next = recv.recv();
+            while let Some(frame) = next {
+                next = service.render(frame, &recv).or_else(|| recv.recv());
+            }
+            info!("Render server shutting down.");
+            for archive in service.archives.iter_mut() {
+                if let Err(e) = archive.flush() {
+                    error!("Snapshot archive flush error: {}.", e);
                 }
             }
         })?;
     info!("Render server started.");
-    Ok(send)
-}
-
-/// Block until a frame is available.
-/// Also optimistically check if there is already one or more frames backed up
-/// behind the first frame.  If so, drain them all and return the last frame
-/// received as well as the number of dropped frames.
-/// If the receiver has disconnected, return None.
-fn get_frame(recv: &mut Receiver<Frame>) -> Option<(u32, Frame)> {
-    let mut dropped_frames = 0;
-    // Wait for a frame.
-    let mut frame = match recv.recv() {
-        Ok(frame) => frame,
-        Err(_) => return None,
-    };
-    loop {
-        match recv.try_recv() {
-            Ok(newer_frame) => {
-                dropped_frames += 1;
-                frame = newer_frame;
+    Ok((send, recv_peaks))
+}
+
+/// The state of the render thread.
+struct RenderService {
+    socket: Socket,
+    checker: FrameChecker,
+    flash_limiter: Option<FlashLimiter>,
+    archives: Vec<Box<dyn Record + Send>>,
+    pixel_maps: Vec<Option<PixelMap>>,
+    diagnostic_layer: bool,
+    interleave: Option<Duration>,
+    show_id: Option<String>,
+    keepalive: Vec<u8>,
+    last_keepalive: Option<Instant>,
+    send_buf: Vec<u8>,
+    throttles: Vec<Throttle>,
+    peaks: LatestSender<ChannelPeaks>,
+}
+
+impl RenderService {
+    /// Render and send a frame.
+    /// When interleaving, each video channel with content is rendered and
+    /// sent at its own deadline through the interleave period.  A newer frame
+    /// that arrives in the meantime is returned, once the rest of this
+    /// frame's video channels have been rendered and sent without waiting.
+    fn render(&mut self, frame: Frame, frames: &LatestReceiver<Frame>) -> Option<Frame> {
+        let received = Instant::now();
+        if !matches!(self.last_keepalive, Some(t) if received - t < KEEPALIVE_INTERVAL) {
+            publish_keepalive(&self.socket, &self.keepalive);
+            self.last_keepalive = Some(received);
+        }
+        let _span = debug_span!("render", frame = frame.number).entered();
+
+        let palette = frame.mixer.palette();
+        let color = frame.mixer.color_correction();
+        let slots = match self.interleave {
+            Some(_) => interleave_slots(&frame.mixer.live_video_channels()),
+            None => vec![(0..Mixer::N_VIDEO_CHANNELS).map(VideoChannel).collect()],
+        };
+        let pixel_mapped = matches!(self.pixel_maps.get(frame.output_profile), Some(Some(_)));
+        // Fixtures have their own correction, so they sample the mix of each
+        // output before the output adjusts it.
+        let mut unadjusted = vec![(LayerCollection::new(), Vec::new()); Mixer::N_VIDEO_CHANNELS];
+        let mut render = frame.mixer.start_render();
+        let mut newer = None;
+        let n_slots = slots.len();
+        for (slot, video_channels) in slots.into_iter().enumerate() {
+            if let (Some(period), None) = (self.interleave, &newer) {
+                let due = received + interleave_offset(period, slot, n_slots);
+                if Instant::now() < due {
+                    newer = frames.recv_until(due);
+                }
             }
-            Err(TryRecvError::Empty) => {
-                return Some((dropped_frames, frame));
+            frame
+                .mixer
+                .render_into(&frame.clocks, &video_channels, &mut render);
+            for VideoChannel(chan) in video_channels {
+                let output = frame.video_outputs.output(chan);
+                let mut layers = std::mem::take(&mut render.video_outs[chan]);
+                let blend = std::mem::take(&mut render.blend[chan]);
+                if pixel_mapped {
+                    unadjusted[output] = (layers.clone(), blend.clone());
+                }
+                let color = frame
+                    .video_outputs
+                    .apply(output, &mut layers, palette.as_ref(), color);
+                self.send(&frame, output, layers, blend, palette.as_ref(), color);
             }
-            Err(TryRecvError::Disconnected) => {
-                return None;
+        }
+        // The show may have stopped listening; that's fine.
+        let _ = self.peaks.send(render.peaks);
+        if let Some(Some(pixel_map)) = self.pixel_maps.get_mut(frame.output_profile) {
+            let (mut resolved, blend): (Vec<_>, Vec<_>) = unadjusted.into_iter().unzip();
+            let mut channel_layers = render.channel_layers;
+            for layers in resolved.iter_mut().chain([&mut channel_layers]) {
+                resolve_layers(layers, palette.as_ref());
+                if let Some(color) = &color {
+                    color.apply_layers(layers);
+                }
             }
+            let limiter = &mut self.flash_limiter;
+            pixel_map.render(&resolved, &blend, &channel_layers, |brightness| {
+                limiter.as_mut().map_or(1.0, |limiter| {
+                    limiter.scale(
+                        flash_limit::Output::PixelMap,
+                        brightness,
+                        frame.timestamp,
+                        frame.flash_limit_override,
+                    )
+                })
+            });
+        }
+        if self.diagnostic_layer {
+            let snapshot = Snapshot {
+                frame_number: frame.number,
+                time: frame.timestamp,
+                layers: diagnostic::render(&frame.clocks, frame.audio_level, frame.listen.as_ref()),
+                palette: None,
+                blend: Vec::new(),
+                color: None,
+            };
+            send_snapshot(
+                &mut self.send_buf,
+                &self.socket,
+                self.show_id.as_deref(),
+                DIAGNOSTIC_CHANNEL,
+                &snapshot,
+            );
         }
+        newer
+    }
+
+    /// Check, limit, send, and record the layers of one output.
+    fn send(
+        &mut self,
+        frame: &Frame,
+        output: usize,
+        layers: LayerCollection,
+        blend: Vec<BlendMode>,
+        palette: Option<&Palette>,
+        color: Option<ColorCorrection>,
+    ) {
+        let mut layers = self.checker.check(frame.number, output, layers);
+        if let Some(limiter) = &mut self.flash_limiter {
+            limiter.limit(
+                output,
+                &mut layers,
+                &blend,
+                frame.video_outputs.aspect_ratio(output),
+                frame.timestamp,
+                frame.flash_limit_override,
+            );
+        }
+        // Only send the palette along when it is drawn from, so clients that
+        // predate palettes can still read everything else.
+        let uses_palette = layers.iter().any(|l| l.iter().any(|arc| arc.palette));
+        let latency = frame.video_outputs.latency(output);
+        let snapshot = Snapshot {
+            frame_number: frame.number,
+            time: frame.timestamp - Timestamp::from_duration(latency),
+            layers,
+            palette: if uses_palette { palette.cloned() } else { None },
+            blend,
+            color,
+        };
+        if serialize_snapshot(&mut self.send_buf, output, &snapshot) {
+            let admit = match frame.video_outputs.bandwidth(output) {
+                Some(budget) => throttle(
+                    &mut self.throttles[output],
+                    output,
+                    self.send_buf.len(),
+                    budget,
+                ),
+                None => true,
+            };
+            if admit {
+                publish_snapshot(
+                    &self.send_buf,
+                    &self.socket,
+                    self.show_id.as_deref(),
+                    output,
+                    snapshot.frame_number,
+                );
+            }
+        }
+        if !self.archives.is_empty() {
+            archive_snapshot(&mut self.archives, output, snapshot);
+        }
+    }
+}
+
+/// Group video channels into the slots they are rendered in when
+/// interleaving, given the video channels with content.  Each video channel
+/// with content gets a slot of its own, and the rest go in the first slot,
+/// since they cost next to nothing.  There is always at least one slot.
+fn interleave_slots(live: &[VideoChannel]) -> Vec<Vec<VideoChannel>> {
+    let mut slots: Vec<Vec<VideoChannel>> = live.iter().map(|vc| vec![*vc]).collect();
+    if slots.is_empty() {
+        slots.push(Vec::new());
+    }
+    slots[0].extend(
+        (0..Mixer::N_VIDEO_CHANNELS)
+            .map(VideoChannel)
+            .filter(|vc| !live.contains(vc)),
+    );
+    slots
+}
+
+/// Return how long after a frame is received to render and send the video
+/// channels in the provided slot, spreading all of the slots evenly across
+/// the period.
+fn interleave_offset(period: Duration, slot: usize, slots: usize) -> Duration {
+    if slots == 0 {
+        return Duration::ZERO;
     }
+    period.mul_f64(slot as f64 / slots as f64)
 }
 
-/// Serialize the provided snapshot and send it to the specified video channel.
+/// Serialize the provided snapshot and send it to the specified video channel
+/// of the show with the provided ID.
 /// Error conditions are logged.
-fn send_snapshot(
-    mut send_buf: &mut Vec<u8>,
+pub fn send_snapshot(
+    send_buf: &mut Vec<u8>,
     socket: &Socket,
+    show_id: Option<&str>,
     video_channel: usize,
-    snapshot: Snapshot,
+    snapshot: &Snapshot,
 ) {
-    let topic = [video_channel as u8; 1];
-    send_buf.clear();
+    if serialize_snapshot(send_buf, video_channel, snapshot) {
+        publish_snapshot(
+            send_buf,
+            socket,
+            show_id,
+            video_channel,
+            snapshot.frame_number,
+        );
+    }
+}
 
+/// Serialize the provided snapshot into the buffer, returning false if it
+/// could not be serialized.
+/// Error conditions are logged.
+fn serialize_snapshot(
+    mut send_buf: &mut Vec<u8>,
+    video_channel: usize,
+    snapshot: &Snapshot,
+) -> bool {
+    send_buf.clear();
     if let Err(e) = snapshot.serialize(&mut Serializer::new(&mut send_buf)) {
         error!(
             "Snapshot serialization error for frame {} channel {}: {}.",
             snapshot.frame_number, video_channel, e,
         );
-        return;
+        return false;
     }
+    true
+}
 
-    let messages: [&[u8]; 2] = [&topic, send_buf];
-    if let Err(e) = socket.send_multipart(messages.iter(), 0) {
+/// Send a serialized snapshot to the specified video channel of the show with
+/// the provided ID.  Very large snapshots are sent in chunks.
+/// Error conditions are logged.
+fn publish_snapshot(
+    serialized: &[u8],
+    socket: &Socket,
+    show_id: Option<&str>,
+    video_channel: usize,
+    frame_number: u64,
+) {
+    let topic = frame_topic(show_id, video_channel as u8);
+    let result = if serialized.len() <= CHUNK_SIZE {
+        let messages: [&[u8]; 2] = [&topic, serialized];
+        socket.send_multipart(messages.iter(), 0)
+    } else {
+        let mut chunks = match chunk::split(serialized, frame_number) {
+            Some(chunks) => chunks,
+            None => {
+                error!(
+                    "Snapshot for frame {} channel {} is too large to send: {} bytes.",
+                    frame_number,
+                    video_channel,
+                    serialized.len()
+                );
+                return;
+            }
+        };
+        chunks.try_for_each(|(header, chunk)| {
+            let header = header.encode();
+            let messages: [&[u8]; 3] = [&topic, &header, chunk];
+            socket.send_multipart(messages.iter(), 0)
+        })
+    };
+    if let Err(e) = result {
         error!(
             "Snapshot send error for frame {} channel {}: {}.",
-            snapshot.frame_number, video_channel, e,
+            frame_number, video_channel, e,
         );
     }
 }
 
+/// Let clients know the server is still here, even if it has nothing to send
+/// them.
+/// Error conditions are logged.
+fn publish_keepalive(socket: &Socket, topic: &[u8]) {
+    let messages: [&[u8]; 2] = [topic, &[]];
+    if let Err(e) = socket.send_multipart(messages.iter(), 0) {
+        error!("Keepalive send error: {}.", e);
+    }
+}
+
+/// Return true if a serialized frame of the provided size fits in a video
+/// channel's bandwidth budget, in bytes per second.
+/// Periodically log how the channel is being throttled.
+fn throttle(throttle: &mut Throttle, video_channel: usize, size: usize, budget: f64) -> bool {
+    let now = Instant::now();
+    let admit = throttle.admit(now, size, budget);
+    if let Some((report, elapsed)) = throttle.take_report(now) {
+        let secs = elapsed.as_secs_f64();
+        warn!(
+            "Video channel {} is over its bandwidth budget of {:.0} kB/s; sent {} and dropped {} frames in the last {:.0} seconds, at {:.0} kB/s.",
+            video_channel,
+            budget / 1000.0,
+            report.sent,
+            report.dropped,
+            secs,
+            report.sent_bytes as f64 / secs / 1000.0,
+        );
+    }
+    admit
+}
+
+/// Record a published snapshot to every archive.  Error conditions are logged.
+fn archive_snapshot(
+    archives: &mut [Box<dyn Record + Send>],
+    video_channel: usize,
+    snapshot: Snapshot,
+) {
+    let frame_number = snapshot.frame_number;
+    let frame = ArchiveFrame {
+        video_channel: video_channel as u8,
+        snapshot,
+    };
+    for archive in archives.iter_mut() {
+        if let Err(e) = archive.write(&frame) {
+            error!(
+                "Snapshot archive error for frame {} channel {}: {}.",
+                frame_number, video_channel, e,
+            );
+        }
+    }
+}
+
 pub struct Frame {
     pub number: u64,
     pub timestamp: Timestamp,
     pub mixer: Mixer,
     pub clocks: ClockBank,
+    pub video_outputs: VideoOutputs,
+    /// Index of the active output profile.
+    pub output_profile: usize,
+    /// The audio input level, if there is an audio input.
+    pub audio_level: Option<UnipolarFloat>,
+    /// If true, flashes are counted but not limited.
+    pub flash_limit_override: bool,
+    /// The current beam with the animation being listened to at full
+    /// weight, for the diagnostic layer.
+    pub listen: Option<Tunnel>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interleave_slots() {
+        let slots = interleave_slots(&[VideoChannel(1), VideoChannel(4)]);
+        assert_eq!(2, slots.len());
+        assert_eq!(vec![VideoChannel(4)], slots[1]);
+        assert_eq!(VideoChannel(1), slots[0][0]);
+        // Every video channel is rendered exactly once.
+        let mut all: Vec<_> = slots.into_iter().flatten().collect();
+        all.sort();
+        assert_eq!(
+            (0..Mixer::N_VIDEO_CHANNELS)
+                .map(VideoChannel)
+                .collect::<Vec<_>>(),
+            all
+        );
+
+        let slots = interleave_slots(&[]);
+        assert_eq!(1, slots.len());
+        assert_eq!(Mixer::N_VIDEO_CHANNELS, slots[0].len());
+    }
+
+    #[test]
+    fn test_interleave_offset() {
+        let period = Duration::from_micros(16000);
+        assert_eq!(Duration::ZERO, interleave_offset(period, 0, 0));
+        assert_eq!(Duration::ZERO, interleave_offset(period, 0, 4));
+        assert_eq!(
+            Duration::from_micros(12000),
+            interleave_offset(period, 3, 4)
+        );
+    }
 }
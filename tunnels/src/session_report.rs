@@ -0,0 +1,385 @@
+//! Statistics gathered over a show, written out as JSON when the show shuts
+//! down cleanly for review after the gig.
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    fs::{create_dir_all, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{device::Device, master_ui::BeamStoreStats};
+
+/// Warn about items dropped by each queue at most this often.
+const DROP_WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keep this many of the most recent samples of each timing, about half an
+/// hour of frames at 60 fps, so a long show doesn't grow without bound.
+const MAX_SAMPLES: usize = 100_000;
+
+/// Accumulates statistics while the show runs.
+pub struct SessionStats {
+    started: DateTime<Local>,
+    start: Instant,
+    /// Time spent computing each frame, in milliseconds.
+    frame_times: Samples,
+    late_frames: u64,
+    control_events: HashMap<Device, u64>,
+    /// Time from each control event arriving to the frame showing its
+    /// effect, in milliseconds.
+    input_latencies: Samples,
+    clients: BTreeMap<usize, ClientHealth>,
+    alerts: u64,
+    dropped: Dropped,
+    drop_warnings: [DropWarning; DropQueue::ALL.len()],
+}
+
+/// The queues between threads that drop items rather than fall behind.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropQueue {
+    /// Frames waiting for the render thread.
+    Frames,
+    /// Control events waiting for the show thread.
+    ControlEvents,
+    /// Channel peaks waiting for the show thread to meter them.
+    ChannelPeaks,
+}
+
+impl DropQueue {
+    pub const ALL: [Self; 3] = [Self::Frames, Self::ControlEvents, Self::ChannelPeaks];
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|q| *q == self).unwrap()
+    }
+}
+
+impl fmt::Display for DropQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Frames => "Render server is falling behind; dropped frames",
+            Self::ControlEvents => "Control events are arriving too fast; dropped events",
+            Self::ChannelPeaks => "Show is falling behind metering channels; dropped peaks",
+        })
+    }
+}
+
+/// A fixed-size ring of the most recent samples of a timing, along with a
+/// count of every sample recorded.
+struct Samples {
+    ring: Vec<f32>,
+    capacity: usize,
+    /// Index of the oldest sample, once the ring is full.
+    next: usize,
+    count: u64,
+}
+
+impl Samples {
+    fn new(capacity: usize) -> Self {
+        Self {
+            ring: Vec::new(),
+            capacity,
+            next: 0,
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, v: f32) {
+        if self.ring.len() < self.capacity {
+            self.ring.push(v);
+        } else {
+            self.ring[self.next] = v;
+            self.next = (self.next + 1) % self.capacity;
+        }
+        self.count += 1;
+    }
+}
+
+/// Items dropped by each queue between threads.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Dropped {
+    pub frames: u64,
+    pub control_events: u64,
+    pub channel_peaks: u64,
+}
+
+/// Drops not yet warned about.
+#[derive(Debug, Default, Clone, Copy)]
+struct DropWarning {
+    pending: u64,
+    last: Option<Instant>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ClientHealth {
+    /// Total time a client was connected to this video channel.
+    pub connected_seconds: f64,
+    /// How many times the client stopped sending heartbeats.
+    pub disconnects: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionReport {
+    pub started: String,
+    pub ended: String,
+    pub duration_seconds: f64,
+    pub frames: u64,
+    /// Time spent computing each of the most recent frames, in milliseconds.
+    pub frame_time_ms: Percentiles,
+    /// Frames computed more than a frame late.
+    pub late_frames: u64,
+    /// Control events received from each device.
+    pub control_events: BTreeMap<String, u64>,
+    /// Time from each of the most recent control events arriving to the
+    /// frame showing its effect, in milliseconds.
+    pub input_latency_ms: Percentiles,
+    pub beams_saved: u64,
+    pub looks_saved: u64,
+    pub beams_recalled: u64,
+    /// Keyed by video channel, for every channel a client ever connected to.
+    pub clients: BTreeMap<usize, ClientHealth>,
+    pub alerts: u64,
+    /// Items dropped by the queues between threads.
+    pub dropped: Dropped,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Percentiles {
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    fn new(mut times: Vec<f32>) -> Self {
+        if times.is_empty() {
+            return Self::default();
+        }
+        times.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| {
+            let i = ((times.len() - 1) as f64 * p).round() as usize;
+            times[i] as f64
+        };
+        Self {
+            mean: times.iter().map(|t| *t as f64).sum::<f64>() / times.len() as f64,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: times[times.len() - 1] as f64,
+        }
+    }
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Self {
+            started: Local::now(),
+            start: Instant::now(),
+            frame_times: Samples::new(MAX_SAMPLES),
+            late_frames: 0,
+            control_events: HashMap::new(),
+            input_latencies: Samples::new(MAX_SAMPLES),
+            clients: BTreeMap::new(),
+            alerts: 0,
+            dropped: Dropped::default(),
+            drop_warnings: Default::default(),
+        }
+    }
+
+    /// Record how long a frame took to compute, and whether it was late.
+    pub fn record_frame(&mut self, elapsed: Duration, late: bool) {
+        self.frame_times.push(elapsed.as_secs_f32() * 1000.0);
+        if late {
+            self.late_frames += 1;
+        }
+    }
+
+    pub fn record_control_event(&mut self, device: Device) {
+        *self.control_events.entry(device).or_default() += 1;
+    }
+
+    /// Record how long a control event waited to be rendered.
+    pub fn record_input_latency(&mut self, latency: Duration) {
+        self.input_latencies.push(latency.as_secs_f32() * 1000.0);
+    }
+
+    /// Record which video channels had a client connected for the last
+    /// delta_t.
+    pub fn record_clients(&mut self, live: &[bool], delta_t: Duration) {
+        for (chan, live) in live.iter().enumerate() {
+            if *live {
+                self.clients.entry(chan).or_default().connected_seconds += delta_t.as_secs_f64();
+            }
+        }
+    }
+
+    pub fn record_disconnect(&mut self, video_channel: usize) {
+        self.clients.entry(video_channel).or_default().disconnects += 1;
+    }
+
+    pub fn record_alert(&mut self) {
+        self.alerts += 1;
+    }
+
+    /// Record items dropped by a queue.
+    /// To keep a struggling show from flooding the log, return the number
+    /// dropped since the last warning only if it is time to warn again.
+    pub fn record_dropped(
+        &mut self,
+        queue: DropQueue,
+        dropped: usize,
+        now: Instant,
+    ) -> Option<u64> {
+        let warning = &mut self.drop_warnings[queue.index()];
+        if dropped > 0 {
+            let dropped = dropped as u64;
+            *match queue {
+                DropQueue::Frames => &mut self.dropped.frames,
+                DropQueue::ControlEvents => &mut self.dropped.control_events,
+                DropQueue::ChannelPeaks => &mut self.dropped.channel_peaks,
+            } += dropped;
+            warning.pending += dropped;
+        }
+        if warning.pending == 0
+            || matches!(warning.last, Some(t) if now - t < DROP_WARNING_INTERVAL)
+        {
+            return None;
+        }
+        warning.last = Some(now);
+        Some(std::mem::take(&mut warning.pending))
+    }
+
+    /// Produce the report for the session so far.
+    pub fn report(&self, beam_store: BeamStoreStats) -> SessionReport {
+        let ended = Local::now();
+        SessionReport {
+            started: self.started.to_rfc3339(),
+            ended: ended.to_rfc3339(),
+            duration_seconds: self.start.elapsed().as_secs_f64(),
+            frames: self.frame_times.count,
+            frame_time_ms: Percentiles::new(self.frame_times.ring.clone()),
+            late_frames: self.late_frames,
+            control_events: self
+                .control_events
+                .iter()
+                .map(|(device, count)| (device.to_string(), *count))
+                .collect(),
+            input_latency_ms: Percentiles::new(self.input_latencies.ring.clone()),
+            beams_saved: beam_store.beams_saved,
+            looks_saved: beam_store.looks_saved,
+            beams_recalled: beam_store.recalled,
+            clients: self.clients.clone(),
+            alerts: self.alerts,
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Return the path to write this session's report to in the provided
+    /// directory.
+    pub fn report_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!(
+            "session_{}.json",
+            self.started.format("%Y-%m-%d_%H-%M-%S")
+        ))
+    }
+}
+
+impl SessionReport {
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let times = Percentiles::new((1..=100).rev().map(|t| t as f32).collect());
+        assert_eq!(50.5, times.mean);
+        assert_eq!(51.0, times.p50);
+        assert_eq!(95.0, times.p95);
+        assert_eq!(99.0, times.p99);
+        assert_eq!(100.0, times.max);
+        assert_eq!(Percentiles::default(), Percentiles::new(Vec::new()));
+    }
+
+    #[test]
+    fn test_samples_ring() {
+        let mut samples = Samples::new(3);
+        for v in 1..=5 {
+            samples.push(v as f32);
+        }
+        assert_eq!(5, samples.count);
+        let mut kept = samples.ring.clone();
+        kept.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(vec![3.0, 4.0, 5.0], kept);
+    }
+
+    #[test]
+    fn test_clients() {
+        let mut stats = SessionStats::new();
+        let frame = Duration::from_millis(500);
+        stats.record_clients(&[true, false], frame);
+        stats.record_clients(&[true, false], frame);
+        stats.record_disconnect(0);
+        let report = stats.report(BeamStoreStats::default());
+        assert_eq!(1, report.clients.len());
+        assert_eq!(
+            ClientHealth {
+                connected_seconds: 1.0,
+                disconnects: 1,
+            },
+            report.clients[&0]
+        );
+    }
+
+    #[test]
+    fn test_input_latency() {
+        let mut stats = SessionStats::new();
+        for ms in [10, 20, 30] {
+            stats.record_input_latency(Duration::from_millis(ms));
+        }
+        let report = stats.report(BeamStoreStats::default());
+        assert_eq!(20.0, report.input_latency_ms.p50);
+        assert_eq!(30.0, report.input_latency_ms.max);
+    }
+
+    #[test]
+    fn test_dropped() {
+        let mut stats = SessionStats::new();
+        let t0 = Instant::now();
+        assert_eq!(None, stats.record_dropped(DropQueue::Frames, 0, t0));
+        assert_eq!(Some(2), stats.record_dropped(DropQueue::Frames, 2, t0));
+        // Further drops are held back until the interval has passed.
+        assert_eq!(None, stats.record_dropped(DropQueue::Frames, 3, t0));
+        assert_eq!(
+            Some(1),
+            stats.record_dropped(DropQueue::ControlEvents, 1, t0)
+        );
+        assert_eq!(
+            Some(4),
+            stats.record_dropped(DropQueue::Frames, 1, t0 + DROP_WARNING_INTERVAL)
+        );
+        let report = stats.report(BeamStoreStats::default());
+        assert_eq!(
+            Dropped {
+                frames: 6,
+                control_events: 1,
+                channel_peaks: 0,
+            },
+            report.dropped
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! A one-shot effect that breaks a tunnel's arcs apart.
+//!
+//! When triggered, every arc splits into pieces separated by random gaps, and
+//! the pieces fly outward, quickly at first and slowing until the effect ends
+//! and the tunnel snaps back together.
+
+use rand::Rng;
+use std::time::Duration;
+use tunnels_lib::ArcSegment;
+
+use crate::{random::ShowRng, waveforms::random_value};
+
+/// Longest a shatter can last, in seconds.
+pub const MAX_DURATION: f64 = 4.0;
+/// Number of pieces each arc splits into.
+pub const PIECES: usize = 6;
+/// Fastest a piece flies outward, as a fraction of its radius.
+const MAX_DISTANCE: f64 = 0.6;
+/// The range of fractions of each piece's share of the arc left as a gap.
+const MIN_GAP: f64 = 0.2;
+const MAX_GAP: f64 = 0.7;
+
+#[derive(Clone, Debug)]
+pub struct Shatter {
+    elapsed: Duration,
+    duration: Duration,
+    /// Chooses the gaps and velocities for this shatter.  Drawn from the
+    /// show's generator on the first update.
+    seed: Option<u32>,
+}
+
+impl Shatter {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            duration,
+            seed: None,
+        }
+    }
+
+    /// Advance the effect, returning false once it has finished.
+    pub fn update_state(&mut self, delta_t: Duration, rng: &mut ShowRng) -> bool {
+        self.seed.get_or_insert_with(|| rng.gen());
+        self.elapsed += delta_t;
+        self.elapsed < self.duration
+    }
+
+    /// Split the arc drawn for the segment with the provided ID into pieces.
+    pub fn split(&self, arc: ArcSegment, seg_num: u8, arcs: &mut Vec<ArcSegment>) {
+        let progress = (self.elapsed.as_secs_f64() / self.duration.as_secs_f64()).min(1.0);
+        // Pieces fly out fastest at first and decelerate to a stop.
+        let travel = 1.0 - (1.0 - progress).powi(2);
+        let share = (arc.stop - arc.start) / PIECES as f64;
+        let seed = self.seed.unwrap_or_default();
+        for piece in 0..PIECES {
+            let i = (seg_num as usize * PIECES + piece) as i64;
+            // Uniform on [0, 1] for each piece.
+            let gap = (random_value(i, seed) + 1.0) / 2.0;
+            let speed = (random_value(i, seed.wrapping_add(1)) + 1.0) / 2.0;
+
+            let gap = share * (MIN_GAP + (MAX_GAP - MIN_GAP) * gap);
+            let start = arc.start + share * piece as f64 + gap / 2.0;
+            let scale = 1.0 + MAX_DISTANCE * speed * travel;
+            arcs.push(ArcSegment {
+                start,
+                stop: start + share - gap,
+                rad_x: arc.rad_x * scale,
+                rad_y: arc.rad_y * scale,
+                ..arc.clone()
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random::stream;
+
+    fn arc() -> ArcSegment {
+        ArcSegment {
+            level: 1.0,
+            thickness: 0.1,
+            hue: 0.0,
+            sat: 0.0,
+            val: 1.0,
+            x: 0.0,
+            y: 0.0,
+            rad_x: 0.5,
+            rad_y: 0.25,
+            start: 0.9,
+            stop: 1.1,
+            rot_angle: 0.0,
+            palette: false,
+        }
+    }
+
+    #[test]
+    fn test_shatter() {
+        let mut rng = stream(0, 0);
+        let mut shatter = Shatter::new(Duration::from_secs(1));
+        assert!(shatter.update_state(Duration::from_millis(500), &mut rng));
+
+        let mut arcs = Vec::new();
+        shatter.split(arc(), 3, &mut arcs);
+        assert_eq!(PIECES, arcs.len());
+        for pair in arcs.windows(2) {
+            assert!(pair[0].start < pair[0].stop);
+            assert!(pair[0].stop < pair[1].start);
+        }
+        assert!(arcs[0].start > 0.9);
+        assert!(arcs[PIECES - 1].stop < 1.1);
+        assert!(arcs.iter().all(|a| a.rad_x >= 0.5 && a.rad_y >= 0.25));
+        assert!(arcs.iter().any(|a| a.rad_x > 0.5));
+
+        assert!(!shatter.update_state(Duration::from_millis(500), &mut rng));
+    }
+}
@@ -1,67 +1,287 @@
-use log::{self, error, info};
+use chrono::Local;
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 use simple_error::bail;
 use std::{
+    collections::{BTreeMap, HashMap},
     error::Error,
-    fs::File,
-    io::BufWriter,
+    fs::{self, File},
+    io::{BufWriter, Write},
     path::{Path, PathBuf},
+    sync::mpsc::Receiver,
     time::{Duration, Instant},
 };
-use tunnels_lib::Timestamp;
+use tracing::{debug_span, error, info, warn};
+use tunnels_lib::{
+    archive::{ArchiveWriter, Record},
+    client_profile::ClientProfile,
+    number::UnipolarFloat,
+    thread_config::ThreadConfig,
+    RunFlag,
+};
 
 use crate::{
-    animation,
+    alert::{AlertKind, AlertQueue, AlertServer},
+    animation, audio,
+    audio::{AudioAnalyzer, AudioInput, LevelMeter},
+    autopilot,
+    beam_store::{BeamStore, BeamStoreAddr},
+    client_presence::ClientPresence,
+    client_registry::ClientRegistry,
     clock_bank::{self, ClockBank},
+    color_organ,
+    config::ShowConfig,
+    config_watch::ConfigWatcher,
+    control_journal::{journal_path, JournalWriter},
+    control_layout::{ControlLayout, LayoutServer},
+    control_queue::ControlQueue,
+    control_server::ControlServer,
     device::Device,
+    dmx::{start_dmx_service, DmxConfig},
+    dmx_merge::DmxMerge,
+    external::{ExternalFeed, ExternalSourceConfig},
+    flash_limit::{self, FlashLimitConfig, FlashLimiter, FlashOverride},
+    frame_check::{ControlHistory, FrameCheckConfig, FrameChecker},
+    frame_clock::FrameClock,
+    gamepad::start_gamepad_service,
+    grid_confirm::GridConfirm,
     master_ui,
     master_ui::MasterUI,
-    midi::{DeviceSpec, Manager},
-    midi_controls::Dispatcher,
+    midi::{DeviceSpec, Event, Manager},
+    midi_controls::{Dispatcher, MIXER_CHANNELS_PER_PAGE},
+    midi_file,
+    midi_file::MidiFilePlayer,
     mixer,
-    mixer::Mixer,
-    send::{start_render_service, Frame},
+    mixer::{ChannelIdx, ChannelMeters, Mixer, VideoChannel},
+    output_profile::{self, OutputProfiles},
+    palette::{self, PaletteConfig},
+    pixel_map::PixelMap,
+    recall_filter,
+    scheduler::Scheduler,
+    screensaver::Screensaver,
+    send::{bind_publisher, start_render_service, Frame, ReplayBufferConfig},
+    session_report::{DropQueue, SessionStats},
+    silence_gate::SilenceGate,
+    slot_server::{Slot, SlotListing, SlotServer},
+    stereo::StereoPair,
     test_mode::TestModeSetup,
     timesync::TimesyncServer,
-    tunnel,
+    trigger::start_trigger_service,
+    tunnel, video_out,
+    video_out::{IdlePolicy, VideoOutputs},
 };
 
 /// How often should we autosave the show?
 pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
 
+/// How often should we check for scheduled actions?
+const SCHEDULE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often may we alert that the show is falling behind?
+const OVERRUN_ALERT_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct Show {
     dispatcher: Dispatcher,
+    control_queue: ControlQueue,
+    /// When each control event handled since the last frame arrived.
+    awaiting_frame: Vec<Instant>,
     state: ShowState,
+    scheduler: Scheduler,
+    output_profiles: OutputProfiles,
+    video_outputs: VideoOutputs,
+    audio: Option<AudioInput>,
+    level_meter: LevelMeter,
+    audio_analyzer: AudioAnalyzer,
+    channel_meters: ChannelMeters,
+    silence_gate: Option<SilenceGate>,
+    screensaver: Option<Screensaver>,
+    last_schedule_poll: Option<Instant>,
     pub save_path: Option<PathBuf>,
     last_save: Option<Instant>,
+    /// If set, record every published snapshot to an archive at this path.
+    pub record_path: Option<PathBuf>,
+    /// If set, write a session report into this directory at shutdown.
+    pub report_dir: Option<PathBuf>,
+    /// If set, apply edits to the show config at this path while running.
+    pub config_path: Option<PathBuf>,
+    replay_buffer: Option<ReplayBufferConfig>,
+    frame_check: FrameCheckConfig,
+    flash_limit: FlashLimitConfig,
+    flash_override: FlashOverride,
+    control_history: ControlHistory,
+    show_thread: ThreadConfig,
+    render_thread: ThreadConfig,
+    interleave: bool,
+    arc_budget: Option<usize>,
+    stereo: Vec<StereoPair>,
+    palettes: Vec<PaletteConfig>,
+    external_sources: HashMap<ChannelIdx, ExternalFeed>,
+    diagnostic_layer: bool,
+    clients: BTreeMap<String, ClientProfile>,
+    show_id: Option<String>,
+    grid_confirm: GridConfirm,
+    autopilot_seed: Option<u64>,
+    /// DMX input as configured at startup.
+    dmx: Option<DmxConfig>,
+    dmx_merge: DmxMerge,
+    midi_file_player: MidiFilePlayer,
+    /// While recording, the control events of the performance.
+    journal: Option<JournalWriter<BufWriter<File>>>,
+    alerts: AlertQueue,
+    slot_listing: SlotListing,
+    /// Frames skipped since we last alerted about it.
+    overruns: u64,
+    last_overrun_alert: Option<Instant>,
+    session: SessionStats,
 }
 
 impl Show {
     /// Create a new show from the provided config.
-    pub fn new(midi_devices: Vec<DeviceSpec>) -> Result<Self, Box<dyn Error>> {
-        // Determine if we need to configure a double-wide mixer for APC20 wing.
-        let use_wing = midi_devices
+    pub fn new(midi_devices: Vec<DeviceSpec>, config: &ShowConfig) -> Result<Self, Box<dyn Error>> {
+        // Give every connected device a page of the mixer, so that a wing
+        // gets a double-wide mixer.
+        let n_pages = midi_devices
             .iter()
-            .any(|spec| spec.device == Device::AkaiApc20);
-
-        let n_pages = if use_wing { 2 } else { 1 };
+            .map(|spec| spec.device.capabilities().page() + 1)
+            .max()
+            .unwrap_or(1);
 
         // Initialize midi system.
         let mut midi_manager = Manager::new();
         for device_spec in midi_devices.into_iter() {
             midi_manager.add_device(device_spec)?;
         }
+        if let Some(port) = &config.midi_clock {
+            midi_manager.add_clock_input(port.clone())?;
+        }
+
+        // Contact-closure triggers share the midi input path.
+        if let Some(triggers) = &config.triggers {
+            triggers.validate(
+                n_pages * MIXER_CHANNELS_PER_PAGE,
+                n_pages * BeamStore::COLS_PER_PAGE,
+                config.midi_files.len(),
+            )?;
+            start_trigger_service(triggers.port, midi_manager.sender())?;
+        }
+
+        // So does DMX.
+        if let Some(dmx) = &config.dmx {
+            dmx.validate(n_pages * MIXER_CHANNELS_PER_PAGE)?;
+            let n_palette_slots = BeamStore::N_ROWS * n_pages * BeamStore::COLS_PER_PAGE;
+            start_dmx_service(dmx, n_palette_slots, midi_manager.sender())?;
+        }
+
+        // And MIDI file playback.
+        let midi_file_player = MidiFilePlayer::new(&config.midi_files, midi_manager.sender())?;
+
+        let silence_gate = config.silence_gate.as_ref().map(SilenceGate::new);
+        let audio = if config.audio.is_some() || silence_gate.is_some() {
+            let audio_config = config.audio.clone().unwrap_or_default();
+            match audio::list_input_devices(audio_config.host.as_deref()) {
+                Ok(devices) => info!("Available audio inputs: {}.", devices.join(", ")),
+                Err(e) => error!("Unable to list audio inputs: {}.", e),
+            }
+            Some(AudioInput::start(&audio_config)?)
+        } else {
+            None
+        };
+
+        if let Some(screensaver) = &config.screensaver {
+            screensaver.validate(n_pages * BeamStore::COLS_PER_PAGE)?;
+        }
+        for rule in &config.schedule {
+            rule.validate_slot(n_pages * BeamStore::COLS_PER_PAGE)?;
+        }
+        let screensaver = config.screensaver.as_ref().map(Screensaver::new);
+
+        let output_profiles = OutputProfiles::new(config.output_profiles());
+        let active_profile = output_profiles.active();
+        let mut video_outputs = VideoOutputs::new(
+            &active_profile.video_outputs,
+            &config.geometry_presets,
+            active_profile.routes(),
+        );
+        set_client_color_temperatures(&mut video_outputs, &config.clients);
+
+        let mut mixer = Mixer::new(n_pages);
+        mixer.set_arc_budget(config.arc_budget);
+        mixer.set_stereo(config.stereo.clone());
+        mixer.set_palettes(config.palettes.clone());
+
+        ExternalSourceConfig::validate_all(
+            &config.external_sources,
+            n_pages * MIXER_CHANNELS_PER_PAGE,
+        )?;
+        for profile in output_profiles.iter() {
+            if let Some(pixel_map) = &profile.pixel_map {
+                pixel_map.validate_channels(n_pages * MIXER_CHANNELS_PER_PAGE)?;
+            }
+        }
+        let mut external_sources = HashMap::new();
+        for source in &config.external_sources {
+            external_sources.insert(ChannelIdx(source.channel), ExternalFeed::start(source)?);
+        }
+        mixer.set_external_sources(external_sources.clone());
+
+        let mut ui = MasterUI::new(n_pages);
+        ui.set_grid_confirm(config.grid_confirm);
+        if let Some(seed) = config.autopilot_seed {
+            ui.set_autopilot_seed(seed);
+        }
 
         Ok(Self {
-            dispatcher: Dispatcher::new(midi_manager),
+            dispatcher: Dispatcher::new(midi_manager, config),
+            control_queue: ControlQueue::new(ControlQueue::CAPACITY),
+            awaiting_frame: Vec::new(),
             state: ShowState {
-                ui: MasterUI::new(n_pages),
-                mixer: Mixer::new(n_pages),
+                ui,
+                mixer,
                 clocks: ClockBank::new(),
+                video_geometry: Vec::new(),
+                output_profile: None,
             },
+            scheduler: Scheduler::new(config.schedule.clone()),
+            output_profiles,
+            video_outputs,
+            audio,
+            level_meter: LevelMeter::new(),
+            audio_analyzer: AudioAnalyzer::new(),
+            channel_meters: ChannelMeters::default(),
+            silence_gate,
+            screensaver,
+            last_schedule_poll: None,
             save_path: None,
             last_save: None,
+            record_path: None,
+            report_dir: None,
+            config_path: None,
+            replay_buffer: config.replay_buffer.clone(),
+            frame_check: config.frame_check.clone(),
+            flash_limit: config.flash_limit.clone(),
+            flash_override: FlashOverride::default(),
+            control_history: ControlHistory::new(),
+            show_thread: config.show_thread.clone(),
+            render_thread: config.render_thread.clone(),
+            arc_budget: config.arc_budget,
+            stereo: config.stereo.clone(),
+            palettes: config.palettes.clone(),
+            external_sources,
+            diagnostic_layer: config.diagnostic_layer,
+            interleave: config.interleave,
+            clients: config.clients.clone(),
+            show_id: config.show_id.clone(),
+            grid_confirm: config.grid_confirm,
+            autopilot_seed: config.autopilot_seed,
+            dmx: config.dmx.clone(),
+            dmx_merge: DmxMerge::new(config.dmx.as_ref()),
+            midi_file_player,
+            journal: None,
+            alerts: AlertQueue::new(),
+            slot_listing: SlotListing::new(),
+            overruns: 0,
+            last_overrun_alert: None,
+            session: SessionStats::new(),
         })
     }
 
@@ -69,8 +289,7 @@ impl Show {
     /// Return an error if the dimensions of the loaded data don't match the
     /// current show.
     pub fn load(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
-        let file = File::open(path)?;
-        let loaded_state = ShowState::deserialize(&mut Deserializer::new(file))?;
+        let loaded_state = ShowState::load(path)?;
         if loaded_state.mixer.channel_count() != self.state.mixer.channel_count() {
             bail!(
                 "Mixer size mismatch. Loaded: {}, show: {}.",
@@ -86,19 +305,48 @@ impl Show {
             );
         }
         self.state = loaded_state;
+        self.state.ui.set_grid_confirm(self.grid_confirm);
+        if let Some(seed) = self.autopilot_seed {
+            self.state.ui.set_autopilot_seed(seed);
+        }
+        self.state.mixer.set_arc_budget(self.arc_budget);
+        self.state.mixer.set_stereo(self.stereo.clone());
+        self.state.mixer.set_palettes(self.palettes.clone());
+        self.state
+            .mixer
+            .set_external_sources(self.external_sources.clone());
+        // Restore the saved output setup as one operation, profile first, as
+        // switching profiles keeps the selected geometry.
+        let mut restore = Vec::new();
+        if let Some(name) = &self.state.output_profile {
+            restore.extend(
+                self.output_profiles
+                    .restore(name)
+                    .map(ControlMessage::OutputProfile),
+            );
+        }
+        restore.extend(
+            self.video_outputs
+                .restore_geometry(&self.state.video_geometry)
+                .into_iter()
+                .map(ControlMessage::VideoOut),
+        );
+        self.handle_control_message(ControlMessage::Batch(restore));
         Ok(())
     }
 
     /// Save the show into the provided file.
-    fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
-        let mut file = File::create(path)?;
-        self.state
-            .serialize(&mut Serializer::new(BufWriter::new(&mut file)))?;
-        Ok(())
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.state.save(path)
     }
 
     /// If a save path is set and we're due to save, save the show.
+    /// Nothing changes while the screensaver is showing, except for the
+    /// screensaver itself, so there is nothing to save.
     fn autosave(&mut self) -> Result<(), Box<dyn Error>> {
+        if matches!(&self.screensaver, Some(s) if s.asleep()) {
+            return Ok(());
+        }
         if let Some(path) = &self.save_path {
             let now = Instant::now();
             let should_save = match self.last_save {
@@ -117,6 +365,11 @@ impl Show {
         Ok(())
     }
 
+    /// Accept input from any connected game controller.
+    pub fn start_gamepad_input(&self) -> Result<(), Box<dyn Error>> {
+        start_gamepad_service(self.dispatcher.manager.sender())
+    }
+
     /// Set up the show in a test mode, defined by the provided setup function.
     pub fn test_mode(&mut self, setup: TestModeSetup) {
         let channel_count = self.state.mixer.channels().count();
@@ -127,9 +380,13 @@ impl Show {
             .for_each(|(i, chan)| setup(channel_count, i, chan));
     }
 
-    /// Run the show in the current thread.
+    /// Run the show in the current thread, until interrupted.
     pub fn run(&mut self, update_interval: Duration) -> Result<(), Box<dyn Error>> {
         info!("Show is starting.");
+        let running = RunFlag::new();
+        let mut interrupt = running.clone();
+        ctrlc::set_handler(move || interrupt.stop())?;
+        self.session = SessionStats::new();
 
         // Emit initial UI state.
         self.state.ui.emit_state(
@@ -137,32 +394,143 @@ impl Show {
             &mut self.state.clocks,
             &mut self.dispatcher,
         );
+        self.video_outputs.emit_state(&mut self.dispatcher);
+        self.output_profiles.emit_state(&mut self.dispatcher);
+        self.flash_override.emit_state(&mut self.dispatcher);
 
-        let mut frame_number = 0;
         let mut ctx = zmq::Context::new();
         let start = Instant::now();
 
         let _timesync = TimesyncServer::start(&mut ctx, start)?;
-        let frame_sender = start_render_service(&mut ctx)?;
+        let mut client_presence = ClientPresence::start(&mut ctx, self.show_id.clone())?;
+        let _client_registry = if self.clients.is_empty() {
+            None
+        } else {
+            Some(ClientRegistry::start(
+                self.clients.clone(),
+                self.show_id.clone(),
+            )?)
+        };
+        let _layout =
+            LayoutServer::start(&mut ctx, &ControlLayout::for_show(self.state.ui.n_pages()))?;
+        let _alert_server = AlertServer::start(&mut ctx, self.alerts.clone())?;
+        self.slot_listing.publish(self.state.ui.slot_listing());
+        let (_slot_server, slot_edits) = SlotServer::start(&mut ctx, self.slot_listing.clone())?;
+        let _control_server = ControlServer::start(&mut ctx, self.dispatcher.manager.sender())?;
+        let mut archives: Vec<Box<dyn Record + Send>> = Vec::new();
+        if let Some(path) = &self.record_path {
+            info!("Recording snapshots to {}.", path.display());
+            archives.push(Box::new(ArchiveWriter::create(path)?));
+            let journal = journal_path(path);
+            info!("Recording control events to {}.", journal.display());
+            self.journal = Some(JournalWriter::create(&journal)?);
+        }
+        if let Some(replay_buffer) = &self.replay_buffer {
+            info!(
+                "Keeping the last {} minutes of output in {}.",
+                replay_buffer.minutes,
+                replay_buffer.dir.display()
+            );
+            archives.push(Box::new(replay_buffer.create()?));
+        }
+        let mut pixel_maps = Vec::new();
+        for profile in self.output_profiles.iter() {
+            pixel_maps.push(match &profile.pixel_map {
+                Some(config) => {
+                    info!(
+                        "Output profile {} drives {} LED fixtures and {} beam fixtures.",
+                        profile.name,
+                        config.fixtures.len(),
+                        config.beams.len()
+                    );
+                    Some(PixelMap::new(config)?)
+                }
+                None => None,
+            });
+        }
+        let checker = FrameChecker::new(self.frame_check.clone(), self.control_history.clone());
+        if self.flash_limit.enabled {
+            info!(
+                "Limiting each output to {} flashes a second.",
+                self.flash_limit.max_flashes
+            );
+        } else {
+            warn!("The flash limit is disabled.");
+        }
+        let (frame_sender, channel_peaks) = start_render_service(
+            bind_publisher(&mut ctx)?,
+            self.render_thread.clone(),
+            checker,
+            self.flash_limit
+                .enabled
+                .then(|| FlashLimiter::new(self.flash_limit.clone())),
+            archives,
+            pixel_maps,
+            self.diagnostic_layer,
+            self.interleave.then(|| update_interval),
+            self.show_id.clone(),
+        )?;
+        if let Err(e) = self.show_thread.apply_to_current() {
+            error!("Unable to configure show thread: {}", e);
+        }
 
-        let mut last_update = start;
-        let mut timestamp = Timestamp(0);
+        let mut config_watcher = self.config_path.clone().map(ConfigWatcher::new);
 
-        loop {
-            if Instant::now() - last_update > update_interval {
-                self.update_state(update_interval);
-                last_update += update_interval;
-                timestamp.step(update_interval);
+        let mut frame_clock = FrameClock::new(start, update_interval);
+
+        while running.should_run() {
+            let now = Instant::now();
+            if let Some(due) = frame_clock.poll(now) {
+                let _span = debug_span!("update", frame = due.number).entered();
+                let late = self.check_overrun(due.skipped);
+                self.update_idle_outputs(&mut client_presence, due.delta_t);
+                self.update_state(due.delta_t);
 
                 if let Err(_) = frame_sender.send(Frame {
-                    number: frame_number,
-                    timestamp: timestamp,
+                    number: due.number,
+                    timestamp: due.timestamp,
                     mixer: self.state.mixer.clone(),
                     clocks: self.state.clocks.clone(),
+                    video_outputs: self.video_outputs.clone(),
+                    output_profile: self.output_profiles.active_index(),
+                    audio_level: self.audio.as_ref().map(|_| self.level_meter.level()),
+                    flash_limit_override: self.flash_override.engaged(),
+                    listen: if self.diagnostic_layer {
+                        self.state.ui.listen_preview(&mut self.state.mixer)
+                    } else {
+                        None
+                    },
                 }) {
                     bail!("Render server hung up.  Aborting show.");
                 }
-                frame_number += 1;
+                let sent = Instant::now();
+                for at in self.awaiting_frame.drain(..) {
+                    self.session.record_input_latency(sent - at);
+                }
+                self.record_dropped(DropQueue::Frames, frame_sender.take_dropped());
+                self.session.record_frame(now.elapsed(), late);
+            }
+
+            // Only the most recently rendered frame is worth metering.
+            if let Some(peaks) = channel_peaks.try_recv() {
+                self.channel_meters.update(&peaks, &mut self.dispatcher);
+            }
+            self.record_dropped(DropQueue::ChannelPeaks, channel_peaks.take_dropped());
+
+            // Run any scheduled actions that have come due.
+            self.poll_scheduler();
+
+            self.apply_slot_edits(&slot_edits);
+
+            if let Some(watcher) = &mut config_watcher {
+                self.poll_config(watcher);
+            }
+
+            for name in self.dispatcher.manager.take_lost() {
+                self.alert(
+                    AlertKind::MidiDeviceLost,
+                    format!("MIDI device {} is not responding.", name),
+                );
             }
 
             // Consider autosaving the show.
@@ -170,37 +538,406 @@ impl Show {
                 error!("Autosave error: {}.", e);
             }
 
-            // Process a control event for a fraction of the time between now
-            // and when we need to update state again.
-            if let Some(time_to_next_update) =
-                (last_update + update_interval).checked_duration_since(Instant::now())
-            {
-                // Use 80% of the time remaining to potentially process a
-                // control event.
-                let timeout = time_to_next_update.mul_f64(0.8);
-                self.service_control_event(timeout);
+            // Handle control events until the next frame is due.
+            self.service_control_events(&frame_clock);
+        }
+
+        info!("Show is shutting down.");
+        self.wake_screensaver();
+        // Save the final state, so a restart picks up exactly where we left
+        // off rather than from the last autosave.
+        if let Some(path) = &self.save_path {
+            match self.save(path) {
+                Ok(()) => info!("Saved the show to {}.", path.display()),
+                Err(e) => error!("Error saving the show: {}.", e),
             }
         }
+        if let Some(dir) = &self.report_dir {
+            let path = self.session.report_path(dir);
+            self.session
+                .report(self.state.ui.beam_store_stats())
+                .write(&path)?;
+            info!("Wrote session report to {}.", path.display());
+        }
+        Ok(())
+    }
+
+    /// Tell the mixer which video channels are routed to outputs with no
+    /// client connected.
+    /// Also record client health for the session report.
+    fn update_idle_outputs(&mut self, client_presence: &mut ClientPresence, delta_t: Duration) {
+        let live = client_presence.poll();
+        self.session.record_clients(&live, delta_t);
+        let idle_outputs = live
+            .iter()
+            .enumerate()
+            .filter(|(_, live)| !**live)
+            .map(|(output, _)| {
+                (
+                    VideoChannel(self.video_outputs.source(output)),
+                    self.video_outputs.idle_policy(output),
+                )
+            })
+            .filter(|(_, policy)| *policy != IdlePolicy::Run)
+            .collect();
+        self.state.mixer.set_idle_outputs(idle_outputs);
+        for chan in client_presence.take_lost() {
+            self.session.record_disconnect(chan);
+            self.alert(
+                AlertKind::ClientLost,
+                format!("Video channel {} client stopped sending heartbeats.", chan),
+            );
+        }
+    }
+
+    /// If the show fell far enough behind to skip frames, alert the operator,
+    /// at most once every OVERRUN_ALERT_INTERVAL.
+    /// Return true if any frames were skipped.
+    fn check_overrun(&mut self, skipped: u64) -> bool {
+        if skipped == 0 {
+            return false;
+        }
+        self.overruns += skipped;
+        let now = Instant::now();
+        if matches!(self.last_overrun_alert, Some(t) if now - t < OVERRUN_ALERT_INTERVAL) {
+            return true;
+        }
+        self.alert(
+            AlertKind::FrameOverrun,
+            format!(
+                "Show update fell {} frames behind; {} frames skipped since the last alert.",
+                skipped, self.overruns
+            ),
+        );
+        self.overruns = 0;
+        self.last_overrun_alert = Some(now);
+        true
+    }
+
+    /// Raise an alert for the operator.
+    fn alert(&mut self, kind: AlertKind, message: String) {
+        self.alerts.push(kind, message);
+        self.session.record_alert();
+        self.state.ui.raise_alert(&mut self.dispatcher);
     }
 
     fn update_state(&mut self, delta_t: Duration) {
+        for (at, msg) in self.dispatcher.manager.take_realtime() {
+            self.state.clocks.receive_midi_clock(msg, at);
+        }
         self.state
             .clocks
             .update_state(delta_t, &mut self.dispatcher);
-        self.state.mixer.update_state(delta_t);
+        self.midi_file_player.update_state(&self.state.clocks);
+        if let Some(journal) = &mut self.journal {
+            journal.update_state(&self.state.clocks, Instant::now());
+        }
+        let mut gate = UnipolarFloat::ONE;
+        if let Some(audio) = &self.audio {
+            let peak = audio.take_peak();
+            let band_peaks = audio.take_band_peaks();
+            self.level_meter.update(delta_t, peak, &mut self.dispatcher);
+            self.state
+                .mixer
+                .color_organ()
+                .update_state(delta_t, band_peaks);
+            self.state
+                .clocks
+                .set_audio_levels(self.audio_analyzer.update(delta_t, peak, band_peaks));
+            if let Some(silence_gate) = &mut self.silence_gate {
+                gate = gate * silence_gate.update(delta_t, peak);
+            }
+        }
+        gate = gate * self.update_screensaver(delta_t);
+        self.state.mixer.set_gate(gate);
+        self.state.mixer.update_state(delta_t, &self.state.clocks);
+        self.flash_override
+            .update_state(Instant::now(), &mut self.dispatcher);
+        self.state.ui.update_state(
+            delta_t,
+            &mut self.state.mixer,
+            &self.state.clocks,
+            &mut self.dispatcher,
+        );
     }
 
-    fn service_control_event(&mut self, timeout: Duration) {
-        if let Some(msg) = self.dispatcher.receive(timeout) {
-            if let Some(control_message) = self.dispatcher.dispatch(msg.0, msg.1) {
-                self.state.ui.handle_control_message(
-                    control_message,
-                    &mut self.state.mixer,
-                    &mut self.state.clocks,
-                    &mut self.dispatcher,
-                )
+    /// Count time without control input, starting the screensaver once the
+    /// show has faded out for it.
+    /// Return the level the show should be scaled by.
+    fn update_screensaver(&mut self, delta_t: Duration) -> UnipolarFloat {
+        let screensaver = match &mut self.screensaver {
+            Some(screensaver) => screensaver,
+            None => return UnipolarFloat::ONE,
+        };
+        let level = screensaver.update(delta_t);
+        if !screensaver.due() {
+            return level;
+        }
+        let (row, col) = screensaver.slot();
+        let replaced = match self.state.ui.stored_look(row, col) {
+            Some(look) => {
+                info!("No control input for a while; starting the screensaver.");
+                let replaced = self.state.mixer.as_look();
+                self.handle_control_message(ControlMessage::MasterUI(
+                    master_ui::ControlMessage::RecallLook(look),
+                ));
+                Some(replaced)
+            }
+            None => {
+                warn!(
+                    "Beam store row {}, column {} holds no look; dimming the current look for the screensaver.",
+                    row, col
+                );
+                None
+            }
+        };
+        if let Some(screensaver) = &mut self.screensaver {
+            screensaver.sleep(replaced);
+        }
+        self.state.ui.emit_state(
+            &mut self.state.mixer,
+            &mut self.state.clocks,
+            &mut self.dispatcher,
+        );
+        level
+    }
+
+    /// Stop the screensaver if it is showing, putting back the look it
+    /// replaced.
+    fn wake_screensaver(&mut self) {
+        let replaced = match self.screensaver.as_mut().and_then(Screensaver::wake) {
+            Some(replaced) => replaced,
+            None => return,
+        };
+        info!("Control input arrived; stopping the screensaver.");
+        if let Some(look) = replaced {
+            self.handle_control_message(ControlMessage::MasterUI(
+                master_ui::ControlMessage::RecallLook(look),
+            ));
+        }
+        self.state.ui.emit_state(
+            &mut self.state.mixer,
+            &mut self.state.clocks,
+            &mut self.dispatcher,
+        );
+    }
+
+    /// Queue every pending control event, waiting for one until the next
+    /// frame is due if there are none, then handle queued events until then.
+    fn service_control_events(&mut self, frame_clock: &FrameClock) {
+        if self.control_queue.is_empty() {
+            let timeout = frame_clock.until_deadline(Instant::now());
+            if timeout.is_zero() {
+                return;
+            }
+            match self.dispatcher.receive(timeout) {
+                Some(msg) => self.queue_control_event(msg),
+                None => return,
+            }
+        }
+        while let Some(msg) = self.dispatcher.try_receive() {
+            self.queue_control_event(msg);
+        }
+        let dropped = self.control_queue.take_dropped() + self.dispatcher.manager.take_dropped();
+        self.record_dropped(DropQueue::ControlEvents, dropped);
+        while Instant::now() < frame_clock.deadline() {
+            match self.control_queue.pop() {
+                Some(msg) => self.handle_control_event(msg),
+                None => break,
+            }
+        }
+    }
+
+    /// Count items dropped by a queue between threads for the session report,
+    /// and warn about them now and then.
+    fn record_dropped(&mut self, queue: DropQueue, dropped: usize) {
+        if let Some(dropped) = self.session.record_dropped(queue, dropped, Instant::now()) {
+            warn!("{}: {} recently.", queue, dropped);
+        }
+    }
+
+    /// Apply any beam store slot edits requested by front ends.
+    fn apply_slot_edits(&mut self, edits: &Receiver<Slot>) {
+        let mut edited = false;
+        for Slot { row, col, meta } in edits.try_iter() {
+            self.handle_control_message(ControlMessage::MasterUI(
+                master_ui::ControlMessage::EditSlot(BeamStoreAddr { row, col }, meta),
+            ));
+            edited = true;
+        }
+        if edited {
+            self.slot_listing.publish(self.state.ui.slot_listing());
+        }
+    }
+
+    fn queue_control_event(&mut self, (at, device, event): (Instant, Device, Event)) {
+        let collapsible = self.dispatcher.collapsible(device, &event);
+        self.control_queue.push(at, device, event, collapsible);
+    }
+
+    fn handle_control_event(&mut self, (at, device, event): (Instant, Device, Event)) {
+        self.session.record_control_event(device);
+        self.wake_screensaver();
+        self.control_history.record(device, event);
+        if let Some(control_message) = self.dispatcher.dispatch(device, event) {
+            // Only journal events that the active mapping acts on.
+            if let Some(journal) = &mut self.journal {
+                if let Err(e) = journal.write(at, device, event) {
+                    error!("Unable to journal control event: {}.", e);
+                }
+            }
+            self.awaiting_frame.push(at);
+            let control_message = self
+                .dmx_merge
+                .merge(device, control_message, &self.state.mixer);
+            self.handle_control_message(control_message);
+        }
+    }
+
+    fn handle_control_message(&mut self, msg: ControlMessage) {
+        match msg {
+            ControlMessage::VideoOut(vm) => {
+                self.video_outputs.control(vm, &mut self.dispatcher);
+                self.state.video_geometry = self.video_outputs.selected_geometry();
+            }
+            ControlMessage::OutputProfile(pm) => {
+                if self.output_profiles.control(pm, &mut self.dispatcher) {
+                    self.apply_output_profile();
+                }
+            }
+            ControlMessage::MidiFile(fm) => self.midi_file_player.control(fm),
+            ControlMessage::FlashLimit(fm) => {
+                if self
+                    .flash_override
+                    .control(fm, Instant::now(), &mut self.dispatcher)
+                    && self.flash_limit.enabled
+                {
+                    self.alert(
+                        AlertKind::FlashLimitOverride,
+                        "Flash limit overridden; the show may now flash at any rate.".to_string(),
+                    );
+                }
+            }
+            ControlMessage::Panic => self.panic(),
+            ControlMessage::Batch(msgs) => {
+                for msg in msgs {
+                    self.handle_control_message(msg);
+                }
+            }
+            msg => self.state.ui.handle_control_message(
+                msg,
+                &mut self.state.mixer,
+                &mut self.state.clocks,
+                &mut self.dispatcher,
+            ),
+        }
+    }
+
+    /// Recover from a tangled state mid-set: restore the grand master,
+    /// release blackout and bumps, disarm every armed mode, cancel everything
+    /// waiting for a beat, stop MIDI file playback, and release the flash
+    /// limit override.  Then resend the entire state, in case any controller
+    /// LEDs have fallen out of sync.
+    /// Beams, levels, and clocks are left alone.
+    fn panic(&mut self) {
+        warn!("Panic: restoring global state.");
+        self.state.mixer.panic();
+        self.state.ui.panic();
+        self.midi_file_player
+            .control(midi_file::ControlMessage::Stop);
+        self.flash_override.release(&mut self.dispatcher);
+        self.state.ui.emit_state(
+            &mut self.state.mixer,
+            &mut self.state.clocks,
+            &mut self.dispatcher,
+        );
+        self.video_outputs.emit_state(&mut self.dispatcher);
+        self.output_profiles.emit_state(&mut self.dispatcher);
+    }
+
+    /// Reconfigure the video outputs for the active output profile, keeping
+    /// the geometry preset selected for each output.
+    fn apply_output_profile(&mut self) {
+        let profile = self.output_profiles.active();
+        self.video_outputs
+            .reconfigure(&profile.video_outputs, profile.routes());
+        set_client_color_temperatures(&mut self.video_outputs, &self.clients);
+        self.state.output_profile = Some(profile.name.clone());
+    }
+
+    /// Apply any edits made to the show config file.
+    fn poll_config(&mut self, watcher: &mut ConfigWatcher) {
+        let result = match watcher.poll(Instant::now()) {
+            Some(result) => result.and_then(|config| self.reload_config(config)),
+            None => return,
+        };
+        match result {
+            Ok(()) => info!("Applied edited show config."),
+            Err(e) => self.alert(
+                AlertKind::ConfigRejected,
+                format!("Edited show config was not applied: {}", e),
+            ),
+        }
+    }
+
+    /// Apply the parts of an edited config that can change while the show is
+    /// running, if it fits the show.  See config_watch for which parts.
+    fn reload_config(&mut self, mut config: ShowConfig) -> Result<(), Box<dyn Error>> {
+        let n_pages = self.state.ui.n_pages();
+        if let Some(triggers) = &config.triggers {
+            triggers.validate(
+                n_pages * MIXER_CHANNELS_PER_PAGE,
+                n_pages * BeamStore::COLS_PER_PAGE,
+                self.midi_file_player.file_count(),
+            )?;
+        }
+        for rule in &config.schedule {
+            rule.validate_slot(n_pages * BeamStore::COLS_PER_PAGE)?;
+        }
+        // The DMX input service keeps the mapping it was started with.
+        config.dmx = self.dmx.clone();
+        self.dispatcher.configure(&config);
+        self.scheduler.set_rules(config.schedule);
+        // Changing the mode forgets any press awaiting confirmation.
+        if config.grid_confirm != self.grid_confirm {
+            self.grid_confirm = config.grid_confirm;
+            self.state.ui.set_grid_confirm(self.grid_confirm);
+        }
+        self.arc_budget = config.arc_budget;
+        self.state.mixer.set_arc_budget(self.arc_budget);
+        self.stereo = config.stereo;
+        self.state.mixer.set_stereo(self.stereo.clone());
+        self.palettes = config.palettes;
+        self.state.mixer.set_palettes(self.palettes.clone());
+        self.state.mixer.palettes().emit_state(&mut self.dispatcher);
+        Ok(())
+    }
+
+    /// If we're due to, check the scheduler and handle any actions it fires.
+    fn poll_scheduler(&mut self) {
+        let now = Instant::now();
+        if let Some(t) = self.last_schedule_poll {
+            if t + SCHEDULE_POLL_INTERVAL > now {
+                return;
             }
         }
+        self.last_schedule_poll = Some(now);
+        if let Some(msg) = self.scheduler.poll(Local::now().naive_local()) {
+            self.handle_control_message(msg);
+        }
+    }
+}
+
+/// Bias the whites of each video output shown by a client toward the color
+/// temperature in its client profile, if any.
+fn set_client_color_temperatures(
+    video_outputs: &mut VideoOutputs,
+    clients: &BTreeMap<String, ClientProfile>,
+) {
+    for profile in clients.values() {
+        if let Some(kelvin) = profile.color_temperature {
+            video_outputs.set_color_temperature(profile.video_channel as usize, kelvin);
+        }
     }
 }
 
@@ -210,6 +947,20 @@ pub enum ControlMessage {
     Mixer(mixer::ControlMessage),
     Clock(clock_bank::ControlMessage),
     MasterUI(master_ui::ControlMessage),
+    Autopilot(autopilot::ControlMessage),
+    ColorOrgan(color_organ::ControlMessage),
+    Palette(palette::ControlMessage),
+    VideoOut(video_out::ControlMessage),
+    OutputProfile(output_profile::ControlMessage),
+    RecallFilter(recall_filter::ControlMessage),
+    MidiFile(midi_file::ControlMessage),
+    FlashLimit(flash_limit::ControlMessage),
+    /// Restore sane global state in one action.
+    Panic,
+    /// Apply several messages in order, as one operation.
+    /// Control messages are only handled between frames, so no frame is ever
+    /// drawn with a batch partly applied.
+    Batch(Vec<ControlMessage>),
 }
 
 pub enum StateChange {
@@ -218,23 +969,105 @@ pub enum StateChange {
     Mixer(mixer::StateChange),
     Clock(clock_bank::StateChange),
     MasterUI(master_ui::StateChange),
+    Autopilot(autopilot::StateChange),
+    ColorOrgan(color_organ::StateChange),
+    Palette(palette::StateChange),
+    VideoOut(video_out::StateChange),
+    OutputProfile(output_profile::StateChange),
+    RecallFilter(recall_filter::StateChange),
+    Audio(audio::StateChange),
+    FlashLimit(flash_limit::StateChange),
 }
 
+/// Saved shows start with this tag, followed by the version of the format they
+/// were saved in.  Shows saved before the format was versioned have no tag,
+/// and are read as version 0.
+const SAVE_TAG: &[u8; 8] = b"tunnels\0";
+
 /// Proxy type for easily saving and loading show state.
 #[derive(Serialize, Deserialize)]
 pub struct ShowState {
     pub ui: MasterUI,
     pub mixer: Mixer,
     pub clocks: ClockBank,
+    /// The name of the geometry preset selected for each video channel.
+    #[serde(default)]
+    pub video_geometry: Vec<Option<String>>,
+    /// The name of the active output profile, if one has been selected.
+    #[serde(default)]
+    pub output_profile: Option<String>,
+}
+
+impl ShowState {
+    /// The version of the saved show format written by this build.
+    /// Bump it when the show state changes in a way older builds can't read,
+    /// so that they refuse to load the show instead of misreading it.
+    /// Fields are saved by position, so new saved fields go after the
+    /// existing ones with `#[serde(default)]` so that older shows still load.
+    pub const VERSION: u32 = 1;
+
+    /// Load a saved show.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Save the show.  The new save only replaces the old one once it has
+    /// been written in full, so a crash part way through can't lose the show.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let partial = path.with_extension("saving");
+        let mut file = BufWriter::new(File::create(&partial)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        drop(file);
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    fn write<W: Write>(&self, mut w: W) -> Result<(), Box<dyn Error>> {
+        w.write_all(SAVE_TAG)?;
+        w.write_all(&Self::VERSION.to_le_bytes())?;
+        self.serialize(&mut Serializer::new(w))?;
+        Ok(())
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let (version, body) = match bytes.strip_prefix(&SAVE_TAG[..]) {
+            Some(rest) if rest.len() >= 4 => {
+                let mut version = [0; 4];
+                version.copy_from_slice(&rest[..4]);
+                (u32::from_le_bytes(version), &rest[4..])
+            }
+            Some(_) => bail!("Saved show is truncated."),
+            None => (0, bytes),
+        };
+        if version > Self::VERSION {
+            bail!(
+                "Show was saved in format version {}, but this version of tunnels only reads up to {}.",
+                version,
+                Self::VERSION
+            );
+        }
+        Ok(Self::deserialize(&mut Deserializer::new(body))?)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::test_mode::stress;
+    use crate::{
+        beam::Beam,
+        master_ui::DummyEmitter,
+        midi::{cc, event},
+        test_mode::stress,
+    };
     use std::{
         collections::hash_map::DefaultHasher,
         hash::{Hash, Hasher},
+        io::Cursor,
+    };
+    use tunnels_lib::{
+        almost_eq, chunk::Reassembler, number::UnipolarFloat, show_id::frame_topic, Snapshot,
+        Timestamp,
     };
 
     fn calculate_hash<T: Hash>(t: &T) -> u64 {
@@ -248,7 +1081,7 @@ mod test {
     /// tunnel state or rendering algorithm.
     #[test]
     fn test_render() -> Result<(), Box<dyn Error>> {
-        let mut show = Show::new(Vec::new())?;
+        let mut show = Show::new(Vec::new(), &ShowConfig::default())?;
 
         show.test_mode(stress);
 
@@ -274,6 +1107,177 @@ mod test {
         Ok(())
     }
 
+    /// Drive a show with scripted control events, render a frame of it
+    /// through the render service, and receive it the way the client does.
+    /// This guards the contract between the server and the client.
+    #[test]
+    fn test_pipeline() -> Result<(), Box<dyn Error>> {
+        let mut show = Show::new(Vec::new(), &ShowConfig::default())?;
+        for (mapping, value) in [
+            // Bring up the first channel's fader.
+            (cc(0, 0x7), 127),
+            // Draw the current tunnel as thin, full-size arcs.
+            (cc(0, 21), 0),
+            (cc(0, 22), 127),
+        ] {
+            show.handle_control_event((Instant::now(), Device::AkaiApc40, event(mapping, value)));
+        }
+        show.update_state(Duration::from_micros(16667));
+
+        let ctx = zmq::Context::new();
+        let publisher = ctx.socket(zmq::PUB)?;
+        publisher.bind("inproc://pipeline")?;
+        let subscriber = ctx.socket(zmq::SUB)?;
+        subscriber.connect("inproc://pipeline")?;
+        subscriber.set_subscribe(&frame_topic(None, 0))?;
+        subscriber.set_rcvtimeo(100)?;
+
+        let (frame_sender, _) = start_render_service(
+            publisher,
+            ThreadConfig::default(),
+            FrameChecker::new(FrameCheckConfig::default(), ControlHistory::new()),
+            None,
+            Vec::new(),
+            Vec::new(),
+            false,
+            None,
+            None,
+        )?;
+
+        // Subscriptions take a moment to reach the publisher, so keep
+        // rendering frames until one gets through.
+        let mut reassembler = Reassembler::new();
+        let mut received = None;
+        for number in 1..=50 {
+            let frame = Frame {
+                number,
+                timestamp: Timestamp::from_micros(16667 * number as i64),
+                mixer: show.state.mixer.clone(),
+                clocks: show.state.clocks.clone(),
+                video_outputs: show.video_outputs.clone(),
+                output_profile: show.output_profiles.active_index(),
+                audio_level: None,
+                flash_limit_override: false,
+                listen: None,
+            };
+            assert!(frame_sender.send(frame).is_ok(), "render service hung up");
+            if let Ok(parts) = subscriber.recv_multipart(0) {
+                received = reassembler.receive(parts)?;
+                if received.is_some() {
+                    break;
+                }
+            }
+        }
+        drop(frame_sender);
+        let msg = received.expect("no frame received");
+        let snapshot = Snapshot::deserialize(&mut Deserializer::new(Cursor::new(&msg[..])))?;
+
+        assert_eq!(
+            Timestamp::from_micros(16667 * snapshot.frame_number as i64),
+            snapshot.time
+        );
+        assert_eq!(None, snapshot.palette);
+        assert_eq!(None, snapshot.color);
+
+        // The scripted controls produced the expected geometry.
+        assert_eq!(1, snapshot.layers.len());
+        let arcs = &snapshot.layers[0];
+        assert!(!arcs.is_empty());
+        for arc in arcs.iter() {
+            assert!(almost_eq(1.0, arc.level), "{:?}", arc);
+            assert!(almost_eq(0.0, arc.thickness), "{:?}", arc);
+            assert!(almost_eq(1.0, arc.rad_y), "{:?}", arc);
+        }
+        Ok(())
+    }
+
+    /// Saving and loading a show mid-motion should resume exactly where it
+    /// left off, rather than snapping phases and angles back to zero.
+    #[test]
+    fn test_save_load_resumes_motion() -> Result<(), Box<dyn Error>> {
+        let mut show = Show::new(Vec::new(), &ShowConfig::default())?;
+        show.test_mode(|channel_count, i, channel| {
+            stress(channel_count, i, channel);
+            if let Beam::Tunnel(tunnel) = &mut channel.beam {
+                tunnel.control(
+                    tunnel::ControlMessage::Set(tunnel::StateChange::WobbleAmount(
+                        UnipolarFloat::ONE,
+                    )),
+                    &mut DummyEmitter,
+                );
+            }
+        });
+        let timestep = Duration::from_micros(16667);
+        for _ in 0..100 {
+            show.update_state(timestep);
+        }
+
+        let mut saved = Vec::new();
+        show.state.serialize(&mut Serializer::new(&mut saved))?;
+        let mut loaded = Show::new(Vec::new(), &ShowConfig::default())?;
+        loaded.state = ShowState::deserialize(&mut Deserializer::new(Cursor::new(&saved[..])))?;
+
+        for _ in 0..10 {
+            assert_eq!(
+                show.state.mixer.render(&show.state.clocks),
+                loaded.state.mixer.render(&loaded.state.clocks)
+            );
+            show.update_state(timestep);
+            loaded.update_state(timestep);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_format() -> Result<(), Box<dyn Error>> {
+        let show = Show::new(Vec::new(), &ShowConfig::default())?;
+        let mut saved = Vec::new();
+        show.state.write(&mut saved)?;
+        assert!(saved.starts_with(SAVE_TAG));
+        let loaded = ShowState::from_bytes(&saved)?;
+        assert_eq!(
+            show.state.mixer.render(&show.state.clocks),
+            loaded.mixer.render(&loaded.clocks)
+        );
+
+        // Shows saved by a newer version.
+        saved[SAVE_TAG.len()..SAVE_TAG.len() + 4]
+            .copy_from_slice(&(ShowState::VERSION + 1).to_le_bytes());
+        assert!(ShowState::from_bytes(&saved).is_err());
+        assert!(ShowState::from_bytes(&saved[..SAVE_TAG.len() + 2]).is_err());
+        Ok(())
+    }
+
+    /// A show in the format written before saves were versioned: untagged,
+    /// with only the fields the show state had at the time.  Channel 0 draws a
+    /// thin tunnel of size 0.8 at full level to video channels 0 and 3,
+    /// channel 1 is a half-level mask, clock 0 has its submaster at half, and
+    /// the first beam store slot holds a look of the mixer.
+    const BASELINE_SHOW: &[u8] = include_bytes!("../test_data/baseline.show");
+
+    #[test]
+    fn test_load_baseline_show() -> Result<(), Box<dyn Error>> {
+        let state = ShowState::from_bytes(BASELINE_SHOW)?;
+        assert_eq!(8, state.mixer.channel_count());
+        assert!(almost_eq(
+            0.5,
+            state.clocks.submaster_level(clock_bank::ClockIdx(0)).val()
+        ));
+        assert!(state.video_geometry.is_empty());
+        assert_eq!(None, state.output_profile);
+
+        let video_feeds = state.mixer.render(&state.clocks);
+        assert_eq!(1, video_feeds[3].len());
+        let arcs = &video_feeds[3][0];
+        assert!(!arcs.is_empty());
+        for arc in arcs.iter() {
+            assert!(almost_eq(1.0, arc.level), "{:?}", arc);
+            assert!(almost_eq(0.0, arc.thickness), "{:?}", arc);
+            assert!(almost_eq(0.8, arc.rad_y), "{:?}", arc);
+        }
+        Ok(())
+    }
+
     /// Render the state of the show, hash the layers, and compare to expectation.
     fn check_render(show: &Show, beam_hashes: Vec<u64>) {
         let video_feeds = show.state.mixer.render(&show.state.clocks);
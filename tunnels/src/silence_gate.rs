@@ -0,0 +1,170 @@
+//! Fade the show out when the room goes quiet, and back in when sound returns.
+//!
+//! Intended for installations that should only run while there is ambient
+//! sound.  The gate scales the output of the mixer without touching the
+//! grand master, so it never fights with the operator's own settings.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{error::Error, time::Duration};
+use tunnels_lib::number::UnipolarFloat;
+
+use crate::audio::db_to_amplitude;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SilenceGateConfig {
+    /// Audio peaking below this level, in dBFS, counts as silence.
+    pub silence_threshold_db: f64,
+    /// Audio must peak above this level, in dBFS, to count as sound again.
+    /// Defaults to the silence threshold.
+    #[serde(default)]
+    pub resume_threshold_db: Option<f64>,
+    /// Seconds of continuous silence before fading out.
+    pub hold: f64,
+    /// Seconds taken to fade out.
+    #[serde(default = "default_fade_out")]
+    pub fade_out: f64,
+    /// Seconds taken to fade back in once sound returns.
+    #[serde(default = "default_fade_in")]
+    pub fade_in: f64,
+}
+
+fn default_fade_out() -> f64 {
+    5.0
+}
+
+fn default_fade_in() -> f64 {
+    0.5
+}
+
+impl SilenceGateConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.resume_threshold_db() < self.silence_threshold_db {
+            bail!(
+                "Silence gate resume threshold {} dB is below the silence threshold {} dB.",
+                self.resume_threshold_db(),
+                self.silence_threshold_db
+            );
+        }
+        for (name, v) in &[
+            ("hold", self.hold),
+            ("fade_out", self.fade_out),
+            ("fade_in", self.fade_in),
+        ] {
+            if !v.is_finite() || *v < 0.0 {
+                bail!("Silence gate {} is {}; it must not be negative.", name, v);
+            }
+        }
+        Ok(())
+    }
+
+    fn resume_threshold_db(&self) -> f64 {
+        self.resume_threshold_db
+            .unwrap_or(self.silence_threshold_db)
+    }
+}
+
+pub struct SilenceGate {
+    silence_threshold: f64,
+    resume_threshold: f64,
+    hold: Duration,
+    fade_out: Duration,
+    fade_in: Duration,
+    /// How long it has been quiet, if it is quiet.
+    silent_for: Option<Duration>,
+    level: UnipolarFloat,
+}
+
+impl SilenceGate {
+    pub fn new(cfg: &SilenceGateConfig) -> Self {
+        Self {
+            silence_threshold: db_to_amplitude(cfg.silence_threshold_db),
+            resume_threshold: db_to_amplitude(cfg.resume_threshold_db()),
+            hold: Duration::from_secs_f64(cfg.hold),
+            fade_out: Duration::from_secs_f64(cfg.fade_out),
+            fade_in: Duration::from_secs_f64(cfg.fade_in),
+            silent_for: None,
+            // Start open, so the show comes up normally even in a quiet room.
+            level: UnipolarFloat::ONE,
+        }
+    }
+
+    /// Update the gate with the peak audio level seen since the last update,
+    /// and return the level the show should be scaled by.
+    pub fn update(&mut self, delta_t: Duration, peak: f64) -> UnipolarFloat {
+        self.silent_for = match self.silent_for {
+            Some(_) if peak >= self.resume_threshold => None,
+            Some(t) => Some(t + delta_t),
+            None if peak < self.silence_threshold => Some(delta_t),
+            None => None,
+        };
+        let closing = matches!(self.silent_for, Some(t) if t > self.hold);
+        let (target, fade) = if closing {
+            (0.0, self.fade_out)
+        } else {
+            (1.0, self.fade_in)
+        };
+        let step = if fade.as_secs_f64() == 0.0 {
+            1.0
+        } else {
+            delta_t.as_secs_f64() / fade.as_secs_f64()
+        };
+        let current = self.level.val();
+        self.level = UnipolarFloat::new(if target > current {
+            (current + step).min(target)
+        } else {
+            (current - step).max(target)
+        });
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LOUD: f64 = 0.5;
+    const QUIET: f64 = 0.0001;
+
+    fn gate() -> SilenceGate {
+        SilenceGate::new(&SilenceGateConfig {
+            silence_threshold_db: -60.0,
+            resume_threshold_db: Some(-50.0),
+            hold: 2.0,
+            fade_out: 1.0,
+            fade_in: 0.5,
+        })
+    }
+
+    /// Run the gate for a number of seconds in 0.1 s steps at a constant peak.
+    fn run(gate: &mut SilenceGate, seconds: f64, peak: f64) -> f64 {
+        let mut level = gate.level;
+        for _ in 0..(seconds * 10.0).round() as usize {
+            level = gate.update(Duration::from_millis(100), peak);
+        }
+        level.val()
+    }
+
+    #[test]
+    fn test_fade_after_hold() {
+        let mut g = gate();
+        assert_eq!(1.0, run(&mut g, 1.0, LOUD));
+        // Still open until the hold time has elapsed.
+        assert_eq!(1.0, run(&mut g, 2.0, QUIET));
+        assert!(run(&mut g, 0.5, QUIET) < 1.0);
+        assert_eq!(0.0, run(&mut g, 1.0, QUIET));
+        // Fade back in when sound returns.
+        assert!(run(&mut g, 0.2, LOUD) > 0.0);
+        assert_eq!(1.0, run(&mut g, 0.5, LOUD));
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut g = gate();
+        assert_eq!(0.0, run(&mut g, 4.0, QUIET));
+        // Above the silence threshold but below the resume threshold.
+        assert_eq!(0.0, run(&mut g, 1.0, db_to_amplitude(-55.0)));
+    }
+}
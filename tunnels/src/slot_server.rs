@@ -0,0 +1,147 @@
+//! Edit beam store slot metadata from front ends.
+//!
+//! Slots may be given a name and a display color, and locked so that a hero
+//! look can't be saved over or deleted by accident.  Front ends talk to the
+//! slot server over a zmq REP socket: each request is a msgpack SlotRequest,
+//! and each reply is the metadata of every slot that has any, as msgpack with
+//! structs encoded as maps.  Edits are handed to the show to apply between
+//! frames, so the reply to an edit may not include it yet; list again to see
+//! the result.  An edit replaces all of the slot's metadata.
+
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    thread,
+};
+use tracing::{error, info, warn};
+use tunnels_lib::{ports, queue::bounded, RunFlag};
+use zmq::Context;
+
+use crate::beam_store::SlotMeta;
+
+const PORT: u16 = ports::SLOTS;
+
+/// Most edits to hold for the show at once.
+const EDIT_CAPACITY: usize = 64;
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub enum SlotRequest {
+    List,
+    Edit(Slot),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub row: usize,
+    pub col: usize,
+    pub meta: SlotMeta,
+}
+
+/// The slot metadata most recently published by the show, shared with the
+/// slot server.
+#[derive(Clone, Default)]
+pub struct SlotListing(Arc<Mutex<Vec<Slot>>>);
+
+impl SlotListing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn publish(&self, slots: Vec<Slot>) {
+        if let Ok(mut listing) = self.0.lock() {
+            *listing = slots;
+        }
+    }
+
+    fn get(&self) -> Vec<Slot> {
+        match self.0.lock() {
+            Ok(listing) => listing.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+pub struct SlotServer {
+    join_handle: Option<thread::JoinHandle<()>>,
+    run: RunFlag,
+}
+
+impl SlotServer {
+    /// Start serving the provided listing.
+    /// Return the server, and a receiver for edits requested by front ends.
+    /// The server will run until it is dropped.
+    pub fn start(
+        ctx: &mut Context,
+        listing: SlotListing,
+    ) -> Result<(Self, Receiver<Slot>), Box<dyn Error>> {
+        let socket = ctx.socket(zmq::REP)?;
+        socket.bind(&format!("tcp://*:{}", PORT))?;
+        // time out once per second
+        socket.set_rcvtimeo(1000)?;
+        let run = RunFlag::new();
+        let run_local = run.clone();
+        // Edits are applied once per frame; a front end flooding the server
+        // with more than this between frames loses the excess.
+        let (send, recv) = bounded(EDIT_CAPACITY);
+
+        let jh = thread::Builder::new()
+            .name("slot_server".to_string())
+            .spawn(move || {
+                let mut resp = Vec::new();
+                loop {
+                    if !run.should_run() {
+                        return;
+                    }
+                    let msg = match socket.recv_bytes(0) {
+                        Err(zmq::Error::EAGAIN) => continue,
+                        Err(e) => {
+                            error!("Slot server receive error: {}.", e);
+                            continue;
+                        }
+                        Ok(msg) => msg,
+                    };
+                    match SlotRequest::deserialize(&mut Deserializer::new(&msg[..])) {
+                        Ok(SlotRequest::List) => (),
+                        Ok(SlotRequest::Edit(slot)) => {
+                            if send.send(slot).is_err() {
+                                // The show has shut down.
+                                return;
+                            }
+                            let dropped = send.take_dropped();
+                            if dropped > 0 {
+                                warn!("Slot edits are arriving too fast; dropped {}.", dropped);
+                            }
+                        }
+                        Err(e) => warn!("Malformed slot request: {}.", e),
+                    }
+                    resp.clear();
+                    if let Err(e) = listing
+                        .get()
+                        .serialize(&mut Serializer::new(&mut resp).with_struct_map())
+                    {
+                        error!("Slot serialization error: {}.", e);
+                    }
+                    if let Err(e) = socket.send(&resp, 0) {
+                        error!("Slot server send error: {}.", e);
+                    }
+                }
+            })?;
+        info!("Slot server started.");
+        Ok((
+            Self {
+                join_handle: Some(jh),
+                run: run_local,
+            },
+            recv,
+        ))
+    }
+}
+
+impl Drop for SlotServer {
+    fn drop(&mut self) {
+        self.run.stop();
+        self.join_handle.take().unwrap().join().unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Stereoscopic output for anaglyph and passive 3D rigs.
+//!
+//! A stereo pair is two video channels showing the same mix, one to each eye,
+//! usually through a pair of projectors fitted with polarizing or red/cyan
+//! filters.  Every mixer channel routed to the left eye's video channel is
+//! also drawn on the right eye's, offset horizontally in proportion to the
+//! channel's depth.  The right eye's video channel shows nothing else; mixer
+//! channels routed to it directly are ignored.
+//!
+//! Positive depth brings a layer out in front of the screen, negative depth
+//! pushes it behind.  At zero depth a layer sits on the screen and both eyes
+//! see it in the same place.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{collections::HashSet, error::Error};
+use tunnels_lib::{number::BipolarFloat, ArcSegment};
+
+use crate::mixer::{Mixer, VideoChannel};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StereoPair {
+    pub left: usize,
+    pub right: usize,
+    /// Horizontal distance between the eyes' images of a layer at full depth,
+    /// in the units of tunnel position.
+    #[serde(default = "default_separation")]
+    pub separation: f64,
+}
+
+fn default_separation() -> f64 {
+    0.05
+}
+
+impl StereoPair {
+    pub fn validate_all(pairs: &[Self]) -> Result<(), Box<dyn Error>> {
+        let mut used = HashSet::new();
+        for pair in pairs {
+            for channel in [pair.left, pair.right].iter() {
+                if *channel >= Mixer::N_VIDEO_CHANNELS {
+                    bail!(
+                        "Stereo video channel {} is out of range; there are {} video channels.",
+                        channel,
+                        Mixer::N_VIDEO_CHANNELS
+                    );
+                }
+                if !used.insert(*channel) {
+                    bail!(
+                        "Video channel {} is used by more than one stereo eye.",
+                        channel
+                    );
+                }
+            }
+            if !pair.separation.is_finite() || pair.separation < 0.0 {
+                bail!(
+                    "Stereo separation is {}; it must not be negative.",
+                    pair.separation
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return the video channels a layer routed to the provided video channels is
+/// drawn on, with the horizontal offset to draw it at on each.
+pub fn eye_offsets<'a>(
+    pairs: &[StereoPair],
+    video_outs: impl Iterator<Item = &'a VideoChannel>,
+    depth: BipolarFloat,
+) -> Vec<(VideoChannel, f64)> {
+    let mut targets = Vec::new();
+    for vc in video_outs {
+        if pairs.iter().any(|pair| pair.right == vc.0) {
+            continue;
+        }
+        match pairs.iter().find(|pair| pair.left == vc.0) {
+            Some(pair) => {
+                // Crossed disparity brings a layer forward: the left eye sees
+                // it further right, and the right eye further left.
+                let offset = depth.val() * pair.separation / 2.0;
+                targets.push((*vc, offset));
+                targets.push((VideoChannel(pair.right), -offset));
+            }
+            None => targets.push((*vc, 0.0)),
+        }
+    }
+    targets
+}
+
+/// Return a copy of a rendered layer moved horizontally.
+pub fn shift(arcs: &[ArcSegment], offset: f64) -> Vec<ArcSegment> {
+    arcs.iter()
+        .map(|arc| ArcSegment {
+            x: arc.x + offset,
+            ..arc.clone()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_eye_offsets() {
+        let pairs = vec![StereoPair {
+            left: 1,
+            right: 2,
+            separation: 0.1,
+        }];
+        let routed = [VideoChannel(0), VideoChannel(1), VideoChannel(2)];
+        let targets = eye_offsets(&pairs, routed.iter(), BipolarFloat::new(0.5));
+        assert_eq!(
+            vec![
+                (VideoChannel(0), 0.0),
+                (VideoChannel(1), 0.025),
+                (VideoChannel(2), -0.025)
+            ],
+            targets
+        );
+    }
+}
@@ -1,9 +1,8 @@
-use crate::master_ui::EmitStateChange;
+use crate::master_ui::DummyEmitter;
 use crate::{
     animation::{Animation, StateChange as AnimationStateChange, Target, Waveform},
     beam::Beam,
     mixer::{Channel, Mixer, VideoChannel},
-    show::StateChange,
     tunnel::{StateChange as TunnelStateChange, Tunnel},
 };
 use tunnels_lib::number::{BipolarFloat, UnipolarFloat};
@@ -70,12 +69,6 @@ pub fn stress(channel_count: usize, i: usize, channel: &mut Channel) {
     }
 }
 
-struct DummyEmitter;
-
-impl EmitStateChange for DummyEmitter {
-    fn emit(&mut self, _: StateChange) {}
-}
-
 fn set_tunnel_state(tunnel: &mut Tunnel, state: TunnelStateChange) {
     use crate::tunnel::ControlMessage;
     tunnel.control(ControlMessage::Set(state), &mut DummyEmitter);
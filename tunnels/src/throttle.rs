@@ -0,0 +1,113 @@
+//! Limit the data rate of the frames published on a video channel.
+//!
+//! A video output may be given a bandwidth budget, to protect a constrained
+//! link such as a long-range wireless bridge to a remote projector.  A frame
+//! is only sent once the channel has the budget for it, so a channel whose
+//! frames are too large for its link is sent at a reduced frame rate rather
+//! than overwhelming the link.  Unused budget carries over for a short window,
+//! so a channel whose frames only occasionally spike doesn't drop any.
+
+use std::time::{Duration, Instant};
+
+/// Longest that unused budget is saved for.
+const BURST_WINDOW: f64 = 0.1;
+
+/// How often to report on a throttled channel.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct Throttle {
+    /// Bytes that may be sent now.  Negative while paying off a frame that was
+    /// larger than the saved budget.
+    available: f64,
+    last_update: Option<Instant>,
+    report: Report,
+    report_start: Option<Instant>,
+}
+
+/// What a throttle did since its last report.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+    pub sent: u32,
+    pub dropped: u32,
+    /// Total size of the frames that were sent, in bytes.
+    pub sent_bytes: usize,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self {
+            available: 0.0,
+            last_update: None,
+            report: Report::default(),
+            report_start: None,
+        }
+    }
+
+    /// Return true if a frame of the provided size in bytes may be sent now,
+    /// given a budget in bytes per second.
+    pub fn admit(&mut self, now: Instant, size: usize, budget: f64) -> bool {
+        let elapsed = match self.last_update {
+            Some(last_update) => now.saturating_duration_since(last_update).as_secs_f64(),
+            // Start out with a full window of budget.
+            None => BURST_WINDOW,
+        };
+        self.last_update = Some(now);
+        self.report_start.get_or_insert(now);
+        self.available = (self.available + elapsed * budget).min(budget * BURST_WINDOW);
+        // Frames larger than the window are sent and paid off afterwards, so
+        // that they still go out at the budgeted rate.
+        if self.available < 0.0 {
+            self.report.dropped += 1;
+            return false;
+        }
+        self.available -= size as f64;
+        self.report.sent += 1;
+        self.report.sent_bytes += size;
+        true
+    }
+
+    /// Periodically return what the throttle did since the last report, if it
+    /// dropped any frames.
+    pub fn take_report(&mut self, now: Instant) -> Option<(Report, Duration)> {
+        let report_start = self.report_start?;
+        let elapsed = now.saturating_duration_since(report_start);
+        if elapsed < REPORT_INTERVAL {
+            return None;
+        }
+        self.report_start = Some(now);
+        let report = std::mem::take(&mut self.report);
+        if report.dropped == 0 {
+            return None;
+        }
+        Some((report, elapsed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let start = Instant::now();
+        let frame = Duration::from_micros(16667);
+        let mut throttle = Throttle::new();
+
+        // 600 byte frames at 60 fps under a 6000 B/s budget go out at 10 fps.
+        let sent = (0..600)
+            .filter(|i| throttle.admit(start + frame * *i, 600, 6000.0))
+            .count();
+        assert!((99..=101).contains(&sent), "{}", sent);
+
+        let (report, elapsed) = throttle.take_report(start + frame * 600).unwrap();
+        assert_eq!(sent as u32, report.sent);
+        assert_eq!(600 - sent as u32, report.dropped);
+        assert_eq!(sent * 600, report.sent_bytes);
+        assert_eq!(frame * 600, elapsed);
+
+        // Frames within the budget are never dropped.
+        let mut throttle = Throttle::new();
+        assert!((0..600).all(|i| throttle.admit(start + frame * i, 60, 6000.0)));
+        assert!(throttle.take_report(start + frame * 600).is_none());
+    }
+}
@@ -1,14 +1,14 @@
-use log::{error, info};
 use std::thread;
 use std::{error::Error, time::Instant};
+use tracing::{error, info};
 
 use rmp_serde::Serializer;
 use serde::Serialize;
-use tunnels_lib::{RunFlag, Timestamp};
+use tunnels_lib::{ports, RunFlag, Timestamp};
 use zmq;
 use zmq::Context;
 
-const PORT: u64 = 8989;
+const PORT: u16 = ports::TIMESYNC;
 pub struct TimesyncServer {
     join_handle: Option<thread::JoinHandle<()>>,
     run: RunFlag,
@@ -0,0 +1,256 @@
+//! Contact-closure trigger inputs, for integrating with venue show control.
+//!
+//! A GPIO or serial bridge connects over TCP and sends one line per contact
+//! state change:
+//!
+//! ```text
+//! <input> <state>
+//! ```
+//!
+//! where input is a number in [0, 127] and state is 1 when the contact closes
+//! and 0 when it opens.  A line containing only an input number is treated as
+//! a momentary press: a close immediately followed by an open.
+//!
+//! Trigger events are injected into the midi input stream as if they came
+//! from a device, so they are mapped to control messages just like any other
+//! input.  A close is a note on and an open is a note off.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{
+    error::Error,
+    io::{BufRead, BufReader},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Instant,
+};
+use tracing::{error, info, warn};
+use tunnels_lib::queue::DropSender;
+
+use crate::{
+    beam_store::BeamStore,
+    clock_bank::N_CLOCKS,
+    device::Device,
+    midi::{event, note_off, note_on, Event, Mapping},
+};
+
+/// Highest valid trigger input number.
+const MAX_INPUT: u8 = 127;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerConfig {
+    /// Listen for trigger bridges on this TCP port.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub inputs: Vec<TriggerInput>,
+}
+
+fn default_port() -> u16 {
+    9100
+}
+
+impl TriggerConfig {
+    /// Check that every input refers to something that exists in a show with
+    /// the provided number of mixer channels, beam store columns and MIDI
+    /// files.
+    pub fn validate(
+        &self,
+        n_channels: usize,
+        n_beam_store_cols: usize,
+        n_midi_files: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut seen = Vec::new();
+        for TriggerInput { input, action } in &self.inputs {
+            if *input > MAX_INPUT {
+                bail!("Trigger input {} is out of range.", input);
+            }
+            if seen.contains(input) {
+                bail!("Trigger input {} is assigned more than once.", input);
+            }
+            seen.push(*input);
+            match *action {
+                TriggerAction::Bump(chan) if chan >= n_channels => {
+                    bail!(
+                        "Trigger input {} bumps channel {} but the show has {} channels.",
+                        input,
+                        chan,
+                        n_channels
+                    );
+                }
+                TriggerAction::Tap(clock) if clock >= N_CLOCKS => {
+                    bail!(
+                        "Trigger input {} taps clock {} but the show has {} clocks.",
+                        input,
+                        clock,
+                        N_CLOCKS
+                    );
+                }
+                TriggerAction::Recall { row, col }
+                    if row >= BeamStore::N_ROWS || col >= n_beam_store_cols =>
+                {
+                    bail!(
+                        "Trigger input {} recalls nonexistent beam store slot ({}, {}).",
+                        input,
+                        row,
+                        col
+                    );
+                }
+                TriggerAction::PlayMidiFile(file) if file >= n_midi_files => {
+                    bail!(
+                        "Trigger input {} plays MIDI file {} but the show has {} MIDI files.",
+                        input,
+                        file,
+                        n_midi_files
+                    );
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Assign an action to a single trigger input.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerInput {
+    pub input: u8,
+    pub action: TriggerAction,
+}
+
+/// Things a contact closure can do.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum TriggerAction {
+    /// Toggle the mixer blackout on close.
+    Blackout,
+    /// Bump a mixer channel for as long as the contact is closed.
+    Bump(usize),
+    /// Tap a clock on close.
+    Tap(usize),
+    /// Recall a beam store slot into the current channel on close.
+    Recall { row: usize, col: usize },
+    /// Recall the next occupied beam store slot into the current channel on close.
+    NextBeam,
+    /// Play a configured MIDI file on close.
+    PlayMidiFile(usize),
+    /// Stop the MIDI file that is playing on close.
+    StopMidiFile,
+}
+
+/// The midi mapping used to inject a contact closing.
+pub const fn close_mapping(input: u8) -> Mapping {
+    note_on(0, input)
+}
+
+/// The midi mapping used to inject a contact opening.
+pub const fn open_mapping(input: u8) -> Mapping {
+    note_off(0, input)
+}
+
+/// Parse a single line of the trigger protocol into the events it represents.
+fn parse_line(line: &str) -> Result<Vec<Event>, Box<dyn Error>> {
+    let mut fields = line.split_whitespace();
+    let input: u8 = match fields.next() {
+        Some(f) => f.parse()?,
+        None => return Ok(Vec::new()),
+    };
+    if input > MAX_INPUT {
+        bail!("input {} is out of range", input);
+    }
+    let close = event(close_mapping(input), 127);
+    let open = event(open_mapping(input), 0);
+    let events = match fields.next() {
+        None => vec![close, open],
+        Some("1") => vec![close],
+        Some("0") => vec![open],
+        Some(other) => bail!("invalid contact state \"{}\"", other),
+    };
+    if fields.next().is_some() {
+        bail!("unexpected trailing data");
+    }
+    Ok(events)
+}
+
+/// Listen for trigger bridge connections in a background thread.
+/// Trigger events are sent on the provided channel.
+pub fn start_trigger_service(
+    port: u16,
+    sender: DropSender<(Instant, Device, Event)>,
+) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    info!("Listening for trigger inputs on port {}.", port);
+    thread::Builder::new()
+        .name("trigger".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || serve_connection(stream, sender));
+                    }
+                    Err(e) => error!("Trigger connection error: {}.", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Read trigger lines from a single bridge until it disconnects.
+fn serve_connection(stream: TcpStream, sender: DropSender<(Instant, Device, Event)>) {
+    let peer = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    info!("Trigger bridge connected from {}.", peer);
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Trigger bridge {} read error: {}.", peer, e);
+                break;
+            }
+        };
+        match parse_line(&line) {
+            Ok(events) => {
+                for e in events {
+                    if sender.send((Instant::now(), Device::Trigger, e)).is_err() {
+                        // The show has shut down.
+                        return;
+                    }
+                }
+            }
+            Err(e) => warn!("Ignoring trigger line \"{}\" from {}: {}.", line, peer, e),
+        }
+    }
+    info!("Trigger bridge {} disconnected.", peer);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mappings(line: &str) -> Vec<(Mapping, u8)> {
+        parse_line(line)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.mapping, e.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(vec![(close_mapping(3), 127)], mappings("3 1"));
+        assert_eq!(vec![(open_mapping(3), 0)], mappings(" 3  0 "));
+        assert_eq!(
+            vec![(close_mapping(12), 127), (open_mapping(12), 0)],
+            mappings("12")
+        );
+        assert!(mappings("").is_empty());
+        assert!(parse_line("128 1").is_err());
+        assert!(parse_line("3 2").is_err());
+        assert!(parse_line("3 1 1").is_err());
+        assert!(parse_line("x").is_err());
+    }
+}
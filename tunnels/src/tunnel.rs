@@ -1,10 +1,20 @@
 use crate::{
     animation::{Animation, Target},
-    clock_bank::ClockBank,
+    clock_bank::{ClockBank, ClockIdx},
+    params,
+    random::ShowRng,
+    recall_filter::ParamClass,
+    shatter::{self, Shatter},
+    validation,
 };
-use crate::{master_ui::EmitStateChange as EmitShowStateChange, waveforms::sawtooth};
+use crate::{
+    master_ui::EmitStateChange as EmitShowStateChange,
+    waveforms::{noise, sawtooth},
+};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
+use std::f64::consts::TAU;
 use std::time::Duration;
 use tunnels_lib::number::{BipolarFloat, Phase, UnipolarFloat};
 use tunnels_lib::smooth::{SmoothMode, Smoother};
@@ -37,11 +47,76 @@ pub struct Tunnel {
     /// bipolar float, internally interpreted as an int on [-16, 16]
     /// defaults to every other chicklet removed
     blacking: BipolarFloat,
+    /// Accumulated rotation, in unit angle.
     curr_rot_angle: Phase,
+    /// Accumulated marquee rotation, in unit angle.
     curr_marquee_angle: Phase,
     x_offset: Smoother<f64>,
     y_offset: Smoother<f64>,
     anims: [Animation; N_ANIM],
+    /// If set, zero the rotation angle the next time this clock ticks.
+    #[serde(skip)]
+    pending_rot_reset: Option<ClockIdx>,
+    /// If set, zero the marquee angle the next time this clock ticks.
+    #[serde(skip)]
+    pending_marquee_reset: Option<ClockIdx>,
+    /// If set, flip the hue to its complement when this clock ticks.
+    #[serde(default)]
+    color_flip_clock: Option<ClockIdx>,
+    /// Chance that any given tick of the color flip clock flips the hue.
+    #[serde(default = "default_color_flip_probability")]
+    color_flip_probability: UnipolarFloat,
+    /// Is the hue currently flipped to its complement?
+    #[serde(skip)]
+    color_flipped: bool,
+    /// How far each segment's size and position wander with noise.
+    #[serde(default = "default_wobble_amount")]
+    wobble_amount: UnipolarFloat,
+    /// How quickly the wobble wanders.
+    #[serde(default = "default_wobble_frequency")]
+    wobble_frequency: UnipolarFloat,
+    /// If set, the wobble wanders in time with this clock rather than freely.
+    #[serde(default)]
+    wobble_clock: Option<ClockIdx>,
+    /// Position along the noise signals driving the wobble, in steps between
+    /// the signals' random values.
+    #[serde(default)]
+    wobble_time: f64,
+    /// Phase of the wobble clock at the last update, in unit angle.  Saved
+    /// along with the clock phases so that a synced wobble doesn't jump on
+    /// load.
+    #[serde(default)]
+    wobble_clock_phase: Phase,
+    /// How long a shatter takes to settle back into the tunnel.
+    #[serde(default = "default_shatter_duration")]
+    shatter_duration: UnipolarFloat,
+    /// If set, color is drawn from the show palette, indexed by what would
+    /// otherwise be the hue.  The saturation knob has no effect.
+    #[serde(default)]
+    use_palette: bool,
+    /// Fixed rotation of the whole tunnel about the origin, in unit angle,
+    /// that sets apart the copies of a symmetric composition.
+    #[serde(default)]
+    rot_offset: Phase,
+    /// The shatter currently in progress, if any.
+    #[serde(skip)]
+    shatter: Option<Shatter>,
+}
+
+fn default_color_flip_probability() -> UnipolarFloat {
+    UnipolarFloat::new(params::COLOR_FLIP_PROBABILITY.default)
+}
+
+fn default_wobble_amount() -> UnipolarFloat {
+    UnipolarFloat::new(params::WOBBLE_AMOUNT.default)
+}
+
+fn default_wobble_frequency() -> UnipolarFloat {
+    UnipolarFloat::new(params::WOBBLE_FREQUENCY.default)
+}
+
+fn default_shatter_duration() -> UnipolarFloat {
+    UnipolarFloat::new(params::SHATTER_DURATION.default)
 }
 
 impl Tunnel {
@@ -49,22 +124,36 @@ impl Tunnel {
 
     pub fn new() -> Self {
         Self {
-            marquee_speed: BipolarFloat::ZERO,
-            rot_speed: BipolarFloat::ZERO,
-            thickness: UnipolarFloat::new(0.1),
-            size: UnipolarFloat::new(0.5),
-            aspect_ratio: UnipolarFloat::new(0.5),
-            col_center: UnipolarFloat::ZERO,
-            col_width: UnipolarFloat::ZERO,
-            col_spread: UnipolarFloat::ZERO,
-            col_sat: UnipolarFloat::ZERO,
-            segs: 126,
-            blacking: BipolarFloat::new(0.15),
+            marquee_speed: BipolarFloat::new(params::MARQUEE_SPEED.default),
+            rot_speed: BipolarFloat::new(params::ROTATION_SPEED.default),
+            thickness: UnipolarFloat::new(params::THICKNESS.default),
+            size: UnipolarFloat::new(params::SIZE.default),
+            aspect_ratio: UnipolarFloat::new(params::ASPECT_RATIO.default),
+            col_center: UnipolarFloat::new(params::COLOR_CENTER.default),
+            col_width: UnipolarFloat::new(params::COLOR_WIDTH.default),
+            col_spread: UnipolarFloat::new(params::COLOR_SPREAD.default),
+            col_sat: UnipolarFloat::new(params::COLOR_SATURATION.default),
+            segs: params::SEGMENTS.default as u8,
+            blacking: BipolarFloat::new(params::BLACKING.default),
             curr_rot_angle: Phase::ZERO,
             curr_marquee_angle: Phase::ZERO,
             x_offset: Smoother::new(0.0, Self::MOVE_SMOOTH_TIME, SmoothMode::Linear),
             y_offset: Smoother::new(0.0, Self::MOVE_SMOOTH_TIME, SmoothMode::Linear),
             anims: Default::default(),
+            pending_rot_reset: None,
+            pending_marquee_reset: None,
+            color_flip_clock: None,
+            color_flip_probability: default_color_flip_probability(),
+            color_flipped: false,
+            wobble_amount: default_wobble_amount(),
+            wobble_frequency: default_wobble_frequency(),
+            wobble_clock: None,
+            wobble_time: 0.,
+            wobble_clock_phase: Phase::ZERO,
+            shatter_duration: default_shatter_duration(),
+            use_palette: false,
+            rot_offset: Phase::ZERO,
+            shatter: None,
         }
     }
 
@@ -94,13 +183,78 @@ impl Tunnel {
         self.anims[anim_num] = new_anim;
     }
 
+    pub fn rotation_offset(&self) -> Phase {
+        self.rot_offset
+    }
+
+    pub fn set_rotation_offset(&mut self, offset: Phase) {
+        self.rot_offset = offset;
+    }
+
+    /// Become a copy of another tunnel, keeping this tunnel's rotation
+    /// offset, so that linked copies move together.
+    pub fn follow(&mut self, leader: &Tunnel) {
+        let rot_offset = self.rot_offset;
+        *self = leader.clone();
+        self.rot_offset = rot_offset;
+    }
+
+    /// Take one class of parameters from another tunnel, such as to keep
+    /// them through a recall.
+    pub fn keep(&mut self, other: &Tunnel, class: ParamClass) {
+        match class {
+            ParamClass::Geometry => {
+                self.thickness = other.thickness;
+                self.size = other.size;
+                self.aspect_ratio = other.aspect_ratio;
+                self.segs = other.segs;
+                self.blacking = other.blacking;
+                self.wobble_amount = other.wobble_amount;
+                self.wobble_frequency = other.wobble_frequency;
+                self.wobble_clock = other.wobble_clock;
+                self.wobble_time = other.wobble_time;
+                self.wobble_clock_phase = other.wobble_clock_phase;
+                self.shatter_duration = other.shatter_duration;
+            }
+            ParamClass::Color => {
+                self.col_center = other.col_center;
+                self.col_width = other.col_width;
+                self.col_spread = other.col_spread;
+                self.col_sat = other.col_sat;
+                self.use_palette = other.use_palette;
+                self.color_flip_clock = other.color_flip_clock;
+                self.color_flip_probability = other.color_flip_probability;
+                self.color_flipped = other.color_flipped;
+            }
+            ParamClass::Position => {
+                self.x_offset = other.x_offset.clone();
+                self.y_offset = other.y_offset.clone();
+                self.rot_offset = other.rot_offset;
+            }
+            ParamClass::Motion => {
+                self.marquee_speed = other.marquee_speed;
+                self.rot_speed = other.rot_speed;
+                self.curr_rot_angle = other.curr_rot_angle;
+                self.curr_marquee_angle = other.curr_marquee_angle;
+                self.pending_rot_reset = other.pending_rot_reset;
+                self.pending_marquee_reset = other.pending_marquee_reset;
+            }
+            ParamClass::Animations => self.anims = other.anims.clone(),
+        }
+    }
+
     /// Get an iterator over animations.
     pub fn animations(&mut self) -> impl Iterator<Item = &mut Animation> {
         self.anims.iter_mut()
     }
 
     /// Update the state of this tunnel in preparation for drawing a frame.
-    pub fn update_state(&mut self, delta_t: Duration) {
+    pub fn update_state(
+        &mut self,
+        delta_t: Duration,
+        external_clocks: &ClockBank,
+        rng: &mut ShowRng,
+    ) {
         // ensure we don't exceed the set bounds of the screen
         // self.x_offset = f64::min(f64::max(self.x_offset, -MAX_X_OFFSET), MAX_X_OFFSET);
         // self.y_offset = f64::min(f64::max(self.y_offset, -MAX_Y_OFFSET), MAX_Y_OFFSET);
@@ -110,34 +264,122 @@ impl Tunnel {
 
         // Update the state of the animations.
         for anim in &mut self.anims {
-            anim.update_state(delta_t);
+            anim.update_state(delta_t, external_clocks, rng);
         }
         let timestep_secs = delta_t.as_secs_f64();
 
         // calulcate the rotation
         // delta_t*30. implies the same speed scale as we had at 30fps with evolution tied to frame
         self.curr_rot_angle +=
-            (scale_speed(self.rot_speed).val() * timestep_secs * 30.) * ROT_SPEED_SCALE;
+            (params::ROTATION_SPEED.taper.apply(self.rot_speed).val() * timestep_secs * 30.)
+                * ROT_SPEED_SCALE;
 
         // calulcate the marquee angle
         // delta_t*30 implies the same speed scale as we had at 30fps with evolution tied to frame
         self.curr_marquee_angle +=
-            (scale_speed(self.marquee_speed).val() * timestep_secs * 30.) * MARQUEE_SPEED_SCALE;
+            (params::MARQUEE_SPEED.taper.apply(self.marquee_speed).val() * timestep_secs * 30.)
+                * MARQUEE_SPEED_SCALE;
+
+        // Perform any resets that were waiting for a beat.
+        if let Some(clock) = self.pending_rot_reset {
+            if external_clocks.ticked(clock) {
+                self.curr_rot_angle = Phase::ZERO;
+                self.pending_rot_reset = None;
+            }
+        }
+        if let Some(clock) = self.pending_marquee_reset {
+            if external_clocks.ticked(clock) {
+                self.curr_marquee_angle = Phase::ZERO;
+                self.pending_marquee_reset = None;
+            }
+        }
+
+        match self.color_flip_clock {
+            Some(clock) => {
+                if external_clocks.ticked(clock)
+                    && rng.gen::<f64>() < self.color_flip_probability.val()
+                {
+                    self.color_flipped = !self.color_flipped;
+                }
+            }
+            None => self.color_flipped = false,
+        }
+
+        // Advance the wobble by time, or by the progress of its clock.
+        let wobble_elapsed = match self.wobble_clock {
+            Some(clock) => {
+                let phase = external_clocks.phase(clock);
+                let elapsed = (phase - self.wobble_clock_phase).val();
+                self.wobble_clock_phase = phase;
+                elapsed
+            }
+            None => timestep_secs,
+        };
+        self.wobble_time += wobble_elapsed * self.wobble_frequency.val() * WOBBLE_MAX_RATE;
+
+        if let Some(shatter) = &mut self.shatter {
+            if !shatter.update_state(delta_t, rng) {
+                self.shatter = None;
+            }
+        }
+    }
+
+    /// Cancel any resets waiting for a clock to tick, and settle any shatter
+    /// in progress.
+    pub fn cancel_pending(&mut self) {
+        self.pending_rot_reset = None;
+        self.pending_marquee_reset = None;
+        self.shatter = None;
+    }
+
+    /// Return the number of segments to divide the tunnel into, when drawn at
+    /// the provided fraction of full resolution.
+    fn segment_count(&self, resolution: f64) -> u8 {
+        // for artistic reasons/convenience, eliminate odd numbers of segments above 40.
+        let segs = if self.segs > 40 && self.segs % 2 != 0 {
+            self.segs + 1
+        } else {
+            self.segs
+        };
+        if resolution >= 1.0 {
+            return segs;
+        }
+        ((segs as f64 * resolution).round() as u8).max(1)
+    }
+
+    /// Return true if the segment with this ID is drawn rather than blacked.
+    fn is_drawn(seg_num: u8, blacking: i32) -> bool {
+        if blacking > 0 {
+            (seg_num as i32) % blacking == 0
+        } else {
+            (seg_num as i32) % blacking != 0
+        }
+    }
+
+    /// Return the number of arcs this tunnel renders at full resolution.
+    pub fn arc_count(&self) -> usize {
+        let blacking = self.blacking_integer();
+        let count = (0..self.segment_count(1.0))
+            .filter(|seg_num| Self::is_drawn(*seg_num, blacking))
+            .count();
+        if self.shatter.is_some() {
+            count * shatter::PIECES
+        } else {
+            count
+        }
     }
 
     /// Render the current state of the tunnel.
+    /// Resolution scales the number of segments drawn, from full resolution
+    /// at 1.0 down to a single segment.
     pub fn render(
         &self,
         level_scale: UnipolarFloat,
         as_mask: bool,
+        resolution: f64,
         external_clocks: &ClockBank,
     ) -> Vec<ArcSegment> {
-        // for artistic reasons/convenience, eliminate odd numbers of segments above 40.
-        let segs = if self.segs > 40 && self.segs % 2 != 0 {
-            self.segs + 1
-        } else {
-            self.segs
-        };
+        let segs = self.segment_count(resolution);
         let blacking = self.blacking_integer();
 
         let mut arcs = Vec::new();
@@ -146,12 +388,7 @@ impl Tunnel {
 
         // Iterate over each segment ID and skip the segments that are blacked.
         for seg_num in 0..segs {
-            let should_draw_segment = if blacking > 0 {
-                (seg_num as i32) % blacking == 0
-            } else {
-                (seg_num as i32) % blacking != 0
-            };
-            if !should_draw_segment {
+            if !Self::is_drawn(seg_num, blacking) {
                 continue;
             }
 
@@ -196,16 +433,36 @@ impl Tunnel {
             let thickness_allowance = self.thickness.val() * THICKNESS_SCALE / 2.;
 
             // geometry calculations
-            let x_center = self.x_offset.val() + x_adjust;
-            let y_center = self.y_offset.val() + y_adjust;
+            let mut x_center = self.x_offset.val() + x_adjust;
+            let mut y_center = self.y_offset.val() + y_adjust;
 
             // compute ellipse parameters
-            let radius_x = ((self.size.val()
+            let mut radius_x = ((self.size.val()
                 * (MAX_ASPECT_RATIO * (self.aspect_ratio.val() + aspect_ratio_adjust))
                 - thickness_allowance)
                 + size_adjust)
                 .abs();
-            let radius_y = (self.size.val() - thickness_allowance + size_adjust).abs();
+            let mut radius_y = (self.size.val() - thickness_allowance + size_adjust).abs();
+
+            // Each segment wobbles along its own noise signals.
+            if self.wobble_amount > 0. {
+                let amount = self.wobble_amount.val();
+                let seed = 3 * seg_num as u32;
+                let scale = 1. + amount * WOBBLE_RADIUS_SCALE * noise(self.wobble_time, seed);
+                radius_x *= scale;
+                radius_y *= scale;
+                x_center += amount * WOBBLE_CENTER_SCALE * noise(self.wobble_time, seed + 1);
+                y_center += amount * WOBBLE_CENTER_SCALE * noise(self.wobble_time, seed + 2);
+            }
+
+            // Copies in a symmetric composition are rotated about the origin.
+            if self.rot_offset.val() != 0. {
+                let (sin, cos) = (self.rot_offset.val() * TAU).sin_cos();
+                (x_center, y_center) = (
+                    cos * x_center - sin * y_center,
+                    sin * x_center + cos * y_center,
+                );
+            }
 
             // The angle of this particular segment.
             let start_angle: Phase = self.curr_marquee_angle
@@ -216,7 +473,7 @@ impl Tunnel {
             // arcs that cross the angular origin.
             let stop_angle = start_angle.val() + marquee_interval;
 
-            let rot_angle = self.curr_rot_angle + rot_angle_adjust;
+            let rot_angle = self.curr_rot_angle + (self.rot_offset.val() + rot_angle_adjust);
 
             let arc = if as_mask {
                 ArcSegment {
@@ -232,6 +489,7 @@ impl Tunnel {
                     start: start_angle.val(),
                     stop: stop_angle,
                     rot_angle: rot_angle.val(),
+                    palette: false,
                 }
             } else {
                 let hue = Phase::new(
@@ -245,10 +503,16 @@ impl Tunnel {
                                 UnipolarFloat::ZERO,
                                 UnipolarFloat::ONE,
                                 false,
-                            )),
+                            ))
+                        + if self.color_flipped { 0.5 } else { 0.0 },
                 );
 
-                let sat = UnipolarFloat::new(self.col_sat.val() + col_sat_adjust);
+                // A palette color is used at its own saturation.
+                let sat = if self.use_palette {
+                    UnipolarFloat::ONE
+                } else {
+                    UnipolarFloat::new(self.col_sat.val() + col_sat_adjust)
+                };
 
                 ArcSegment {
                     level: level_scale.val(),
@@ -263,9 +527,13 @@ impl Tunnel {
                     start: start_angle.val(),
                     stop: stop_angle,
                     rot_angle: rot_angle.val(),
+                    palette: self.use_palette,
                 }
             };
-            arcs.push(arc);
+            match &self.shatter {
+                Some(shatter) => shatter.split(arc, seg_num, &mut arcs),
+                None => arcs.push(arc),
+            }
         }
         arcs
     }
@@ -282,10 +550,17 @@ impl Tunnel {
         emitter.emit_tunnel_state_change(ColorWidth(self.col_width));
         emitter.emit_tunnel_state_change(ColorSpread(self.col_spread));
         emitter.emit_tunnel_state_change(ColorSaturation(self.col_sat));
+        emitter.emit_tunnel_state_change(UsePalette(self.use_palette));
         emitter.emit_tunnel_state_change(Segments(self.segs));
         emitter.emit_tunnel_state_change(Blacking(self.blacking));
         emitter.emit_tunnel_state_change(PositionX(self.x_offset.target()));
         emitter.emit_tunnel_state_change(PositionY(self.y_offset.target()));
+        emitter.emit_tunnel_state_change(ColorFlipClock(self.color_flip_clock));
+        emitter.emit_tunnel_state_change(ColorFlipProbability(self.color_flip_probability));
+        emitter.emit_tunnel_state_change(WobbleAmount(self.wobble_amount));
+        emitter.emit_tunnel_state_change(WobbleFrequency(self.wobble_frequency));
+        emitter.emit_tunnel_state_change(WobbleClock(self.wobble_clock));
+        emitter.emit_tunnel_state_change(ShatterDuration(self.shatter_duration));
     }
 
     /// Handle a control event.
@@ -310,6 +585,9 @@ impl Tunnel {
                 StateChange::PositionY(self.y_offset.target() - Y_NUDGE),
                 emitter,
             ),
+            TogglePalette => {
+                self.handle_state_change(StateChange::UsePalette(!self.use_palette), emitter)
+            }
             ResetPosition => {
                 self.handle_state_change(StateChange::PositionX(0.), emitter);
                 self.handle_state_change(StateChange::PositionY(0.), emitter);
@@ -324,11 +602,30 @@ impl Tunnel {
                 self.curr_marquee_angle = Phase::ZERO;
                 emitter.emit_tunnel_state_change(StateChange::MarqueeSpeed(BipolarFloat::ZERO));
             }
+            ZeroRotationAngle(None) => {
+                self.curr_rot_angle = Phase::ZERO;
+                self.pending_rot_reset = None;
+            }
+            ZeroRotationAngle(clock) => self.pending_rot_reset = clock,
+            ZeroMarqueeAngle(None) => {
+                self.curr_marquee_angle = Phase::ZERO;
+                self.pending_marquee_reset = None;
+            }
+            ZeroMarqueeAngle(clock) => self.pending_marquee_reset = clock,
+            Shatter => {
+                let duration =
+                    Duration::from_secs_f64(self.shatter_duration.val() * shatter::MAX_DURATION);
+                self.shatter = (!duration.is_zero()).then(|| shatter::Shatter::new(duration));
+            }
         }
     }
 
     fn handle_state_change<E: EmitStateChange>(&mut self, sc: StateChange, emitter: &mut E) {
         use StateChange::*;
+        let sc = match validation::tunnel(sc) {
+            Some(sc) => sc,
+            None => return,
+        };
         match sc {
             MarqueeSpeed(v) => self.marquee_speed = v,
             RotationSpeed(v) => self.rot_speed = v,
@@ -339,25 +636,22 @@ impl Tunnel {
             ColorWidth(v) => self.col_width = v,
             ColorSpread(v) => self.col_spread = v,
             ColorSaturation(v) => self.col_sat = v,
+            UsePalette(v) => self.use_palette = v,
             Segments(v) => self.segs = v,
             Blacking(v) => self.blacking = v,
             PositionX(v) => self.x_offset.set_target(v),
             PositionY(v) => self.y_offset.set_target(v),
+            ColorFlipClock(v) => self.color_flip_clock = v,
+            ColorFlipProbability(v) => self.color_flip_probability = v,
+            WobbleAmount(v) => self.wobble_amount = v,
+            WobbleFrequency(v) => self.wobble_frequency = v,
+            WobbleClock(v) => self.wobble_clock = v,
+            ShatterDuration(v) => self.shatter_duration = v,
         };
         emitter.emit_tunnel_state_change(sc);
     }
 }
 
-/// Scale speeds with a quadratic curve.
-/// This provides more resolution for slower speeds.
-fn scale_speed(speed: BipolarFloat) -> BipolarFloat {
-    let mut scaled = f64::powi(speed.val(), 2);
-    if speed < 0. {
-        scaled *= -1.
-    }
-    BipolarFloat::new(scaled)
-}
-
 #[derive(
     Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize, TypedIndex,
 )]
@@ -367,7 +661,7 @@ pub struct AnimationIdx(pub usize);
 // TODO: move some of these into associated constants
 pub const N_ANIM: usize = 4;
 /// legacy tuning parameter; tunnel rotated this many radial units/frame at 30fps
-const ROT_SPEED_SCALE: f64 = 0.023;
+pub const ROT_SPEED_SCALE: f64 = 0.023;
 /// legacy tuning parameter; marquee rotated this many radial units/frame at 30fps
 const MARQUEE_SPEED_SCALE: f64 = 0.023;
 const COLOR_SPREAD_SCALE: f64 = 16.;
@@ -378,6 +672,13 @@ const Y_NUDGE: f64 = 0.025;
 /// line thickness scale as fraction of min half-screen
 const THICKNESS_SCALE: f64 = 0.5;
 const MAX_ASPECT_RATIO: f64 = 2.0;
+/// Full-scale wobble frequency, in noise values per second, or per cycle of
+/// the wobble clock.
+const WOBBLE_MAX_RATE: f64 = 8.0;
+/// Largest fraction of its size a segment grows or shrinks by at full wobble.
+const WOBBLE_RADIUS_SCALE: f64 = 0.25;
+/// Largest distance a segment moves from its center at full wobble.
+const WOBBLE_CENTER_SCALE: f64 = 0.05;
 
 pub enum StateChange {
     MarqueeSpeed(BipolarFloat),
@@ -389,10 +690,20 @@ pub enum StateChange {
     ColorWidth(UnipolarFloat),
     ColorSpread(UnipolarFloat),
     ColorSaturation(UnipolarFloat),
+    /// Draw color from the show palette rather than the color wheel.
+    UsePalette(bool),
     Segments(u8), // FIXME integer knob
     Blacking(BipolarFloat),
     PositionX(f64),
     PositionY(f64),
+    /// Flip the hue to its complement on ticks of this clock, or never.
+    ColorFlipClock(Option<ClockIdx>),
+    ColorFlipProbability(UnipolarFloat),
+    WobbleAmount(UnipolarFloat),
+    WobbleFrequency(UnipolarFloat),
+    /// Wobble in time with this clock, or freely.
+    WobbleClock(Option<ClockIdx>),
+    ShatterDuration(UnipolarFloat),
 }
 pub enum ControlMessage {
     Set(StateChange),
@@ -401,8 +712,19 @@ pub enum ControlMessage {
     NudgeUp,
     NudgeDown,
     ResetPosition,
+    TogglePalette,
+    /// Stop rotation and zero the rotation angle.
     ResetRotation,
+    /// Stop the marquee and zero the marquee angle.
     ResetMarquee,
+    /// Zero the rotation angle without changing speed.
+    /// If a clock is provided, wait until the next time it ticks.
+    ZeroRotationAngle(Option<ClockIdx>),
+    /// Zero the marquee angle without changing speed.
+    /// If a clock is provided, wait until the next time it ticks.
+    ZeroMarqueeAngle(Option<ClockIdx>),
+    /// Break the tunnel apart into flying pieces that settle back together.
+    Shatter,
 }
 
 pub trait EmitStateChange {
@@ -0,0 +1,162 @@
+//! Validation of parameter changes before they reach the show model.
+//!
+//! Every change to a tunnel or animation passes through here, whether it came
+//! from a control surface, the scheduler, or the autopilot.  Values are
+//! clamped to the range registered for their parameter, and values that are
+//! not numbers at all are rejected, so that a misbehaving controller can't
+//! produce degenerate geometry.
+
+use tracing::warn;
+use tunnels_lib::number::{BipolarFloat, UnipolarFloat};
+
+use crate::{
+    animation::StateChange as AnimationStateChange,
+    clock_bank::ClockIdx,
+    palette::StateChange as PaletteStateChange,
+    params::{self, ParamSpec},
+    tunnel::StateChange as TunnelStateChange,
+};
+
+/// Clamp a value to the range of a parameter, logging any violation.
+/// Return None if the value is NaN or infinite.
+fn check(group: &str, spec: &ParamSpec, v: f64) -> Option<f64> {
+    if !v.is_finite() {
+        warn!("Ignoring {} {} set to {}.", group, spec.name, v);
+        return None;
+    }
+    let (min, max) = spec.kind.range();
+    let clamped = v.clamp(min, max);
+    if clamped != v {
+        warn!(
+            "{} {} set to {}, outside of [{}, {}]; clamped to {}.",
+            group, spec.name, v, min, max, clamped
+        );
+    }
+    Some(clamped)
+}
+
+fn unipolar(group: &str, spec: &ParamSpec, v: UnipolarFloat) -> Option<UnipolarFloat> {
+    check(group, spec, v.val()).map(UnipolarFloat::new)
+}
+
+fn bipolar(group: &str, spec: &ParamSpec, v: BipolarFloat) -> Option<BipolarFloat> {
+    check(group, spec, v.val()).map(BipolarFloat::new)
+}
+
+/// Clock choices are represented as an index offset by one, with zero
+/// selecting no clock.
+fn clock(group: &str, spec: &ParamSpec, v: Option<ClockIdx>) -> Option<Option<ClockIdx>> {
+    let index = v.map(|c| c.0 as f64 + 1.).unwrap_or(0.);
+    let clamped = check(group, spec, index)? as usize;
+    Some(clamped.checked_sub(1).map(ClockIdx))
+}
+
+/// Validate a change to a tunnel parameter.
+/// Return None if the change should be ignored.
+pub fn tunnel(sc: TunnelStateChange) -> Option<TunnelStateChange> {
+    use TunnelStateChange::*;
+    const GROUP: &str = "Tunnel";
+    Some(match sc {
+        MarqueeSpeed(v) => MarqueeSpeed(bipolar(GROUP, &params::MARQUEE_SPEED, v)?),
+        RotationSpeed(v) => RotationSpeed(bipolar(GROUP, &params::ROTATION_SPEED, v)?),
+        Thickness(v) => Thickness(unipolar(GROUP, &params::THICKNESS, v)?),
+        Size(v) => Size(unipolar(GROUP, &params::SIZE, v)?),
+        AspectRatio(v) => AspectRatio(unipolar(GROUP, &params::ASPECT_RATIO, v)?),
+        ColorCenter(v) => ColorCenter(unipolar(GROUP, &params::COLOR_CENTER, v)?),
+        ColorWidth(v) => ColorWidth(unipolar(GROUP, &params::COLOR_WIDTH, v)?),
+        ColorSpread(v) => ColorSpread(unipolar(GROUP, &params::COLOR_SPREAD, v)?),
+        ColorSaturation(v) => ColorSaturation(unipolar(GROUP, &params::COLOR_SATURATION, v)?),
+        UsePalette(v) => UsePalette(v),
+        Segments(v) => Segments(check(GROUP, &params::SEGMENTS, v as f64)? as u8),
+        Blacking(v) => Blacking(bipolar(GROUP, &params::BLACKING, v)?),
+        PositionX(v) => PositionX(check(GROUP, &params::POSITION_X, v)?),
+        PositionY(v) => PositionY(check(GROUP, &params::POSITION_Y, v)?),
+        ColorFlipClock(v) => ColorFlipClock(clock(GROUP, &params::COLOR_FLIP_CLOCK, v)?),
+        ColorFlipProbability(v) => {
+            ColorFlipProbability(unipolar(GROUP, &params::COLOR_FLIP_PROBABILITY, v)?)
+        }
+        WobbleAmount(v) => WobbleAmount(unipolar(GROUP, &params::WOBBLE_AMOUNT, v)?),
+        WobbleFrequency(v) => WobbleFrequency(unipolar(GROUP, &params::WOBBLE_FREQUENCY, v)?),
+        WobbleClock(v) => WobbleClock(clock(GROUP, &params::WOBBLE_CLOCK, v)?),
+        ShatterDuration(v) => ShatterDuration(unipolar(GROUP, &params::SHATTER_DURATION, v)?),
+    })
+}
+
+/// Validate a change to the palette selection, given how many palettes are
+/// defined.
+/// Return None if the change should be ignored.
+pub fn palette(sc: PaletteStateChange, n_palettes: usize) -> Option<PaletteStateChange> {
+    use PaletteStateChange::*;
+    const GROUP: &str = "Palette";
+    Some(match sc {
+        Select(_, Some(i)) if i >= n_palettes => {
+            warn!(
+                "Ignoring selection of palette {}; only {} are defined.",
+                i, n_palettes
+            );
+            return None;
+        }
+        Select(deck, v) => Select(deck, v),
+        Crossfade(v) => Crossfade(unipolar(GROUP, &params::PALETTE_CROSSFADE, v)?),
+    })
+}
+
+/// Validate a change to an animation parameter.
+/// Return None if the change should be ignored.
+pub fn animation(sc: AnimationStateChange) -> Option<AnimationStateChange> {
+    use AnimationStateChange::*;
+    const GROUP: &str = "Animation";
+    Some(match sc {
+        NPeriods(v) => NPeriods(check(GROUP, &params::ANIMATION_N_PERIODS, v as f64)? as i32),
+        Speed(v) => Speed(bipolar(GROUP, &params::ANIMATION_SPEED, v)?),
+        Weight(v) => Weight(unipolar(GROUP, &params::ANIMATION_WEIGHT, v)?),
+        DutyCycle(v) => DutyCycle(unipolar(GROUP, &params::ANIMATION_DUTY_CYCLE, v)?),
+        Smoothing(v) => Smoothing(unipolar(GROUP, &params::ANIMATION_SMOOTHING, v)?),
+        ClockSource(v) => ClockSource(clock(GROUP, &params::ANIMATION_CLOCK_SOURCE, v)?),
+        Probability(v) => Probability(unipolar(GROUP, &params::ANIMATION_PROBABILITY, v)?),
+        // Enums and booleans can't be out of range.
+        sc @ Waveform(_)
+        | sc @ Pulse(_)
+        | sc @ Invert(_)
+        | sc @ Target(_)
+        | sc @ AudioSource(_) => sc,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tunnel() {
+        match tunnel(TunnelStateChange::PositionX(2.5)) {
+            Some(TunnelStateChange::PositionX(v)) => assert_eq!(1., v),
+            _ => panic!("Position should have been clamped."),
+        }
+        assert!(tunnel(TunnelStateChange::PositionY(f64::NAN)).is_none());
+        assert!(matches!(
+            tunnel(TunnelStateChange::Segments(0)),
+            Some(TunnelStateChange::Segments(1))
+        ));
+        assert!(matches!(
+            tunnel(TunnelStateChange::ColorFlipClock(Some(ClockIdx(9)))),
+            Some(TunnelStateChange::ColorFlipClock(Some(ClockIdx(3))))
+        ));
+        assert!(matches!(
+            tunnel(TunnelStateChange::ColorFlipClock(None)),
+            Some(TunnelStateChange::ColorFlipClock(None))
+        ));
+    }
+
+    #[test]
+    fn test_animation() {
+        assert!(matches!(
+            animation(AnimationStateChange::NPeriods(-3)),
+            Some(AnimationStateChange::NPeriods(0))
+        ));
+        assert!(matches!(
+            animation(AnimationStateChange::NPeriods(100)),
+            Some(AnimationStateChange::NPeriods(14))
+        ));
+    }
+}
@@ -0,0 +1,702 @@
+//! Adjustments applied to each video channel as it is rendered.
+//!
+//! These let the same programmed content drive outputs with different
+//! geometry and display characteristics, without requiring clients to distort
+//! the image themselves.
+//!
+//! The physical properties of each output come from the active output
+//! profile, which also routes each video channel to its output.  Venue
+//! geometry presets are defined in the show config, while the preset
+//! selected for each video channel is saved with the show.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use simple_error::bail;
+use std::{collections::HashSet, error::Error, f64::consts::PI, sync::Arc, time::Duration};
+use tracing::warn;
+use tunnels_lib::{
+    color::ColorCorrection,
+    palette::{self, Palette},
+    ArcSegment, LayerCollection,
+};
+
+use crate::{
+    master_ui::EmitStateChange as EmitShowStateChange,
+    mixer::{Mixer, VideoChannel},
+    white_point::WhitePoint,
+};
+
+const TWO_PI: f64 = 2.0 * PI;
+
+/// Most display latency that can be made up for, in seconds.  Clients only
+/// buffer so far ahead.
+const MAX_LATENCY: f64 = 1.0;
+
+/// Configure the physical properties of a video channel's output.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct VideoOutputConfig {
+    pub channel: usize,
+    /// Displayed width of a pixel relative to its height.
+    /// An anamorphic projector that stretches the image horizontally by 4/3
+    /// has a pixel aspect ratio of 1.333.
+    #[serde(default = "default_pixel_aspect_ratio")]
+    pub pixel_aspect_ratio: f64,
+    /// How arc levels are shaped before being sent to this output.
+    #[serde(default)]
+    pub dimming_curve: DimmingCurve,
+    /// Color temperature in kelvin to bias this output's whites toward.
+    /// Lower is warmer.  Colors are unchanged if not provided.
+    #[serde(default)]
+    pub color_temperature: Option<f64>,
+    /// What to do while no client is showing this output.
+    #[serde(default)]
+    pub when_idle: IdlePolicy,
+    /// Most data to publish for this output, in kilobytes per second.  If
+    /// its frames would exceed this, they are sent at a lower frame rate.
+    #[serde(default)]
+    pub bandwidth: Option<f64>,
+    /// How long this output's display takes to show a frame, in seconds.
+    /// Frames for this output are timestamped this much early, so that its
+    /// clients draw them ahead to make up for it.
+    #[serde(default)]
+    pub latency: f64,
+    /// Width of this output's screen relative to its height, used to measure
+    /// how brightly it is lit for the flash limit.
+    #[serde(default = "default_aspect_ratio")]
+    pub aspect_ratio: f64,
+}
+
+fn default_pixel_aspect_ratio() -> f64 {
+    1.0
+}
+
+fn default_aspect_ratio() -> f64 {
+    16.0 / 9.0
+}
+
+impl VideoOutputConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.channel >= Mixer::N_VIDEO_CHANNELS {
+            bail!(
+                "Video output {} is out of range; there are {} video channels.",
+                self.channel,
+                Mixer::N_VIDEO_CHANNELS
+            );
+        }
+        if !self.pixel_aspect_ratio.is_finite() || self.pixel_aspect_ratio <= 0.0 {
+            bail!(
+                "Video output {} has pixel aspect ratio {}; it must be positive.",
+                self.channel,
+                self.pixel_aspect_ratio
+            );
+        }
+        if let Some(kelvin) = self.color_temperature {
+            WhitePoint::validate_temperature(kelvin)?;
+        }
+        if let Some(bandwidth) = self.bandwidth {
+            if !bandwidth.is_finite() || bandwidth <= 0.0 {
+                bail!(
+                    "Video output {} has bandwidth {}; it must be positive.",
+                    self.channel,
+                    bandwidth
+                );
+            }
+        }
+        if !self.aspect_ratio.is_finite() || self.aspect_ratio <= 0.0 {
+            bail!(
+                "Video output {} has aspect ratio {}; it must be positive.",
+                self.channel,
+                self.aspect_ratio
+            );
+        }
+        if !self.latency.is_finite() || self.latency < 0.0 || self.latency > MAX_LATENCY {
+            bail!(
+                "Video output {} has latency {}; it must be between 0 and {} seconds.",
+                self.channel,
+                self.latency,
+                MAX_LATENCY
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The response curve used to map arc level onto output intensity.
+///
+/// Many displays render low intensities much brighter than a linear level
+/// would suggest; the steeper curves give more resolution near black.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, JsonSchema)]
+pub enum DimmingCurve {
+    Linear,
+    /// Level squared.
+    SquareLaw,
+    /// Smoothstep: gentle at both ends, steepest through the middle.
+    SCurve,
+}
+
+impl Default for DimmingCurve {
+    fn default() -> Self {
+        Self::Linear
+    }
+}
+
+impl DimmingCurve {
+    pub fn apply(self, level: f64) -> f64 {
+        match self {
+            Self::Linear => level,
+            Self::SquareLaw => level * level,
+            Self::SCurve => level * level * (3.0 - 2.0 * level),
+        }
+    }
+}
+
+/// How much work to do for an output while no client is showing it.
+///
+/// Clients that predate heartbeats are never seen as connected, so outputs
+/// they show should keep the default policy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+pub enum IdlePolicy {
+    /// Keep computing and sending the output as usual.
+    Run,
+    /// Freeze beams that only feed idle outputs, resuming where they left off
+    /// once a client connects.
+    Pause,
+    /// Stop rendering the output entirely.
+    Blackout,
+}
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        Self::Run
+    }
+}
+
+/// A named adjustment to the geometry of an entire composition, used to fit
+/// programmed content to the surface at a particular venue.
+///
+/// The composition is scaled and rotated about the origin, then offset.
+/// Offsets are in the same units as beam position.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GeometryPreset {
+    pub name: String,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub x_offset: f64,
+    #[serde(default)]
+    pub y_offset: f64,
+    /// Rotation as a unit angle.
+    #[serde(default)]
+    pub rotation: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl GeometryPreset {
+    /// The most presets that can be selected from a control surface.
+    pub const MAX_COUNT: usize = 15;
+
+    /// Check a collection of presets for consistency.
+    pub fn validate_all(presets: &[Self]) -> Result<(), Box<dyn Error>> {
+        if presets.len() > Self::MAX_COUNT {
+            bail!(
+                "{} geometry presets are defined; at most {} are supported.",
+                presets.len(),
+                Self::MAX_COUNT
+            );
+        }
+        let mut names = HashSet::new();
+        for preset in presets {
+            if !names.insert(&preset.name) {
+                bail!(
+                    "Geometry preset name {} is used more than once.",
+                    preset.name
+                );
+            }
+            if !preset.scale.is_finite() || preset.scale <= 0.0 {
+                bail!(
+                    "Geometry preset {} has scale {}; it must be positive.",
+                    preset.name,
+                    preset.scale
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Output adjustments for every video channel.
+///
+/// Rendered video channels are first routed to their outputs; everything
+/// else is indexed by output.
+#[derive(Debug, Clone)]
+pub struct VideoOutputs {
+    outputs: Vec<VideoOutput>,
+    presets: Arc<Vec<GeometryPreset>>,
+    /// The output each video channel is sent to.
+    routes: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct VideoOutput {
+    pixel_aspect_ratio: f64,
+    dimming_curve: DimmingCurve,
+    white_point: WhitePoint,
+    when_idle: IdlePolicy,
+    /// Bandwidth budget in bytes per second.
+    bandwidth: Option<f64>,
+    latency: Duration,
+    aspect_ratio: f64,
+    /// Index of the selected geometry preset, if any.
+    geometry: Option<usize>,
+}
+
+impl Default for VideoOutput {
+    fn default() -> Self {
+        Self {
+            pixel_aspect_ratio: default_pixel_aspect_ratio(),
+            dimming_curve: DimmingCurve::default(),
+            white_point: WhitePoint::default(),
+            when_idle: IdlePolicy::default(),
+            bandwidth: None,
+            latency: Duration::ZERO,
+            aspect_ratio: default_aspect_ratio(),
+            geometry: None,
+        }
+    }
+}
+
+impl VideoOutputs {
+    /// Create output adjustments from configuration, sending each video
+    /// channel to the output given by routes.
+    /// Unconfigured outputs are passed through unmodified.
+    pub fn new(
+        configs: &[VideoOutputConfig],
+        presets: &[GeometryPreset],
+        routes: Vec<usize>,
+    ) -> Self {
+        let mut outputs = vec![VideoOutput::default(); Mixer::N_VIDEO_CHANNELS];
+        for cfg in configs {
+            outputs[cfg.channel].pixel_aspect_ratio = cfg.pixel_aspect_ratio;
+            outputs[cfg.channel].dimming_curve = cfg.dimming_curve;
+            outputs[cfg.channel].when_idle = cfg.when_idle;
+            outputs[cfg.channel].bandwidth = cfg.bandwidth.map(|kb| kb * 1000.0);
+            outputs[cfg.channel].latency = Duration::from_secs_f64(cfg.latency);
+            outputs[cfg.channel].aspect_ratio = cfg.aspect_ratio;
+            if let Some(kelvin) = cfg.color_temperature {
+                outputs[cfg.channel].white_point = WhitePoint::from_temperature(kelvin);
+            }
+        }
+        Self {
+            outputs,
+            presets: Arc::new(presets.to_vec()),
+            routes,
+        }
+    }
+
+    /// Replace the configuration of every output and the routing, such as
+    /// when switching output profiles.  The geometry preset selected for each
+    /// output is kept.
+    pub fn reconfigure(&mut self, configs: &[VideoOutputConfig], routes: Vec<usize>) {
+        let mut reconfigured = Self::new(configs, &[], routes);
+        for (output, old) in reconfigured.outputs.iter_mut().zip(&self.outputs) {
+            output.geometry = old.geometry;
+        }
+        reconfigured.presets = self.presets.clone();
+        *self = reconfigured;
+    }
+
+    /// The output a video channel is sent to.
+    pub fn output(&self, video_channel: usize) -> usize {
+        self.routes
+            .get(video_channel)
+            .copied()
+            .unwrap_or(video_channel)
+    }
+
+    /// The video channel sent to an output.
+    pub fn source(&self, output: usize) -> usize {
+        self.routes
+            .iter()
+            .position(|out| *out == output)
+            .unwrap_or(output)
+    }
+
+    /// Bias a video channel's whites toward a color temperature in kelvin.
+    pub fn set_color_temperature(&mut self, video_channel: usize, kelvin: f64) {
+        self.outputs[video_channel].white_point = WhitePoint::from_temperature(kelvin);
+    }
+
+    /// Apply an output's adjustments to the layers sent to it.
+    /// White point correction works on raw color, so arcs on corrected
+    /// outputs are resolved out of the palette, and have any master color
+    /// correction applied, first.  Return the master color correction still
+    /// to be applied by the output's clients.
+    pub fn apply(
+        &self,
+        output: usize,
+        layers: &mut LayerCollection,
+        palette: Option<&Palette>,
+        color: Option<ColorCorrection>,
+    ) -> Option<ColorCorrection> {
+        let adjustments = &self.outputs[output];
+        let geometry = adjustments.geometry.map(|i| &self.presets[i]);
+        adjustments.apply(geometry, palette, color, layers)
+    }
+
+    /// What to do while no client is showing a video channel.
+    pub fn idle_policy(&self, video_channel: usize) -> IdlePolicy {
+        self.outputs[video_channel].when_idle
+    }
+
+    /// The most bytes per second to publish for a video channel, if limited.
+    pub fn bandwidth(&self, video_channel: usize) -> Option<f64> {
+        self.outputs[video_channel].bandwidth
+    }
+
+    /// How long a video channel's display takes to show a frame.
+    pub fn latency(&self, video_channel: usize) -> Duration {
+        self.outputs[video_channel].latency
+    }
+
+    /// Width of a video channel's screen relative to its height.
+    pub fn aspect_ratio(&self, video_channel: usize) -> f64 {
+        self.outputs[video_channel].aspect_ratio
+    }
+
+    /// The name of the geometry preset selected for each video channel.
+    pub fn selected_geometry(&self) -> Vec<Option<String>> {
+        self.outputs
+            .iter()
+            .map(|o| o.geometry.map(|i| self.presets[i].name.clone()))
+            .collect()
+    }
+
+    /// Return the messages that select geometry presets by name for each
+    /// video channel, such as when loading a saved show.  Presets that no
+    /// longer exist are ignored.
+    pub fn restore_geometry(&self, names: &[Option<String>]) -> Vec<ControlMessage> {
+        let presets = &self.presets;
+        names
+            .iter()
+            .take(self.outputs.len())
+            .enumerate()
+            .map(|(chan, name)| {
+                let index = name.as_ref().and_then(|name| {
+                    let index = presets.iter().position(|p| &p.name == name);
+                    if index.is_none() {
+                        warn!("Saved geometry preset {} is not defined.", name);
+                    }
+                    index
+                });
+                ControlMessage::Set(StateChange::Geometry(VideoChannel(chan), index))
+            })
+            .collect()
+    }
+
+    /// Emit the current value of all controllable state.
+    pub fn emit_state<E: EmitStateChange>(&self, emitter: &mut E) {
+        for (i, output) in self.outputs.iter().enumerate() {
+            emitter.emit_video_out_state_change(StateChange::Geometry(
+                VideoChannel(i),
+                output.geometry,
+            ));
+        }
+    }
+
+    /// Handle a control event.
+    /// Emit any state changes that have happened as a result of handling.
+    pub fn control<E: EmitStateChange>(&mut self, msg: ControlMessage, emitter: &mut E) {
+        match msg {
+            ControlMessage::Set(sc) => self.handle_state_change(sc, emitter),
+        }
+    }
+
+    fn handle_state_change<E: EmitStateChange>(&mut self, sc: StateChange, emitter: &mut E) {
+        match sc {
+            StateChange::Geometry(VideoChannel(chan), preset) => {
+                if chan >= self.outputs.len() {
+                    return;
+                }
+                if let Some(i) = preset {
+                    if i >= self.presets.len() {
+                        return;
+                    }
+                }
+                self.outputs[chan].geometry = preset;
+            }
+        }
+        emitter.emit_video_out_state_change(sc);
+    }
+}
+
+impl VideoOutput {
+    fn apply(
+        &self,
+        geometry: Option<&GeometryPreset>,
+        palette: Option<&Palette>,
+        color: Option<ColorCorrection>,
+        layers: &mut LayerCollection,
+    ) -> Option<ColorCorrection> {
+        let transform_geometry = geometry.is_some() || self.pixel_aspect_ratio != 1.0;
+        let shape_levels = self.dimming_curve != DimmingCurve::Linear;
+        let correct_color = !self.white_point.is_neutral();
+        if !transform_geometry && !shape_levels && !correct_color {
+            return color;
+        }
+        // Squeeze horizontally to cancel out the stretch of the display.
+        let aspect = [[1.0 / self.pixel_aspect_ratio, 0.0], [0.0, 1.0]];
+        let (m, offset) = match geometry {
+            Some(g) => {
+                let (sin, cos) = (g.rotation * TWO_PI).sin_cos();
+                let rot_scale = [
+                    [g.scale * cos, -g.scale * sin],
+                    [g.scale * sin, g.scale * cos],
+                ];
+                (
+                    mul(aspect, rot_scale),
+                    (aspect[0][0] * g.x_offset, g.y_offset),
+                )
+            }
+            None => (aspect, (0.0, 0.0)),
+        };
+        for layer in layers.iter_mut() {
+            // Layers may be shared between video channels; only copy if so.
+            for arc in Arc::make_mut(layer).iter_mut() {
+                if transform_geometry {
+                    transform(arc, m);
+                    arc.x += offset.0;
+                    arc.y += offset.1;
+                }
+                arc.level = self.dimming_curve.apply(arc.level);
+                if correct_color {
+                    palette::resolve(arc, palette);
+                    if let Some(color) = &color {
+                        color.apply(arc);
+                    }
+                    self.white_point.apply(arc);
+                }
+            }
+        }
+        if correct_color {
+            None
+        } else {
+            color
+        }
+    }
+}
+
+/// Multiply two 2x2 matrices.
+fn mul(a: [[f64; 2]; 2], b: [[f64; 2]; 2]) -> [[f64; 2]; 2] {
+    [
+        [
+            a[0][0] * b[0][0] + a[0][1] * b[1][0],
+            a[0][0] * b[0][1] + a[0][1] * b[1][1],
+        ],
+        [
+            a[1][0] * b[0][0] + a[1][1] * b[1][0],
+            a[1][0] * b[0][1] + a[1][1] * b[1][1],
+        ],
+    ]
+}
+
+/// Apply a linear transformation to an arc segment, such that every point
+/// drawn on the segment is transformed by the matrix m.
+///
+/// An ellipse transformed by an arbitrary linear map is still an ellipse, but
+/// in general with different radii and rotation.  We find them from the
+/// singular value decomposition of the combined map from the unit circle.
+fn transform(arc: &mut ArcSegment, m: [[f64; 2]; 2]) {
+    let (x, y) = (arc.x, arc.y);
+    arc.x = m[0][0] * x + m[0][1] * y;
+    arc.y = m[1][0] * x + m[1][1] * y;
+
+    // The ellipse is the unit circle, scaled by its radii, then rotated.
+    let (sin, cos) = (arc.rot_angle * TWO_PI).sin_cos();
+    let c = mul(
+        m,
+        [
+            [cos * arc.rad_x, -sin * arc.rad_y],
+            [sin * arc.rad_x, cos * arc.rad_y],
+        ],
+    );
+
+    // Closed-form 2x2 SVD: c = rot(phi) * diag(sx, sy) * rot(theta).
+    let e = (c[0][0] + c[1][1]) / 2.0;
+    let f = (c[0][0] - c[1][1]) / 2.0;
+    let g = (c[1][0] + c[0][1]) / 2.0;
+    let h = (c[1][0] - c[0][1]) / 2.0;
+    let q = e.hypot(h);
+    let r = f.hypot(g);
+    let a1 = g.atan2(f);
+    let a2 = h.atan2(e);
+    let theta = (a2 - a1) / 2.0;
+    let phi = (a2 + a1) / 2.0;
+
+    arc.rad_x = q + r;
+    // Negative if the transformation mirrors the image.
+    arc.rad_y = q - r;
+    arc.rot_angle = phi / TWO_PI;
+    // Rotation on the input side of the scaling moves the arc endpoints.
+    arc.start += theta / TWO_PI;
+    arc.stop += theta / TWO_PI;
+}
+
+pub enum ControlMessage {
+    Set(StateChange),
+}
+
+pub enum StateChange {
+    /// Select a geometry preset by index for a video channel, or none.
+    Geometry(VideoChannel, Option<usize>),
+}
+
+pub trait EmitStateChange {
+    fn emit_video_out_state_change(&mut self, sc: StateChange);
+}
+
+impl<T: EmitShowStateChange> EmitStateChange for T {
+    fn emit_video_out_state_change(&mut self, sc: StateChange) {
+        use crate::show::StateChange as ShowStateChange;
+        self.emit(ShowStateChange::VideoOut(sc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::master_ui::DummyEmitter;
+    use tunnels_lib::assert_almost_eq;
+
+    fn arc(rad_x: f64, rad_y: f64, rot_angle: f64) -> ArcSegment {
+        ArcSegment {
+            level: 1.0,
+            thickness: 0.1,
+            hue: 0.0,
+            sat: 0.0,
+            val: 1.0,
+            x: 0.2,
+            y: -0.1,
+            rad_x,
+            rad_y,
+            start: 0.1,
+            stop: 0.35,
+            rot_angle,
+            palette: false,
+        }
+    }
+
+    /// The point at parametric angle t along the arc, as a client draws it.
+    fn point(arc: &ArcSegment, t: f64) -> (f64, f64) {
+        let (sin_r, cos_r) = (arc.rot_angle * TWO_PI).sin_cos();
+        let (sin_t, cos_t) = ((arc.start + t * (arc.stop - arc.start)) * TWO_PI).sin_cos();
+        let (px, py) = (cos_t * arc.rad_x, sin_t * arc.rad_y);
+        (
+            arc.x + cos_r * px - sin_r * py,
+            arc.y + sin_r * px + cos_r * py,
+        )
+    }
+
+    #[test]
+    fn test_transform() {
+        let m = [[0.75, 0.0], [0.0, 1.0]];
+        for &(rad_x, rad_y, rot) in &[(0.5, 0.5, 0.0), (0.5, 0.5, 0.3), (0.8, 0.3, 0.15)] {
+            let before = arc(rad_x, rad_y, rot);
+            let mut after = before.clone();
+            transform(&mut after, m);
+            for i in 0..=10 {
+                let t = i as f64 / 10.0;
+                let (x0, y0) = point(&before, t);
+                let (x1, y1) = point(&after, t);
+                assert_almost_eq(0.75 * x0, x1);
+                assert_almost_eq(y0, y1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_geometry() {
+        let preset = GeometryPreset {
+            name: "venue".to_string(),
+            scale: 0.5,
+            x_offset: 0.1,
+            y_offset: -0.2,
+            rotation: 0.25,
+        };
+        let output = VideoOutput {
+            pixel_aspect_ratio: 2.0,
+            dimming_curve: DimmingCurve::Linear,
+            white_point: WhitePoint::default(),
+            when_idle: IdlePolicy::Run,
+            bandwidth: None,
+            latency: Duration::ZERO,
+            geometry: Some(0),
+            aspect_ratio: default_aspect_ratio(),
+        };
+        let before = arc(0.8, 0.3, 0.15);
+        let mut layers = vec![Arc::new(vec![before.clone()])];
+        output.apply(Some(&preset), None, None, &mut layers);
+        let after = &layers[0][0];
+        for i in 0..=10 {
+            let t = i as f64 / 10.0;
+            let (x0, y0) = point(&before, t);
+            let (x1, y1) = point(after, t);
+            // A quarter turn maps (x, y) to (-y, x).
+            assert_almost_eq((-0.5 * y0 + 0.1) / 2.0, x1);
+            assert_almost_eq(0.5 * x0 - 0.2, y1);
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        let mut routes: Vec<usize> = (0..Mixer::N_VIDEO_CHANNELS).collect();
+        routes.swap(0, 2);
+        let outputs = VideoOutputs::new(&[], &[], routes);
+        assert_eq!(2, outputs.output(0));
+        assert_eq!(0, outputs.output(2));
+        assert_eq!(1, outputs.output(1));
+        assert_eq!(2, outputs.source(0));
+        assert_eq!(1, outputs.source(1));
+    }
+
+    #[test]
+    fn test_restore_geometry() {
+        let preset = GeometryPreset {
+            name: "venue".to_string(),
+            scale: 1.0,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            rotation: 0.0,
+        };
+        let routes = (0..Mixer::N_VIDEO_CHANNELS).collect();
+        let mut outputs = VideoOutputs::new(&[], &[preset], routes);
+        let saved = vec![None, Some("venue".to_string()), Some("gone".to_string())];
+        for msg in outputs.restore_geometry(&saved) {
+            outputs.control(msg, &mut DummyEmitter);
+        }
+        assert_eq!(
+            vec![None, Some("venue".to_string()), None],
+            outputs.selected_geometry()[..3]
+        );
+    }
+
+    #[test]
+    fn test_dimming_curves() {
+        for &curve in &[
+            DimmingCurve::Linear,
+            DimmingCurve::SquareLaw,
+            DimmingCurve::SCurve,
+        ] {
+            assert_eq!(0.0, curve.apply(0.0));
+            assert_eq!(1.0, curve.apply(1.0));
+        }
+        assert_eq!(0.25, DimmingCurve::SquareLaw.apply(0.5));
+        assert_eq!(0.5, DimmingCurve::SCurve.apply(0.5));
+        assert!(DimmingCurve::SCurve.apply(0.1) < 0.1);
+        assert!(DimmingCurve::SCurve.apply(0.9) > 0.9);
+    }
+}
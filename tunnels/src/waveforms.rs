@@ -124,6 +124,30 @@ pub fn sawtooth(
     }
 }
 
+/// Smooth random noise on [-1, 1].
+/// Random values are chosen at every integer t and eased between, so the
+/// noise wanders through about one new value per unit of t.
+/// Each seed produces an independent signal.
+pub fn noise(t: f64, seed: u32) -> f64 {
+    let i = t.floor();
+    let frac = t - i;
+    let (a, b) = (
+        random_value(i as i64, seed),
+        random_value(i as i64 + 1, seed),
+    );
+    a + (b - a) * frac * frac * (3.0 - 2.0 * frac)
+}
+
+/// Hash an integer and seed to a random value on [-1, 1].
+pub fn random_value(i: i64, seed: u32) -> f64 {
+    let mut x = (i as u64) ^ (seed as u64).rotate_left(32);
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
 #[cfg(test)]
 #[allow(unused)]
 mod test {
@@ -131,6 +155,19 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_noise() {
+        assert_eq!(random_value(3, 7), noise(3.0, 7));
+        assert_ne!(noise(3.5, 7), noise(3.5, 8));
+        let mut last = noise(0.0, 1);
+        for i in 1..1000 {
+            let v = noise(i as f64 * 0.01, 1);
+            assert!((-1.0..=1.0).contains(&v));
+            assert!((v - last).abs() < 0.05, "noise jumped at {}", i);
+            last = v;
+        }
+    }
+
     fn debug() -> Result<(), Box<dyn Error>> {
         use plotters::prelude::*;
         let points = generate_span(sawtooth, 0.1, 0.5, true);
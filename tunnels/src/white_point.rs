@@ -0,0 +1,124 @@
+//! Color temperature correction for video outputs.
+//!
+//! Different projector models render the same white quite differently.  A
+//! white point scales the red, green, and blue components of every color sent
+//! to an output, so that a rig with mixed displays can be matched by eye.
+//!
+//! Arcs carry color as HSV, so we convert into RGB using the same model as
+//! the client, scale, and convert back.
+
+use simple_error::bail;
+use std::error::Error;
+use tunnels_lib::{
+    color::{hsv_to_rgb, rgb_to_hsv},
+    ArcSegment,
+};
+
+/// Color temperature of an uncorrected output, in kelvin.
+pub const NEUTRAL_TEMPERATURE: f64 = 6500.0;
+
+const MIN_TEMPERATURE: f64 = 1000.0;
+const MAX_TEMPERATURE: f64 = 40000.0;
+
+/// Relative gains applied to the red, green, and blue components of a color.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WhitePoint([f64; 3]);
+
+impl WhitePoint {
+    /// Check that a color temperature is within the supported range.
+    pub fn validate_temperature(kelvin: f64) -> Result<(), Box<dyn Error>> {
+        if !(MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&kelvin) {
+            bail!(
+                "Color temperature {} K is out of range; it must be between {} K and {} K.",
+                kelvin,
+                MIN_TEMPERATURE,
+                MAX_TEMPERATURE
+            );
+        }
+        Ok(())
+    }
+
+    /// Bias whites toward the given color temperature.
+    /// Lower temperatures are warmer, higher temperatures are cooler.
+    ///
+    /// The gains are normalized so that the brightest component is unscaled;
+    /// correction only ever dims a color.
+    pub fn from_temperature(kelvin: f64) -> Self {
+        let target = blackbody(kelvin);
+        let neutral = blackbody(NEUTRAL_TEMPERATURE);
+        let mut gains = [
+            target[0] / neutral[0],
+            target[1] / neutral[1],
+            target[2] / neutral[2],
+        ];
+        let max = gains.iter().cloned().fold(0.0, f64::max);
+        for gain in &mut gains {
+            *gain /= max;
+        }
+        Self(gains)
+    }
+
+    /// Return true if this white point leaves colors unchanged.
+    pub fn is_neutral(&self) -> bool {
+        self.0 == [1.0; 3]
+    }
+
+    /// Correct the color of an arc.
+    pub fn apply(&self, arc: &mut ArcSegment) {
+        let [r, g, b] = hsv_to_rgb(arc.hue, arc.sat, arc.val);
+        let (hue, sat, val) = rgb_to_hsv(r * self.0[0], g * self.0[1], b * self.0[2]);
+        // Leave the hue of grays alone so it still interpolates sensibly.
+        if sat > 0.0 {
+            arc.hue = hue;
+        }
+        arc.sat = sat;
+        arc.val = val;
+    }
+}
+
+impl Default for WhitePoint {
+    fn default() -> Self {
+        Self([1.0; 3])
+    }
+}
+
+/// Approximate the color of a blackbody radiator as RGB on [0, 1].
+/// Based on Tanner Helland's fit to the CIE color matching functions.
+fn blackbody(kelvin: f64) -> [f64; 3] {
+    let t = kelvin / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592)
+    };
+    let g = if t <= 66.0 {
+        99.4708025861 * t.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+    let scale = |c: f64| c.clamp(0.0, 255.0) / 255.0;
+    [scale(r), scale(g), scale(b)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_temperature() {
+        assert!(WhitePoint::from_temperature(NEUTRAL_TEMPERATURE).is_neutral());
+        let [r, _, b] = WhitePoint::from_temperature(3200.0).0;
+        assert_eq!(1.0, r);
+        assert!(b < 1.0);
+        let [r, _, b] = WhitePoint::from_temperature(9000.0).0;
+        assert!(r < 1.0);
+        assert_eq!(1.0, b);
+    }
+}
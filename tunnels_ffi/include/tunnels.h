@@ -0,0 +1,131 @@
+#ifndef TUNNELS_H
+#define TUNNELS_H
+
+/* Generated by cbindgen from tunnels_ffi; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// How a layer is combined with the layers drawn beneath it.
+typedef enum TunnelsBlendMode {
+  // Cover what is beneath in proportion to level.
+  TunnelsBlendMode_ALPHA,
+  // Add to what is beneath, so that overlapping beams glow.
+  TunnelsBlendMode_ADDITIVE,
+  // Darken what is beneath in proportion to level.
+  TunnelsBlendMode_MASK,
+} TunnelsBlendMode;
+
+// Collects the chunks of large snapshots.
+typedef struct TunnelsReassembler TunnelsReassembler;
+
+// A parsed snapshot of a single frame.
+typedef struct TunnelsSnapshot TunnelsSnapshot;
+
+// A command to draw a single arc segment, in resolved color.
+// Angles are fractions of a full turn.
+typedef struct TunnelsArc {
+  double level;
+  double thickness;
+  double hue;
+  double sat;
+  double val;
+  double x;
+  double y;
+  double rad_x;
+  double rad_y;
+  double start;
+  double stop;
+  double rot_angle;
+} TunnelsArc;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parse a serialized snapshot, the last part of a two-part frame message.
+// Return null if it can't be parsed.
+//
+// # Safety
+// buf must point to len readable bytes.
+TunnelsSnapshot *tunnels_snapshot_parse(const uint8_t *buf, uintptr_t len);
+
+// Free a snapshot.  Does nothing if snapshot is null.
+//
+// # Safety
+// snapshot must be null or have come from this library, and not already
+// have been freed.
+void tunnels_snapshot_free(TunnelsSnapshot *snapshot);
+
+// The number of the frame, counting up from the start of the show.
+//
+// # Safety
+// snapshot must be a live snapshot from this library.
+uint64_t tunnels_snapshot_frame_number(const TunnelsSnapshot *snapshot);
+
+// The time on the server the frame is for, in microseconds.
+//
+// # Safety
+// snapshot must be a live snapshot from this library.
+int64_t tunnels_snapshot_time(const TunnelsSnapshot *snapshot);
+
+// The number of layers in the frame, drawn from first to last.
+//
+// # Safety
+// snapshot must be a live snapshot from this library.
+uintptr_t tunnels_snapshot_layer_count(const TunnelsSnapshot *snapshot);
+
+// The number of arcs in a layer, or 0 if there is no such layer.
+//
+// # Safety
+// snapshot must be a live snapshot from this library.
+uintptr_t tunnels_snapshot_arc_count(const TunnelsSnapshot *snapshot, uintptr_t layer);
+
+// How a layer is blended.
+//
+// # Safety
+// snapshot must be a live snapshot from this library.
+TunnelsBlendMode tunnels_snapshot_blend_mode(const TunnelsSnapshot *snapshot, uintptr_t layer);
+
+// Copy an arc of a layer into out.  Return false, leaving out untouched,
+// if there is no such arc.
+//
+// # Safety
+// snapshot must be a live snapshot from this library, and out must point
+// to a writable TunnelsArc.
+bool tunnels_snapshot_arc(const TunnelsSnapshot *snapshot,
+                          uintptr_t layer,
+                          uintptr_t index,
+                          TunnelsArc *out);
+
+// Create a reassembler for chunked snapshots.  Keep one for each
+// subscription.
+TunnelsReassembler *tunnels_reassembler_new(void);
+
+// Free a reassembler.  Does nothing if reassembler is null.
+//
+// # Safety
+// reassembler must be null or have come from this library, and not already
+// have been freed.
+void tunnels_reassembler_free(TunnelsReassembler *reassembler);
+
+// Take in one chunk, the last two parts of a three-part frame message.
+// Return the snapshot if this chunk completed it, or null if more chunks
+// are needed or the frame was damaged.
+//
+// # Safety
+// reassembler must be a live reassembler from this library, header must
+// point to header_len readable bytes, and chunk to chunk_len readable bytes.
+TunnelsSnapshot *tunnels_reassembler_push(TunnelsReassembler *reassembler,
+                                          const uint8_t *header,
+                                          uintptr_t header_len,
+                                          const uint8_t *chunk,
+                                          uintptr_t chunk_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TUNNELS_H */
@@ -0,0 +1,368 @@
+//! A C interface to the frame stream, for render clients written in other
+//! languages such as C++ or openFrameworks.
+//!
+//! Clients subscribe to the server's frames over 0mq themselves, and hand
+//! each message to this library rather than decoding msgpack.  A frame
+//! arrives either as a two-part message of the video channel topic and the
+//! serialized snapshot, which is parsed with `tunnels_snapshot_parse`, or as
+//! a run of three-part messages of the topic, a chunk header, and a chunk,
+//! which are put back together with a `TunnelsReassembler`.
+//!
+//! Parsed snapshots are fully resolved: arcs drawn from a palette and any
+//! master color correction have been applied, so every arc carries the color
+//! it should be drawn in.  Everything returned is owned by the caller and
+//! must be freed with the matching function.
+//!
+//! The C header in include/tunnels.h is generated with cbindgen; see
+//! cbindgen.toml.
+
+use std::{ptr, slice};
+use tunnels_lib::{
+    chunk::{ChunkHeader, Reassembler},
+    ArcSegment, BlendMode, Snapshot,
+};
+
+/// A parsed snapshot of a single frame.
+pub struct TunnelsSnapshot(Snapshot);
+
+/// Collects the chunks of large snapshots.
+pub struct TunnelsReassembler(Reassembler);
+
+/// A command to draw a single arc segment, in resolved color.
+/// Angles are fractions of a full turn.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TunnelsArc {
+    pub level: f64,
+    pub thickness: f64,
+    pub hue: f64,
+    pub sat: f64,
+    pub val: f64,
+    pub x: f64,
+    pub y: f64,
+    pub rad_x: f64,
+    pub rad_y: f64,
+    pub start: f64,
+    pub stop: f64,
+    pub rot_angle: f64,
+}
+
+impl From<&ArcSegment> for TunnelsArc {
+    fn from(arc: &ArcSegment) -> Self {
+        Self {
+            level: arc.level,
+            thickness: arc.thickness,
+            hue: arc.hue,
+            sat: arc.sat,
+            val: arc.val,
+            x: arc.x,
+            y: arc.y,
+            rad_x: arc.rad_x,
+            rad_y: arc.rad_y,
+            start: arc.start,
+            stop: arc.stop,
+            rot_angle: arc.rot_angle,
+        }
+    }
+}
+
+/// How a layer is combined with the layers drawn beneath it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelsBlendMode {
+    /// Cover what is beneath in proportion to level.
+    Alpha,
+    /// Add to what is beneath, so that overlapping beams glow.
+    Additive,
+    /// Darken what is beneath in proportion to level.
+    Mask,
+}
+
+impl From<BlendMode> for TunnelsBlendMode {
+    fn from(mode: BlendMode) -> Self {
+        match mode {
+            BlendMode::Alpha => Self::Alpha,
+            BlendMode::Additive => Self::Additive,
+            BlendMode::Mask => Self::Mask,
+        }
+    }
+}
+
+/// Parse and resolve a serialized snapshot, and box it up for the caller.
+fn parse(buf: &[u8]) -> *mut TunnelsSnapshot {
+    match rmp_serde::from_read::<_, Snapshot>(buf) {
+        Ok(mut snapshot) => {
+            snapshot.resolve_color();
+            Box::into_raw(Box::new(TunnelsSnapshot(snapshot)))
+        }
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Borrow a snapshot from C.
+///
+/// # Safety
+/// snapshot must be a live snapshot from this library.
+unsafe fn snapshot_ref<'a>(snapshot: *const TunnelsSnapshot) -> &'a Snapshot {
+    &(*snapshot).0
+}
+
+/// Return a slice over a buffer from C, treating null as empty.
+///
+/// # Safety
+/// Unless null, buf must point to len readable bytes.
+unsafe fn bytes<'a>(buf: *const u8, len: usize) -> &'a [u8] {
+    if buf.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(buf, len)
+    }
+}
+
+/// Parse a serialized snapshot, the last part of a two-part frame message.
+/// Return null if it can't be parsed.
+///
+/// # Safety
+/// buf must point to len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_parse(
+    buf: *const u8,
+    len: usize,
+) -> *mut TunnelsSnapshot {
+    parse(bytes(buf, len))
+}
+
+/// Free a snapshot.  Does nothing if snapshot is null.
+///
+/// # Safety
+/// snapshot must be null or have come from this library, and not already
+/// have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_free(snapshot: *mut TunnelsSnapshot) {
+    if !snapshot.is_null() {
+        drop(Box::from_raw(snapshot));
+    }
+}
+
+/// The number of the frame, counting up from the start of the show.
+///
+/// # Safety
+/// snapshot must be a live snapshot from this library.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_frame_number(snapshot: *const TunnelsSnapshot) -> u64 {
+    snapshot_ref(snapshot).frame_number
+}
+
+/// The time on the server the frame is for, in microseconds.
+///
+/// # Safety
+/// snapshot must be a live snapshot from this library.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_time(snapshot: *const TunnelsSnapshot) -> i64 {
+    snapshot_ref(snapshot).time.0
+}
+
+/// The number of layers in the frame, drawn from first to last.
+///
+/// # Safety
+/// snapshot must be a live snapshot from this library.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_layer_count(snapshot: *const TunnelsSnapshot) -> usize {
+    snapshot_ref(snapshot).layers.len()
+}
+
+/// The number of arcs in a layer, or 0 if there is no such layer.
+///
+/// # Safety
+/// snapshot must be a live snapshot from this library.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_arc_count(
+    snapshot: *const TunnelsSnapshot,
+    layer: usize,
+) -> usize {
+    snapshot_ref(snapshot)
+        .layers
+        .get(layer)
+        .map_or(0, |l| l.len())
+}
+
+/// How a layer is blended.
+///
+/// # Safety
+/// snapshot must be a live snapshot from this library.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_blend_mode(
+    snapshot: *const TunnelsSnapshot,
+    layer: usize,
+) -> TunnelsBlendMode {
+    snapshot_ref(snapshot).blend_mode(layer).into()
+}
+
+/// Copy an arc of a layer into out.  Return false, leaving out untouched,
+/// if there is no such arc.
+///
+/// # Safety
+/// snapshot must be a live snapshot from this library, and out must point
+/// to a writable TunnelsArc.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_snapshot_arc(
+    snapshot: *const TunnelsSnapshot,
+    layer: usize,
+    index: usize,
+    out: *mut TunnelsArc,
+) -> bool {
+    match snapshot_ref(snapshot)
+        .layers
+        .get(layer)
+        .and_then(|l| l.get(index))
+    {
+        Some(arc) if !out.is_null() => {
+            *out = arc.into();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Create a reassembler for chunked snapshots.  Keep one for each
+/// subscription.
+#[no_mangle]
+pub extern "C" fn tunnels_reassembler_new() -> *mut TunnelsReassembler {
+    Box::into_raw(Box::new(TunnelsReassembler(Reassembler::new())))
+}
+
+/// Free a reassembler.  Does nothing if reassembler is null.
+///
+/// # Safety
+/// reassembler must be null or have come from this library, and not already
+/// have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_reassembler_free(reassembler: *mut TunnelsReassembler) {
+    if !reassembler.is_null() {
+        drop(Box::from_raw(reassembler));
+    }
+}
+
+/// Take in one chunk, the last two parts of a three-part frame message.
+/// Return the snapshot if this chunk completed it, or null if more chunks
+/// are needed or the frame was damaged.
+///
+/// # Safety
+/// reassembler must be a live reassembler from this library, header must
+/// point to header_len readable bytes, and chunk to chunk_len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tunnels_reassembler_push(
+    reassembler: *mut TunnelsReassembler,
+    header: *const u8,
+    header_len: usize,
+    chunk: *const u8,
+    chunk_len: usize,
+) -> *mut TunnelsSnapshot {
+    let header = match ChunkHeader::decode(bytes(header, header_len)) {
+        Some(header) => header,
+        None => return ptr::null_mut(),
+    };
+    let reassembler = &mut (*reassembler).0;
+    match reassembler.push(header, bytes(chunk, chunk_len)) {
+        Some(buf) => parse(&buf),
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use tunnels_lib::{chunk::split, color::ColorCorrection, Timestamp};
+
+    fn snapshot() -> Snapshot {
+        let arc = ArcSegment {
+            level: 1.0,
+            thickness: 0.1,
+            hue: 0.25,
+            sat: 1.0,
+            val: 1.0,
+            x: 0.0,
+            y: 0.5,
+            rad_x: 0.5,
+            rad_y: 0.5,
+            start: 0.0,
+            stop: 0.5,
+            rot_angle: 0.0,
+            palette: false,
+        };
+        Snapshot {
+            frame_number: 7,
+            time: Timestamp::from_micros(1000),
+            layers: vec![Arc::new(vec![arc.clone()]), Arc::new(vec![arc; 2])],
+            palette: None,
+            blend: vec![BlendMode::Alpha, BlendMode::Additive],
+            color: Some(ColorCorrection {
+                brightness: 0.5,
+                ..ColorCorrection::IDENTITY
+            }),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let buf = rmp_serde::to_vec(&snapshot()).unwrap();
+        unsafe {
+            let parsed = tunnels_snapshot_parse(buf.as_ptr(), buf.len());
+            assert!(!parsed.is_null());
+            assert_eq!(7, tunnels_snapshot_frame_number(parsed));
+            assert_eq!(1000, tunnels_snapshot_time(parsed));
+            assert_eq!(2, tunnels_snapshot_layer_count(parsed));
+            assert_eq!(2, tunnels_snapshot_arc_count(parsed, 1));
+            assert_eq!(0, tunnels_snapshot_arc_count(parsed, 2));
+            assert_eq!(
+                TunnelsBlendMode::Additive,
+                tunnels_snapshot_blend_mode(parsed, 1)
+            );
+            assert_eq!(
+                TunnelsBlendMode::Alpha,
+                tunnels_snapshot_blend_mode(parsed, 2)
+            );
+
+            let mut arc = TunnelsArc::default();
+            assert!(tunnels_snapshot_arc(parsed, 1, 1, &mut arc));
+            assert_eq!(0.25, arc.hue);
+            // Color correction has been applied.
+            assert_eq!(0.5, arc.val);
+            assert!(!tunnels_snapshot_arc(parsed, 1, 2, &mut arc));
+            tunnels_snapshot_free(parsed);
+
+            assert!(tunnels_snapshot_parse(buf.as_ptr(), 3).is_null());
+            assert!(tunnels_snapshot_parse(ptr::null(), 0).is_null());
+        }
+    }
+
+    #[test]
+    fn test_reassemble() {
+        // Pad the frame out so that it takes more than one chunk.
+        let mut big = snapshot();
+        big.layers = vec![Arc::new(vec![big.layers[0][0].clone(); 30_000])];
+        let buf = rmp_serde::to_vec(&big).unwrap();
+        let chunks: Vec<_> = split(&buf, big.frame_number).unwrap().collect();
+        assert!(chunks.len() > 1);
+        unsafe {
+            let reassembler = tunnels_reassembler_new();
+            let mut parsed: *mut TunnelsSnapshot = ptr::null_mut();
+            for (header, chunk) in chunks {
+                let header = header.encode();
+                assert!(parsed.is_null());
+                parsed = tunnels_reassembler_push(
+                    reassembler,
+                    header.as_ptr(),
+                    header.len(),
+                    chunk.as_ptr(),
+                    chunk.len(),
+                );
+            }
+            assert!(!parsed.is_null());
+            assert_eq!(30_000, tunnels_snapshot_arc_count(parsed, 0));
+            tunnels_snapshot_free(parsed);
+            tunnels_reassembler_free(reassembler);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Math on unit angles, where one full turn is 1.0.
+//!
+//! Angles are periodic, so naive arithmetic goes the long way around when
+//! values straddle the origin.  Everything that fades, morphs, or interpolates
+//! an angle should use these helpers rather than rolling its own.
+
+/// True modulus operator.
+#[inline(always)]
+pub fn modulo(a: f64, b: f64) -> f64 {
+    ((a % b) + b) % b
+}
+
+/// Wrap an angle onto [0, 1).
+#[inline(always)]
+pub fn wrap(a: f64) -> f64 {
+    modulo(a, 1.0)
+}
+
+/// Minimum included angle between two unit angles.
+/// Might be negative.
+#[inline(always)]
+pub fn min_included_angle(a: f64, b: f64) -> f64 {
+    ((((b - a) % 1.0) + 1.5) % 1.0) - 0.5
+}
+
+/// Interpolate between two angles along the shortest path between them.
+/// Alpha is on [0, 1]; the result is wrapped onto [0, 1).
+#[inline(always)]
+pub fn interpolate(a: f64, b: f64, alpha: f64) -> f64 {
+    wrap(a + min_included_angle(a, b) * alpha)
+}
+
+/// Clamp an angle to the arc that runs in the positive direction from start
+/// to stop.  Angles outside the arc snap to whichever end is closer.
+/// The result is wrapped onto [0, 1).
+pub fn clamp(a: f64, start: f64, stop: f64) -> f64 {
+    let (a, start) = (wrap(a), wrap(start));
+    let span = wrap(stop - start);
+    let offset = wrap(a - start);
+    if offset <= span {
+        return a;
+    }
+    // Outside the arc; compare the distance past the stop with the distance
+    // before the start.
+    if offset - span <= 1.0 - offset {
+        wrap(start + span)
+    } else {
+        start
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assert_almost_eq;
+
+    #[test]
+    fn test_min_included_angle() {
+        assert_almost_eq(0.1, min_included_angle(0.0, 0.1));
+        assert_almost_eq(-0.1, min_included_angle(0.0, 0.9));
+        assert_almost_eq(0.2, min_included_angle(0.9, 0.1));
+        assert_almost_eq(0.0, min_included_angle(0.3, 1.3));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        assert_almost_eq(0.0, interpolate(0.0, 0.0, 0.0));
+        assert_almost_eq(0.0, interpolate(0.0, 1.0, 0.5));
+        assert_almost_eq(0.95, interpolate(0.0, 0.9, 0.5));
+        assert_almost_eq(0.0, interpolate(0.2, 0.8, 0.5));
+        assert_almost_eq(0.9, interpolate(0.8, 0.2, 0.25));
+    }
+
+    #[test]
+    fn test_clamp() {
+        // Inside a simple arc.
+        assert_almost_eq(0.3, clamp(0.3, 0.2, 0.4));
+        // Outside, nearer the stop.
+        assert_almost_eq(0.4, clamp(0.5, 0.2, 0.4));
+        // Outside, nearer the start.
+        assert_almost_eq(0.2, clamp(0.1, 0.2, 0.4));
+        // Arc that crosses the origin.
+        assert_almost_eq(0.05, clamp(0.05, 0.9, 0.1));
+        assert_almost_eq(0.95, clamp(-0.05, 0.9, 0.1));
+        assert_almost_eq(0.1, clamp(0.3, 0.9, 0.1));
+        assert_almost_eq(0.9, clamp(0.7, 0.9, 0.1));
+    }
+}
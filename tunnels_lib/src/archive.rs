@@ -0,0 +1,343 @@
+//! Recording and playback of published snapshot streams.
+//!
+//! An archive is a short magic header followed by a flat stream of msgpacked
+//! frames, each tagged with the video channel it was published on.  Frames are
+//! written in the order they were published, so timestamps are monotonic.
+//!
+//! A rolling archive is a directory of archive segments, each covering a fixed
+//! span of time.  Old segments are deleted as new ones are written, so only
+//! the most recent history is kept.
+
+use crate::{Snapshot, Timestamp};
+use rmp_serde::{decode::Error as DecodeError, Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Every archive starts with these bytes.
+const MAGIC: &[u8; 8] = b"TUNARC01";
+
+/// A single archived snapshot, along with the video channel it belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveFrame {
+    pub video_channel: u8,
+    pub snapshot: Snapshot,
+}
+
+/// Append snapshots to an archive.
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+}
+
+impl ArchiveWriter<BufWriter<File>> {
+    /// Create a new archive at the provided path, clobbering any existing file.
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, Box<dyn Error>> {
+        writer.write_all(MAGIC)?;
+        Ok(Self { writer })
+    }
+
+    /// Write a single frame to the archive.
+    pub fn write(&mut self, frame: &ArchiveFrame) -> Result<(), Box<dyn Error>> {
+        frame.serialize(&mut Serializer::new(&mut self.writer))?;
+        Ok(())
+    }
+
+    /// Flush any buffered frames to the underlying writer.
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read snapshots back out of an archive.
+pub struct ArchiveReader<R: Read> {
+    reader: R,
+}
+
+impl ArchiveReader<BufReader<File>> {
+    /// Open the archive at the provided path.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("Not a tunnels snapshot archive.");
+        }
+        Ok(Self { reader })
+    }
+
+    /// Read the next frame from the archive.
+    /// Return None if we have cleanly reached the end of the archive.
+    pub fn read(&mut self) -> Result<Option<ArchiveFrame>, Box<dyn Error>> {
+        match ArchiveFrame::deserialize(&mut Deserializer::new(&mut self.reader)) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(DecodeError::InvalidMarkerRead(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = Result<ArchiveFrame, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+/// Anything that published frames can be recorded into.
+pub trait Record {
+    fn write(&mut self, frame: &ArchiveFrame) -> Result<(), Box<dyn Error>>;
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>>;
+}
+
+impl<W: Write> Record for ArchiveWriter<W> {
+    fn write(&mut self, frame: &ArchiveFrame) -> Result<(), Box<dyn Error>> {
+        ArchiveWriter::write(self, frame)
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        ArchiveWriter::flush(self)
+    }
+}
+
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_EXTENSION: &str = "tunarc";
+
+/// Record frames into a directory of archive segments, keeping only the most
+/// recent history.
+pub struct RollingArchiveWriter {
+    dir: PathBuf,
+    segment_duration: i64,
+    max_segments: usize,
+    /// Segments on disk, oldest first.
+    segments: VecDeque<PathBuf>,
+    /// The segment being written and the time of its first frame.
+    current: Option<(Timestamp, ArchiveWriter<BufWriter<File>>)>,
+    next_index: u64,
+}
+
+impl RollingArchiveWriter {
+    /// Start a rolling archive in dir, keeping at least the provided
+    /// retention of history in segments of the provided duration.
+    ///
+    /// If dir already contains a rolling archive, such as one left behind by
+    /// a crashed show, it is moved aside rather than deleted so that it can
+    /// still be played back.
+    pub fn create(
+        dir: &Path,
+        retention: Duration,
+        segment_duration: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        if segment_duration.as_micros() == 0 {
+            bail!("Archive segment duration must be positive.");
+        }
+        if dir.exists() && !segment_paths(dir)?.is_empty() {
+            let mut previous = dir.as_os_str().to_owned();
+            previous.push(".previous");
+            let previous = PathBuf::from(previous);
+            if previous.exists() {
+                fs::remove_dir_all(&previous)?;
+            }
+            fs::rename(dir, &previous)?;
+        }
+        fs::create_dir_all(dir)?;
+        let full_segments = (retention.as_secs_f64() / segment_duration.as_secs_f64()).ceil();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            segment_duration: segment_duration.as_micros() as i64,
+            // Keep one more segment than needed, since the newest is partial.
+            max_segments: full_segments as usize + 1,
+            segments: VecDeque::new(),
+            current: None,
+            next_index: 0,
+        })
+    }
+
+    /// Close the current segment and start a new one, deleting the oldest
+    /// segments if we have exceeded our retention.
+    fn start_segment(&mut self, start: Timestamp) -> Result<(), Box<dyn Error>> {
+        if let Some((_, mut writer)) = self.current.take() {
+            writer.flush()?;
+        }
+        let path = self.dir.join(format!(
+            "{}{:08}.{}",
+            SEGMENT_PREFIX, self.next_index, SEGMENT_EXTENSION
+        ));
+        self.next_index += 1;
+        self.current = Some((start, ArchiveWriter::create(&path)?));
+        self.segments.push_back(path);
+        while self.segments.len() > self.max_segments {
+            if let Some(oldest) = self.segments.pop_front() {
+                fs::remove_file(oldest)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Record for RollingArchiveWriter {
+    fn write(&mut self, frame: &ArchiveFrame) -> Result<(), Box<dyn Error>> {
+        let time = frame.snapshot.time;
+        let roll = match &self.current {
+            Some((start, _)) => (time - *start).0 >= self.segment_duration,
+            None => true,
+        };
+        if roll {
+            self.start_segment(time)?;
+        }
+        if let Some((_, writer)) = self.current.as_mut() {
+            writer.write(frame)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some((_, writer)) = self.current.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the paths of the segments of a rolling archive, oldest first.
+fn segment_paths(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_segment = path.extension() == Some(OsStr::new(SEGMENT_EXTENSION))
+            && path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with(SEGMENT_PREFIX))
+                .unwrap_or(false);
+        if is_segment {
+            paths.push(path);
+        }
+    }
+    // Segment indices are zero-padded, so they sort by name.
+    paths.sort();
+    Ok(paths)
+}
+
+/// Every frame in an archive, in order.
+pub type Frames = Box<dyn Iterator<Item = Result<ArchiveFrame, Box<dyn Error>>>>;
+
+/// Open either a single archive or a rolling archive directory, and return
+/// an iterator over every frame it contains, in order.
+pub fn open(path: &Path) -> Result<Frames, Box<dyn Error>> {
+    if !path.is_dir() {
+        return Ok(Box::new(ArchiveReader::open(path)?));
+    }
+    let segments = segment_paths(path)?;
+    if segments.is_empty() {
+        bail!("{} contains no archive segments.", path.display());
+    }
+    Ok(Box::new(segments.into_iter().flat_map(|segment| {
+        let frames: Frames = match ArchiveReader::open(&segment) {
+            Ok(reader) => Box::new(reader),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        };
+        frames
+    })))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ArcSegment, Timestamp};
+    use std::{io::Cursor, sync::Arc};
+
+    fn frame(video_channel: u8, frame_number: u64) -> ArchiveFrame {
+        let arc = ArcSegment {
+            level: 1.0,
+            thickness: 0.1,
+            hue: 0.2,
+            sat: 0.3,
+            val: 1.0,
+            x: 0.0,
+            y: 0.0,
+            rad_x: 0.5,
+            rad_y: 0.25,
+            start: 0.0,
+            stop: 0.5,
+            rot_angle: 0.1,
+            palette: false,
+        };
+        ArchiveFrame {
+            video_channel,
+            snapshot: Snapshot {
+                frame_number,
+                time: Timestamp(frame_number as i64 * 16667),
+                layers: vec![Arc::new(vec![arc])],
+                palette: None,
+                blend: Vec::new(),
+                color: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), Box<dyn Error>> {
+        let frames = vec![frame(0, 0), frame(3, 0), frame(0, 1)];
+        let mut writer = ArchiveWriter::new(Vec::new())?;
+        for f in &frames {
+            writer.write(f)?;
+        }
+        let reader = ArchiveReader::new(Cursor::new(writer.writer))?;
+        let read_back = reader.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(frames, read_back);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rolling_retention() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("tunnels-rolling-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // Keep 2 segments of 10 frames each, plus the partial segment.
+        let segment = Duration::from_micros(10 * 16667);
+        let mut writer = RollingArchiveWriter::create(&dir, segment * 2, segment)?;
+        for n in 0..100 {
+            writer.write(&frame(0, n))?;
+        }
+        writer.flush()?;
+        assert_eq!(3, segment_paths(&dir)?.len());
+        let frames = open(&dir)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            (70..100).collect::<Vec<_>>(),
+            frames
+                .iter()
+                .map(|f| f.snapshot.frame_number)
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        assert!(ArchiveReader::new(Cursor::new(b"not an archive".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Fitting the rendered image to the projection surface.
+//!
+//! A calibration consists of a corner-pin warp of the whole image, soft edge
+//! blends for overlapping projectors, and a mask that blacks out part of the
+//! output.  All positions are expressed as fractions of the window size, with
+//! the origin at the top left and y increasing downwards, so that a
+//! calibration survives a change of resolution.
+//!
+//! Calibrations are drawn by the client, but may be managed by the server.
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::error::Error;
+
+/// Corners of an unwarped image, in order top left, top right, bottom right,
+/// bottom left.
+pub const UNIT_CORNERS: [[f64; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calibration {
+    /// Where each corner of the image lands on the output, in the same order
+    /// as UNIT_CORNERS.
+    pub corners: [[f64; 2]; 4],
+    pub blend: EdgeBlend,
+    /// Vertices of a convex region of the output to black out.
+    /// Fewer than three vertices masks nothing.
+    pub mask: Vec<[f64; 2]>,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            corners: UNIT_CORNERS,
+            blend: EdgeBlend::default(),
+            mask: Vec::new(),
+        }
+    }
+}
+
+/// Width of the brightness ramp at each edge of the output, as a fraction of
+/// the window size.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct EdgeBlend {
+    pub left: f64,
+    pub right: f64,
+    pub top: f64,
+    pub bottom: f64,
+    /// Display gamma, used to make the ramp linear in light output so that
+    /// overlapping ramps sum to a constant brightness.
+    pub gamma: f64,
+}
+
+impl Default for EdgeBlend {
+    fn default() -> Self {
+        Self {
+            left: 0.0,
+            right: 0.0,
+            top: 0.0,
+            bottom: 0.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+impl EdgeBlend {
+    /// Brightness multiplier at a position across a ramp, from 0 at the
+    /// outside edge of the output to 1 at the inside edge of the ramp.
+    pub fn ramp(&self, t: f64) -> f64 {
+        t.clamp(0.0, 1.0).powf(1.0 / self.gamma)
+    }
+}
+
+impl Calibration {
+    /// Return the warp to apply to drawn geometry, or None if there is none.
+    pub fn warp(&self) -> Option<Homography> {
+        if self.corners == UNIT_CORNERS {
+            None
+        } else {
+            Some(Homography::from_unit_square(&self.corners))
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let points = self.corners.iter().chain(self.mask.iter());
+        if !points.flatten().all(|v| v.is_finite()) {
+            bail!("Calibration points must be finite.");
+        }
+        let b = &self.blend;
+        if ![b.left, b.right, b.top, b.bottom]
+            .iter()
+            .all(|w| (0.0..=1.0).contains(w))
+        {
+            bail!("Edge blend widths must be between 0 and 1.");
+        }
+        if !b.gamma.is_finite() || b.gamma <= 0.0 {
+            bail!("Edge blend gamma is {}; it must be positive.", b.gamma);
+        }
+        Ok(())
+    }
+}
+
+/// A projective transformation of the plane, mapping the unit square onto an
+/// arbitrary quadrilateral.
+#[derive(Debug, Copy, Clone)]
+pub struct Homography([f64; 8]);
+
+impl Homography {
+    /// Find the transformation that maps each of UNIT_CORNERS onto the
+    /// corresponding provided corner.
+    /// See Heckbert, "Fundamentals of Texture Mapping and Image Warping".
+    pub fn from_unit_square(corners: &[[f64; 2]; 4]) -> Self {
+        let [[x0, y0], [x1, y1], [x2, y2], [x3, y3]] = *corners;
+        let (dx1, dx2, dx3) = (x1 - x2, x3 - x2, x0 - x1 + x2 - x3);
+        let (dy1, dy2, dy3) = (y1 - y2, y3 - y2, y0 - y1 + y2 - y3);
+        let (g, h) = if dx3 == 0.0 && dy3 == 0.0 {
+            // The quadrilateral is a parallelogram.
+            (0.0, 0.0)
+        } else {
+            let den = dx1 * dy2 - dy1 * dx2;
+            ((dx3 * dy2 - dy3 * dx2) / den, (dx1 * dy3 - dy1 * dx3) / den)
+        };
+        Self([
+            x1 - x0 + g * x1,
+            x3 - x0 + h * x3,
+            x0,
+            y1 - y0 + g * y1,
+            y3 - y0 + h * y3,
+            y0,
+            g,
+            h,
+        ])
+    }
+
+    /// Transform a point.
+    pub fn apply(&self, [u, v]: [f64; 2]) -> [f64; 2] {
+        let [a, b, c, d, e, f, g, h] = self.0;
+        let w = g * u + h * v + 1.0;
+        [(a * u + b * v + c) / w, (d * u + e * v + f) / w]
+    }
+
+    /// Transform a vertex in normalized device coordinates, which span
+    /// [-1, 1] with y increasing upwards.
+    pub fn apply_ndc(&self, [x, y]: [f32; 2]) -> [f32; 2] {
+        let [u, v] = self.apply([(f64::from(x) + 1.0) / 2.0, (1.0 - f64::from(y)) / 2.0]);
+        [(2.0 * u - 1.0) as f32, (1.0 - 2.0 * v) as f32]
+    }
+
+    /// The transformation done by apply_ndc, as a column major matrix acting
+    /// on homogeneous coordinates, for use in a shader.
+    pub fn ndc_matrix(&self) -> [f32; 9] {
+        let [a, b, c, d, e, f, g, h] = self.0;
+        // From normalized device coordinates to the unit square, and back.
+        let to_unit = [[0.5, 0.0, 0.5], [0.0, -0.5, 0.5], [0.0, 0.0, 1.0]];
+        let from_unit = [[2.0, 0.0, -1.0], [0.0, -2.0, 1.0], [0.0, 0.0, 1.0]];
+        let m = mul(
+            &from_unit,
+            &mul(&[[a, b, c], [d, e, f], [g, h, 1.0]], &to_unit),
+        );
+        let mut columns = [0.0; 9];
+        for (i, v) in columns.iter_mut().enumerate() {
+            *v = m[i % 3][i / 3] as f32;
+        }
+        columns
+    }
+}
+
+fn mul(x: &[[f64; 3]; 3], y: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| x[i][k] * y[k][j]).sum();
+        }
+    }
+    product
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assert_almost_eq;
+
+    #[test]
+    fn test_homography_corners() {
+        let corners = [[0.1, 0.05], [0.95, 0.0], [0.9, 1.0], [0.0, 0.85]];
+        let h = Homography::from_unit_square(&corners);
+        for (unit, corner) in UNIT_CORNERS.iter().zip(corners.iter()) {
+            let [x, y] = h.apply(*unit);
+            assert_almost_eq(corner[0], x);
+            assert_almost_eq(corner[1], y);
+        }
+    }
+
+    #[test]
+    fn test_ndc_matrix() {
+        let h = Homography::from_unit_square(&[[0.1, 0.05], [0.95, 0.0], [0.9, 1.0], [0.0, 0.85]]);
+        let m = h.ndc_matrix();
+        for &[x, y] in &[[-1.0, 1.0], [0.3, -0.2], [0.9, 0.7]] {
+            let [u, v] = h.apply_ndc([x, y]);
+            let row = |i: usize| m[i] * x + m[3 + i] * y + m[6 + i];
+            assert_almost_eq(f64::from(u), f64::from(row(0) / row(2)));
+            assert_almost_eq(f64::from(v), f64::from(row(1) / row(2)));
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Splitting large serialized snapshots into chunks, and putting them back
+//! together.
+//!
+//! A snapshot normally travels as a two-part message: its topic, then the
+//! msgpacked snapshot.  A very large snapshot, such as one from a show with
+//! many layers or LED fixtures, is instead sent as a run of three-part
+//! messages: the topic, a chunk header, and one chunk of the serialized
+//! snapshot.  Each chunk is its own message, so no single message grows
+//! without bound, and the receiver can start taking in a frame before the
+//! sender has finished sending it.
+//!
+//! Chunks from one publisher arrive in order, but the network may drop any of
+//! them.  A frame missing a chunk is discarded.
+
+use simple_error::bail;
+use std::{convert::TryFrom, error::Error};
+
+/// Serialized snapshots larger than this many bytes are sent in chunks.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Identify one chunk of a serialized snapshot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChunkHeader {
+    pub frame_number: u64,
+    pub index: u16,
+    pub count: u16,
+}
+
+impl ChunkHeader {
+    /// The length of an encoded header, in bytes.
+    pub const LEN: usize = 12;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..8].copy_from_slice(&self.frame_number.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.index.to_le_bytes());
+        bytes[10..].copy_from_slice(&self.count.to_le_bytes());
+        bytes
+    }
+
+    /// Return None if the bytes aren't a valid header.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let mut frame_number = [0; 8];
+        frame_number.copy_from_slice(&bytes[..8]);
+        let header = Self {
+            frame_number: u64::from_le_bytes(frame_number),
+            index: u16::from_le_bytes([bytes[8], bytes[9]]),
+            count: u16::from_le_bytes([bytes[10], bytes[11]]),
+        };
+        if header.index >= header.count {
+            return None;
+        }
+        Some(header)
+    }
+}
+
+/// Split a serialized snapshot into chunks.
+/// Return None if it would take more chunks than a header can count.
+pub fn split(
+    serialized: &[u8],
+    frame_number: u64,
+) -> Option<impl Iterator<Item = (ChunkHeader, &[u8])>> {
+    let count = u16::try_from(serialized.chunks(CHUNK_SIZE).len()).ok()?;
+    Some(
+        serialized
+            .chunks(CHUNK_SIZE)
+            .enumerate()
+            .map(move |(index, chunk)| {
+                let header = ChunkHeader {
+                    frame_number,
+                    index: index as u16,
+                    count,
+                };
+                (header, chunk)
+            }),
+    )
+}
+
+/// Put chunked snapshots back together.
+#[derive(Default)]
+pub struct Reassembler {
+    /// The frame being reassembled, and the index of the next chunk expected.
+    pending: Option<(u64, u16)>,
+    buffer: Vec<u8>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in a received message, including its topic.
+    /// Return the serialized snapshot if the message held a whole snapshot or
+    /// completed one.
+    pub fn receive(&mut self, mut parts: Vec<Vec<u8>>) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match parts.len() {
+            2 => Ok(parts.pop()),
+            3 => match ChunkHeader::decode(&parts[1]) {
+                Some(header) => Ok(self.push(header, &parts[2])),
+                None => bail!("Malformed chunk header: {:?}.", parts[1]),
+            },
+            n => bail!("Received a message in {} parts.", n),
+        }
+    }
+
+    /// Take in a chunk.
+    /// Return the serialized snapshot if this chunk completed it.
+    pub fn push(&mut self, header: ChunkHeader, chunk: &[u8]) -> Option<Vec<u8>> {
+        if header.index == 0 {
+            // Abandon any frame that never finished.
+            self.buffer.clear();
+        } else if self.pending != Some((header.frame_number, header.index)) {
+            // We missed a chunk of this frame.
+            self.pending = None;
+            return None;
+        }
+        self.buffer.extend_from_slice(chunk);
+        if header.index + 1 == header.count {
+            self.pending = None;
+            return Some(std::mem::take(&mut self.buffer));
+        }
+        self.pending = Some((header.frame_number, header.index + 1));
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reassemble() {
+        let serialized = (0..CHUNK_SIZE * 2 + 10)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let chunks = split(&serialized, 7).unwrap().collect::<Vec<_>>();
+        assert_eq!(3, chunks.len());
+        for (header, _) in &chunks {
+            assert_eq!(Some(*header), ChunkHeader::decode(&header.encode()));
+        }
+
+        let mut reassembler = Reassembler::new();
+        let complete = chunks
+            .iter()
+            .filter_map(|(header, chunk)| reassembler.push(*header, chunk))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![serialized.clone()], complete);
+
+        // Losing a chunk loses the frame, but not the next one.
+        assert_eq!(None, reassembler.push(chunks[0].0, chunks[0].1));
+        assert_eq!(None, reassembler.push(chunks[2].0, chunks[2].1));
+        assert_eq!(None, reassembler.push(chunks[0].0, chunks[0].1));
+        assert_eq!(None, reassembler.push(chunks[1].0, chunks[1].1));
+        assert_eq!(
+            Some(&serialized[..]),
+            reassembler.push(chunks[2].0, chunks[2].1).as_deref()
+        );
+
+        let bad = ChunkHeader {
+            frame_number: 0,
+            index: 2,
+            count: 2,
+        };
+        assert_eq!(None, ChunkHeader::decode(&bad.encode()));
+        assert_eq!(None, ChunkHeader::decode(&[0; 3]));
+
+        let message = |header: ChunkHeader, chunk: &[u8]| {
+            vec![vec![0], header.encode().to_vec(), chunk.to_vec()]
+        };
+        assert_eq!(
+            Some(vec![1]),
+            reassembler.receive(vec![vec![0], vec![1]]).unwrap()
+        );
+        assert_eq!(
+            None,
+            reassembler
+                .receive(message(chunks[0].0, chunks[0].1))
+                .unwrap()
+        );
+        assert!(reassembler.receive(message(bad, &[])).is_err());
+        assert!(reassembler.receive(vec![vec![0]]).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Render client settings that are managed centrally by the server.
+//!
+//! The server keeps a profile for each known client, keyed by the name the
+//! client advertises its remote control service under.  When a client appears
+//! on the network, the server pushes its profile over the same channel used by
+//! the client administrator, and the client starts a show using it.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::error::Error;
+
+use crate::{calibration::Calibration, projection::Projection};
+
+/// Clients advertise their remote control service under this name.
+pub const SERVICE_NAME: &str = "tunnelclient";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClientProfile {
+    /// Virtual video channel to show.
+    pub video_channel: u64,
+    /// Requested window size, in logical pixels.
+    pub resolution: (u32, u32),
+    #[serde(default = "default_true")]
+    pub fullscreen: bool,
+    #[serde(default)]
+    pub flip_horizontal: bool,
+    #[serde(default = "default_true")]
+    pub anti_alias: bool,
+    #[serde(default = "default_true")]
+    pub alpha_blend: bool,
+    #[serde(default)]
+    pub dither: bool,
+    #[serde(default = "default_true")]
+    pub capture_mouse: bool,
+    /// Delay between current time and time to render, in seconds.
+    #[serde(default = "default_render_delay")]
+    pub render_delay: f64,
+    /// Delay between host/client time synchronization updates, in seconds.
+    #[serde(default = "default_timesync_interval")]
+    pub timesync_interval: u64,
+    /// Fit of the image to the projection surface.
+    #[serde(default)]
+    pub calibration: Calibration,
+    /// If set, re-project the image for a dome or a panorama.
+    #[serde(default)]
+    pub projection: Option<Projection>,
+    /// Color temperature in kelvin to bias this client's whites toward.
+    /// Color correction is applied by the server to the client's video
+    /// channel, so every client showing a channel shares its correction.
+    #[serde(default)]
+    pub color_temperature: Option<f64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_render_delay() -> f64 {
+    0.040
+}
+
+fn default_timesync_interval() -> u64 {
+    60
+}
+
+impl ClientProfile {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let (width, height) = self.resolution;
+        if width == 0 || height == 0 {
+            bail!("Resolution {}x{} is empty.", width, height);
+        }
+        if !self.render_delay.is_finite() || self.render_delay < 0.0 {
+            bail!(
+                "Render delay is {}; it must not be negative.",
+                self.render_delay
+            );
+        }
+        if self.timesync_interval == 0 {
+            bail!("Timesync interval must be positive.");
+        }
+        self.calibration.validate()?;
+        if let Some(projection) = &self.projection {
+            projection.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Sent by the server to a client to start a show using a profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssignProfile {
+    /// Hostname of the machine running the server.
+    pub server_hostname: String,
+    pub profile: ClientProfile,
+    /// The ID of the server's show, if it has one.
+    #[serde(default)]
+    pub show_id: Option<String>,
+}
@@ -0,0 +1,147 @@
+//! Conversions between the HSV color carried by arcs and RGB, and master
+//! color correction of whole frames.
+//!
+//! These follow the same model the client uses to draw, so anything the
+//! server computes in RGB matches what ends up on screen.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{almost_eq, angle, ArcSegment, LayerCollection};
+
+/// A color ride applied to every arc in a frame.  Sent once per frame rather
+/// than written into every arc, and applied after any palette.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct ColorCorrection {
+    /// Rotation of every hue, as a fraction of the way around the wheel.
+    pub hue_shift: f64,
+    /// Scale of every saturation.
+    pub saturation: f64,
+    /// Scale of every value.
+    pub brightness: f64,
+}
+
+impl ColorCorrection {
+    pub const IDENTITY: Self = Self {
+        hue_shift: 0.0,
+        saturation: 1.0,
+        brightness: 1.0,
+    };
+
+    /// Return true if applying this correction changes nothing.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    /// Apply this correction to the color of an arc.
+    pub fn apply(&self, arc: &mut ArcSegment) {
+        arc.hue = angle::wrap(arc.hue + self.hue_shift);
+        arc.sat *= self.saturation;
+        arc.val *= self.brightness;
+    }
+
+    /// Apply this correction to every arc.
+    pub fn apply_layers(&self, layers: &mut LayerCollection) {
+        if self.is_identity() {
+            return;
+        }
+        for layer in layers.iter_mut() {
+            for arc in Arc::make_mut(layer).iter_mut() {
+                self.apply(arc);
+            }
+        }
+    }
+}
+
+impl PartialEq for ColorCorrection {
+    fn eq(&self, o: &Self) -> bool {
+        almost_eq(self.hue_shift, o.hue_shift)
+            && almost_eq(self.saturation, o.saturation)
+            && almost_eq(self.brightness, o.brightness)
+    }
+}
+
+impl Eq for ColorCorrection {}
+
+/// Convert HSV to RGB, exactly as the client does.
+pub fn hsv_to_rgb(hue: f64, sat: f64, val: f64) -> [f64; 3] {
+    if sat == 0.0 {
+        return [val, val, val];
+    }
+    let h = if hue == 1.0 { 0.0 } else { hue * 6.0 };
+    let i = h.floor();
+    let v1 = val * (1.0 - sat);
+    let v2 = val * (1.0 - sat * (h - i));
+    let v3 = val * (1.0 - sat * (1.0 - (h - i)));
+    match i as i64 {
+        0 => [val, v3, v1],
+        1 => [v2, val, v1],
+        2 => [v1, val, v3],
+        3 => [v1, v2, val],
+        4 => [v3, v1, val],
+        _ => [val, v1, v2],
+    }
+}
+
+pub fn rgb_to_hsv(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    if max == 0.0 || delta == 0.0 {
+        return (0.0, 0.0, max);
+    }
+    let sector = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    (sector / 6.0, delta / max, max)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assert_almost_eq;
+
+    #[test]
+    fn test_correction() {
+        let mut arc = ArcSegment {
+            level: 1.,
+            thickness: 0.1,
+            hue: 0.9,
+            sat: 0.8,
+            val: 0.5,
+            x: 0.,
+            y: 0.,
+            rad_x: 0.5,
+            rad_y: 0.5,
+            start: 0.,
+            stop: 1.,
+            rot_angle: 0.,
+            palette: false,
+        };
+        ColorCorrection {
+            hue_shift: 0.2,
+            saturation: 0.5,
+            brightness: 0.5,
+        }
+        .apply(&mut arc);
+        assert_almost_eq(0.1, arc.hue);
+        assert_almost_eq(0.4, arc.sat);
+        assert_almost_eq(0.25, arc.val);
+        assert!(ColorCorrection::IDENTITY.is_identity());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &(h, s, v) in &[(0.0, 1.0, 1.0), (0.3, 0.5, 0.8), (0.75, 0.2, 0.4)] {
+            let [r, g, b] = hsv_to_rgb(h, s, v);
+            let (h1, s1, v1) = rgb_to_hsv(r, g, b);
+            assert_almost_eq(h, h1);
+            assert_almost_eq(s, s1);
+            assert_almost_eq(v, v1);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! The line protocol spoken by the show's control server.
+//!
+//! Scripts talk to the control server over a zmq REP socket.  Each request
+//! holds one or more lines, each describing a control surface event:
+//!
+//! ```text
+//! <device> <note_on|note_off|cc> <channel> <control> <value>
+//! ```
+//!
+//! where device is one of the device names accepted by `--midi`, such as
+//! TouchOsc or AkaiApc40.  The reply is "ok", or a description of the first
+//! malformed line, in which case none of the request's events are applied.
+//!
+//! Device names are checked by the server, which knows which devices exist.
+
+use serde::{Serialize, Serializer};
+use simple_error::bail;
+use std::{error::Error, fmt, str::FromStr};
+
+/// The port the control server listens on.
+pub const PORT: u16 = crate::ports::CONTROL;
+
+/// The reply to a request that was applied.
+pub const OK: &str = "ok";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    NoteOn,
+    NoteOff,
+    ControlChange,
+}
+
+impl EventKind {
+    /// The name of this kind of event in the protocol.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoteOn => "note_on",
+            Self::NoteOff => "note_off",
+            Self::ControlChange => "cc",
+        }
+    }
+}
+
+/// Event kinds serialize as they are written in requests.
+impl Serialize for EventKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "note_on" => Ok(Self::NoteOn),
+            "note_off" => Ok(Self::NoteOff),
+            "cc" => Ok(Self::ControlChange),
+            other => Err(format!("unknown event type \"{}\"", other)),
+        }
+    }
+}
+
+/// A single control surface event, as sent to the control server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlEvent {
+    pub device: String,
+    pub kind: EventKind,
+    pub channel: u8,
+    pub control: u8,
+    pub value: u8,
+}
+
+impl ControlEvent {
+    /// Create an event, checking that every field fits in a midi message.
+    pub fn new(
+        device: &str,
+        kind: EventKind,
+        channel: u8,
+        control: u8,
+        value: u8,
+    ) -> Result<Self, Box<dyn Error>> {
+        if device.is_empty() || device.contains(char::is_whitespace) {
+            bail!("device name \"{}\" is empty or contains spaces", device);
+        }
+        if channel > 15 {
+            bail!("channel {} is out of range", channel);
+        }
+        if control > 127 || value > 127 {
+            bail!("control and value must be at most 127");
+        }
+        Ok(Self {
+            device: device.to_string(),
+            kind,
+            channel,
+            control,
+            value,
+        })
+    }
+
+    /// Parse a single line of a request.
+    /// Return None for a blank line.
+    pub fn parse_line(line: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields[..] {
+            [] => Ok(None),
+            [device, kind, channel, control, value] => Ok(Some(Self::new(
+                device,
+                kind.parse()?,
+                channel.parse()?,
+                control.parse()?,
+                value.parse()?,
+            )?)),
+            _ => bail!("expected 5 fields but found {}", fields.len()),
+        }
+    }
+}
+
+impl fmt::Display for ControlEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.device,
+            self.kind.name(),
+            self.channel,
+            self.control,
+            self.value
+        )
+    }
+}
+
+/// Parse every line of a request.
+/// Return a description of the first malformed line, if any.
+pub fn parse_request(request: &str) -> Result<Vec<ControlEvent>, String> {
+    let mut events = Vec::new();
+    for line in request.lines() {
+        match ControlEvent::parse_line(line) {
+            Ok(Some(e)) => events.push(e),
+            Ok(None) => (),
+            Err(e) => return Err(format!("\"{}\": {}", line, e)),
+        }
+    }
+    Ok(events)
+}
+
+/// Format events as a request.
+pub fn format_request(events: &[ControlEvent]) -> String {
+    events
+        .iter()
+        .map(ControlEvent::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let events = vec![
+            ControlEvent::new("TouchOsc", EventKind::ControlChange, 0, 21, 127).unwrap(),
+            ControlEvent::new("AkaiApc40", EventKind::NoteOn, 1, 2, 0).unwrap(),
+        ];
+        assert_eq!(events, parse_request(&format_request(&events)).unwrap());
+        assert_eq!(
+            events,
+            parse_request("TouchOsc cc 0 21 127\n\n  AkaiApc40 note_on 1 2 0 ").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(parse_request("").unwrap().is_empty());
+        // One bad line spoils the request.
+        assert!(parse_request("TouchOsc cc 0 21 127\nTouchOsc cc 0 21").is_err());
+        assert!(parse_request("TouchOsc pitch_bend 0 21 127").is_err());
+        assert!(parse_request("TouchOsc cc 16 21 127").is_err());
+        assert!(parse_request("TouchOsc cc 0 128 127").is_err());
+        assert!(parse_request("TouchOsc cc 0 21 -1").is_err());
+        assert!(ControlEvent::new("Touch Osc", EventKind::NoteOn, 0, 0, 0).is_err());
+    }
+}
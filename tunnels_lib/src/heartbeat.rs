@@ -0,0 +1,23 @@
+//! Clients periodically tell the server which video channel they are showing,
+//! so the server knows which outputs are actually being watched.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The server listens for heartbeats on this port.
+pub const PORT: u16 = crate::ports::HEARTBEAT;
+
+/// Clients send a heartbeat this often.
+pub const INTERVAL: Duration = Duration::from_secs(1);
+
+/// A video channel is considered unwatched if no heartbeat has arrived for it
+/// in this long.
+pub const TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    pub video_channel: u64,
+    /// The ID of the show the client belongs to, if any.
+    #[serde(default)]
+    pub show_id: Option<String>,
+}
@@ -1,12 +1,33 @@
 //! Code shared between the tunnels console and client.
 
+pub mod angle;
+pub mod archive;
+pub mod calibration;
+pub mod chunk;
+pub mod client_profile;
+pub mod color;
+pub mod control_protocol;
+pub mod heartbeat;
 pub mod number;
+pub mod palette;
+pub mod ports;
+pub mod projection;
+pub mod queue;
+pub mod sample;
+pub mod show_controls;
+pub mod show_id;
 pub mod smooth;
+pub mod thread_config;
 
+pub use angle::{min_included_angle, modulo};
+
+use color::ColorCorrection;
 use derive_more::{Add, Display, Div, Mul, Sub};
 use ordered_float::OrderedFloat;
-use serde::{Deserialize, Serialize};
+use palette::Palette;
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 use std::{
+    convert::TryFrom,
     hash::{Hash, Hasher},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -16,8 +37,13 @@ use std::{
 };
 
 /// Timestamp used for expressing moments in time, has units of microseconds.
-/// Normally computed by the show controller as the number of microseconds since
-/// the show launched.
+///
+/// This is the show timebase: the number of microseconds since the show
+/// launched, measured on the show controller's monotonic clock.  Snapshots are
+/// stamped with it, and clients estimate it through timesync.  It never jumps
+/// when the wall clock is adjusted, and it won't overflow for hundreds of
+/// thousands of years of uptime.
+///
 /// Signed type to support possible situations where we need to subtract one
 /// timestamp from another and end up with a negative result.
 #[derive(
@@ -40,12 +66,53 @@ use std::{
 pub struct Timestamp(pub i64);
 
 impl Timestamp {
+    pub const ZERO: Self = Self(0);
+
     pub fn since(start: Instant) -> Self {
         Self::from_duration(start.elapsed())
     }
 
+    /// Convert a duration, saturating if it is too long to represent.
     pub fn from_duration(d: Duration) -> Self {
-        Self(d.as_micros() as i64)
+        Self(i64::try_from(d.as_micros()).unwrap_or(i64::MAX))
+    }
+
+    pub fn from_micros(micros: i64) -> Self {
+        Self(micros)
+    }
+
+    pub fn as_micros(self) -> i64 {
+        self.0
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        self.0 as f64 / 1_000_000.
+    }
+
+    /// Return the time elapsed since an earlier timestamp, or zero if the
+    /// other timestamp is actually later.
+    pub fn duration_since(self, earlier: Self) -> Duration {
+        Duration::from_micros(self.0.saturating_sub(earlier.0).max(0) as u64)
+    }
+
+    /// Interpolate between this timestamp and another.
+    /// Only the difference between them passes through floating point, so
+    /// the result is exact to the microsecond however long the show has run.
+    pub fn lerp(self, other: Self, alpha: f64) -> Self {
+        Self(self.0 + ((other.0 - self.0) as f64 * alpha).round() as i64)
+    }
+
+    /// Return the mean of a collection of timestamps.
+    /// As with lerp, the result is exact however long the show has run.
+    pub fn mean(timestamps: impl IntoIterator<Item = Self>) -> Option<Self> {
+        let mut timestamps = timestamps.into_iter();
+        let first = timestamps.next()?;
+        let (mut sum, mut count) = (0i128, 1i128);
+        for t in timestamps {
+            sum += (t.0 - first.0) as i128;
+            count += 1;
+        }
+        Some(Self(first.0 + (sum / count) as i64))
     }
 
     // Step mutably increments this timestamp by the provided step.
@@ -104,6 +171,16 @@ pub struct ArcSegment {
     pub start: f64,
     pub stop: f64,
     pub rot_angle: f64,
+    /// If set, hue is a coordinate into the palette of the snapshot rather
+    /// than a hue, and the palette color's saturation and value are scaled by
+    /// sat and val.  Left out of the message when unset, so arcs in raw color
+    /// look the same on the wire as they always have.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub palette: bool,
+}
+
+fn is_false(v: &bool) -> bool {
+    !v
 }
 
 impl Hash for ArcSegment {
@@ -121,6 +198,10 @@ impl Hash for ArcSegment {
         OrderedFloat(self.start).hash(state);
         OrderedFloat(self.stop).hash(state);
         OrderedFloat(self.rot_angle).hash(state);
+        // Raw color arcs hash the same as they did before palettes existed.
+        if self.palette {
+            self.palette.hash(state);
+        }
     }
 }
 
@@ -138,6 +219,7 @@ impl PartialEq for ArcSegment {
             && angle_almost_eq(self.start, o.start)
             && angle_almost_eq(self.stop, o.stop)
             && angle_almost_eq(self.rot_angle, o.rot_angle)
+            && self.palette == o.palette
     }
 }
 
@@ -145,30 +227,94 @@ impl Eq for ArcSegment {}
 
 pub type LayerCollection = Vec<Arc<Vec<ArcSegment>>>;
 
+/// How a layer is combined with the layers drawn beneath it.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Cover what is beneath in proportion to level, or draw over it if the
+    /// client doesn't alpha blend.
+    #[default]
+    Alpha,
+    /// Add to what is beneath, so that overlapping beams glow.
+    Additive,
+    /// Darken what is beneath in proportion to level.
+    Mask,
+}
+
 /// A complete single-frame video snapshot.
 /// This is the top-level structure sent in each serialized frame.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Snapshot {
     pub frame_number: u64,
     pub time: Timestamp,
     pub layers: LayerCollection,
+    /// The palette that arcs in palette mode draw from, if any.
+    #[serde(default)]
+    pub palette: Option<Palette>,
+    /// How each layer is blended.  Layers without an entry are alpha blended.
+    #[serde(default)]
+    pub blend: Vec<BlendMode>,
+    /// Master color correction of every arc, if any.
+    #[serde(default)]
+    pub color: Option<ColorCorrection>,
 }
 
-const ALMOST_EQ_TOLERANCE: f64 = 0.000_000_1;
+impl Snapshot {
+    /// Replace the palette coordinates of any arcs in palette mode with raw
+    /// color.
+    pub fn resolve_palette(&mut self) {
+        palette::resolve_layers(&mut self.layers, self.palette.as_ref());
+    }
 
-/// True modulus operator.
-#[inline(always)]
-pub fn modulo(a: f64, b: f64) -> f64 {
-    ((a % b) + b) % b
+    /// Resolve any palette, then apply any color correction, so that every
+    /// arc carries the color it is drawn in.
+    pub fn resolve_color(&mut self) {
+        self.resolve_palette();
+        if let Some(color) = self.color.take() {
+            color.apply_layers(&mut self.layers);
+        }
+    }
+
+    /// Return how the layer with this index is blended.
+    pub fn blend_mode(&self, layer: usize) -> BlendMode {
+        self.blend.get(layer).copied().unwrap_or_default()
+    }
 }
 
-/// Minimum included angle between two unit angles.
-/// Might be negative.
-#[inline(always)]
-pub fn min_included_angle(a: f64, b: f64) -> f64 {
-    ((((b - a) % 1.0) + 1.5) % 1.0) - 0.5
+impl Serialize for Snapshot {
+    /// Trailing fields are left off when they are unused, so that clients
+    /// that predate them can still read everything else.  Fields are sent by
+    /// position, so an empty palette is sent ahead of any blend modes, and
+    /// both are sent ahead of any color correction.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let send_color = self.color.is_some();
+        let send_blend = send_color || self.blend.iter().any(|b| *b != BlendMode::Alpha);
+        let send_palette = send_blend || self.palette.is_some();
+        let len = 3 + send_palette as usize + send_blend as usize + send_color as usize;
+        let mut state = serializer.serialize_struct("Snapshot", len)?;
+        state.serialize_field("frame_number", &self.frame_number)?;
+        state.serialize_field("time", &self.time)?;
+        state.serialize_field("layers", &self.layers)?;
+        if send_palette {
+            state.serialize_field("palette", &self.palette)?;
+        } else {
+            state.skip_field("palette")?;
+        }
+        if send_blend {
+            state.serialize_field("blend", &self.blend)?;
+        } else {
+            state.skip_field("blend")?;
+        }
+        if send_color {
+            state.serialize_field("color", &self.color)?;
+        } else {
+            state.skip_field("color")?;
+        }
+        state.end()
+    }
 }
 
+const ALMOST_EQ_TOLERANCE: f64 = 0.000_000_1;
+
 /// Return True if two f64 are within 10^-6 of each other.
 /// This is OK because all of our floats are on the unit range, so even though
 /// this comparison is absolute it should be good enough for art.
@@ -188,3 +334,78 @@ pub fn angle_almost_eq(a: f64, b: f64) -> bool {
 pub fn assert_almost_eq(a: f64, b: f64) {
     assert!(almost_eq(a, b), "{} != {}", a, b);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Six weeks of uptime.
+    const UPTIME: Duration = Duration::from_secs(6 * 7 * 24 * 60 * 60);
+
+    #[test]
+    fn test_long_uptime() {
+        let t = Timestamp::from_duration(UPTIME);
+        assert_eq!(UPTIME.as_micros() as i64, t.as_micros());
+        assert_eq!(UPTIME.as_secs_f64(), t.as_secs_f64());
+
+        let later = t + Timestamp::from_micros(1);
+        assert_eq!(Duration::from_micros(1), later.duration_since(t));
+        assert_eq!(Duration::ZERO, t.duration_since(later));
+
+        // Interpolation and averaging resolve single microseconds.
+        let frame = Timestamp::from_micros(16_667);
+        assert_eq!(t + Timestamp::from_micros(8_334), t.lerp(t + frame, 0.5));
+        assert_eq!(t, t.lerp(t + frame, 0.));
+        assert_eq!(
+            Some(later),
+            Timestamp::mean(vec![t, later, later + later - t])
+        );
+        assert_eq!(None, Timestamp::mean(Vec::new()));
+    }
+
+    #[test]
+    fn test_from_duration_saturates() {
+        assert_eq!(
+            Timestamp(i64::MAX),
+            Timestamp::from_duration(Duration::from_secs(u64::MAX))
+        );
+    }
+    #[test]
+    fn test_snapshot_wire() {
+        let mut snapshot = Snapshot {
+            frame_number: 1,
+            time: Timestamp::ZERO,
+            layers: vec![Arc::new(Vec::new()), Arc::new(Vec::new())],
+            palette: None,
+            blend: vec![BlendMode::Alpha; 2],
+            color: None,
+        };
+        let roundtrip = |snapshot: &Snapshot| -> (u8, Snapshot) {
+            let bytes = rmp_serde::to_vec(snapshot).unwrap();
+            (bytes[0], rmp_serde::from_slice(&bytes).unwrap())
+        };
+        // Snapshots that only alpha blend are sent as the same three fields
+        // as ever.
+        let (header, received) = roundtrip(&snapshot);
+        assert_eq!(0x90 + 3, header);
+        assert_eq!(BlendMode::Alpha, received.blend_mode(1));
+
+        // Blend modes are sent after an empty palette.
+        snapshot.blend[1] = BlendMode::Additive;
+        let (header, received) = roundtrip(&snapshot);
+        assert_eq!(0x90 + 5, header);
+        assert_eq!(snapshot, received);
+        assert_eq!(BlendMode::Additive, received.blend_mode(1));
+        assert_eq!(BlendMode::Alpha, received.blend_mode(2));
+
+        // Color correction is sent after the blend modes.
+        snapshot.blend[1] = BlendMode::Alpha;
+        snapshot.color = Some(ColorCorrection {
+            hue_shift: 0.5,
+            ..ColorCorrection::IDENTITY
+        });
+        let (header, received) = roundtrip(&snapshot);
+        assert_eq!(0x90 + 6, header);
+        assert_eq!(snapshot, received);
+    }
+}
@@ -0,0 +1,226 @@
+//! Palettes of colors that arcs can draw from.
+//!
+//! A palette is a ring of anchor colors.  A coordinate on the unit range picks
+//! a color by blending between the two nearest anchors, wrapping around from
+//! the last anchor back to the first just like hue wraps around the color
+//! wheel.  Arcs in palette mode carry such a coordinate in place of a hue, and
+//! are drawn from the palette sent along with their snapshot.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{almost_eq, angle, angle_almost_eq, ArcSegment, LayerCollection};
+
+/// A color, with each component on the unit range.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Copy, Clone)]
+pub struct Hsv {
+    pub hue: f64,
+    pub sat: f64,
+    pub val: f64,
+}
+
+impl Hsv {
+    /// Blend towards another color.  Hue takes the short way around.
+    pub fn lerp(self, other: Self, alpha: f64) -> Self {
+        Self {
+            hue: angle::interpolate(self.hue, other.hue, alpha),
+            sat: self.sat + (other.sat - self.sat) * alpha,
+            val: self.val + (other.val - self.val) * alpha,
+        }
+    }
+}
+
+impl PartialEq for Hsv {
+    fn eq(&self, o: &Self) -> bool {
+        angle_almost_eq(self.hue, o.hue) && almost_eq(self.sat, o.sat) && almost_eq(self.val, o.val)
+    }
+}
+
+impl Eq for Hsv {}
+
+/// A ring of anchor colors.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Palette(pub Vec<Hsv>);
+
+impl Palette {
+    /// How many anchors a palette caught mid-crossfade is sampled into.
+    const CROSSFADE_RESOLUTION: usize = 32;
+
+    /// Return the color at a coordinate, or None if the palette is empty.
+    pub fn sample(&self, coordinate: f64) -> Option<Hsv> {
+        let n = self.0.len();
+        if n == 0 {
+            return None;
+        }
+        let position = angle::wrap(coordinate) * n as f64;
+        let i = (position.floor() as usize).min(n - 1);
+        Some(self.0[i].lerp(self.0[(i + 1) % n], position - i as f64))
+    }
+
+    /// Blend this palette into another, by an amount on the unit range.
+    /// The palettes may have different numbers of anchors, so a palette part
+    /// way through a crossfade is resampled.
+    pub fn crossfade(&self, other: &Self, amount: f64) -> Self {
+        if amount <= 0. || other.0.is_empty() {
+            return self.clone();
+        }
+        if amount >= 1. || self.0.is_empty() {
+            return other.clone();
+        }
+        Self(
+            (0..Self::CROSSFADE_RESOLUTION)
+                .map(|i| {
+                    let coordinate = i as f64 / Self::CROSSFADE_RESOLUTION as f64;
+                    // Neither palette is empty.
+                    let from = self.sample(coordinate).unwrap();
+                    let to = other.sample(coordinate).unwrap();
+                    from.lerp(to, amount)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Replace the palette coordinate of an arc in palette mode with raw color.
+/// The saturation and value of the arc scale those of the palette color.
+/// Without a palette to draw from, the coordinate is used as a hue.
+pub fn resolve(arc: &mut ArcSegment, palette: Option<&Palette>) {
+    if !arc.palette {
+        return;
+    }
+    arc.palette = false;
+    if let Some(color) = palette.and_then(|p| p.sample(arc.hue)) {
+        arc.hue = color.hue;
+        arc.sat *= color.sat;
+        arc.val *= color.val;
+    }
+}
+
+/// Resolve every arc in palette mode into raw color.
+/// Layers without any such arcs are left alone, so they stay shared.
+pub fn resolve_layers(layers: &mut LayerCollection, palette: Option<&Palette>) {
+    for layer in layers.iter_mut() {
+        if layer.iter().any(|arc| arc.palette) {
+            for arc in Arc::make_mut(layer).iter_mut() {
+                resolve(arc, palette);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assert_almost_eq, Snapshot, Timestamp};
+
+    fn hsv(hue: f64, sat: f64, val: f64) -> Hsv {
+        Hsv { hue, sat, val }
+    }
+
+    #[test]
+    fn test_sample() {
+        let palette = Palette(vec![hsv(0.9, 1., 1.), hsv(0.1, 0., 0.5)]);
+        assert_eq!(hsv(0.9, 1., 1.), palette.sample(0.).unwrap());
+        assert_eq!(hsv(0.1, 0., 0.5), palette.sample(0.5).unwrap());
+        // Hue blends the short way around the wheel.
+        assert_eq!(hsv(0.0, 0.5, 0.75), palette.sample(0.25).unwrap());
+        // The last anchor blends back into the first.
+        assert_eq!(hsv(0.0, 0.5, 0.75), palette.sample(0.75).unwrap());
+        assert_eq!(palette.sample(0.25), palette.sample(1.25));
+        assert_eq!(palette.sample(0.75), palette.sample(-0.25));
+        assert!(Palette(Vec::new()).sample(0.5).is_none());
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let a = Palette(vec![hsv(0.2, 1., 1.)]);
+        let b = Palette(vec![hsv(0.4, 0., 1.), hsv(0.6, 0., 1.)]);
+        assert_eq!(a, a.crossfade(&b, 0.));
+        assert_eq!(b, a.crossfade(&b, 1.));
+        let faded = a.crossfade(&b, 0.5);
+        assert_eq!(hsv(0.3, 0.5, 1.), faded.sample(0.).unwrap());
+        assert_eq!(hsv(0.4, 0.5, 1.), faded.sample(0.5).unwrap());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let palette = Palette(vec![hsv(0.5, 0.8, 0.5)]);
+        let mut arc = ArcSegment {
+            level: 1.,
+            thickness: 0.1,
+            hue: 0.3,
+            sat: 0.5,
+            val: 1.,
+            x: 0.,
+            y: 0.,
+            rad_x: 0.5,
+            rad_y: 0.5,
+            start: 0.,
+            stop: 1.,
+            rot_angle: 0.,
+            palette: false,
+        };
+        let raw = arc.clone();
+        resolve(&mut arc, Some(&palette));
+        assert_eq!(raw, arc);
+
+        arc.palette = true;
+        resolve(&mut arc, Some(&palette));
+        assert!(!arc.palette);
+        assert_almost_eq(0.5, arc.hue);
+        assert_almost_eq(0.4, arc.sat);
+        assert_almost_eq(0.5, arc.val);
+
+        // Without a palette, the coordinate stands in for hue.
+        arc.palette = true;
+        resolve(&mut arc, None);
+        assert!(!arc.palette);
+        assert_almost_eq(0.5, arc.hue);
+    }
+
+    #[test]
+    fn test_wire() {
+        let arc = ArcSegment {
+            level: 1.,
+            thickness: 0.1,
+            hue: 0.3,
+            sat: 0.5,
+            val: 1.,
+            x: 0.,
+            y: 0.,
+            rad_x: 0.5,
+            rad_y: 0.5,
+            start: 0.,
+            stop: 1.,
+            rot_angle: 0.,
+            palette: false,
+        };
+        // Raw color arcs are sent as the same twelve fields as ever.
+        let raw = rmp_serde::to_vec(&arc).unwrap();
+        assert_eq!(0x90 + 12, raw[0]);
+
+        let mut snapshot = Snapshot {
+            frame_number: 1,
+            time: Timestamp::ZERO,
+            layers: vec![Arc::new(vec![ArcSegment {
+                palette: true,
+                ..arc.clone()
+            }])],
+            palette: Some(Palette(vec![Hsv {
+                hue: 0.7,
+                sat: 1.,
+                val: 1.,
+            }])),
+            blend: Vec::new(),
+            color: None,
+        };
+        let received: Snapshot =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&snapshot).unwrap()).unwrap();
+        assert_eq!(snapshot, received);
+
+        snapshot.resolve_palette();
+        assert!(!snapshot.layers[0][0].palette);
+        assert_almost_eq(0.7, snapshot.layers[0][0].hue);
+    }
+}
@@ -0,0 +1,40 @@
+//! Every port the show and its clients listen on, kept together so that no
+//! two services can end up on the same one.
+
+/// The show publishes snapshots to clients.
+pub const SNAPSHOTS: u16 = 6000;
+/// The show answers clients synchronizing their clocks to it.
+pub const TIMESYNC: u16 = 8989;
+/// The show serves its control layout to front ends.
+pub const CONTROL_LAYOUT: u16 = 8990;
+/// The show listens for client heartbeats.
+pub const HEARTBEAT: u16 = 8991;
+/// The show serves the beam store slot listing.
+pub const SLOTS: u16 = 8992;
+/// The show accepts control events from scripts and front ends.
+pub const CONTROL: u16 = 8993;
+/// The show queues alerts for front ends.
+pub const ALERTS: u16 = 8994;
+/// Each client serves remote control requests.
+pub const CLIENT_REMOTE: u16 = 15000;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_ports_are_unique() {
+        let ports = [
+            SNAPSHOTS,
+            TIMESYNC,
+            CONTROL_LAYOUT,
+            HEARTBEAT,
+            SLOTS,
+            CONTROL,
+            ALERTS,
+            CLIENT_REMOTE,
+        ];
+        assert_eq!(ports.len(), ports.iter().collect::<HashSet<_>>().len());
+    }
+}
@@ -0,0 +1,193 @@
+//! Re-projecting the tunnel image for immersive displays.
+//!
+//! The flat image is treated as a perspective view looking down the tunnel,
+//! with the edge of the image at a configurable angle from the tunnel's axis.
+//! Rings centered in the image become circles around the axis, so each layer
+//! wraps around the viewer like a cylinder.
+//!
+//! - Dome: a 180 degree equidistant fisheye, the "dome master" format used by
+//!   planetarium projectors.  The dome fills the circle inscribed in the
+//!   window: its center is the zenith and its edge is the horizon.  Tilting
+//!   the view moves the end of the tunnel from the zenith toward the front of
+//!   the dome, at the bottom of the dome master, to suit tilted domes and
+//!   seating that faces forward.
+//! - Equirectangular: a 360 by 180 degree panorama filling the window, with
+//!   longitude across and latitude up, for previewing an installation in a
+//!   VR headset.  The end of the tunnel is straight ahead, and can be raised
+//!   toward the zenith.
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::{
+    error::Error,
+    f64::consts::{FRAC_PI_2, PI},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Projection {
+    Dome(Dome),
+    Equirectangular(Panorama),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dome {
+    /// Angle in degrees between the zenith and the end of the tunnel.
+    pub tilt: f64,
+    /// Angle in degrees between the end of the tunnel and the edge of the
+    /// flat image.  Larger angles wrap more of the tunnel around the
+    /// audience.
+    pub edge_angle: f64,
+}
+
+impl Default for Dome {
+    fn default() -> Self {
+        Self {
+            tilt: 0.0,
+            edge_angle: 60.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Panorama {
+    /// Angle in degrees between the horizon and the end of the tunnel.
+    pub elevation: f64,
+    /// Angle in degrees between the end of the tunnel and the edge of the
+    /// flat image.
+    pub edge_angle: f64,
+}
+
+impl Default for Panorama {
+    fn default() -> Self {
+        Self {
+            elevation: 0.0,
+            edge_angle: 60.0,
+        }
+    }
+}
+
+impl Projection {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let (name, axis, edge_angle) = match self {
+            Self::Dome(dome) => ("Dome tilt", dome.tilt, dome.edge_angle),
+            Self::Equirectangular(pano) => ("Panorama elevation", pano.elevation, pano.edge_angle),
+        };
+        if !(-90.0..=90.0).contains(&axis) {
+            bail!("{} is {}; it must be within 90 degrees.", name, axis);
+        }
+        if !(edge_angle > 0.0 && edge_angle < 90.0) {
+            bail!(
+                "Edge angle is {}; it must be between 0 and 90 degrees.",
+                edge_angle
+            );
+        }
+        Ok(())
+    }
+
+    /// Prepare the projection for a window whose shorter side is scaled to
+    /// the provided fraction of each side, in normalized device coordinates.
+    pub fn mapping(&self, scale: [f64; 2]) -> ProjectionMapping {
+        // Both projections rotate the view about the horizontal axis.  The
+        // dome's tunnel starts at its zenith and tilts forward; the
+        // panorama's starts straight ahead and rises.
+        let (panorama, rotation, edge_angle) = match self {
+            Self::Dome(dome) => (false, -dome.tilt, dome.edge_angle),
+            Self::Equirectangular(pano) => (true, pano.elevation, pano.edge_angle),
+        };
+        let rotation = rotation.to_radians();
+        ProjectionMapping {
+            panorama,
+            focal_length: 1.0 / edge_angle.to_radians().tan(),
+            rotation_sin: rotation.sin(),
+            rotation_cos: rotation.cos(),
+            scale,
+        }
+    }
+}
+
+/// A projection ready to apply to vertices.
+/// Clients project in a shader, from these parameters.
+#[derive(Debug, Copy, Clone)]
+pub struct ProjectionMapping {
+    /// True for a panorama, which wraps around at its left and right edges.
+    pub panorama: bool,
+    /// Distance from the viewer to the flat image, which has unit radius.
+    pub focal_length: f64,
+    pub rotation_sin: f64,
+    pub rotation_cos: f64,
+    /// Fraction of each side of the window spanned by the shorter side.
+    pub scale: [f64; 2],
+}
+
+impl ProjectionMapping {
+    /// Project a point in the flat image, scaled so the circle inscribed in
+    /// the window has unit radius, with y increasing upwards.  Return the
+    /// projected point in normalized device coordinates.
+    pub fn apply(&self, [u, v]: [f64; 2]) -> [f64; 2] {
+        // Direction of the point from the viewer, with z along the axis of
+        // the projection.
+        let (x, y, z) = (u, v, self.focal_length);
+        let (y, z) = (
+            y * self.rotation_cos + z * self.rotation_sin,
+            z * self.rotation_cos - y * self.rotation_sin,
+        );
+        if self.panorama {
+            let longitude = x.atan2(z);
+            let latitude = y.atan2(x.hypot(z));
+            return [longitude / PI, latitude / FRAC_PI_2];
+        }
+        let horizontal = x.hypot(y);
+        if horizontal == 0.0 {
+            return [0.0, 0.0];
+        }
+        // Equidistant: distance from the center is proportional to the
+        // angle from the zenith.
+        let r = horizontal.atan2(z) / FRAC_PI_2;
+        let [sx, sy] = self.scale;
+        [x * r / horizontal * sx, y * r / horizontal * sy]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assert_almost_eq;
+
+    #[test]
+    fn test_dome() {
+        let dome = Projection::Dome(Dome::default()).mapping([1.0, 1.0]);
+        assert_eq!([0.0, 0.0], dome.apply([0.0, 0.0]));
+        // The edge of the flat image lands at the edge angle.
+        let [x, y] = dome.apply([1.0, 0.0]);
+        assert_almost_eq(60.0 / 90.0, x);
+        assert_almost_eq(0.0, y);
+
+        let tilted = Projection::Dome(Dome {
+            tilt: 30.0,
+            ..Default::default()
+        })
+        .mapping([1.0, 1.0]);
+        let [x, y] = tilted.apply([0.0, 0.0]);
+        assert_almost_eq(0.0, x);
+        assert_almost_eq(-30.0 / 90.0, y);
+    }
+
+    #[test]
+    fn test_equirectangular() {
+        let pano = Projection::Equirectangular(Panorama {
+            elevation: 90.0,
+            ..Default::default()
+        })
+        .mapping([1.0, 1.0]);
+        // Looking straight up, the end of the tunnel is the zenith.
+        let [_, y] = pano.apply([0.0, 0.0]);
+        assert_almost_eq(1.0, y);
+        // The edge of the flat image is 30 degrees above the horizon, at
+        // every longitude.
+        let [x, y] = pano.apply([1.0, 0.0]);
+        assert_almost_eq(0.5, x);
+        assert_almost_eq(30.0 / 90.0, y);
+    }
+}
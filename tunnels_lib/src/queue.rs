@@ -0,0 +1,288 @@
+//! Bounded queues between threads.
+//!
+//! An installation may run for days, so no queue between threads may grow
+//! without bound when its consumer gets stuck.  Producers that must never
+//! wait, such as input callbacks and network receivers, send without blocking
+//! and drop whatever doesn't fit.  Drops are counted, so that the consumer can
+//! report them.
+//!
+//! Where only the newest item matters, such as frames waiting to be rendered,
+//! a latest-value slot is used instead: each item replaces any that hasn't
+//! been received yet, so a consumer that falls behind skips straight to the
+//! newest item rather than working through stale ones.
+
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Condvar, Mutex,
+    },
+    time::Instant,
+};
+
+/// Create a queue that holds at most capacity items.
+pub fn bounded<T>(capacity: usize) -> (DropSender<T>, Receiver<T>) {
+    let (send, recv) = sync_channel(capacity);
+    (
+        DropSender {
+            send,
+            dropped: Arc::new(AtomicUsize::new(0)),
+        },
+        recv,
+    )
+}
+
+/// Sends to a bounded queue, dropping items when it is full.
+pub struct DropSender<T> {
+    send: SyncSender<T>,
+    /// Shared by every clone of this sender.
+    dropped: Arc<AtomicUsize>,
+}
+
+impl<T> Clone for DropSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            send: self.send.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<T> DropSender<T> {
+    /// Send an item without waiting.  If the queue is full, the item is
+    /// dropped and counted.
+    /// Return an error if the receiver has hung up.
+    pub fn send(&self, item: T) -> Result<(), Disconnected> {
+        match self.send.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(Disconnected),
+        }
+    }
+
+    /// Return the number of items dropped by this sender and all of its
+    /// clones since the last call.
+    pub fn take_dropped(&self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Create a slot that holds only the newest item sent to it.
+pub fn latest<T>() -> (LatestSender<T>, LatestReceiver<T>) {
+    let shared = Arc::new(Slot {
+        state: Mutex::new(SlotState {
+            item: None,
+            sender: true,
+            receiver: true,
+        }),
+        ready: Condvar::new(),
+        dropped: AtomicUsize::new(0),
+    });
+    (
+        LatestSender {
+            shared: shared.clone(),
+        },
+        LatestReceiver { shared },
+    )
+}
+
+struct Slot<T> {
+    state: Mutex<SlotState<T>>,
+    ready: Condvar,
+    dropped: AtomicUsize,
+}
+
+struct SlotState<T> {
+    item: Option<T>,
+    /// Whether each end is still connected.
+    sender: bool,
+    receiver: bool,
+}
+
+impl<T> Slot<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, SlotState<T>> {
+        // Nothing can panic while the lock is held.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn take_dropped(&self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Sends to a latest-value slot.
+pub struct LatestSender<T> {
+    shared: Arc<Slot<T>>,
+}
+
+impl<T> LatestSender<T> {
+    /// Send an item without waiting.  An item that hasn't been received yet
+    /// is replaced, and counted as dropped.
+    /// Return an error if the receiver has hung up.
+    pub fn send(&self, item: T) -> Result<(), Disconnected> {
+        let mut state = self.shared.lock();
+        if !state.receiver {
+            return Err(Disconnected);
+        }
+        if state.item.replace(item).is_some() {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+
+    /// Return the number of items replaced before they were received since
+    /// the last call.
+    pub fn take_dropped(&self) -> usize {
+        self.shared.take_dropped()
+    }
+}
+
+impl<T> Drop for LatestSender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().sender = false;
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Receives from a latest-value slot.
+pub struct LatestReceiver<T> {
+    shared: Arc<Slot<T>>,
+}
+
+impl<T> LatestReceiver<T> {
+    /// Wait for an item.
+    /// Return None once the sender has hung up and the slot is empty.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = state.item.take() {
+                return Some(item);
+            }
+            if !state.sender {
+                return None;
+            }
+            state = self
+                .shared
+                .ready
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Wait for an item until the deadline.
+    /// Return None if none arrives in time, or once the sender has hung up
+    /// and the slot is empty.
+    pub fn recv_until(&self, deadline: Instant) -> Option<T> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = state.item.take() {
+                return Some(item);
+            }
+            let now = Instant::now();
+            if !state.sender || now >= deadline {
+                return None;
+            }
+            state = self
+                .shared
+                .ready
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Return the item in the slot, if there is one, without waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.lock().item.take()
+    }
+
+    /// Return the number of items replaced before they were received since
+    /// the last call.
+    pub fn take_dropped(&self) -> usize {
+        self.shared.take_dropped()
+    }
+}
+
+impl<T> Drop for LatestReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver = false;
+    }
+}
+
+/// The receiving end of a queue has hung up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "receiver hung up")
+    }
+}
+
+impl Error for Disconnected {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bounded() {
+        let (send, recv) = bounded(2);
+        let other = send.clone();
+        assert_eq!(Ok(()), send.send(1));
+        assert_eq!(Ok(()), other.send(2));
+        assert_eq!(Ok(()), send.send(3));
+        assert_eq!(1, other.take_dropped());
+        assert_eq!(0, send.take_dropped());
+        assert_eq!(vec![1, 2], recv.try_iter().collect::<Vec<_>>());
+
+        assert_eq!(Ok(()), send.send(4));
+        drop(recv);
+        assert_eq!(Err(Disconnected), send.send(5));
+    }
+
+    #[test]
+    fn test_latest() {
+        let (send, recv) = latest();
+        assert_eq!(None, recv.try_recv());
+        assert_eq!(Ok(()), send.send(1));
+        assert_eq!(Ok(()), send.send(2));
+        assert_eq!(Ok(()), send.send(3));
+        // Only the newest is kept.
+        assert_eq!(2, recv.take_dropped());
+        assert_eq!(Some(3), recv.recv());
+        assert_eq!(None, recv.try_recv());
+
+        let waiting = std::thread::spawn(move || (recv.recv(), recv.recv()));
+        assert_eq!(Ok(()), send.send(4));
+        drop(send);
+        assert_eq!((Some(4), None), waiting.join().unwrap());
+
+        let (send, recv) = latest();
+        drop(recv);
+        assert_eq!(Err(Disconnected), send.send(5));
+    }
+
+    #[test]
+    fn test_recv_until() {
+        let (send, recv) = latest();
+        let after = |ms| Instant::now() + std::time::Duration::from_millis(ms);
+        assert_eq!(None, recv.recv_until(after(10)));
+        assert_eq!(Ok(()), send.send(1));
+        assert_eq!(Some(1), recv.recv_until(Instant::now()));
+
+        // An item or a hang up ends the wait early.
+        let waiting = std::thread::spawn(move || {
+            (recv.recv_until(after(5000)), recv.recv_until(after(5000)))
+        });
+        assert_eq!(Ok(()), send.send(2));
+        drop(send);
+        assert_eq!((Some(2), None), waiting.join().unwrap());
+    }
+}
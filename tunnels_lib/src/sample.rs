@@ -0,0 +1,215 @@
+//! Evaluating the composited color of a video channel at points, without
+//! rasterizing.
+//!
+//! Each arc is tested analytically against the point using the same geometry
+//! the client draws with, and covering arcs are composited in draw order,
+//! in the blend mode of their layer.
+//! This is cheap enough to run for thousands of points per frame on the
+//! server, for driving LED pixels and fixtures from the same composition as
+//! the projectors.
+//!
+//! Points are expressed as fractions of the window size, with the origin at
+//! the top left and y increasing downwards, as in a calibration.
+
+use std::f64::consts::PI;
+
+use crate::{color::hsv_to_rgb, ArcSegment, BlendMode, LayerCollection};
+
+/// Lineweight scale used by the client.
+const THICKNESS_SCALE: f64 = 0.5;
+
+/// Bisection steps used to locate a point across the width of an arc.
+const BISECTION_STEPS: usize = 40;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Sampler {
+    /// Width of the output relative to its height.
+    aspect_ratio: f64,
+    /// Composite as a client with alpha blending enabled does.
+    alpha_blend: bool,
+}
+
+impl Sampler {
+    pub fn new(aspect_ratio: f64, alpha_blend: bool) -> Self {
+        Self {
+            aspect_ratio,
+            alpha_blend,
+        }
+    }
+
+    /// Return the RGB color of the layers at a point, with each component
+    /// on [0, 1].  Each layer is blended in the corresponding mode, or alpha
+    /// blended if it has none.
+    pub fn sample(
+        &self,
+        layers: &LayerCollection,
+        blend: &[BlendMode],
+        point: [f64; 2],
+    ) -> [f64; 3] {
+        // Work in units of the window height.
+        let (width, height) = (self.aspect_ratio, 1.0);
+        let critical_size = width.min(height);
+        let pos = [point[0] * width, point[1] * height];
+        let mut color = [0.0; 3];
+        for (i, layer) in layers.iter().enumerate() {
+            let mode = blend.get(i).copied().unwrap_or_default();
+            for arc in layer.iter() {
+                let center = [arc.x * width + width / 2.0, arc.y * height + height / 2.0];
+                if covers(arc, critical_size, center, pos) {
+                    self.composite(&mut color, arc, mode);
+                }
+            }
+        }
+        color
+    }
+
+    /// Blend a covering arc into the color beneath it, as the client does.
+    fn composite(&self, color: &mut [f64; 3], arc: &ArcSegment, mode: BlendMode) {
+        match (mode, self.alpha_blend) {
+            (BlendMode::Alpha, true) => {
+                let rgb = hsv_to_rgb(arc.hue, arc.sat, arc.val);
+                for (c, v) in color.iter_mut().zip(rgb.iter()) {
+                    *c = *c * (1.0 - arc.level) + v * arc.level;
+                }
+            }
+            (BlendMode::Alpha, false) => {
+                *color = hsv_to_rgb(arc.hue, arc.sat, arc.val * arc.level);
+            }
+            (BlendMode::Additive, _) => {
+                let rgb = hsv_to_rgb(arc.hue, arc.sat, arc.val * arc.level);
+                for (c, v) in color.iter_mut().zip(rgb.iter()) {
+                    *c = (*c + v).min(1.0);
+                }
+            }
+            (BlendMode::Mask, true) => color.iter_mut().for_each(|c| *c *= 1.0 - arc.level),
+            (BlendMode::Mask, false) => *color = [0.0; 3],
+        }
+    }
+
+    /// Sample the layers at every point.
+    pub fn sample_all(
+        &self,
+        layers: &LayerCollection,
+        blend: &[BlendMode],
+        points: &[[f64; 2]],
+    ) -> Vec<[f64; 3]> {
+        points
+            .iter()
+            .map(|p| self.sample(layers, blend, *p))
+            .collect()
+    }
+}
+
+/// Return true if the arc covers the point.
+/// The arc is an elliptical band centered on the ellipse given by its radii,
+/// swept from its start angle to its stop angle in its rotated frame.
+fn covers(arc: &ArcSegment, critical_size: f64, center: [f64; 2], pos: [f64; 2]) -> bool {
+    let twopi = 2.0 * PI;
+    let start = arc.start * twopi;
+    let sweep = ((arc.stop * twopi - start) % twopi + twopi) % twopi;
+    if sweep == 0.0 {
+        return false;
+    }
+    let half_width = arc.thickness * critical_size * THICKNESS_SCALE / 2.0;
+    let (rx, ry) = (arc.rad_x * critical_size, arc.rad_y * critical_size);
+
+    // Move into the frame of the arc.
+    let (dx, dy) = (pos[0] - center[0], pos[1] - center[1]);
+    let (sin, cos) = (arc.rot_angle * twopi).sin_cos();
+    let (lx, ly) = (dx * cos + dy * sin, dy * cos - dx * sin);
+
+    // A point on the band is at parametric angle a on the ellipse grown by s,
+    // for some s across the band's width.  The ellipse grows monotonically
+    // with s, so find where the point lies by bisection.
+    let inside = |s: f64| (lx / (rx + s)).powi(2) + (ly / (ry + s)).powi(2) <= 1.0;
+    let mut lo = (-half_width).max(-rx.min(ry) + f64::EPSILON);
+    let mut hi = half_width;
+    if lo >= hi || !inside(hi) {
+        return false;
+    }
+    if lx == 0.0 && ly == 0.0 {
+        // Only a band that reaches the center covers it, at every angle.
+        return lo > -half_width;
+    }
+    if inside(lo) {
+        return false;
+    }
+    for _ in 0..BISECTION_STEPS {
+        let mid = (lo + hi) / 2.0;
+        if inside(mid) {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    let angle = (ly / (ry + hi)).atan2(lx / (rx + hi));
+    (angle - start).rem_euclid(twopi) <= sweep
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    fn ring(hue: f64, level: f64, stop: f64) -> ArcSegment {
+        ArcSegment {
+            level,
+            thickness: 0.1,
+            hue,
+            sat: 1.0,
+            val: 1.0,
+            x: 0.0,
+            y: 0.0,
+            rad_x: 0.25,
+            rad_y: 0.25,
+            start: 0.0,
+            stop,
+            rot_angle: 0.0,
+            palette: false,
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        let sampler = Sampler::new(1.0, false);
+        let layers = vec![Arc::new(vec![ring(0.0, 1.0, 0.5)])];
+        // On the ring, in the half that is drawn; y increases downwards.
+        assert_eq!([1.0, 0.0, 0.0], sampler.sample(&layers, &[], [0.5, 0.75]));
+        assert_eq!([0.0, 0.0, 0.0], sampler.sample(&layers, &[], [0.5, 0.25]));
+        // Off the ring.
+        assert_eq!([0.0, 0.0, 0.0], sampler.sample(&layers, &[], [0.5, 0.5]));
+        assert_eq!([0.0, 0.0, 0.0], sampler.sample(&layers, &[], [0.5, 0.95]));
+
+        // Later layers are drawn on top.
+        let layers = vec![
+            Arc::new(vec![ring(0.0, 1.0, 0.75)]),
+            Arc::new(vec![ring(1.0 / 3.0, 0.5, 0.75)]),
+        ];
+        assert_eq!([0.0, 0.5, 0.0], sampler.sample(&layers, &[], [0.75, 0.5]));
+        let blended = Sampler::new(1.0, true).sample(&layers, &[], [0.75, 0.5]);
+        assert_eq!([0.5, 0.5, 0.0], blended);
+    }
+
+    #[test]
+    fn test_blend_modes() {
+        let layers = vec![
+            Arc::new(vec![ring(0.0, 1.0, 0.75)]),
+            Arc::new(vec![ring(1.0 / 3.0, 0.5, 0.75)]),
+        ];
+        let point = [0.75, 0.5];
+        for alpha_blend in [false, true] {
+            let sampler = Sampler::new(1.0, alpha_blend);
+            // An additive layer adds to what is beneath, whether or not the
+            // client alpha blends.
+            let blend = [BlendMode::Alpha, BlendMode::Additive];
+            assert_eq!([1.0, 0.5, 0.0], sampler.sample(&layers, &blend, point));
+        }
+        // A mask darkens what is beneath in proportion to its level, or
+        // blacks it out if the client doesn't alpha blend.
+        let blend = [BlendMode::Alpha, BlendMode::Mask];
+        let masked = Sampler::new(1.0, true).sample(&layers, &blend, point);
+        assert_eq!([0.5, 0.0, 0.0], masked);
+        let masked = Sampler::new(1.0, false).sample(&layers, &blend, point);
+        assert_eq!([0.0, 0.0, 0.0], masked);
+    }
+}
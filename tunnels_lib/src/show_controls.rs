@@ -0,0 +1,140 @@
+//! Control events for common show controls, for scripts that would rather
+//! not look up control surface mappings.
+//!
+//! Each function builds the event that the control surface showing the
+//! control would send, so the show handles it exactly as a press or a move of
+//! that control.  The show's tests check every one of these against its own
+//! mappings.
+
+use simple_error::bail;
+use std::error::Error;
+
+use crate::control_protocol::{ControlEvent, EventKind};
+
+/// The surfaces showing the first and second pages of mixer channels and of
+/// the beam store grid.
+const PAGE_SURFACES: [&str; 2] = ["TouchOsc", "AkaiApc20"];
+const CHANNELS_PER_PAGE: usize = 8;
+const BEAM_STORE_ROWS: usize = 5;
+const BEAM_STORE_COLS_PER_PAGE: usize = 8;
+/// The surface carrying the clock controls.
+const CLOCK_SURFACE: &str = "BehringerCmdMM1";
+const N_CLOCKS: usize = 4;
+
+const GRAND_MASTER: u8 = 0x0E;
+const BLACKOUT: u8 = 0x51;
+const FADER: u8 = 0x7;
+const BUMP: u8 = 0x32;
+const MASK: u8 = 0x31;
+const CHANNEL_SELECT: u8 = 0x33;
+const RECALL_CHANNEL: u8 = 13;
+const RECALL_0: u8 = 32;
+const CLOCK_CHANNEL: u8 = 4;
+const CLOCK_TAP_0: u8 = 48;
+
+fn unipolar(level: f64) -> Result<u8, Box<dyn Error>> {
+    if !(0.0..=1.0).contains(&level) {
+        bail!("level {} is outside of [0, 1]", level);
+    }
+    Ok((level * 127.) as u8)
+}
+
+/// The surface and midi channel for a mixer channel.
+fn channel_surface(channel: usize) -> Result<(&'static str, u8), Box<dyn Error>> {
+    match PAGE_SURFACES.get(channel / CHANNELS_PER_PAGE) {
+        Some(device) => Ok((device, (channel % CHANNELS_PER_PAGE) as u8)),
+        None => bail!("no control surface shows mixer channel {}", channel),
+    }
+}
+
+/// Set the grand master level, in [0, 1].
+pub fn grand_master(level: f64) -> Result<ControlEvent, Box<dyn Error>> {
+    ControlEvent::new(
+        PAGE_SURFACES[0],
+        EventKind::ControlChange,
+        0,
+        GRAND_MASTER,
+        unipolar(level)?,
+    )
+}
+
+/// Toggle blackout.
+pub fn toggle_blackout() -> Result<ControlEvent, Box<dyn Error>> {
+    ControlEvent::new(PAGE_SURFACES[0], EventKind::NoteOn, 0, BLACKOUT, 127)
+}
+
+/// Set the level of a mixer channel, in [0, 1].
+pub fn channel_level(channel: usize, level: f64) -> Result<ControlEvent, Box<dyn Error>> {
+    let (device, midi_channel) = channel_surface(channel)?;
+    ControlEvent::new(
+        device,
+        EventKind::ControlChange,
+        midi_channel,
+        FADER,
+        unipolar(level)?,
+    )
+}
+
+/// Press or release the bump button of a mixer channel.
+pub fn channel_bump(channel: usize, on: bool) -> Result<ControlEvent, Box<dyn Error>> {
+    let (device, midi_channel) = channel_surface(channel)?;
+    let (kind, value) = if on {
+        (EventKind::NoteOn, 127)
+    } else {
+        (EventKind::NoteOff, 0)
+    };
+    ControlEvent::new(device, kind, midi_channel, BUMP, value)
+}
+
+/// Toggle whether a mixer channel is a mask.
+pub fn toggle_channel_mask(channel: usize) -> Result<ControlEvent, Box<dyn Error>> {
+    let (device, midi_channel) = channel_surface(channel)?;
+    ControlEvent::new(device, EventKind::NoteOn, midi_channel, MASK, 127)
+}
+
+/// Make a mixer channel the current channel.
+pub fn select_channel(channel: usize) -> Result<ControlEvent, Box<dyn Error>> {
+    let (device, midi_channel) = channel_surface(channel)?;
+    ControlEvent::new(device, EventKind::NoteOn, midi_channel, CHANNEL_SELECT, 127)
+}
+
+/// Recall a beam store slot into the current channel.  Unlike pressing the
+/// slot on the grid, this recalls whatever mode the beam store is in.
+pub fn recall_beam(row: usize, col: usize) -> Result<ControlEvent, Box<dyn Error>> {
+    let page = col / BEAM_STORE_COLS_PER_PAGE;
+    let device = match PAGE_SURFACES.get(page) {
+        Some(device) if row < BEAM_STORE_ROWS => device,
+        _ => bail!("no control surface shows beam store slot {}, {}", row, col),
+    };
+    let note = RECALL_0 as usize + row * BEAM_STORE_COLS_PER_PAGE + col % BEAM_STORE_COLS_PER_PAGE;
+    ControlEvent::new(device, EventKind::NoteOn, RECALL_CHANNEL, note as u8, 127)
+}
+
+/// Tap a clock.
+pub fn tap_clock(clock: usize) -> Result<ControlEvent, Box<dyn Error>> {
+    if clock >= N_CLOCKS {
+        bail!("there is no clock {}", clock);
+    }
+    ControlEvent::new(
+        CLOCK_SURFACE,
+        EventKind::NoteOn,
+        CLOCK_CHANNEL,
+        CLOCK_TAP_0 + clock as u8,
+        127,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(grand_master(1.5).is_err());
+        assert!(grand_master(f64::NAN).is_err());
+        assert!(channel_level(2 * CHANNELS_PER_PAGE, 0.5).is_err());
+        assert!(recall_beam(BEAM_STORE_ROWS, 0).is_err());
+        assert!(recall_beam(0, 2 * BEAM_STORE_COLS_PER_PAGE).is_err());
+        assert!(tap_clock(N_CLOCKS).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Namespacing for running several independent shows on one network.
+//!
+//! A show may be given an ID, configured identically on the server and on its
+//! clients.  Snapshot topics, client heartbeats, and the name of the clients'
+//! remote control service are all tagged with it, so that two rigs sharing a
+//! LAN ignore each other.  A show without an ID uses the untagged names, so
+//! existing setups keep working unchanged.
+
+use simple_error::bail;
+use std::error::Error;
+
+use crate::client_profile::SERVICE_NAME;
+
+/// Longest allowed show ID, keeping the tagged service name within the 15
+/// characters DNS-SD allows.
+pub const MAX_LEN: usize = 12;
+
+/// Check that a show ID is usable as a topic prefix and in a service name.
+/// IDs are lowercase letters, digits, and hyphens, starting with a letter.
+/// Starting with a letter also keeps tagged topics from colliding with
+/// untagged ones, which start with a small video channel number.
+pub fn validate(show_id: &str) -> Result<(), Box<dyn Error>> {
+    if show_id.is_empty() || show_id.len() > MAX_LEN {
+        bail!(
+            "Show ID \"{}\" must be between 1 and {} characters long.",
+            show_id,
+            MAX_LEN
+        );
+    }
+    if !show_id.starts_with(|c: char| c.is_ascii_lowercase())
+        || !show_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        bail!(
+            "Show ID \"{}\" must start with a lowercase letter and contain only lowercase letters, digits, and hyphens.",
+            show_id
+        );
+    }
+    Ok(())
+}
+
+/// The zmq topic that snapshots for a video channel are published under.
+pub fn frame_topic(show_id: Option<&str>, video_channel: u8) -> Vec<u8> {
+    let mut topic = Vec::new();
+    if let Some(show_id) = show_id {
+        // The separator stops one ID from matching as a prefix of another.
+        topic.extend_from_slice(show_id.as_bytes());
+        topic.push(b'/');
+    }
+    topic.push(video_channel);
+    topic
+}
+
+/// Keepalives are published as if on this video channel, well clear of any
+/// real one.
+const KEEPALIVE_CHANNEL: u8 = u8::MAX;
+
+/// The zmq topic the server publishes keepalives under, so that clients can
+/// tell a server with nothing to send from one that has gone away.
+pub fn keepalive_topic(show_id: Option<&str>) -> Vec<u8> {
+    frame_topic(show_id, KEEPALIVE_CHANNEL)
+}
+
+/// The name clients advertise their remote control service under.
+pub fn service_name(show_id: Option<&str>) -> String {
+    match show_id {
+        Some(show_id) => format!("tc-{}", show_id),
+        None => SERVICE_NAME.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_show_id() {
+        assert!(validate("rig-2").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("2rig").is_err());
+        assert!(validate("Rig").is_err());
+        assert!(validate("rig/a").is_err());
+        assert!(validate("a-very-long-id").is_err());
+
+        assert_eq!(vec![3], frame_topic(None, 3));
+        assert_eq!(b"rig/\x03".to_vec(), frame_topic(Some("rig"), 3));
+        assert!(!frame_topic(Some("rig-2"), 0).starts_with(&frame_topic(Some("rig"), b'-')));
+        assert_eq!(b"rig/\xff".to_vec(), keepalive_topic(Some("rig")));
+
+        assert_eq!(SERVICE_NAME, service_name(None));
+        assert_eq!("tc-rig", service_name(Some("rig")));
+    }
+}
@@ -0,0 +1,70 @@
+//! Scheduling options for latency-sensitive threads.
+//!
+//! On a busy machine the OS may migrate or preempt the threads that produce
+//! and draw frames, causing visible hitches.  Pinning such a thread to a core
+//! that nothing else is using, and raising its priority, avoids most of this.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::error::Error;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ThreadConfig {
+    /// Pin the thread to the CPU core with this index.
+    #[serde(default)]
+    pub core: Option<usize>,
+    /// Run the thread under the real-time round-robin scheduler.
+    /// This usually requires elevated privileges.
+    #[serde(default)]
+    pub high_priority: bool,
+}
+
+impl ThreadConfig {
+    /// Apply these options to the calling thread.
+    pub fn apply_to_current(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(core) = self.core {
+            let core_ids = match core_affinity::get_core_ids() {
+                Some(ids) => ids,
+                None => bail!("Unable to list CPU cores."),
+            };
+            match core_ids.into_iter().find(|c| c.id == core) {
+                Some(core_id) => core_affinity::set_for_current(core_id),
+                None => bail!("CPU core {} does not exist.", core),
+            }
+        }
+        if self.high_priority {
+            raise_priority()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn raise_priority() -> Result<(), Box<dyn Error>> {
+    // Safe: these calls only affect the scheduling of the calling thread.
+    unsafe {
+        let policy = libc::SCHED_RR;
+        let min = libc::sched_get_priority_min(policy);
+        let max = libc::sched_get_priority_max(policy);
+        // Stay clear of the top of the range, which the kernel's own threads
+        // may need.
+        let param = libc::sched_param {
+            sched_priority: min + (max - min) / 2,
+        };
+        let err = libc::pthread_setschedparam(libc::pthread_self(), policy, &param);
+        if err != 0 {
+            bail!(
+                "Unable to raise thread priority: {}.",
+                std::io::Error::from_raw_os_error(err)
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn raise_priority() -> Result<(), Box<dyn Error>> {
+    bail!("Raising thread priority is not supported on this platform.");
+}
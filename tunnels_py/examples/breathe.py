@@ -0,0 +1,14 @@
+"""Slowly raise and lower the level of mixer channel 0, as a smoke test of
+the control server.  Run with the show running and tunnels_control built."""
+
+import math
+import time
+
+import tunnels_control as tc
+
+show = tc.ControlClient()
+start = time.time()
+while True:
+    level = 0.5 - 0.5 * math.cos(time.time() - start)
+    show.send([tc.channel_level(0, level)])
+    time.sleep(1 / 30)
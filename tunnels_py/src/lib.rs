@@ -0,0 +1,240 @@
+//! Python bindings for driving the show from scripts.
+//!
+//! Events are built in Python and sent to the show's control server, which
+//! handles each one as if the named control surface had sent it.  Build the
+//! module with maturin:
+//!
+//! ```text
+//! cd tunnels_py && maturin develop --release
+//! ```
+//!
+//! and then, from Python:
+//!
+//! ```text
+//! import tunnels_control as tc
+//! show = tc.ControlClient()
+//! show.send([tc.cc("TouchOsc", 0, 21, 127), tc.note_on("AkaiApc40", 0, 0x52)])
+//! show.send([tc.channel_level(0, 0.5), tc.recall_beam(0, 3)])
+//! ```
+//!
+//! The raw events reach every control; common show controls also have their
+//! own functions, which need no knowledge of the control surface mappings.
+
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+};
+use std::error::Error;
+use tunnels_lib::{
+    control_protocol::{format_request, ControlEvent, EventKind, OK, PORT},
+    show_controls,
+};
+
+create_exception!(
+    tunnels_control,
+    ControlError,
+    PyException,
+    "The show could not be reached, or rejected a request."
+);
+
+/// A single control surface event.
+#[pyclass(name = "Event", module = "tunnels_control")]
+#[derive(Clone)]
+struct PyEvent(ControlEvent);
+
+fn new_event(
+    device: &str,
+    kind: EventKind,
+    channel: u8,
+    control: u8,
+    value: u8,
+) -> PyResult<PyEvent> {
+    py_event(ControlEvent::new(device, kind, channel, control, value))
+}
+
+fn py_event(event: Result<ControlEvent, Box<dyn Error>>) -> PyResult<PyEvent> {
+    event
+        .map(PyEvent)
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+#[pymethods]
+impl PyEvent {
+    /// Kind is one of note_on, note_off, or cc.
+    #[new]
+    fn new(device: &str, kind: &str, channel: u8, control: u8, value: u8) -> PyResult<Self> {
+        let kind = kind.parse().map_err(PyValueError::new_err)?;
+        new_event(device, kind, channel, control, value)
+    }
+
+    #[getter]
+    fn device(&self) -> &str {
+        &self.0.device
+    }
+
+    /// One of note_on, note_off, or cc.
+    #[getter]
+    fn kind(&self) -> &str {
+        self.0.kind.name()
+    }
+
+    #[getter]
+    fn channel(&self) -> u8 {
+        self.0.channel
+    }
+
+    #[getter]
+    fn control(&self) -> u8 {
+        self.0.control
+    }
+
+    #[getter]
+    fn value(&self) -> u8 {
+        self.0.value
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Event(\"{}\")", self.0)
+    }
+}
+
+/// A note on event, such as a button press.
+#[pyfunction]
+#[pyo3(signature = (device, channel, control, value = 127))]
+fn note_on(device: &str, channel: u8, control: u8, value: u8) -> PyResult<PyEvent> {
+    new_event(device, EventKind::NoteOn, channel, control, value)
+}
+
+/// A note off event, such as a button release.
+#[pyfunction]
+fn note_off(device: &str, channel: u8, control: u8) -> PyResult<PyEvent> {
+    new_event(device, EventKind::NoteOff, channel, control, 0)
+}
+
+/// A control change event, such as a fader or knob move.
+#[pyfunction]
+fn cc(device: &str, channel: u8, control: u8, value: u8) -> PyResult<PyEvent> {
+    new_event(device, EventKind::ControlChange, channel, control, value)
+}
+
+/// Set the grand master level, in [0, 1].
+#[pyfunction]
+fn grand_master(level: f64) -> PyResult<PyEvent> {
+    py_event(show_controls::grand_master(level))
+}
+
+/// Toggle blackout.
+#[pyfunction]
+fn toggle_blackout() -> PyResult<PyEvent> {
+    py_event(show_controls::toggle_blackout())
+}
+
+/// Set the level of a mixer channel, in [0, 1].
+#[pyfunction]
+fn channel_level(channel: usize, level: f64) -> PyResult<PyEvent> {
+    py_event(show_controls::channel_level(channel, level))
+}
+
+/// Press, or with on=False release, the bump button of a mixer channel.
+#[pyfunction]
+#[pyo3(signature = (channel, on = true))]
+fn channel_bump(channel: usize, on: bool) -> PyResult<PyEvent> {
+    py_event(show_controls::channel_bump(channel, on))
+}
+
+/// Toggle whether a mixer channel is a mask.
+#[pyfunction]
+fn toggle_channel_mask(channel: usize) -> PyResult<PyEvent> {
+    py_event(show_controls::toggle_channel_mask(channel))
+}
+
+/// Make a mixer channel the current channel.
+#[pyfunction]
+fn select_channel(channel: usize) -> PyResult<PyEvent> {
+    py_event(show_controls::select_channel(channel))
+}
+
+/// Recall a beam store slot into the current channel, whatever mode the beam
+/// store is in.
+#[pyfunction]
+fn recall_beam(row: usize, col: usize) -> PyResult<PyEvent> {
+    py_event(show_controls::recall_beam(row, col))
+}
+
+/// Tap a clock.
+#[pyfunction]
+fn tap_clock(clock: usize) -> PyResult<PyEvent> {
+    py_event(show_controls::tap_clock(clock))
+}
+
+/// A connection to the show's control server.
+#[pyclass(unsendable, module = "tunnels_control")]
+struct ControlClient {
+    socket: zmq::Socket,
+}
+
+fn zmq_error(e: zmq::Error) -> PyErr {
+    ControlError::new_err(e.to_string())
+}
+
+#[pymethods]
+impl ControlClient {
+    #[new]
+    #[pyo3(signature = (host = "localhost", port = PORT, timeout_ms = 1000))]
+    fn new(host: &str, port: u16, timeout_ms: i32) -> PyResult<Self> {
+        let socket = zmq::Context::new().socket(zmq::REQ).map_err(zmq_error)?;
+        socket.set_rcvtimeo(timeout_ms).map_err(zmq_error)?;
+        socket.set_linger(0).map_err(zmq_error)?;
+        // Allow a new request after one whose reply timed out.
+        socket.set_req_relaxed(true).map_err(zmq_error)?;
+        socket.set_req_correlate(true).map_err(zmq_error)?;
+        socket
+            .connect(&format!("tcp://{}:{}", host, port))
+            .map_err(zmq_error)?;
+        Ok(Self { socket })
+    }
+
+    /// Send events to the show, to be applied in order.  If the show rejects
+    /// any of them, none are applied and ControlError is raised.
+    fn send(&self, events: Vec<PyEvent>) -> PyResult<()> {
+        let events = events.into_iter().map(|e| e.0).collect::<Vec<_>>();
+        self.socket
+            .send(format_request(&events).as_bytes(), 0)
+            .map_err(zmq_error)?;
+        let reply = self.socket.recv_bytes(0).map_err(|e| match e {
+            zmq::Error::EAGAIN => ControlError::new_err("timed out waiting for the show"),
+            e => zmq_error(e),
+        })?;
+        let reply = String::from_utf8_lossy(&reply);
+        if reply == OK {
+            Ok(())
+        } else {
+            Err(ControlError::new_err(reply.into_owned()))
+        }
+    }
+}
+
+#[pymodule]
+fn tunnels_control(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyEvent>()?;
+    m.add_class::<ControlClient>()?;
+    m.add_function(wrap_pyfunction!(note_on, m)?)?;
+    m.add_function(wrap_pyfunction!(note_off, m)?)?;
+    m.add_function(wrap_pyfunction!(cc, m)?)?;
+    m.add_function(wrap_pyfunction!(grand_master, m)?)?;
+    m.add_function(wrap_pyfunction!(toggle_blackout, m)?)?;
+    m.add_function(wrap_pyfunction!(channel_level, m)?)?;
+    m.add_function(wrap_pyfunction!(channel_bump, m)?)?;
+    m.add_function(wrap_pyfunction!(toggle_channel_mask, m)?)?;
+    m.add_function(wrap_pyfunction!(select_channel, m)?)?;
+    m.add_function(wrap_pyfunction!(recall_beam, m)?)?;
+    m.add_function(wrap_pyfunction!(tap_clock, m)?)?;
+    m.add("ControlError", py.get_type::<ControlError>())?;
+    m.add("PORT", PORT)?;
+    Ok(())
+}